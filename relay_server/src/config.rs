@@ -0,0 +1,380 @@
+//! Configuration of the relay, from command line flags and `RELAY_*` environment
+//! variables. A flag takes precedence over the variable of the same name, so
+//! `--tcp-port 4001` overrides `RELAY_TCP_PORT`.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use libp2p::{Multiaddr, PeerId, relay};
+
+/// Flags of the relay, with a description for `--help`. Flags marked as lists may be
+/// given more than once, and their variables hold comma separated values.
+const OPTIONS: &[(&str, bool, &str)] = &[
+    (
+        "key-file",
+        false,
+        "Protobuf encoded keypair, created on first start [relay-keypair.proto]",
+    ),
+    ("listen-ip", false, "Address to listen on [0.0.0.0]"),
+    ("tcp-port", false, "TCP port [4001]"),
+    ("quic-port", false, "UDP port of QUIC [4001]"),
+    (
+        "external-addr",
+        true,
+        "Public address clients reach the relay at, e.g. /ip4/1.2.3.4/udp/4001/quic-v1",
+    ),
+    ("max-reservations", false, "Reservations in total [128]"),
+    (
+        "max-reservations-per-peer",
+        false,
+        "Reservations of a single peer [4]",
+    ),
+    (
+        "reservation-duration",
+        false,
+        "Seconds a reservation lasts before it has to be renewed [3600]",
+    ),
+    ("max-circuits", false, "Circuits in total [16]"),
+    (
+        "max-circuits-per-peer",
+        false,
+        "Circuits of a single peer [4]",
+    ),
+    (
+        "max-circuit-duration",
+        false,
+        "Seconds a circuit stays open [120]",
+    ),
+    (
+        "max-circuit-bytes",
+        false,
+        "Bytes relayed over a circuit before it is closed [131072]",
+    ),
+    (
+        "allowed-peer",
+        true,
+        "Peer id that may connect. Without any, every peer may.",
+    ),
+    (
+        "allowed-peers-file",
+        false,
+        "File with a peer id that may connect on every line",
+    ),
+    (
+        "metrics-addr",
+        false,
+        "Address to serve Prometheus metrics on at /metrics, e.g. 0.0.0.0:9090",
+    ),
+];
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub key_file: PathBuf,
+    pub listen_ip: IpAddr,
+    pub tcp_port: u16,
+    pub quic_port: u16,
+    /// Addresses the relay hands out in reservations. Clients can't be reached through
+    /// the relay without one.
+    pub external_addrs: Vec<Multiaddr>,
+    pub limits: RelayLimits,
+    /// Only these peers may connect, if set
+    pub allowed_peers: Option<Vec<PeerId>>,
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+/// Limits of the relay. The defaults are the ones of libp2p.
+#[derive(Debug, Clone)]
+pub struct RelayLimits {
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    pub reservation_duration: Duration,
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    pub max_circuit_duration: Duration,
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        let defaults = relay::Config::default();
+        Self {
+            max_reservations: defaults.max_reservations,
+            max_reservations_per_peer: defaults.max_reservations_per_peer,
+            reservation_duration: defaults.reservation_duration,
+            max_circuits: defaults.max_circuits,
+            max_circuits_per_peer: defaults.max_circuits_per_peer,
+            max_circuit_duration: defaults.max_circuit_duration,
+            max_circuit_bytes: defaults.max_circuit_bytes,
+        }
+    }
+}
+
+impl RelayLimits {
+    pub fn relay_config(&self) -> relay::Config {
+        relay::Config {
+            max_reservations: self.max_reservations,
+            max_reservations_per_peer: self.max_reservations_per_peer,
+            reservation_duration: self.reservation_duration,
+            max_circuits: self.max_circuits,
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            max_circuit_duration: self.max_circuit_duration,
+            max_circuit_bytes: self.max_circuit_bytes,
+            ..Default::default()
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config from the arguments of the process and the environment
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        Self::parse(std::env::args().skip(1), |name| std::env::var(name).ok())
+    }
+
+    fn parse(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut values: HashMap<&str, Vec<String>> = HashMap::new();
+        for &(name, list, _) in OPTIONS {
+            if let Some(value) = env(&env_var(name)) {
+                let value = if list {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|value| !value.is_empty())
+                        .map(String::from)
+                        .collect()
+                } else {
+                    vec![value]
+                };
+                values.insert(name, value);
+            }
+        }
+
+        // Flags replace the values of the environment
+        let mut from_args: HashMap<&str, Vec<String>> = HashMap::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("Unexpected argument: {arg}").into());
+            };
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let Some(&(name, list, _)) = OPTIONS.iter().find(|(name, ..)| *name == flag) else {
+                return Err(format!("Unknown flag: --{flag}").into());
+            };
+            let value = match value.or_else(|| args.next()) {
+                Some(value) => value,
+                None => return Err(format!("Missing value of --{name}").into()),
+            };
+            let entry = from_args.entry(name).or_default();
+            if !list {
+                entry.clear();
+            }
+            entry.push(value);
+        }
+        values.extend(from_args);
+
+        let single = |name: &str| values.get(name).and_then(|values| values.last());
+        let defaults = RelayLimits::default();
+        let mut allowed_peers = values
+            .get("allowed-peer")
+            .map(|peers| {
+                peers
+                    .iter()
+                    .map(|peer| parse_value("allowed-peer", peer))
+                    .collect::<Result<Vec<PeerId>, _>>()
+            })
+            .transpose()?;
+        if let Some(path) = single("allowed-peers-file") {
+            let file = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read allowed peers from {path}: {e}"))?;
+            let peers = allowed_peers.get_or_insert_with(Vec::new);
+            for line in file.lines().map(str::trim) {
+                if !line.is_empty() && !line.starts_with('#') {
+                    peers.push(parse_value("allowed-peers-file", line)?);
+                }
+            }
+        }
+
+        Ok(Self {
+            key_file: single("key-file")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("relay-keypair.proto")),
+            listen_ip: parse_or(
+                single("listen-ip"),
+                "listen-ip",
+                Ipv4Addr::UNSPECIFIED.into(),
+            )?,
+            tcp_port: parse_or(single("tcp-port"), "tcp-port", 4001)?,
+            quic_port: parse_or(single("quic-port"), "quic-port", 4001)?,
+            external_addrs: values
+                .get("external-addr")
+                .map(|addrs| {
+                    addrs
+                        .iter()
+                        .map(|addr| parse_value("external-addr", addr))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()?
+                .unwrap_or_default(),
+            limits: RelayLimits {
+                max_reservations: parse_or(
+                    single("max-reservations"),
+                    "max-reservations",
+                    defaults.max_reservations,
+                )?,
+                max_reservations_per_peer: parse_or(
+                    single("max-reservations-per-peer"),
+                    "max-reservations-per-peer",
+                    defaults.max_reservations_per_peer,
+                )?,
+                reservation_duration: parse_or(
+                    single("reservation-duration"),
+                    "reservation-duration",
+                    defaults.reservation_duration.as_secs(),
+                )
+                .map(Duration::from_secs)?,
+                max_circuits: parse_or(
+                    single("max-circuits"),
+                    "max-circuits",
+                    defaults.max_circuits,
+                )?,
+                max_circuits_per_peer: parse_or(
+                    single("max-circuits-per-peer"),
+                    "max-circuits-per-peer",
+                    defaults.max_circuits_per_peer,
+                )?,
+                max_circuit_duration: parse_or(
+                    single("max-circuit-duration"),
+                    "max-circuit-duration",
+                    defaults.max_circuit_duration.as_secs(),
+                )
+                .map(Duration::from_secs)?,
+                max_circuit_bytes: parse_or(
+                    single("max-circuit-bytes"),
+                    "max-circuit-bytes",
+                    defaults.max_circuit_bytes,
+                )?,
+            },
+            allowed_peers,
+            metrics_addr: single("metrics-addr")
+                .map(|addr| parse_value("metrics-addr", addr))
+                .transpose()?,
+        })
+    }
+}
+
+/// Text printed for `--help`
+pub fn usage() -> String {
+    let mut usage = String::from("Usage: relay_server [FLAGS]\n\nFlags:\n");
+    for &(name, list, description) in OPTIONS {
+        let repeat = if list { ", may be repeated" } else { "" };
+        usage.push_str(&format!(
+            "  --{name} <value>\n      {description}\n      (env {}{repeat})\n",
+            env_var(name)
+        ));
+    }
+    usage
+}
+
+fn env_var(name: &str) -> String {
+    format!("RELAY_{}", name.replace('-', "_").to_uppercase())
+}
+
+fn parse_value<T>(name: &str, value: &str) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value {value:?} of {name}: {e}").into())
+}
+
+fn parse_or<T>(value: Option<&String>, name: &str, default: T) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.map_or(Ok(default), |value| parse_value(name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_flags_override_env() {
+        let peer = PeerId::random();
+        let env = |name: &str| match name {
+            "RELAY_TCP_PORT" => Some("5001".to_string()),
+            "RELAY_QUIC_PORT" => Some("5002".to_string()),
+            "RELAY_EXTERNAL_ADDR" => {
+                Some("/ip4/1.2.3.4/tcp/5001, /ip4/1.2.3.4/udp/5002/quic-v1".into())
+            }
+            _ => None,
+        };
+        let config = Config::parse(
+            args(&[
+                "--tcp-port=6001",
+                "--max-circuit-bytes",
+                "1048576",
+                "--allowed-peer",
+                &peer.to_string(),
+            ]),
+            env,
+        )
+        .unwrap();
+
+        assert_eq!(config.tcp_port, 6001);
+        assert_eq!(config.quic_port, 5002);
+        assert_eq!(config.external_addrs.len(), 2);
+        assert_eq!(config.limits.max_circuit_bytes, 1 << 20);
+        assert_eq!(
+            config.limits.max_circuits,
+            relay::Config::default().max_circuits
+        );
+        assert_eq!(config.allowed_peers, Some(vec![peer]));
+        assert!(config.metrics_addr.is_none());
+    }
+
+    #[test]
+    fn test_invalid_flags() {
+        let no_env = |_: &str| None;
+        assert!(Config::parse(args(&["--tcp-port", "http"]), no_env).is_err());
+        assert!(Config::parse(args(&["--unknown", "1"]), no_env).is_err());
+        assert!(Config::parse(args(&["--metrics-addr"]), no_env).is_err());
+        assert!(Config::parse(args(&["--allowed-peer", "not-a-peer"]), no_env).is_err());
+    }
+
+    #[test]
+    fn test_allowed_peers_file() {
+        let (listed, flagged) = (PeerId::random(), PeerId::random());
+        let path = std::env::temp_dir().join(format!("allowed-peers-{listed}"));
+        std::fs::write(&path, format!("# Peers of the team\n{listed}\n\n  \n")).unwrap();
+        let env =
+            |name: &str| (name == "RELAY_ALLOWED_PEERS_FILE").then(|| path.display().to_string());
+
+        let config = Config::parse(args(&["--allowed-peer", &flagged.to_string()]), env).unwrap();
+        assert_eq!(config.allowed_peers, Some(vec![flagged, listed]));
+
+        // Files with invalid peer ids and missing files are refused
+        std::fs::write(&path, "not-a-peer\n").unwrap();
+        assert!(Config::parse(Vec::new(), env).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(Config::parse(Vec::new(), env).is_err());
+    }
+}
@@ -1,20 +1,28 @@
 use futures_util::StreamExt;
 use libp2p::{
-    SwarmBuilder, identity,
+    PeerId, SwarmBuilder, allow_block_list, identity,
     multiaddr::{Multiaddr, Protocol},
-    ping,
-    relay::Behaviour as RelayServerBehaviour,
-    swarm::{NetworkBehaviour, SwarmEvent},
+    ping, relay, rendezvous,
+    swarm::{NetworkBehaviour, SwarmEvent, behaviour::toggle::Toggle},
 };
-use std::error::Error;
-use tracing::info;
+use std::{error::Error, path::Path, sync::Arc};
+use tracing::{debug, info, warn};
+
+use crate::{config::Config, metrics::RelayMetrics};
+
+mod config;
+mod metrics;
 
 // The NetworkBehaviour for our relay node.
-// It only needs the relay server, identify to tell others its address,
-// and ping for keep-alives. It does NOT need dcutr or a relay client.
+// It needs the relay server, identify to tell others its address, ping for
+// keep-alives and a rendezvous server for clients to find each other. It does NOT
+// need dcutr or a relay client.
 #[derive(NetworkBehaviour)]
 struct RelayBehaviour {
-    relay: RelayServerBehaviour,
+    // Only set when the relay is limited to an allowlist of peers
+    allowlist: Toggle<allow_block_list::Behaviour<allow_block_list::AllowedPeers>>,
+    relay: relay::Behaviour,
+    rendezvous: rendezvous::server::Behaviour,
     ping: ping::Behaviour,
     identify: libp2p::identify::Behaviour,
 }
@@ -23,9 +31,15 @@ struct RelayBehaviour {
 async fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt::init();
 
-    // Create a stable identity for the relay.
-    // In a real deployment, you would load this from a file to have a persistent PeerId.
-    let local_key = identity::Keypair::generate_ed25519();
+    if std::env::args().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", config::usage());
+        return Ok(());
+    }
+    let config = Config::load()?;
+
+    // The identity is kept across restarts, so that the PeerId in the multiaddr
+    // clients are configured with stays valid.
+    let local_key = load_keypair(&config.key_file)?;
     let local_peer_id = local_key.public().to_peer_id();
     info!("Relay PeerId: {}", local_peer_id);
 
@@ -43,37 +57,167 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 key.public(),
             ));
             RelayBehaviour {
-                relay: RelayServerBehaviour::new(key.public().to_peer_id(), Default::default()),
+                allowlist: allowlist(config.allowed_peers.as_deref()),
+                relay: relay::Behaviour::new(
+                    key.public().to_peer_id(),
+                    config.limits.relay_config(),
+                ),
+                rendezvous: rendezvous::server::Behaviour::new(
+                    rendezvous::server::Config::default(),
+                ),
                 ping: ping::Behaviour::new(ping::Config::new()),
                 identify,
             }
         })?
         .build();
 
-    // Listen on all interfaces on a fixed port for both TCP and QUIC.
+    match &config.allowed_peers {
+        Some(peers) => info!("Only {} allowed peers may connect", peers.len()),
+        None => info!("Every peer may connect"),
+    }
+    info!("Relay limits: {:?}", config.limits);
+
     // '0.0.0.0' makes it accessible from outside the local machine.
-    let listen_addr_tcp = "/ip4/0.0.0.0/tcp/4001".parse::<Multiaddr>()?;
-    let listen_addr_quic = "/ip4/0.0.0.0/udp/4001/quic-v1".parse::<Multiaddr>()?;
+    let listen_addr_tcp = Multiaddr::from(config.listen_ip).with(Protocol::Tcp(config.tcp_port));
+    let listen_addr_quic = Multiaddr::from(config.listen_ip)
+        .with(Protocol::Udp(config.quic_port))
+        .with(Protocol::QuicV1);
     swarm.listen_on(listen_addr_tcp)?;
     swarm.listen_on(listen_addr_quic)?;
 
+    // Reservations only carry the addresses the relay knows to be reachable
+    if config.external_addrs.is_empty() {
+        warn!("No external address configured, clients can't be reached through the relay");
+    }
+    for addr in &config.external_addrs {
+        swarm.add_external_address(addr.clone());
+        info!(
+            "Relay reachable at: {}",
+            addr.clone().with(Protocol::P2p(local_peer_id))
+        );
+    }
+
+    let metrics = Arc::new(RelayMetrics::default());
+    if let Some(addr) = config.metrics_addr {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr, metrics).await {
+                warn!("Metrics endpoint stopped: {e}");
+            }
+        });
+    }
+
     // Main event loop
     loop {
         match swarm.select_next_some().await {
             SwarmEvent::NewListenAddr { address, .. } => {
-                // This is the crucial part. Print the full public address for clients to use.
+                // Print the full address for clients to use.
                 info!(
                     "Relay listening on: {}",
                     address.with(Protocol::P2p(local_peer_id))
                 );
             }
+            SwarmEvent::ConnectionEstablished { .. } => metrics.connection_opened(),
+            SwarmEvent::ConnectionClosed { .. } => metrics.connection_closed(),
+            SwarmEvent::IncomingConnectionError { error, .. } => {
+                if matches!(error, libp2p::swarm::ListenError::Denied { .. }) {
+                    metrics.connection_denied();
+                }
+                debug!("Incoming connection failed: {error}");
+            }
             SwarmEvent::Behaviour(RelayBehaviourEvent::Identify(event)) => {
-                info!("Identify event: {:?}", event);
+                debug!("Identify event: {:?}", event);
             }
             SwarmEvent::Behaviour(RelayBehaviourEvent::Relay(event)) => {
+                metrics.record(&event);
                 info!("Relay server event: {:?}", event);
             }
+            SwarmEvent::Behaviour(RelayBehaviourEvent::Rendezvous(event)) => {
+                debug!("Rendezvous server event: {:?}", event);
+            }
             _ => {}
         }
     }
 }
+
+/// Refuses connections of every peer but the allowed ones, if any are configured
+fn allowlist(
+    allowed_peers: Option<&[PeerId]>,
+) -> Toggle<allow_block_list::Behaviour<allow_block_list::AllowedPeers>> {
+    allowed_peers
+        .map(|peers| {
+            let mut allowlist = allow_block_list::Behaviour::default();
+            for peer in peers {
+                allowlist.allow_peer(*peer);
+            }
+            allowlist
+        })
+        .into()
+}
+
+/// Reads the relay's keypair, or creates it on the first start
+fn load_keypair(path: &Path) -> Result<identity::Keypair, Box<dyn Error>> {
+    if path.is_file() {
+        return Ok(identity::Keypair::from_protobuf_encoding(&std::fs::read(
+            path,
+        )?)?);
+    }
+    let key = identity::Keypair::generate_ed25519();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, &key.to_protobuf_encoding()?)?;
+    info!("Created new relay keypair at {}", path.display());
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::swarm::ConnectionId;
+
+    use super::*;
+
+    fn accepts(
+        allowlist: &mut Toggle<allow_block_list::Behaviour<allow_block_list::AllowedPeers>>,
+        peer: PeerId,
+    ) -> bool {
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        allowlist
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(0),
+                peer,
+                &addr,
+                &addr,
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let (allowed, stranger) = (PeerId::random(), PeerId::random());
+
+        let mut allowlist = allowlist(Some(&[allowed]));
+        assert!(accepts(&mut allowlist, allowed));
+        assert!(!accepts(&mut allowlist, stranger));
+
+        // Without an allowlist every peer may connect
+        let mut open = super::allowlist(None);
+        assert!(accepts(&mut open, stranger));
+    }
+
+    #[test]
+    fn test_load_keypair_keeps_identity() {
+        let path = std::env::temp_dir()
+            .join(format!("relay-{}", PeerId::random()))
+            .join("keypair.proto");
+
+        let created = load_keypair(&path).unwrap();
+        let loaded = load_keypair(&path).unwrap();
+        assert_eq!(created.public(), loaded.public());
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
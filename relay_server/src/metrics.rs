@@ -0,0 +1,246 @@
+//! Counts of the relay's reservations and circuits, served in the Prometheus text
+//! format.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use libp2p::relay;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tracing::{info, warn};
+
+#[derive(Default)]
+pub struct RelayMetrics {
+    reservations: AtomicU64,
+    reservations_accepted: AtomicU64,
+    reservations_denied: AtomicU64,
+    circuits: AtomicU64,
+    circuits_accepted: AtomicU64,
+    circuits_denied: AtomicU64,
+    connections: AtomicU64,
+    connections_denied: AtomicU64,
+}
+
+impl RelayMetrics {
+    pub fn record(&self, event: &relay::Event) {
+        match event {
+            relay::Event::ReservationReqAccepted { renewed, .. } => {
+                self.reservations_accepted.fetch_add(1, Ordering::Relaxed);
+                if !renewed {
+                    self.reservations.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            relay::Event::ReservationReqDenied { .. } => {
+                self.reservations_denied.fetch_add(1, Ordering::Relaxed);
+            }
+            relay::Event::ReservationTimedOut { .. } => decrement(&self.reservations),
+            relay::Event::CircuitReqAccepted { .. } => {
+                self.circuits_accepted.fetch_add(1, Ordering::Relaxed);
+                self.circuits.fetch_add(1, Ordering::Relaxed);
+            }
+            relay::Event::CircuitReqDenied { .. } => {
+                self.circuits_denied.fetch_add(1, Ordering::Relaxed);
+            }
+            relay::Event::CircuitClosed { .. } => decrement(&self.circuits),
+            _ => {}
+        }
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        decrement(&self.connections);
+    }
+
+    /// A connection of a peer that isn't on the allowlist was refused
+    pub fn connection_denied(&self) {
+        self.connections_denied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn encode(&self) -> String {
+        let metrics = [
+            (
+                "relay_reservations",
+                "gauge",
+                "Reservations currently held",
+                &self.reservations,
+            ),
+            (
+                "relay_reservations_accepted_total",
+                "counter",
+                "Reservations accepted, including renewals",
+                &self.reservations_accepted,
+            ),
+            (
+                "relay_reservations_denied_total",
+                "counter",
+                "Reservations denied",
+                &self.reservations_denied,
+            ),
+            (
+                "relay_circuits",
+                "gauge",
+                "Circuits currently open",
+                &self.circuits,
+            ),
+            (
+                "relay_circuits_accepted_total",
+                "counter",
+                "Circuits accepted",
+                &self.circuits_accepted,
+            ),
+            (
+                "relay_circuits_denied_total",
+                "counter",
+                "Circuits denied",
+                &self.circuits_denied,
+            ),
+            (
+                "relay_connections",
+                "gauge",
+                "Connections currently open",
+                &self.connections,
+            ),
+            (
+                "relay_connections_denied_total",
+                "counter",
+                "Connections of peers that aren't allowed",
+                &self.connections_denied,
+            ),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {}\n",
+                value.load(Ordering::Relaxed)
+            ));
+        }
+        out
+    }
+}
+
+fn decrement(value: &AtomicU64) {
+    value
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+            Some(value.saturating_sub(1))
+        })
+        .ok();
+}
+
+/// Serves the metrics at `/metrics` until the listener fails
+pub async fn serve(addr: SocketAddr, metrics: Arc<RelayMetrics>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving metrics on http://{addr}/metrics");
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // Requests to scrape metrics fit in one read
+            let mut request = [0; 1024];
+            let read = match stream.read(&mut request).await {
+                Ok(read) => read,
+                Err(e) => {
+                    warn!(%peer, "Failed to read metrics request: {e}");
+                    return;
+                }
+            };
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let (status, body) = if path == "/metrics" {
+                ("200 OK", metrics.encode())
+            } else {
+                ("404 Not Found", String::new())
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                warn!(%peer, "Failed to send metrics: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::PeerId;
+
+    use super::*;
+
+    #[test]
+    fn test_record_reservations_and_circuits() {
+        let metrics = RelayMetrics::default();
+        let src_peer_id = PeerId::random();
+        let dst_peer_id = PeerId::random();
+        metrics.record(&relay::Event::ReservationReqAccepted {
+            src_peer_id,
+            renewed: false,
+        });
+        metrics.record(&relay::Event::ReservationReqAccepted {
+            src_peer_id,
+            renewed: true,
+        });
+        metrics.record(&relay::Event::CircuitReqAccepted {
+            src_peer_id,
+            dst_peer_id,
+        });
+        metrics.record(&relay::Event::CircuitClosed {
+            src_peer_id,
+            dst_peer_id,
+            error: None,
+        });
+        // Closing more than was opened doesn't wrap around
+        metrics.record(&relay::Event::CircuitClosed {
+            src_peer_id,
+            dst_peer_id,
+            error: None,
+        });
+
+        let encoded = metrics.encode();
+        assert!(encoded.contains("relay_reservations 1\n"));
+        assert!(encoded.contains("relay_reservations_accepted_total 2\n"));
+        assert!(encoded.contains("relay_circuits 0\n"));
+        assert!(encoded.contains("relay_circuits_accepted_total 1\n"));
+    }
+
+    #[test]
+    fn test_record_denials_and_connections() {
+        let metrics = RelayMetrics::default();
+        let src_peer_id = PeerId::random();
+        let dst_peer_id = PeerId::random();
+        metrics.record(&relay::Event::ReservationReqAccepted {
+            src_peer_id,
+            renewed: false,
+        });
+        metrics.record(&relay::Event::ReservationTimedOut { src_peer_id });
+        metrics.record(&relay::Event::ReservationReqDenied { src_peer_id });
+        metrics.record(&relay::Event::CircuitReqDenied {
+            src_peer_id,
+            dst_peer_id,
+        });
+        metrics.connection_opened();
+        metrics.connection_opened();
+        metrics.connection_closed();
+        metrics.connection_denied();
+
+        let encoded = metrics.encode();
+        assert!(encoded.contains("relay_reservations 0\n"));
+        assert!(encoded.contains("relay_reservations_denied_total 1\n"));
+        assert!(encoded.contains("relay_circuits 0\n"));
+        assert!(encoded.contains("relay_circuits_denied_total 1\n"));
+        assert!(encoded.contains("relay_connections 1\n"));
+        assert!(encoded.contains("relay_connections_denied_total 1\n"));
+        assert!(encoded.contains("# TYPE relay_connections gauge\n"));
+    }
+}
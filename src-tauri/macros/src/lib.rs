@@ -2,13 +2,11 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{ItemImpl, Path, PathArguments, PathSegment, Type, TypePath, parse_macro_input};
 
-/// A procedural attribute macro to automatically implement the `Askable` trait
-/// and call the `signed_impl` macro.
+/// A procedural attribute macro to automatically implement the `Askable` trait,
+/// which lets peers call the message through `actors::rpc`.
 ///
 /// This macro inspects an `impl Message<Actor>` block, extracts the necessary types,
-/// and generates two pieces of code:
-/// 1. An `impl Askable<Actor>` block with the correctly unwrapped reply type.
-/// 2. A call to `signed_impl!(Actor, MessageType)`.
+/// and generates an `impl Askable<Actor>` block with the correctly unwrapped reply type.
 #[proc_macro_attribute]
 pub fn askable(_attr: TokenStream, item: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree representing the `impl` block.
@@ -78,11 +76,6 @@ pub fn askable(_attr: TokenStream, item: TokenStream) -> TokenStream {
         impl crate::actors::Askable<#message_ty> for #self_ty {
             type ActualReply = #actual_reply_type;
         }
-
-        // Third, generate the call to the signed_impl macro, killing the second bird.
-        // We assume `signed_impl` is accessible via this path.
-        #[automatically_derived]
-        crate::signed_impl!(#message_ty, #self_ty);
     };
 
     // Return the generated code as a TokenStream.
@@ -0,0 +1,47 @@
+-- Document chunk embeddings live in the document vector store instead of `memory_vectors`,
+-- so `embedding_id` can no longer reference that table. SQLite can't drop a foreign key,
+-- so the table is rebuilt. Chunks are also removed together with their document now.
+
+CREATE TABLE document_chunks_new (
+    id BLOB PRIMARY KEY NOT NULL,
+    document_id BLOB NOT NULL,
+    parent_chunk_id BLOB, -- For hierarchical chunking
+    content TEXT NOT NULL,
+    content_hash TEXT NOT NULL, -- SHA256 for deduplication
+    chunk_type INTEGER NOT NULL DEFAULT 0, -- 0: 'semantic', 1: 'fixed-size', 2: 'paragraph', 3: 'sentence', 4: 'other'
+    order_index INTEGER NOT NULL, -- The order of the chunk in the document
+    semantic_level INTEGER DEFAULT 0, -- 0: 'header', 1: 'paragraph', 2: 'sentence', 3: 'other'
+    metadata TEXT CHECK (metadata IS NULL OR json_valid(metadata)), -- JSON with metadata
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    embedding_id BLOB, -- Id of the entry in the document vector store
+    workspace_id BLOB,
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE SET NULL,
+    FOREIGN KEY (document_id) REFERENCES documents(id) ON DELETE CASCADE,
+    FOREIGN KEY (parent_chunk_id) REFERENCES document_chunks(id) ON DELETE SET NULL
+);
+
+INSERT INTO document_chunks_new (
+    id, document_id, parent_chunk_id, content, content_hash, chunk_type, order_index,
+    semantic_level, metadata, created_at, updated_at, embedding_id, workspace_id
+)
+SELECT
+    id, document_id, parent_chunk_id, content, content_hash, chunk_type, order_index,
+    semantic_level, metadata, created_at, updated_at, embedding_id, workspace_id
+FROM document_chunks;
+
+DROP TABLE document_chunks;
+ALTER TABLE document_chunks_new RENAME TO document_chunks;
+
+CREATE INDEX idx_document_chunks_order ON document_chunks(document_id, order_index);
+CREATE INDEX idx_document_chunks_semantic ON document_chunks(document_id, semantic_level, order_index);
+CREATE INDEX idx_document_chunks_hash ON document_chunks(content_hash);
+CREATE INDEX idx_document_chunks_workspace_id ON document_chunks(workspace_id);
+CREATE INDEX idx_document_chunks_embedding_id ON document_chunks(embedding_id);
+
+CREATE TRIGGER trigger_document_chunks_updated_at
+AFTER UPDATE ON document_chunks
+FOR EACH ROW
+BEGIN
+    UPDATE document_chunks SET updated_at = CURRENT_TIMESTAMP WHERE id = OLD.id;
+END;
@@ -0,0 +1,103 @@
+-- Agent chains run a fixed sequence of agent steps, passing the output of each step on
+-- to the next one. Every run and every step of a run is recorded so that failed runs can
+-- be resumed from the last successful step.
+
+CREATE TABLE agent_chains (
+    id BLOB PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    status INTEGER NOT NULL DEFAULT 0, -- 0: 'ACTIVE', 1: 'INACTIVE', 2: 'ARCHIVED'
+    config TEXT CHECK (config IS NULL OR json_valid(config)), -- JSON object with configuration
+    metadata TEXT CHECK (metadata IS NULL OR json_valid(metadata)), -- JSON object with additional metadata
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    workspace_id BLOB,
+    created_by_id BLOB,
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_agent_chains_status ON agent_chains(status);
+CREATE INDEX idx_agent_chains_workspace_id ON agent_chains(workspace_id);
+
+CREATE TABLE agent_chain_steps (
+    id BLOB PRIMARY KEY NOT NULL,
+    chain_id BLOB NOT NULL,
+    step_number INTEGER NOT NULL, -- Steps run in ascending order
+    agent_id BLOB NOT NULL,
+    step_name TEXT NOT NULL,
+    step_type INTEGER NOT NULL DEFAULT 0, -- 0: 'AGENT', 1: 'DELEGATE'
+    input_template TEXT, -- Prompt template, `{{input}}` is replaced with the previous output
+    timeout_seconds INTEGER NOT NULL DEFAULT 300,
+    retry_count INTEGER NOT NULL DEFAULT 0,
+    config TEXT CHECK (config IS NULL OR json_valid(config)), -- JSON object with configuration
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (chain_id, step_number),
+    FOREIGN KEY (chain_id) REFERENCES agent_chains(id) ON DELETE CASCADE,
+    FOREIGN KEY (agent_id) REFERENCES agents(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_agent_chain_steps_chain_id ON agent_chain_steps(chain_id, step_number);
+CREATE INDEX idx_agent_chain_steps_agent_id ON agent_chain_steps(agent_id);
+
+CREATE TABLE agent_chain_executions (
+    id BLOB PRIMARY KEY NOT NULL,
+    chain_id BLOB NOT NULL,
+    conversation_id BLOB,
+    triggered_by_id BLOB, -- Participant that started the run
+    status INTEGER NOT NULL DEFAULT 0, -- 0: 'PENDING', 1: 'RUNNING', 2: 'COMPLETED', 3: 'FAILED', 4: 'CANCELLED'
+    current_step_id BLOB,
+    input_data TEXT,
+    output_data TEXT,
+    error_details TEXT,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (chain_id) REFERENCES agent_chains(id) ON DELETE CASCADE,
+    FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE SET NULL,
+    FOREIGN KEY (triggered_by_id) REFERENCES participants(id) ON DELETE SET NULL,
+    FOREIGN KEY (current_step_id) REFERENCES agent_chain_steps(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_agent_chain_executions_chain_id ON agent_chain_executions(chain_id);
+CREATE INDEX idx_agent_chain_executions_conversation_id ON agent_chain_executions(conversation_id);
+CREATE INDEX idx_agent_chain_executions_status ON agent_chain_executions(status);
+
+CREATE TABLE agent_chain_step_executions (
+    id BLOB PRIMARY KEY NOT NULL,
+    execution_id BLOB NOT NULL,
+    step_id BLOB NOT NULL,
+    agent_id BLOB NOT NULL,
+    delegated_by_id BLOB, -- Step execution of the operator that handed out this subtask
+    status INTEGER NOT NULL DEFAULT 0, -- 0: 'PENDING', 1: 'RUNNING', 2: 'COMPLETED', 3: 'FAILED', 4: 'CANCELLED'
+    input_data TEXT,
+    output_data TEXT,
+    error_details TEXT,
+    retry_attempt INTEGER NOT NULL DEFAULT 0,
+    started_at TIMESTAMP,
+    completed_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (execution_id) REFERENCES agent_chain_executions(id) ON DELETE CASCADE,
+    FOREIGN KEY (step_id) REFERENCES agent_chain_steps(id) ON DELETE CASCADE,
+    FOREIGN KEY (agent_id) REFERENCES agents(id) ON DELETE CASCADE,
+    FOREIGN KEY (delegated_by_id) REFERENCES agent_chain_step_executions(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_agent_chain_step_executions_execution_id ON agent_chain_step_executions(execution_id);
+CREATE INDEX idx_agent_chain_step_executions_step_id ON agent_chain_step_executions(step_id);
+CREATE INDEX idx_agent_chain_step_executions_status ON agent_chain_step_executions(status);
+
+CREATE TRIGGER trigger_agent_chains_updated_at
+AFTER UPDATE ON agent_chains
+FOR EACH ROW
+BEGIN
+    UPDATE agent_chains SET updated_at = CURRENT_TIMESTAMP WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER trigger_agent_chain_steps_updated_at
+AFTER UPDATE ON agent_chain_steps
+FOR EACH ROW
+BEGIN
+    UPDATE agent_chain_steps SET updated_at = CURRENT_TIMESTAMP WHERE id = OLD.id;
+END;
@@ -0,0 +1,26 @@
+-- Permissions of remote peers. Every message a peer sends to our gateway is checked
+-- against the peer's row, and every decision is recorded in `audit_log`. Peers that
+-- don't belong to a contact start out pending until the user approves them.
+
+CREATE TABLE peer_permissions (
+    id BLOB PRIMARY KEY NOT NULL,
+    peer_id BLOB NOT NULL UNIQUE,
+    contact_id BLOB, -- The contact the peer belongs to, if it is known
+    status INTEGER NOT NULL DEFAULT 0, -- 0: 'PENDING', 1: 'ALLOWED', 2: 'DENIED'
+    allowed_messages TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(allowed_messages)), -- JSON array of message kinds
+    allowed_tools TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(allowed_tools)), -- JSON array of tool names
+    allowed_agents TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(allowed_agents)), -- JSON array of agent ids
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (contact_id) REFERENCES contacts(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_peer_permissions_status ON peer_permissions(status);
+CREATE INDEX idx_peer_permissions_contact_id ON peer_permissions(contact_id);
+
+CREATE TRIGGER trigger_peer_permissions_updated_at
+AFTER UPDATE ON peer_permissions
+FOR EACH ROW
+BEGIN
+    UPDATE peer_permissions SET updated_at = CURRENT_TIMESTAMP WHERE id = OLD.id;
+END;
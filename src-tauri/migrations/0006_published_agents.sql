@@ -0,0 +1,29 @@
+-- Local agents that peers may run on this machine. Peers still need the agent in the
+-- `allowed_agents` of their permissions. The limits apply to every peer separately,
+-- and the tokens each peer used are counted per day in `peer_agent_usage`.
+
+CREATE TABLE published_agents (
+    agent_id BLOB PRIMARY KEY NOT NULL,
+    max_concurrent_requests INTEGER NOT NULL DEFAULT 1, -- Requests a peer may run at the same time
+    daily_token_quota INTEGER, -- Tokens a peer may use per day, NULL for no limit
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (agent_id) REFERENCES agents(id) ON DELETE CASCADE
+);
+
+CREATE TABLE peer_agent_usage (
+    peer_id BLOB NOT NULL,
+    agent_id BLOB NOT NULL,
+    day DATE NOT NULL, -- UTC day the usage is counted for
+    requests INTEGER NOT NULL DEFAULT 0,
+    tokens INTEGER NOT NULL DEFAULT 0, -- Estimated prompt and response tokens
+    PRIMARY KEY (peer_id, agent_id, day),
+    FOREIGN KEY (agent_id) REFERENCES agents(id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER trigger_published_agents_updated_at
+AFTER UPDATE ON published_agents
+FOR EACH ROW
+BEGIN
+    UPDATE published_agents SET updated_at = CURRENT_TIMESTAMP WHERE agent_id = OLD.agent_id;
+END;
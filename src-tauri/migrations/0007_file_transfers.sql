@@ -0,0 +1,33 @@
+-- Files sent to and received from peers. The sender splits a file into chunks that
+-- are addressed by their SHA-256 hash, and the receiver fetches the chunks it doesn't
+-- have yet, so that a transfer resumes where it stopped after a disconnect. An outgoing
+-- row is what allows the peer to fetch the file.
+
+CREATE TABLE file_transfers (
+    id BLOB PRIMARY KEY NOT NULL,
+    file_id BLOB NOT NULL, -- Id the sender gave the file
+    peer_id BLOB NOT NULL, -- The peer the file is sent to or received from
+    direction INTEGER NOT NULL, -- 0: 'OUTGOING', 1: 'INCOMING'
+    status INTEGER NOT NULL DEFAULT 0, -- 0: 'PENDING', 1: 'ACTIVE', 2: 'PAUSED', 3: 'COMPLETED', 4: 'FAILED', 5: 'CANCELLED'
+    file_name TEXT NOT NULL,
+    file_path TEXT NOT NULL, -- Read from when sending, written to when receiving
+    size INTEGER NOT NULL,
+    hash TEXT NOT NULL, -- SHA-256 of the whole file, hex encoded
+    chunk_size INTEGER NOT NULL,
+    chunks TEXT NOT NULL CHECK (json_valid(chunks)), -- JSON array of the chunk hashes
+    done_chunks TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(done_chunks)), -- JSON array of the indexes of the chunks sent or received
+    error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (file_id, peer_id, direction)
+);
+
+CREATE INDEX idx_file_transfers_peer_id ON file_transfers(peer_id);
+CREATE INDEX idx_file_transfers_status ON file_transfers(status);
+
+CREATE TRIGGER trigger_file_transfers_updated_at
+AFTER UPDATE ON file_transfers
+FOR EACH ROW
+BEGIN
+    UPDATE file_transfers SET updated_at = CURRENT_TIMESTAMP WHERE id = OLD.id;
+END;
@@ -0,0 +1,41 @@
+-- The certificates of peers. A peer's device key, its peer id, is certified by the user
+-- key of its owner. Peers announce key rotations signed by the old and the new key, and
+-- the trust we put in the old key moves to the new one.
+
+CREATE TABLE peer_certificates (
+    peer_id BLOB PRIMARY KEY NOT NULL,
+    user_key BLOB NOT NULL, -- Protobuf encoded public key of the user the device belongs to
+    certificate TEXT NOT NULL CHECK (json_valid(certificate)), -- The user key's certificate of the device key
+    replaced_by BLOB, -- Peer id of the device key that replaced this one
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_peer_certificates_user_key ON peer_certificates(user_key);
+
+CREATE TABLE key_rotations (
+    id BLOB PRIMARY KEY NOT NULL,
+    peer_id BLOB NOT NULL, -- The peer that announced the rotation
+    kind INTEGER NOT NULL, -- 0: 'USER', 1: 'DEVICE'
+    old_key BLOB NOT NULL,
+    new_key BLOB NOT NULL,
+    statement TEXT NOT NULL CHECK (json_valid(statement)), -- The signed rotation
+    rotated_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (old_key, new_key)
+);
+
+CREATE INDEX idx_key_rotations_peer_id ON key_rotations(peer_id);
+
+CREATE TRIGGER trigger_peer_certificates_updated_at
+AFTER UPDATE ON peer_certificates
+FOR EACH ROW
+BEGIN
+    UPDATE peer_certificates SET updated_at = CURRENT_TIMESTAMP WHERE peer_id = OLD.peer_id;
+END;
+
+-- Peers that were allowed before may announce key rotations as well
+UPDATE peer_permissions
+SET allowed_messages = json_insert(allowed_messages, '$[#]', 'keyRotation')
+WHERE status = 1
+    AND NOT EXISTS (SELECT 1 FROM json_each(allowed_messages) WHERE value = 'keyRotation');
@@ -0,0 +1,62 @@
+-- MCP servers are connected to over stdio or HTTP and their tools are offered to agents.
+-- The tables never matched the entities, so they can't hold rows written by the app and
+-- are created anew. Tools keep the input schema the server declared, and are synced by
+-- name.
+
+DROP TABLE mcp_tools;
+DROP TABLE mcp_servers;
+
+CREATE TABLE mcp_servers (
+    id BLOB PRIMARY KEY NOT NULL,
+    workspace_id BLOB,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    transport INTEGER NOT NULL DEFAULT 0, -- 0: 'STDIO', 1: 'HTTP', 2: 'SSE'
+    url TEXT, -- Endpoint of HTTP and SSE servers
+    command TEXT, -- Program started for stdio servers
+    args TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(args)), -- JSON array of the program's arguments
+    env TEXT NOT NULL DEFAULT '{}' CHECK (json_valid(env)), -- JSON object of environment variables of the program
+    api_key TEXT,
+    auth_token TEXT,
+    is_active BOOLEAN NOT NULL DEFAULT 1, -- Whether the server is connected to
+    is_default BOOLEAN NOT NULL DEFAULT 0,
+    capabilities TEXT CHECK (capabilities IS NULL OR json_valid(capabilities)), -- JSON object the server declared when it was initialized
+    server_info TEXT CHECK (server_info IS NULL OR json_valid(server_info)), -- JSON object with the server's name and version
+    connection_status INTEGER NOT NULL DEFAULT 0, -- 0: 'DISCONNECTED', 1: 'CONNECTING', 2: 'CONNECTED', 3: 'FAILED'
+    last_error TEXT,
+    last_connected_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_mcp_servers_workspace_id ON mcp_servers(workspace_id);
+
+CREATE TABLE mcp_tools (
+    id BLOB PRIMARY KEY NOT NULL,
+    mcp_server_id BLOB NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    input_schema TEXT NOT NULL DEFAULT '{}' CHECK (json_valid(input_schema)), -- JSON schema of the arguments
+    is_enabled BOOLEAN NOT NULL DEFAULT 0, -- 0: false, 1: true
+    type TEXT NOT NULL, -- 'REST', 'GRPC', 'LOCAL'
+    status INTEGER NOT NULL DEFAULT 0, -- 'ACTIVE', 'ARCHIVED', 'DELETED'
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (mcp_server_id, name),
+    FOREIGN KEY (mcp_server_id) REFERENCES mcp_servers(id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER trigger_mcp_servers_updated_at
+AFTER UPDATE ON mcp_servers
+FOR EACH ROW
+BEGIN
+    UPDATE mcp_servers SET updated_at = CURRENT_TIMESTAMP WHERE id = OLD.id;
+END;
+
+CREATE TRIGGER trigger_mcp_tools_updated_at
+AFTER UPDATE ON mcp_tools
+FOR EACH ROW
+BEGIN
+    UPDATE mcp_tools SET updated_at = CURRENT_TIMESTAMP WHERE id = OLD.id;
+END;
@@ -0,0 +1,56 @@
+-- Views the `query_database` tool may read. The tool refuses every table, so these views
+-- decide what agents can see: tasks, events and notes, without metadata or anything
+-- linking them to accounts and credentials.
+
+CREATE VIEW tool_tasks AS
+SELECT
+    id,
+    title,
+    description,
+    CASE status WHEN 0 THEN 'pending' WHEN 1 THEN 'inprogress' WHEN 2 THEN 'completed' ELSE 'failed' END AS status,
+    CASE priority WHEN 0 THEN 'low' WHEN 1 THEN 'medium' ELSE 'high' END AS priority,
+    CASE importance WHEN 0 THEN 'low' WHEN 1 THEN 'medium' ELSE 'high' END AS importance,
+    start_time,
+    end_time,
+    due_date,
+    tags,
+    url,
+    workspace_id,
+    plan_id,
+    created_at,
+    updated_at
+FROM tasks;
+
+CREATE VIEW tool_events AS
+SELECT
+    id,
+    title,
+    description,
+    CASE event_type WHEN 0 THEN 'meeting' WHEN 1 THEN 'call' WHEN 2 THEN 'email' WHEN 3 THEN 'task' ELSE 'other' END AS event_type,
+    CASE status WHEN 0 THEN 'scheduled' WHEN 1 THEN 'completed' WHEN 2 THEN 'cancelled' ELSE 'rescheduled' END AS status,
+    start_time,
+    end_time,
+    is_all_day_event,
+    location,
+    agenda,
+    summary,
+    task_id,
+    workspace_id,
+    created_at,
+    updated_at
+FROM events
+WHERE is_private = 0;
+
+CREATE VIEW tool_notes AS
+SELECT
+    id,
+    CASE type WHEN 0 THEN 'note' WHEN 1 THEN 'task' WHEN 2 THEN 'event' ELSE 'other' END AS note_type,
+    title,
+    content,
+    parent_note_id,
+    task_id,
+    event_id,
+    workspace_id,
+    created_at,
+    updated_at
+FROM notes;
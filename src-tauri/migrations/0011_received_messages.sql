@@ -0,0 +1,11 @@
+-- Signed messages peers delivered to us from their queues. Queued messages can be older
+-- than the replay window of the gateway, so they are remembered for good to keep a peer
+-- from delivering a message it captured again in a new delivery.
+
+CREATE TABLE received_messages (
+    peer_id BLOB NOT NULL, -- The peer that signed the message
+    nonce BLOB NOT NULL,
+    issued_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (peer_id, nonce)
+);
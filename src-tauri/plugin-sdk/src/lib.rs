@@ -0,0 +1,80 @@
+//! SDK for WebAssembly plugins of Evo
+//!
+//! Plugins are WebAssembly components built for `wasm32-wasip2`. A plugin implements
+//! [`Guest`] and exports it with [`export_plugin!`]:
+//!
+//! ```ignore
+//! use evo_plugin_sdk::{Guest, export_plugin, tools};
+//!
+//! struct Greeter;
+//!
+//! impl Guest for Greeter {
+//!     fn initialize(_config: String) -> Result<(), String> {
+//!         tools::register_tool(&tools::ToolDefinition {
+//!             name: "greet".to_string(),
+//!             description: "Greets someone by name".to_string(),
+//!             parameters: r#"{"type":"object","properties":{"name":{"type":"string"}}}"#
+//!                 .to_string(),
+//!         })
+//!     }
+//!
+//!     fn start() -> Result<(), String> {
+//!         Ok(())
+//!     }
+//!
+//!     fn stop() -> Result<(), String> {
+//!         Ok(())
+//!     }
+//!
+//!     fn call_tool(_name: String, args: String) -> Result<String, String> {
+//!         let args: serde_json::Value = serde_json::from_str(&args).map_err(|e| e.to_string())?;
+//!         Ok(serde_json::json!(format!("Hello, {}!", args["name"])).to_string())
+//!     }
+//!
+//!     fn handle_event(_event_type: String, _data: String) -> Result<(), String> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! export_plugin!(Greeter);
+//! ```
+//!
+//! The host interfaces can only be used with the matching permission in the plugin's
+//! `manifest.json`, granted by the user. A plugin that uses an interface it wasn't
+//! granted fails to load, and calls outside of what was granted, e.g. requests to
+//! other hosts, return an error.
+
+use serde::{Serialize, de::DeserializeOwned};
+
+wit_bindgen::generate!({
+    path: "wit",
+    world: "evo-plugin",
+    pub_export_macro: true,
+    export_macro_name: "export_plugin",
+    default_bindings_module: "evo_plugin_sdk",
+});
+
+pub use evo::plugin::{events, filesystem, network, storage, tools};
+pub use exports::evo::plugin::guest::Guest;
+
+/// Reads a value stored as JSON
+pub fn get_json<T: DeserializeOwned>(key: &str) -> Result<Option<T>, String> {
+    match storage::get(key)? {
+        Some(value) => serde_json::from_str(&value)
+            .map(Some)
+            .map_err(|e| format!("Invalid value of {key}: {e}")),
+        None => Ok(None),
+    }
+}
+
+/// Stores a value as JSON
+pub fn set_json<T: Serialize>(key: &str, value: &T) -> Result<(), String> {
+    let value = serde_json::to_string(value).map_err(|e| e.to_string())?;
+    storage::set(key, &value)
+}
+
+/// Publishes an event with its data as JSON
+pub fn emit<T: Serialize>(event_type: &str, data: &T) -> Result<(), String> {
+    let data = serde_json::to_string(data).map_err(|e| e.to_string())?;
+    events::emit(event_type, &data)
+}
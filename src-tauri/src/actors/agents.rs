@@ -1,35 +1,45 @@
-use std::sync::Arc;
+use std::collections::HashMap;
 
 use color_eyre::eyre::eyre;
 use futures_util::StreamExt;
 use kameo::prelude::{ActorRef as LocalActorRef, *};
-use kameo_actors::{message_bus::Publish, pool::ActorPool};
+use kameo_actors::{
+    message_bus::Publish,
+    pool::{ActorPool, WorkerMsg},
+};
 use rig::{
     agent::AgentBuilder,
-    client::{CompletionClient, ProviderClient, completion::CompletionModelHandle},
-    completion::{CompletionModel, Message as RigMessage, ToolDefinition},
+    completion::{Message as RigMessage, Prompt, ToolDefinition},
     message::{AssistantContent, ToolCall},
-    providers::{anthropic, cohere, deepseek, gemini, ollama, openai, perplexity, xai},
     streaming::StreamingChat,
 };
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use sync_wrapper::SyncFuture;
+use tokio::sync::mpsc;
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::{
     actors::{
         ActorRef, SystemEventBus,
+        context::{ContextTruncation, prepare_context},
+        database::{DatabaseActor, GetAgent, GetModel, ListAgentTools},
+        memory::{self, RECALL_LIMIT, RecallMemories, recalled_context},
+        providers::ProviderRegistry,
+        rpc,
         tools::{ToolExecutorActor, UseTool},
     },
-    entities::{Agent, Model, ModelProvider},
+    entities::{Agent, Model},
     error::{AppError, Result},
-    utils::tell_ask,
+    tools::{offered_to, selected_tools},
 };
 
 #[derive(Actor)]
 pub struct AgentActor {
     pub bus: LocalActorRef<SystemEventBus>,
+    pub db: LocalActorRef<DatabaseActor>,
+    pub providers: ProviderRegistry,
 }
 
 #[derive(Actor)]
@@ -38,39 +48,6 @@ pub struct AgentManagerActor {
     pub pool: LocalActorRef<ActorPool<AgentActor>>,
 }
 
-fn provider_to_model<'a>(
-    provider: ModelProvider,
-    name: &'a str,
-    _api_key: Option<&'a str>,
-) -> impl CompletionModel {
-    match provider {
-        ModelProvider::OpenAI => CompletionModelHandle {
-            inner: Arc::new(openai::Client::from_env().completion_model(name)),
-        },
-        ModelProvider::Cohere => CompletionModelHandle {
-            inner: Arc::new(cohere::Client::from_env().completion_model(name)),
-        },
-        ModelProvider::Anthropic => CompletionModelHandle {
-            inner: Arc::new(anthropic::Client::from_env().completion_model(name)),
-        },
-        ModelProvider::Perplexity => CompletionModelHandle {
-            inner: Arc::new(perplexity::Client::from_env().completion_model(name)),
-        },
-        ModelProvider::Gemini => CompletionModelHandle {
-            inner: Arc::new(gemini::Client::from_env().completion_model(name)),
-        },
-        ModelProvider::XAi => CompletionModelHandle {
-            inner: Arc::new(xai::Client::from_env().completion_model(name)),
-        },
-        ModelProvider::DeepSeek => CompletionModelHandle {
-            inner: Arc::new(deepseek::Client::from_env().completion_model(name)),
-        },
-        ModelProvider::Ollama => CompletionModelHandle {
-            inner: Arc::new(ollama::Client::from_env().completion_model(name)),
-        },
-    }
-}
-
 impl Message<AgentRequest> for AgentActor {
     type Reply = Result<()>;
     async fn handle(
@@ -81,37 +58,142 @@ impl Message<AgentRequest> for AgentActor {
         let mut agent_res = AgentResponseEvent {
             agent_id: msg.agent.id,
             conversation_id: msg.conversation_id,
+            turn_id: Uuid::new_v4(),
             workspace_id: msg.agent.workspace_id.unwrap_or_default(),
             response: StreamedPart::Error("Unstarted".to_string()),
         };
         let Some(tool_ref) = msg.tool_ref else {
             return Err(eyre!("No tool ref provided").into());
         };
-        let model = provider_to_model(msg.model.provider, &msg.model.name, None);
+        let sink = msg
+            .sink
+            .unwrap_or_else(|| ResponseSink::Bus(self.bus.clone()));
+        let provider = match self.providers.resolve(&msg.agent, &msg.model).await {
+            Ok(provider) => provider,
+            Err(e) => {
+                agent_res.response = StreamedPart::Error(e.to_string());
+                sink.send(&agent_res).await;
+                return Err(e);
+            }
+        };
+        let recalled = match memory::recall(
+            &self.db,
+            &self.providers,
+            RecallMemories {
+                agent_id: msg.agent.id,
+                workspace_id: msg.agent.workspace_id,
+                query: msg.prompt.clone(),
+                limit: RECALL_LIMIT,
+            },
+        )
+        .await
+        {
+            Ok(recalled) => recalled_context(&recalled),
+            Err(e) => {
+                warn!(agent_id = %msg.agent.id, "Failed to recall memories: {e}");
+                None
+            }
+        };
+        // Built-in tools are offered only to the agents that selected them
+        let linked = match self.db.ask(ListAgentTools(msg.agent.id)).await {
+            Ok(tools) => tools,
+            Err(e) => {
+                warn!(agent_id = %msg.agent.id, "Failed to list the tools of the agent: {e}");
+                Vec::new()
+            }
+        };
+        let tool_definitions = offered_to(
+            msg.tool_definitions,
+            &selected_tools(
+                msg.agent.tool_config.as_deref(),
+                linked.into_iter().map(|tool| tool.name),
+            ),
+        );
+        let context = match prepare_context(
+            &self.db,
+            &self.providers,
+            &provider,
+            &msg.agent,
+            &msg.model,
+            msg.conversation_id,
+            &msg.prompt,
+            recalled.as_deref(),
+            &tool_definitions,
+            msg.history,
+        )
+        .await
+        {
+            Ok(context) => context,
+            Err(e) => {
+                agent_res.response = StreamedPart::Error(e.to_string());
+                sink.send(&agent_res).await;
+                return Err(e);
+            }
+        };
+        if let Some(truncation) = context.truncation {
+            agent_res.response = StreamedPart::ContextTruncated(truncation);
+            sink.send(&agent_res).await;
+        }
+        let model = self
+            .providers
+            .completion_model(&provider, &msg.model.name)
+            .await;
         let mut agent = AgentBuilder::new(model);
-        for tool in msg.tool_definitions {
-            agent = agent.tool(SandboxedTool {
+        if let Some(recalled) = &recalled {
+            agent = agent.context(recalled);
+        }
+        if let Some(summary) = &context.summary {
+            agent = agent.context(&format!("Summary of the earlier conversation:\n{summary}"));
+        }
+        let mut tools = HashMap::new();
+        for tool in tool_definitions {
+            let tool = SandboxedTool {
                 definition: tool,
                 actor_ref: tool_ref.clone(),
-            });
+            };
+            tools.insert(tool.definition.name.clone(), tool.clone());
+            agent = agent.tool(tool);
         }
         let agent = agent.build();
-        let mut stream = agent.stream_chat(msg.prompt, msg.history).await?;
+        let mut stream = agent.stream_chat(msg.prompt, context.history).await?;
         while let Some(part) = stream.next().await {
             let part = match part {
                 Ok(k) => k,
                 Err(e) => {
                     agent_res.response = StreamedPart::Error(e.to_string());
-                    self.bus.tell(Publish(agent_res.clone())).await.ok();
+                    sink.send(&agent_res).await;
                     return Err(e.into());
                 }
             };
-            let stream_part = match part {
-                AssistantContent::Text(text) => StreamedPart::Token(text.text),
-                AssistantContent::ToolCall(tool_call) => StreamedPart::ToolCall(tool_call),
+            let tool_call = match part {
+                AssistantContent::Text(text) => {
+                    agent_res.response = StreamedPart::Token(text.text);
+                    sink.send(&agent_res).await;
+                    continue;
+                }
+                AssistantContent::ToolCall(tool_call) => tool_call,
+            };
+            agent_res.response = StreamedPart::ToolCall(tool_call.clone());
+            sink.send(&agent_res).await;
+            // Tools of a streamed response are run by us, so that their results can be
+            // reported with the id of the call
+            let output = match tools.get(&tool_call.function.name) {
+                Some(tool) => {
+                    rig::tool::Tool::call(tool, tool_call.function.arguments.to_string()).await
+                }
+                None => Err(AppError::not_found("Tool", &tool_call.function.name)),
+            };
+            agent_res.response = StreamedPart::ToolResult {
+                tool_call_id: tool_call.id,
+                tool_name: tool_call.function.name,
+                tool_output: match output {
+                    Ok(output) => {
+                        serde_json::from_str(&output).unwrap_or(serde_json::Value::String(output))
+                    }
+                    Err(e) => serde_json::json!({ "error": e.to_string() }),
+                },
             };
-            agent_res.response = stream_part;
-            self.bus.tell(Publish(agent_res.clone())).await.ok();
+            sink.send(&agent_res).await;
         }
         let (full_response, tool_calls) = stream.choice.into_iter().fold(
             (String::new(), Vec::new()),
@@ -127,11 +209,82 @@ impl Message<AgentRequest> for AgentActor {
             full_response,
             tool_calls,
         };
-        self.bus.tell(Publish(agent_res.clone())).await.ok();
+        sink.send(&agent_res).await;
         Ok(())
     }
 }
 
+impl Message<AgentRequest> for AgentManagerActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: AgentRequest,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        // Responses are streamed back over the bus, so there is no need to wait
+        // for the worker to finish the whole turn here.
+        self.pool.tell(WorkerMsg(msg)).await?;
+        Ok(())
+    }
+}
+
+/// Loads an agent together with its model
+pub(crate) async fn agent_model(
+    db: &LocalActorRef<DatabaseActor>,
+    agent_id: Uuid,
+) -> Result<(Agent, Model)> {
+    let agent = db
+        .ask(GetAgent(agent_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Agent", agent_id))?;
+    let model_id = agent
+        .model_id
+        .ok_or_else(|| AppError::configuration(format!("Agent {agent_id} has no model")))?;
+    let model = db
+        .ask(GetModel(model_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Model", model_id))?;
+    Ok((agent, model))
+}
+
+/// The system prompt of an agent: the `systemPrompt` of its config or its description
+fn preamble(agent: &Agent) -> String {
+    agent
+        .config
+        .as_ref()
+        .and_then(|config| config.0.get("systemPrompt"))
+        .and_then(|prompt| prompt.as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| match &agent.description {
+            Some(description) => format!("You are {}. {description}", agent.name),
+            None => format!("You are {}.", agent.name),
+        })
+}
+
+/// Prompts an agent once, without history or tools. Used where agents work on a task
+/// outside of a conversation, e.g. in chains and workflows.
+pub(crate) async fn prompt_agent(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    agent_id: Uuid,
+    instructions: Option<&str>,
+    prompt: String,
+) -> Result<String> {
+    let (agent, model) = agent_model(db, agent_id).await?;
+    let provider = providers.resolve(&agent, &model).await?;
+    let completion_model = providers.completion_model(&provider, &model.name).await;
+    let mut builder = AgentBuilder::new(completion_model).preamble(&preamble(&agent));
+    if let Some(instructions) = instructions {
+        builder = builder.context(instructions);
+    }
+    builder
+        .build()
+        .prompt(prompt)
+        .await
+        .map_err(|e| AppError::external_service(format!("Agent {agent_id} failed: {e}")))
+}
+
 #[derive(Clone)]
 pub struct SandboxedTool {
     pub definition: ToolDefinition,
@@ -163,7 +316,8 @@ impl rig::tool::Tool for SandboxedTool {
             Ok(match &self.actor_ref {
                 ActorRef::Local(actor_ref) => actor_ref.ask(msg).await?,
                 ActorRef::Remote(actor_ref) => {
-                    tell_ask::<_, ToolExecutorActor>(actor_ref, msg).await??
+                    rpc::call::<ToolExecutorActor, _>(actor_ref, msg, rpc::DEFAULT_DEADLINE)
+                        .await??
                 }
             })
         })
@@ -186,12 +340,41 @@ pub struct AgentRequest {
     pub participants: Vec<Uuid>,
     #[serde(skip, default)]
     pub tool_ref: Option<ActorRef<ToolExecutorActor>>,
+    /// Where the response goes, the bus unless the request was made by a peer
+    #[serde(skip, default)]
+    pub sink: Option<ResponseSink>,
+}
+
+/// Where the parts of an agent's response are sent
+#[derive(Clone)]
+pub enum ResponseSink {
+    /// Published as [`AgentResponseEvent`]s for the local conversation
+    Bus(LocalActorRef<SystemEventBus>),
+    /// Streamed back to the peer that requested the response
+    Stream(mpsc::UnboundedSender<StreamedPart>),
+}
+
+impl ResponseSink {
+    async fn send(&self, event: &AgentResponseEvent) {
+        match self {
+            ResponseSink::Bus(bus) => {
+                bus.tell(Publish(event.clone())).await.ok();
+            }
+            ResponseSink::Stream(tx) => {
+                // The peer stopped listening, the turn still finishes to count its tokens
+                tx.send(event.response.clone()).ok();
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentResponseEvent {
     pub agent_id: Uuid,
     pub conversation_id: Uuid,
+    /// Identifies the turn the part belongs to, as an agent may take several turns in
+    /// a conversation at once
+    pub turn_id: Uuid,
     pub workspace_id: Uuid,
     pub response: StreamedPart,
 }
@@ -211,6 +394,7 @@ pub enum StreamedPart {
     ToolCall(ToolCall),
     /// The result from a tool call.
     ToolResult {
+        tool_call_id: String,
         tool_name: String,
         tool_output: serde_json::Value,
     },
@@ -219,6 +403,8 @@ pub enum StreamedPart {
         full_response: String,
         tool_calls: Vec<ToolCall>,
     },
+    /// Older messages were left out of the history to fit the agent's context window.
+    ContextTruncated(ContextTruncation),
     /// An error occurred that should be displayed to the user.
     Error(String),
 }
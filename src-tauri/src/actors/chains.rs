@@ -0,0 +1,632 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use kameo_actors::message_bus::Publish;
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        SystemEventBus,
+        agents::{agent_model, prompt_agent},
+        database::{
+            DatabaseActor, GetAgentChain, GetAgentChainExecution, ListAgentChainStepExecutions,
+            ListAgentChainSteps, ListAgents, UpdateAgentChainExecution,
+            UpdateAgentChainStepExecution,
+        },
+        providers::ProviderRegistry,
+    },
+    entities::{
+        Agent, AgentChainExecution, AgentChainStatus, AgentChainStep, AgentChainStepExecution,
+        AgentChainStepType, AgentFilter, AgentStatus, AgentType, CreateAgentChainExecution,
+        CreateAgentChainStepExecution, ExecutionStatus,
+    },
+    error::{AppError, Result},
+};
+
+/// Upper bound for the subtasks an operator may hand out in a single step
+const MAX_SUBTASKS: usize = 8;
+
+const DELEGATION_PREAMBLE: &str = "You are an operator that splits a task into subtasks for \
+    the workers listed below. Reply with a JSON array of objects with the fields `agentId` (the \
+    id of a listed worker) and `task` (a self-contained description of the subtask). Only use \
+    the listed workers.";
+
+const SYNTHESIS_PROMPT: &str = "Your workers finished their subtasks. Combine their results \
+    into the answer to the original task.";
+
+/// Runs agent chains step by step and hands subtasks of operator steps to worker agents
+#[derive(Actor)]
+pub struct ChainExecutorActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub providers: ProviderRegistry,
+    pub bus: LocalActorRef<SystemEventBus>,
+    /// Runs that are in progress, so that they can be cancelled
+    pub running: HashMap<Uuid, AbortHandle>,
+}
+
+/// An entry of an operator's `delegation_rules`.
+/// A worker is eligible if it matches any of the rules.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DelegationRule {
+    /// Worker the rule applies to. Without it the rule applies to all direct workers.
+    pub agent_id: Option<Uuid>,
+    pub agent_type: Option<AgentType>,
+    pub max_subtasks: Option<usize>,
+}
+
+impl DelegationRule {
+    fn matches(&self, worker: &Agent) -> bool {
+        self.agent_id.is_none_or(|agent_id| agent_id == worker.id)
+            && self
+                .agent_type
+                .is_none_or(|agent_type| agent_type == worker.agent_type)
+    }
+}
+
+/// The delegation rules of an operator. Operators without rules may delegate to all
+/// of their direct workers.
+fn delegation_rules(operator: &Agent) -> Result<Vec<DelegationRule>> {
+    match &operator.delegation_rules {
+        Some(rules) => Ok(serde_json::from_value(rules.0.clone())?),
+        None => Ok(vec![DelegationRule::default()]),
+    }
+}
+
+/// Workers an operator may delegate to: its direct workers and workers named by its
+/// rules that sit below it in the hierarchy and match one of its rules
+fn eligible_workers(
+    operator: &Agent,
+    rules: &[DelegationRule],
+    candidates: Vec<Agent>,
+) -> Vec<Agent> {
+    candidates
+        .into_iter()
+        .filter(|worker| worker.id != operator.id && worker.status == AgentStatus::Active)
+        .filter(|worker| worker.operator_level > operator.operator_level)
+        .filter(|worker| {
+            rules.iter().any(|rule| {
+                rule.matches(worker)
+                    && (rule.agent_id.is_some() || worker.parent_agent_id == Some(operator.id))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Subtask {
+    agent_id: Uuid,
+    task: String,
+}
+
+/// Reads the JSON array of subtasks out of the operator's reply
+fn parse_subtasks(reply: &str) -> Result<Vec<Subtask>> {
+    let span = reply
+        .find('[')
+        .zip(reply.rfind(']'))
+        .filter(|(start, end)| start < end);
+    let Some((start, end)) = span else {
+        return Err(AppError::external_service(
+            "Operator didn't return a JSON array of subtasks",
+        ));
+    };
+    Ok(serde_json::from_str(&reply[start..=end])?)
+}
+
+/// Fills in the input template of a step
+fn render_input(template: Option<&str>, input: &str, chain_input: &str) -> String {
+    match template {
+        Some(template) => template
+            .replace("{{input}}", input)
+            .replace("{{chain_input}}", chain_input),
+        None => input.to_string(),
+    }
+}
+
+/// Everything a chain run needs, shared by the steps of the run
+#[derive(Clone)]
+struct ChainRun {
+    db: LocalActorRef<DatabaseActor>,
+    providers: ProviderRegistry,
+    bus: LocalActorRef<SystemEventBus>,
+}
+
+impl ChainRun {
+    async fn prompt_agent(
+        &self,
+        agent_id: Uuid,
+        instructions: Option<&str>,
+        prompt: String,
+    ) -> Result<String> {
+        prompt_agent(&self.db, &self.providers, agent_id, instructions, prompt).await
+    }
+
+    async fn record(
+        &self,
+        chain_id: Uuid,
+        step_execution: CreateAgentChainStepExecution,
+    ) -> Result<AgentChainStepExecution> {
+        let step_execution = self.db.ask(step_execution).await?;
+        self.publish(chain_id, &step_execution).await;
+        Ok(step_execution)
+    }
+
+    async fn finish(
+        &self,
+        chain_id: Uuid,
+        mut step_execution: AgentChainStepExecution,
+        result: &Result<String>,
+    ) -> Result<()> {
+        match result {
+            Ok(output) => {
+                step_execution.status = ExecutionStatus::Completed;
+                step_execution.output_data = Some(output.clone());
+            }
+            Err(e) => {
+                step_execution.status = ExecutionStatus::Failed;
+                step_execution.error_details = Some(e.to_string());
+            }
+        }
+        step_execution.completed_at = Some(Utc::now());
+        let step_execution = self
+            .db
+            .ask(UpdateAgentChainStepExecution(step_execution))
+            .await?;
+        self.publish(chain_id, &step_execution).await;
+        Ok(())
+    }
+
+    async fn publish(&self, chain_id: Uuid, step_execution: &AgentChainStepExecution) {
+        self.bus
+            .tell(Publish(ChainStepEvent {
+                chain_id,
+                step_execution: step_execution.clone(),
+            }))
+            .await
+            .ok();
+    }
+
+    /// Runs a step, retrying it up to the step's retry count
+    async fn run_step(
+        &self,
+        execution: &AgentChainExecution,
+        step: &AgentChainStep,
+        input: String,
+    ) -> Result<String> {
+        let mut attempt = 0;
+        loop {
+            let step_execution = self
+                .record(
+                    execution.chain_id,
+                    CreateAgentChainStepExecution {
+                        execution_id: execution.id,
+                        step_id: step.id,
+                        agent_id: step.agent_id,
+                        delegated_by_id: None,
+                        status: ExecutionStatus::Running,
+                        input_data: Some(input.clone()),
+                        output_data: None,
+                        error_details: None,
+                        retry_attempt: attempt,
+                        started_at: Some(Utc::now()),
+                        completed_at: None,
+                    },
+                )
+                .await?;
+            let timeout = Duration::from_secs(step.timeout_seconds.max(1) as u64);
+            let run = async {
+                match step.step_type {
+                    AgentChainStepType::Agent => {
+                        self.prompt_agent(step.agent_id, None, input.clone()).await
+                    }
+                    AgentChainStepType::Delegate => {
+                        self.delegate(execution, step, &step_execution, &input).await
+                    }
+                }
+            };
+            let result = match tokio::time::timeout(timeout, run).await {
+                Ok(result) => result,
+                Err(_) => Err(AppError::external_service(format!(
+                    "Step \"{}\" timed out after {}s",
+                    step.step_name,
+                    timeout.as_secs()
+                ))),
+            };
+            self.finish(execution.chain_id, step_execution, &result)
+                .await?;
+            match result {
+                Ok(output) => return Ok(output),
+                Err(e) if attempt < step.retry_count => {
+                    warn!(step_id = %step.id, attempt, "Chain step failed, retrying: {e}");
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Lets the operator of a step split its input into subtasks for its workers and
+    /// combine their results
+    async fn delegate(
+        &self,
+        execution: &AgentChainExecution,
+        step: &AgentChainStep,
+        operator_execution: &AgentChainStepExecution,
+        input: &str,
+    ) -> Result<String> {
+        let (operator, _) = agent_model(&self.db, step.agent_id).await?;
+        if operator.agent_type != AgentType::Operator {
+            return Err(AppError::validation(format!(
+                "Agent {} of step \"{}\" is not an operator",
+                operator.id, step.step_name
+            )));
+        }
+        let rules = delegation_rules(&operator)?;
+        let candidates = self
+            .db
+            .ask(ListAgents(AgentFilter::default()))
+            .await?
+            .into_iter()
+            .filter(|agent| {
+                agent.parent_agent_id == Some(operator.id)
+                    || rules.iter().any(|rule| rule.agent_id == Some(agent.id))
+            })
+            .collect();
+        let workers = eligible_workers(&operator, &rules, candidates);
+        if workers.is_empty() {
+            return Err(AppError::validation(format!(
+                "Operator {} has no workers it may delegate to",
+                operator.id
+            )));
+        }
+
+        let roster = workers
+            .iter()
+            .map(|worker| {
+                format!(
+                    "- {} ({}): {}",
+                    worker.id,
+                    worker.name,
+                    worker.description.as_deref().unwrap_or("no description")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let plan = self
+            .prompt_agent(
+                operator.id,
+                Some(&format!("{DELEGATION_PREAMBLE}\n\nWorkers:\n{roster}")),
+                input.to_string(),
+            )
+            .await?;
+        let subtasks = parse_subtasks(&plan)?;
+        if subtasks.is_empty() {
+            return Err(AppError::external_service("Operator didn't hand out any subtasks"));
+        }
+        for subtask in &subtasks {
+            if !workers.iter().any(|worker| worker.id == subtask.agent_id) {
+                return Err(AppError::authorization(format!(
+                    "Operator {} may not delegate to agent {}",
+                    operator.id, subtask.agent_id
+                )));
+            }
+        }
+        let max_subtasks = rules
+            .iter()
+            .filter_map(|rule| rule.max_subtasks)
+            .max()
+            .unwrap_or(MAX_SUBTASKS)
+            .min(MAX_SUBTASKS);
+        if subtasks.len() > max_subtasks {
+            return Err(AppError::validation(format!(
+                "Operator {} handed out {} subtasks, at most {max_subtasks} are allowed",
+                operator.id,
+                subtasks.len()
+            )));
+        }
+
+        let results = join_all(subtasks.iter().map(|subtask| async move {
+            let step_execution = self
+                .record(
+                    execution.chain_id,
+                    CreateAgentChainStepExecution {
+                        execution_id: execution.id,
+                        step_id: step.id,
+                        agent_id: subtask.agent_id,
+                        delegated_by_id: Some(operator_execution.id),
+                        status: ExecutionStatus::Running,
+                        input_data: Some(subtask.task.clone()),
+                        output_data: None,
+                        error_details: None,
+                        retry_attempt: 0,
+                        started_at: Some(Utc::now()),
+                        completed_at: None,
+                    },
+                )
+                .await?;
+            let result = self
+                .prompt_agent(subtask.agent_id, None, subtask.task.clone())
+                .await;
+            self.finish(execution.chain_id, step_execution, &result)
+                .await?;
+            result
+        }))
+        .await;
+
+        let mut report = format!("Original task:\n{input}\n");
+        for (subtask, result) in subtasks.iter().zip(results) {
+            report.push_str(&format!(
+                "\nSubtask for {}: {}\nResult: {}\n",
+                subtask.agent_id, subtask.task, result?
+            ));
+        }
+        self.prompt_agent(operator.id, Some(SYNTHESIS_PROMPT), report)
+            .await
+    }
+
+    /// Runs the steps of a chain that haven't completed in an earlier attempt of the run
+    async fn run(&self, mut execution: AgentChainExecution) -> Result<AgentChainExecution> {
+        let steps = self.db.ask(ListAgentChainSteps(execution.chain_id)).await?;
+        let completed: HashMap<Uuid, String> = self
+            .db
+            .ask(ListAgentChainStepExecutions(execution.id))
+            .await?
+            .into_iter()
+            .filter(|step_execution| {
+                step_execution.status == ExecutionStatus::Completed
+                    && step_execution.delegated_by_id.is_none()
+            })
+            .map(|step_execution| {
+                (
+                    step_execution.step_id,
+                    step_execution.output_data.unwrap_or_default(),
+                )
+            })
+            .collect();
+        let chain_input = execution.input_data.clone().unwrap_or_default();
+        let mut output = chain_input.clone();
+
+        for step in &steps {
+            if let Some(previous) = completed.get(&step.id) {
+                output = previous.clone();
+                continue;
+            }
+            execution.current_step_id = Some(step.id);
+            execution = self.db.ask(UpdateAgentChainExecution(execution)).await?;
+            let input = render_input(step.input_template.as_deref(), &output, &chain_input);
+            match self.run_step(&execution, step, input).await {
+                Ok(step_output) => output = step_output,
+                Err(e) => {
+                    warn!(execution_id = %execution.id, step_id = %step.id, "Chain step failed: {e}");
+                    execution.status = ExecutionStatus::Failed;
+                    execution.error_details = Some(format!("Step \"{}\": {e}", step.step_name));
+                    execution.completed_at = Some(Utc::now());
+                    return Ok(self.db.ask(UpdateAgentChainExecution(execution)).await?);
+                }
+            }
+        }
+
+        execution.status = ExecutionStatus::Completed;
+        execution.output_data = Some(output);
+        execution.error_details = None;
+        execution.completed_at = Some(Utc::now());
+        info!(execution_id = %execution.id, "Agent chain completed");
+        Ok(self.db.ask(UpdateAgentChainExecution(execution)).await?)
+    }
+}
+
+impl ChainExecutorActor {
+    fn run_context(&self) -> ChainRun {
+        ChainRun {
+            db: self.db.clone(),
+            providers: self.providers.clone(),
+            bus: self.bus.clone(),
+        }
+    }
+
+    /// Runs an execution in the background
+    fn spawn_run(&mut self, execution: AgentChainExecution, actor_ref: LocalActorRef<Self>) {
+        let execution_id = execution.id;
+        let run = self.run_context();
+        let handle = tokio::spawn(async move {
+            let db = run.db.clone();
+            if let Err(e) = run.run(execution).await {
+                warn!(%execution_id, "Agent chain run failed: {e}");
+                mark_failed(&db, execution_id, &e).await;
+            }
+            actor_ref.tell(ChainRunFinished(execution_id)).await.ok();
+        });
+        self.running.insert(execution_id, handle.abort_handle());
+    }
+}
+
+/// Records an error that happened outside of a step, e.g. while loading the chain
+async fn mark_failed(db: &LocalActorRef<DatabaseActor>, execution_id: Uuid, error: &AppError) {
+    let Ok(Some(mut execution)) = db.ask(GetAgentChainExecution(execution_id)).await else {
+        return;
+    };
+    execution.status = ExecutionStatus::Failed;
+    execution.error_details = Some(error.to_string());
+    execution.completed_at = Some(Utc::now());
+    db.ask(UpdateAgentChainExecution(execution)).await.ok();
+}
+
+impl Message<RunChain> for ChainExecutorActor {
+    type Reply = Result<AgentChainExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: RunChain,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let chain = self
+            .db
+            .ask(GetAgentChain(msg.chain_id))
+            .await?
+            .ok_or_else(|| AppError::not_found("AgentChain", msg.chain_id))?;
+        if chain.status != AgentChainStatus::Active {
+            return Err(AppError::validation(format!(
+                "Agent chain {} is not active",
+                chain.id
+            )));
+        }
+        let execution = self
+            .db
+            .ask(CreateAgentChainExecution {
+                chain_id: chain.id,
+                conversation_id: msg.conversation_id,
+                triggered_by_id: msg.triggered_by_id,
+                status: ExecutionStatus::Running,
+                current_step_id: None,
+                input_data: Some(msg.input),
+                output_data: None,
+                error_details: None,
+            })
+            .await?;
+        self.spawn_run(execution.clone(), ctx.actor_ref());
+        Ok(execution)
+    }
+}
+
+impl Message<ResumeChain> for ChainExecutorActor {
+    type Reply = Result<AgentChainExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: ResumeChain,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let mut execution = self
+            .db
+            .ask(GetAgentChainExecution(msg.0))
+            .await?
+            .ok_or_else(|| AppError::not_found("AgentChainExecution", msg.0))?;
+        if self.running.contains_key(&execution.id)
+            || !matches!(
+                execution.status,
+                ExecutionStatus::Failed | ExecutionStatus::Cancelled
+            )
+        {
+            return Err(AppError::validation(format!(
+                "Agent chain execution {} can't be resumed while it is {:?}",
+                execution.id, execution.status
+            )));
+        }
+        execution.status = ExecutionStatus::Running;
+        execution.error_details = None;
+        execution.completed_at = None;
+        let execution = self.db.ask(UpdateAgentChainExecution(execution)).await?;
+        self.spawn_run(execution.clone(), ctx.actor_ref());
+        Ok(execution)
+    }
+}
+
+impl Message<CancelChain> for ChainExecutorActor {
+    type Reply = Result<AgentChainExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: CancelChain,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(handle) = self.running.remove(&msg.0) else {
+            return Err(AppError::validation(format!(
+                "Agent chain execution {} is not running",
+                msg.0
+            )));
+        };
+        handle.abort();
+        let mut execution = self
+            .db
+            .ask(GetAgentChainExecution(msg.0))
+            .await?
+            .ok_or_else(|| AppError::not_found("AgentChainExecution", msg.0))?;
+        execution.status = ExecutionStatus::Cancelled;
+        execution.completed_at = Some(Utc::now());
+        // Steps that were interrupted are retried when the run is resumed
+        for mut step_execution in self
+            .db
+            .ask(ListAgentChainStepExecutions(execution.id))
+            .await?
+            .into_iter()
+            .filter(|step_execution| step_execution.status == ExecutionStatus::Running)
+        {
+            step_execution.status = ExecutionStatus::Cancelled;
+            step_execution.completed_at = Some(Utc::now());
+            self.db
+                .ask(UpdateAgentChainStepExecution(step_execution))
+                .await?;
+        }
+        Ok(self.db.ask(UpdateAgentChainExecution(execution)).await?)
+    }
+}
+
+impl Message<ChainRunFinished> for ChainExecutorActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: ChainRunFinished,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.running.remove(&msg.0);
+    }
+}
+
+/// Starts a run of an agent chain. Replies once the run is recorded, the steps run
+/// in the background.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunChain {
+    pub chain_id: Uuid,
+    pub input: String,
+    pub conversation_id: Option<Uuid>,
+    pub triggered_by_id: Option<Uuid>,
+}
+
+/// Continues a failed or cancelled run after its last successful step
+pub struct ResumeChain(pub Uuid);
+
+pub struct CancelChain(pub Uuid);
+
+struct ChainRunFinished(Uuid);
+
+/// Published whenever a step of a chain run starts or finishes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainStepEvent {
+    pub chain_id: Uuid,
+    pub step_execution: AgentChainStepExecution,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_input_replaces_placeholders() {
+        assert_eq!(
+            render_input(Some("Review {{input}} for {{chain_input}}"), "draft", "goal"),
+            "Review draft for goal"
+        );
+        assert_eq!(render_input(None, "draft", "goal"), "draft");
+    }
+
+    #[test]
+    fn test_parse_subtasks_ignores_surrounding_text() {
+        let agent_id = Uuid::new_v4();
+        let reply = format!("Plan:\n[{{\"agentId\": \"{agent_id}\", \"task\": \"Write tests\"}}]");
+
+        let subtasks = parse_subtasks(&reply).unwrap();
+
+        assert_eq!(subtasks.len(), 1);
+        assert_eq!(subtasks[0].agent_id, agent_id);
+        assert!(parse_subtasks("No plan").is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use kameo::prelude::ActorRef as LocalActorRef;
+use rig::{
+    agent::AgentBuilder,
+    completion::{Message as RigMessage, Prompt, ToolDefinition},
+    message::{AssistantContent, UserContent},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        database::{CreateMemory, DatabaseActor, ListMemories, UpdateMemory},
+        providers::{ProviderRegistry, ResolvedProvider},
+        transcript::agent_participant_id,
+    },
+    entities::{Agent, MemoryFilter, MemoryType, Model, ModelProvider},
+    error::{AppError, Result},
+};
+
+/// Tokens added per message for role markers and separators
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Share of the available context the rolling summary may take up
+const SUMMARY_BUDGET_DIVISOR: usize = 8;
+
+/// `kind` stored in the metadata of rolling summary memories
+pub(crate) const SUMMARY_KIND: &str = "contextSummary";
+
+const SUMMARY_PREAMBLE: &str = "You maintain a running summary of a conversation between a user \
+    and an AI assistant. Merge the previous summary with the new transcript into a single concise \
+    summary. Keep facts, decisions, open questions and results of tool calls. Reply with the \
+    summary only.";
+
+/// Average number of characters per token for the tokenizers used by a provider
+fn chars_per_token(provider: ModelProvider) -> f64 {
+    match provider {
+        ModelProvider::OpenAI
+        | ModelProvider::XAi
+        | ModelProvider::DeepSeek
+        | ModelProvider::Perplexity
+        | ModelProvider::Gemini => 4.0,
+        ModelProvider::Cohere => 4.2,
+        ModelProvider::Anthropic => 3.5,
+        // Local models use all kinds of tokenizers, so err on the side of caution
+        ModelProvider::Ollama => 3.2,
+    }
+}
+
+/// Estimates the number of tokens a piece of text takes up for the given provider
+pub fn estimate_tokens(provider: ModelProvider, text: &str) -> usize {
+    (text.chars().count() as f64 / chars_per_token(provider)).ceil() as usize
+}
+
+/// Estimates the number of tokens a history message takes up for the given provider
+pub fn estimate_message_tokens(provider: ModelProvider, message: &RigMessage) -> usize {
+    estimate_tokens(provider, &message_text(message)) + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Renders a message as plain text, tool calls and results are rendered as JSON
+fn message_text(message: &RigMessage) -> String {
+    match message {
+        RigMessage::User { content } => content
+            .iter()
+            .map(|content| match content {
+                UserContent::Text(text) => text.text.clone(),
+                other => serde_json::to_string(other).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        RigMessage::Assistant { content } => content
+            .iter()
+            .map(|content| match content {
+                AssistantContent::Text(text) => text.text.clone(),
+                other => serde_json::to_string(other).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn is_tool_result(message: &RigMessage) -> bool {
+    matches!(message, RigMessage::User { content }
+        if content.iter().any(|c| matches!(c, UserContent::ToolResult(_))))
+}
+
+/// The number of tokens an agent may spend on its prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextBudget {
+    pub context_window: usize,
+    /// Tokens kept free for the response
+    pub reserved_tokens: usize,
+}
+
+impl ContextBudget {
+    /// Uses the smaller of the agent's and the model's window, reserving room for the
+    /// model's maximum response (but never more than half of the window).
+    pub fn for_agent(agent: &Agent, model: &Model) -> Self {
+        let context_window = match (agent.context_window, model.context_size) {
+            (agent, model) if agent > 0 && model > 0 => agent.min(model),
+            (agent, _) if agent > 0 => agent,
+            (_, model) => model.max(0),
+        } as usize;
+        Self {
+            context_window,
+            reserved_tokens: (model.max_tokens.max(0) as usize).min(context_window / 2),
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        self.context_window.saturating_sub(self.reserved_tokens)
+    }
+}
+
+/// The result of fitting a history into a [`ContextBudget`]
+#[derive(Debug, Clone)]
+pub struct FittedHistory {
+    /// The most recent messages that fit into the budget
+    pub history: Vec<RigMessage>,
+    /// The oldest messages that had to be left out
+    pub dropped: Vec<RigMessage>,
+    /// Estimated size of the prompt including the kept history
+    pub estimated_tokens: usize,
+}
+
+/// Drops the oldest messages of `history` until it fits into `available` tokens together
+/// with `fixed_tokens` (prompt, tool definitions, ...). Tool results are never separated
+/// from the tool calls that produced them.
+pub fn fit_history(
+    provider: ModelProvider,
+    available: usize,
+    fixed_tokens: usize,
+    mut history: Vec<RigMessage>,
+) -> FittedHistory {
+    let sizes: Vec<usize> = history
+        .iter()
+        .map(|message| estimate_message_tokens(provider, message))
+        .collect();
+    let mut estimated_tokens = fixed_tokens + sizes.iter().sum::<usize>();
+    let mut split = 0;
+    while estimated_tokens > available && split < history.len() {
+        estimated_tokens -= sizes[split];
+        split += 1;
+        while split < history.len() && is_tool_result(&history[split]) {
+            estimated_tokens -= sizes[split];
+            split += 1;
+        }
+    }
+    let kept = history.split_off(split);
+    FittedHistory {
+        history: kept,
+        dropped: history,
+        estimated_tokens,
+    }
+}
+
+/// Metadata of a rolling summary memory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SummaryMetadata {
+    kind: String,
+    agent_id: Uuid,
+    /// How many of the oldest history messages the summary covers
+    summarized_messages: usize,
+}
+
+/// What was left out of the prompt when the history didn't fit
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextTruncation {
+    pub dropped_messages: usize,
+    pub estimated_tokens: usize,
+    pub context_window: usize,
+    /// Whether the dropped messages are covered by the rolling summary
+    pub summarized: bool,
+}
+
+/// The history an agent turn is run with
+pub struct PreparedContext {
+    pub history: Vec<RigMessage>,
+    /// Rolling summary of the messages that were dropped from the history
+    pub summary: Option<String>,
+    pub truncation: Option<ContextTruncation>,
+}
+
+/// Fits the history of an agent turn into the agent's context window. Messages that
+/// don't fit are folded into a rolling summary that is stored as an episodic memory
+/// of the agent, so that they only need to be summarized once. Recalled long-term
+/// memories are part of the fixed context and are never dropped.
+#[allow(clippy::too_many_arguments)]
+pub async fn prepare_context(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    provider: &ResolvedProvider,
+    agent: &Agent,
+    model: &Model,
+    conversation_id: Uuid,
+    prompt: &str,
+    recalled: Option<&str>,
+    tool_definitions: &[ToolDefinition],
+    history: Vec<RigMessage>,
+) -> Result<PreparedContext> {
+    let budget = ContextBudget::for_agent(agent, model);
+    let fixed_tokens = estimate_tokens(model.provider, prompt)
+        + recalled.map_or(0, |recalled| estimate_tokens(model.provider, recalled))
+        + estimate_tokens(
+            model.provider,
+            &serde_json::to_string(tool_definitions).unwrap_or_default(),
+        );
+    let fitted = fit_history(model.provider, budget.available(), fixed_tokens, history);
+    if fitted.dropped.is_empty() {
+        return Ok(PreparedContext {
+            history: fitted.history,
+            summary: None,
+            truncation: None,
+        });
+    }
+
+    // Make room for the summary before deciding what has to be dropped
+    let summary_budget = budget.available() / SUMMARY_BUDGET_DIVISOR;
+    let mut history = fitted.dropped;
+    history.extend(fitted.history);
+    let fitted = fit_history(
+        model.provider,
+        budget.available(),
+        fixed_tokens + summary_budget,
+        history,
+    );
+    debug!(
+        agent_id = %agent.id,
+        dropped = fitted.dropped.len(),
+        estimated_tokens = fitted.estimated_tokens,
+        "History exceeds the context window"
+    );
+
+    let summary = match update_rolling_summary(
+        db,
+        providers,
+        provider,
+        agent,
+        model,
+        conversation_id,
+        &fitted.dropped,
+        summary_budget,
+    )
+    .await
+    {
+        Ok(summary) => Some(summary),
+        Err(e) => {
+            warn!(agent_id = %agent.id, "Failed to summarize dropped history: {e}");
+            None
+        }
+    };
+    Ok(PreparedContext {
+        truncation: Some(ContextTruncation {
+            dropped_messages: fitted.dropped.len(),
+            estimated_tokens: fitted.estimated_tokens
+                + summary
+                    .as_deref()
+                    .map_or(0, |summary| estimate_tokens(model.provider, summary)),
+            context_window: budget.context_window,
+            summarized: summary.is_some(),
+        }),
+        history: fitted.history,
+        summary,
+    })
+}
+
+/// Folds the dropped messages that aren't covered yet into the stored rolling summary
+#[allow(clippy::too_many_arguments)]
+async fn update_rolling_summary(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    provider: &ResolvedProvider,
+    agent: &Agent,
+    model: &Model,
+    conversation_id: Uuid,
+    dropped: &[RigMessage],
+    max_tokens: usize,
+) -> Result<String> {
+    let participant_id = agent_participant_id(db, agent.id).await?;
+    let existing = db
+        .ask(ListMemories(MemoryFilter {
+            participant_id: Some(participant_id),
+            conversation_id: Some(conversation_id),
+            memory_type: Some(MemoryType::Episodic),
+            ..Default::default()
+        }))
+        .await?
+        .into_iter()
+        .find_map(|memory| {
+            let metadata = serde_json::from_str::<SummaryMetadata>(memory.metadata.as_deref()?)
+                .ok()
+                .filter(|metadata| metadata.kind == SUMMARY_KIND)?;
+            Some((memory, metadata))
+        });
+
+    let covered = existing
+        .as_ref()
+        .map_or(0, |(_, metadata)| metadata.summarized_messages);
+    if covered >= dropped.len() {
+        if let Some(summary) = existing
+            .as_ref()
+            .and_then(|(memory, _)| memory.summary.clone())
+        {
+            return Ok(summary);
+        }
+    }
+
+    let previous = existing
+        .as_ref()
+        .and_then(|(memory, _)| memory.summary.as_deref());
+    let new_messages = &dropped[covered.min(dropped.len())..];
+    let transcript = render_transcript(new_messages);
+    let summary = summarize(providers, provider, model, previous, &transcript, max_tokens).await?;
+    let metadata = serde_json::to_string(&SummaryMetadata {
+        kind: SUMMARY_KIND.to_string(),
+        agent_id: agent.id,
+        summarized_messages: dropped.len(),
+    })?;
+
+    match existing {
+        Some((mut memory, _)) => {
+            memory.content.push_str("\n\n");
+            memory.content.push_str(&transcript);
+            memory.summary = Some(summary.clone());
+            memory.metadata = Some(metadata);
+            db.ask(UpdateMemory(memory)).await?;
+        }
+        None => {
+            db.ask(CreateMemory {
+                workspace_id: agent.workspace_id,
+                participant_id: Some(participant_id),
+                conversation_id: Some(conversation_id),
+                memory_type: MemoryType::Episodic,
+                content: transcript,
+                summary: Some(summary.clone()),
+                importance: 0.5,
+                last_accessed_at: chrono::Utc::now(),
+                access_count: 0,
+                metadata: Some(metadata),
+                embedding: None,
+            })
+            .await?;
+        }
+    }
+    Ok(summary)
+}
+
+pub(crate) fn render_transcript(messages: &[RigMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            let role = match message {
+                RigMessage::User { .. } => "User",
+                RigMessage::Assistant { .. } => "Assistant",
+            };
+            format!("{role}: {}", message_text(message))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn summarize(
+    providers: &ProviderRegistry,
+    provider: &ResolvedProvider,
+    model: &Model,
+    previous: Option<&str>,
+    transcript: &str,
+    max_tokens: usize,
+) -> Result<String> {
+    let completion_model = providers.completion_model(provider, &model.name).await;
+    let agent = AgentBuilder::new(completion_model)
+        .preamble(SUMMARY_PREAMBLE)
+        .max_tokens(max_tokens as u64)
+        .build();
+    let prompt = format!(
+        "Previous summary:\n{}\n\nNew transcript:\n{transcript}",
+        previous.unwrap_or("(none)")
+    );
+    agent
+        .prompt(prompt)
+        .await
+        .map_err(|e| AppError::external_service(format!("Failed to summarize history: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use rig::{OneOrMany, message::ToolResultContent};
+
+    use super::*;
+
+    #[test]
+    fn test_fit_history_drops_oldest_turns_with_their_tool_results() {
+        let history = vec![
+            RigMessage::user("a".repeat(400)),
+            RigMessage::assistant("b".repeat(400)),
+            RigMessage::User {
+                content: OneOrMany::one(UserContent::tool_result(
+                    "call-1",
+                    OneOrMany::one(ToolResultContent::text("c".repeat(40))),
+                )),
+            },
+            RigMessage::user("d".repeat(40)),
+        ];
+
+        let fitted = fit_history(ModelProvider::OpenAI, 50, 0, history);
+
+        assert_eq!(fitted.dropped.len(), 3);
+        assert_eq!(fitted.history.len(), 1);
+        assert!(fitted.estimated_tokens <= 50);
+    }
+
+    #[test]
+    fn test_fit_history_keeps_everything_within_budget() {
+        let history = vec![RigMessage::user("hello"), RigMessage::assistant("hi")];
+
+        let fitted = fit_history(ModelProvider::Anthropic, 1000, 100, history);
+
+        assert!(fitted.dropped.is_empty());
+        assert_eq!(fitted.history.len(), 2);
+    }
+}
@@ -1,34 +1,103 @@
 use std::collections::HashMap;
 
-use color_eyre::eyre::eyre;
-use futures_util::{StreamExt, stream};
 use kameo::prelude::{ActorRef as LocalActorRef, *};
-use libp2p::PeerId;
+use kameo_actors::message_bus::Publish;
 use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
 use uuid::Uuid;
 
 use crate::{
     actors::{
-        ActorRef,
-        agents::{AgentManagerActor, AgentResponseEvent, StreamedPart},
-        database::{DatabaseActor, GetConversationParticipantIds},
-        gateway::GatewayActor,
-        tools::ToolExecutorActor,
+        ActorRef, SystemEventBus,
+        agents::{AgentManagerActor, AgentRequest, AgentResponseEvent},
+        database::{
+            CreateBatchParticipants, DatabaseActor, GetAgent, GetContactPeerIds,
+            GetConversationParticipantId, GetConversationParticipantIds, GetModel,
+            GetParticipantsByPeerId,
+        },
+        delivery::{DeliveryActor, Enqueue},
+        tools::{GetTools, ToolExecutorActor},
+        transcript::{agent_participant_id, load_history},
     },
     entities::{
-        Conversation, ConversationStatus, ConversationType, CreateConversation,
-        CreateConversationParticipant, Message as ChatMessage, ParticipantType,
+        CreateConversationParticipant, CreateMessage, Message as ChatMessage, MessageStatus,
+        ParticipantRole, ParticipantType, PeerIdWrapper,
     },
-    error::Result,
+    error::{AppError, Result},
     keys::Signed,
-    utils::get_gateway_id,
 };
 
 #[derive(Actor)]
 pub struct ConversationManagerActor {
     pub agent_manager: LocalActorRef<AgentManagerActor>,
+    pub tool_executor: LocalActorRef<ToolExecutorActor>,
     pub db: LocalActorRef<DatabaseActor>,
-    pub conversation_peers: HashMap<Uuid, Vec<RemoteActorRef<GatewayActor>>>,
+    pub bus: LocalActorRef<SystemEventBus>,
+    pub delivery: LocalActorRef<DeliveryActor>,
+    pub conversation_peers: HashMap<Uuid, Vec<PeerIdWrapper>>,
+}
+
+/// Collects the peers backing every contact in the given participant list.
+async fn resolve_peers(
+    db: &LocalActorRef<DatabaseActor>,
+    participants: &[ParticipantType],
+) -> Result<Vec<PeerIdWrapper>> {
+    let mut peers = Vec::new();
+    for participant in participants {
+        let ParticipantType::Contact(contact_id) = participant else {
+            continue;
+        };
+        peers.extend(db.ask(GetContactPeerIds(*contact_id)).await?);
+    }
+    Ok(peers)
+}
+
+/// Builds the request used to run a single agent turn for a conversation.
+async fn build_agent_request(
+    db: &LocalActorRef<DatabaseActor>,
+    tool_executor: &LocalActorRef<ToolExecutorActor>,
+    agent_id: Uuid,
+    conversation_id: Uuid,
+    prompt_message_id: Uuid,
+    prompt: String,
+    participants: &[ParticipantType],
+) -> Result<AgentRequest> {
+    let agent = db
+        .ask(GetAgent(agent_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Agent", agent_id))?;
+    let model_id = agent
+        .model_id
+        .ok_or_else(|| AppError::configuration(format!("Agent {agent_id} has no model")))?;
+    let model = db
+        .ask(GetModel(model_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Model", model_id))?;
+    let tool_definitions = tool_executor.ask(GetTools).await?;
+    let history = load_history(
+        db,
+        conversation_id,
+        agent_participant_id(db, agent_id).await?,
+        Some(prompt_message_id),
+    )
+    .await?;
+    Ok(AgentRequest {
+        agent,
+        model,
+        prompt,
+        history,
+        tool_definitions,
+        conversation_id,
+        participants: participants
+            .iter()
+            .filter_map(|p| {
+                let (user_id, agent_id, contact_id) = p.into_id_triplet();
+                user_id.or(agent_id).or(contact_id)
+            })
+            .collect(),
+        tool_ref: Some(ActorRef::Local(tool_executor.clone())),
+        sink: None,
+    })
 }
 
 impl Message<SendMessage> for ConversationManagerActor {
@@ -40,29 +109,109 @@ impl Message<SendMessage> for ConversationManagerActor {
         ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         let (delegated, sender) = ctx.reply_sender();
+        let cached_peers = self.conversation_peers.get(&msg.conversation_id).cloned();
         tokio::spawn({
             let db = self.db.clone();
             let agent_manager = self.agent_manager.clone();
+            let tool_executor = self.tool_executor.clone();
+            let bus = self.bus.clone();
+            let delivery = self.delivery.clone();
+            let actor_ref = ctx.actor_ref();
             async move {
                 let res = async {
                     let conversation_id = msg.conversation_id;
+                    let sender_id = db
+                        .ask(GetConversationParticipantId {
+                            conversation_id,
+                            participant: msg.type_,
+                        })
+                        .await?
+                        .ok_or_else(|| {
+                            AppError::authorization(format!(
+                                "{:?} is not a participant of conversation {conversation_id}",
+                                msg.type_
+                            ))
+                        })?;
+                    let message = db
+                        .ask(CreateMessage {
+                            conversation_id,
+                            sender_id,
+                            parent_message_id: None,
+                            content: msg.content.clone(),
+                            status: MessageStatus::Sent,
+                            refs: None,
+                            metadata: None,
+                            reply_to_id: None,
+                            branch_conversation_id: None,
+                            parent_id: None,
+                            workspace_id: msg.workspace_id,
+                        })
+                        .await?;
+                    // Local users are notified through the UI by this event
+                    bus.tell(Publish(message.clone())).await.ok();
+
                     let participants = db
                         .ask(GetConversationParticipantIds(conversation_id))
                         .await?;
-                    for p in participants {
-                        match p {
-                            ParticipantType::User(id) => {
-                                todo!()
-                            }
+                    for p in &participants {
+                        // Never echo a message back to whoever sent it
+                        if *p == msg.type_ {
+                            continue;
+                        }
+                        match *p {
                             ParticipantType::Agent(id) => {
-                                todo!()
-                            }
-                            ParticipantType::Contact(id) => {
-                                todo!()
+                                let request = build_agent_request(
+                                    &db,
+                                    &tool_executor,
+                                    id,
+                                    conversation_id,
+                                    message.id,
+                                    msg.content.clone(),
+                                    &participants,
+                                )
+                                .await;
+                                let res = match request {
+                                    Ok(request) => agent_manager
+                                        .ask(request)
+                                        .await
+                                        .map_err(AppError::from),
+                                    Err(e) => Err(e),
+                                };
+                                if let Err(e) = res {
+                                    error!(agent_id = %id, "Failed to dispatch message to agent: {e}");
+                                }
                             }
+                            // Local users are notified through the UI and contacts are
+                            // handled below once their peers have been resolved
+                            ParticipantType::User(_) | ParticipantType::Contact(_) => {}
                             _ => {} // Ignore system participants
                         }
                     }
+
+                    let peers = match cached_peers {
+                        Some(peers) => peers,
+                        None => {
+                            let peers = resolve_peers(&db, &participants).await?;
+                            actor_ref
+                                .tell(CacheConversationPeers {
+                                    conversation_id,
+                                    peers: peers.clone(),
+                                })
+                                .await
+                                .ok();
+                            peers
+                        }
+                    };
+                    if !peers.is_empty() {
+                        // Queued so that peers which are offline receive it once they connect
+                        delivery
+                            .ask(Enqueue {
+                                recipients: peers,
+                                conversation_id: Some(conversation_id),
+                                message: Signed::new(message).into(),
+                            })
+                            .await?;
+                    }
                     Ok(())
                 }
                 .await;
@@ -83,10 +232,40 @@ impl Message<Signed<ChatMessage>> for ConversationManagerActor {
         msg: Signed<ChatMessage>,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
+        // The signature has already been verified by the gateway, we only need to make sure
+        // that the sending peer actually belongs to the conversation.
+        let peer_id = PeerIdWrapper(*msg.client_peer_id());
         let msg = msg.into_inner();
-        // self.db.ask(msg).await?;
-
-        todo!()
+        let Some(sender) = self
+            .db
+            .ask(GetParticipantsByPeerId(msg.conversation_id, peer_id.clone()))
+            .await?
+            .into_iter()
+            .next()
+        else {
+            return Err(AppError::authorization(format!(
+                "Peer {peer_id} is not a participant of conversation {}",
+                msg.conversation_id
+            )));
+        };
+        let message = self
+            .db
+            .ask(CreateMessage {
+                conversation_id: msg.conversation_id,
+                sender_id: sender.id,
+                parent_message_id: msg.parent_message_id,
+                content: msg.content,
+                status: MessageStatus::Delivered,
+                refs: msg.refs,
+                metadata: msg.metadata,
+                reply_to_id: msg.reply_to_id,
+                branch_conversation_id: msg.branch_conversation_id,
+                parent_id: msg.parent_id,
+                workspace_id: msg.workspace_id,
+            })
+            .await?;
+        self.bus.tell(Publish(message)).await.ok();
+        Ok(())
     }
 }
 
@@ -98,7 +277,38 @@ impl Message<InviteParticipants> for ConversationManagerActor {
         msg: InviteParticipants,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        todo!()
+        if msg.participants.is_empty() {
+            return Ok(());
+        }
+        self.db
+            .ask(CreateBatchParticipants(
+                msg.participants
+                    .into_iter()
+                    .map(|participant_id| CreateConversationParticipant {
+                        conversation_id: msg.conversation_id,
+                        participant_id,
+                        is_active: true,
+                        role: ParticipantRole::Member,
+                    })
+                    .collect(),
+            ))
+            .await?;
+        // New contacts may have been added, so the peers need to be resolved again
+        self.conversation_peers.remove(&msg.conversation_id);
+        Ok(())
+    }
+}
+
+impl Message<CacheConversationPeers> for ConversationManagerActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: CacheConversationPeers,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.conversation_peers
+            .insert(msg.conversation_id, msg.peers);
     }
 }
 
@@ -111,30 +321,50 @@ impl Message<AgentResponseEvent> for ConversationManagerActor {
         ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         let (delegated, sender) = ctx.reply_sender();
-        let participants = match self.conversation_peers.get(&msg.conversation_id).cloned() {
-            Some(participants) => participants,
+        let conversation_id = msg.conversation_id;
+        let peers = match self.conversation_peers.get(&conversation_id).cloned() {
+            Some(peers) => peers,
             None => {
-                let Ok(participants) = self
-                    .db
-                    .ask(GetConversationParticipantIds(msg.conversation_id))
-                    .await
-                else {
-                    if let Some(tx) = sender {
-                        tx.send(());
+                let peers = async {
+                    let participants = self
+                        .db
+                        .ask(GetConversationParticipantIds(conversation_id))
+                        .await?;
+                    resolve_peers(&self.db, &participants).await
+                }
+                .await;
+                let peers = match peers {
+                    Ok(peers) => peers,
+                    Err(e) => {
+                        warn!(%conversation_id, "Failed to resolve conversation peers: {e}");
+                        if let Some(tx) = sender {
+                            tx.send(());
+                        }
+                        return delegated;
                     }
-                    return delegated;
                 };
-                todo!()
+                self.conversation_peers
+                    .insert(conversation_id, peers.clone());
+                peers
             }
         };
-        let signed = Signed::new(msg);
+        let delivery = self.delivery.clone();
         tokio::spawn(async move {
-            stream::iter(participants).for_each_concurrent(5, |p| {
-                let signed = signed.clone();
-                async move {
-                    p.tell(&signed).await.ok();
+            if !peers.is_empty() {
+                let res = delivery
+                    .ask(Enqueue {
+                        recipients: peers,
+                        conversation_id: Some(conversation_id),
+                        message: Signed::new(msg).into(),
+                    })
+                    .await;
+                if let Err(e) = res {
+                    warn!(%conversation_id, "Failed to queue agent response for peers: {e}");
                 }
-            });
+            }
+            if let Some(tx) = sender {
+                tx.send(());
+            }
         });
         delegated
     }
@@ -143,10 +373,10 @@ impl Message<AgentResponseEvent> for ConversationManagerActor {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SendMessage {
     /// The id of the conversation to send the message to
-    /// If None, a new conversation will be created
     pub conversation_id: Uuid,
     pub workspace_id: Option<Uuid>,
     pub content: String,
+    /// The participant sending the message
     pub type_: ParticipantType,
 }
 
@@ -155,3 +385,227 @@ pub struct InviteParticipants {
     pub conversation_id: Uuid,
     pub participants: Vec<Uuid>,
 }
+
+/// Stores the resolved peers of a conversation so they don't have to be looked up
+/// for every message.
+pub struct CacheConversationPeers {
+    pub conversation_id: Uuid,
+    pub peers: Vec<PeerIdWrapper>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use kameo_actors::{DeliveryStrategy, message_bus::Register, pool::ActorPool};
+    use libp2p::PeerId;
+    use serde_json::json;
+    use sqlx::{Pool, Sqlite};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        actors::{
+            agents::{AgentActor, StreamedPart},
+            database::{ListDeliverableP2pMessages, ListMessages},
+            providers::ProviderRegistry,
+        },
+        entities::MessageFilter,
+        integration::{KeyDerivationMethod, SecretCipher},
+        keys::{KEY_PAIR, PEER_ID},
+        repositories::RepositoryFactory,
+        storage::db::DatabaseManager,
+    };
+
+    /// Forwards the agent responses published on the bus
+    #[derive(Actor)]
+    struct AgentResponses(mpsc::UnboundedSender<AgentResponseEvent>);
+
+    impl Message<AgentResponseEvent> for AgentResponses {
+        type Reply = ();
+
+        async fn handle(
+            &mut self,
+            msg: AgentResponseEvent,
+            _ctx: &mut Context<Self, Self::Reply>,
+        ) -> Self::Reply {
+            self.0.send(msg).ok();
+        }
+    }
+
+    async fn add_participant(
+        pool: &Pool<Sqlite>,
+        conversation_id: Uuid,
+        participant: ParticipantType,
+    ) -> Uuid {
+        let id = Uuid::new_v4();
+        let (user_id, agent_id, contact_id) = participant.into_id_triplet();
+        sqlx::query(
+            "INSERT INTO participants (id, user_id, agent_id, contact_id, display_name)
+             VALUES (?, ?, ?, ?, 'Participant')",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(agent_id)
+        .bind(contact_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO conversation_participants (conversation_id, participant_id) VALUES (?, ?)",
+        )
+        .bind(conversation_id)
+        .bind(id)
+        .execute(pool)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_send_message() {
+        PEER_ID.get_or_init(|| KEY_PAIR.read().unwrap().public().to_peer_id());
+        let db = DatabaseManager::setup_test_db().await;
+        let pool = db.pool.clone();
+        let repo_factory = RepositoryFactory::new(db.pool.clone());
+        let db = DatabaseActor::spawn(DatabaseActor { db, repo_factory });
+        let bus = SystemEventBus::spawn(SystemEventBus::new(DeliveryStrategy::BestEffort));
+        let cipher = SecretCipher::new(&KeyDerivationMethod::Password {
+            password: "test".to_string(),
+            salt: Uuid::new_v4().as_bytes().to_vec(),
+            iterations: 1_000,
+        })
+        .unwrap();
+        let providers = ProviderRegistry::new(db.clone(), cipher, None);
+        let agent_manager = AgentManagerActor::spawn(AgentManagerActor {
+            bus: bus.clone(),
+            pool: ActorPool::spawn(ActorPool::new(1, {
+                let (bus, db) = (bus.clone(), db.clone());
+                move || {
+                    AgentActor::spawn(AgentActor {
+                        bus: bus.clone(),
+                        db: db.clone(),
+                        providers: providers.clone(),
+                    })
+                }
+            })),
+        });
+        let conversation_manager = ConversationManagerActor::spawn(ConversationManagerActor {
+            agent_manager,
+            tool_executor: ToolExecutorActor::spawn(ToolExecutorActor {
+                tools: HashMap::new(),
+            }),
+            db: db.clone(),
+            bus: bus.clone(),
+            delivery: DeliveryActor::spawn(DeliveryActor {
+                db: db.clone(),
+                flushing: HashMap::new(),
+            }),
+            conversation_peers: HashMap::new(),
+        });
+        let (tx, mut agent_responses) = mpsc::unbounded_channel();
+        let forwarder = AgentResponses::spawn(AgentResponses(tx));
+        bus.tell(Register(forwarder.recipient::<AgentResponseEvent>()))
+            .await
+            .unwrap();
+
+        // A conversation of a user with an agent and a contact on another peer. The
+        // agent's provider can't be resolved, so its turn ends right away.
+        let (conversation_id, user_id, agent_id, contact_id, model_id) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        sqlx::query("INSERT INTO conversations (id, title) VALUES (?, 'Chat')")
+            .bind(conversation_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO models (id, provider, name) VALUES (?, 'openai', 'gpt-4o')")
+            .bind(model_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO agents (id, name, model_id, config) VALUES (?, 'Agent', ?, ?)")
+            .bind(agent_id)
+            .bind(model_id)
+            .bind(json!({ "provider": { "credential": 42 } }).to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let sender_id =
+            add_participant(&pool, conversation_id, ParticipantType::User(user_id)).await;
+        add_participant(&pool, conversation_id, ParticipantType::Agent(agent_id)).await;
+        let contact =
+            add_participant(&pool, conversation_id, ParticipantType::Contact(contact_id)).await;
+        let peer_id = PeerIdWrapper(PeerId::random());
+        sqlx::query("INSERT INTO p2p_nodes (participant_id, peer_id, multiaddr) VALUES (?, ?, '')")
+            .bind(contact)
+            .bind(&peer_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        conversation_manager
+            .ask(SendMessage {
+                conversation_id,
+                workspace_id: None,
+                content: "Hello".to_string(),
+                type_: ParticipantType::User(user_id),
+            })
+            .await
+            .unwrap();
+
+        // The message is stored as sent by the user
+        let messages = db
+            .ask(ListMessages(MessageFilter {
+                conversation_id: Some(conversation_id),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].sender_id, sender_id);
+        assert_eq!(messages[0].content, "Hello");
+
+        // The agent takes its turn, but not the user who sent it
+        let response = tokio::time::timeout(Duration::from_secs(5), agent_responses.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.agent_id, agent_id);
+        assert_eq!(response.conversation_id, conversation_id);
+        assert!(matches!(response.response, StreamedPart::Error(_)));
+
+        // And it's queued for the peer of the contact
+        let queued = db
+            .ask(ListDeliverableP2pMessages(peer_id.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].conversation_id, Some(conversation_id));
+
+        // Users of other conversations can't send messages to it
+        let stranger_id = Uuid::new_v4();
+        add_participant(&pool, Uuid::new_v4(), ParticipantType::User(stranger_id)).await;
+        let res = conversation_manager
+            .ask(SendMessage {
+                conversation_id,
+                workspace_id: None,
+                content: "Hi".to_string(),
+                type_: ParticipantType::User(stranger_id),
+            })
+            .await;
+        assert!(res.is_err());
+        let messages = db
+            .ask(ListMessages(MessageFilter {
+                conversation_id: Some(conversation_id),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+}
@@ -1,15 +1,31 @@
-use chrono::Utc;
+use chrono::{DateTime, NaiveDate, Utc};
 use kameo::prelude::{ActorRef as LocalActorRef, *};
+use serde_json::{Value, json};
 use uuid::Uuid;
 
 use crate::{
+    actors::{discovery::NetworkConfig, transfer::TransferPolicy},
     entities::{
-        Agent, AgentFilter, Conversation, ConversationFilter, ConversationParticipant, CreateAgent,
-        CreateConversation, CreateConversationParticipant, CreateP2pNode, CreateParticipant,
-        CreateTask, CreateUser, P2pNode, Participant, ParticipantFilter, ParticipantType, Task,
-        TaskFilter, User, UserFilter,
+        ApiKey, ApiKeyFilter, CreateApiKey,
+        Agent, AgentChain, AgentChainExecution, AgentChainExecutionFilter, AgentChainFilter,
+        AgentChainStep, AgentChainStepExecution, CreateAgentChain, CreateAgentChainExecution,
+        CreateAgentChainStep, CreateAgentChainStepExecution, AgentFilter, Conversation, ConversationFilter, ConversationParticipant, CreateAgent,
+        CreateConversation, CreateConversationParticipant, CreateCredential, CreateDocumentChunk,
+        CreateMemory, CreateMessage, CreateP2pNode, CreateParticipant, CreateTask, CreateUser,
+        Credential, Document, DocumentChunk, Memory, MemoryFilter, MemoryType, Message as ChatMessage,
+        MessageFilter, Model, ModelFilter, P2pMessageQueue, P2pNode, Participant, ParticipantFilter,
+        ParticipantType, PeerIdWrapper, Task, TaskFilter, User, UserFilter, CreateWorkflow,
+        CreateWorkflowExecution, CreateWorkflowStep, CreateWorkflowStepExecution, Workflow,
+        WorkflowExecution, WorkflowExecutionFilter, WorkflowFilter, WorkflowStep,
+        WorkflowStepExecution, AuditLogEntry, AuditLogFilter, PeerAction, PeerDecision,
+        PeerPermission, PeerPermissionFilter, P2pNetworkStats, P2pNodeFilter, PeerAgent,
+        PublishedAgent, FileTransfer, FileTransferFilter, TransferStatus, PeerCertificate,
+        McpConnectionStatus, McpServer, McpServerFilter, McpTool, McpToolFilter, McpToolType,
+        CreateNote, Event, EventFilter, Note, NoteFilter, Tool as ToolRecord, ToolCategory,
     },
-    error::Result,
+    error::{AppError, Result},
+    keys::KeyRotation,
+    mcp::{endpoint::McpEndpointSettings, protocol::Tool},
     repositories::RepositoryFactory,
     storage::db::DatabaseManager,
 };
@@ -35,6 +51,84 @@ impl Message<GetConversationParticipantIds> for DatabaseActor {
     }
 }
 
+impl Message<GetConversationParticipantId> for DatabaseActor {
+    type Reply = Result<Option<Uuid>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetConversationParticipantId,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .get_conversation_participant_id(&msg.conversation_id, msg.participant)
+            .await
+    }
+}
+
+impl Message<GetContactPeerIds> for DatabaseActor {
+    type Reply = Result<Vec<PeerIdWrapper>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetContactPeerIds,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_peer_ids_by_contact_id(&msg.0).await
+    }
+}
+
+impl Message<CreateMessage> for DatabaseActor {
+    type Reply = Result<ChatMessage>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateMessage,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let message = ChatMessage {
+            id: Uuid::new_v4(),
+            conversation_id: msg.conversation_id,
+            sender_id: msg.sender_id,
+            parent_message_id: msg.parent_message_id,
+            content: msg.content,
+            status: msg.status,
+            refs: msg.refs,
+            metadata: msg.metadata,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            reply_to_id: msg.reply_to_id,
+            branch_conversation_id: msg.branch_conversation_id,
+            parent_id: msg.parent_id,
+            workspace_id: msg.workspace_id,
+        };
+        self.db.create_message(&message).await
+    }
+}
+
+impl Message<ListMessages> for DatabaseActor {
+    type Reply = Result<Vec<ChatMessage>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListMessages,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_messages(&msg.0).await
+    }
+}
+
+impl Message<GetParticipantsByPeerId> for DatabaseActor {
+    type Reply = Result<Vec<Participant>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetParticipantsByPeerId,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_participant_by_peer_id(msg.0, &msg.1).await
+    }
+}
+
 impl Message<CreateConversation> for DatabaseActor {
     type Reply = Result<Conversation>;
 
@@ -108,6 +202,18 @@ impl Message<ListTasks> for DatabaseActor {
     }
 }
 
+impl Message<GetTask> for DatabaseActor {
+    type Reply = Result<Option<Task>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetTask,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_task_by_id(&msg.0).await
+    }
+}
+
 impl Message<CreateTask> for DatabaseActor {
     type Reply = Result<Task>;
 
@@ -257,6 +363,238 @@ impl Message<ListAgents> for DatabaseActor {
     }
 }
 
+impl Message<GetAgent> for DatabaseActor {
+    type Reply = Result<Option<Agent>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetAgent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        Ok(self.db.get_agent_by_id(&msg.0).await?)
+    }
+}
+
+impl Message<GetModel> for DatabaseActor {
+    type Reply = Result<Option<Model>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetModel,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        Ok(self.db.get_model_by_id(&msg.0).await?)
+    }
+}
+
+impl Message<CreateCredential> for DatabaseActor {
+    type Reply = Result<Credential>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateCredential,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_credential(&msg).await
+    }
+}
+
+impl Message<GetCredential> for DatabaseActor {
+    type Reply = Result<Option<Credential>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetCredential,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_credential_by_id(&msg.0).await
+    }
+}
+
+impl Message<GetWorkspaceCredential> for DatabaseActor {
+    type Reply = Result<Option<Credential>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetWorkspaceCredential,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .get_workspace_credential(msg.workspace_id.as_ref(), &msg.credential_name)
+            .await
+    }
+}
+
+impl Message<DeleteCredential> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteCredential,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_credential(&msg.0).await
+    }
+}
+
+impl Message<CreateMemory> for DatabaseActor {
+    type Reply = Result<Memory>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateMemory,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_memory(&msg).await
+    }
+}
+
+impl Message<ListMemories> for DatabaseActor {
+    type Reply = Result<Vec<Memory>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListMemories,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_memories(&msg.0).await
+    }
+}
+
+impl Message<UpdateMemory> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateMemory,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_memories(&msg.0).await
+    }
+}
+
+impl Message<TouchMemories> for DatabaseActor {
+    type Reply = Result<u64>;
+
+    async fn handle(
+        &mut self,
+        msg: TouchMemories,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.touch_memories(&msg.0).await
+    }
+}
+
+impl Message<DecayMemories> for DatabaseActor {
+    type Reply = Result<u64>;
+
+    async fn handle(
+        &mut self,
+        msg: DecayMemories,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .decay_memory_importance(msg.memory_type, msg.factor, msg.floor, msg.accessed_before)
+            .await
+    }
+}
+
+impl Message<GetDocument> for DatabaseActor {
+    type Reply = Result<Option<Document>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetDocument,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_document_by_id(&msg.0).await
+    }
+}
+
+impl Message<SetDocumentIndexed> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SetDocumentIndexed,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.set_document_indexed(&msg.0, msg.1).await
+    }
+}
+
+impl Message<SetDocumentEmbedded> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SetDocumentEmbedded,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.set_document_embedded(&msg.0, msg.1).await
+    }
+}
+
+impl Message<CreateDocumentChunk> for DatabaseActor {
+    type Reply = Result<DocumentChunk>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateDocumentChunk,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_document_chunk(&msg).await
+    }
+}
+
+impl Message<GetDocumentChunk> for DatabaseActor {
+    type Reply = Result<Option<DocumentChunk>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetDocumentChunk,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_document_chunk_by_id(&msg.0).await
+    }
+}
+
+impl Message<SetDocumentChunkEmbedding> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SetDocumentChunkEmbedding,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.set_document_chunk_embedding(&msg.0, &msg.1).await
+    }
+}
+
+impl Message<DeleteDocumentChunks> for DatabaseActor {
+    type Reply = Result<u64>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteDocumentChunks,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_document_chunks_for_document(&msg.0).await
+    }
+}
+
+impl Message<ListModels> for DatabaseActor {
+    type Reply = Result<Vec<Model>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListModels,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_models(&msg.0).await
+    }
+}
+
 impl Message<CreateP2pNode> for DatabaseActor {
     type Reply = Result<P2pNode>;
 
@@ -353,20 +691,1553 @@ impl Message<ListConversations> for DatabaseActor {
     }
 }
 
-pub struct GetConversationParticipantIds(pub Uuid);
-pub struct CreateBatchParticipants(pub Vec<CreateConversationParticipant>);
-pub struct ListAgents(pub AgentFilter);
-pub struct UpdateAgent(pub Agent);
-pub struct DeleteAgent(pub Uuid);
-pub struct ListTasks(pub TaskFilter);
-pub struct UpdateTask(pub Task);
-pub struct DeleteTask(pub Uuid);
-pub struct ListUsers(pub UserFilter);
-pub struct UpdateUser(pub User);
-pub struct DeleteUser(pub Uuid);
-pub struct UpdateP2pNode(pub P2pNode);
-pub struct DeleteP2pNode(pub Uuid, pub Uuid);
-pub struct UpdateParticipant(pub Participant);
-pub struct DeleteParticipant(pub Uuid);
-pub struct ListParticipants(pub ParticipantFilter);
-pub struct ListConversations(pub ConversationFilter);
+impl Message<CreateAgentChain> for DatabaseActor {
+    type Reply = Result<AgentChain>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateAgentChain,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_agent_chain(&msg).await
+    }
+}
+
+impl Message<GetAgentChain> for DatabaseActor {
+    type Reply = Result<Option<AgentChain>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetAgentChain,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_agent_chain_by_id(&msg.0).await
+    }
+}
+
+impl Message<ListAgentChains> for DatabaseActor {
+    type Reply = Result<Vec<AgentChain>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListAgentChains,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_agent_chains(&msg.0).await
+    }
+}
+
+impl Message<UpdateAgentChain> for DatabaseActor {
+    type Reply = Result<AgentChain>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateAgentChain,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_agent_chain(&msg.0).await
+    }
+}
+
+impl Message<DeleteAgentChain> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteAgentChain,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_agent_chain(&msg.0).await
+    }
+}
+
+impl Message<CreateAgentChainStep> for DatabaseActor {
+    type Reply = Result<AgentChainStep>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateAgentChainStep,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_agent_chain_step(&msg).await
+    }
+}
+
+impl Message<ListAgentChainSteps> for DatabaseActor {
+    type Reply = Result<Vec<AgentChainStep>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListAgentChainSteps,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_agent_chain_steps(&msg.0).await
+    }
+}
+
+impl Message<UpdateAgentChainStep> for DatabaseActor {
+    type Reply = Result<AgentChainStep>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateAgentChainStep,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_agent_chain_step(&msg.0).await
+    }
+}
+
+impl Message<DeleteAgentChainStep> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteAgentChainStep,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_agent_chain_step(&msg.0).await
+    }
+}
+
+impl Message<CreateAgentChainExecution> for DatabaseActor {
+    type Reply = Result<AgentChainExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateAgentChainExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_agent_chain_execution(&msg).await
+    }
+}
+
+impl Message<GetAgentChainExecution> for DatabaseActor {
+    type Reply = Result<Option<AgentChainExecution>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetAgentChainExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_agent_chain_execution_by_id(&msg.0).await
+    }
+}
+
+impl Message<ListAgentChainExecutions> for DatabaseActor {
+    type Reply = Result<Vec<AgentChainExecution>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListAgentChainExecutions,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_agent_chain_executions(&msg.0).await
+    }
+}
+
+impl Message<UpdateAgentChainExecution> for DatabaseActor {
+    type Reply = Result<AgentChainExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateAgentChainExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_agent_chain_execution(&msg.0).await
+    }
+}
+
+impl Message<CreateAgentChainStepExecution> for DatabaseActor {
+    type Reply = Result<AgentChainStepExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateAgentChainStepExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_agent_chain_step_execution(&msg).await
+    }
+}
+
+impl Message<UpdateAgentChainStepExecution> for DatabaseActor {
+    type Reply = Result<AgentChainStepExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateAgentChainStepExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_agent_chain_step_execution(&msg.0).await
+    }
+}
+
+impl Message<ListAgentChainStepExecutions> for DatabaseActor {
+    type Reply = Result<Vec<AgentChainStepExecution>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListAgentChainStepExecutions,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_agent_chain_step_executions(&msg.0).await
+    }
+}
+
+impl Message<GetParticipant> for DatabaseActor {
+    type Reply = Result<Option<Participant>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetParticipant,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_participant(&msg.0).await
+    }
+}
+
+impl Message<CreateWorkflow> for DatabaseActor {
+    type Reply = Result<Workflow>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateWorkflow,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_workflow(&msg).await
+    }
+}
+
+impl Message<GetWorkflow> for DatabaseActor {
+    type Reply = Result<Option<Workflow>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetWorkflow,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_workflow_by_id(&msg.0).await
+    }
+}
+
+impl Message<ListWorkflows> for DatabaseActor {
+    type Reply = Result<Vec<Workflow>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListWorkflows,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_workflows(&msg.0).await
+    }
+}
+
+impl Message<UpdateWorkflow> for DatabaseActor {
+    type Reply = Result<Workflow>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateWorkflow,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_workflow(&msg.0).await
+    }
+}
+
+impl Message<DeleteWorkflow> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteWorkflow,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_workflow(&msg.0).await
+    }
+}
+
+impl Message<CreateWorkflowStep> for DatabaseActor {
+    type Reply = Result<WorkflowStep>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateWorkflowStep,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_workflow_step(&msg).await
+    }
+}
+
+impl Message<ListWorkflowSteps> for DatabaseActor {
+    type Reply = Result<Vec<WorkflowStep>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListWorkflowSteps,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_workflow_steps(&msg.0).await
+    }
+}
+
+impl Message<UpdateWorkflowStep> for DatabaseActor {
+    type Reply = Result<WorkflowStep>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateWorkflowStep,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_workflow_step(&msg.0).await
+    }
+}
+
+impl Message<DeleteWorkflowStep> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteWorkflowStep,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_workflow_step(&msg.0).await
+    }
+}
+
+impl Message<CreateWorkflowExecution> for DatabaseActor {
+    type Reply = Result<WorkflowExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateWorkflowExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_workflow_execution(&msg).await
+    }
+}
+
+impl Message<GetWorkflowExecution> for DatabaseActor {
+    type Reply = Result<Option<WorkflowExecution>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetWorkflowExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_workflow_execution_by_id(&msg.0).await
+    }
+}
+
+impl Message<ListWorkflowExecutions> for DatabaseActor {
+    type Reply = Result<Vec<WorkflowExecution>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListWorkflowExecutions,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_workflow_executions(&msg.0).await
+    }
+}
+
+impl Message<UpdateWorkflowExecution> for DatabaseActor {
+    type Reply = Result<WorkflowExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateWorkflowExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_workflow_execution(&msg.0).await
+    }
+}
+
+impl Message<CreateWorkflowStepExecution> for DatabaseActor {
+    type Reply = Result<WorkflowStepExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateWorkflowStepExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_workflow_step_execution(&msg).await
+    }
+}
+
+impl Message<UpdateWorkflowStepExecution> for DatabaseActor {
+    type Reply = Result<WorkflowStepExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateWorkflowStepExecution,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_workflow_step_execution(&msg.0).await
+    }
+}
+
+impl Message<CheckpointWorkflowStep> for DatabaseActor {
+    type Reply = Result<(WorkflowStepExecution, WorkflowExecution)>;
+
+    async fn handle(
+        &mut self,
+        msg: CheckpointWorkflowStep,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.checkpoint_workflow_step(&msg.0, &msg.1).await
+    }
+}
+
+impl Message<ListWorkflowStepExecutions> for DatabaseActor {
+    type Reply = Result<Vec<WorkflowStepExecution>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListWorkflowStepExecutions,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_workflow_step_executions(&msg.0).await
+    }
+}
+
+impl Message<CreateP2pMessage> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateP2pMessage,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::create(&self.db.pool, &msg.0).await
+    }
+}
+
+impl Message<GetP2pMessage> for DatabaseActor {
+    type Reply = Result<Option<P2pMessageQueue>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetP2pMessage,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::get_by_id(&self.db.pool, &msg.0).await
+    }
+}
+
+impl Message<ListDeliverableP2pMessages> for DatabaseActor {
+    type Reply = Result<Vec<P2pMessageQueue>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListDeliverableP2pMessages,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::get_deliverable_for_peer(&self.db.pool, &msg.0).await
+    }
+}
+
+impl Message<ListP2pMessageRecipients> for DatabaseActor {
+    type Reply = Result<Vec<String>>;
+
+    async fn handle(
+        &mut self,
+        _msg: ListP2pMessageRecipients,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::get_peers_with_deliverable_messages(&self.db.pool).await
+    }
+}
+
+impl Message<MarkP2pMessageSent> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: MarkP2pMessageSent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::mark_as_sent(&self.db.pool, &msg.0).await
+    }
+}
+
+impl Message<MarkP2pMessageDelivered> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: MarkP2pMessageDelivered,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::mark_as_delivered(&self.db.pool, &msg.0).await
+    }
+}
+
+impl Message<MarkP2pMessageFailed> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: MarkP2pMessageFailed,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::mark_as_failed(&self.db.pool, &msg.0, msg.1).await
+    }
+}
+
+impl Message<FailUnacknowledgedP2pMessages> for DatabaseActor {
+    type Reply = Result<u64>;
+
+    async fn handle(
+        &mut self,
+        msg: FailUnacknowledgedP2pMessages,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::mark_unacknowledged_as_failed(&self.db.pool, &msg.0).await
+    }
+}
+
+impl Message<ExpireP2pMessages> for DatabaseActor {
+    type Reply = Result<u64>;
+
+    async fn handle(
+        &mut self,
+        _msg: ExpireP2pMessages,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::mark_expired_messages(&self.db.pool).await
+    }
+}
+
+impl Message<RecordReceivedMessage> for DatabaseActor {
+    type Reply = Result<bool>;
+
+    async fn handle(
+        &mut self,
+        msg: RecordReceivedMessage,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        P2pMessageQueue::record_received(&self.db.pool, &msg.signer, &msg.issued_at, &msg.nonce)
+            .await
+    }
+}
+
+impl Message<AuthorizePeer> for DatabaseActor {
+    type Reply = Result<(PeerDecision, bool)>;
+
+    async fn handle(
+        &mut self,
+        msg: AuthorizePeer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.authorize_peer(&msg.0, &msg.1).await
+    }
+}
+
+impl Message<GetPeerPermission> for DatabaseActor {
+    type Reply = Result<Option<PeerPermission>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetPeerPermission,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_peer_permission(&msg.0).await
+    }
+}
+
+impl Message<ListPeerPermissions> for DatabaseActor {
+    type Reply = Result<Vec<PeerPermission>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListPeerPermissions,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_peer_permissions(&msg.0).await
+    }
+}
+
+impl Message<UpdatePeerPermission> for DatabaseActor {
+    type Reply = Result<PeerPermission>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdatePeerPermission,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_peer_permission(&msg.0).await
+    }
+}
+
+impl Message<ListAuditLog> for DatabaseActor {
+    type Reply = Result<Vec<AuditLogEntry>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListAuditLog,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_audit_log(&msg.0).await
+    }
+}
+
+impl Message<RecordDiscoveredPeer> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: RecordDiscoveredPeer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .record_discovered_peer(&msg.peer_id, &msg.multiaddr, &msg.source)
+            .await
+    }
+}
+
+impl Message<GetNetworkConfig> for DatabaseActor {
+    type Reply = Result<NetworkConfig>;
+
+    async fn handle(
+        &mut self,
+        _msg: GetNetworkConfig,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        NetworkConfig::load(&self.db).await
+    }
+}
+
+impl Message<SaveNetworkConfig> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SaveNetworkConfig,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        msg.0.save(&self.db).await
+    }
+}
+
+impl Message<ListP2pNodes> for DatabaseActor {
+    type Reply = Result<Vec<P2pNode>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListP2pNodes,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_p2p_nodes(&msg.0).await
+    }
+}
+
+impl Message<GetNetworkStats> for DatabaseActor {
+    type Reply = Result<P2pNetworkStats>;
+
+    async fn handle(
+        &mut self,
+        _msg: GetNetworkStats,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_network_stats().await
+    }
+}
+
+impl Message<UpdatePeerLink> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdatePeerLink,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .update_p2p_node_connection_quality(&msg.peer_id, msg.quality, msg.latency_ms)
+            .await?;
+        self.db
+            .patch_p2p_node_metadata(&msg.peer_id, &json!({ "relayed": msg.relayed }))
+            .await
+    }
+}
+
+impl Message<RecordPeerIdentity> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: RecordPeerIdentity,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .record_peer_identity(&msg.peer_id, &msg.protocols, msg.is_relay, &msg.metadata)
+            .await
+    }
+}
+
+impl Message<PatchP2pNodeMetadata> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: PatchP2pNodeMetadata,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.patch_p2p_node_metadata(&msg.0, &msg.1).await
+    }
+}
+
+impl Message<MarkP2pNodeOffline> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: MarkP2pNodeOffline,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.mark_offline(&msg.0).await
+    }
+}
+
+impl Message<MarkStaleP2pNodesOffline> for DatabaseActor {
+    type Reply = Result<u64>;
+
+    async fn handle(
+        &mut self,
+        msg: MarkStaleP2pNodesOffline,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.mark_stale_as_offline(&msg.0).await
+    }
+}
+
+impl Message<PublishAgent> for DatabaseActor {
+    type Reply = Result<PublishedAgent>;
+
+    async fn handle(
+        &mut self,
+        msg: PublishAgent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .publish_agent(
+                msg.agent_id,
+                msg.max_concurrent_requests,
+                msg.daily_token_quota,
+            )
+            .await
+    }
+}
+
+impl Message<UnpublishAgent> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: UnpublishAgent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.unpublish_agent(msg.0).await
+    }
+}
+
+impl Message<GetPublishedAgent> for DatabaseActor {
+    type Reply = Result<Option<PublishedAgent>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetPublishedAgent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_published_agent(msg.0).await
+    }
+}
+
+impl Message<ListPeerAgents> for DatabaseActor {
+    type Reply = Result<Vec<PeerAgent>>;
+
+    async fn handle(
+        &mut self,
+        _msg: ListPeerAgents,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_peer_agents().await
+    }
+}
+
+impl Message<GetPeerAgentTokens> for DatabaseActor {
+    type Reply = Result<i64>;
+
+    async fn handle(
+        &mut self,
+        msg: GetPeerAgentTokens,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .get_peer_agent_tokens(&msg.peer_id, msg.agent_id, msg.day)
+            .await
+    }
+}
+
+impl Message<RecordPeerAgentUsage> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: RecordPeerAgentUsage,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .record_peer_agent_usage(&msg.peer_id, msg.agent_id, msg.day, msg.tokens)
+            .await
+    }
+}
+
+impl Message<CreateFileTransfer> for DatabaseActor {
+    type Reply = Result<FileTransfer>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateFileTransfer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_file_transfer(&msg.0).await
+    }
+}
+
+impl Message<GetFileTransfer> for DatabaseActor {
+    type Reply = Result<Option<FileTransfer>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetFileTransfer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_file_transfer(msg.0).await
+    }
+}
+
+impl Message<ListFileTransfers> for DatabaseActor {
+    type Reply = Result<Vec<FileTransfer>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListFileTransfers,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_file_transfers(&msg.0).await
+    }
+}
+
+impl Message<MarkFileTransferChunkDone> for DatabaseActor {
+    type Reply = Result<FileTransfer>;
+
+    async fn handle(
+        &mut self,
+        msg: MarkFileTransferChunkDone,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.mark_file_transfer_chunk_done(msg.0, msg.1).await
+    }
+}
+
+impl Message<UpdateFileTransferStatus> for DatabaseActor {
+    type Reply = Result<FileTransfer>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateFileTransferStatus,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .update_file_transfer_status(
+                msg.id,
+                msg.status,
+                msg.error.as_deref(),
+                msg.file_path.as_deref(),
+            )
+            .await
+    }
+}
+
+impl Message<GetTransferPolicy> for DatabaseActor {
+    type Reply = Result<TransferPolicy>;
+
+    async fn handle(
+        &mut self,
+        _msg: GetTransferPolicy,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        TransferPolicy::load(&self.db).await
+    }
+}
+
+impl Message<SaveTransferPolicy> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SaveTransferPolicy,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        msg.0.save(&self.db).await
+    }
+}
+
+impl Message<GetPeerCertificate> for DatabaseActor {
+    type Reply = Result<Option<PeerCertificate>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetPeerCertificate,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_peer_certificate(&msg.0).await
+    }
+}
+
+impl Message<ApplyKeyRotation> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: ApplyKeyRotation,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.apply_key_rotation(&msg.peer_id, &msg.rotation).await
+    }
+}
+
+impl Message<ListTrustingPeerIds> for DatabaseActor {
+    type Reply = Result<Vec<PeerIdWrapper>>;
+
+    async fn handle(
+        &mut self,
+        _msg: ListTrustingPeerIds,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_trusting_peer_ids().await
+    }
+}
+
+impl Message<ListMcpServers> for DatabaseActor {
+    type Reply = Result<Vec<McpServer>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListMcpServers,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_mcp_servers(&msg.0).await
+    }
+}
+
+impl Message<GetMcpServer> for DatabaseActor {
+    type Reply = Result<Option<McpServer>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetMcpServer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_mcp_server_by_id(&msg.0).await
+    }
+}
+
+impl Message<CreateMcpServer> for DatabaseActor {
+    type Reply = Result<McpServer>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateMcpServer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_mcp_server(&msg.0).await?;
+        self.db
+            .get_mcp_server_by_id(&msg.0.id)
+            .await?
+            .ok_or_else(|| AppError::not_found("MCP server", msg.0.id))
+    }
+}
+
+impl Message<SetMcpServerActive> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SetMcpServerActive,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.set_mcp_server_active(&msg.id, msg.is_active).await
+    }
+}
+
+impl Message<DeleteMcpServer> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteMcpServer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_mcp_server(&msg.0).await
+    }
+}
+
+impl Message<UpdateMcpServerConnection> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateMcpServerConnection,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db
+            .update_mcp_server_connection(
+                &msg.id,
+                msg.status,
+                msg.error.as_deref(),
+                msg.capabilities.as_ref(),
+                msg.server_info.as_ref(),
+            )
+            .await
+    }
+}
+
+impl Message<SyncMcpTools> for DatabaseActor {
+    type Reply = Result<Vec<McpTool>>;
+
+    async fn handle(
+        &mut self,
+        msg: SyncMcpTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        McpTool::sync_server_tools(&self.db.pool, &msg.server_id, msg.tool_type, &msg.tools).await
+    }
+}
+
+impl Message<ListMcpTools> for DatabaseActor {
+    type Reply = Result<Vec<McpTool>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListMcpTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        McpTool::list(&self.db.pool, &msg.0).await
+    }
+}
+
+impl Message<SetMcpToolEnabled> for DatabaseActor {
+    type Reply = Result<McpTool>;
+
+    async fn handle(
+        &mut self,
+        msg: SetMcpToolEnabled,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        McpTool::update_enabled(&self.db.pool, &msg.id, msg.is_enabled).await?;
+        McpTool::get_by_id(&self.db.pool, &msg.id)
+            .await?
+            .ok_or_else(|| AppError::not_found("MCP tool", msg.id))
+    }
+}
+
+impl Message<CreateEvent> for DatabaseActor {
+    type Reply = Result<Event>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_event(&msg.0).await
+    }
+}
+
+impl Message<GetEvent> for DatabaseActor {
+    type Reply = Result<Option<Event>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_event_by_id(&msg.0).await
+    }
+}
+
+impl Message<ListEvents> for DatabaseActor {
+    type Reply = Result<Vec<Event>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListEvents,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_events(&msg.0).await
+    }
+}
+
+impl Message<UpdateEvent> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_event(&msg.0).await
+    }
+}
+
+impl Message<DeleteEvent> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_event(&msg.0).await
+    }
+}
+
+impl Message<CreateNote> for DatabaseActor {
+    type Reply = Result<Note>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateNote,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_note(&msg).await
+    }
+}
+
+impl Message<GetNote> for DatabaseActor {
+    type Reply = Result<Option<Note>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetNote,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_note_by_id(&msg.0).await
+    }
+}
+
+impl Message<ListNotes> for DatabaseActor {
+    type Reply = Result<Vec<Note>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListNotes,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_notes(&msg.0).await
+    }
+}
+
+impl Message<UpdateNote> for DatabaseActor {
+    type Reply = Result<Note>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateNote,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_note(&msg.0).await
+    }
+}
+
+impl Message<DeleteNote> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteNote,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_note(&msg.0).await
+    }
+}
+
+impl Message<EnsureBuiltinTools> for DatabaseActor {
+    type Reply = Result<Vec<ToolRecord>>;
+
+    async fn handle(
+        &mut self,
+        msg: EnsureBuiltinTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let mut tools = Vec::with_capacity(msg.0.len());
+        for (name, description) in msg.0 {
+            let tool = match self.db.get_tool_by_name(name).await? {
+                Some(tool) => tool,
+                None => {
+                    self.db
+                        .create_tool(&ToolRecord {
+                            id: Uuid::new_v4(),
+                            name: name.to_string(),
+                            description: Some(description.to_string()),
+                            category: ToolCategory::System,
+                            definition: None,
+                            config: None,
+                            auth_required: false,
+                            is_active: false,
+                            workspace_id: None,
+                            created_by_id: None,
+                            created_at: Utc::now(),
+                            updated_at: Utc::now(),
+                        })
+                        .await?
+                }
+            };
+            tools.push(tool);
+        }
+        Ok(tools)
+    }
+}
+
+impl Message<GetToolByName> for DatabaseActor {
+    type Reply = Result<Option<ToolRecord>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetToolByName,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_tool_by_name(&msg.0).await
+    }
+}
+
+impl Message<UpdateTool> for DatabaseActor {
+    type Reply = Result<ToolRecord>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateTool,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_tool(&msg.0).await
+    }
+}
+
+impl Message<ListAgentTools> for DatabaseActor {
+    type Reply = Result<Vec<ToolRecord>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListAgentTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_agent_tools(&msg.0).await
+    }
+}
+
+impl Message<SetAgentTools> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SetAgentTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.set_agent_tools(&msg.agent_id, &msg.tool_ids).await
+    }
+}
+
+impl Message<GetMcpEndpointSettings> for DatabaseActor {
+    type Reply = Result<McpEndpointSettings>;
+
+    async fn handle(
+        &mut self,
+        _msg: GetMcpEndpointSettings,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        McpEndpointSettings::load(&self.db).await
+    }
+}
+
+impl Message<SaveMcpEndpointSettings> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: SaveMcpEndpointSettings,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        msg.0.save(&self.db).await
+    }
+}
+
+impl Message<CreateApiKey> for DatabaseActor {
+    type Reply = Result<ApiKey>;
+
+    async fn handle(
+        &mut self,
+        msg: CreateApiKey,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.create_api_key(&msg).await
+    }
+}
+
+impl Message<GetApiKeyByHash> for DatabaseActor {
+    type Reply = Result<Option<ApiKey>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetApiKeyByHash,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.get_api_key_by_hash(&msg.0).await
+    }
+}
+
+impl Message<ListApiKeys> for DatabaseActor {
+    type Reply = Result<Vec<ApiKey>>;
+
+    async fn handle(
+        &mut self,
+        msg: ListApiKeys,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.list_api_keys(&msg.0).await
+    }
+}
+
+impl Message<TouchApiKey> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: TouchApiKey,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.update_api_key_last_used(&msg.0).await
+    }
+}
+
+impl Message<DeleteApiKey> for DatabaseActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: DeleteApiKey,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.delete_api_key(&msg.0).await
+    }
+}
+
+pub struct GetConversationParticipantIds(pub Uuid);
+pub struct GetConversationParticipantId {
+    pub conversation_id: Uuid,
+    pub participant: ParticipantType,
+}
+pub struct GetContactPeerIds(pub Uuid);
+pub struct GetParticipantsByPeerId(pub Uuid, pub PeerIdWrapper);
+pub struct ListMessages(pub MessageFilter);
+pub struct GetAgent(pub Uuid);
+pub struct GetModel(pub Uuid);
+pub struct GetCredential(pub Uuid);
+pub struct GetWorkspaceCredential {
+    pub workspace_id: Option<Uuid>,
+    pub credential_name: String,
+}
+pub struct DeleteCredential(pub Uuid);
+pub struct ListMemories(pub MemoryFilter);
+pub struct UpdateMemory(pub Memory);
+pub struct TouchMemories(pub Vec<Uuid>);
+pub struct DecayMemories {
+    pub memory_type: MemoryType,
+    pub factor: f64,
+    pub floor: f64,
+    pub accessed_before: DateTime<Utc>,
+}
+pub struct GetDocument(pub Uuid);
+pub struct SetDocumentIndexed(pub Uuid, pub bool);
+pub struct SetDocumentEmbedded(pub Uuid, pub bool);
+pub struct GetDocumentChunk(pub Uuid);
+pub struct SetDocumentChunkEmbedding(pub Uuid, pub Uuid);
+pub struct DeleteDocumentChunks(pub Uuid);
+pub struct ListModels(pub ModelFilter);
+pub struct CreateBatchParticipants(pub Vec<CreateConversationParticipant>);
+pub struct ListAgents(pub AgentFilter);
+pub struct UpdateAgent(pub Agent);
+pub struct DeleteAgent(pub Uuid);
+pub struct ListTasks(pub TaskFilter);
+pub struct GetTask(pub Uuid);
+pub struct UpdateTask(pub Task);
+pub struct DeleteTask(pub Uuid);
+pub struct ListUsers(pub UserFilter);
+pub struct UpdateUser(pub User);
+pub struct DeleteUser(pub Uuid);
+pub struct UpdateP2pNode(pub P2pNode);
+pub struct DeleteP2pNode(pub Uuid, pub Uuid);
+pub struct UpdateParticipant(pub Participant);
+pub struct DeleteParticipant(pub Uuid);
+pub struct ListParticipants(pub ParticipantFilter);
+pub struct ListConversations(pub ConversationFilter);
+pub struct GetAgentChain(pub Uuid);
+pub struct ListAgentChains(pub AgentChainFilter);
+pub struct UpdateAgentChain(pub AgentChain);
+pub struct DeleteAgentChain(pub Uuid);
+pub struct ListAgentChainSteps(pub Uuid);
+pub struct UpdateAgentChainStep(pub AgentChainStep);
+pub struct DeleteAgentChainStep(pub Uuid);
+pub struct GetAgentChainExecution(pub Uuid);
+pub struct ListAgentChainExecutions(pub AgentChainExecutionFilter);
+pub struct UpdateAgentChainExecution(pub AgentChainExecution);
+pub struct UpdateAgentChainStepExecution(pub AgentChainStepExecution);
+pub struct ListAgentChainStepExecutions(pub Uuid);
+pub struct GetParticipant(pub Uuid);
+pub struct GetWorkflow(pub Uuid);
+pub struct ListWorkflows(pub WorkflowFilter);
+pub struct UpdateWorkflow(pub Workflow);
+pub struct DeleteWorkflow(pub Uuid);
+pub struct ListWorkflowSteps(pub Uuid);
+pub struct UpdateWorkflowStep(pub WorkflowStep);
+pub struct DeleteWorkflowStep(pub Uuid);
+pub struct GetWorkflowExecution(pub Uuid);
+pub struct ListWorkflowExecutions(pub WorkflowExecutionFilter);
+pub struct UpdateWorkflowExecution(pub WorkflowExecution);
+pub struct UpdateWorkflowStepExecution(pub WorkflowStepExecution);
+pub struct CheckpointWorkflowStep(pub WorkflowStepExecution, pub WorkflowExecution);
+pub struct ListWorkflowStepExecutions(pub Uuid);
+pub struct CreateP2pMessage(pub P2pMessageQueue);
+pub struct GetP2pMessage(pub Uuid);
+pub struct ListDeliverableP2pMessages(pub String);
+pub struct ListP2pMessageRecipients;
+pub struct MarkP2pMessageSent(pub Uuid);
+pub struct MarkP2pMessageDelivered(pub Uuid);
+pub struct MarkP2pMessageFailed(pub Uuid, pub Option<String>);
+pub struct FailUnacknowledgedP2pMessages(pub DateTime<Utc>);
+pub struct ExpireP2pMessages;
+/// Remembers a message delivered from a peer's queue, replies whether it is new
+pub struct RecordReceivedMessage {
+    pub signer: PeerIdWrapper,
+    pub issued_at: DateTime<Utc>,
+    pub nonce: Uuid,
+}
+pub struct AuthorizePeer(pub PeerIdWrapper, pub PeerAction);
+pub struct GetPeerPermission(pub PeerIdWrapper);
+pub struct ListPeerPermissions(pub PeerPermissionFilter);
+pub struct UpdatePeerPermission(pub PeerPermission);
+pub struct ListAuditLog(pub AuditLogFilter);
+pub struct RecordDiscoveredPeer {
+    pub peer_id: PeerIdWrapper,
+    pub multiaddr: String,
+    /// How the peer was found, e.g. `mdns`
+    pub source: String,
+}
+pub struct GetNetworkConfig;
+pub struct SaveNetworkConfig(pub NetworkConfig);
+pub struct ListP2pNodes(pub P2pNodeFilter);
+pub struct GetNetworkStats;
+pub struct UpdatePeerLink {
+    pub peer_id: PeerIdWrapper,
+    pub quality: f64,
+    pub latency_ms: Option<i64>,
+    pub relayed: bool,
+}
+pub struct RecordPeerIdentity {
+    pub peer_id: PeerIdWrapper,
+    pub protocols: Vec<String>,
+    pub is_relay: bool,
+    pub metadata: Value,
+}
+pub struct PatchP2pNodeMetadata(pub PeerIdWrapper, pub Value);
+pub struct MarkP2pNodeOffline(pub PeerIdWrapper);
+pub struct MarkStaleP2pNodesOffline(pub DateTime<Utc>);
+pub struct PublishAgent {
+    pub agent_id: Uuid,
+    pub max_concurrent_requests: i64,
+    pub daily_token_quota: Option<i64>,
+}
+pub struct UnpublishAgent(pub Uuid);
+pub struct GetPublishedAgent(pub Uuid);
+pub struct ListPeerAgents;
+pub struct GetPeerAgentTokens {
+    pub peer_id: PeerIdWrapper,
+    pub agent_id: Uuid,
+    pub day: NaiveDate,
+}
+pub struct RecordPeerAgentUsage {
+    pub peer_id: PeerIdWrapper,
+    pub agent_id: Uuid,
+    pub day: NaiveDate,
+    pub tokens: i64,
+}
+pub struct CreateFileTransfer(pub FileTransfer);
+pub struct GetFileTransfer(pub Uuid);
+pub struct ListFileTransfers(pub FileTransferFilter);
+pub struct MarkFileTransferChunkDone(pub Uuid, pub i64);
+pub struct UpdateFileTransferStatus {
+    pub id: Uuid,
+    pub status: TransferStatus,
+    pub error: Option<String>,
+    /// Where a received file was moved to once it's complete
+    pub file_path: Option<String>,
+}
+pub struct GetTransferPolicy;
+pub struct SaveTransferPolicy(pub TransferPolicy);
+pub struct GetPeerCertificate(pub PeerIdWrapper);
+/// Applies a rotation that was verified to be announced by `peer_id`
+pub struct ApplyKeyRotation {
+    pub peer_id: PeerIdWrapper,
+    pub rotation: KeyRotation,
+}
+pub struct ListTrustingPeerIds;
+pub struct ListMcpServers(pub McpServerFilter);
+pub struct GetMcpServer(pub Uuid);
+pub struct CreateMcpServer(pub McpServer);
+pub struct SetMcpServerActive {
+    pub id: Uuid,
+    pub is_active: bool,
+}
+pub struct DeleteMcpServer(pub Uuid);
+pub struct UpdateMcpServerConnection {
+    pub id: Uuid,
+    pub status: McpConnectionStatus,
+    pub error: Option<String>,
+    pub capabilities: Option<Value>,
+    pub server_info: Option<Value>,
+}
+/// Brings the stored tools of a server in line with the ones it lists
+pub struct SyncMcpTools {
+    pub server_id: Uuid,
+    pub tool_type: McpToolType,
+    pub tools: Vec<Tool>,
+}
+pub struct ListMcpTools(pub McpToolFilter);
+pub struct SetMcpToolEnabled {
+    pub id: Uuid,
+    pub is_enabled: bool,
+}
+pub struct GetMcpEndpointSettings;
+pub struct SaveMcpEndpointSettings(pub McpEndpointSettings);
+/// Finds an API key by the hash of the key
+pub struct GetApiKeyByHash(pub String);
+pub struct ListApiKeys(pub ApiKeyFilter);
+/// Records that an API key was used
+pub struct TouchApiKey(pub Uuid);
+pub struct DeleteApiKey(pub Uuid);
+pub struct CreateEvent(pub Event);
+pub struct GetEvent(pub Uuid);
+pub struct ListEvents(pub EventFilter);
+pub struct UpdateEvent(pub Event);
+pub struct DeleteEvent(pub Uuid);
+pub struct GetNote(pub Uuid);
+pub struct ListNotes(pub NoteFilter);
+pub struct UpdateNote(pub Note);
+pub struct DeleteNote(pub Uuid);
+/// Gets the rows of the built-in tools by name, adding the missing ones inactive
+pub struct EnsureBuiltinTools(pub Vec<(&'static str, &'static str)>);
+pub struct GetToolByName(pub String);
+pub struct UpdateTool(pub ToolRecord);
+pub struct ListAgentTools(pub Uuid);
+pub struct SetAgentTools {
+    pub agent_id: Uuid,
+    pub tool_ids: Vec<Uuid>,
+}
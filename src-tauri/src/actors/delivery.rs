@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        agents::AgentResponseEvent,
+        database::{
+            CreateP2pMessage, DatabaseActor, ExpireP2pMessages, FailUnacknowledgedP2pMessages,
+            GetP2pMessage, ListDeliverableP2pMessages, ListP2pMessageRecipients,
+            MarkP2pMessageDelivered, MarkP2pMessageFailed, MarkP2pMessageSent,
+        },
+        gateway::GatewayActor,
+        swarm::ConnectionEstablished,
+    },
+    entities::{
+        Message as ChatMessage, P2pMessagePriority, P2pMessageQueue, P2pMessageStatus,
+        P2pMessageType, PeerIdWrapper,
+    },
+    error::{AppError, Result},
+    keys::{KeyRotation, PEER_ID, Signed},
+    utils::get_gateway_id,
+};
+
+/// How often failed deliveries are retried and unacknowledged ones are timed out
+pub const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Attempts after which a message is given up on
+const MAX_RETRIES: i32 = 5;
+/// Time a peer has to acknowledge a message before it is sent again
+const ACK_TIMEOUT_SECS: i64 = 120;
+const BASE_RETRY_DELAY_SECS: i64 = 15;
+const MAX_RETRY_DELAY_SECS: i64 = 30 * 60;
+/// Streamed agent output is only useful for a while, chat messages never expire
+const AGENT_RESPONSE_TTL_SECS: i64 = 60 * 60;
+
+/// Queues outbound messages for peers in `p2p_message_queue` and delivers them in
+/// priority order whenever the peer is reachable. Messages stay queued until the
+/// receiving gateway acknowledges them, so contacts that are offline get them once
+/// they come back.
+#[derive(Actor)]
+pub struct DeliveryActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    /// Peers whose queue is being delivered, and whether another pass was requested
+    /// in the meantime
+    pub flushing: HashMap<String, bool>,
+}
+
+/// A message that can be queued for delivery to a peer
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type", content = "message")]
+pub enum Outbound {
+    ChatMessage(Signed<ChatMessage>),
+    AgentResponse(Signed<AgentResponseEvent>),
+    KeyRotation(Signed<KeyRotation>),
+}
+
+impl Outbound {
+    fn priority(&self) -> P2pMessagePriority {
+        match self {
+            Outbound::ChatMessage(_) => P2pMessagePriority::High,
+            Outbound::AgentResponse(_) => P2pMessagePriority::Normal,
+            // Contacts have to learn of a new key before messages signed with it arrive
+            Outbound::KeyRotation(_) => P2pMessagePriority::Urgent,
+        }
+    }
+
+    fn message_type(&self) -> P2pMessageType {
+        match self {
+            Outbound::ChatMessage(_) | Outbound::AgentResponse(_) => P2pMessageType::AgentMessage,
+            Outbound::KeyRotation(_) => P2pMessageType::SystemMessage,
+        }
+    }
+
+    /// Checks the signature of the message
+    pub fn verify_signature(&self) -> bool {
+        match self {
+            Outbound::ChatMessage(message) => message.verify_signature(),
+            Outbound::AgentResponse(event) => event.verify_signature(),
+            Outbound::KeyRotation(rotation) => rotation.verify_signature(),
+        }
+    }
+
+    /// The peer that signed the message
+    pub fn signer(&self) -> &PeerId {
+        match self {
+            Outbound::ChatMessage(message) => message.client_peer_id(),
+            Outbound::AgentResponse(event) => event.client_peer_id(),
+            Outbound::KeyRotation(rotation) => rotation.client_peer_id(),
+        }
+    }
+
+    /// When and with which nonce the message was signed, which tells it apart from the
+    /// other messages of its signer
+    pub fn signed_at(&self) -> (DateTime<Utc>, Uuid) {
+        match self {
+            Outbound::ChatMessage(message) => (*message.issued_at(), *message.nonce()),
+            Outbound::AgentResponse(event) => (*event.issued_at(), *event.nonce()),
+            Outbound::KeyRotation(rotation) => (*rotation.issued_at(), *rotation.nonce()),
+        }
+    }
+
+    fn expires_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Outbound::ChatMessage(_) | Outbound::KeyRotation(_) => None,
+            Outbound::AgentResponse(_) => Some(now + Duration::seconds(AGENT_RESPONSE_TTL_SECS)),
+        }
+    }
+}
+
+impl From<Signed<ChatMessage>> for Outbound {
+    fn from(message: Signed<ChatMessage>) -> Self {
+        Outbound::ChatMessage(message)
+    }
+}
+
+impl From<Signed<AgentResponseEvent>> for Outbound {
+    fn from(event: Signed<AgentResponseEvent>) -> Self {
+        Outbound::AgentResponse(event)
+    }
+}
+
+impl From<Signed<KeyRotation>> for Outbound {
+    fn from(rotation: Signed<KeyRotation>) -> Self {
+        Outbound::KeyRotation(rotation)
+    }
+}
+
+/// What the gateway of the receiving peer is sent. The id is the id of the queue
+/// entry and is echoed back in the acknowledgement.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: Uuid,
+    pub message: Outbound,
+}
+
+/// Sent back by the receiving gateway once it accepted a delivery
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeliveryAck {
+    pub id: Uuid,
+}
+
+/// Delay before a message that failed `retry_count` times is sent again
+fn retry_delay(retry_count: i32) -> Duration {
+    let exponent = retry_count.saturating_sub(1).clamp(0, 16) as u32;
+    Duration::seconds((BASE_RETRY_DELAY_SECS << exponent).min(MAX_RETRY_DELAY_SECS))
+}
+
+/// Whether a queued message should be sent now. `force` skips the backoff of failed
+/// messages, e.g. because the peer just connected.
+fn is_due(message: &P2pMessageQueue, now: DateTime<Utc>, force: bool) -> bool {
+    match message.status {
+        P2pMessageStatus::Pending => true,
+        P2pMessageStatus::Failed => {
+            force
+                || message
+                    .sent_at
+                    .is_none_or(|sent_at| sent_at + retry_delay(message.retry_count) <= now)
+        }
+        _ => false,
+    }
+}
+
+/// Sends the due messages of a peer to its gateway, stopping at the first message
+/// that can't be sent
+async fn deliver(db: &LocalActorRef<DatabaseActor>, peer: &str, force: bool) -> Result<()> {
+    let now = Utc::now();
+    let due: Vec<_> = db
+        .ask(ListDeliverableP2pMessages(peer.to_string()))
+        .await?
+        .into_iter()
+        .filter(|message| is_due(message, now, force))
+        .collect();
+    if due.is_empty() {
+        return Ok(());
+    }
+    let peer_id: PeerId = peer
+        .parse()
+        .map_err(|e| AppError::validation(format!("Invalid peer id {peer}: {e}")))?;
+    // Messages for unreachable peers stay pending without using up their retries
+    let Some(gateway) = RemoteActorRef::<GatewayActor>::lookup(&get_gateway_id(&peer_id)).await?
+    else {
+        debug!(%peer_id, queued = due.len(), "Peer is not reachable, keeping messages queued");
+        return Ok(());
+    };
+
+    for message in due {
+        let outbound: Outbound = match serde_json::from_str(&message.payload) {
+            Ok(outbound) => outbound,
+            Err(e) => {
+                warn!(message_id = %message.id, "Skipping unreadable queued message: {e}");
+                db.ask(MarkP2pMessageFailed(message.id, Some(e.to_string())))
+                    .await?;
+                continue;
+            }
+        };
+        let delivery = Signed::new(Delivery {
+            id: message.id,
+            message: outbound,
+        });
+        // Marked before sending, so that a quick acknowledgement isn't overwritten
+        db.ask(MarkP2pMessageSent(message.id)).await?;
+        if let Err(e) = gateway.tell(&delivery).await {
+            warn!(%peer_id, message_id = %message.id, "Failed to deliver message: {e}");
+            db.ask(MarkP2pMessageFailed(message.id, Some(e.to_string())))
+                .await?;
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl DeliveryActor {
+    /// Delivers the queue of a peer in the background, one pass at a time
+    fn flush(&mut self, peer: String, force: bool, actor_ref: LocalActorRef<Self>) {
+        if let Some(again) = self.flushing.get_mut(&peer) {
+            *again = true;
+            return;
+        }
+        self.flushing.insert(peer.clone(), false);
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = deliver(&db, &peer, force).await {
+                warn!(%peer, "Failed to deliver queued messages: {e}");
+            }
+            actor_ref.tell(FlushFinished(peer)).await.ok();
+        });
+    }
+}
+
+impl Message<Enqueue> for DeliveryActor {
+    type Reply = Result<()>;
+
+    async fn handle(&mut self, msg: Enqueue, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        let now = Utc::now();
+        let payload = serde_json::to_string(&msg.message)?;
+        let from_peer_id = PEER_ID.get().map(ToString::to_string).unwrap_or_default();
+        for recipient in msg.recipients {
+            let to_peer_id = recipient.to_string();
+            self.db
+                .ask(CreateP2pMessage(P2pMessageQueue {
+                    id: Uuid::new_v4(),
+                    from_peer_id: from_peer_id.clone(),
+                    to_peer_id: to_peer_id.clone(),
+                    message_type: msg.message.message_type(),
+                    priority: msg.message.priority(),
+                    payload: payload.clone(),
+                    conversation_id: msg.conversation_id,
+                    agent_chain_execution_id: None,
+                    status: P2pMessageStatus::Pending,
+                    retry_count: 0,
+                    max_retries: MAX_RETRIES,
+                    expires_at: msg.message.expires_at(now),
+                    sent_at: None,
+                    delivered_at: None,
+                    error_details: None,
+                    created_at: now,
+                }))
+                .await?;
+            self.flush(to_peer_id, false, ctx.actor_ref());
+        }
+        Ok(())
+    }
+}
+
+impl Message<ConnectionEstablished> for DeliveryActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: ConnectionEstablished,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.flush(msg.peer_id().to_string(), true, ctx.actor_ref());
+    }
+}
+
+impl Message<Signed<DeliveryAck>> for DeliveryActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Signed<DeliveryAck>,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let peer_id = msg.client_peer_id().to_string();
+        let id = msg.into_inner().id;
+        let res = async {
+            let Some(message) = self.db.ask(GetP2pMessage(id)).await? else {
+                return Ok(());
+            };
+            // Only the peer a message was addressed to can acknowledge it
+            if message.to_peer_id != peer_id {
+                return Err(AppError::authorization(format!(
+                    "Peer {peer_id} acknowledged message {id} addressed to another peer"
+                )));
+            }
+            Ok(self.db.ask(MarkP2pMessageDelivered(id)).await?)
+        }
+        .await;
+        if let Err(e) = res {
+            warn!(%peer_id, message_id = %id, "Failed to record delivery acknowledgement: {e}");
+        }
+    }
+}
+
+impl Message<RetryDeliveries> for DeliveryActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        _msg: RetryDeliveries,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let expired = self.db.ask(ExpireP2pMessages).await?;
+        let unacknowledged = self
+            .db
+            .ask(FailUnacknowledgedP2pMessages(
+                Utc::now() - Duration::seconds(ACK_TIMEOUT_SECS),
+            ))
+            .await?;
+        if expired > 0 || unacknowledged > 0 {
+            info!(expired, unacknowledged, "Updated queued peer messages");
+        }
+        for peer in self.db.ask(ListP2pMessageRecipients).await? {
+            self.flush(peer, false, ctx.actor_ref());
+        }
+        Ok(())
+    }
+}
+
+impl Message<FlushFinished> for DeliveryActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: FlushFinished,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if self.flushing.remove(&msg.0) == Some(true) {
+            self.flush(msg.0, false, ctx.actor_ref());
+        }
+    }
+}
+
+/// Queues a message for each of the given peers and tries to deliver it right away
+pub struct Enqueue {
+    pub recipients: Vec<PeerIdWrapper>,
+    pub conversation_id: Option<Uuid>,
+    pub message: Outbound,
+}
+
+/// Times out unacknowledged messages and retries the failed ones whose backoff has
+/// passed
+pub struct RetryDeliveries;
+
+struct FlushFinished(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::db::DatabaseManager;
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        assert_eq!(retry_delay(1), Duration::seconds(15));
+        assert_eq!(retry_delay(3), Duration::seconds(60));
+        assert_eq!(retry_delay(20), Duration::seconds(MAX_RETRY_DELAY_SECS));
+    }
+
+    #[tokio::test]
+    async fn test_received_messages_are_recorded_once() {
+        let db = DatabaseManager::setup_test_db().await;
+        let (a, b) = (
+            PeerIdWrapper(PeerId::random()),
+            PeerIdWrapper(PeerId::random()),
+        );
+        let (issued_at, nonce) = (Utc::now(), Uuid::new_v4());
+        let pool = &db.pool;
+
+        let first = P2pMessageQueue::record_received(pool, &a, &issued_at, &nonce).await;
+        assert!(first.unwrap());
+        let again = P2pMessageQueue::record_received(pool, &a, &issued_at, &nonce).await;
+        assert!(!again.unwrap());
+        // Nonces are only unique per signer
+        let other = P2pMessageQueue::record_received(pool, &b, &issued_at, &nonce).await;
+        assert!(other.unwrap());
+    }
+}
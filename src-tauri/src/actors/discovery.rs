@@ -0,0 +1,357 @@
+//! Network configuration and peer discovery.
+//!
+//! Peers find each other in three ways: through the relays of the bootstrap nodes, by
+//! registering at and querying rendezvous points under the namespaces of our
+//! workspaces, and through mDNS on the local network. Discovered peers are recorded in
+//! `p2p_nodes`.
+
+use std::{collections::HashMap, time::Duration};
+
+use kameo::prelude::ActorRef as LocalActorRef;
+use libp2p::{
+    Multiaddr, PeerId, Swarm,
+    multiaddr::Protocol,
+    rendezvous::{self, Cookie, Namespace},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    actors::{
+        database::{DatabaseActor, RecordDiscoveredPeer},
+        swarm::Behaviour,
+    },
+    entities::{PeerIdWrapper, SettingsType},
+    error::Result,
+    storage::db::DatabaseManager,
+    utils::get_data_dir,
+};
+
+/// Name of the global setting holding the [`NetworkConfig`] as JSON
+pub const NETWORK_SETTING: &str = "network";
+/// Name of the per workspace setting holding the workspace's rendezvous namespace
+pub const RENDEZVOUS_NAMESPACE_SETTING: &str = "rendezvous_namespace";
+/// Used when there is no `network` setting
+const CONFIG_FILE: &str = "network.json";
+/// How often the rendezvous points are asked for new peers
+pub const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a registration at a rendezvous point lasts, it's renewed on reconnect
+const REGISTRATION_TTL_SECS: u64 = 2 * 60 * 60;
+
+/// Changes take effect the next time the app starts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NetworkConfig {
+    /// Relays we listen through so that peers outside our network can reach us
+    pub bootstrap_nodes: Vec<String>,
+    pub listen_addresses: Vec<String>,
+    /// Rendezvous servers, as addresses ending with `/p2p/<peer id>`
+    pub rendezvous_points: Vec<String>,
+    /// Namespaces we register under and discover peers in, in addition to the ones set
+    /// for each workspace
+    pub rendezvous_namespaces: Vec<String>,
+    /// Whether peers on the local network are discovered through mDNS
+    pub mdns: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bootstrap_nodes: vec!["/ip4/150.136.100.92/udp/4001/quic-v1".into()],
+            listen_addresses: vec![
+                "/ip4/0.0.0.0/udp/0/quic-v1".into(),
+                "/ip4/0.0.0.0/tcp/0".into(),
+            ],
+            rendezvous_points: Vec::new(),
+            rendezvous_namespaces: Vec::new(),
+            mdns: true,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Loads the config from the `network` setting, or from `network.json` in the data
+    /// directory if there is no such setting, and adds the rendezvous namespaces of the
+    /// workspaces. An invalid config is logged and replaced by the default one.
+    pub async fn load(db: &DatabaseManager) -> Result<Self> {
+        let path = get_data_dir().join(CONFIG_FILE);
+        let mut config = match db.get_global_setting(NETWORK_SETTING).await? {
+            Some(setting) => serde_json::from_str(&setting.value).unwrap_or_else(|e| {
+                warn!("Invalid network setting, using the default config: {e}");
+                Self::default()
+            }),
+            None if path.is_file() => std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| {
+                    warn!(path = %path.display(), "Invalid network config, using the default: {e}");
+                    Self::default()
+                }),
+            None => Self::default(),
+        };
+        for setting in db
+            .list_settings_by_name(RENDEZVOUS_NAMESPACE_SETTING)
+            .await?
+        {
+            if !config.rendezvous_namespaces.contains(&setting.value) {
+                config.rendezvous_namespaces.push(setting.value);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Stores the config in the `network` setting
+    pub async fn save(&self, db: &DatabaseManager) -> Result<()> {
+        db.set_global_setting(
+            NETWORK_SETTING,
+            &serde_json::to_string(self)?,
+            SettingsType::Object,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub fn bootstrap_addrs(&self) -> Vec<Multiaddr> {
+        parse_addrs(&self.bootstrap_nodes, "bootstrap node")
+    }
+
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        parse_addrs(&self.listen_addresses, "listen address")
+    }
+}
+
+fn parse_addrs(addrs: &[String], kind: &str) -> Vec<Multiaddr> {
+    addrs
+        .iter()
+        .filter_map(|addr| match addr.parse() {
+            Ok(addr) => Some(addr),
+            Err(e) => {
+                warn!(%addr, "Ignoring invalid {kind}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// The peer id at the end of an address like `/ip4/1.2.3.4/tcp/4001/p2p/<peer id>`
+fn peer_of(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Some(peer_id),
+        _ => None,
+    }
+}
+
+/// Discovery state driven by the swarm handler, which owns the swarm
+pub struct Discovery {
+    db: LocalActorRef<DatabaseActor>,
+    mdns: bool,
+    rendezvous_points: HashMap<PeerId, Multiaddr>,
+    namespaces: Vec<Namespace>,
+    /// Lets a rendezvous point only return registrations we haven't seen yet
+    cookies: HashMap<(PeerId, Namespace), Cookie>,
+}
+
+impl Discovery {
+    pub fn new(config: &NetworkConfig, db: LocalActorRef<DatabaseActor>) -> Self {
+        let rendezvous_points = parse_addrs(&config.rendezvous_points, "rendezvous point")
+            .into_iter()
+            .filter_map(|addr| match peer_of(&addr) {
+                Some(peer_id) => Some((peer_id, addr)),
+                None => {
+                    warn!(%addr, "Ignoring rendezvous point without a /p2p/<peer id> suffix");
+                    None
+                }
+            })
+            .collect();
+        let namespaces = config
+            .rendezvous_namespaces
+            .iter()
+            .filter_map(|namespace| match Namespace::new(namespace.clone()) {
+                Ok(namespace) => Some(namespace),
+                Err(e) => {
+                    warn!(%namespace, "Ignoring invalid rendezvous namespace: {e}");
+                    None
+                }
+            })
+            .collect();
+        Self {
+            db,
+            mdns: config.mdns,
+            rendezvous_points,
+            namespaces,
+            cookies: HashMap::new(),
+        }
+    }
+
+    /// Connects to the rendezvous points we aren't connected to. Registration and
+    /// discovery start once the connection is established.
+    pub fn dial_rendezvous_points(&self, swarm: &mut Swarm<Behaviour>) {
+        for (peer_id, addr) in &self.rendezvous_points {
+            if !swarm.is_connected(peer_id)
+                && let Err(e) = swarm.dial(addr.clone())
+            {
+                warn!(%addr, "Failed to dial rendezvous point: {e}");
+            }
+        }
+    }
+
+    pub fn on_connection_established(&mut self, swarm: &mut Swarm<Behaviour>, peer_id: &PeerId) {
+        if !self.rendezvous_points.contains_key(peer_id) {
+            return;
+        }
+        for namespace in &self.namespaces {
+            let res = swarm.behaviour_mut().rendezvous.register(
+                namespace.clone(),
+                *peer_id,
+                Some(REGISTRATION_TTL_SECS),
+            );
+            if let Err(e) = res {
+                warn!(%peer_id, %namespace, "Failed to register at rendezvous point: {e}");
+            }
+        }
+        self.discover_at(swarm, *peer_id);
+    }
+
+    /// Asks every rendezvous point for peers, reconnecting to the ones we lost
+    pub fn discover(&mut self, swarm: &mut Swarm<Behaviour>) {
+        let points: Vec<PeerId> = self.rendezvous_points.keys().copied().collect();
+        for peer_id in points {
+            if swarm.is_connected(&peer_id) {
+                self.discover_at(swarm, peer_id);
+            }
+        }
+        self.dial_rendezvous_points(swarm);
+    }
+
+    fn discover_at(&self, swarm: &mut Swarm<Behaviour>, rendezvous_node: PeerId) {
+        for namespace in &self.namespaces {
+            let cookie = self
+                .cookies
+                .get(&(rendezvous_node, namespace.clone()))
+                .cloned();
+            swarm.behaviour_mut().rendezvous.discover(
+                Some(namespace.clone()),
+                cookie,
+                None,
+                rendezvous_node,
+            );
+        }
+    }
+
+    pub fn on_rendezvous_event(
+        &mut self,
+        swarm: &mut Swarm<Behaviour>,
+        event: rendezvous::client::Event,
+    ) {
+        match event {
+            rendezvous::client::Event::Discovered {
+                rendezvous_node,
+                registrations,
+                cookie,
+            } => {
+                if let Some(namespace) = cookie.namespace() {
+                    self.cookies
+                        .insert((rendezvous_node, namespace.clone()), cookie.clone());
+                }
+                let local_peer_id = *swarm.local_peer_id();
+                for registration in registrations {
+                    let peer_id = registration.record.peer_id();
+                    if peer_id == local_peer_id {
+                        continue;
+                    }
+                    let source = format!("rendezvous:{}", registration.namespace);
+                    for addr in registration.record.addresses() {
+                        swarm.add_peer_address(peer_id, addr.clone());
+                    }
+                    if let Some(addr) = registration.record.addresses().first() {
+                        self.record_peer(peer_id, addr, source);
+                    }
+                }
+            }
+            rendezvous::client::Event::Registered {
+                rendezvous_node,
+                namespace,
+                ..
+            } => {
+                info!(%rendezvous_node, %namespace, "Registered at rendezvous point");
+            }
+            rendezvous::client::Event::RegisterFailed {
+                rendezvous_node,
+                namespace,
+                error,
+            } => {
+                warn!(%rendezvous_node, %namespace, "Failed to register at rendezvous point: {error:?}");
+            }
+            rendezvous::client::Event::DiscoverFailed {
+                rendezvous_node,
+                error,
+                ..
+            } => {
+                warn!(%rendezvous_node, "Failed to discover peers at rendezvous point: {error:?}");
+            }
+            rendezvous::client::Event::Expired { peer } => {
+                debug!(%peer, "Rendezvous registration of peer expired");
+            }
+        }
+    }
+
+    /// Records the peers found on the local network. Returns whether the event should
+    /// be passed on to the actor swarm, which is not the case when mDNS is disabled.
+    pub fn on_mdns_event(&self, event: &libp2p::mdns::Event) -> bool {
+        if !self.mdns {
+            return false;
+        }
+        if let libp2p::mdns::Event::Discovered(peers) = event {
+            for (peer_id, addr) in peers {
+                self.record_peer(*peer_id, addr, "mdns".into());
+            }
+        }
+        true
+    }
+
+    fn record_peer(&self, peer_id: PeerId, addr: &Multiaddr, source: String) {
+        let db = self.db.clone();
+        let multiaddr = addr.to_string();
+        tokio::spawn(async move {
+            let res = db
+                .ask(RecordDiscoveredPeer {
+                    peer_id: PeerIdWrapper(peer_id),
+                    multiaddr,
+                    source,
+                })
+                .await;
+            if let Err(e) = res {
+                warn!(%peer_id, "Failed to record discovered peer: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    #[test]
+    fn test_config_defaults_missing_fields() {
+        let config: NetworkConfig =
+            serde_json::from_str(r#"{ "mdns": false, "rendezvousNamespaces": ["team"] }"#).unwrap();
+
+        assert!(!config.mdns);
+        assert_eq!(config.rendezvous_namespaces, ["team"]);
+        assert_eq!(config.listen_addrs().len(), 2);
+        assert_eq!(config.bootstrap_addrs().len(), 1);
+    }
+
+    #[test]
+    fn test_peer_of() {
+        let peer_id = Keypair::generate_ed25519().public().to_peer_id();
+        let addr: Multiaddr = format!("/ip4/10.0.0.1/tcp/4001/p2p/{peer_id}")
+            .parse()
+            .unwrap();
+
+        assert_eq!(peer_of(&addr), Some(peer_id));
+        assert_eq!(peer_of(&"/ip4/10.0.0.1/tcp/4001".parse().unwrap()), None);
+    }
+}
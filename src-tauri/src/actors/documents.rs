@@ -0,0 +1,428 @@
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
+
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use sync_wrapper::SyncFuture;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        database::{
+            DatabaseActor, DeleteDocumentChunks, GetDocument, GetDocumentChunk, GetModel,
+            ListModels, SetDocumentChunkEmbedding, SetDocumentEmbedded, SetDocumentIndexed,
+        },
+        providers::ProviderRegistry,
+        tools::{Tool, ToolDefinition},
+    },
+    entities::{
+        CreateDocumentChunk, DocumentChunk, DocumentChunkType, Model, ModelFilter, ModelType,
+    },
+    error::{AppError, Result},
+    storage::{
+        chunking::{ChunkOptions, chunk_text},
+        vector::{
+            ScoredEntry, SearchFilter, VectorEntry, VectorStore, VectorStoreConfig,
+            VectorStoreRegistry,
+        },
+    },
+};
+
+/// Chunks, embeds and indexes workspace documents and answers retrieval queries over them.
+#[derive(Actor)]
+pub struct DocumentIndexerActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub providers: ProviderRegistry,
+    /// One vector store per embedding model, vectors of different models can't be compared
+    pub stores: VectorStoreRegistry,
+}
+
+/// Picks the requested embedding model, or the first active one if none was requested
+pub(crate) async fn embedding_model(db: &LocalActorRef<DatabaseActor>, id: Option<Uuid>) -> Result<Model> {
+    match id {
+        Some(id) => db
+            .ask(GetModel(id))
+            .await?
+            .ok_or_else(|| AppError::not_found("Model", id)),
+        None => db
+            .ask(ListModels(ModelFilter {
+                model_type: Some(ModelType::Embedding),
+                is_active: Some(true),
+                limit: Some(1),
+                ..Default::default()
+            }))
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| AppError::configuration("No active embedding model configured")),
+    }
+}
+
+fn entry_uuid(entry: &VectorEntry, key: &str) -> Option<Uuid> {
+    entry.metadata.get(key)?.as_str()?.parse().ok()
+}
+
+/// Opens the vector store of an embedding model.
+///
+/// The index settings can be tuned in the `vectorIndex` key of the model config,
+/// the dimension defaults to the length of the model's embeddings.
+async fn model_store(
+    stores: &VectorStoreRegistry,
+    model: &Model,
+    dimension: usize,
+) -> Result<Arc<VectorStore>> {
+    let mut config = model
+        .config
+        .as_ref()
+        .and_then(|config| config.0.get("vectorIndex"))
+        .map(|value| serde_json::from_value::<VectorStoreConfig>(value.clone()))
+        .transpose()?
+        .unwrap_or_default();
+    config.dimension = model
+        .config
+        .as_ref()
+        .and_then(|config| config.0.get("dimensions"))
+        .and_then(Value::as_u64)
+        .map_or(dimension, |dimensions| dimensions as usize);
+    Ok(stores.open(&model.id.to_string(), config).await?)
+}
+
+async fn ingest(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    stores: &VectorStoreRegistry,
+    msg: IngestDocument,
+) -> Result<IngestReport> {
+    let document_id = msg.document_id;
+    let document = db
+        .ask(GetDocument(document_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Document", document_id))?;
+    let text = match (&document.content, &document.file_path) {
+        (Some(content), _) => content.clone(),
+        (None, Some(path)) => tokio::fs::read_to_string(path).await?,
+        (None, None) => {
+            return Err(AppError::validation(format!(
+                "Document {document_id} has no content to index"
+            )));
+        }
+    };
+    let model = embedding_model(db, msg.embedding_model_id).await?;
+    let provider = providers
+        .resolve_model(&model, document.workspace_id)
+        .await?;
+
+    // Start from a clean slate, the flags are only set again once each stage has finished
+    db.ask(SetDocumentIndexed(document_id, false)).await?;
+    db.ask(SetDocumentEmbedded(document_id, false)).await?;
+    // The document may have been indexed with a different model before
+    let previous = SearchFilter::new().eq("documentId", document_id.to_string());
+    let embedding_models = db
+        .ask(ListModels(ModelFilter {
+            model_type: Some(ModelType::Embedding),
+            ..Default::default()
+        }))
+        .await?;
+    for embedding_model in embedding_models {
+        if let Some(store) = stores.get(&embedding_model.id.to_string()).await? {
+            store.delete_where(&previous).await?;
+        }
+    }
+    db.ask(DeleteDocumentChunks(document_id)).await?;
+
+    let defaults = ChunkOptions::default();
+    let pieces = chunk_text(
+        &text,
+        msg.strategy,
+        ChunkOptions {
+            max_chars: msg.max_chars.unwrap_or(defaults.max_chars),
+            overlap: msg.overlap.unwrap_or(defaults.overlap),
+        },
+    );
+    let mut chunks: Vec<DocumentChunk> = Vec::with_capacity(pieces.len());
+    for (order_index, piece) in pieces.iter().enumerate() {
+        let chunk = db
+            .ask(CreateDocumentChunk {
+                document_id,
+                // Header chunks always come before the chunks of their section
+                parent_chunk_id: piece
+                    .parent
+                    .and_then(|parent| chunks.get(parent))
+                    .map(|parent| parent.id),
+                content: piece.content.clone(),
+                content_hash: format!("{:x}", Sha256::digest(piece.content.as_bytes())),
+                chunk_type: msg.strategy,
+                order_index: order_index as i64,
+                semantic_level: Some(piece.semantic_level),
+                metadata: piece
+                    .heading
+                    .as_ref()
+                    .map(|heading| Json(json!({ "heading": heading }))),
+                embedding_id: None,
+                workspace_id: document.workspace_id,
+            })
+            .await?;
+        chunks.push(chunk);
+    }
+    debug!(%document_id, chunks = chunks.len(), "Stored document chunks");
+
+    let texts = pieces
+        .iter()
+        .map(|piece| match &piece.heading {
+            // Embedding the heading with the text helps to find sections by their topic
+            Some(heading) if heading != &piece.content => {
+                format!("{heading}\n\n{}", piece.content)
+            }
+            _ => piece.content.clone(),
+        })
+        .collect();
+    let embeddings = providers.embed(&provider, &model.name, texts).await?;
+    if embeddings.len() != chunks.len() {
+        return Err(AppError::external_service(format!(
+            "Expected {} embeddings but got {}",
+            chunks.len(),
+            embeddings.len()
+        )));
+    }
+    if let Some(first) = embeddings.first() {
+        let store = model_store(stores, &model, first.len()).await?;
+        for (chunk, embedding) in chunks.iter().zip(embeddings) {
+            let embedding_id = Uuid::new_v4();
+            store
+                .insert(VectorEntry {
+                    id: embedding_id,
+                    workspace_id: document.workspace_id,
+                    text: chunk.content.clone(),
+                    embedding,
+                    metadata: json!({
+                        "documentId": document_id,
+                        "chunkId": chunk.id,
+                    }),
+                })
+                .await?;
+            db.ask(SetDocumentChunkEmbedding(chunk.id, embedding_id))
+                .await?;
+        }
+    }
+    db.ask(SetDocumentEmbedded(document_id, true)).await?;
+    db.ask(SetDocumentIndexed(document_id, true)).await?;
+    info!(%document_id, chunks = chunks.len(), "Indexed document");
+
+    Ok(IngestReport {
+        document_id,
+        chunks: chunks.len(),
+    })
+}
+
+async fn retrieve(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    stores: &VectorStoreRegistry,
+    msg: RetrieveChunks,
+) -> Result<Vec<RetrievedChunk>> {
+    let model = embedding_model(db, msg.embedding_model_id).await?;
+    let Some(store) = stores.get(&model.id.to_string()).await? else {
+        // Nothing has been indexed with this model yet
+        return Ok(Vec::new());
+    };
+    let provider = providers.resolve_model(&model, msg.workspace_id).await?;
+    let query = providers
+        .embed(&provider, &model.name, vec![msg.query])
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::external_service("Embedding model returned no embedding"))?;
+
+    let mut filter = SearchFilter::new().workspace(msg.workspace_id);
+    if let Some(ids) = &msg.document_ids {
+        filter = filter.one_of(
+            "documentId",
+            ids.iter().map(|id| Value::from(id.to_string())).collect(),
+        );
+    }
+    let matches = store.search(&query, msg.limit, &filter).await?;
+
+    let mut document_names = HashMap::new();
+    let mut results = Vec::new();
+    for ScoredEntry { entry, score } in matches {
+        let Some(chunk_id) = entry_uuid(&entry, "chunkId") else {
+            continue;
+        };
+        let Some(chunk) = db.ask(GetDocumentChunk(chunk_id)).await? else {
+            // The chunk was removed after it was indexed
+            continue;
+        };
+        let document_name = match document_names.get(&chunk.document_id) {
+            Some(name) => name.clone(),
+            None => {
+                let name = db
+                    .ask(GetDocument(chunk.document_id))
+                    .await?
+                    .map(|document| document.name)
+                    .unwrap_or_default();
+                document_names.insert(chunk.document_id, name.clone());
+                name
+            }
+        };
+        results.push(RetrievedChunk {
+            citation: format!("[{document_name} #{}]", chunk.order_index + 1),
+            document_id: chunk.document_id,
+            document_name,
+            chunk_id: chunk.id,
+            content: chunk.content,
+            heading: chunk
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.0.get("heading"))
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            score,
+        });
+    }
+    Ok(results)
+}
+
+impl Message<IngestDocument> for DocumentIndexerActor {
+    type Reply = DelegatedReply<Result<IngestReport>>;
+
+    async fn handle(
+        &mut self,
+        msg: IngestDocument,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let db = self.db.clone();
+        let providers = self.providers.clone();
+        let stores = self.stores.clone();
+        tokio::spawn(async move {
+            let res = ingest(&db, &providers, &stores, msg).await;
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+impl Message<RetrieveChunks> for DocumentIndexerActor {
+    type Reply = DelegatedReply<Result<Vec<RetrievedChunk>>>;
+
+    async fn handle(
+        &mut self,
+        msg: RetrieveChunks,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let db = self.db.clone();
+        let providers = self.providers.clone();
+        let stores = self.stores.clone();
+        tokio::spawn(async move {
+            let res = retrieve(&db, &providers, &stores, msg).await;
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+/// Built-in tool that lets agents search the indexed documents
+pub struct SearchDocuments {
+    pub indexer: LocalActorRef<DocumentIndexerActor>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct SearchDocumentsArgs {
+    /// What to look for
+    pub query: String,
+    /// Maximum number of passages to return, defaults to 5
+    pub limit: Option<usize>,
+    /// Only search these documents
+    pub document_ids: Option<Vec<Uuid>>,
+}
+
+impl Tool for SearchDocuments {
+    type Error = AppError;
+    type Args = SearchDocumentsArgs;
+    type Output = Vec<RetrievedChunk>;
+
+    const NAME: &'static str = "search_documents";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Borrowed(
+                "Searches the workspace documents for passages relevant to a query. \
+                Cite passages using their `citation` field.",
+            ),
+            params: schema_for!(SearchDocumentsArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let indexer = self.indexer.clone();
+        SyncFuture::new(async move {
+            Ok(indexer
+                .ask(RetrieveChunks {
+                    query: args.query,
+                    workspace_id: None,
+                    document_ids: args.document_ids,
+                    limit: args.limit.unwrap_or(5),
+                    embedding_model_id: None,
+                })
+                .await?)
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestDocument {
+    pub document_id: Uuid,
+    pub strategy: DocumentChunkType,
+    pub max_chars: Option<usize>,
+    pub overlap: Option<usize>,
+    /// Defaults to the first active embedding model
+    pub embedding_model_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestReport {
+    pub document_id: Uuid,
+    pub chunks: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrieveChunks {
+    pub query: String,
+    pub workspace_id: Option<Uuid>,
+    pub document_ids: Option<Vec<Uuid>>,
+    pub limit: usize,
+    /// Defaults to the first active embedding model
+    pub embedding_model_id: Option<Uuid>,
+}
+
+/// A passage returned by a retrieval query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrievedChunk {
+    pub document_id: Uuid,
+    pub document_name: String,
+    pub chunk_id: Uuid,
+    pub content: String,
+    pub heading: Option<String>,
+    /// Similarity between the query and the chunk, higher is closer
+    pub score: f32,
+    /// Human readable reference to the passage, e.g. `[Handbook.md #3]`
+    pub citation: String,
+}
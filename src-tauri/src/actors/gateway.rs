@@ -1,51 +1,40 @@
-use std::{any::Any, collections::HashMap, sync::OnceLock};
+use std::{collections::HashMap, sync::OnceLock};
 
+use chrono::Utc;
 use color_eyre::eyre::eyre;
 use kameo::prelude::{ActorRef as LocalActorRef, *};
 use kameo_actors::message_bus::Publish;
-use rig::completion::ToolDefinition;
-use tokio::sync::oneshot;
+use libp2p::PeerId;
+use serde::Serialize;
+use tokio::{sync::mpsc, task::AbortHandle};
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::{
     actors::{
         ActorRef, AgentManagerActor, DatabaseActor, SystemEventBus,
-        agents::{AgentRequest, AgentResponseEvent},
+        agents::AgentResponseEvent,
         conversation::SendMessage,
+        database::{AuthorizePeer, GetPeerCertificate, GetPeerPermission, RecordReceivedMessage},
+        delivery::{Delivery, DeliveryAck, Outbound},
+        hosting::{
+            HostAgentRequest, HostingActor, ListPublishedAgents, RemoteAgentRequest,
+            response_stream,
+        },
+        rpc::{PendingCall, RpcRequest},
         tools::{GetTools, ToolExecutorActor, UseTool},
     },
-    entities::{Conversation, CreateConversation, Message as ChatMessage},
+    entities::{
+        Conversation, CreateConversation, Message as ChatMessage, PeerAction, PeerDecision,
+        PeerIdWrapper,
+    },
     error::{AppError, Result},
-    keys::Signed,
-    utils::SaveTask,
+    keys::{ReplayCache, Signed},
+    utils::get_gateway_id,
 };
 
 pub static GATEWAY_ACTOR: OnceLock<LocalActorRef<GatewayActor>> = OnceLock::new();
 
-macro_rules! task_impl {
-    ($name:ty, $id:literal) => {
-        #[remote_message($id)]
-        impl Message<Signed<$name>> for GatewayActor {
-            type Reply = ();
-
-            async fn handle(
-                &mut self,
-                msg: Signed<$name>,
-                _ctx: &mut Context<Self, Self::Reply>,
-            ) -> Self::Reply {
-                if !msg.verify_signature() {
-                    return;
-                }
-                if let Some(task_id) = msg.task_id()
-                    && let Some(sender) = self.active_tasks.remove(task_id)
-                {
-                    let _ = sender.send(Box::new(msg.into_inner()));
-                }
-            }
-        }
-    };
-}
-
 // State inside the client's GatewayActor
 #[derive(Actor, RemoteActor)]
 pub struct GatewayActor {
@@ -53,7 +42,60 @@ pub struct GatewayActor {
     pub bus: LocalActorRef<SystemEventBus>,
     pub agent_manager: LocalActorRef<AgentManagerActor>,
     pub tool_executor: LocalActorRef<ToolExecutorActor>,
-    pub active_tasks: HashMap<Uuid, oneshot::Sender<Box<dyn Any + Send + Sync + 'static>>>,
+    pub hosting: LocalActorRef<HostingActor>,
+    /// Our calls to other peers that are waiting for replies
+    pub pending_calls: HashMap<Uuid, PendingCall>,
+    /// Calls of other peers we are working on
+    pub serving: HashMap<(PeerId, Uuid), AbortHandle>,
+    pub replay_cache: ReplayCache,
+}
+
+impl GatewayActor {
+    /// Verifies that an incoming message was signed by the peer it claims to come from
+    /// and that it isn't stale or a replay of a message we already accepted.
+    pub(crate) fn authenticate<T: Serialize>(&mut self, msg: &Signed<T>) -> Result<()> {
+        if !msg.verify_signature() {
+            return Err(eyre!("Invalid signature").into());
+        }
+        if !self.replay_cache.check(msg, Utc::now()) {
+            return Err(AppError::authorization(format!(
+                "Rejected stale or replayed message from peer {}",
+                msg.client_peer_id()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the peer may perform the action. Peers we haven't seen before are
+    /// announced to the UI so that the user can approve them.
+    async fn authorize(&self, peer_id: &PeerId, action: PeerAction) -> Result<()> {
+        let peer_id = PeerIdWrapper(*peer_id);
+        let (decision, is_new) = self.db.ask(AuthorizePeer(peer_id.clone(), action)).await?;
+        match decision {
+            PeerDecision::Allow => Ok(()),
+            PeerDecision::Deny(reason) => Err(AppError::authorization(reason)),
+            PeerDecision::Pending => {
+                if is_new {
+                    self.bus
+                        .tell(Publish(PeerApprovalRequested {
+                            peer_id: peer_id.clone(),
+                        }))
+                        .await
+                        .ok();
+                }
+                Err(AppError::authorization(format!(
+                    "Peer {peer_id} is waiting for approval"
+                )))
+            }
+        }
+    }
+}
+
+/// Published when a peer we don't know contacts us for the first time
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerApprovalRequested {
+    pub peer_id: PeerIdWrapper,
 }
 
 // Handles incoming network messages to create a conversation on this peer.
@@ -66,9 +108,9 @@ impl Message<Signed<CreateConversation>> for GatewayActor {
         msg: Signed<CreateConversation>,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        if !msg.verify_signature() {
-            return Err(eyre!("Invalid signature").into());
-        }
+        self.authenticate(&msg)?;
+        self.authorize(msg.client_peer_id(), PeerAction::CreateConversation)
+            .await?;
         self.bus.tell(Publish(msg)).await.ok();
         Ok(())
     }
@@ -84,96 +126,279 @@ impl Message<Signed<SendMessage>> for GatewayActor {
         msg: Signed<SendMessage>,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        if !msg.verify_signature() {
-            return Err(eyre!("Invalid signature").into());
-        }
+        self.authenticate(&msg)?;
+        self.authorize(msg.client_peer_id(), PeerAction::SendMessage)
+            .await?;
         self.bus.tell(Publish(msg)).await.ok();
         Ok(())
     }
 }
 
-// Existing handlers...
-
-#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe06e")]
-impl Message<Signed<GetTools>> for GatewayActor {
-    type Reply = Result<Vec<rig::completion::ToolDefinition>>;
+// Handles chat messages sent by peers taking part in one of our conversations.
+#[remote_message("313da359-6c9a-4d16-9ee0-d567b00f67d1")]
+impl Message<Signed<ChatMessage>> for GatewayActor {
+    type Reply = Result<()>;
 
     async fn handle(
         &mut self,
-        msg: Signed<GetTools>,
+        msg: Signed<ChatMessage>,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        if !msg.verify_signature() {
-            return Err(eyre!("Invalid signature").into());
-        }
-        Ok(self.tool_executor.ask(msg.into_inner()).await?)
+        self.authenticate(&msg)?;
+        self.authorize(msg.client_peer_id(), PeerAction::ChatMessage)
+            .await?;
+        self.bus.tell(Publish(msg)).await.ok();
+        Ok(())
     }
 }
 
-#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe07e")]
-impl Message<Signed<UseTool>> for GatewayActor {
-    type Reply = Result<String>;
+// Handles messages that a peer queued for us while we were unreachable, and
+// acknowledges them so that the peer stops sending them.
+#[remote_message("313da359-6c9a-4d16-9ee0-d567b00f67d2")]
+impl Message<Signed<Delivery>> for GatewayActor {
+    type Reply = Result<()>;
 
     async fn handle(
         &mut self,
-        msg: Signed<UseTool>,
+        msg: Signed<Delivery>,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        if !msg.verify_signature() {
+        self.authenticate(&msg)?;
+        let peer_id = *msg.client_peer_id();
+        let Delivery { id, message } = msg.into_inner();
+        // Queued messages can be older than the replay window, it's the delivery
+        // envelope around them that has to be fresh
+        if !message.verify_signature() {
             return Err(eyre!("Invalid signature").into());
         }
-        Ok(self.tool_executor.ask(msg.into_inner()).await?)
+        let signer = *message.signer();
+        let action = match &message {
+            Outbound::ChatMessage(_) => PeerAction::ChatMessage,
+            Outbound::AgentResponse(_) => PeerAction::AgentResponse,
+            Outbound::KeyRotation(_) => PeerAction::KeyRotation,
+        };
+        // A device key rotation reaches us from the new peer id once the peer restarted,
+        // so it's the old peer that signed it that has to be allowed. Anything else must
+        // come from its signer, or from the peer that took over the signer's device key.
+        let sender = match &message {
+            Outbound::KeyRotation(_) => signer,
+            _ if signer == peer_id => peer_id,
+            _ => {
+                let successor = self
+                    .db
+                    .ask(GetPeerCertificate(PeerIdWrapper(signer)))
+                    .await?
+                    .and_then(|certificate| certificate.replaced_by);
+                if successor.as_deref() != Some(&peer_id) {
+                    return Err(AppError::authorization(format!(
+                        "Peer {peer_id} delivered a message signed by peer {signer}"
+                    )));
+                }
+                peer_id
+            }
+        };
+        // Not acknowledging a rejected message lets the peer retry it once we approve it
+        self.authorize(&sender, action).await?;
+        // Deliveries are sent again when an acknowledgement gets lost, and the delivery
+        // id is chosen by the sender, so it's the signed message itself that is recorded
+        let (issued_at, nonce) = message.signed_at();
+        let fresh = self
+            .db
+            .ask(RecordReceivedMessage {
+                signer: PeerIdWrapper(signer),
+                issued_at,
+                nonce,
+            })
+            .await?;
+        if fresh {
+            match message {
+                Outbound::ChatMessage(message) => self.bus.tell(Publish(message)).await.ok(),
+                Outbound::AgentResponse(event) => self.bus.tell(Publish(event)).await.ok(),
+                Outbound::KeyRotation(rotation) => self.bus.tell(Publish(rotation)).await.ok(),
+            };
+        }
+        let ack = Signed::new(DeliveryAck { id });
+        tokio::spawn(async move {
+            match RemoteActorRef::<GatewayActor>::lookup(&get_gateway_id(&peer_id)).await {
+                Ok(Some(gateway)) => {
+                    if let Err(e) = gateway.tell(&ack).await {
+                        warn!(%peer_id, "Failed to acknowledge delivery: {e}");
+                    }
+                }
+                Ok(None) => {
+                    warn!(%peer_id, "Gateway of peer is not reachable to acknowledge delivery")
+                }
+                Err(e) => warn!(%peer_id, "Failed to look up gateway of peer: {e}"),
+            }
+        });
+        Ok(())
     }
 }
 
-#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe08e")]
-impl Message<Signed<AgentResponseEvent>> for GatewayActor {
+// Handles acknowledgements of messages we delivered to a peer. These only update the
+// queue entries addressed to the acknowledging peer, so they don't need a permission.
+#[remote_message("313da359-6c9a-4d16-9ee0-d567b00f67d3")]
+impl Message<Signed<DeliveryAck>> for GatewayActor {
     type Reply = Result<()>;
 
     async fn handle(
         &mut self,
-        msg: Signed<AgentResponseEvent>,
+        msg: Signed<DeliveryAck>,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        if !msg.verify_signature() {
-            return Err(eyre!("Invalid signature").into());
-        }
+        self.authenticate(&msg)?;
         self.bus.tell(Publish(msg)).await.ok();
         Ok(())
     }
 }
 
-#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe09e")]
-impl Message<Signed<AgentRequest>> for GatewayActor {
+// Existing handlers...
+
+// Lists our tools to a peer, limited to the ones the peer may use.
+#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe06f")]
+impl Message<Signed<RpcRequest<GetTools>>> for GatewayActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Signed<RpcRequest<GetTools>>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(call) = self.accept_call(&msg) else {
+            return;
+        };
+        let authorized = self.authorize(&call.peer_id, PeerAction::GetTools).await;
+        let peer_id = PeerIdWrapper(call.peer_id);
+        let db = self.db.clone();
+        let tool_executor = self.tool_executor.clone();
+        self.serve(ctx.actor_ref(), call, async move {
+            authorized?;
+            let permission = db.ask(GetPeerPermission(peer_id)).await?;
+            let mut tools = tool_executor.ask(msg.into_inner().body).await?;
+            tools.retain(|tool| {
+                permission
+                    .as_ref()
+                    .is_some_and(|p| p.allows_tool(&tool.name))
+            });
+            Ok(tools)
+        });
+    }
+}
+
+// Runs one of our tools for a peer. Failures of the tool itself are part of the reply.
+#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe07f")]
+impl Message<Signed<RpcRequest<UseTool>>> for GatewayActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Signed<RpcRequest<UseTool>>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(call) = self.accept_call(&msg) else {
+            return;
+        };
+        let authorized = self
+            .authorize(
+                &call.peer_id,
+                PeerAction::UseTool {
+                    tool: msg.inner().body.name.to_string(),
+                },
+            )
+            .await;
+        let tool_executor = self.tool_executor.clone();
+        self.serve(ctx.actor_ref(), call, async move {
+            authorized?;
+            Ok(tool_executor
+                .ask(msg.into_inner().body)
+                .await
+                .map_err(AppError::from))
+        });
+    }
+}
+
+#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe08e")]
+impl Message<Signed<AgentResponseEvent>> for GatewayActor {
     type Reply = Result<()>;
 
     async fn handle(
         &mut self,
-        msg: Signed<AgentRequest>,
+        msg: Signed<AgentResponseEvent>,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        if !msg.verify_signature() {
-            return Err(eyre!("Invalid signature").into());
-        }
-        self.bus.tell(Publish(msg)).await;
+        self.authenticate(&msg)?;
+        self.authorize(msg.client_peer_id(), PeerAction::AgentResponse)
+            .await?;
+        self.bus.tell(Publish(msg)).await.ok();
         Ok(())
     }
 }
 
-task_impl!(Result<String>, "9593a23b-71ea-4d50-91e8-a905784628e4");
-task_impl!(
-    Result<Vec<ToolDefinition>>,
-    "9593a23b-71ea-4d50-91e8-a905784628e4"
-);
+// Runs one of our published agents for a peer and streams the response back.
+// Refused requests are answered with the reason.
+#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe09f")]
+impl Message<Signed<RpcRequest<RemoteAgentRequest>>> for GatewayActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Signed<RpcRequest<RemoteAgentRequest>>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(call) = self.accept_call(&msg) else {
+            return;
+        };
+        let request = msg.into_inner().body;
+        let action = PeerAction::AgentRequest {
+            agent_id: request.agent_id,
+        };
+        let parts = match self.authorize(&call.peer_id, action).await {
+            Ok(()) => self
+                .hosting
+                .ask(HostAgentRequest {
+                    peer_id: call.peer_id,
+                    request,
+                })
+                .await
+                .map_err(AppError::from),
+            Err(e) => Err(e),
+        };
+        let parts = parts.unwrap_or_else(|e| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            tx.send(Err(e)).ok();
+            rx
+        });
+        self.serve_stream(ctx.actor_ref(), call, response_stream(parts));
+    }
+}
 
-impl Message<SaveTask> for GatewayActor {
+// Lists our published agents to a peer, limited to the ones the peer may run.
+#[remote_message("e8f5abf6-a4af-4410-b0da-38e9c1ffe0af")]
+impl Message<Signed<RpcRequest<ListPublishedAgents>>> for GatewayActor {
     type Reply = ();
 
     async fn handle(
         &mut self,
-        msg: SaveTask,
-        _ctx: &mut Context<Self, Self::Reply>,
+        msg: Signed<RpcRequest<ListPublishedAgents>>,
+        ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        self.active_tasks.insert(msg.task_id, msg.sender);
+        let Some(call) = self.accept_call(&msg) else {
+            return;
+        };
+        let authorized = self.authorize(&call.peer_id, PeerAction::ListAgents).await;
+        let peer_id = PeerIdWrapper(call.peer_id);
+        let db = self.db.clone();
+        let hosting = self.hosting.clone();
+        self.serve(ctx.actor_ref(), call, async move {
+            authorized?;
+            let permission = db.ask(GetPeerPermission(peer_id)).await?;
+            let mut agents = hosting.ask(msg.into_inner().body).await?;
+            agents.retain(|agent| {
+                permission
+                    .as_ref()
+                    .is_some_and(|p| p.allows_agent(&agent.id))
+            });
+            Ok(Ok(agents))
+        });
     }
 }
@@ -0,0 +1,432 @@
+//! Agents we run for other peers, and the agents other peers run for us.
+//!
+//! Agents are published to peers in `published_agents`. A peer runs one with a
+//! [`RemoteAgentRequest`] through [`rpc`], and the response is streamed back as
+//! [`StreamedPart`]s. The tools the peer passes along run on the peer's machine. Each
+//! peer may only run a limited number of requests of an agent at a time and use a
+//! limited number of tokens of it per day.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use chrono::{NaiveDate, Utc};
+use futures_util::{Stream, stream};
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use kameo_actors::message_bus::Publish;
+use libp2p::PeerId;
+use macros::askable;
+use rig::completion::{Message as RigMessage, ToolDefinition};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        ActorRef, SystemEventBus,
+        agents::{AgentManagerActor, AgentRequest, ResponseSink, StreamedPart, agent_model},
+        context::{estimate_message_tokens, estimate_tokens},
+        database::{
+            DatabaseActor, GetPeerAgentTokens, GetPublishedAgent, ListPeerAgents,
+            RecordPeerAgentUsage,
+        },
+        rpc::{self, peer_gateway},
+    },
+    entities::{ModelProvider, PeerAgent, PeerIdWrapper},
+    error::{AppError, Result},
+};
+
+/// Time a peer's agent has to finish its response
+pub const AGENT_DEADLINE: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+#[derive(Actor)]
+pub struct HostingActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub agent_manager: LocalActorRef<AgentManagerActor>,
+    /// Number of requests every peer is running, per agent
+    pub running: HashMap<(PeerId, Uuid), usize>,
+    /// Token budgets shared by the running requests of every peer, per agent
+    pub budgets: HashMap<(PeerId, Uuid), Arc<TokenBudget>>,
+}
+
+/// Sent by a peer to run one of our published agents
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteAgentRequest {
+    pub agent_id: Uuid,
+    /// Conversation of the caller the turn belongs to
+    pub conversation_id: Uuid,
+    pub prompt: String,
+    pub history: Vec<RigMessage>,
+    /// Tools of the caller the agent may use. They are run on the caller's machine.
+    pub tool_definitions: Vec<ToolDefinition>,
+}
+
+/// Lists the agents we publish. The gateway leaves out the ones the calling peer may
+/// not run.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ListPublishedAgents;
+
+/// Published for every part of the response of an agent a peer runs for us
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerAgentResponseEvent {
+    pub peer_id: PeerIdWrapper,
+    pub agent_id: Uuid,
+    pub conversation_id: Uuid,
+    pub response: StreamedPart,
+}
+
+#[askable]
+impl Message<ListPublishedAgents> for HostingActor {
+    type Reply = Result<Vec<PeerAgent>>;
+
+    async fn handle(
+        &mut self,
+        _msg: ListPublishedAgents,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        Ok(self.db.ask(ListPeerAgents).await?)
+    }
+}
+
+/// Starts a request of a peer that passed the permission check. Replies with the
+/// parts of the response, which end with an error when the peer runs out of tokens.
+pub struct HostAgentRequest {
+    pub peer_id: PeerId,
+    pub request: RemoteAgentRequest,
+}
+
+impl Message<HostAgentRequest> for HostingActor {
+    type Reply = Result<mpsc::UnboundedReceiver<Result<StreamedPart>>>;
+
+    async fn handle(
+        &mut self,
+        msg: HostAgentRequest,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let HostAgentRequest { peer_id, request } = msg;
+        let agent_id = request.agent_id;
+        let published = self
+            .db
+            .ask(GetPublishedAgent(agent_id))
+            .await?
+            .ok_or_else(|| AppError::not_found("PublishedAgent", agent_id))?;
+        let running = self.running.get(&(peer_id, agent_id)).copied().unwrap_or(0);
+        if running as i64 >= published.max_concurrent_requests {
+            return Err(AppError::resource_limit_exceeded(format!(
+                "Peer {peer_id} may only run {} requests of agent {agent_id} at a time",
+                published.max_concurrent_requests
+            )));
+        }
+        let day = Utc::now().date_naive();
+        // Running requests only record their tokens once they're done, so they share
+        // a budget with the requests started after them
+        let budget = match self.budgets.get(&(peer_id, agent_id)) {
+            Some(budget) if budget.day == day => budget.clone(),
+            _ => {
+                let used = self
+                    .db
+                    .ask(GetPeerAgentTokens {
+                        peer_id: PeerIdWrapper(peer_id),
+                        agent_id,
+                        day,
+                    })
+                    .await?;
+                Arc::new(TokenBudget::new(day, published.daily_token_quota, used))
+            }
+        };
+        let (agent, model) = agent_model(&self.db, agent_id).await?;
+        let provider = model.provider;
+        let prompt_tokens = estimate_tokens(provider, &request.prompt)
+            + request
+                .history
+                .iter()
+                .map(|message| estimate_message_tokens(provider, message))
+                .sum::<usize>();
+        // The peer's tools are called back through its gateway
+        let caller = peer_gateway(&peer_id).await?;
+        if !budget.try_spend(prompt_tokens) {
+            return Err(quota_exceeded(&peer_id, agent_id));
+        }
+        let (tx, mut parts) = mpsc::unbounded_channel();
+        let res = self
+            .agent_manager
+            .ask(AgentRequest {
+                agent,
+                model,
+                prompt: request.prompt,
+                history: request.history,
+                tool_definitions: request.tool_definitions,
+                conversation_id: request.conversation_id,
+                participants: Vec::new(),
+                tool_ref: Some(ActorRef::Remote(caller)),
+                sink: Some(ResponseSink::Stream(tx)),
+            })
+            .await;
+        if let Err(e) = res {
+            budget.refund(prompt_tokens);
+            return Err(e.into());
+        }
+        *self.running.entry((peer_id, agent_id)).or_default() += 1;
+        self.budgets.insert((peer_id, agent_id), budget.clone());
+
+        let (out, rx) = mpsc::unbounded_channel();
+        let db = self.db.clone();
+        let actor_ref = ctx.actor_ref();
+        tokio::spawn(async move {
+            // The agent can't be stopped once it runs, so the parts are counted until
+            // it's done even when the peer stopped listening or ran out of tokens
+            let mut forwarding = true;
+            let mut spent = prompt_tokens;
+            while let Some(part) = parts.recv().await {
+                let tokens = part_tokens(provider, &part);
+                spent += tokens;
+                let within_quota = budget.spend(tokens);
+                if !forwarding {
+                    continue;
+                }
+                let item = if within_quota {
+                    Ok(part)
+                } else {
+                    forwarding = false;
+                    Err(quota_exceeded(&peer_id, agent_id))
+                };
+                if out.send(item).is_err() {
+                    forwarding = false;
+                }
+            }
+            let res = db
+                .ask(RecordPeerAgentUsage {
+                    peer_id: PeerIdWrapper(peer_id),
+                    agent_id,
+                    day,
+                    tokens: spent as i64,
+                })
+                .await;
+            if let Err(e) = res {
+                warn!(%peer_id, %agent_id, "Failed to record agent usage of peer: {e}");
+            }
+            actor_ref
+                .tell(HostedRequestFinished { peer_id, agent_id })
+                .await
+                .ok();
+        });
+        Ok(rx)
+    }
+}
+
+struct HostedRequestFinished {
+    peer_id: PeerId,
+    agent_id: Uuid,
+}
+
+impl Message<HostedRequestFinished> for HostingActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: HostedRequestFinished,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let key = (msg.peer_id, msg.agent_id);
+        if let Some(running) = self.running.get_mut(&key) {
+            *running = running.saturating_sub(1);
+            if *running == 0 {
+                self.running.remove(&key);
+                self.budgets.remove(&key);
+            }
+        }
+    }
+}
+
+fn quota_exceeded(peer_id: &PeerId, agent_id: Uuid) -> AppError {
+    AppError::resource_limit_exceeded(format!(
+        "Peer {peer_id} used up its daily tokens of agent {agent_id}"
+    ))
+}
+
+/// Turns the parts of a hosted response into the stream answering the peer's call
+pub(crate) fn response_stream(
+    parts: mpsc::UnboundedReceiver<Result<StreamedPart>>,
+) -> impl Stream<Item = Result<StreamedPart>> {
+    stream::unfold(parts, |mut parts| async move {
+        parts.recv().await.map(|part| (part, parts))
+    })
+}
+
+/// Tokens a peer may still use of an agent on a day
+pub struct TokenBudget {
+    day: NaiveDate,
+    /// `None` when the agent has no quota
+    remaining: Option<u64>,
+    /// Tokens of the running requests
+    spent: AtomicU64,
+}
+
+impl TokenBudget {
+    fn new(day: NaiveDate, quota: Option<i64>, used: i64) -> Self {
+        Self {
+            day,
+            remaining: quota.map(|quota| quota.saturating_sub(used).max(0) as u64),
+            spent: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts the tokens and returns whether they were within the quota
+    fn spend(&self, tokens: usize) -> bool {
+        let spent = self.spent.fetch_add(tokens as u64, Ordering::SeqCst) + tokens as u64;
+        self.remaining.is_none_or(|remaining| spent <= remaining)
+    }
+
+    /// Only counts the tokens if they're within the quota
+    fn try_spend(&self, tokens: usize) -> bool {
+        self.spent
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |spent| {
+                let spent = spent + tokens as u64;
+                self.remaining
+                    .is_none_or(|remaining| spent <= remaining)
+                    .then_some(spent)
+            })
+            .is_ok()
+    }
+
+    fn refund(&self, tokens: usize) {
+        self.spent.fetch_sub(tokens as u64, Ordering::SeqCst);
+    }
+}
+
+/// Tokens a part of a response took to generate or adds to the agent's context
+fn part_tokens(provider: ModelProvider, part: &StreamedPart) -> usize {
+    match part {
+        StreamedPart::Token(text) => estimate_tokens(provider, text),
+        StreamedPart::ToolCall(tool_call) => estimate_tokens(
+            provider,
+            &serde_json::to_string(tool_call).unwrap_or_default(),
+        ),
+        StreamedPart::ToolResult { tool_output, .. } => {
+            estimate_tokens(provider, &tool_output.to_string())
+        }
+        StreamedPart::EndOfStream { .. }
+        | StreamedPart::ContextTruncated(_)
+        | StreamedPart::Error(_) => 0,
+    }
+}
+
+/// Lists the agents a peer publishes that we may run
+pub async fn list_peer_agents(peer_id: &PeerId) -> Result<Vec<PeerAgent>> {
+    let gateway = peer_gateway(peer_id).await?;
+    rpc::call::<HostingActor, _>(&gateway, ListPublishedAgents, rpc::DEFAULT_DEADLINE).await?
+}
+
+/// Runs an agent of a peer. The response is published as [`PeerAgentResponseEvent`]s.
+pub async fn prompt_peer_agent(
+    bus: LocalActorRef<SystemEventBus>,
+    peer_id: PeerId,
+    request: RemoteAgentRequest,
+) -> Result<()> {
+    let agent_id = request.agent_id;
+    let conversation_id = request.conversation_id;
+    let gateway = peer_gateway(&peer_id).await?;
+    let mut parts = rpc::call_stream::<_, StreamedPart>(&gateway, request, AGENT_DEADLINE).await?;
+    tokio::spawn(async move {
+        while let Some(part) = parts.next().await {
+            let response = part.unwrap_or_else(|e| StreamedPart::Error(e.to_string()));
+            bus.tell(Publish(PeerAgentResponseEvent {
+                peer_id: PeerIdWrapper(peer_id),
+                agent_id,
+                conversation_id,
+                response,
+            }))
+            .await
+            .ok();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_budget() {
+        let day = Utc::now().date_naive();
+        let budget = TokenBudget::new(day, Some(100), 60);
+        assert!(budget.spend(30));
+        assert!(budget.spend(10));
+        assert!(!budget.try_spend(1));
+        assert!(!budget.spend(1));
+        assert_eq!(budget.spent.load(Ordering::SeqCst), 41);
+
+        let used_up = TokenBudget::new(day, Some(100), 150);
+        assert!(used_up.spend(0));
+        assert!(!used_up.spend(1));
+
+        let unlimited = TokenBudget::new(day, None, i64::MAX);
+        assert!(unlimited.spend(usize::MAX / 2));
+    }
+
+    #[tokio::test]
+    async fn test_token_budget_is_shared_by_concurrent_requests() {
+        let budget = Arc::new(TokenBudget::new(Utc::now().date_naive(), Some(100), 40));
+        let request = |budget: Arc<TokenBudget>| {
+            tokio::spawn(async move {
+                if !budget.try_spend(20) {
+                    return 0;
+                }
+                let mut forwarded = 20;
+                for _ in 0..10 {
+                    if !budget.spend(5) {
+                        break;
+                    }
+                    forwarded += 5;
+                    tokio::task::yield_now().await;
+                }
+                forwarded
+            })
+        };
+
+        let (first, second) = (request(budget.clone()), request(budget.clone()));
+        let forwarded = first.await.unwrap() + second.await.unwrap();
+        assert!(forwarded <= 60);
+        // A request started once the quota is used up is refused right away
+        assert!(!budget.try_spend(20));
+    }
+
+    #[test]
+    fn test_part_tokens() {
+        let provider = ModelProvider::OpenAI;
+
+        assert_eq!(
+            part_tokens(provider, &StreamedPart::Token("abcdefgh".into())),
+            2
+        );
+        assert_eq!(
+            part_tokens(
+                provider,
+                &StreamedPart::ToolResult {
+                    tool_call_id: "call-1".into(),
+                    tool_name: "search".into(),
+                    tool_output: serde_json::json!("abcdef"),
+                }
+            ),
+            2
+        );
+        assert_eq!(
+            part_tokens(
+                provider,
+                &StreamedPart::EndOfStream {
+                    full_response: "abcdefgh".into(),
+                    tool_calls: Vec::new(),
+                }
+            ),
+            0
+        );
+    }
+}
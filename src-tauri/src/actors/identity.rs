@@ -0,0 +1,82 @@
+//! Rotation of our identity keys, and of the keys of our contacts.
+//!
+//! A rotation is announced to every peer that trusts us through the delivery queue,
+//! so that contacts that are offline learn of it once they come back. Rotations of
+//! other peers arrive on the bus once the gateway checked the signature of the envelope.
+
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use tracing::{info, warn};
+
+use crate::{
+    actors::{
+        database::{ApplyKeyRotation, DatabaseActor, ListTrustingPeerIds},
+        delivery::{DeliveryActor, Enqueue},
+    },
+    entities::PeerIdWrapper,
+    error::Result,
+    keys::{KEYSTORE, KeyKind, KeyRotation, Signed},
+};
+
+#[derive(Actor)]
+pub struct IdentityActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub delivery: LocalActorRef<DeliveryActor>,
+}
+
+/// Replaces our user or device key and tells the peers that trust us
+pub struct RotateKey(pub KeyKind);
+
+impl Message<RotateKey> for IdentityActor {
+    type Reply = Result<KeyRotation>;
+
+    async fn handle(
+        &mut self,
+        msg: RotateKey,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let rotation = KEYSTORE.write().unwrap().rotate(msg.0)?;
+        info!("Rotated {:?} key", msg.0);
+        let recipients = self.db.ask(ListTrustingPeerIds).await?;
+        if !recipients.is_empty() {
+            // Signed with the device key in use, which is the one the peers know
+            self.delivery
+                .ask(Enqueue {
+                    recipients,
+                    conversation_id: None,
+                    message: Signed::new(rotation.clone()).into(),
+                })
+                .await?;
+        }
+        Ok(rotation)
+    }
+}
+
+impl Message<Signed<KeyRotation>> for IdentityActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: Signed<KeyRotation>,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let peer_id = *msg.client_peer_id();
+        let rotation = msg.into_inner();
+        if let Err(e) = rotation.verify(&peer_id) {
+            warn!(%peer_id, "Rejected key rotation: {e}");
+            return Err(e);
+        }
+        self.db
+            .ask(ApplyKeyRotation {
+                peer_id: PeerIdWrapper(peer_id),
+                rotation: rotation.clone(),
+            })
+            .await?;
+        info!(
+            %peer_id,
+            new_peer_id = %rotation.certificate.peer_id(),
+            "Applied {:?} key rotation of peer",
+            rotation.kind
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,507 @@
+//! Connections to the MCP servers the user added.
+//!
+//! Every active server is connected to at startup. Its tools are synced into
+//! `mcp_tools` and the enabled ones are registered on the [`ToolExecutorActor`], so
+//! agents can call them. Connections are pinged periodically, and servers that went
+//! away are reconnected to with an increasing delay.
+
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
+
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        database::{
+            DatabaseActor, GetMcpServer, ListMcpServers, ListMcpTools, SetMcpToolEnabled,
+            SyncMcpTools, UpdateMcpServerConnection,
+        },
+        tools::{RegisterTools, ToolDyn, ToolExecutorActor, UnregisterTools},
+    },
+    entities::{
+        McpConnectionStatus, McpServer, McpServerFilter, McpTool, McpToolFilter, McpToolStatus,
+        McpToolType, McpTransport,
+    },
+    error::{AppError, Result},
+    mcp::{ClientEvent, McpClient, McpRemoteTool},
+};
+
+/// How often connected servers are pinged
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Actor)]
+pub struct McpManagerActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub tool_executor: LocalActorRef<ToolExecutorActor>,
+    pub servers: HashMap<Uuid, McpConnection>,
+}
+
+#[derive(Default)]
+pub struct McpConnection {
+    client: Option<Arc<McpClient>>,
+    /// Names of the tools registered on the executor
+    tools: Vec<Cow<'static, str>>,
+    /// Tells apart the attempts to connect, so the results and events of a connection
+    /// that was replaced are ignored
+    generation: u64,
+    /// Failed attempts since the last connection
+    failures: u32,
+}
+
+impl McpManagerActor {
+    async fn unregister_tools(&mut self, server_id: Uuid) {
+        let Some(connection) = self.servers.get_mut(&server_id) else {
+            return;
+        };
+        let names = std::mem::take(&mut connection.tools);
+        if !names.is_empty() {
+            self.tool_executor.tell(UnregisterTools(names)).await.ok();
+        }
+    }
+
+    /// Registers the enabled tools of a connected server in place of the ones before
+    async fn register_tools(&mut self, server: &McpServer, tools: &[McpTool]) {
+        self.unregister_tools(server.id).await;
+        let Some(connection) = self.servers.get_mut(&server.id) else {
+            return;
+        };
+        let Some(client) = connection.client.clone() else {
+            return;
+        };
+        let tools: Vec<Arc<dyn ToolDyn>> = tools
+            .iter()
+            .filter(|tool| tool.is_enabled && matches!(tool.status, McpToolStatus::Active))
+            .map(|tool| {
+                Arc::new(McpRemoteTool::new(&server.name, tool, client.clone())) as Arc<dyn ToolDyn>
+            })
+            .collect();
+        connection.tools = tools.iter().map(|tool| tool.name()).collect();
+        info!(server = %server.name, "Offering {} tools of MCP server", tools.len());
+        self.tool_executor.tell(RegisterTools(tools)).await.ok();
+    }
+
+    /// Lists the tools of a connected server and stores them
+    async fn sync_tools(&mut self, server: &McpServer) -> Result<Vec<McpTool>> {
+        let client = self
+            .servers
+            .get(&server.id)
+            .and_then(|connection| connection.client.clone())
+            .ok_or_else(|| {
+                AppError::external_service(format!("MCP server {} isn't connected", server.name))
+            })?;
+        let tools = client.list_tools().await?;
+        let tool_type = match server.transport {
+            McpTransport::Stdio => McpToolType::Local,
+            McpTransport::Http | McpTransport::Sse => McpToolType::Rest,
+        };
+        let tools = self
+            .db
+            .ask(SyncMcpTools {
+                server_id: server.id,
+                tool_type,
+                tools,
+            })
+            .await?;
+        self.register_tools(server, &tools).await;
+        Ok(tools)
+    }
+
+    async fn set_status(
+        &self,
+        id: Uuid,
+        status: McpConnectionStatus,
+        error: Option<String>,
+        client: Option<&McpClient>,
+    ) {
+        let res = self
+            .db
+            .ask(UpdateMcpServerConnection {
+                id,
+                status,
+                error,
+                capabilities: client.map(|client| client.capabilities.clone()),
+                server_info: client
+                    .and_then(|client| serde_json::to_value(&client.server_info).ok()),
+            })
+            .await;
+        if let Err(e) = res {
+            warn!(%id, "Failed to store connection status of MCP server: {e}");
+        }
+    }
+
+    /// Drops the connection after a failure, and tries again later
+    async fn connection_lost(&mut self, id: Uuid, reason: String, actor_ref: LocalActorRef<Self>) {
+        self.unregister_tools(id).await;
+        let Some(connection) = self.servers.get_mut(&id) else {
+            return;
+        };
+        connection.client = None;
+        connection.failures += 1;
+        let delay = reconnect_delay(connection.failures);
+        let generation = connection.generation;
+        warn!(%id, "MCP server failed, reconnecting in {delay:?}: {reason}");
+        self.set_status(id, McpConnectionStatus::Failed, Some(reason), None)
+            .await;
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            actor_ref.tell(Reconnect { id, generation }).await.ok();
+        });
+    }
+}
+
+/// Waits twice as long after every failed attempt
+fn reconnect_delay(failures: u32) -> Duration {
+    MIN_RECONNECT_DELAY
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_RECONNECT_DELAY)
+}
+
+/// Connects to every active server, sent at startup
+pub struct ConnectMcpServers;
+
+impl Message<ConnectMcpServers> for McpManagerActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        _msg: ConnectMcpServers,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let servers = self
+            .db
+            .ask(ListMcpServers(McpServerFilter {
+                is_active: Some(true),
+                ..Default::default()
+            }))
+            .await?;
+        for server in servers {
+            ctx.actor_ref().tell(ConnectMcpServer(server.id)).await.ok();
+        }
+        Ok(())
+    }
+}
+
+/// Connects to a server, replacing the connection it has
+pub struct ConnectMcpServer(pub Uuid);
+
+impl Message<ConnectMcpServer> for McpManagerActor {
+    type Reply = DelegatedReply<Result<Vec<McpTool>>>;
+
+    async fn handle(
+        &mut self,
+        msg: ConnectMcpServer,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let server = match self.db.ask(GetMcpServer(msg.0)).await {
+            Ok(Some(server)) => server,
+            Ok(None) => {
+                if let Some(tx) = sender {
+                    tx.send(Err(AppError::not_found("MCP server", msg.0)));
+                }
+                return delegated;
+            }
+            Err(e) => {
+                if let Some(tx) = sender {
+                    tx.send(Err(e.into()));
+                }
+                return delegated;
+            }
+        };
+        self.unregister_tools(server.id).await;
+        let connection = self.servers.entry(server.id).or_default();
+        connection.client = None;
+        connection.generation += 1;
+        let generation = connection.generation;
+        self.set_status(server.id, McpConnectionStatus::Connecting, None, None)
+            .await;
+
+        let actor_ref = ctx.actor_ref();
+        tokio::spawn(async move {
+            let id = server.id;
+            let connected = McpClient::connect(&server).await;
+            let res = actor_ref
+                .ask(Connected {
+                    server,
+                    generation,
+                    connected,
+                })
+                .await
+                .map_err(AppError::from);
+            if let Err(e) = &res {
+                error!(%id, "Failed to connect to MCP server: {e}");
+            }
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+/// Stops using a server, until it is connected to again
+pub struct DisconnectMcpServer(pub Uuid);
+
+impl Message<DisconnectMcpServer> for McpManagerActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: DisconnectMcpServer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.unregister_tools(msg.0).await;
+        if self.servers.remove(&msg.0).is_some() {
+            info!(id = %msg.0, "Disconnected from MCP server");
+        }
+        self.set_status(msg.0, McpConnectionStatus::Disconnected, None, None)
+            .await;
+    }
+}
+
+/// Lists the tools of a connected server again
+pub struct RefreshMcpTools(pub Uuid);
+
+impl Message<RefreshMcpTools> for McpManagerActor {
+    type Reply = Result<Vec<McpTool>>;
+
+    async fn handle(
+        &mut self,
+        msg: RefreshMcpTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let server = self
+            .db
+            .ask(GetMcpServer(msg.0))
+            .await?
+            .ok_or_else(|| AppError::not_found("MCP server", msg.0))?;
+        self.sync_tools(&server).await
+    }
+}
+
+/// Enables or disables a tool of a server, for agents to use
+pub struct SetMcpToolAvailable {
+    pub id: Uuid,
+    pub is_enabled: bool,
+}
+
+impl Message<SetMcpToolAvailable> for McpManagerActor {
+    type Reply = Result<McpTool>;
+
+    async fn handle(
+        &mut self,
+        msg: SetMcpToolAvailable,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let tool = self
+            .db
+            .ask(SetMcpToolEnabled {
+                id: msg.id,
+                is_enabled: msg.is_enabled,
+            })
+            .await?;
+        let server = self.db.ask(GetMcpServer(tool.mcp_server_id)).await?;
+        if let Some(server) = server {
+            let tools = self
+                .db
+                .ask(ListMcpTools(McpToolFilter {
+                    mcp_server_id: Some(server.id),
+                    ..Default::default()
+                }))
+                .await?;
+            self.register_tools(&server, &tools).await;
+        }
+        Ok(tool)
+    }
+}
+
+/// Pings the connected servers, sent periodically
+pub struct CheckMcpServers;
+
+impl Message<CheckMcpServers> for McpManagerActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _msg: CheckMcpServers,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        for (&id, connection) in &self.servers {
+            let Some(client) = connection.client.clone() else {
+                continue;
+            };
+            let generation = connection.generation;
+            let actor_ref = ctx.actor_ref();
+            tokio::spawn(async move {
+                if let Err(e) = client.ping().await {
+                    actor_ref
+                        .tell(McpServerEvent {
+                            id,
+                            generation,
+                            event: ClientEvent::Closed(Some(format!("Ping failed: {e}"))),
+                        })
+                        .await
+                        .ok();
+                }
+            });
+        }
+    }
+}
+
+/// The outcome of connecting to a server
+struct Connected {
+    server: McpServer,
+    generation: u64,
+    connected: Result<(
+        Arc<McpClient>,
+        tokio::sync::mpsc::UnboundedReceiver<ClientEvent>,
+    )>,
+}
+
+impl Message<Connected> for McpManagerActor {
+    type Reply = Result<Vec<McpTool>>;
+
+    async fn handle(
+        &mut self,
+        msg: Connected,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let id = msg.server.id;
+        let Some(connection) = self.servers.get_mut(&id) else {
+            return Err(AppError::external_service(format!(
+                "MCP server {} was disconnected",
+                msg.server.name
+            )));
+        };
+        if connection.generation != msg.generation {
+            return Err(AppError::external_service(format!(
+                "Connection to MCP server {} was replaced",
+                msg.server.name
+            )));
+        }
+        let (client, mut events) = match msg.connected {
+            Ok(connected) => connected,
+            Err(e) => {
+                self.connection_lost(id, e.to_string(), ctx.actor_ref())
+                    .await;
+                return Err(e);
+            }
+        };
+        connection.client = Some(client.clone());
+        connection.failures = 0;
+
+        let tools = match self.sync_tools(&msg.server).await {
+            Ok(tools) => tools,
+            Err(e) => {
+                self.connection_lost(id, e.to_string(), ctx.actor_ref())
+                    .await;
+                return Err(e);
+            }
+        };
+        self.set_status(
+            id,
+            McpConnectionStatus::Connected,
+            None,
+            Some(client.as_ref()),
+        )
+        .await;
+
+        let generation = msg.generation;
+        let actor_ref = ctx.actor_ref();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let closed = matches!(event, ClientEvent::Closed(_));
+                let event = McpServerEvent {
+                    id,
+                    generation,
+                    event,
+                };
+                if actor_ref.tell(event).await.is_err() || closed {
+                    break;
+                }
+            }
+        });
+        Ok(tools)
+    }
+}
+
+struct McpServerEvent {
+    id: Uuid,
+    generation: u64,
+    event: ClientEvent,
+}
+
+impl Message<McpServerEvent> for McpManagerActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: McpServerEvent,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(connection) = self.servers.get(&msg.id) else {
+            return;
+        };
+        if connection.generation != msg.generation || connection.client.is_none() {
+            return;
+        }
+        match msg.event {
+            ClientEvent::ToolsChanged => {
+                let server = match self.db.ask(GetMcpServer(msg.id)).await {
+                    Ok(Some(server)) => server,
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!(id = %msg.id, "Failed to get MCP server: {e}");
+                        return;
+                    }
+                };
+                if let Err(e) = self.sync_tools(&server).await {
+                    warn!(server = %server.name, "Failed to sync tools of MCP server: {e}");
+                }
+            }
+            ClientEvent::Closed(reason) => {
+                let reason = reason.unwrap_or_else(|| "The connection closed".to_string());
+                self.connection_lost(msg.id, reason, ctx.actor_ref()).await;
+            }
+        }
+    }
+}
+
+/// Tries to connect again after a failure, unless the server was connected to or
+/// removed since
+struct Reconnect {
+    id: Uuid,
+    generation: u64,
+}
+
+impl Message<Reconnect> for McpManagerActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Reconnect,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let current = self.servers.get(&msg.id).is_some_and(|connection| {
+            connection.generation == msg.generation && connection.client.is_none()
+        });
+        if current {
+            ctx.actor_ref().tell(ConnectMcpServer(msg.id)).await.ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(1), MIN_RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(3), MIN_RECONNECT_DELAY * 4);
+        assert_eq!(reconnect_delay(40), MAX_RECONNECT_DELAY);
+    }
+}
@@ -0,0 +1,410 @@
+//! Serves the tools of the [`ToolExecutorActor`] and the selected agents to MCP
+//! clients, see [`crate::mcp::endpoint`].
+//!
+//! An agent is offered as a tool taking a prompt. Calling it runs a full
+//! [`AgentRequest`], with the tools of the executor, and returns the agent's answer.
+//! Arguments are checked against the tool's JSON schema before anything runs, and
+//! every request counts towards the rate limit of the API key that made it.
+
+use std::{collections::HashMap, time::Instant};
+
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use serde_json::{Value, json};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        ActorRef,
+        agents::{AgentManagerActor, AgentRequest, ResponseSink, StreamedPart, agent_model},
+        database::{
+            DatabaseActor, GetAgent, GetApiKeyByHash, GetMcpEndpointSettings,
+            SaveMcpEndpointSettings, TouchApiKey,
+        },
+        hosting::AGENT_DEADLINE,
+        tools::{GetTools, ToolExecutorActor, UseTool},
+        workflows::schema,
+    },
+    entities::ApiKey,
+    error::{AppError, Result},
+    mcp::{
+        endpoint::{self, McpEndpointSettings, RequestWindow, hash_api_key, scope_allows},
+        protocol::{
+            CallToolParams, CallToolResult, INTERNAL_ERROR, INVALID_PARAMS, Implementation,
+            InitializeResult, JsonRpcMessage, JsonRpcResponse, ListToolsResult, METHOD_NOT_FOUND,
+            SUPPORTED_PROTOCOL_VERSIONS, Tool,
+        },
+        tool_name,
+    },
+};
+
+/// Answered to requests of a key that used up its rate limit
+pub const RATE_LIMIT_EXCEEDED: i64 = -32000;
+
+#[derive(Actor)]
+pub struct McpEndpointActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub tool_executor: LocalActorRef<ToolExecutorActor>,
+    pub agent_manager: LocalActorRef<AgentManagerActor>,
+    pub settings: McpEndpointSettings,
+    /// Requests of every API key in the current window
+    pub windows: HashMap<Uuid, RequestWindow>,
+    /// Task serving the endpoint, while it is enabled
+    pub server: Option<JoinHandle<()>>,
+}
+
+/// What a tool offered over MCP runs
+enum Target {
+    Tool,
+    Agent(Uuid),
+}
+
+struct OfferedTool {
+    tool: Tool,
+    target: Target,
+}
+
+type RequestError = (i64, String);
+
+enum Handled {
+    Done(Value),
+    /// A tool call, run outside the actor
+    Call(BoxFuture<'static, CallToolResult>),
+}
+
+impl McpEndpointActor {
+    /// Serves the endpoint as the settings say, replacing the server that ran before
+    fn restart(&mut self, actor_ref: LocalActorRef<Self>) {
+        if let Some(server) = self.server.take() {
+            server.abort();
+        }
+        if !self.settings.enabled {
+            return;
+        }
+        let addr = self.settings.listen_addr;
+        self.server = Some(tokio::spawn(async move {
+            if let Err(e) = endpoint::serve(addr, actor_ref).await {
+                error!("Failed to serve MCP on {addr}: {e}");
+            }
+        }));
+    }
+
+    /// The tools and agents the key may use
+    async fn offered_tools(&self, key: &ApiKey) -> Result<Vec<OfferedTool>> {
+        let mut offered: Vec<OfferedTool> = self
+            .tool_executor
+            .ask(GetTools)
+            .await?
+            .into_iter()
+            .filter(|tool| scope_allows(&key.scopes.0, "tools", &tool.name))
+            .map(|tool| OfferedTool {
+                tool: Tool {
+                    name: tool.name,
+                    description: Some(tool.description),
+                    input_schema: tool.parameters,
+                },
+                target: Target::Tool,
+            })
+            .collect();
+        for &agent_id in &self.settings.agent_ids {
+            if !scope_allows(&key.scopes.0, "agents", &agent_id.to_string()) {
+                continue;
+            }
+            let Some(agent) = self.db.ask(GetAgent(agent_id)).await? else {
+                warn!(%agent_id, "Agent offered over MCP doesn't exist");
+                continue;
+            };
+            offered.push(OfferedTool {
+                tool: Tool {
+                    name: tool_name("agent", &agent.name),
+                    description: Some(
+                        agent
+                            .description
+                            .unwrap_or_else(|| format!("Asks the agent {}", agent.name)),
+                    ),
+                    input_schema: json!({
+                        "type": "object",
+                        "properties": {
+                            "prompt": {
+                                "type": "string",
+                                "description": "What to ask the agent"
+                            }
+                        },
+                        "required": ["prompt"],
+                        "additionalProperties": false
+                    }),
+                },
+                target: Target::Agent(agent_id),
+            });
+        }
+        Ok(offered)
+    }
+
+    async fn handle_request(
+        &self,
+        key: &ApiKey,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<Handled, RequestError> {
+        let internal = |e: AppError| (INTERNAL_ERROR, e.to_string());
+        match method {
+            "initialize" => {
+                let requested = params
+                    .as_ref()
+                    .and_then(|params| params["protocolVersion"].as_str());
+                let protocol_version = requested
+                    .filter(|version| SUPPORTED_PROTOCOL_VERSIONS.contains(version))
+                    .unwrap_or(SUPPORTED_PROTOCOL_VERSIONS[0]);
+                let result = InitializeResult {
+                    protocol_version: protocol_version.to_string(),
+                    capabilities: json!({ "tools": { "listChanged": false } }),
+                    server_info: Implementation::evo_pro(),
+                    instructions: Some(
+                        "Tools named agent__<name> ask an evo-pro agent and return its answer"
+                            .to_string(),
+                    ),
+                };
+                Ok(Handled::Done(
+                    serde_json::to_value(result).map_err(|e| internal(e.into()))?,
+                ))
+            }
+            "ping" => Ok(Handled::Done(json!({}))),
+            "tools/list" => {
+                let tools = self
+                    .offered_tools(key)
+                    .await
+                    .map_err(internal)?
+                    .into_iter()
+                    .map(|offered| offered.tool)
+                    .collect();
+                let result = ListToolsResult {
+                    tools,
+                    next_cursor: None,
+                };
+                Ok(Handled::Done(
+                    serde_json::to_value(result).map_err(|e| internal(e.into()))?,
+                ))
+            }
+            "tools/call" => {
+                let params: CallToolParams = params
+                    .ok_or_else(|| "Missing params".to_string())
+                    .and_then(|params| serde_json::from_value(params).map_err(|e| e.to_string()))
+                    .map_err(|e| (INVALID_PARAMS, e))?;
+                let offered = self
+                    .offered_tools(key)
+                    .await
+                    .map_err(internal)?
+                    .into_iter()
+                    .find(|offered| offered.tool.name == params.name)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("Unknown tool: {}", params.name)))?;
+                let arguments = match params.arguments {
+                    Value::Null => json!({}),
+                    arguments => arguments,
+                };
+                if let Err(errors) = schema::validate(&offered.tool.input_schema, &arguments) {
+                    return Err((
+                        INVALID_PARAMS,
+                        format!("Invalid arguments: {}", errors.join("; ")),
+                    ));
+                }
+                Ok(Handled::Call(self.call(offered, arguments)))
+            }
+            method => Err((METHOD_NOT_FOUND, format!("Method not found: {method}"))),
+        }
+    }
+
+    fn call(&self, offered: OfferedTool, arguments: Value) -> BoxFuture<'static, CallToolResult> {
+        let tool_executor = self.tool_executor.clone();
+        match offered.target {
+            Target::Tool => Box::pin(async move {
+                let res = tool_executor
+                    .ask(UseTool {
+                        name: offered.tool.name.into(),
+                        args: arguments.to_string(),
+                    })
+                    .await;
+                match res {
+                    Ok(output) => CallToolResult::text(output),
+                    Err(e) => CallToolResult::error(e.to_string()),
+                }
+            }),
+            Target::Agent(agent_id) => {
+                let db = self.db.clone();
+                let agent_manager = self.agent_manager.clone();
+                let prompt = arguments["prompt"].as_str().unwrap_or_default().to_string();
+                Box::pin(async move {
+                    let run = run_agent(db, agent_manager, tool_executor, agent_id, prompt);
+                    match tokio::time::timeout(AGENT_DEADLINE, run).await {
+                        Ok(Ok(answer)) => CallToolResult::text(answer),
+                        Ok(Err(e)) => CallToolResult::error(e.to_string()),
+                        Err(_) => CallToolResult::error("The agent didn't answer in time"),
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// Runs a turn of an agent in a conversation of its own, and returns its answer
+async fn run_agent(
+    db: LocalActorRef<DatabaseActor>,
+    agent_manager: LocalActorRef<AgentManagerActor>,
+    tool_executor: LocalActorRef<ToolExecutorActor>,
+    agent_id: Uuid,
+    prompt: String,
+) -> Result<String> {
+    let (agent, model) = agent_model(&db, agent_id).await?;
+    let tool_definitions = tool_executor.ask(GetTools).await?;
+    let (tx, mut parts) = mpsc::unbounded_channel();
+    agent_manager
+        .ask(AgentRequest {
+            agent,
+            model,
+            prompt,
+            history: Vec::new(),
+            tool_definitions,
+            conversation_id: Uuid::new_v4(),
+            participants: Vec::new(),
+            tool_ref: Some(ActorRef::Local(tool_executor)),
+            sink: Some(ResponseSink::Stream(tx)),
+        })
+        .await?;
+    let mut answer = String::new();
+    while let Some(part) = parts.recv().await {
+        match part {
+            StreamedPart::Token(text) => answer.push_str(&text),
+            StreamedPart::EndOfStream { full_response, .. } => return Ok(full_response),
+            StreamedPart::Error(e) => return Err(AppError::external_service(e)),
+            _ => {}
+        }
+    }
+    Ok(answer)
+}
+
+/// Loads the settings and serves the endpoint if it is enabled, sent at startup
+pub struct StartMcpEndpoint;
+
+impl Message<StartMcpEndpoint> for McpEndpointActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        _msg: StartMcpEndpoint,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.settings = self.db.ask(GetMcpEndpointSettings).await?;
+        self.restart(ctx.actor_ref());
+        Ok(())
+    }
+}
+
+/// Stores new settings and applies them
+pub struct UpdateMcpEndpointSettings(pub McpEndpointSettings);
+
+impl Message<UpdateMcpEndpointSettings> for McpEndpointActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateMcpEndpointSettings,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.db.ask(SaveMcpEndpointSettings(msg.0.clone())).await?;
+        let restart = msg.0.enabled != self.settings.enabled
+            || msg.0.listen_addr != self.settings.listen_addr;
+        self.settings = msg.0;
+        if restart {
+            self.restart(ctx.actor_ref());
+        }
+        Ok(())
+    }
+}
+
+/// Finds the key a client sent, `None` when it isn't a usable key
+pub struct AuthenticateMcpKey(pub String);
+
+impl Message<AuthenticateMcpKey> for McpEndpointActor {
+    type Reply = Result<Option<ApiKey>>;
+
+    async fn handle(
+        &mut self,
+        msg: AuthenticateMcpKey,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(key) = self.db.ask(GetApiKeyByHash(hash_api_key(&msg.0))).await? else {
+            return Ok(None);
+        };
+        if !key.is_active
+            || key
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            return Ok(None);
+        }
+        if let Err(e) = self.db.ask(TouchApiKey(key.id)).await {
+            warn!(id = %key.id, "Failed to record use of API key: {e}");
+        }
+        Ok(Some(key))
+    }
+}
+
+/// A message of an authenticated client. Requests are answered, everything else
+/// is acknowledged with `None`.
+pub struct HandleMcpMessage {
+    pub key: ApiKey,
+    pub message: JsonRpcMessage,
+}
+
+impl Message<HandleMcpMessage> for McpEndpointActor {
+    type Reply = DelegatedReply<Option<JsonRpcResponse>>;
+
+    async fn handle(
+        &mut self,
+        msg: HandleMcpMessage,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let Some(tx) = sender else {
+            return delegated;
+        };
+        let JsonRpcMessage::Request(request) = msg.message else {
+            tx.send(None);
+            return delegated;
+        };
+        let id = request.id;
+        let admitted = self
+            .windows
+            .entry(msg.key.id)
+            .or_insert_with(|| RequestWindow::new(Instant::now()))
+            .admit(msg.key.rate_limit, Instant::now());
+        if !admitted {
+            tx.send(Some(JsonRpcResponse::error(
+                Some(id),
+                RATE_LIMIT_EXCEEDED,
+                format!(
+                    "API key {} may make {} requests per minute",
+                    msg.key.name,
+                    msg.key.rate_limit.unwrap_or_default()
+                ),
+            )));
+            return delegated;
+        }
+        match self
+            .handle_request(&msg.key, &request.method, request.params)
+            .await
+        {
+            Ok(Handled::Done(result)) => tx.send(Some(JsonRpcResponse::success(id, result))),
+            Ok(Handled::Call(call)) => {
+                tokio::spawn(async move {
+                    let result = serde_json::to_value(call.await).unwrap_or_default();
+                    tx.send(Some(JsonRpcResponse::success(id, result)));
+                });
+            }
+            Err((code, message)) => tx.send(Some(JsonRpcResponse::error(Some(id), code, message))),
+        }
+        delegated
+    }
+}
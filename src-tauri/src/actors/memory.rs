@@ -0,0 +1,764 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration as StdDuration,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use rig::{agent::AgentBuilder, completion::Prompt};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        agents::{AgentResponseEvent, StreamedPart, agent_model},
+        context::{SUMMARY_KIND, render_transcript},
+        database::{
+            CreateMemory, DatabaseActor, DecayMemories, ListMemories, ListMessages, TouchMemories,
+            UpdateMemory,
+        },
+        documents::embedding_model,
+        providers::ProviderRegistry,
+        transcript::{agent_participant_id, messages_to_history},
+    },
+    entities::{Memory, MemoryFilter, MemoryType, MessageFilter},
+    error::{AppError, Result},
+};
+
+/// How often idle conversations are turned into memories and importance is decayed
+pub const MAINTENANCE_INTERVAL: StdDuration = StdDuration::from_secs(5 * 60);
+
+/// How long a conversation has to be quiet before memories are extracted from it
+const CONVERSATION_IDLE_MINUTES: i64 = 15;
+
+/// Number of memories injected into an agent turn
+pub const RECALL_LIMIT: usize = 5;
+
+/// Number of stored memories considered for a recall
+const RECALL_CANDIDATES: u32 = 200;
+
+const SIMILARITY_WEIGHT: f64 = 0.5;
+const IMPORTANCE_WEIGHT: f64 = 0.3;
+const RECENCY_WEIGHT: f64 = 0.2;
+
+/// Hours after which the recency of a memory has halved
+const RECENCY_HALF_LIFE_HOURS: f64 = 72.0;
+
+/// Memories less similar to the query than this are never recalled
+const MIN_RECALL_SIMILARITY: f64 = 0.15;
+const MIN_RECALL_SCORE: f64 = 0.3;
+
+/// Days after which the importance of an unused memory has halved
+const EPISODIC_HALF_LIFE_DAYS: f64 = 30.0;
+const SEMANTIC_HALF_LIFE_DAYS: f64 = 180.0;
+const IMPORTANCE_FLOOR: f64 = 0.05;
+
+/// Episodic memories older than this are consolidated into semantic ones
+const CONSOLIDATE_AFTER_DAYS: i64 = 7;
+const CONSOLIDATION_SIMILARITY: f64 = 0.8;
+const MIN_CLUSTER_SIZE: usize = 3;
+
+/// Upper bound for the transcript handed to the extraction prompt
+const MAX_TRANSCRIPT_CHARS: usize = 24_000;
+
+const EXTRACTED_KIND: &str = "extracted";
+const CONSOLIDATED_KIND: &str = "consolidated";
+
+const EXTRACTION_PREAMBLE: &str = "You extract long-term memories from a conversation between a \
+    user and an AI assistant. Only keep what will still be useful in future conversations: facts \
+    about the user and their work (semantic), notable events and decisions (episodic) and how the \
+    user wants things done (procedural). Reply with a JSON array of objects with the fields \
+    `content` (one self-contained sentence), `type` (\"episodic\", \"semantic\" or \
+    \"procedural\") and `importance` (0.0 to 1.0). Reply with `[]` if there is nothing worth \
+    remembering.";
+
+const CONSOLIDATION_PREAMBLE: &str = "You are given related memories of an AI assistant. Merge \
+    them into a single general statement that stays true independent of the individual \
+    occasions. Reply with the statement only.";
+
+/// Extracts memories from finished conversations, recalls them for new agent turns and
+/// lets unused memories fade over time.
+#[derive(Actor)]
+pub struct MemoryManagerActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub providers: ProviderRegistry,
+    /// Last agent activity of conversations that haven't been turned into memories yet
+    pub pending: HashMap<(Uuid, Uuid), DateTime<Utc>>,
+    /// When importance was last decayed
+    pub last_decay: DateTime<Utc>,
+}
+
+/// Metadata of memories created by the memory manager
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MemoryMetadata {
+    kind: String,
+    agent_id: Uuid,
+    /// Creation time of the last message the memory was extracted from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extracted_through: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    embedding_model_id: Option<Uuid>,
+    /// Memories a consolidated memory was created from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<Uuid>,
+    /// The semantic memory an episodic memory was consolidated into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    consolidated_into: Option<Uuid>,
+}
+
+fn metadata(memory: &Memory) -> Option<MemoryMetadata> {
+    serde_json::from_str(memory.metadata.as_deref()?).ok()
+}
+
+/// The embedding of a memory, if it was embedded with the given model
+fn memory_embedding(memory: &Memory, model_id: Uuid) -> Option<Vec<f32>> {
+    metadata(memory).filter(|metadata| metadata.embedding_model_id == Some(model_id))?;
+    serde_json::from_str(memory.embedding.as_deref()?).ok()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    (dot / (norm_a * norm_b)) as f64
+}
+
+fn terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() > 2)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Share of the query's terms that occur in the text, used when there are no embeddings
+fn lexical_similarity(query: &str, text: &str) -> f64 {
+    let query = terms(query);
+    if query.is_empty() {
+        return 0.0;
+    }
+    query.intersection(&terms(text)).count() as f64 / query.len() as f64
+}
+
+/// Combines how similar, important and recently used a memory is into a recall score
+pub fn recall_score(
+    similarity: f64,
+    importance: f64,
+    last_accessed_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> f64 {
+    let hours = (now - last_accessed_at).num_seconds().max(0) as f64 / 3600.0;
+    let recency = 0.5f64.powf(hours / RECENCY_HALF_LIFE_HOURS);
+    SIMILARITY_WEIGHT * similarity.clamp(0.0, 1.0)
+        + IMPORTANCE_WEIGHT * importance.clamp(0.0, 1.0)
+        + RECENCY_WEIGHT * recency
+}
+
+/// Importance multiplier for memories that weren't used for `elapsed`
+pub fn decay_factor(elapsed: Duration, half_life_days: f64) -> f64 {
+    0.5f64.powf(elapsed.num_seconds().max(0) as f64 / 86_400.0 / half_life_days)
+}
+
+/// Embeds texts with the default embedding model.
+/// Returns `None` if no embedding model is configured or embedding failed.
+async fn embed(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    workspace_id: Option<Uuid>,
+    texts: Vec<String>,
+) -> Option<(Uuid, Vec<Vec<f32>>)> {
+    let model = embedding_model(db, None).await.ok()?;
+    let embeddings = async {
+        let provider = providers.resolve_model(&model, workspace_id).await?;
+        providers.embed(&provider, &model.name, texts).await
+    }
+    .await;
+    match embeddings {
+        Ok(embeddings) => Some((model.id, embeddings)),
+        Err(e) => {
+            warn!(model_id = %model.id, "Failed to embed memories: {e}");
+            None
+        }
+    }
+}
+
+/// Recalls the memories of an agent that are most relevant to a query and records
+/// that they were accessed.
+pub async fn recall(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    msg: RecallMemories,
+) -> Result<Vec<RecalledMemory>> {
+    let participant_id = agent_participant_id(db, msg.agent_id).await?;
+    let candidates: Vec<Memory> = db
+        .ask(ListMemories(MemoryFilter {
+            participant_id: Some(participant_id),
+            limit: Some(RECALL_CANDIDATES),
+            ..Default::default()
+        }))
+        .await?
+        .into_iter()
+        .filter(|memory| memory.memory_type != MemoryType::Working)
+        .filter(|memory| match (msg.workspace_id, memory.workspace_id) {
+            (Some(workspace_id), Some(memory_workspace)) => workspace_id == memory_workspace,
+            _ => true,
+        })
+        // Rolling summaries are part of the context already and consolidated
+        // memories are covered by the memory they were merged into
+        .filter(|memory| {
+            metadata(memory).is_none_or(|metadata| {
+                metadata.kind != SUMMARY_KIND && metadata.consolidated_into.is_none()
+            })
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let query_embedding = embed(db, providers, msg.workspace_id, vec![msg.query.clone()])
+        .await
+        .and_then(|(model_id, embeddings)| Some((model_id, embeddings.into_iter().next()?)));
+    let now = Utc::now();
+    let mut recalled: Vec<RecalledMemory> = candidates
+        .into_iter()
+        .map(|memory| {
+            let similarity = query_embedding
+                .as_ref()
+                .and_then(|(model_id, query)| {
+                    Some(cosine_similarity(query, &memory_embedding(&memory, *model_id)?))
+                })
+                .unwrap_or_else(|| lexical_similarity(&msg.query, &memory.content));
+            let score = recall_score(similarity, memory.importance, memory.last_accessed_at, now);
+            RecalledMemory {
+                memory,
+                similarity,
+                score,
+            }
+        })
+        .filter(|recalled| {
+            recalled.similarity >= MIN_RECALL_SIMILARITY && recalled.score >= MIN_RECALL_SCORE
+        })
+        .collect();
+    recalled.sort_by(|a, b| b.score.total_cmp(&a.score));
+    recalled.truncate(msg.limit);
+
+    db.ask(TouchMemories(
+        recalled.iter().map(|recalled| recalled.memory.id).collect(),
+    ))
+    .await?;
+    Ok(recalled)
+}
+
+/// Renders recalled memories as context for an agent turn
+pub fn recalled_context(memories: &[RecalledMemory]) -> Option<String> {
+    if memories.is_empty() {
+        return None;
+    }
+    let lines = memories
+        .iter()
+        .map(|recalled| format!("- {}", recalled.memory.content))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(format!("Things you remember from earlier conversations:\n{lines}"))
+}
+
+#[derive(Debug, Deserialize)]
+struct ExtractedMemory {
+    content: String,
+    #[serde(rename = "type")]
+    memory_type: ExtractedType,
+    #[serde(default = "default_importance")]
+    importance: f64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExtractedType {
+    Episodic,
+    Semantic,
+    Procedural,
+}
+
+impl From<ExtractedType> for MemoryType {
+    fn from(value: ExtractedType) -> Self {
+        match value {
+            ExtractedType::Episodic => MemoryType::Episodic,
+            ExtractedType::Semantic => MemoryType::Semantic,
+            ExtractedType::Procedural => MemoryType::Procedural,
+        }
+    }
+}
+
+fn default_importance() -> f64 {
+    0.5
+}
+
+/// Reads the JSON array of memories out of the extraction reply
+fn parse_extracted(reply: &str) -> Result<Vec<ExtractedMemory>> {
+    let (Some(start), Some(end)) = (reply.find('['), reply.rfind(']')) else {
+        return Err(AppError::external_service(
+            "Memory extraction didn't return a JSON array",
+        ));
+    };
+    if end < start {
+        return Err(AppError::external_service(
+            "Memory extraction didn't return a JSON array",
+        ));
+    }
+    Ok(
+        serde_json::from_str::<Vec<ExtractedMemory>>(&reply[start..=end])?
+            .into_iter()
+            .filter(|memory| !memory.content.trim().is_empty())
+            .collect(),
+    )
+}
+
+/// Turns the messages of a conversation that weren't looked at yet into memories of an agent
+async fn extract(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    conversation_id: Uuid,
+    agent_id: Uuid,
+) -> Result<Vec<Memory>> {
+    let (agent, model) = agent_model(db, agent_id).await?;
+    let participant_id = agent_participant_id(db, agent_id).await?;
+    let extracted_through = db
+        .ask(ListMemories(MemoryFilter {
+            participant_id: Some(participant_id),
+            conversation_id: Some(conversation_id),
+            ..Default::default()
+        }))
+        .await?
+        .iter()
+        .filter_map(metadata)
+        .filter(|metadata| metadata.kind == EXTRACTED_KIND)
+        .filter_map(|metadata| metadata.extracted_through)
+        .max();
+    let messages: Vec<_> = db
+        .ask(ListMessages(MessageFilter {
+            conversation_id: Some(conversation_id),
+            after_date: extracted_through,
+            ..Default::default()
+        }))
+        .await?
+        .into_iter()
+        .filter(|message| extracted_through.is_none_or(|through| message.created_at > through))
+        .collect();
+    let Some(last_message_at) = messages.iter().map(|message| message.created_at).max() else {
+        return Ok(Vec::new());
+    };
+
+    let mut transcript = render_transcript(&messages_to_history(&messages, participant_id));
+    let excess = transcript.chars().count().saturating_sub(MAX_TRANSCRIPT_CHARS);
+    if excess > 0 {
+        // The end of a conversation usually holds its outcome
+        transcript = transcript.chars().skip(excess).collect();
+    }
+    let provider = providers.resolve(&agent, &model).await?;
+    let completion_model = providers.completion_model(&provider, &model.name).await;
+    let reply = AgentBuilder::new(completion_model)
+        .preamble(EXTRACTION_PREAMBLE)
+        .build()
+        .prompt(transcript)
+        .await
+        .map_err(|e| AppError::external_service(format!("Failed to extract memories: {e}")))?;
+    let extracted = parse_extracted(&reply)?;
+    if extracted.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let embeddings = embed(
+        db,
+        providers,
+        agent.workspace_id,
+        extracted.iter().map(|memory| memory.content.clone()).collect(),
+    )
+    .await;
+    let mut memories = Vec::with_capacity(extracted.len());
+    for (index, extracted) in extracted.into_iter().enumerate() {
+        let embedding = embeddings
+            .as_ref()
+            .and_then(|(model_id, embeddings)| Some((*model_id, embeddings.get(index)?)));
+        let metadata = MemoryMetadata {
+            kind: EXTRACTED_KIND.to_string(),
+            agent_id,
+            extracted_through: Some(last_message_at),
+            embedding_model_id: embedding.map(|(model_id, _)| model_id),
+            ..Default::default()
+        };
+        let memory = db
+            .ask(CreateMemory {
+                workspace_id: agent.workspace_id,
+                participant_id: Some(participant_id),
+                conversation_id: Some(conversation_id),
+                memory_type: extracted.memory_type.into(),
+                content: extracted.content.trim().to_string(),
+                summary: None,
+                importance: extracted.importance.clamp(0.0, 1.0),
+                last_accessed_at: Utc::now(),
+                access_count: 0,
+                metadata: Some(serde_json::to_string(&metadata)?),
+                embedding: embedding
+                    .map(|(_, embedding)| serde_json::to_string(embedding))
+                    .transpose()?,
+            })
+            .await?;
+        memories.push(memory);
+    }
+    info!(%conversation_id, %agent_id, memories = memories.len(), "Extracted memories");
+    Ok(memories)
+}
+
+/// Lowers the importance of memories that weren't used since the last decay
+async fn decay(
+    db: &LocalActorRef<DatabaseActor>,
+    elapsed: Duration,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    // Procedural memories describe how to do things and don't fade
+    for (memory_type, half_life_days) in [
+        (MemoryType::Episodic, EPISODIC_HALF_LIFE_DAYS),
+        (MemoryType::Semantic, SEMANTIC_HALF_LIFE_DAYS),
+    ] {
+        let decayed = db
+            .ask(DecayMemories {
+                memory_type,
+                factor: decay_factor(elapsed, half_life_days),
+                floor: IMPORTANCE_FLOOR,
+                accessed_before: now - elapsed,
+            })
+            .await?;
+        debug!(?memory_type, decayed, "Decayed memory importance");
+    }
+    Ok(())
+}
+
+/// Greedily groups items whose similarity to the first item of the group reaches
+/// `CONSOLIDATION_SIMILARITY`. Only groups of at least `MIN_CLUSTER_SIZE` are returned.
+fn cluster(items: &[(Option<(Uuid, Vec<f32>)>, String)]) -> Vec<Vec<usize>> {
+    let similarity = |a: usize, b: usize| match (&items[a].0, &items[b].0) {
+        (Some((model_a, a)), Some((model_b, b))) if model_a == model_b => cosine_similarity(a, b),
+        _ => lexical_similarity(&items[a].1, &items[b].1),
+    };
+    let mut assigned = vec![false; items.len()];
+    let mut clusters = Vec::new();
+    for seed in 0..items.len() {
+        if assigned[seed] {
+            continue;
+        }
+        let members: Vec<usize> = std::iter::once(seed)
+            .chain(
+                (seed + 1..items.len())
+                    .filter(|&other| !assigned[other])
+                    .filter(|&other| similarity(seed, other) >= CONSOLIDATION_SIMILARITY),
+            )
+            .collect();
+        if members.len() >= MIN_CLUSTER_SIZE {
+            for &member in &members {
+                assigned[member] = true;
+            }
+            clusters.push(members);
+        }
+    }
+    clusters
+}
+
+/// Merges clusters of related old episodic memories into semantic memories
+async fn consolidate(db: &LocalActorRef<DatabaseActor>, providers: &ProviderRegistry) -> Result<()> {
+    let memories = db
+        .ask(ListMemories(MemoryFilter {
+            memory_type: Some(MemoryType::Episodic),
+            created_before: Some(Utc::now() - Duration::days(CONSOLIDATE_AFTER_DAYS)),
+            ..Default::default()
+        }))
+        .await?;
+    let mut by_agent: HashMap<Uuid, Vec<(Memory, MemoryMetadata)>> = HashMap::new();
+    for memory in memories {
+        let Some(metadata) = metadata(&memory) else {
+            continue;
+        };
+        if metadata.kind == EXTRACTED_KIND && metadata.consolidated_into.is_none() {
+            by_agent
+                .entry(metadata.agent_id)
+                .or_default()
+                .push((memory, metadata));
+        }
+    }
+    for (agent_id, memories) in by_agent {
+        if let Err(e) = consolidate_agent(db, providers, agent_id, memories).await {
+            warn!(%agent_id, "Failed to consolidate memories: {e}");
+        }
+    }
+    Ok(())
+}
+
+async fn consolidate_agent(
+    db: &LocalActorRef<DatabaseActor>,
+    providers: &ProviderRegistry,
+    agent_id: Uuid,
+    memories: Vec<(Memory, MemoryMetadata)>,
+) -> Result<()> {
+    let items: Vec<_> = memories
+        .iter()
+        .map(|(memory, metadata)| {
+            let embedding = metadata
+                .embedding_model_id
+                .and_then(|model_id| Some((model_id, memory_embedding(memory, model_id)?)));
+            (embedding, memory.content.clone())
+        })
+        .collect();
+    let clusters = cluster(&items);
+    if clusters.is_empty() {
+        return Ok(());
+    }
+
+    let (agent, model) = agent_model(db, agent_id).await?;
+    let provider = providers.resolve(&agent, &model).await?;
+    let consolidator = AgentBuilder::new(providers.completion_model(&provider, &model.name).await)
+        .preamble(CONSOLIDATION_PREAMBLE)
+        .build();
+    for members in clusters {
+        let sources: Vec<&(Memory, MemoryMetadata)> =
+            members.iter().map(|&index| &memories[index]).collect();
+        let prompt = sources
+            .iter()
+            .map(|(memory, _)| format!("- {}", memory.content))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let content = consolidator.prompt(prompt).await.map_err(|e| {
+            AppError::external_service(format!("Failed to consolidate memories: {e}"))
+        })?;
+        let content = content.trim().to_string();
+        let (first, _) = sources[0];
+        let embedding = embed(db, providers, first.workspace_id, vec![content.clone()])
+            .await
+            .and_then(|(model_id, embeddings)| Some((model_id, embeddings.into_iter().next()?)));
+        let metadata = MemoryMetadata {
+            kind: CONSOLIDATED_KIND.to_string(),
+            agent_id,
+            embedding_model_id: embedding.as_ref().map(|(model_id, _)| *model_id),
+            sources: sources.iter().map(|(memory, _)| memory.id).collect(),
+            ..Default::default()
+        };
+        let consolidated = db
+            .ask(CreateMemory {
+                workspace_id: first.workspace_id,
+                participant_id: first.participant_id,
+                conversation_id: None,
+                memory_type: MemoryType::Semantic,
+                content,
+                summary: None,
+                // A pattern that keeps coming up is worth more than any single occasion
+                importance: (sources
+                    .iter()
+                    .map(|(memory, _)| memory.importance)
+                    .fold(0.0, f64::max)
+                    + 0.1)
+                    .min(1.0),
+                last_accessed_at: Utc::now(),
+                access_count: sources.iter().map(|(memory, _)| memory.access_count).sum(),
+                metadata: Some(serde_json::to_string(&metadata)?),
+                embedding: embedding
+                    .map(|(_, embedding)| serde_json::to_string(&embedding))
+                    .transpose()?,
+            })
+            .await?;
+
+        for (memory, metadata) in sources {
+            let mut memory = memory.clone();
+            let mut metadata = metadata.clone();
+            metadata.consolidated_into = Some(consolidated.id);
+            memory.metadata = Some(serde_json::to_string(&metadata)?);
+            memory.importance = (memory.importance / 2.0).max(IMPORTANCE_FLOOR);
+            db.ask(UpdateMemory(memory)).await?;
+        }
+        debug!(%agent_id, memory_id = %consolidated.id, "Consolidated memories");
+    }
+    Ok(())
+}
+
+impl Message<AgentResponseEvent> for MemoryManagerActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: AgentResponseEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if let StreamedPart::EndOfStream { .. } = msg.response {
+            self.pending
+                .insert((msg.conversation_id, msg.agent_id), Utc::now());
+        }
+    }
+}
+
+impl Message<MaintainMemories> for MemoryManagerActor {
+    type Reply = DelegatedReply<Result<()>>;
+
+    async fn handle(
+        &mut self,
+        _msg: MaintainMemories,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let now = Utc::now();
+        let idle_since = now - Duration::minutes(CONVERSATION_IDLE_MINUTES);
+        let finished: Vec<(Uuid, Uuid)> = self
+            .pending
+            .iter()
+            .filter(|(_, last_activity)| **last_activity <= idle_since)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &finished {
+            self.pending.remove(key);
+        }
+        let elapsed = now - self.last_decay;
+        self.last_decay = now;
+
+        let db = self.db.clone();
+        let providers = self.providers.clone();
+        tokio::spawn(async move {
+            for (conversation_id, agent_id) in finished {
+                if let Err(e) = extract(&db, &providers, conversation_id, agent_id).await {
+                    warn!(%conversation_id, %agent_id, "Failed to extract memories: {e}");
+                }
+            }
+            let res = async {
+                decay(&db, elapsed, now).await?;
+                consolidate(&db, &providers).await
+            }
+            .await;
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+impl Message<ExtractMemories> for MemoryManagerActor {
+    type Reply = DelegatedReply<Result<Vec<Memory>>>;
+
+    async fn handle(
+        &mut self,
+        msg: ExtractMemories,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        self.pending.remove(&(msg.conversation_id, msg.agent_id));
+        let db = self.db.clone();
+        let providers = self.providers.clone();
+        tokio::spawn(async move {
+            let res = extract(&db, &providers, msg.conversation_id, msg.agent_id).await;
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+impl Message<RecallMemories> for MemoryManagerActor {
+    type Reply = DelegatedReply<Result<Vec<RecalledMemory>>>;
+
+    async fn handle(
+        &mut self,
+        msg: RecallMemories,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let db = self.db.clone();
+        let providers = self.providers.clone();
+        tokio::spawn(async move {
+            let res = recall(&db, &providers, msg).await;
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+/// Extracts memories from idle conversations, decays importance and consolidates
+/// old episodic memories
+pub struct MaintainMemories;
+
+/// Extracts memories from a conversation right away, e.g. when it is archived
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractMemories {
+    pub conversation_id: Uuid,
+    pub agent_id: Uuid,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecallMemories {
+    pub agent_id: Uuid,
+    pub workspace_id: Option<Uuid>,
+    pub query: String,
+    pub limit: usize,
+}
+
+/// A memory together with why it was recalled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecalledMemory {
+    pub memory: Memory,
+    pub similarity: f64,
+    pub score: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recall_score_prefers_recent_memories() {
+        let now = Utc::now();
+        let fresh = recall_score(0.8, 0.5, now, now);
+        let stale = recall_score(0.8, 0.5, now - Duration::days(30), now);
+
+        assert!(fresh > stale);
+        assert!((fresh - (0.4 + 0.15 + 0.2)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_decay_factor_halves_after_half_life() {
+        assert!((decay_factor(Duration::days(30), 30.0) - 0.5).abs() < 1e-9);
+        assert_eq!(decay_factor(Duration::zero(), 30.0), 1.0);
+    }
+
+    #[test]
+    fn test_parse_extracted_ignores_surrounding_text() {
+        let reply = "Here you go:\n```json\n[{\"content\": \"The user prefers Rust.\", \
+            \"type\": \"semantic\", \"importance\": 0.8}, {\"content\": \" \", \"type\": \"episodic\"}]\n```";
+
+        let extracted = parse_extracted(reply).unwrap();
+
+        assert_eq!(extracted.len(), 1);
+        assert!(matches!(extracted[0].memory_type, ExtractedType::Semantic));
+        assert_eq!(extracted[0].importance, 0.8);
+    }
+
+    #[test]
+    fn test_cluster_groups_similar_embeddings() {
+        let model_id = Uuid::new_v4();
+        let item = |embedding: Vec<f32>| (Some((model_id, embedding)), String::new());
+        let items = vec![
+            item(vec![1.0, 0.0]),
+            item(vec![0.0, 1.0]),
+            item(vec![0.95, 0.05]),
+            item(vec![0.9, 0.1]),
+        ];
+
+        assert_eq!(cluster(&items), vec![vec![0, 2, 3]]);
+    }
+}
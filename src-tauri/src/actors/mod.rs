@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use color_eyre::eyre::eyre;
 use futures_util::{StreamExt, stream};
@@ -12,75 +15,48 @@ use kameo_actors::{
     pool::ActorPool,
 };
 use libp2p::{
-    Multiaddr, Swarm, SwarmBuilder, dcutr, identify,
+    Swarm, SwarmBuilder, dcutr, identify,
     multiaddr::Protocol,
-    noise, ping, relay,
+    noise, ping, relay, rendezvous, request_response,
     swarm::{NetworkBehaviour, SwarmEvent},
     yamux,
 };
 use serde::{Serialize, de::DeserializeOwned};
 use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::mpsc;
 use tracing::{error, info};
 
-// NEW: Define your bootstrap nodes. In a real app, this would come from a config file.
-const BOOTSTRAP_NODES: &[&str] = &["/ip4/150.136.100.92/udp/4001/quic-v1"];
-
 pub mod agents;
+pub mod chains;
+pub mod context;
 pub mod conversation;
 pub mod database;
+pub mod delivery;
+pub mod discovery;
+pub mod documents;
 pub mod fault_detection;
 pub mod gateway;
+pub mod hosting;
+pub mod identity;
 pub mod ipc;
 pub mod lifecycle;
 pub mod lifecycle_utils;
+pub mod mcp;
+pub mod mcp_endpoint;
+pub mod memory;
 pub mod metrics;
+pub mod providers;
+pub mod rpc;
 pub mod supervision;
 pub mod supervision_tree;
 pub mod swarm;
+pub mod telemetry;
 pub mod tools;
+pub mod transcript;
+pub mod transfer;
 pub mod ui_notifier;
 pub mod websocket;
-
-#[macro_export]
-macro_rules! signed_impl {
-    ($name:ty, $actor:ty) => {
-        impl ::kameo::prelude::Message<$crate::keys::Signed<$name>> for $actor {
-            type Reply = ();
-
-            async fn handle(
-                &mut self,
-                msg: $crate::keys::Signed<$name>,
-                ctx: &mut Context<Self, Self::Reply>,
-            ) -> Self::Reply {
-                use $crate::{
-                    actors::{GatewayActor, RemoteActorRef},
-                    error::AppError,
-                    keys::Signed,
-                    utils::get_gateway_id,
-                };
-                let peer_id = msg.client_peer_id().clone();
-                let task_id = msg.task_id().cloned();
-                let msg = msg.into_inner();
-                let Ok(Some(remote)) =
-                    RemoteActorRef::<GatewayActor>::lookup(&get_gateway_id(&peer_id)).await
-                else {
-                    // We couldn't make the connnection back to the gateway
-                    // and this will always be sent by a `tell` call so just retur
-                    return;
-                };
-                let actor_ref = ctx.actor_ref();
-                tokio::task::spawn(async move {
-                    let out = actor_ref
-                        .ask(msg)
-                        .await
-                        .map_err(|e| AppError::SendError(format!("Could not send message!: {e}")));
-                    let signed = Signed::with_task(out, task_id);
-                    remote.tell(&signed).await.ok();
-                });
-            }
-        }
-    };
-}
+pub mod workflows;
 
 #[macro_export]
 macro_rules! register_actor {
@@ -99,22 +75,53 @@ macro_rules! register_actor {
 use crate::{
     actors::{
         agents::{AgentActor, AgentManagerActor, AgentResponseEvent},
+        chains::{ChainExecutorActor, ChainStepEvent},
         conversation::{ConversationManagerActor, SendMessage},
-        database::DatabaseActor,
-        gateway::{GATEWAY_ACTOR, GatewayActor},
+        database::{DatabaseActor, MarkStaleP2pNodesOffline},
+        delivery::{DeliveryAck, DeliveryActor, RETRY_INTERVAL, RetryDeliveries},
+        discovery::{Discovery, NetworkConfig},
+        documents::{DocumentIndexerActor, SearchDocuments},
+        gateway::{GATEWAY_ACTOR, GatewayActor, PeerApprovalRequested},
+        hosting::{HostingActor, PeerAgentResponseEvent},
+        identity::IdentityActor,
+        mcp::{CheckMcpServers, ConnectMcpServers, HEALTH_CHECK_INTERVAL, McpManagerActor},
+        mcp_endpoint::{McpEndpointActor, StartMcpEndpoint},
+        memory::{MAINTENANCE_INTERVAL, MaintainMemories, MemoryManagerActor},
+        providers::ProviderRegistry,
         swarm::{
             Behaviour, ConnectionClosed, ConnectionEstablished, ConnectionManager, swarm_handler,
         },
-        tools::ToolExecutorActor,
+        telemetry::{PeerTelemetry, STALE_AFTER, STALE_SWEEP_INTERVAL},
+        tools::{Tool, ToolDyn, ToolExecutorActor, ToolWrapper},
+        transcript::TranscriptActor,
+        transfer::{
+            FileTransferActor, FileTransferCodec, FileTransferProgress, PROTOCOL,
+            REQUEST_TIMEOUT, TransferNetwork,
+        },
         ui_notifier::UINotifierActor,
+        workflows::{RecoverWorkflows, WorkflowEngineActor, WorkflowStepEvent},
     },
+    entities::Message as ChatMessage,
     error::Result,
-    keys::{PEER_ID, Signed, fetch_peer_keypair},
+    integration::{
+        EncryptedFileCredentialStore, EncryptedFileCredentialStoreConfig, KeyDerivationMethod,
+        SecretCipher,
+    },
+    keys::{
+        KEY_PAIR, KeyRotation, PEER_ID, REPLAY_CACHE_CAPACITY, REPLAY_CACHE_PEERS, ReplayCache,
+        Signed,
+    },
+    plugins::PLUGIN_TOOL_EXECUTOR,
     repositories::RepositoryFactory,
     state::ActorManager,
-    storage::db::DatabaseManager,
+    storage::{db::DatabaseManager, vector::VectorStoreRegistry},
+    tools::BuiltinTools,
+    utils::get_data_dir,
 };
 
+/// Key in the OS keychain provider credentials are sealed with at rest
+const CREDENTIALS_KEY_ID: &str = "evo-pro-credentials";
+
 /// A trait to ensure that a reply type is a subtype of the actual reply type.
 /// And that the actual reply is able to be sent over the peer-to-peer network.
 pub trait Askable<T: Send + 'static>: Message<T> {
@@ -124,7 +131,10 @@ pub trait Askable<T: Send + 'static>: Message<T> {
 pub type SystemEventBus = MessageBus;
 
 pub async fn setup_actors(handle: AppHandle, db: DatabaseManager) -> Result<ActorManager> {
-    let key_pair = fetch_peer_keypair();
+    let network = NetworkConfig::load(&db).await?;
+    // A device key rotated while we run is used from the next start, the swarm can't
+    // change its peer id
+    let key_pair = KEY_PAIR.read().unwrap().clone();
     let mut swarm = SwarmBuilder::with_existing_identity(key_pair)
         .with_tokio()
         .with_quic()
@@ -141,6 +151,12 @@ pub async fn setup_actors(handle: AppHandle, db: DatabaseManager) -> Result<Acto
                     keypair.public(),
                 )),
                 dcutr: dcutr::Behaviour::new(keypair.public().to_peer_id()),
+                rendezvous: rendezvous::client::Behaviour::new(keypair.clone()),
+                file_transfer: request_response::Behaviour::with_codec(
+                    FileTransferCodec,
+                    [(PROTOCOL, request_response::ProtocolSupport::Full)],
+                    request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+                ),
             })
         })
         .expect("Failed to initialize behaviour")
@@ -150,6 +166,8 @@ pub async fn setup_actors(handle: AppHandle, db: DatabaseManager) -> Result<Acto
     let system_event_bus_ref =
         SystemEventBus::spawn(SystemEventBus::new(DeliveryStrategy::BestEffort));
 
+    let db_path = db.db_path.clone();
+
     // Initialize repository factory with database pool
     let repo_factory = RepositoryFactory::new(db.pool.clone());
 
@@ -158,57 +176,171 @@ pub async fn setup_actors(handle: AppHandle, db: DatabaseManager) -> Result<Acto
         db,
         repo_factory,
     });
+    let key_derivation = KeyDerivationMethod::SystemProtected {
+        key_id: CREDENTIALS_KEY_ID.to_string(),
+    };
+    let providers = ProviderRegistry::new(
+        db_actor.clone(),
+        SecretCipher::new(&key_derivation)?,
+        Some(Arc::new(EncryptedFileCredentialStore::new(
+            EncryptedFileCredentialStoreConfig {
+                file_path: get_data_dir().join("credentials.enc"),
+                key_derivation,
+            },
+        ))),
+    );
     let agent_manager = AgentManagerActor::spawn(AgentManagerActor {
         bus: system_event_bus_ref.clone(),
         pool: ActorPool::spawn(ActorPool::new(8, {
             let bus = system_event_bus_ref.clone();
-            move || AgentActor::spawn(AgentActor { bus: bus.clone() })
+            let db = db_actor.clone();
+            let providers = providers.clone();
+            move || {
+                AgentActor::spawn(AgentActor {
+                    bus: bus.clone(),
+                    db: db.clone(),
+                    providers: providers.clone(),
+                })
+            }
         })),
     });
+    let document_indexer = DocumentIndexerActor::spawn(DocumentIndexerActor {
+        db: db_actor.clone(),
+        providers: providers.clone(),
+        stores: VectorStoreRegistry::new(get_data_dir().join("vectors").join("documents")),
+    });
     let tool_executor = ToolExecutorActor::spawn(ToolExecutorActor {
-        tools: HashMap::new(),
+        tools: HashMap::from([(
+            SearchDocuments::NAME.into(),
+            ToolWrapper(Arc::new(SearchDocuments {
+                indexer: document_indexer.clone(),
+            }) as Arc<dyn ToolDyn>),
+        )]),
+    });
+    PLUGIN_TOOL_EXECUTOR.set(tool_executor.clone()).ok();
+    let builtin_tools = BuiltinTools {
+        db: db_actor.clone(),
+        tool_executor: tool_executor.clone(),
+        db_path,
+    };
+    builtin_tools.load().await?;
+    let mcp_manager = McpManagerActor::spawn(McpManagerActor {
+        db: db_actor.clone(),
+        tool_executor: tool_executor.clone(),
+        servers: HashMap::new(),
+    });
+    let mcp_endpoint = McpEndpointActor::spawn(McpEndpointActor {
+        db: db_actor.clone(),
+        tool_executor: tool_executor.clone(),
+        agent_manager: agent_manager.clone(),
+        settings: Default::default(),
+        windows: HashMap::new(),
+        server: None,
+    });
+    let delivery = DeliveryActor::spawn(DeliveryActor {
+        db: db_actor.clone(),
+        flushing: HashMap::new(),
+    });
+    let identity = IdentityActor::spawn(IdentityActor {
+        db: db_actor.clone(),
+        delivery: delivery.clone(),
     });
     let conversation_manager = ConversationManagerActor::spawn(ConversationManagerActor {
         agent_manager: agent_manager.clone(),
+        tool_executor: tool_executor.clone(),
         db: db_actor.clone(),
+        bus: system_event_bus_ref.clone(),
+        delivery: delivery.clone(),
         conversation_peers: HashMap::new(),
     });
+    let chain_executor = ChainExecutorActor::spawn(ChainExecutorActor {
+        db: db_actor.clone(),
+        providers: providers.clone(),
+        bus: system_event_bus_ref.clone(),
+        running: HashMap::new(),
+    });
+    let workflow_engine = WorkflowEngineActor::spawn(WorkflowEngineActor {
+        db: db_actor.clone(),
+        providers: providers.clone(),
+        bus: system_event_bus_ref.clone(),
+        running: HashMap::new(),
+    });
+    let memory_manager = MemoryManagerActor::spawn(MemoryManagerActor {
+        db: db_actor.clone(),
+        providers: providers.clone(),
+        pending: HashMap::new(),
+        last_decay: chrono::Utc::now(),
+    });
+    let transcript = TranscriptActor::spawn(TranscriptActor {
+        db: db_actor.clone(),
+        turns: HashMap::new(),
+    });
     let ui_notifier = UINotifierActor::spawn(UINotifierActor {
         handle: handle.clone(),
     });
+    let hosting = HostingActor::spawn(HostingActor {
+        db: db_actor.clone(),
+        agent_manager: agent_manager.clone(),
+        running: HashMap::new(),
+        budgets: HashMap::new(),
+    });
     PEER_ID.set(*actor_swarm.local_peer_id()).ok();
     let gateway = GatewayActor::spawn(GatewayActor {
         db: db_actor.clone(), // Use db_actor here
         bus: system_event_bus_ref.clone(),
         agent_manager: agent_manager.clone(),
         tool_executor: tool_executor.clone(),
-        active_tasks: HashMap::new(),
+        hosting,
+        pending_calls: HashMap::new(),
+        serving: HashMap::new(),
+        replay_cache: ReplayCache::new(REPLAY_CACHE_CAPACITY, REPLAY_CACHE_PEERS),
+    });
+    let (transfer_commands, transfer_rx) = mpsc::unbounded_channel();
+    let file_transfers = FileTransferActor::spawn(FileTransferActor {
+        db: db_actor.clone(),
+        bus: system_event_bus_ref.clone(),
+        swarm: transfer_commands.clone(),
+        downloads: HashMap::new(),
     });
+    let transfers = TransferNetwork::new(
+        db_actor.clone(),
+        system_event_bus_ref.clone(),
+        transfer_rx,
+        transfer_commands,
+    );
     let connection_manager = ConnectionManager::spawn(ConnectionManager {
+        db: db_actor.clone(),
         active_connections: HashSet::new(),
+        links: HashMap::new(),
     });
     register_actor!(
         system_event_bus_ref,
         conversation_manager,
-        [AgentResponseEvent]
+        [AgentResponseEvent, Signed<ChatMessage>]
     );
-    register_actor!(system_event_bus_ref, ui_notifier, [AgentResponseEvent, Signed<AgentResponseEvent>, SendMessage, Signed<SendMessage>]);
+    register_actor!(system_event_bus_ref, transcript, [AgentResponseEvent]);
+    register_actor!(system_event_bus_ref, memory_manager, [AgentResponseEvent]);
+    register_actor!(system_event_bus_ref, ui_notifier, [AgentResponseEvent, Signed<AgentResponseEvent>, SendMessage, Signed<SendMessage>, ChatMessage, ChainStepEvent, WorkflowStepEvent, PeerApprovalRequested, PeerAgentResponseEvent, FileTransferProgress]);
     register_actor!(
         system_event_bus_ref,
         connection_manager,
-        [ConnectionEstablished, ConnectionClosed]
+        [ConnectionEstablished, ConnectionClosed, PeerTelemetry]
+    );
+    register_actor!(
+        system_event_bus_ref,
+        delivery,
+        [ConnectionEstablished, Signed<DeliveryAck>]
     );
+    register_actor!(system_event_bus_ref, file_transfers, [ConnectionEstablished]);
+    register_actor!(system_event_bus_ref, identity, [Signed<KeyRotation>]);
     GATEWAY_ACTOR.set(gateway.clone()).ok();
     gateway
         .register(&format!("gateway-{}", &PEER_ID.get().unwrap()))
         .await?;
-    // MODIFIED: Listen on dynamic ports instead of a fixed one.
-    actor_swarm
-        .listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse().unwrap())
-        .await?;
-    actor_swarm
-        .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
-        .await?;
+    for addr in network.listen_addrs() {
+        actor_swarm.listen_on(addr).await?;
+    }
+    let discovery = Discovery::new(&network, db_actor.clone());
 
     let manager = ActorManager {
         bus: system_event_bus_ref,
@@ -216,28 +348,92 @@ pub async fn setup_actors(handle: AppHandle, db: DatabaseManager) -> Result<Acto
         agent_manager: agent_manager,
         tool_ref: tool_executor,
         conversation_manager,
+        document_indexer,
+        chain_executor,
+        workflow_engine: workflow_engine.clone(),
+        memory_manager: memory_manager.clone(),
+        file_transfers,
+        identity,
+        mcp_manager: mcp_manager.clone(),
+        mcp_endpoint: mcp_endpoint.clone(),
+        providers,
+        builtin_tools,
     };
 
-    // NEW: Dial the bootstrap nodes in a background task to join the network.
+    // Continue the workflow runs that were interrupted when the app was closed
+    workflow_engine.tell(RecoverWorkflows).await?;
+
+    // Offer the tools of the MCP servers to agents, and keep the connections alive
+    mcp_manager.tell(ConnectMcpServers).await?;
+    mcp_endpoint.tell(StartMcpEndpoint).await?;
     tokio::spawn(async move {
-        stream::iter(
-            BOOTSTRAP_NODES
-                .iter()
-                .filter_map(|addr| addr.parse::<Multiaddr>().ok()),
-        )
-        .for_each_concurrent(10, |multiaddr| async {
-            let listen_addr = multiaddr.with(Protocol::P2pCircuit);
-            if let Err(e) = actor_swarm.listen_on(listen_addr.clone()).await {
-                error!("Failed to listen on bootstrap node: {}", e);
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        // The first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if mcp_manager.tell(CheckMcpServers).await.is_err() {
+                break;
             }
-            info!("Listening on bootstrap node: {}", listen_addr);
-        })
-        .await;
+        }
+    });
+
+    // Turn finished conversations into long-term memories in the background
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+        // The first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = memory_manager.ask(MaintainMemories).await {
+                error!("Failed to maintain memories: {e}");
+            }
+        }
+    });
+
+    // Retry the queued peer messages that could not be delivered yet
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = delivery.ask(RetryDeliveries).await {
+                error!("Failed to retry queued peer messages: {e}");
+            }
+        }
+    });
+
+    // Mark the nodes that stopped answering pings, or were online when the app was
+    // closed, as offline
+    tokio::spawn({
+        let db = manager.db.clone();
+        async move {
+            let mut interval = tokio::time::interval(STALE_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let cutoff = chrono::Utc::now() - STALE_AFTER;
+                if let Err(e) = db.ask(MarkStaleP2pNodesOffline(cutoff)).await {
+                    error!("Failed to mark stale peers offline: {e}");
+                }
+            }
+        }
+    });
+
+    // Listen through the relays of the bootstrap nodes to be reachable from outside our network
+    tokio::spawn(async move {
+        stream::iter(network.bootstrap_addrs())
+            .for_each_concurrent(10, |multiaddr| async {
+                let listen_addr = multiaddr.with(Protocol::P2pCircuit);
+                if let Err(e) = actor_swarm.listen_on(listen_addr.clone()).await {
+                    error!("Failed to listen on bootstrap node: {}", e);
+                }
+                info!("Listening on bootstrap node: {}", listen_addr);
+            })
+            .await;
     });
     // Start the swarm handler in a separate thread
     tokio::spawn({
         let manager = manager.clone();
-        async move { swarm_handler(&mut swarm, &mut handler, manager).await }
+        async move { swarm_handler(&mut swarm, &mut handler, manager, discovery, transfers).await }
     });
 
     Ok(manager)
@@ -0,0 +1,559 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use kameo::prelude::ActorRef as LocalActorRef;
+use rig::{
+    client::{
+        CompletionClient, EmbeddingsClient, ProviderClient as _,
+        completion::CompletionModelHandle,
+    },
+    completion::CompletionModel,
+    embeddings::EmbeddingModel,
+    providers::{anthropic, cohere, deepseek, gemini, ollama, openai, perplexity, xai},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
+use tokio::sync::RwLock;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    actors::database::{DatabaseActor, GetCredential, GetWorkspaceCredential},
+    entities::{Agent, CreateCredential, Credential, Model, ModelProvider},
+    error::{AppError, Result},
+    integration::{CredentialStore, Credentials, SecretCipher},
+};
+
+/// Where the API key of a provider is read from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CredentialRef {
+    /// A row of the `credentials` table
+    Database(Uuid),
+    /// An entry of the configured [`CredentialStore`]
+    Store(String),
+    /// An environment variable
+    Env(String),
+}
+
+/// Provider settings stored under the `provider` key of an agent's or a model's config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderConfig {
+    pub credential: Option<CredentialRef>,
+    pub base_url: Option<String>,
+}
+
+impl ProviderConfig {
+    /// Reads the `provider` key of a config. A malformed one is an error rather than
+    /// ignored, which would let resolution fall back to other keys unnoticed.
+    pub fn from_config(config: Option<&Json<Value>>, owner: impl fmt::Display) -> Result<Self> {
+        match config.and_then(|config| config.0.get("provider")) {
+            Some(provider) => serde_json::from_value(provider.clone()).map_err(|e| {
+                AppError::validation(format!("Invalid provider config of {owner}: {e}"))
+            }),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// Everything needed to build a client for a provider.
+/// Without an API key the client falls back to the provider's environment variable.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ResolvedProvider {
+    pub provider: ModelProvider,
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+}
+
+impl fmt::Debug for ResolvedProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolvedProvider")
+            .field("provider", &self.provider)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+/// Maximum number of texts sent to an embedding model in one request
+const EMBEDDING_BATCH_SIZE: usize = 64;
+
+macro_rules! keyed_client {
+    ($provider:ident, $resolved:expr) => {
+        match ($resolved.api_key.as_deref(), $resolved.base_url.as_deref()) {
+            (Some(key), Some(url)) => $provider::Client::from_url(key, url),
+            (Some(key), None) => $provider::Client::new(key),
+            (None, Some(url)) => $provider::Client::from_url("", url),
+            (None, None) => $provider::Client::from_env(),
+        }
+    };
+}
+
+#[derive(Clone)]
+enum CachedClient {
+    OpenAI(openai::Client),
+    Cohere(cohere::Client),
+    Anthropic(anthropic::Client),
+    Perplexity(perplexity::Client),
+    Gemini(gemini::Client),
+    XAi(xai::Client),
+    DeepSeek(deepseek::Client),
+    Ollama(ollama::Client),
+}
+
+impl CachedClient {
+    fn new(resolved: &ResolvedProvider) -> Self {
+        match resolved.provider {
+            ModelProvider::OpenAI => Self::OpenAI(keyed_client!(openai, resolved)),
+            ModelProvider::Cohere => Self::Cohere(keyed_client!(cohere, resolved)),
+            ModelProvider::Anthropic => Self::Anthropic(
+                match (resolved.api_key.as_deref(), resolved.base_url.as_deref()) {
+                    (Some(key), Some(url)) => {
+                        anthropic::ClientBuilder::new(key).base_url(url).build()
+                    }
+                    (Some(key), None) => anthropic::ClientBuilder::new(key).build(),
+                    (None, Some(url)) => {
+                        let key = std::env::var("ANTHROPIC_API_KEY").unwrap_or_default();
+                        anthropic::ClientBuilder::new(&key).base_url(url).build()
+                    }
+                    (None, None) => anthropic::Client::from_env(),
+                },
+            ),
+            ModelProvider::Perplexity => Self::Perplexity(keyed_client!(perplexity, resolved)),
+            ModelProvider::Gemini => Self::Gemini(keyed_client!(gemini, resolved)),
+            ModelProvider::XAi => Self::XAi(keyed_client!(xai, resolved)),
+            ModelProvider::DeepSeek => Self::DeepSeek(keyed_client!(deepseek, resolved)),
+            // Ollama runs locally and doesn't take an API key
+            ModelProvider::Ollama => Self::Ollama(match resolved.base_url.as_deref() {
+                Some(url) => ollama::Client::from_url(url),
+                None => ollama::Client::from_env(),
+            }),
+        }
+    }
+
+    fn completion_model(&self, name: &str) -> impl CompletionModel {
+        match self {
+            Self::OpenAI(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+            Self::Cohere(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+            Self::Anthropic(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+            Self::Perplexity(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+            Self::Gemini(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+            Self::XAi(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+            Self::DeepSeek(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+            Self::Ollama(client) => CompletionModelHandle {
+                inner: Arc::new(client.completion_model(name)),
+            },
+        }
+    }
+}
+
+macro_rules! embed_with {
+    ($client:expr, $name:expr, $texts:expr) => {{
+        let model = EmbeddingsClient::embedding_model($client, $name);
+        let mut embeddings = Vec::with_capacity($texts.len());
+        for batch in $texts.chunks(EMBEDDING_BATCH_SIZE) {
+            embeddings.extend(
+                model
+                    .embed_texts(batch.to_vec())
+                    .await
+                    .map_err(|e| {
+                        AppError::external_service(format!("Failed to embed texts: {e}"))
+                    })?
+                    .into_iter()
+                    .map(|embedding| embedding.vec.into_iter().map(|v| v as f32).collect()),
+            );
+        }
+        Ok(embeddings)
+    }};
+}
+
+impl CachedClient {
+    async fn embed(&self, name: &str, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        match self {
+            Self::OpenAI(client) => embed_with!(client, name, texts),
+            Self::Cohere(client) => embed_with!(client, name, texts),
+            Self::Gemini(client) => embed_with!(client, name, texts),
+            Self::XAi(client) => embed_with!(client, name, texts),
+            Self::Ollama(client) => embed_with!(client, name, texts),
+            Self::Anthropic(_) | Self::Perplexity(_) | Self::DeepSeek(_) => Err(
+                AppError::operation_not_supported("This provider doesn't offer embedding models"),
+            ),
+        }
+    }
+}
+
+/// Resolves provider credentials for agents and caches the clients built from them.
+///
+/// Keys are looked up in this order:
+/// 1. the `provider` config of the agent
+/// 2. the `provider` config of the model
+/// 3. a credential named after the provider in the agent's workspace (or a global one)
+/// 4. credentials for the provider in the [`CredentialStore`]
+/// 5. the provider's environment variable
+#[derive(Clone)]
+pub struct ProviderRegistry {
+    db: LocalActorRef<DatabaseActor>,
+    cipher: Arc<SecretCipher>,
+    store: Option<Arc<dyn CredentialStore>>,
+    clients: Arc<RwLock<HashMap<ResolvedProvider, CachedClient>>>,
+}
+
+impl ProviderRegistry {
+    pub fn new(
+        db: LocalActorRef<DatabaseActor>,
+        cipher: SecretCipher,
+        store: Option<Arc<dyn CredentialStore>>,
+    ) -> Self {
+        Self {
+            db,
+            cipher: Arc::new(cipher),
+            store,
+            clients: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Resolves the provider settings used to run the given agent on the given model
+    pub async fn resolve(&self, agent: &Agent, model: &Model) -> Result<ResolvedProvider> {
+        let agent_config =
+            ProviderConfig::from_config(agent.config.as_ref(), format_args!("agent {}", agent.id))?;
+        let resolved = self
+            .resolve_with(agent_config, model, agent.workspace_id)
+            .await?;
+        debug!(agent_id = %agent.id, ?resolved, "Resolved provider for agent");
+        Ok(resolved)
+    }
+
+    /// Resolves the provider settings of a model that isn't run by an agent, e.g. an
+    /// embedding model
+    pub async fn resolve_model(
+        &self,
+        model: &Model,
+        workspace_id: Option<Uuid>,
+    ) -> Result<ResolvedProvider> {
+        self.resolve_with(ProviderConfig::default(), model, workspace_id)
+            .await
+    }
+
+    async fn resolve_with(
+        &self,
+        overrides: ProviderConfig,
+        model: &Model,
+        workspace_id: Option<Uuid>,
+    ) -> Result<ResolvedProvider> {
+        let model_config =
+            ProviderConfig::from_config(model.config.as_ref(), format_args!("model {}", model.id))?;
+        let api_key = match overrides.credential.or(model_config.credential) {
+            Some(credential) => Some(self.read_credential(&credential).await?),
+            None => self.default_api_key(model.provider, workspace_id).await?,
+        };
+        Ok(ResolvedProvider {
+            provider: model.provider,
+            api_key,
+            base_url: overrides.base_url.or(model_config.base_url),
+        })
+    }
+
+    /// Returns a completion model for the resolved provider, reusing a cached client if possible
+    pub async fn completion_model(
+        &self,
+        resolved: &ResolvedProvider,
+        name: &str,
+    ) -> impl CompletionModel {
+        self.client(resolved).await.completion_model(name)
+    }
+
+    /// Embeds the given texts with an embedding model of the resolved provider
+    pub async fn embed(
+        &self,
+        resolved: &ResolvedProvider,
+        name: &str,
+        texts: Vec<String>,
+    ) -> Result<Vec<Vec<f32>>> {
+        let client = self.client(resolved).await;
+        client.embed(name, texts).await
+    }
+
+    async fn client(&self, resolved: &ResolvedProvider) -> CachedClient {
+        if let Some(client) = self.clients.read().await.get(resolved) {
+            return client.clone();
+        }
+        let client = CachedClient::new(resolved);
+        self.clients
+            .write()
+            .await
+            .insert(resolved.clone(), client.clone());
+        client
+    }
+
+    /// Encrypts and stores a new credential
+    pub async fn store_credential(
+        &self,
+        workspace_id: Option<Uuid>,
+        name: String,
+        credential_name: String,
+        value: &str,
+    ) -> Result<Credential> {
+        let encrypted_value = BASE64_STANDARD.encode(self.cipher.encrypt(value.as_bytes())?);
+        Ok(self
+            .db
+            .ask(CreateCredential {
+                workspace_id,
+                name,
+                credential_name,
+                encrypted_value,
+            })
+            .await?)
+    }
+
+    /// Drops every cached client, e.g. after a credential was removed
+    pub async fn clear_cache(&self) {
+        self.clients.write().await.clear();
+    }
+
+    async fn read_credential(&self, credential: &CredentialRef) -> Result<String> {
+        match credential {
+            CredentialRef::Database(id) => {
+                let credential = self
+                    .db
+                    .ask(GetCredential(*id))
+                    .await?
+                    .ok_or_else(|| AppError::not_found("Credential", id))?;
+                self.decrypt(&credential)
+            }
+            CredentialRef::Store(id) => {
+                let store = self
+                    .store
+                    .as_ref()
+                    .ok_or_else(|| AppError::configuration("No credential store configured"))?;
+                api_key_parameter(&store.get_credentials(id)?)
+            }
+            CredentialRef::Env(var) => std::env::var(var).map_err(|_| {
+                AppError::configuration(format!("Environment variable {var} is not set"))
+            }),
+        }
+    }
+
+    async fn default_api_key(
+        &self,
+        provider: ModelProvider,
+        workspace_id: Option<Uuid>,
+    ) -> Result<Option<String>> {
+        let credential_name = provider.credential_name();
+        if let Some(credential) = self
+            .db
+            .ask(GetWorkspaceCredential {
+                workspace_id,
+                credential_name: credential_name.to_string(),
+            })
+            .await?
+        {
+            return self.decrypt(&credential).map(Some);
+        }
+        if let Some(store) = &self.store {
+            if let Some(credentials) = store
+                .get_credentials_for_service(credential_name)?
+                .first()
+            {
+                return api_key_parameter(credentials).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn decrypt(&self, credential: &Credential) -> Result<String> {
+        let encrypted = BASE64_STANDARD.decode(&credential.encrypted_value).map_err(|e| {
+            AppError::validation(format!("Credential {} is malformed: {e}", credential.id))
+        })?;
+        String::from_utf8(self.cipher.decrypt(&encrypted)?).map_err(|e| {
+            AppError::validation(format!("Credential {} is malformed: {e}", credential.id))
+        })
+    }
+}
+
+fn api_key_parameter(credentials: &Credentials) -> Result<String> {
+    credentials
+        .parameters
+        .get("api_key")
+        .cloned()
+        .ok_or_else(|| {
+            AppError::configuration(format!(
+                "Credentials {} have no api_key parameter",
+                credentials.id
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use kameo::Actor;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        entities::{AgentStatus, AgentType, ModelType},
+        integration::{AuthMethod, InMemoryCredentialStore, KeyDerivationMethod},
+        repositories::RepositoryFactory,
+        storage::db::DatabaseManager,
+    };
+
+    fn agent(config: Option<Value>) -> Agent {
+        Agent {
+            id: Uuid::new_v4(),
+            name: "Agent".to_string(),
+            description: None,
+            avatar_url: None,
+            agent_type: AgentType::Worker,
+            status: AgentStatus::Active,
+            version: "1".to_string(),
+            config: config.map(Json),
+            tool_config: None,
+            context_window: 8192,
+            parent_agent_id: None,
+            operator_level: 0,
+            delegation_rules: None,
+            performance_metrics: None,
+            last_interaction_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            model_id: None,
+            participant_id: None,
+            created_by_id: None,
+            operator_user_id: None,
+            registry_id: None,
+            workspace_id: None,
+        }
+    }
+
+    fn model(config: Option<Value>) -> Model {
+        Model {
+            id: Uuid::new_v4(),
+            provider: ModelProvider::OpenAI,
+            name: "gpt-4o".to_string(),
+            display_name: None,
+            model_type: ModelType::Llm,
+            context_size: 8192,
+            max_tokens: 1024,
+            supports_functions: true,
+            supports_vision: false,
+            supports_streaming: true,
+            input_cost: None,
+            output_cost: None,
+            config: config.map(Json),
+            is_active: true,
+            is_deprecated: false,
+            registry_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn credentials(id: &str, service_id: &str, api_key: &str) -> Credentials {
+        Credentials {
+            id: id.to_string(),
+            service_id: service_id.to_string(),
+            method: AuthMethod::ApiKey,
+            parameters: HashMap::from([("api_key".to_string(), api_key.to_string())]),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            expires_at: None,
+        }
+    }
+
+    fn provider_config(store_id: &str) -> Value {
+        json!({
+            "provider": ProviderConfig {
+                credential: Some(CredentialRef::Store(store_id.to_string())),
+                base_url: None,
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_resolve_order() {
+        let db = DatabaseManager::setup_test_db().await;
+        let repo_factory = RepositoryFactory::new(db.pool.clone());
+        let db = DatabaseActor::spawn(DatabaseActor { db, repo_factory });
+        let store = Arc::new(InMemoryCredentialStore::new());
+        let cipher = SecretCipher::new(&KeyDerivationMethod::Password {
+            password: "test".to_string(),
+            salt: Uuid::new_v4().as_bytes().to_vec(),
+            iterations: 1_000,
+        })
+        .unwrap();
+        let registry =
+            ProviderRegistry::new(db, cipher, Some(store.clone() as Arc<dyn CredentialStore>));
+        store
+            .store_credentials(&credentials("agent", "other", "agent-key"))
+            .unwrap();
+        store
+            .store_credentials(&credentials("model", "other", "model-key"))
+            .unwrap();
+        let api_key = |agent: Agent, model: Model| {
+            let registry = registry.clone();
+            async move { registry.resolve(&agent, &model).await.unwrap().api_key }
+        };
+
+        // Nothing configured leaves it to the provider's environment variable
+        assert_eq!(api_key(agent(None), model(None)).await, None);
+
+        store
+            .store_credentials(&credentials("openai", "openai", "store-key"))
+            .unwrap();
+        assert_eq!(
+            api_key(agent(None), model(None)).await.as_deref(),
+            Some("store-key")
+        );
+
+        registry
+            .store_credential(None, "OpenAI".to_string(), "openai".to_string(), "db-key")
+            .await
+            .unwrap();
+        assert_eq!(
+            api_key(agent(None), model(None)).await.as_deref(),
+            Some("db-key")
+        );
+
+        assert_eq!(
+            api_key(agent(None), model(Some(provider_config("model"))))
+                .await
+                .as_deref(),
+            Some("model-key")
+        );
+
+        assert_eq!(
+            api_key(
+                agent(Some(provider_config("agent"))),
+                model(Some(provider_config("model")))
+            )
+            .await
+            .as_deref(),
+            Some("agent-key")
+        );
+
+        // A malformed config isn't skipped
+        let malformed = json!({ "provider": { "credential": 42 } });
+        assert!(
+            registry
+                .resolve(&agent(Some(malformed)), &model(None))
+                .await
+                .is_err()
+        );
+    }
+}
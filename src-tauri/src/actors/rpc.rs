@@ -0,0 +1,661 @@
+//! Calls to actors of other peers.
+//!
+//! kameo's remote `ask` can't reach the actors behind a peer's gateway, so a call is
+//! made of `tell`s in both directions: the caller sends a signed [`RpcRequest`] to the
+//! peer's gateway, which answers with one or more signed [`RpcResponse`]s to the
+//! caller's gateway. Both carry the id of the call as the task id of the signed
+//! envelope. The caller stops waiting at the deadline of the call, and dropping an
+//! [`RpcCall`] or [`RpcStream`] before it completes cancels the work on the peer.
+
+use std::{any::Any, collections::BTreeMap, future::Future, marker::PhantomData, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use kameo::{
+    prelude::{ActorRef as LocalActorRef, *},
+    remote::RemoteMessage,
+};
+use libp2p::PeerId;
+use rig::completion::ToolDefinition;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        Askable,
+        agents::StreamedPart,
+        gateway::{GATEWAY_ACTOR, GatewayActor},
+    },
+    entities::PeerAgent,
+    error::{AppError, Result},
+    keys::Signed,
+    utils::get_gateway_id,
+};
+
+/// Time a peer has to answer a call, unless the caller sets another deadline
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(60);
+/// Longest a peer may keep our actors busy with a single call
+const MAX_DEADLINE: Duration = Duration::from_secs(15 * 60);
+/// Items of a streamed reply that may arrive ahead of a missing one
+const MAX_OUT_OF_ORDER: usize = 256;
+
+/// Reply channel of a call we are waiting on
+pub(crate) type ReplySender = mpsc::UnboundedSender<Box<dyn Any + Send>>;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcRequest<T> {
+    /// The caller gives up on the call after this
+    pub deadline: DateTime<Utc>,
+    pub body: T,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type", content = "data")]
+pub enum RpcResponse<R> {
+    /// The only reply of a call
+    Reply(R),
+    /// One item of a streamed reply. Items can overtake each other on the way.
+    #[serde(rename_all = "camelCase")]
+    Item { seq: u64, item: R },
+    /// Sent after the last item of a streamed reply
+    #[serde(rename_all = "camelCase")]
+    End { items: u64 },
+    /// The call failed, or the peer refused it
+    Error(AppError),
+}
+
+/// Asks a peer to stop working on the call with the task id of the envelope
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RpcCancel;
+
+/// A call of ours that a peer hasn't finished answering
+pub struct PendingCall {
+    pub peer_id: PeerId,
+    pub replies: ReplySender,
+}
+
+/// Time left until a peer's deadline, bounded by [`MAX_DEADLINE`]
+fn time_left(deadline: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    (deadline - now)
+        .to_std()
+        .unwrap_or(Duration::ZERO)
+        .min(MAX_DEADLINE)
+}
+
+/// Calls a local actor of the peer behind `gateway` and waits for the reply
+pub async fn call<A, T>(
+    gateway: &RemoteActorRef<GatewayActor>,
+    msg: T,
+    timeout: Duration,
+) -> Result<<A as Askable<T>>::ActualReply>
+where
+    A: Askable<T>,
+    T: Send + Sync + Serialize + 'static,
+    GatewayActor: RemoteMessage<Signed<RpcRequest<T>>> + Message<Signed<RpcRequest<T>>>,
+{
+    RpcCall::start(gateway, msg, timeout).await?.reply().await
+}
+
+/// Calls a local actor of the peer behind `gateway` that replies with a stream of items
+pub async fn call_stream<T, I>(
+    gateway: &RemoteActorRef<GatewayActor>,
+    msg: T,
+    timeout: Duration,
+) -> Result<RpcStream<I>>
+where
+    T: Send + Sync + Serialize + 'static,
+    I: DeserializeOwned + Send + 'static,
+    GatewayActor: RemoteMessage<Signed<RpcRequest<T>>> + Message<Signed<RpcRequest<T>>>,
+{
+    Ok(RpcStream {
+        call: RpcCall::start(gateway, msg, timeout).await?,
+        buffer: StreamBuffer::default(),
+    })
+}
+
+/// A call waiting for its reply, which is cancelled when dropped unfinished
+pub struct RpcCall<R> {
+    id: Uuid,
+    peer_id: PeerId,
+    gateway: RemoteActorRef<GatewayActor>,
+    replies: mpsc::UnboundedReceiver<Box<dyn Any + Send>>,
+    deadline: tokio::time::Instant,
+    finished: bool,
+    _reply: PhantomData<fn() -> R>,
+}
+
+impl<R: Send + 'static> RpcCall<R> {
+    async fn start<T>(
+        gateway: &RemoteActorRef<GatewayActor>,
+        msg: T,
+        timeout: Duration,
+    ) -> Result<Self>
+    where
+        T: Send + Sync + Serialize + 'static,
+        GatewayActor: RemoteMessage<Signed<RpcRequest<T>>> + Message<Signed<RpcRequest<T>>>,
+    {
+        let peer_id = *gateway
+            .id()
+            .peer_id()
+            .ok_or_else(|| AppError::internal("Remote gateway has no peer id"))?;
+        let local = GATEWAY_ACTOR
+            .get()
+            .ok_or_else(|| AppError::internal("Gateway is not running"))?;
+        let id = Uuid::new_v4();
+        let (tx, rx) = mpsc::unbounded_channel();
+        local
+            .ask(RegisterCall {
+                id,
+                peer_id,
+                replies: tx,
+            })
+            .await?;
+        let call = Self {
+            id,
+            peer_id,
+            gateway: gateway.clone(),
+            replies: rx,
+            deadline: tokio::time::Instant::now() + timeout,
+            finished: false,
+            _reply: PhantomData,
+        };
+        // The request is signed with the id we registered, so that replies find the call
+        let request = Signed::with_task(
+            RpcRequest {
+                deadline: Utc::now()
+                    + chrono::Duration::from_std(timeout.min(MAX_DEADLINE)).unwrap_or_default(),
+                body: msg,
+            },
+            Some(id),
+        );
+        gateway.tell(&request).await?;
+        Ok(call)
+    }
+
+    async fn recv(&mut self) -> Result<RpcResponse<R>> {
+        let reply = tokio::time::timeout_at(self.deadline, self.replies.recv())
+            .await
+            .map_err(|_| {
+                AppError::timeout(format!(
+                    "Call {} to peer {} timed out",
+                    self.id, self.peer_id
+                ))
+            })?
+            .ok_or_else(|| AppError::internal(format!("Call {} was dropped", self.id)))?;
+        match reply.downcast::<RpcResponse<R>>() {
+            Ok(reply) => Ok(*reply),
+            Err(_) => Err(AppError::internal(format!(
+                "Invalid reply type received for call {}",
+                self.id
+            ))),
+        }
+    }
+
+    /// Waits for the single reply of the call
+    pub async fn reply(mut self) -> Result<R> {
+        let res = match self.recv().await? {
+            RpcResponse::Reply(reply) => Ok(reply),
+            RpcResponse::Error(e) => Err(e),
+            RpcResponse::Item { .. } | RpcResponse::End { .. } => {
+                Err(AppError::validation(format!(
+                    "Peer {} streamed the reply to call {}",
+                    self.peer_id, self.id
+                )))
+            }
+        };
+        self.finished = true;
+        res
+    }
+}
+
+impl<R> Drop for RpcCall<R> {
+    fn drop(&mut self) {
+        let id = self.id;
+        let peer_id = self.peer_id;
+        let gateway = self.gateway.clone();
+        let finished = self.finished;
+        // Without a runtime, e.g. when the call outlives it on shutdown, there is no
+        // one left to tell
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            debug!(%peer_id, %id, "No runtime left to cancel call");
+            return;
+        };
+        runtime.spawn(async move {
+            if !finished && let Err(e) = gateway.tell(&Signed::with_task(RpcCancel, Some(id))).await
+            {
+                debug!(%peer_id, %id, "Failed to cancel call: {e}");
+            }
+            if let Some(local) = GATEWAY_ACTOR.get() {
+                local.tell(ForgetCall(id)).await.ok();
+            }
+        });
+    }
+}
+
+/// The items of a streamed reply, in the order the peer sent them
+pub struct RpcStream<I> {
+    call: RpcCall<I>,
+    buffer: StreamBuffer<I>,
+}
+
+impl<I: Send + 'static> RpcStream<I> {
+    /// The next item, or `None` once the peer sent all of them
+    pub async fn next(&mut self) -> Option<Result<I>> {
+        loop {
+            if let Some(item) = self.buffer.pop() {
+                return Some(Ok(item));
+            }
+            if self.call.finished || self.buffer.is_done() {
+                self.call.finished = true;
+                return None;
+            }
+            let res = match self.call.recv().await {
+                Ok(RpcResponse::Item { seq, item }) => self.buffer.push(seq, item),
+                Ok(RpcResponse::End { items }) => {
+                    self.buffer.finish(items);
+                    Ok(())
+                }
+                Ok(RpcResponse::Reply(item)) => {
+                    // A single reply is a stream of one item
+                    self.buffer.finish(1);
+                    self.buffer.push(0, item)
+                }
+                Ok(RpcResponse::Error(e)) | Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                self.call.finished = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Puts the items of a streamed reply back in order
+struct StreamBuffer<I> {
+    next: u64,
+    end: Option<u64>,
+    pending: BTreeMap<u64, I>,
+}
+
+impl<I> Default for StreamBuffer<I> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            end: None,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<I> StreamBuffer<I> {
+    fn push(&mut self, seq: u64, item: I) -> Result<()> {
+        // Items we already passed on were sent twice
+        if seq < self.next {
+            return Ok(());
+        }
+        if self.pending.len() >= MAX_OUT_OF_ORDER {
+            return Err(AppError::resource_limit_exceeded(format!(
+                "Item {} of the stream is missing",
+                self.next
+            )));
+        }
+        self.pending.insert(seq, item);
+        Ok(())
+    }
+
+    fn finish(&mut self, items: u64) {
+        self.end = Some(items);
+    }
+
+    fn pop(&mut self) -> Option<I> {
+        let item = self.pending.remove(&self.next)?;
+        self.next += 1;
+        Some(item)
+    }
+
+    fn is_done(&self) -> bool {
+        self.end.is_some_and(|end| self.next >= end)
+    }
+}
+
+/// Registers a call with the local gateway before its request is sent
+pub struct RegisterCall {
+    pub id: Uuid,
+    pub peer_id: PeerId,
+    pub replies: ReplySender,
+}
+
+impl Message<RegisterCall> for GatewayActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: RegisterCall,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        // Calls whose caller went away without forgetting them
+        self.pending_calls
+            .retain(|_, call| !call.replies.is_closed());
+        self.pending_calls.insert(
+            msg.id,
+            PendingCall {
+                peer_id: msg.peer_id,
+                replies: msg.replies,
+            },
+        );
+    }
+}
+
+pub struct ForgetCall(pub Uuid);
+
+impl Message<ForgetCall> for GatewayActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: ForgetCall,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.pending_calls.remove(&msg.0);
+    }
+}
+
+/// Sent by the task answering a call of a peer once it's done
+pub struct CallFinished {
+    pub peer_id: PeerId,
+    pub id: Uuid,
+}
+
+impl Message<CallFinished> for GatewayActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: CallFinished,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.serving.remove(&(msg.peer_id, msg.id));
+    }
+}
+
+/// A call a peer made to one of our actors
+pub(crate) struct IncomingCall {
+    pub peer_id: PeerId,
+    pub id: Uuid,
+    pub deadline: DateTime<Utc>,
+}
+
+impl GatewayActor {
+    /// Authenticates a call. Calls that can't be answered because we don't know who
+    /// made them or which call it is are dropped.
+    pub(crate) fn accept_call<T: Serialize>(
+        &mut self,
+        msg: &Signed<RpcRequest<T>>,
+    ) -> Option<IncomingCall> {
+        if let Err(e) = self.authenticate(msg) {
+            warn!(peer_id = %msg.client_peer_id(), "Dropping call: {e}");
+            return None;
+        }
+        let Some(id) = msg.task_id().copied() else {
+            warn!(peer_id = %msg.client_peer_id(), "Dropping call without an id");
+            return None;
+        };
+        Some(IncomingCall {
+            peer_id: *msg.client_peer_id(),
+            id,
+            deadline: msg.inner().deadline,
+        })
+    }
+
+    /// Whether we are still answering a call with the id. Peers choose the ids of their
+    /// calls, so a second call with the same id is dropped instead of taking its place.
+    fn is_serving(&self, peer_id: PeerId, id: Uuid) -> bool {
+        let serving = self
+            .serving
+            .get(&(peer_id, id))
+            .is_some_and(|task| !task.is_finished());
+        if serving {
+            warn!(%peer_id, %id, "Dropping call with the id of a call we are answering");
+        }
+        serving
+    }
+
+    /// Answers a call with the output of `work`, or with an error once the deadline
+    /// of the call passed
+    pub(crate) fn serve<R, F>(
+        &mut self,
+        actor_ref: LocalActorRef<Self>,
+        call: IncomingCall,
+        work: F,
+    ) where
+        R: Serialize + Send + Sync + 'static,
+        F: Future<Output = Result<R>> + Send + 'static,
+        GatewayActor: RemoteMessage<Signed<RpcResponse<R>>> + Message<Signed<RpcResponse<R>>>,
+    {
+        let IncomingCall {
+            peer_id,
+            id,
+            deadline,
+        } = call;
+        if self.is_serving(peer_id, id) {
+            return;
+        }
+        let timeout = time_left(deadline, Utc::now());
+        let task = tokio::spawn(async move {
+            let response = match tokio::time::timeout(timeout, work).await {
+                Ok(Ok(reply)) => RpcResponse::Reply(reply),
+                Ok(Err(e)) => RpcResponse::Error(e),
+                Err(_) => {
+                    RpcResponse::Error(AppError::timeout(format!("Call {id} passed its deadline")))
+                }
+            };
+            match peer_gateway(&peer_id).await {
+                Ok(gateway) => respond(&gateway, &peer_id, id, response).await,
+                Err(e) => warn!(%peer_id, %id, "Failed to answer call: {e}"),
+            }
+            actor_ref.tell(CallFinished { peer_id, id }).await.ok();
+        });
+        self.serving.insert((peer_id, id), task.abort_handle());
+    }
+
+    /// Answers a call with the items of `stream`, as long as the deadline of the call
+    /// hasn't passed
+    pub(crate) fn serve_stream<I, S>(
+        &mut self,
+        actor_ref: LocalActorRef<Self>,
+        call: IncomingCall,
+        stream: S,
+    ) where
+        I: Serialize + Send + Sync + 'static,
+        S: Stream<Item = Result<I>> + Send + 'static,
+        GatewayActor: RemoteMessage<Signed<RpcResponse<I>>> + Message<Signed<RpcResponse<I>>>,
+    {
+        let IncomingCall {
+            peer_id,
+            id,
+            deadline,
+        } = call;
+        if self.is_serving(peer_id, id) {
+            return;
+        }
+        let deadline = tokio::time::Instant::now() + time_left(deadline, Utc::now());
+        let task = tokio::spawn(async move {
+            let gateway = match peer_gateway(&peer_id).await {
+                Ok(gateway) => gateway,
+                Err(e) => {
+                    warn!(%peer_id, %id, "Failed to answer call: {e}");
+                    actor_ref.tell(CallFinished { peer_id, id }).await.ok();
+                    return;
+                }
+            };
+            let mut stream = std::pin::pin!(stream);
+            let mut seq = 0;
+            loop {
+                let response = match tokio::time::timeout_at(deadline, stream.next()).await {
+                    Ok(Some(Ok(item))) => RpcResponse::Item { seq, item },
+                    Ok(Some(Err(e))) => RpcResponse::Error(e),
+                    Ok(None) => RpcResponse::End { items: seq },
+                    Err(_) => RpcResponse::Error(AppError::timeout(format!(
+                        "Call {id} passed its deadline"
+                    ))),
+                };
+                let last = !matches!(response, RpcResponse::Item { .. });
+                respond(&gateway, &peer_id, id, response).await;
+                if last {
+                    break;
+                }
+                seq += 1;
+            }
+            actor_ref.tell(CallFinished { peer_id, id }).await.ok();
+        });
+        self.serving.insert((peer_id, id), task.abort_handle());
+    }
+
+    /// Passes a reply on to the call waiting for it
+    fn complete_call<R: Serialize + Send + 'static>(&mut self, msg: Signed<RpcResponse<R>>) {
+        if let Err(e) = self.authenticate(&msg) {
+            warn!(peer_id = %msg.client_peer_id(), "Dropping reply: {e}");
+            return;
+        }
+        let Some(id) = msg.task_id().copied() else {
+            return;
+        };
+        let Some(call) = self.pending_calls.get(&id) else {
+            debug!(%id, "Dropping reply to a call that is no longer pending");
+            return;
+        };
+        // Only the peer we called may answer
+        if call.peer_id != *msg.client_peer_id() {
+            warn!(peer_id = %msg.client_peer_id(), %id, "Dropping reply from the wrong peer");
+            return;
+        }
+        if call.replies.send(Box::new(msg.into_inner())).is_err() {
+            self.pending_calls.remove(&id);
+        }
+    }
+}
+
+/// Looks up the gateway of a peer
+pub(crate) async fn peer_gateway(peer_id: &PeerId) -> Result<RemoteActorRef<GatewayActor>> {
+    RemoteActorRef::<GatewayActor>::lookup(&get_gateway_id(peer_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Gateway of peer", peer_id))
+}
+
+async fn respond<R>(
+    gateway: &RemoteActorRef<GatewayActor>,
+    peer_id: &PeerId,
+    id: Uuid,
+    response: RpcResponse<R>,
+) where
+    R: Serialize + Send + Sync + 'static,
+    GatewayActor: RemoteMessage<Signed<RpcResponse<R>>> + Message<Signed<RpcResponse<R>>>,
+{
+    if let Err(e) = gateway.tell(&Signed::with_task(response, Some(id))).await {
+        warn!(%peer_id, %id, "Failed to answer call: {e}");
+    }
+}
+
+// Stops the work on a call the peer no longer waits for. Keyed by peer, so that peers
+// can only cancel their own calls.
+#[remote_message("5d1c7f0e-2b6a-4f8e-9a3d-6c1b2e4f7a01")]
+impl Message<Signed<RpcCancel>> for GatewayActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Signed<RpcCancel>,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if self.authenticate(&msg).is_err() {
+            return;
+        }
+        if let Some(id) = msg.task_id()
+            && let Some(task) = self.serving.remove(&(*msg.client_peer_id(), *id))
+        {
+            debug!(peer_id = %msg.client_peer_id(), %id, "Call cancelled by peer");
+            task.abort();
+        }
+    }
+}
+
+macro_rules! rpc_response_impl {
+    ($reply:ty, $id:literal) => {
+        #[remote_message($id)]
+        impl Message<Signed<RpcResponse<$reply>>> for GatewayActor {
+            type Reply = ();
+
+            async fn handle(
+                &mut self,
+                msg: Signed<RpcResponse<$reply>>,
+                _ctx: &mut Context<Self, Self::Reply>,
+            ) -> Self::Reply {
+                self.complete_call(msg);
+            }
+        }
+    };
+}
+
+rpc_response_impl!(Result<String>, "5d1c7f0e-2b6a-4f8e-9a3d-6c1b2e4f7a02");
+rpc_response_impl!(Vec<ToolDefinition>, "5d1c7f0e-2b6a-4f8e-9a3d-6c1b2e4f7a03");
+rpc_response_impl!(StreamedPart, "5d1c7f0e-2b6a-4f8e-9a3d-6c1b2e4f7a04");
+rpc_response_impl!(
+    Result<Vec<PeerAgent>>,
+    "5d1c7f0e-2b6a-4f8e-9a3d-6c1b2e4f7a05"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_buffer_reorders() {
+        let mut buffer = StreamBuffer::default();
+        buffer.push(1, "b").unwrap();
+        assert_eq!(buffer.pop(), None);
+
+        buffer.push(0, "a").unwrap();
+        buffer.finish(3);
+        assert_eq!(buffer.pop(), Some("a"));
+        assert_eq!(buffer.pop(), Some("b"));
+        assert!(!buffer.is_done());
+
+        // Sent twice
+        buffer.push(0, "a").unwrap();
+        buffer.push(2, "c").unwrap();
+        assert_eq!(buffer.pop(), Some("c"));
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_done());
+    }
+
+    #[test]
+    fn test_stream_buffer_limits_gaps() {
+        let mut buffer = StreamBuffer::default();
+        for seq in 1..=MAX_OUT_OF_ORDER as u64 {
+            buffer.push(seq, seq).unwrap();
+        }
+
+        assert!(buffer.push(MAX_OUT_OF_ORDER as u64 + 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_time_left() {
+        let now = Utc::now();
+
+        assert_eq!(
+            time_left(now + chrono::Duration::seconds(5), now),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            time_left(now - chrono::Duration::seconds(5), now),
+            Duration::ZERO
+        );
+        assert_eq!(
+            time_left(now + chrono::Duration::days(1), now),
+            MAX_DEADLINE
+        );
+    }
+}
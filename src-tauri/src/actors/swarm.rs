@@ -1,11 +1,18 @@
+use chrono::Utc;
 use kameo_actors::message_bus::Publish;
-use std::{collections::HashSet, io, num::NonZero, time::Duration};
-use tracing::debug;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    num::NonZero,
+    time::Duration,
+};
+use tracing::{debug, warn};
 
 use futures_util::StreamExt;
 use kameo::{
     Actor,
-    prelude::*,
+    prelude::{ActorRef as LocalActorRef, *},
     remote::{
         ActorSwarmBehaviour, ActorSwarmBehaviourEvent, ActorSwarmEvent, ActorSwarmHandler,
         SwarmBehaviour,
@@ -14,11 +21,25 @@ use kameo::{
 use libp2p::{
     Multiaddr, PeerId, Swarm, TransportError,
     core::ConnectedPoint,
-    dcutr, identify, ping, relay,
+    dcutr, identify,
+    multiaddr::Protocol,
+    ping, relay, rendezvous, request_response,
     swarm::{ConnectionId, NetworkBehaviour, SwarmEvent},
 };
 
-use crate::state::ActorManager;
+use crate::{
+    actors::{
+        database::{
+            DatabaseActor, MarkP2pNodeOffline, PatchP2pNodeMetadata, RecordDiscoveredPeer,
+            RecordPeerIdentity, UpdatePeerLink,
+        },
+        discovery::{DISCOVERY_INTERVAL, Discovery},
+        telemetry::{LinkQuality, PeerTelemetry},
+        transfer::{FileTransferCodec, TransferNetwork},
+    },
+    entities::PeerIdWrapper,
+    state::ActorManager,
+};
 
 #[derive(NetworkBehaviour)]
 pub struct Behaviour {
@@ -27,6 +48,8 @@ pub struct Behaviour {
     pub identify: identify::Behaviour,
     pub relay_client: relay::client::Behaviour,
     pub ping: ping::Behaviour,
+    pub rendezvous: rendezvous::client::Behaviour,
+    pub file_transfer: request_response::Behaviour<FileTransferCodec>,
 }
 
 impl SwarmBehaviour for Behaviour {
@@ -200,6 +223,8 @@ pub async fn swarm_handler(
     swarm: &mut Swarm<Behaviour>,
     handler: &mut ActorSwarmHandler,
     actors: ActorManager,
+    mut discovery: Discovery,
+    mut transfers: TransferNetwork,
 ) {
     loop {
         tokio::select! {
@@ -217,11 +242,23 @@ pub async fn swarm_handler(
             }
         }
     }
+    discovery.dial_rendezvous_points(swarm);
+    let mut discovery_interval = tokio::time::interval(DISCOVERY_INTERVAL);
     loop {
         tokio::select! {
             Some(cmd) = handler.next_command() => handler.handle_command(swarm, cmd),
+            Some(cmd) = transfers.next_command() => transfers.handle_command(swarm, cmd),
+            _ = discovery_interval.tick() => discovery.discover(swarm),
             Some(event) = swarm.next() => {
                 match event {
+                    SwarmEvent::NewListenAddr { address, .. } => {
+                        // Addresses on a relay are reachable from outside our network, which
+                        // is what we register at rendezvous points
+                        if address.iter().any(|p| matches!(p, Protocol::P2pCircuit)) {
+                            swarm.add_external_address(address.clone());
+                        }
+                        tracing::info!(%address, "Listening on address");
+                    }
                     SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, num_established, cause } => {
                         actors.bus.tell(Publish(ConnectionClosed {
                             peer_id: peer_id.clone(),
@@ -237,8 +274,21 @@ pub async fn swarm_handler(
                     )) => {
                         debug!("✅ Relay accepted our reservation request. We are now publicly reachable.");
                     }
-                    SwarmEvent::Behaviour(BehaviourEvent::Dcutr(dcutr::Event{ result, .. })) => {
+                    SwarmEvent::Behaviour(BehaviourEvent::Dcutr(dcutr::Event{ remote_peer_id, result })) => {
                         debug!("Hole punching event: {:?}", result);
+                        actors.bus.tell(Publish(PeerTelemetry::HolePunch {
+                            peer_id: remote_peer_id,
+                            succeeded: result.is_ok(),
+                        })).await.ok();
+                    }
+                    SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result, .. })) => {
+                        actors.bus.tell(Publish(PeerTelemetry::Ping {
+                            peer_id: peer,
+                            rtt: result.ok(),
+                        })).await.ok();
+                    }
+                    SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. })) => {
+                        actors.bus.tell(Publish(PeerTelemetry::Identified { peer_id, info })).await.ok();
                     }
                     SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, num_established, concurrent_dial_errors, established_in } => {
                         actors.bus.tell(Publish(ConnectionEstablished {
@@ -249,6 +299,7 @@ pub async fn swarm_handler(
                             established_in,
                         })).await.ok();
                         debug!("--> Connected to peer: {}. Concurrent dial errors: {:?}", peer_id, concurrent_dial_errors);
+                        discovery.on_connection_established(swarm, &peer_id);
                     }
                     SwarmEvent::Behaviour(BehaviourEvent::Kameo(ActorSwarmBehaviourEvent::Kademlia(event))) => {
                         handler.handle_event(swarm, ActorSwarmEvent::Behaviour(Box::new(ActorSwarmBehaviourEvent::Kademlia(event))));
@@ -257,7 +308,15 @@ pub async fn swarm_handler(
                         handler.handle_event(swarm, ActorSwarmEvent::Behaviour(Box::new(ActorSwarmBehaviourEvent::RequestResponse(event))));
                     }
                     SwarmEvent::Behaviour(BehaviourEvent::Kameo(ActorSwarmBehaviourEvent::Mdns(event))) => {
-                        handler.handle_event(swarm, ActorSwarmEvent::Behaviour(Box::new(ActorSwarmBehaviourEvent::Mdns(event))));
+                        if discovery.on_mdns_event(&event) {
+                            handler.handle_event(swarm, ActorSwarmEvent::Behaviour(Box::new(ActorSwarmBehaviourEvent::Mdns(event))));
+                        }
+                    }
+                    SwarmEvent::Behaviour(BehaviourEvent::Rendezvous(event)) => {
+                        discovery.on_rendezvous_event(swarm, event);
+                    }
+                    SwarmEvent::Behaviour(BehaviourEvent::FileTransfer(event)) => {
+                        transfers.on_event(event);
                     }
                     _ => {},
                 }
@@ -276,6 +335,12 @@ pub struct ConnectionEstablished {
     established_in: Duration,
 }
 
+impl ConnectionEstablished {
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+}
+
 #[derive(Clone)]
 pub struct ConnectionClosed {
     peer_id: PeerId,
@@ -284,9 +349,33 @@ pub struct ConnectionClosed {
     num_established: u32,
 }
 
+/// Tracks the connections to other peers and the quality of the links to them, which
+/// it keeps up to date in `p2p_nodes`
 #[derive(Actor)]
 pub struct ConnectionManager {
+    pub db: LocalActorRef<DatabaseActor>,
     pub active_connections: HashSet<PeerId>,
+    pub links: HashMap<PeerId, LinkQuality>,
+}
+
+impl ConnectionManager {
+    async fn save_link(&self, peer_id: PeerId) {
+        let Some(link) = self.links.get(&peer_id) else {
+            return;
+        };
+        let res = self
+            .db
+            .ask(UpdatePeerLink {
+                peer_id: PeerIdWrapper(peer_id),
+                quality: link.score(),
+                latency_ms: link.latency_ms(),
+                relayed: link.is_relayed(),
+            })
+            .await;
+        if let Err(e) = res {
+            warn!(%peer_id, "Failed to save connection quality: {e}");
+        }
+    }
 }
 
 pub struct GetInactiveConnections {
@@ -317,6 +406,24 @@ impl Message<ConnectionEstablished> for ConnectionManager {
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
         self.active_connections.insert(msg.peer_id);
+        self.links
+            .entry(msg.peer_id)
+            .or_default()
+            .connected(msg.connection_id, msg.endpoint.is_relayed());
+        if msg.num_established.get() == 1 {
+            let res = self
+                .db
+                .ask(RecordDiscoveredPeer {
+                    peer_id: PeerIdWrapper(msg.peer_id),
+                    multiaddr: msg.endpoint.get_remote_address().to_string(),
+                    source: "connection".into(),
+                })
+                .await;
+            if let Err(e) = res {
+                warn!(peer_id = %msg.peer_id, "Failed to record connected peer: {e}");
+            }
+        }
+        self.save_link(msg.peer_id).await;
     }
 }
 
@@ -328,6 +435,76 @@ impl Message<ConnectionClosed> for ConnectionManager {
         msg: ConnectionClosed,
         _ctx: &mut Context<Self, Self::Reply>,
     ) -> Self::Reply {
-        self.active_connections.remove(&msg.peer_id);
+        let last = msg.num_established == 0;
+        if let Some(link) = self.links.get_mut(&msg.peer_id) {
+            link.closed(msg.connection_id, last);
+        }
+        self.save_link(msg.peer_id).await;
+        if last {
+            self.active_connections.remove(&msg.peer_id);
+            let res = self
+                .db
+                .ask(MarkP2pNodeOffline(PeerIdWrapper(msg.peer_id)))
+                .await;
+            if let Err(e) = res {
+                warn!(peer_id = %msg.peer_id, "Failed to mark peer offline: {e}");
+            }
+        }
+    }
+}
+
+impl Message<PeerTelemetry> for ConnectionManager {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: PeerTelemetry,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        match msg {
+            PeerTelemetry::Ping { peer_id, rtt } => {
+                self.links.entry(peer_id).or_default().ping(rtt);
+                self.save_link(peer_id).await;
+            }
+            PeerTelemetry::Identified { peer_id, info } => {
+                let protocols: Vec<String> =
+                    info.protocols.iter().map(ToString::to_string).collect();
+                let is_relay = info.protocols.contains(&relay::HOP_PROTOCOL_NAME);
+                let listen_addrs: Vec<String> =
+                    info.listen_addrs.iter().map(ToString::to_string).collect();
+                let res = self
+                    .db
+                    .ask(RecordPeerIdentity {
+                        peer_id: PeerIdWrapper(peer_id),
+                        protocols,
+                        is_relay,
+                        metadata: json!({
+                            "agentVersion": info.agent_version,
+                            "protocolVersion": info.protocol_version,
+                            "listenAddrs": listen_addrs,
+                            "observedAddr": info.observed_addr.to_string(),
+                        }),
+                    })
+                    .await;
+                if let Err(e) = res {
+                    warn!(%peer_id, "Failed to record peer identity: {e}");
+                }
+            }
+            PeerTelemetry::HolePunch { peer_id, succeeded } => {
+                let res = self
+                    .db
+                    .ask(PatchP2pNodeMetadata(
+                        PeerIdWrapper(peer_id),
+                        json!({
+                            "holePunch": if succeeded { "succeeded" } else { "failed" },
+                            "holePunchedAt": Utc::now(),
+                        }),
+                    ))
+                    .await;
+                if let Err(e) = res {
+                    warn!(%peer_id, "Failed to record hole punching result: {e}");
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,170 @@
+//! Quality of our links to other peers.
+//!
+//! The swarm handler publishes ping, identify and hole punching results next to the
+//! connection events, and the [`ConnectionManager`](super::swarm::ConnectionManager)
+//! folds them into a [`LinkQuality`] per peer that is persisted to `p2p_nodes`.
+
+use std::{collections::HashSet, time::Duration};
+
+use libp2p::{PeerId, identify, swarm::ConnectionId};
+
+/// How often nodes that stopped answering are marked offline
+pub const STALE_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Time without a ping after which a node counts as offline. Connected peers are
+/// pinged every 15 seconds.
+pub const STALE_AFTER: chrono::Duration = chrono::Duration::minutes(3);
+
+/// Weight of a new round trip time in the moving average
+const RTT_SMOOTHING: f64 = 0.2;
+/// Weight of a ping outcome in the moving success rate
+const RELIABILITY_SMOOTHING: f64 = 0.1;
+/// Round trip time that halves the latency part of the score
+const REFERENCE_RTT_MS: f64 = 200.0;
+/// Share of the stability lost whenever the peer drops its last connection
+const DISCONNECT_PENALTY: f64 = 0.2;
+/// Share of the lost stability regained with every answered ping
+const STABILITY_RECOVERY: f64 = 0.05;
+/// Relayed links depend on a third node and are slower than direct ones
+const RELAYED_FACTOR: f64 = 0.8;
+
+/// Published by the swarm handler for the protocols that tell us about a peer
+#[derive(Clone)]
+pub enum PeerTelemetry {
+    /// `rtt` is `None` when the ping failed
+    Ping {
+        peer_id: PeerId,
+        rtt: Option<Duration>,
+    },
+    Identified {
+        peer_id: PeerId,
+        info: identify::Info,
+    },
+    /// Result of trying to upgrade a relayed connection to a direct one
+    HolePunch { peer_id: PeerId, succeeded: bool },
+}
+
+/// Running measurements of the link to a peer. Kept across reconnects so that peers
+/// that keep dropping out score lower.
+#[derive(Debug, Clone)]
+pub struct LinkQuality {
+    rtt_ms: Option<f64>,
+    /// Moving share of answered pings
+    reliability: f64,
+    /// Lowered by disconnects and recovered by answered pings
+    stability: f64,
+    direct: HashSet<ConnectionId>,
+    relayed: HashSet<ConnectionId>,
+}
+
+impl Default for LinkQuality {
+    fn default() -> Self {
+        Self {
+            rtt_ms: None,
+            reliability: 1.0,
+            stability: 1.0,
+            direct: HashSet::new(),
+            relayed: HashSet::new(),
+        }
+    }
+}
+
+impl LinkQuality {
+    pub fn connected(&mut self, connection_id: ConnectionId, relayed: bool) {
+        if relayed {
+            self.relayed.insert(connection_id);
+        } else {
+            self.direct.insert(connection_id);
+        }
+    }
+
+    /// `last` is whether it was the last connection to the peer
+    pub fn closed(&mut self, connection_id: ConnectionId, last: bool) {
+        self.direct.remove(&connection_id);
+        self.relayed.remove(&connection_id);
+        if last {
+            self.stability *= 1.0 - DISCONNECT_PENALTY;
+        }
+    }
+
+    pub fn ping(&mut self, rtt: Option<Duration>) {
+        match rtt {
+            Some(rtt) => {
+                let rtt_ms = rtt.as_secs_f64() * 1000.0;
+                self.rtt_ms = Some(match self.rtt_ms {
+                    Some(avg) => avg + RTT_SMOOTHING * (rtt_ms - avg),
+                    None => rtt_ms,
+                });
+                self.reliability += RELIABILITY_SMOOTHING * (1.0 - self.reliability);
+                self.stability += STABILITY_RECOVERY * (1.0 - self.stability);
+            }
+            None => self.reliability -= RELIABILITY_SMOOTHING * self.reliability,
+        }
+    }
+
+    /// Whether every connection to the peer goes through a relay
+    pub fn is_relayed(&self) -> bool {
+        self.direct.is_empty() && !self.relayed.is_empty()
+    }
+
+    pub fn latency_ms(&self) -> Option<i64> {
+        self.rtt_ms.map(|rtt| rtt.round() as i64)
+    }
+
+    /// Score between 0.0 and 1.0 combining latency, answered pings, disconnects and
+    /// whether the link is relayed
+    pub fn score(&self) -> f64 {
+        let responsiveness = match self.rtt_ms {
+            Some(rtt) => (1.0 / (1.0 + rtt / REFERENCE_RTT_MS) + self.reliability) / 2.0,
+            None => self.reliability,
+        };
+        let relayed = if self.is_relayed() {
+            RELAYED_FACTOR
+        } else {
+            1.0
+        };
+        (responsiveness * self.stability * relayed).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_prefers_fast_direct_links() {
+        let mut fast = LinkQuality::default();
+        fast.connected(ConnectionId::new_unchecked(1), false);
+        fast.ping(Some(Duration::from_millis(20)));
+        let mut slow = LinkQuality::default();
+        slow.connected(ConnectionId::new_unchecked(2), false);
+        slow.ping(Some(Duration::from_millis(800)));
+        let mut relayed = fast.clone();
+        relayed.closed(ConnectionId::new_unchecked(1), false);
+        relayed.connected(ConnectionId::new_unchecked(3), true);
+
+        assert!(fast.score() > slow.score());
+        assert!(fast.score() > relayed.score());
+        assert!(relayed.is_relayed());
+        assert_eq!(fast.latency_ms(), Some(20));
+    }
+
+    #[test]
+    fn test_score_drops_with_failures_and_disconnects() {
+        let mut link = LinkQuality::default();
+        link.connected(ConnectionId::new_unchecked(1), false);
+        link.ping(Some(Duration::from_millis(50)));
+        let healthy = link.score();
+
+        link.ping(None);
+        link.ping(None);
+        let failing = link.score();
+        link.closed(ConnectionId::new_unchecked(1), true);
+
+        assert!(failing < healthy);
+        assert!(link.score() < failing);
+        for _ in 0..200 {
+            link.ping(Some(Duration::from_millis(50)));
+        }
+        assert!((link.score() - healthy).abs() < 0.01);
+    }
+}
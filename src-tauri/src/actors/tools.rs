@@ -1,12 +1,12 @@
 use macros::askable;
 use rig::tool::ToolError;
 use serde::ser::StdError;
-use std::{borrow::Cow, collections::HashMap, io, ops::Deref, path::PathBuf, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, ops::Deref, sync::Arc};
 
 use color_eyre::eyre::eyre;
 use futures_util::{StreamExt, future::BoxFuture};
 use kameo::prelude::*;
-use schemars::{JsonSchema, schema::RootSchema, schema_for};
+use schemars::schema::RootSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::error::{AppError, LossyError, Result};
@@ -52,9 +52,42 @@ impl Message<GetTools> for ToolExecutorActor {
     }
 }
 
+impl Message<RegisterTools> for ToolExecutorActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: RegisterTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        for tool in msg.0 {
+            self.tools.insert(tool.name(), ToolWrapper(tool));
+        }
+    }
+}
+
+impl Message<UnregisterTools> for ToolExecutorActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: UnregisterTools,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        for name in msg.0 {
+            self.tools.remove(&name);
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct GetTools;
 
+/// Adds tools, replacing the ones of the same name
+pub struct RegisterTools(pub Vec<Arc<dyn ToolDyn>>);
+
+pub struct UnregisterTools(pub Vec<Cow<'static, str>>);
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct UseTool {
     pub name: Cow<'static, str>,
@@ -197,67 +230,3 @@ impl<T: Tool> ToolDyn for T {
         }
     }
 }
-
-pub struct ReadFile;
-
-#[derive(Serialize, Deserialize, JsonSchema)]
-pub struct ReadFileArgs {
-    pub path: PathBuf,
-}
-
-impl Tool for ReadFile {
-    type Error = io::Error;
-    type Args = ReadFileArgs;
-    type Output = String;
-
-    const NAME: &'static str = "read_file";
-
-    fn definition(&self, _prompt: String) -> ToolDefinition {
-        ToolDefinition {
-            name: Self::NAME.into(),
-            description: "Reads the entire contents of a file on disk.".into(),
-            params: schema_for!(ReadFileArgs),
-            returns: Some(schema_for!(String)),
-        }
-    }
-
-    fn call(
-        &self,
-        args: Self::Args,
-    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
-        // Use an `async move` block to create a 'static future
-        async move { Ok(tokio::fs::read_to_string(&args.path).await?) }
-    }
-}
-
-pub struct WriteFile;
-
-#[derive(Serialize, Deserialize, JsonSchema)]
-pub struct WriteFileArgs {
-    pub path: PathBuf,
-    pub content: String,
-}
-
-impl Tool for WriteFile {
-    type Error = io::Error;
-    type Args = WriteFileArgs;
-    type Output = ();
-
-    const NAME: &'static str = "write_file";
-
-    fn definition(&self, _prompt: String) -> ToolDefinition {
-        ToolDefinition {
-            name: Self::NAME.into(),
-            description: "Reads the entire contents of a file on disk.".into(),
-            params: schema_for!(WriteFileArgs),
-            returns: None,
-        }
-    }
-
-    fn call(
-        &self,
-        args: Self::Args,
-    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
-        async move { Ok(tokio::fs::write(&args.path, &args.content).await?) }
-    }
-}
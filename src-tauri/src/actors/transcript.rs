@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use rig::{
+    OneOrMany,
+    completion::Message as RigMessage,
+    message::{AssistantContent, ToolCall, ToolResultContent, UserContent},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::types::Json;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        agents::{AgentResponseEvent, StreamedPart},
+        database::{DatabaseActor, GetAgent, ListMessages, ListParticipants},
+    },
+    entities::{
+        CreateMessage, Message as ChatMessage, MessageFilter, MessageStatus, ParticipantFilter,
+        ParticipantType,
+    },
+    error::{AppError, Result},
+};
+
+/// Subscribes to agent responses on the bus and persists every finished turn as a `Message`.
+#[derive(Actor)]
+pub struct TranscriptActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    /// Tool activity of the turns being streamed, by turn id
+    pub turns: HashMap<Uuid, TurnBuffer>,
+}
+
+/// Tool activity collected while an agent turn is being streamed
+#[derive(Default)]
+pub struct TurnBuffer {
+    pub tool_calls: Vec<ToolCall>,
+    pub tool_results: Vec<ToolResultRecord>,
+}
+
+/// A tool result as it is stored in a message's metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolResultRecord {
+    pub tool_call_id: String,
+    pub tool_name: String,
+    pub tool_output: Value,
+}
+
+/// Resolves the participant id an agent speaks as in conversations.
+pub async fn agent_participant_id(
+    db: &LocalActorRef<DatabaseActor>,
+    agent_id: Uuid,
+) -> Result<Uuid> {
+    if let Some(participant_id) = db
+        .ask(GetAgent(agent_id))
+        .await?
+        .and_then(|agent| agent.participant_id)
+    {
+        return Ok(participant_id);
+    }
+    db.ask(ListParticipants(ParticipantFilter {
+        type_: Some(ParticipantType::Agent(agent_id)),
+        limit: Some(1),
+        ..Default::default()
+    }))
+    .await?
+    .into_iter()
+    .next()
+    .map(|p| p.id)
+    .ok_or_else(|| AppError::not_found("Participant for agent", agent_id))
+}
+
+/// Loads the stored messages of a conversation and turns them into the history
+/// for the next `AgentRequest` of the given agent.
+pub async fn load_history(
+    db: &LocalActorRef<DatabaseActor>,
+    conversation_id: Uuid,
+    agent_participant_id: Uuid,
+    exclude_message_id: Option<Uuid>,
+) -> Result<Vec<RigMessage>> {
+    let messages = db
+        .ask(ListMessages(MessageFilter {
+            conversation_id: Some(conversation_id),
+            ..Default::default()
+        }))
+        .await?;
+    Ok(messages_to_history(
+        messages
+            .iter()
+            .filter(|m| Some(m.id) != exclude_message_id),
+        agent_participant_id,
+    ))
+}
+
+/// Converts stored messages into rig chat history from the point of view of one agent.
+/// Messages sent by the agent become assistant turns (including their tool calls),
+/// everything else becomes user input.
+pub fn messages_to_history<'a>(
+    messages: impl IntoIterator<Item = &'a ChatMessage>,
+    agent_participant_id: Uuid,
+) -> Vec<RigMessage> {
+    let mut history = Vec::new();
+    for message in messages {
+        if message.sender_id != agent_participant_id {
+            history.push(RigMessage::user(message.content.clone()));
+            continue;
+        }
+        if message.status == MessageStatus::Failed {
+            continue;
+        }
+
+        let tool_calls: Vec<ToolCall> = message
+            .refs
+            .as_ref()
+            .and_then(|refs| serde_json::from_value(refs.0.clone()).ok())
+            .unwrap_or_default();
+        let mut tool_results: HashMap<String, ToolResultRecord> = message
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.0.get("toolResults").cloned())
+            .and_then(|results| serde_json::from_value::<Vec<ToolResultRecord>>(results).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|result| (result.tool_call_id.clone(), result))
+            .collect();
+
+        if !tool_calls.is_empty() {
+            let mut results = Vec::new();
+            for call in &tool_calls {
+                let Some(result) = tool_results.remove(&call.id) else {
+                    continue;
+                };
+                let output = match result.tool_output {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                results.push(UserContent::tool_result(
+                    call.id.clone(),
+                    OneOrMany::one(ToolResultContent::text(output)),
+                ));
+            }
+            if let Ok(content) = OneOrMany::many(
+                tool_calls
+                    .iter()
+                    .cloned()
+                    .map(AssistantContent::ToolCall)
+                    .collect::<Vec<_>>(),
+            ) {
+                history.push(RigMessage::Assistant { content });
+            }
+            if let Ok(content) = OneOrMany::many(results) {
+                history.push(RigMessage::User { content });
+            }
+        }
+        if !message.content.is_empty() {
+            history.push(RigMessage::assistant(message.content.clone()));
+        }
+    }
+    history
+}
+
+impl Message<AgentResponseEvent> for TranscriptActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: AgentResponseEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let key = msg.turn_id;
+        let (content, status, tool_calls, metadata) = match msg.response {
+            StreamedPart::Token(_) | StreamedPart::ContextTruncated(_) => return,
+            StreamedPart::ToolCall(tool_call) => {
+                self.turns
+                    .entry(key)
+                    .or_default()
+                    .tool_calls
+                    .push(tool_call);
+                return;
+            }
+            StreamedPart::ToolResult {
+                tool_call_id,
+                tool_name,
+                tool_output,
+            } => {
+                self.turns
+                    .entry(key)
+                    .or_default()
+                    .tool_results
+                    .push(ToolResultRecord {
+                        tool_call_id,
+                        tool_name,
+                        tool_output,
+                    });
+                return;
+            }
+            StreamedPart::EndOfStream {
+                full_response,
+                tool_calls,
+            } => {
+                let turn = self.turns.remove(&key).unwrap_or_default();
+                let tool_calls = if turn.tool_calls.is_empty() {
+                    tool_calls
+                } else {
+                    turn.tool_calls
+                };
+                (
+                    full_response,
+                    MessageStatus::Sent,
+                    tool_calls,
+                    json!({ "agentId": msg.agent_id, "toolResults": turn.tool_results }),
+                )
+            }
+            StreamedPart::Error(error) => {
+                let turn = self.turns.remove(&key).unwrap_or_default();
+                (
+                    String::new(),
+                    MessageStatus::Failed,
+                    turn.tool_calls,
+                    json!({
+                        "agentId": msg.agent_id,
+                        "toolResults": turn.tool_results,
+                        "error": error,
+                    }),
+                )
+            }
+        };
+
+        let sender_id = match agent_participant_id(&self.db, msg.agent_id).await {
+            Ok(id) => id,
+            Err(e) => {
+                warn!(agent_id = %msg.agent_id, "Dropping agent response without a participant: {e}");
+                return;
+            }
+        };
+        let refs = (!tool_calls.is_empty())
+            .then(|| serde_json::to_value(&tool_calls).ok().map(Json))
+            .flatten();
+        let res = self
+            .db
+            .ask(CreateMessage {
+                conversation_id: msg.conversation_id,
+                sender_id,
+                parent_message_id: None,
+                content,
+                status,
+                refs,
+                metadata: Some(Json(metadata)),
+                reply_to_id: None,
+                branch_conversation_id: None,
+                parent_id: None,
+                workspace_id: (!msg.workspace_id.is_nil()).then_some(msg.workspace_id),
+            })
+            .await;
+        if let Err(e) = res {
+            error!(conversation_id = %msg.conversation_id, "Failed to persist agent response: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use rig::message::ToolFunction;
+
+    use super::*;
+
+    fn message(sender_id: Uuid, content: &str) -> ChatMessage {
+        ChatMessage {
+            id: Uuid::new_v4(),
+            conversation_id: Uuid::nil(),
+            sender_id,
+            parent_message_id: None,
+            content: content.to_string(),
+            status: MessageStatus::Sent,
+            refs: None,
+            metadata: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            reply_to_id: None,
+            branch_conversation_id: None,
+            parent_id: None,
+            workspace_id: None,
+        }
+    }
+
+    #[test]
+    fn test_messages_to_history_pairs_tool_results() {
+        let agent = Uuid::new_v4();
+        let user = Uuid::new_v4();
+        let mut reply = message(agent, "The files say hello and bye");
+        let read_file = |id: &str, path: &str| ToolCall {
+            id: id.to_string(),
+            function: ToolFunction {
+                name: "read_file".to_string(),
+                arguments: json!({ "path": path }),
+            },
+        };
+        reply.refs = Some(Json(json!([
+            read_file("call-1", "hello.txt"),
+            read_file("call-2", "bye.txt")
+        ])));
+        // Results are stored in the order the tools finished in
+        reply.metadata = Some(Json(json!({
+            "toolResults": [
+                { "toolCallId": "call-2", "toolName": "read_file", "toolOutput": "bye" },
+                { "toolCallId": "call-1", "toolName": "read_file", "toolOutput": "hello" }
+            ]
+        })));
+        let mut failed = message(agent, "");
+        failed.status = MessageStatus::Failed;
+
+        let history = messages_to_history(
+            &[message(user, "What do the files say?"), reply, failed],
+            agent,
+        );
+
+        assert_eq!(history.len(), 4);
+        assert!(matches!(history[0], RigMessage::User { .. }));
+        assert!(matches!(history[1], RigMessage::Assistant { .. }));
+        assert!(matches!(history[3], RigMessage::Assistant { .. }));
+        let RigMessage::User { content } = &history[2] else {
+            panic!("Expected the tool results");
+        };
+        let result = |id: &str, output: &str| {
+            UserContent::tool_result(id, OneOrMany::one(ToolResultContent::text(output)))
+        };
+        assert_eq!(
+            content.iter().cloned().collect::<Vec<_>>(),
+            vec![result("call-1", "hello"), result("call-2", "bye")]
+        );
+    }
+}
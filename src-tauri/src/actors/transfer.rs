@@ -0,0 +1,1180 @@
+//! Files sent to and received from peers.
+//!
+//! A shared file is described by a [`FileManifest`]: the hash of the file and the
+//! hashes of the chunks it's split into. The receiver asks the sender for the manifest
+//! and then for the chunks it doesn't have yet, by hash, over the [`PROTOCOL`] stream
+//! protocol. Every chunk and the whole file are verified against the manifest. Downloads
+//! stopped by a disconnect are resumed when the peer connects again, and files larger
+//! than the receiver's [`TransferPolicy`] allows are refused.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::{StreamExt, stream};
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use kameo_actors::message_bus::Publish;
+use libp2p::{
+    PeerId, StreamProtocol, Swarm,
+    futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    request_response::{self, OutboundRequestId, ResponseChannel},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use tokio::{
+    fs,
+    io::{AsyncReadExt as _, AsyncSeekExt, AsyncWriteExt as _},
+    sync::{mpsc, oneshot},
+    task::AbortHandle,
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        SystemEventBus,
+        database::{
+            CreateFileTransfer, DatabaseActor, GetFileTransfer, GetTransferPolicy,
+            ListFileTransfers, MarkFileTransferChunkDone, UpdateFileTransferStatus,
+        },
+        swarm::{Behaviour, ConnectionEstablished},
+    },
+    entities::{
+        FileTransfer, FileTransferFilter, PeerIdWrapper, SettingsType, TransferDirection,
+        TransferStatus,
+    },
+    error::{AppError, Result},
+    storage::db::DatabaseManager,
+    utils::get_data_dir,
+};
+
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/evo-design/file-transfer/1.0.0");
+/// Name of the global setting holding the [`TransferPolicy`] as JSON
+pub const TRANSFER_POLICY_SETTING: &str = "file_transfer";
+/// Size of the chunks shared files are split into
+pub const CHUNK_SIZE: u64 = 256 * 1024;
+/// Largest chunk size accepted in a peer's manifest
+const MAX_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+/// Enough for the chunk hashes of a file of about 30 GiB
+const MAX_MANIFEST_SIZE: usize = 8 * 1024 * 1024;
+/// Chunks requested from a peer at the same time
+const PARALLEL_CHUNKS: usize = 4;
+/// Time a peer has to answer a file request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What we accept from peers. Changes apply to the downloads started afterwards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TransferPolicy {
+    /// Largest file we download from a peer, in bytes
+    pub max_file_size: u64,
+    /// Where received files are saved, `downloads` in the data directory if not set
+    pub download_dir: Option<PathBuf>,
+}
+
+impl Default for TransferPolicy {
+    fn default() -> Self {
+        Self {
+            max_file_size: 512 * 1024 * 1024,
+            download_dir: None,
+        }
+    }
+}
+
+impl TransferPolicy {
+    /// Loads the policy from the `file_transfer` setting. An invalid policy is logged
+    /// and replaced by the default one.
+    pub async fn load(db: &DatabaseManager) -> Result<Self> {
+        Ok(
+            match db.get_global_setting(TRANSFER_POLICY_SETTING).await? {
+                Some(setting) => serde_json::from_str(&setting.value).unwrap_or_else(|e| {
+                    warn!("Invalid file transfer setting, using the default policy: {e}");
+                    Self::default()
+                }),
+                None => Self::default(),
+            },
+        )
+    }
+
+    /// Stores the policy in the `file_transfer` setting
+    pub async fn save(&self, db: &DatabaseManager) -> Result<()> {
+        db.set_global_setting(
+            TRANSFER_POLICY_SETTING,
+            &serde_json::to_string(self)?,
+            SettingsType::Object,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub fn download_dir(&self) -> PathBuf {
+        self.download_dir
+            .clone()
+            .unwrap_or_else(|| get_data_dir().join("downloads"))
+    }
+
+    /// Whether we accept the file of a manifest
+    pub fn check(&self, manifest: &FileManifest) -> Result<()> {
+        if manifest.size > self.max_file_size {
+            return Err(AppError::resource_limit_exceeded(format!(
+                "File {} has {} bytes, files of at most {} bytes are accepted",
+                manifest.name, manifest.size, self.max_file_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Describes a shared file to the peers that download it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileManifest {
+    pub file_id: Uuid,
+    pub name: String,
+    pub size: u64,
+    /// SHA-256 of the whole file, hex encoded
+    pub hash: String,
+    pub chunk_size: u64,
+    /// Hashes of the chunks, in the order they appear in the file
+    pub chunks: Vec<String>,
+}
+
+impl FileManifest {
+    /// Reads and hashes a file. Blocks until the whole file is read.
+    pub fn build(file_id: Uuid, path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| AppError::validation(format!("{} is not a file", path.display())))?;
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut chunks = Vec::new();
+        let mut size = 0;
+        let mut buf = Vec::with_capacity(CHUNK_SIZE as usize);
+        loop {
+            buf.clear();
+            (&mut file).take(CHUNK_SIZE).read_to_end(&mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+            hasher.update(&buf);
+            chunks.push(chunk_hash(&buf));
+            size += buf.len() as u64;
+        }
+        Ok(Self {
+            file_id,
+            name,
+            size,
+            hash: format!("{:x}", hasher.finalize()),
+            chunk_size: CHUNK_SIZE,
+            chunks,
+        })
+    }
+
+    fn from_transfer(transfer: &FileTransfer) -> Self {
+        Self {
+            file_id: transfer.file_id,
+            name: transfer.file_name.clone(),
+            size: transfer.size as u64,
+            hash: transfer.hash.clone(),
+            chunk_size: transfer.chunk_size as u64,
+            chunks: transfer.chunks.0.clone(),
+        }
+    }
+
+    /// Checks that a peer's manifest is consistent before anything is downloaded
+    pub fn validate(&self) -> Result<()> {
+        if self.chunk_size == 0 || self.chunk_size > MAX_CHUNK_SIZE {
+            return Err(AppError::validation(format!(
+                "Chunks of file {} have an invalid size of {} bytes",
+                self.file_id, self.chunk_size
+            )));
+        }
+        if self.size > i64::MAX as u64
+            || self.chunks.len() as u64 != chunk_count(self.size, self.chunk_size)
+        {
+            return Err(AppError::validation(format!(
+                "File {} has {} chunks, which doesn't match its size",
+                self.file_id,
+                self.chunks.len()
+            )));
+        }
+        if !is_hash(&self.hash) || !self.chunks.iter().all(|hash| is_hash(hash)) {
+            return Err(AppError::validation(format!(
+                "File {} has invalid hashes",
+                self.file_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn chunk_count(size: u64, chunk_size: u64) -> u64 {
+    size.div_ceil(chunk_size)
+}
+
+/// Length of the chunk at `index`, the last chunk may be shorter than the others
+fn chunk_len(size: u64, chunk_size: u64, index: u64) -> u64 {
+    size.saturating_sub(index * chunk_size).min(chunk_size)
+}
+
+pub fn chunk_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64
+        && hash
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn transferred_bytes(transfer: &FileTransfer) -> u64 {
+    transfer
+        .done_chunks
+        .iter()
+        .map(|&index| {
+            chunk_len(
+                transfer.size as u64,
+                transfer.chunk_size as u64,
+                index as u64,
+            )
+        })
+        .sum()
+}
+
+/// The name a received file is saved under. Peers choose the name, so anything that
+/// would place the file outside the download directory is dropped.
+fn safe_file_name(name: &str, file_id: Uuid) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    match name {
+        "" | "." | ".." => file_id.to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// `name` in `dir`, with a number added when a file of that name exists already
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let name = Path::new(name);
+    let stem = name
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = name
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned());
+    (1..)
+        .map(|n| match &extension {
+            Some(ext) => dir.join(format!("{stem} ({n}).{ext}")),
+            None => dir.join(format!("{stem} ({n})")),
+        })
+        .find(|path| !path.exists())
+        .expect("there is a free file name")
+}
+
+/// SHA-256 of a file, hex encoded. Blocks until the whole file is read.
+fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum FileRequest {
+    Manifest {
+        file_id: Uuid,
+    },
+    /// Chunks are asked for by hash, so that a peer can't send one in place of another
+    Chunk {
+        file_id: Uuid,
+        hash: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum FileResponse {
+    Manifest(FileManifest),
+    Chunk(Vec<u8>),
+    /// The peer won't send the file, e.g. because it wasn't shared with us
+    Refused(String),
+}
+
+const MANIFEST_TAG: u8 = 0;
+const CHUNK_TAG: u8 = 1;
+const REFUSED_TAG: u8 = 2;
+
+/// Requests are length prefixed JSON. Responses start with a tag byte, followed by the
+/// length prefixed manifest as JSON, the raw chunk, or the reason of the refusal.
+#[derive(Debug, Clone, Default)]
+pub struct FileTransferCodec;
+
+#[async_trait]
+impl request_response::Codec for FileTransferCodec {
+    type Protocol = StreamProtocol;
+    type Request = FileRequest;
+    type Response = FileResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<FileRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let bytes = read_frame(io, MAX_REQUEST_SIZE).await?;
+        serde_json::from_slice(&bytes).map_err(invalid_data)
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<FileResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut tag = [0];
+        io.read_exact(&mut tag).await?;
+        match tag[0] {
+            MANIFEST_TAG => serde_json::from_slice(&read_frame(io, MAX_MANIFEST_SIZE).await?)
+                .map(FileResponse::Manifest)
+                .map_err(invalid_data),
+            CHUNK_TAG => Ok(FileResponse::Chunk(
+                read_frame(io, MAX_CHUNK_SIZE as usize).await?,
+            )),
+            REFUSED_TAG => String::from_utf8(read_frame(io, MAX_REQUEST_SIZE).await?)
+                .map(FileResponse::Refused)
+                .map_err(invalid_data),
+            tag => Err(invalid_data(format!("Unknown response tag {tag}"))),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        req: FileRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &serde_json::to_vec(&req)?).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        res: FileResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let (tag, payload) = match res {
+            FileResponse::Manifest(manifest) => (MANIFEST_TAG, serde_json::to_vec(&manifest)?),
+            FileResponse::Chunk(data) => (CHUNK_TAG, data),
+            FileResponse::Refused(reason) => (REFUSED_TAG, reason.into_bytes()),
+        };
+        io.write_all(&[tag]).await?;
+        write_frame(io, &payload).await
+    }
+}
+
+async fn read_frame<T>(io: &mut T, max_len: usize) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut len = [0; 4];
+    io.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(invalid_data(format!(
+            "Frame of {len} bytes is larger than the limit of {max_len} bytes"
+        )));
+    }
+    let mut buf = vec![0; len];
+    io.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn write_frame<T>(io: &mut T, payload: &[u8]) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    let len = u32::try_from(payload.len()).map_err(invalid_data)?;
+    io.write_all(&len.to_be_bytes()).await?;
+    io.write_all(payload).await?;
+    io.flush().await
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+pub enum TransferCommand {
+    Request {
+        peer_id: PeerId,
+        request: FileRequest,
+        reply: oneshot::Sender<Result<FileResponse>>,
+    },
+    Respond {
+        channel: ResponseChannel<FileResponse>,
+        response: FileResponse,
+    },
+}
+
+/// The swarm handler's side of the file transfers. It sends our requests, and answers
+/// the requests of peers from the outgoing transfers.
+pub struct TransferNetwork {
+    db: LocalActorRef<DatabaseActor>,
+    bus: LocalActorRef<SystemEventBus>,
+    commands: mpsc::UnboundedReceiver<TransferCommand>,
+    /// Sends the answers to peers' requests back to the swarm handler
+    responder: mpsc::UnboundedSender<TransferCommand>,
+    pending: HashMap<OutboundRequestId, oneshot::Sender<Result<FileResponse>>>,
+}
+
+impl TransferNetwork {
+    pub fn new(
+        db: LocalActorRef<DatabaseActor>,
+        bus: LocalActorRef<SystemEventBus>,
+        commands: mpsc::UnboundedReceiver<TransferCommand>,
+        responder: mpsc::UnboundedSender<TransferCommand>,
+    ) -> Self {
+        Self {
+            db,
+            bus,
+            commands,
+            responder,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub async fn next_command(&mut self) -> Option<TransferCommand> {
+        self.commands.recv().await
+    }
+
+    pub fn handle_command(&mut self, swarm: &mut Swarm<Behaviour>, cmd: TransferCommand) {
+        let behaviour = &mut swarm.behaviour_mut().file_transfer;
+        match cmd {
+            TransferCommand::Request {
+                peer_id,
+                request,
+                reply,
+            } => {
+                let request_id = behaviour.send_request(&peer_id, request);
+                self.pending.insert(request_id, reply);
+            }
+            TransferCommand::Respond { channel, response } => {
+                if behaviour.send_response(channel, response).is_err() {
+                    debug!("Peer stopped waiting for the answer to its file request");
+                }
+            }
+        }
+    }
+
+    pub fn on_event(&mut self, event: request_response::Event<FileRequest, FileResponse>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                ..
+            } => {
+                let db = self.db.clone();
+                let bus = self.bus.clone();
+                let responder = self.responder.clone();
+                tokio::spawn(async move {
+                    let response = serve(&db, &bus, peer, request).await.unwrap_or_else(|e| {
+                        debug!(%peer, "Refused file request of peer: {e}");
+                        FileResponse::Refused(e.to_string())
+                    });
+                    responder
+                        .send(TransferCommand::Respond { channel, response })
+                        .ok();
+                });
+            }
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            } => {
+                if let Some(reply) = self.pending.remove(&request_id) {
+                    reply.send(Ok(response)).ok();
+                }
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            } => {
+                if let Some(reply) = self.pending.remove(&request_id) {
+                    reply
+                        .send(Err(AppError::external_service(format!(
+                            "File request to peer {peer} failed: {error}"
+                        ))))
+                        .ok();
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!(%peer, "File request of peer failed: {error}");
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+}
+
+/// Sends a file request to a peer through the swarm handler
+async fn request(
+    swarm: &mpsc::UnboundedSender<TransferCommand>,
+    peer_id: PeerId,
+    request: FileRequest,
+) -> Result<FileResponse> {
+    let (reply, rx) = oneshot::channel();
+    swarm
+        .send(TransferCommand::Request {
+            peer_id,
+            request,
+            reply,
+        })
+        .map_err(|_| AppError::internal("The swarm handler stopped"))?;
+    match rx
+        .await
+        .map_err(|_| AppError::internal("The swarm handler dropped a file request"))??
+    {
+        FileResponse::Refused(reason) => Err(AppError::authorization(format!(
+            "Peer {peer_id} refused to send the file: {reason}"
+        ))),
+        response => Ok(response),
+    }
+}
+
+/// Answers the request of a peer. Only files shared with the peer are sent.
+async fn serve(
+    db: &LocalActorRef<DatabaseActor>,
+    bus: &LocalActorRef<SystemEventBus>,
+    peer_id: PeerId,
+    request: FileRequest,
+) -> Result<FileResponse> {
+    let file_id = match &request {
+        FileRequest::Manifest { file_id } | FileRequest::Chunk { file_id, .. } => *file_id,
+    };
+    let transfer = db
+        .ask(ListFileTransfers(FileTransferFilter {
+            peer_id: Some(PeerIdWrapper(peer_id)),
+            file_id: Some(file_id),
+            direction: Some(TransferDirection::Outgoing),
+            ..Default::default()
+        }))
+        .await?
+        .into_iter()
+        .find(|transfer| transfer.status != TransferStatus::Cancelled)
+        .ok_or_else(|| AppError::not_found("File", file_id))?;
+    let FileRequest::Chunk { hash, .. } = request else {
+        return Ok(FileResponse::Manifest(FileManifest::from_transfer(
+            &transfer,
+        )));
+    };
+    let index = transfer
+        .chunks
+        .iter()
+        .position(|chunk| *chunk == hash)
+        .ok_or_else(|| AppError::not_found("Chunk", &hash))? as u64;
+    let chunk_size = transfer.chunk_size as u64;
+    let mut file = fs::File::open(&transfer.file_path).await?;
+    file.seek(SeekFrom::Start(index * chunk_size)).await?;
+    let mut data = vec![0; chunk_len(transfer.size as u64, chunk_size, index) as usize];
+    file.read_exact(&mut data).await?;
+    if chunk_hash(&data) != hash {
+        return Err(AppError::validation(format!(
+            "File {} changed since it was shared",
+            transfer.file_name
+        )));
+    }
+    let mut transfer = db
+        .ask(MarkFileTransferChunkDone(transfer.id, index as i64))
+        .await?;
+    if transfer.done_chunks.len() == transfer.chunks.len()
+        && transfer.status != TransferStatus::Completed
+    {
+        transfer = db
+            .ask(UpdateFileTransferStatus {
+                id: transfer.id,
+                status: TransferStatus::Completed,
+                error: None,
+                file_path: None,
+            })
+            .await?;
+    }
+    publish_progress(bus, &transfer).await;
+    Ok(FileResponse::Chunk(data))
+}
+
+/// Published whenever a transfer advances or changes status
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileTransferProgress {
+    pub transfer_id: Uuid,
+    pub file_id: Uuid,
+    pub peer_id: PeerIdWrapper,
+    pub direction: TransferDirection,
+    pub status: TransferStatus,
+    pub file_name: String,
+    pub transferred_bytes: u64,
+    pub size: u64,
+    pub error: Option<String>,
+}
+
+impl From<&FileTransfer> for FileTransferProgress {
+    fn from(transfer: &FileTransfer) -> Self {
+        Self {
+            transfer_id: transfer.id,
+            file_id: transfer.file_id,
+            peer_id: transfer.peer_id.clone(),
+            direction: transfer.direction,
+            status: transfer.status,
+            file_name: transfer.file_name.clone(),
+            transferred_bytes: transferred_bytes(transfer),
+            size: transfer.size as u64,
+            error: transfer.error.clone(),
+        }
+    }
+}
+
+async fn publish_progress(bus: &LocalActorRef<SystemEventBus>, transfer: &FileTransfer) {
+    bus.tell(Publish(FileTransferProgress::from(transfer)))
+        .await
+        .ok();
+}
+
+fn new_transfer(
+    manifest: &FileManifest,
+    peer_id: PeerId,
+    direction: TransferDirection,
+    file_path: &Path,
+) -> FileTransfer {
+    FileTransfer {
+        id: Uuid::new_v4(),
+        file_id: manifest.file_id,
+        peer_id: PeerIdWrapper(peer_id),
+        direction,
+        status: TransferStatus::Pending,
+        file_name: manifest.name.clone(),
+        file_path: file_path.to_string_lossy().into_owned(),
+        size: manifest.size as i64,
+        hash: manifest.hash.clone(),
+        chunk_size: manifest.chunk_size as i64,
+        chunks: Json(manifest.chunks.clone()),
+        done_chunks: Json(Vec::new()),
+        error: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+#[derive(Actor)]
+pub struct FileTransferActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub bus: LocalActorRef<SystemEventBus>,
+    /// Sends our requests to the swarm handler
+    pub swarm: mpsc::UnboundedSender<TransferCommand>,
+    /// Downloads that are running, by transfer
+    pub downloads: HashMap<Uuid, AbortHandle>,
+}
+
+impl FileTransferActor {
+    fn start_download(&mut self, transfer: FileTransfer, actor_ref: LocalActorRef<Self>) {
+        if self
+            .downloads
+            .get(&transfer.id)
+            .is_some_and(|download| !download.is_finished())
+        {
+            return;
+        }
+        let transfer_id = transfer.id;
+        let db = self.db.clone();
+        let bus = self.bus.clone();
+        let swarm = self.swarm.clone();
+        let task = tokio::spawn(async move {
+            let result = download(&db, &bus, &swarm, transfer).await;
+            actor_ref
+                .tell(DownloadFinished {
+                    transfer_id,
+                    result,
+                })
+                .await
+                .ok();
+        });
+        self.downloads.insert(transfer_id, task.abort_handle());
+    }
+}
+
+/// Lets peers download a file. Replies with the manifest once the file is hashed.
+pub struct ShareFile {
+    pub path: PathBuf,
+    pub peer_ids: Vec<PeerId>,
+    /// Id of the file in our messages, a new one is used if not set
+    pub file_id: Option<Uuid>,
+}
+
+impl Message<ShareFile> for FileTransferActor {
+    type Reply = DelegatedReply<Result<FileManifest>>;
+
+    async fn handle(
+        &mut self,
+        msg: ShareFile,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let db = self.db.clone();
+        let bus = self.bus.clone();
+        tokio::spawn(async move {
+            let res = share(&db, &bus, msg).await;
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+async fn share(
+    db: &LocalActorRef<DatabaseActor>,
+    bus: &LocalActorRef<SystemEventBus>,
+    msg: ShareFile,
+) -> Result<FileManifest> {
+    let file_id = msg.file_id.unwrap_or_else(Uuid::new_v4);
+    let path = msg.path.clone();
+    let manifest = tokio::task::spawn_blocking(move || FileManifest::build(file_id, &path))
+        .await
+        .map_err(|e| AppError::internal(format!("Failed to hash {}: {e}", msg.path.display())))??;
+    for peer_id in msg.peer_ids {
+        let transfer = db
+            .ask(CreateFileTransfer(new_transfer(
+                &manifest,
+                peer_id,
+                TransferDirection::Outgoing,
+                &msg.path,
+            )))
+            .await?;
+        publish_progress(bus, &transfer).await;
+    }
+    Ok(manifest)
+}
+
+/// Downloads a file a peer shared with us, or resumes the download. Replies with the
+/// transfer once the manifest is received and accepted by our policy.
+pub struct DownloadFile {
+    pub peer_id: PeerId,
+    pub file_id: Uuid,
+}
+
+impl Message<DownloadFile> for FileTransferActor {
+    type Reply = DelegatedReply<Result<FileTransfer>>;
+
+    async fn handle(
+        &mut self,
+        msg: DownloadFile,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let (delegated, sender) = ctx.reply_sender();
+        let db = self.db.clone();
+        let swarm = self.swarm.clone();
+        let actor_ref = ctx.actor_ref();
+        tokio::spawn(async move {
+            let res = prepare_download(&db, &swarm, msg).await;
+            if let Ok(transfer) = &res
+                && transfer.status != TransferStatus::Completed
+            {
+                actor_ref.tell(StartDownload(transfer.clone())).await.ok();
+            }
+            if let Some(tx) = sender {
+                tx.send(res);
+            }
+        });
+        delegated
+    }
+}
+
+/// The incoming transfer of a file, which is created from the peer's manifest the first
+/// time the file is downloaded
+async fn prepare_download(
+    db: &LocalActorRef<DatabaseActor>,
+    swarm: &mpsc::UnboundedSender<TransferCommand>,
+    msg: DownloadFile,
+) -> Result<FileTransfer> {
+    let DownloadFile { peer_id, file_id } = msg;
+    let existing = db
+        .ask(ListFileTransfers(FileTransferFilter {
+            peer_id: Some(PeerIdWrapper(peer_id)),
+            file_id: Some(file_id),
+            direction: Some(TransferDirection::Incoming),
+            ..Default::default()
+        }))
+        .await?
+        .into_iter()
+        .next();
+    if let Some(transfer) = existing {
+        return Ok(transfer);
+    }
+    let FileResponse::Manifest(manifest) =
+        request(swarm, peer_id, FileRequest::Manifest { file_id }).await?
+    else {
+        return Err(AppError::external_service(format!(
+            "Peer {peer_id} didn't answer with the manifest of file {file_id}"
+        )));
+    };
+    if manifest.file_id != file_id {
+        return Err(AppError::validation(format!(
+            "Peer {peer_id} sent the manifest of file {} instead of {file_id}",
+            manifest.file_id
+        )));
+    }
+    manifest.validate()?;
+    let policy = db.ask(GetTransferPolicy).await?;
+    policy.check(&manifest)?;
+    let transfer_id = Uuid::new_v4();
+    let part = policy.download_dir().join(format!("{transfer_id}.part"));
+    let mut transfer = new_transfer(&manifest, peer_id, TransferDirection::Incoming, &part);
+    transfer.id = transfer_id;
+    Ok(db.ask(CreateFileTransfer(transfer)).await?)
+}
+
+/// Fetches the chunks that are missing, then verifies the file and moves it next to
+/// its partial file under the name the peer gave it
+async fn download(
+    db: &LocalActorRef<DatabaseActor>,
+    bus: &LocalActorRef<SystemEventBus>,
+    swarm: &mpsc::UnboundedSender<TransferCommand>,
+    transfer: FileTransfer,
+) -> Result<FileTransfer> {
+    let peer_id = transfer.peer_id.0;
+    let manifest = FileManifest::from_transfer(&transfer);
+    let mut transfer = db
+        .ask(UpdateFileTransferStatus {
+            id: transfer.id,
+            status: TransferStatus::Active,
+            error: None,
+            file_path: None,
+        })
+        .await?;
+    publish_progress(bus, &transfer).await;
+
+    let part = PathBuf::from(&transfer.file_path);
+    let dir = part.parent().map(Path::to_path_buf).unwrap_or_default();
+    fs::create_dir_all(&dir).await?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&part)
+        .await?;
+    file.set_len(manifest.size).await?;
+    let missing: Vec<u64> = (0..manifest.chunks.len() as u64)
+        .filter(|index| !transfer.done_chunks.contains(&(*index as i64)))
+        .collect();
+    let mut chunks = stream::iter(missing)
+        .map(|index| fetch_chunk(swarm, peer_id, &manifest, index))
+        .buffer_unordered(PARALLEL_CHUNKS);
+    while let Some(chunk) = chunks.next().await {
+        let (index, data) = chunk?;
+        file.seek(SeekFrom::Start(index * manifest.chunk_size))
+            .await?;
+        file.write_all(&data).await?;
+        file.flush().await?;
+        transfer = db
+            .ask(MarkFileTransferChunkDone(transfer.id, index as i64))
+            .await?;
+        publish_progress(bus, &transfer).await;
+    }
+    file.sync_all().await?;
+    drop(file);
+
+    let hash = tokio::task::spawn_blocking({
+        let part = part.clone();
+        move || hash_file(&part)
+    })
+    .await
+    .map_err(|e| AppError::internal(format!("Failed to hash {}: {e}", part.display())))??;
+    if hash != manifest.hash {
+        return Err(AppError::validation(format!(
+            "File {} doesn't match the hash of its manifest",
+            manifest.file_id
+        )));
+    }
+    let path = unique_path(&dir, &safe_file_name(&manifest.name, manifest.file_id));
+    fs::rename(&part, &path).await?;
+    let transfer = db
+        .ask(UpdateFileTransferStatus {
+            id: transfer.id,
+            status: TransferStatus::Completed,
+            error: None,
+            file_path: Some(path.to_string_lossy().into_owned()),
+        })
+        .await?;
+    publish_progress(bus, &transfer).await;
+    Ok(transfer)
+}
+
+async fn fetch_chunk(
+    swarm: &mpsc::UnboundedSender<TransferCommand>,
+    peer_id: PeerId,
+    manifest: &FileManifest,
+    index: u64,
+) -> Result<(u64, Vec<u8>)> {
+    let hash = &manifest.chunks[index as usize];
+    let FileResponse::Chunk(data) = request(
+        swarm,
+        peer_id,
+        FileRequest::Chunk {
+            file_id: manifest.file_id,
+            hash: hash.clone(),
+        },
+    )
+    .await?
+    else {
+        return Err(AppError::external_service(format!(
+            "Peer {peer_id} didn't answer with chunk {index} of file {}",
+            manifest.file_id
+        )));
+    };
+    if data.len() as u64 != chunk_len(manifest.size, manifest.chunk_size, index)
+        || chunk_hash(&data) != *hash
+    {
+        return Err(AppError::validation(format!(
+            "Chunk {index} of file {} doesn't match its hash",
+            manifest.file_id
+        )));
+    }
+    Ok((index, data))
+}
+
+struct StartDownload(FileTransfer);
+
+impl Message<StartDownload> for FileTransferActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: StartDownload,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.start_download(msg.0, ctx.actor_ref());
+    }
+}
+
+struct DownloadFinished {
+    transfer_id: Uuid,
+    result: Result<FileTransfer>,
+}
+
+impl Message<DownloadFinished> for FileTransferActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: DownloadFinished,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.downloads.remove(&msg.transfer_id);
+        let Err(e) = msg.result else {
+            return;
+        };
+        // Network errors are retried when the peer connects again, files that don't
+        // match their manifest are not
+        let status = if e.is_retriable() {
+            TransferStatus::Paused
+        } else {
+            TransferStatus::Failed
+        };
+        warn!(transfer_id = %msg.transfer_id, "Download stopped: {e}");
+        let res = self
+            .db
+            .ask(UpdateFileTransferStatus {
+                id: msg.transfer_id,
+                status,
+                error: Some(e.to_string()),
+                file_path: None,
+            })
+            .await;
+        match res {
+            Ok(transfer) => publish_progress(&self.bus, &transfer).await,
+            Err(e) => warn!(transfer_id = %msg.transfer_id, "Failed to update file transfer: {e}"),
+        }
+    }
+}
+
+/// Stops a transfer. An outgoing file can no longer be downloaded by the peer, and a
+/// download that is cancelled continues where it stopped when it's started again.
+pub struct CancelFileTransfer(pub Uuid);
+
+impl Message<CancelFileTransfer> for FileTransferActor {
+    type Reply = Result<FileTransfer>;
+
+    async fn handle(
+        &mut self,
+        msg: CancelFileTransfer,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let id = msg.0;
+        let transfer = self
+            .db
+            .ask(GetFileTransfer(id))
+            .await?
+            .ok_or_else(|| AppError::not_found("FileTransfer", id))?;
+        if transfer.status == TransferStatus::Completed {
+            return Err(AppError::validation(format!(
+                "File transfer {id} is completed already"
+            )));
+        }
+        if let Some(download) = self.downloads.remove(&id) {
+            download.abort();
+        }
+        let transfer = self
+            .db
+            .ask(UpdateFileTransferStatus {
+                id,
+                status: TransferStatus::Cancelled,
+                error: None,
+                file_path: None,
+            })
+            .await?;
+        publish_progress(&self.bus, &transfer).await;
+        Ok(transfer)
+    }
+}
+
+/// Resumes the downloads from a peer that were stopped by a disconnect, or by closing
+/// the app
+impl Message<ConnectionEstablished> for FileTransferActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: ConnectionEstablished,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let peer_id = *msg.peer_id();
+        let res = self
+            .db
+            .ask(ListFileTransfers(FileTransferFilter {
+                peer_id: Some(PeerIdWrapper(peer_id)),
+                direction: Some(TransferDirection::Incoming),
+                ..Default::default()
+            }))
+            .await;
+        match res {
+            Ok(transfers) => {
+                for transfer in transfers
+                    .into_iter()
+                    .filter(|transfer| !transfer.status.is_final())
+                {
+                    self.start_download(transfer, ctx.actor_ref());
+                }
+            }
+            Err(e) => warn!(%peer_id, "Failed to list downloads from peer: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::{futures::io::Cursor, request_response::Codec};
+
+    use super::*;
+
+    #[test]
+    fn test_chunk_len() {
+        assert_eq!(chunk_count(0, 4), 0);
+        assert_eq!(chunk_count(10, 4), 3);
+        assert_eq!(chunk_len(10, 4, 0), 4);
+        assert_eq!(chunk_len(10, 4, 2), 2);
+        assert_eq!(chunk_len(10, 4, 3), 0);
+        assert_eq!(chunk_len(8, 4, 1), 4);
+    }
+
+    #[test]
+    fn test_validate_manifest() {
+        let data = b"hello world";
+        let mut manifest = FileManifest {
+            file_id: Uuid::new_v4(),
+            name: "hello.txt".into(),
+            size: data.len() as u64,
+            hash: chunk_hash(data),
+            chunk_size: 8,
+            chunks: vec![chunk_hash(&data[..8]), chunk_hash(&data[8..])],
+        };
+        assert!(manifest.validate().is_ok());
+
+        manifest.chunks.pop();
+        assert!(manifest.validate().is_err());
+
+        manifest.chunk_size = 0;
+        assert!(manifest.validate().is_err());
+
+        manifest.chunk_size = 16;
+        manifest.hash = "not a hash".into();
+        assert!(manifest.validate().is_err());
+    }
+
+    #[test]
+    fn test_safe_file_name() {
+        let file_id = Uuid::new_v4();
+        assert_eq!(safe_file_name("report.pdf", file_id), "report.pdf");
+        assert_eq!(
+            safe_file_name("../../.ssh/authorized_keys", file_id),
+            "authorized_keys"
+        );
+        assert_eq!(
+            safe_file_name("C:\\Users\\me\\notes.txt", file_id),
+            "notes.txt"
+        );
+        assert_eq!(safe_file_name("..", file_id), file_id.to_string());
+        assert_eq!(safe_file_name("dir/", file_id), file_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_codec_round_trip() {
+        let mut codec = FileTransferCodec;
+        let mut io = Cursor::new(Vec::new());
+        let data = vec![7; 1000];
+        codec
+            .write_response(&PROTOCOL, &mut io, FileResponse::Chunk(data.clone()))
+            .await
+            .unwrap();
+        codec
+            .write_response(&PROTOCOL, &mut io, FileResponse::Refused("nope".into()))
+            .await
+            .unwrap();
+        io.set_position(0);
+
+        let FileResponse::Chunk(read) = codec.read_response(&PROTOCOL, &mut io).await.unwrap()
+        else {
+            panic!("expected a chunk");
+        };
+        assert_eq!(read, data);
+        let FileResponse::Refused(reason) = codec.read_response(&PROTOCOL, &mut io).await.unwrap()
+        else {
+            panic!("expected a refusal");
+        };
+        assert_eq!(reason, "nope");
+
+        let mut oversized = Cursor::new(Vec::new());
+        write_frame(&mut oversized, &vec![0; MAX_REQUEST_SIZE + 1])
+            .await
+            .unwrap();
+        oversized.set_position(0);
+        assert!(codec.read_request(&PROTOCOL, &mut oversized).await.is_err());
+    }
+}
@@ -2,7 +2,12 @@ use kameo::prelude::*;
 use tauri::{AppHandle, Emitter};
 
 use crate::{
-    actors::{agents::AgentResponseEvent, conversation::SendMessage},
+    actors::{
+        agents::AgentResponseEvent, chains::ChainStepEvent, conversation::SendMessage,
+        gateway::PeerApprovalRequested, hosting::PeerAgentResponseEvent,
+        transfer::FileTransferProgress, workflows::WorkflowStepEvent,
+    },
+    entities::Message as ChatMessage,
     keys::Signed,
 };
 
@@ -58,3 +63,75 @@ impl Message<Signed<SendMessage>> for UINotifierActor {
         self.handle.emit("send-message", msg.into_inner()).ok();
     }
 }
+
+impl Message<ChatMessage> for UINotifierActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: ChatMessage,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.handle.emit("new-message", msg).ok();
+    }
+}
+
+impl Message<ChainStepEvent> for UINotifierActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: ChainStepEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.handle.emit("chain-step", msg).ok();
+    }
+}
+
+impl Message<WorkflowStepEvent> for UINotifierActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: WorkflowStepEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.handle.emit("workflow-step", msg).ok();
+    }
+}
+
+impl Message<PeerApprovalRequested> for UINotifierActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: PeerApprovalRequested,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.handle.emit("peer-approval-requested", msg).ok();
+    }
+}
+
+impl Message<PeerAgentResponseEvent> for UINotifierActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: PeerAgentResponseEvent,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.handle.emit("peer-agent-response", msg).ok();
+    }
+}
+
+impl Message<FileTransferProgress> for UINotifierActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: FileTransferProgress,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.handle.emit("file-transfer-progress", msg).ok();
+    }
+}
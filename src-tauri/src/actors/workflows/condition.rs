@@ -0,0 +1,159 @@
+//! Conditions that condition and loop steps evaluate against the workflow state.
+//!
+//! A condition is either a comparison of the value at a JSON pointer with a constant,
+//! e.g. `{ "path": "/review/approved", "op": "eq", "value": true }`, or a combination
+//! of conditions: `{ "all": [...] }`, `{ "any": [...] }` or `{ "not": ... }`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// The path resolves to a value other than `null`
+    Exists,
+    /// The string at the path contains `value`, or the array at the path has `value`
+    /// as an element
+    Contains,
+    /// The value at the path is present and not `false`, `0`, `""`, `[]` or `{}`
+    Truthy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Condition {
+    All {
+        all: Vec<Condition>,
+    },
+    Any {
+        any: Vec<Condition>,
+    },
+    Not {
+        not: Box<Condition>,
+    },
+    Compare {
+        /// JSON pointer into the workflow state, `""` is the whole state
+        #[serde(default)]
+        path: String,
+        op: Operator,
+        #[serde(default)]
+        value: Value,
+    },
+}
+
+impl Condition {
+    pub fn evaluate(&self, state: &Value) -> bool {
+        match self {
+            Condition::All { all } => all.iter().all(|c| c.evaluate(state)),
+            Condition::Any { any } => any.iter().any(|c| c.evaluate(state)),
+            Condition::Not { not } => !not.evaluate(state),
+            Condition::Compare { path, op, value } => {
+                let actual = state.pointer(path).unwrap_or(&Value::Null);
+                compare(actual, *op, value)
+            }
+        }
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+fn compare(actual: &Value, op: Operator, expected: &Value) -> bool {
+    match op {
+        Operator::Eq => equals(actual, expected),
+        Operator::Ne => !equals(actual, expected),
+        Operator::Exists => !actual.is_null(),
+        Operator::Truthy => truthy(actual),
+        Operator::Contains => match (actual, expected) {
+            (Value::String(s), Value::String(needle)) => s.contains(needle.as_str()),
+            (Value::Array(items), _) => items.iter().any(|item| equals(item, expected)),
+            (Value::Object(map), Value::String(key)) => map.contains_key(key),
+            _ => false,
+        },
+        Operator::Gt | Operator::Gte | Operator::Lt | Operator::Lte => {
+            let ordering = match (actual, expected) {
+                (Value::Number(a), Value::Number(b)) => a
+                    .as_f64()
+                    .zip(b.as_f64())
+                    .and_then(|(a, b)| a.partial_cmp(&b)),
+                (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                _ => None,
+            };
+            ordering.is_some_and(|ordering| match op {
+                Operator::Gt => ordering.is_gt(),
+                Operator::Gte => ordering.is_ge(),
+                Operator::Lt => ordering.is_lt(),
+                _ => ordering.is_le(),
+            })
+        }
+    }
+}
+
+/// Equality that treats `1` and `1.0` as the same number
+fn equals(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn condition(value: Value) -> Condition {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_compare() {
+        let state = json!({ "score": 7, "tags": ["draft"], "title": "Quarterly report" });
+
+        assert!(condition(json!({ "path": "/score", "op": "gte", "value": 7.0 })).evaluate(&state));
+        assert!(!condition(json!({ "path": "/score", "op": "lt", "value": 5 })).evaluate(&state));
+        assert!(
+            condition(json!({ "path": "/tags", "op": "contains", "value": "draft" }))
+                .evaluate(&state)
+        );
+        assert!(
+            condition(json!({ "path": "/title", "op": "contains", "value": "report" }))
+                .evaluate(&state)
+        );
+        assert!(!condition(json!({ "path": "/missing", "op": "exists" })).evaluate(&state));
+        assert!(!condition(json!({ "path": "/missing", "op": "gt", "value": 0 })).evaluate(&state));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let state = json!({ "approved": false, "attempts": 2 });
+        let retry = condition(json!({
+            "all": [
+                { "not": { "path": "/approved", "op": "truthy" } },
+                { "any": [
+                    { "path": "/attempts", "op": "lt", "value": 3 },
+                    { "path": "/force", "op": "eq", "value": true }
+                ] }
+            ]
+        }));
+
+        assert!(retry.evaluate(&state));
+        assert!(!retry.evaluate(&json!({ "approved": true, "attempts": 2 })));
+        assert!(!retry.evaluate(&json!({ "approved": false, "attempts": 3 })));
+    }
+}
@@ -0,0 +1,845 @@
+//! Execution of workflows. A run walks the steps of a workflow in `step_order`,
+//! passing a JSON state from step to step. Task steps replace the state with the
+//! output of their participant, condition and loop steps decide which step runs next.
+//! After every step the run stores a checkpoint, so that it can continue where it
+//! left off after it was paused, failed or the app was restarted.
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::Utc;
+use kameo::prelude::{ActorRef as LocalActorRef, *};
+use kameo_actors::message_bus::Publish;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use sqlx::types::Json;
+use tokio::{sync::watch, task::AbortHandle};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        SystemEventBus,
+        agents::prompt_agent,
+        database::{
+            CheckpointWorkflowStep, DatabaseActor, GetParticipant, GetWorkflow,
+            GetWorkflowExecution, ListWorkflowExecutions, ListWorkflowStepExecutions,
+            ListWorkflowSteps, UpdateWorkflowExecution, UpdateWorkflowStepExecution,
+        },
+        providers::ProviderRegistry,
+    },
+    entities::{
+        CreateWorkflowExecution, CreateWorkflowStepExecution, ParticipantType, WorkflowExecution,
+        WorkflowExecutionFilter, WorkflowExecutionStatus, WorkflowStatus, WorkflowStep,
+        WorkflowStepExecution, WorkflowStepType,
+    },
+    error::{AppError, Result},
+};
+
+pub mod condition;
+pub mod schema;
+
+use condition::Condition;
+
+/// Key of the checkpoint in the metadata of a workflow execution
+const CHECKPOINT_KEY: &str = "checkpoint";
+/// Key of the error that stopped a run in the metadata of a workflow execution
+const ERROR_KEY: &str = "error";
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 3600;
+const DEFAULT_MAX_ITERATIONS: u32 = 10;
+/// Guards against condition steps that keep jumping back and forth
+const MAX_STEPS_PER_RUN: u32 = 10_000;
+
+const OUTPUT_INSTRUCTIONS: &str = "Reply with JSON only, without any explanation or code \
+    fences. The reply must match this JSON schema:";
+
+/// Runs workflows in the background and keeps track of the runs in progress
+#[derive(Actor)]
+pub struct WorkflowEngineActor {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub providers: ProviderRegistry,
+    pub bus: LocalActorRef<SystemEventBus>,
+    pub running: HashMap<Uuid, RunningWorkflow>,
+}
+
+pub struct RunningWorkflow {
+    /// Set to ask the run to stop at the next step boundary
+    pause: watch::Sender<bool>,
+    abort: AbortHandle,
+}
+
+/// Where a run continues from, stored in the metadata of the execution
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    /// Step the run continues with, `None` once the last step has completed
+    pub next_step_id: Option<Uuid>,
+    /// Output of the last task step, or the input of the run before the first one
+    pub state: Value,
+    /// Times each loop step has jumped back since the loop was entered
+    #[serde(default)]
+    pub loop_iterations: HashMap<Uuid, u32>,
+    #[serde(default)]
+    pub steps_run: u32,
+}
+
+impl Checkpoint {
+    fn of(execution: &WorkflowExecution) -> Option<Self> {
+        let checkpoint = execution.metadata.as_ref()?.0.get(CHECKPOINT_KEY)?;
+        serde_json::from_value(checkpoint.clone()).ok()
+    }
+}
+
+fn set_metadata(execution: &mut WorkflowExecution, key: &str, value: Option<Value>) {
+    let mut metadata = match execution.metadata.take() {
+        Some(Json(Value::Object(metadata))) => metadata,
+        _ => Default::default(),
+    };
+    match value {
+        Some(value) => metadata.insert(key.to_string(), value),
+        None => metadata.remove(key),
+    };
+    execution.metadata = Some(Json(Value::Object(metadata)));
+}
+
+/// How often and how quickly a failed task step is retried
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryPolicy {
+    /// Attempts including the first one
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub backoff_multiplier: f64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_delay_ms: 1000,
+            backoff_multiplier: 2.0,
+            max_delay_ms: 60_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt that follows the failed `attempt`, counting from 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .backoff_multiplier
+            .max(1.0)
+            .powi(attempt.saturating_sub(1) as i32);
+        let delay = (self.initial_delay_ms as f64 * factor).min(self.max_delay_ms as f64);
+        Duration::from_millis(delay as u64)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TaskConfig {
+    prompt: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConditionConfig {
+    condition: Condition,
+    then: Option<String>,
+    #[serde(rename = "else")]
+    otherwise: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoopConfig {
+    condition: Condition,
+    target: String,
+    max_iterations: Option<u32>,
+}
+
+fn step_config<T: DeserializeOwned>(step: &WorkflowStep) -> Result<T> {
+    let config = step
+        .participant_config
+        .as_ref()
+        .map(|config| config.0.clone())
+        .unwrap_or_else(|| json!({}));
+    serde_json::from_value(config).map_err(|e| {
+        AppError::validation(format!(
+            "Invalid configuration of step \"{}\": {e}",
+            step.name
+        ))
+    })
+}
+
+fn retry_policy(step: &WorkflowStep) -> Result<RetryPolicy> {
+    match &step.retry_policy {
+        Some(policy) => serde_json::from_value(policy.0.clone()).map_err(|e| {
+            AppError::validation(format!(
+                "Invalid retry policy of step \"{}\": {e}",
+                step.name
+            ))
+        }),
+        None => Ok(RetryPolicy::default()),
+    }
+}
+
+fn find_step<'a>(steps: &'a [WorkflowStep], name: &str) -> Result<&'a WorkflowStep> {
+    steps
+        .iter()
+        .find(|step| step.name == name)
+        .ok_or_else(|| AppError::validation(format!("Workflow has no step named \"{name}\"")))
+}
+
+fn check_schema(
+    step: &WorkflowStep,
+    schema: Option<&Json<Value>>,
+    value: &Value,
+    kind: &str,
+) -> Result<()> {
+    let Some(schema) = schema else {
+        return Ok(());
+    };
+    schema::validate(&schema.0, value).map_err(|errors| {
+        AppError::validation(format!(
+            "{kind} of step \"{}\" doesn't match its schema: {}",
+            step.name,
+            errors.join("; ")
+        ))
+    })
+}
+
+/// Fills the state into the prompt template of a task step
+fn render_prompt(template: Option<&str>, input: &Value) -> String {
+    let input = match input {
+        Value::String(s) => s.clone(),
+        other => serde_json::to_string_pretty(other).unwrap_or_default(),
+    };
+    match template {
+        Some(template) => template.replace("{{input}}", &input),
+        None => input,
+    }
+}
+
+/// Reads the JSON out of an agent's reply, falling back to the reply as a string
+fn parse_output(reply: &str) -> Value {
+    let reply = reply.trim();
+    if let Ok(value) = serde_json::from_str(reply) {
+        return value;
+    }
+    let span = reply
+        .find(['{', '['])
+        .zip(reply.rfind(['}', ']']))
+        .filter(|(start, end)| start < end);
+    span.and_then(|(start, end)| serde_json::from_str(&reply[start..=end]).ok())
+        .unwrap_or_else(|| Value::String(reply.to_string()))
+}
+
+fn complete(mut step_execution: WorkflowStepExecution, output: Value) -> WorkflowStepExecution {
+    step_execution.status = WorkflowExecutionStatus::Completed;
+    step_execution.output_data = Some(Json(output));
+    step_execution.completed_at = Some(Utc::now());
+    step_execution
+}
+
+/// Everything a workflow run needs
+struct WorkflowRun {
+    db: LocalActorRef<DatabaseActor>,
+    providers: ProviderRegistry,
+    bus: LocalActorRef<SystemEventBus>,
+    pause: watch::Receiver<bool>,
+}
+
+impl WorkflowRun {
+    async fn publish(&self, workflow_id: Uuid, step_execution: &WorkflowStepExecution) {
+        self.bus
+            .tell(Publish(WorkflowStepEvent {
+                workflow_id,
+                step_execution: step_execution.clone(),
+            }))
+            .await
+            .ok();
+    }
+
+    async fn record(
+        &self,
+        execution: &WorkflowExecution,
+        step: &WorkflowStep,
+        attempt: u32,
+        input: &Value,
+    ) -> Result<WorkflowStepExecution> {
+        let step_execution = self
+            .db
+            .ask(CreateWorkflowStepExecution {
+                workflow_execution_id: execution.id,
+                workflow_step_id: step.id,
+                status: WorkflowExecutionStatus::Running,
+                attempt_number: attempt as i64,
+                input_data: Some(Json(input.clone())),
+                output_data: None,
+                error_details: None,
+                completed_at: None,
+                metadata: None,
+            })
+            .await?;
+        self.publish(execution.workflow_id, &step_execution).await;
+        Ok(step_execution)
+    }
+
+    async fn fail_step(
+        &self,
+        workflow_id: Uuid,
+        mut step_execution: WorkflowStepExecution,
+        error: &AppError,
+    ) -> Result<()> {
+        step_execution.status = WorkflowExecutionStatus::Failed;
+        step_execution.error_details = Some(error.to_string());
+        step_execution.completed_at = Some(Utc::now());
+        let step_execution = self
+            .db
+            .ask(UpdateWorkflowStepExecution(step_execution))
+            .await?;
+        self.publish(workflow_id, &step_execution).await;
+        Ok(())
+    }
+
+    /// Runs the participant of a task step on the state, retrying it according to
+    /// the step's retry policy. Returns the step execution that succeeded, which is
+    /// stored together with the next checkpoint, and the validated output.
+    async fn run_task(
+        &self,
+        execution: &WorkflowExecution,
+        step: &WorkflowStep,
+        input: &Value,
+    ) -> Result<(WorkflowStepExecution, Value)> {
+        check_schema(step, step.input_schema.as_ref(), input, "Input")?;
+        let participant = self
+            .db
+            .ask(GetParticipant(step.participant_id))
+            .await?
+            .ok_or_else(|| AppError::not_found("Participant", step.participant_id))?;
+        let ParticipantType::Agent(agent_id) = participant.type_ else {
+            return Err(AppError::validation(format!(
+                "Participant {} of step \"{}\" is not an agent",
+                participant.id, step.name
+            )));
+        };
+        let config: TaskConfig = step_config(step)?;
+        let policy = retry_policy(step)?;
+        let timeout = Duration::from_secs(
+            step.timeout_seconds
+                .filter(|seconds| *seconds > 0)
+                .map_or(DEFAULT_TIMEOUT_SECONDS, |seconds| seconds as u64),
+        );
+        let prompt = render_prompt(config.prompt.as_deref(), input);
+        let instructions = step
+            .output_schema
+            .as_ref()
+            .map(|schema| format!("{OUTPUT_INSTRUCTIONS}\n{}", schema.0));
+
+        let mut attempt = 1;
+        loop {
+            let step_execution = self.record(execution, step, attempt, input).await?;
+            let run = async {
+                let reply = prompt_agent(
+                    &self.db,
+                    &self.providers,
+                    agent_id,
+                    instructions.as_deref(),
+                    prompt.clone(),
+                )
+                .await?;
+                let output = match &step.output_schema {
+                    Some(_) => parse_output(&reply),
+                    None => Value::String(reply),
+                };
+                check_schema(step, step.output_schema.as_ref(), &output, "Output")?;
+                Ok::<_, AppError>(output)
+            };
+            let result = match tokio::time::timeout(timeout, run).await {
+                Ok(result) => result,
+                Err(_) => Err(AppError::external_service(format!(
+                    "Step \"{}\" timed out after {}s",
+                    step.name,
+                    timeout.as_secs()
+                ))),
+            };
+            match result {
+                Ok(output) => return Ok((complete(step_execution, output.clone()), output)),
+                Err(e) => {
+                    self.fail_step(execution.workflow_id, step_execution, &e)
+                        .await?;
+                    if attempt >= policy.max_attempts {
+                        return Err(e);
+                    }
+                    let delay = policy.delay(attempt);
+                    warn!(step_id = %step.id, attempt, ?delay, "Workflow step failed, retrying: {e}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Runs the step at `index` and returns its completed execution together with
+    /// the checkpoint that follows it
+    async fn run_step(
+        &self,
+        execution: &WorkflowExecution,
+        steps: &[WorkflowStep],
+        index: usize,
+        checkpoint: &Checkpoint,
+    ) -> Result<(WorkflowStepExecution, Checkpoint)> {
+        let step = &steps[index];
+        let mut next = checkpoint.clone();
+        next.next_step_id = steps.get(index + 1).map(|step| step.id);
+        match step.step_type {
+            WorkflowStepType::Task => {
+                let (step_execution, output) =
+                    self.run_task(execution, step, &checkpoint.state).await?;
+                next.state = output;
+                Ok((step_execution, next))
+            }
+            WorkflowStepType::Condition => {
+                let config: ConditionConfig = step_config(step)?;
+                let result = config.condition.evaluate(&checkpoint.state);
+                let target = if result {
+                    config.then
+                } else {
+                    config.otherwise
+                };
+                if let Some(target) = target {
+                    next.next_step_id = Some(find_step(steps, &target)?.id);
+                }
+                let step_execution = self.record(execution, step, 1, &checkpoint.state).await?;
+                Ok((complete(step_execution, json!({ "result": result })), next))
+            }
+            WorkflowStepType::Loop => {
+                let config: LoopConfig = step_config(step)?;
+                let target = find_step(steps, &config.target)?;
+                let max_iterations = config.max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS);
+                let iterations = next.loop_iterations.get(&step.id).copied().unwrap_or(0);
+                let repeat =
+                    iterations < max_iterations && config.condition.evaluate(&checkpoint.state);
+                if repeat {
+                    next.loop_iterations.insert(step.id, iterations + 1);
+                    next.next_step_id = Some(target.id);
+                } else {
+                    // Entering the loop again starts counting from zero
+                    next.loop_iterations.remove(&step.id);
+                }
+                let step_execution = self.record(execution, step, 1, &checkpoint.state).await?;
+                Ok((
+                    complete(
+                        step_execution,
+                        json!({ "repeat": repeat, "iterations": iterations + repeat as u32 }),
+                    ),
+                    next,
+                ))
+            }
+        }
+    }
+
+    async fn fail(
+        &self,
+        mut execution: WorkflowExecution,
+        error: String,
+    ) -> Result<WorkflowExecution> {
+        warn!(execution_id = %execution.id, "Workflow run failed: {error}");
+        execution.status = WorkflowExecutionStatus::Failed;
+        execution.completed_at = Some(Utc::now());
+        set_metadata(&mut execution, ERROR_KEY, Some(Value::String(error)));
+        Ok(self.db.ask(UpdateWorkflowExecution(execution)).await?)
+    }
+
+    /// Runs the steps of a workflow from the checkpoint of the execution until the
+    /// workflow ends, a step fails or the run is paused
+    async fn run(&self, mut execution: WorkflowExecution) -> Result<WorkflowExecution> {
+        let steps = self
+            .db
+            .ask(ListWorkflowSteps(execution.workflow_id))
+            .await?;
+        let mut checkpoint = Checkpoint::of(&execution).unwrap_or_else(|| Checkpoint {
+            next_step_id: steps.first().map(|step| step.id),
+            state: execution
+                .input_data
+                .as_ref()
+                .map(|input| input.0.clone())
+                .unwrap_or_default(),
+            ..Default::default()
+        });
+
+        while let Some(step_id) = checkpoint.next_step_id {
+            if *self.pause.borrow() {
+                info!(execution_id = %execution.id, "Workflow run paused");
+                execution.status = WorkflowExecutionStatus::Paused;
+                return Ok(self.db.ask(UpdateWorkflowExecution(execution)).await?);
+            }
+            let Some(index) = steps.iter().position(|step| step.id == step_id) else {
+                return self
+                    .fail(execution, format!("Step {step_id} no longer exists"))
+                    .await;
+            };
+            if checkpoint.steps_run >= MAX_STEPS_PER_RUN {
+                return self
+                    .fail(
+                        execution,
+                        format!("Stopped after running {MAX_STEPS_PER_RUN} steps"),
+                    )
+                    .await;
+            }
+            let step = &steps[index];
+            match self.run_step(&execution, &steps, index, &checkpoint).await {
+                Ok((step_execution, mut next)) => {
+                    next.steps_run += 1;
+                    set_metadata(
+                        &mut execution,
+                        CHECKPOINT_KEY,
+                        Some(serde_json::to_value(&next)?),
+                    );
+                    let (step_execution, saved) = self
+                        .db
+                        .ask(CheckpointWorkflowStep(step_execution, execution))
+                        .await?;
+                    self.publish(saved.workflow_id, &step_execution).await;
+                    execution = saved;
+                    checkpoint = next;
+                }
+                Err(e) => {
+                    return self
+                        .fail(execution, format!("Step \"{}\": {e}", step.name))
+                        .await;
+                }
+            }
+        }
+
+        execution.status = WorkflowExecutionStatus::Completed;
+        execution.output_data = Some(Json(checkpoint.state));
+        execution.completed_at = Some(Utc::now());
+        info!(execution_id = %execution.id, "Workflow completed");
+        Ok(self.db.ask(UpdateWorkflowExecution(execution)).await?)
+    }
+}
+
+impl WorkflowEngineActor {
+    /// Runs an execution in the background
+    fn spawn_run(&mut self, execution: WorkflowExecution, actor_ref: LocalActorRef<Self>) {
+        let execution_id = execution.id;
+        let (pause, paused) = watch::channel(false);
+        let run = WorkflowRun {
+            db: self.db.clone(),
+            providers: self.providers.clone(),
+            bus: self.bus.clone(),
+            pause: paused,
+        };
+        let handle = tokio::spawn(async move {
+            let db = run.db.clone();
+            if let Err(e) = run.run(execution).await {
+                warn!(%execution_id, "Workflow run failed: {e}");
+                mark_failed(&db, execution_id, &e).await;
+            }
+            actor_ref.tell(WorkflowRunFinished(execution_id)).await.ok();
+        });
+        self.running.insert(
+            execution_id,
+            RunningWorkflow {
+                pause,
+                abort: handle.abort_handle(),
+            },
+        );
+    }
+
+    async fn get_execution(&self, id: Uuid) -> Result<WorkflowExecution> {
+        self.db
+            .ask(GetWorkflowExecution(id))
+            .await?
+            .ok_or_else(|| AppError::not_found("WorkflowExecution", id))
+    }
+
+    /// Marks the step executions of a run that never finished
+    async fn close_step_executions(
+        &self,
+        execution_id: Uuid,
+        status: WorkflowExecutionStatus,
+        error: Option<&str>,
+    ) -> Result<()> {
+        for mut step_execution in self
+            .db
+            .ask(ListWorkflowStepExecutions(execution_id))
+            .await?
+            .into_iter()
+            .filter(|step_execution| step_execution.status == WorkflowExecutionStatus::Running)
+        {
+            step_execution.status = status;
+            step_execution.error_details = error.map(str::to_string);
+            step_execution.completed_at = Some(Utc::now());
+            self.db
+                .ask(UpdateWorkflowStepExecution(step_execution))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Records an error that happened outside of a step, e.g. while loading the steps
+async fn mark_failed(db: &LocalActorRef<DatabaseActor>, execution_id: Uuid, error: &AppError) {
+    let Ok(Some(mut execution)) = db.ask(GetWorkflowExecution(execution_id)).await else {
+        return;
+    };
+    execution.status = WorkflowExecutionStatus::Failed;
+    execution.completed_at = Some(Utc::now());
+    set_metadata(
+        &mut execution,
+        ERROR_KEY,
+        Some(Value::String(error.to_string())),
+    );
+    db.ask(UpdateWorkflowExecution(execution)).await.ok();
+}
+
+impl Message<StartWorkflow> for WorkflowEngineActor {
+    type Reply = Result<WorkflowExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: StartWorkflow,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let workflow = self
+            .db
+            .ask(GetWorkflow(msg.workflow_id))
+            .await?
+            .ok_or_else(|| AppError::not_found("Workflow", msg.workflow_id))?;
+        if workflow.status != WorkflowStatus::Active {
+            return Err(AppError::validation(format!(
+                "Workflow {} is not active",
+                workflow.id
+            )));
+        }
+        let execution = self
+            .db
+            .ask(CreateWorkflowExecution {
+                workflow_id: workflow.id,
+                initiated_by_participant_id: msg.initiated_by_participant_id,
+                status: WorkflowExecutionStatus::Running,
+                input_data: Some(Json(msg.input)),
+                output_data: None,
+                metadata: None,
+            })
+            .await?;
+        self.spawn_run(execution.clone(), ctx.actor_ref());
+        Ok(execution)
+    }
+}
+
+impl Message<PauseWorkflow> for WorkflowEngineActor {
+    type Reply = Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: PauseWorkflow,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Some(running) = self.running.get(&msg.0) else {
+            return Err(AppError::validation(format!(
+                "Workflow execution {} is not running",
+                msg.0
+            )));
+        };
+        running.pause.send_replace(true);
+        Ok(())
+    }
+}
+
+impl Message<ResumeWorkflow> for WorkflowEngineActor {
+    type Reply = Result<WorkflowExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: ResumeWorkflow,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let mut execution = self.get_execution(msg.0).await?;
+        if self.running.contains_key(&execution.id)
+            || !matches!(
+                execution.status,
+                WorkflowExecutionStatus::Paused
+                    | WorkflowExecutionStatus::Failed
+                    | WorkflowExecutionStatus::Cancelled
+            )
+        {
+            return Err(AppError::validation(format!(
+                "Workflow execution {} can't be resumed while it is {:?}",
+                execution.id, execution.status
+            )));
+        }
+        execution.status = WorkflowExecutionStatus::Running;
+        execution.completed_at = None;
+        set_metadata(&mut execution, ERROR_KEY, None);
+        let execution = self.db.ask(UpdateWorkflowExecution(execution)).await?;
+        self.spawn_run(execution.clone(), ctx.actor_ref());
+        Ok(execution)
+    }
+}
+
+impl Message<CancelWorkflow> for WorkflowEngineActor {
+    type Reply = Result<WorkflowExecution>;
+
+    async fn handle(
+        &mut self,
+        msg: CancelWorkflow,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if let Some(running) = self.running.remove(&msg.0) {
+            running.abort.abort();
+        }
+        let mut execution = self.get_execution(msg.0).await?;
+        if !matches!(
+            execution.status,
+            WorkflowExecutionStatus::Pending
+                | WorkflowExecutionStatus::Running
+                | WorkflowExecutionStatus::Paused
+        ) {
+            return Err(AppError::validation(format!(
+                "Workflow execution {} can't be cancelled while it is {:?}",
+                execution.id, execution.status
+            )));
+        }
+        // Steps that were interrupted are retried when the run is resumed
+        self.close_step_executions(execution.id, WorkflowExecutionStatus::Cancelled, None)
+            .await?;
+        execution.status = WorkflowExecutionStatus::Cancelled;
+        execution.completed_at = Some(Utc::now());
+        Ok(self.db.ask(UpdateWorkflowExecution(execution)).await?)
+    }
+}
+
+impl Message<RecoverWorkflows> for WorkflowEngineActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        _msg: RecoverWorkflows,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let executions = match self
+            .db
+            .ask(ListWorkflowExecutions(WorkflowExecutionFilter {
+                status: Some(WorkflowExecutionStatus::Running),
+                ..Default::default()
+            }))
+            .await
+        {
+            Ok(executions) => executions,
+            Err(e) => {
+                warn!("Failed to load interrupted workflow runs: {e}");
+                return;
+            }
+        };
+        for execution in executions {
+            if self.running.contains_key(&execution.id) {
+                continue;
+            }
+            if let Err(e) = self
+                .close_step_executions(
+                    execution.id,
+                    WorkflowExecutionStatus::Failed,
+                    Some("Interrupted by an app restart"),
+                )
+                .await
+            {
+                warn!(execution_id = %execution.id, "Failed to recover workflow run: {e}");
+                continue;
+            }
+            info!(execution_id = %execution.id, "Resuming interrupted workflow run");
+            self.spawn_run(execution, ctx.actor_ref());
+        }
+    }
+}
+
+impl Message<WorkflowRunFinished> for WorkflowEngineActor {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: WorkflowRunFinished,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.running.remove(&msg.0);
+    }
+}
+
+/// Starts a run of a workflow. Replies once the run is recorded, the steps run in
+/// the background.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartWorkflow {
+    pub workflow_id: Uuid,
+    #[serde(default)]
+    pub input: Value,
+    pub initiated_by_participant_id: Option<Uuid>,
+}
+
+/// Stops a run once its current step has finished
+pub struct PauseWorkflow(pub Uuid);
+
+/// Continues a paused, failed or cancelled run from its last checkpoint
+pub struct ResumeWorkflow(pub Uuid);
+
+pub struct CancelWorkflow(pub Uuid);
+
+/// Continues the runs that were interrupted when the app was closed
+pub struct RecoverWorkflows;
+
+struct WorkflowRunFinished(Uuid);
+
+/// Published whenever a step of a workflow run starts or finishes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowStepEvent {
+    pub workflow_id: Uuid,
+    pub step_execution: WorkflowStepExecution,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_backs_off_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_delay_ms: 500,
+            backoff_multiplier: 3.0,
+            max_delay_ms: 10_000,
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_millis(1500));
+        assert_eq!(policy.delay(5), Duration::from_millis(10_000));
+        assert_eq!(
+            serde_json::from_value::<RetryPolicy>(json!({ "maxAttempts": 3 })).unwrap(),
+            RetryPolicy {
+                max_attempts: 3,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(
+            parse_output("Here you go:\n```json\n{\"approved\": true}\n```"),
+            json!({ "approved": true })
+        );
+        assert_eq!(parse_output(" [1, 2] "), json!([1, 2]));
+        assert_eq!(parse_output("plain text"), json!("plain text"));
+    }
+
+    #[test]
+    fn test_render_prompt() {
+        assert_eq!(
+            render_prompt(Some("Summarize: {{input}}"), &json!("the report")),
+            "Summarize: the report"
+        );
+        assert_eq!(render_prompt(None, &json!({ "a": 1 })), "{\n  \"a\": 1\n}");
+    }
+}
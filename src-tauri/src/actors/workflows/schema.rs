@@ -0,0 +1,288 @@
+//! Validation of workflow step input and output against the step's JSON schema.
+//!
+//! Supports the commonly used subset of JSON Schema: `type`, `enum`, `const`,
+//! `properties`, `required`, `additionalProperties`, `items`, `minItems`/`maxItems`,
+//! `minLength`/`maxLength`, `minimum`/`maximum` (and their exclusive variants),
+//! `allOf`, `anyOf`, `oneOf` and `not`. Other keywords are ignored.
+
+use serde_json::{Map, Value};
+
+/// Validates `value` against `schema` and returns every violation, prefixed with the
+/// JSON pointer of the offending value.
+pub fn validate(schema: &Value, value: &Value) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "", &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn is_valid(schema: &Value, value: &Value) -> bool {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "", &mut errors);
+    errors.is_empty()
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "number" => value.is_number(),
+        "integer" => match value {
+            Value::Number(n) => {
+                n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+            }
+            _ => false,
+        },
+        other => type_name(value) == other,
+    }
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            errors.push(format!("{path}: no value is allowed here"));
+            return;
+        }
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{path}: expected {}, found {}",
+                types.join(" or "),
+                type_name(value)
+            ));
+            // The remaining keywords assume the right type
+            return;
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum")
+        && !allowed.contains(value)
+    {
+        errors.push(format!(
+            "{path}: {value} is not one of {}",
+            Value::Array(allowed.clone())
+        ));
+    }
+    if let Some(expected) = schema.get("const")
+        && expected != value
+    {
+        errors.push(format!("{path}: expected {expected}, found {value}"));
+    }
+
+    match value {
+        Value::Object(object) => validate_object(schema, object, path, errors),
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{path}/{index}"), errors);
+                }
+            }
+            check_bounds(
+                schema,
+                "minItems",
+                "maxItems",
+                items.len(),
+                "items",
+                path,
+                errors,
+            );
+        }
+        Value::String(s) => {
+            check_bounds(
+                schema,
+                "minLength",
+                "maxLength",
+                s.chars().count(),
+                "characters",
+                path,
+                errors,
+            );
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            let limit = |key: &str| schema.get(key).and_then(Value::as_f64);
+            if limit("minimum").is_some_and(|min| n < min) {
+                errors.push(format!(
+                    "{path}: {n} is less than the minimum {}",
+                    schema["minimum"]
+                ));
+            }
+            if limit("maximum").is_some_and(|max| n > max) {
+                errors.push(format!(
+                    "{path}: {n} is greater than the maximum {}",
+                    schema["maximum"]
+                ));
+            }
+            if limit("exclusiveMinimum").is_some_and(|min| n <= min) {
+                errors.push(format!(
+                    "{path}: {n} must be greater than {}",
+                    schema["exclusiveMinimum"]
+                ));
+            }
+            if limit("exclusiveMaximum").is_some_and(|max| n >= max) {
+                errors.push(format!(
+                    "{path}: {n} must be less than {}",
+                    schema["exclusiveMaximum"]
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        for sub in schemas {
+            validate_at(sub, value, path, errors);
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("anyOf")
+        && !schemas.iter().any(|sub| is_valid(sub, value))
+    {
+        errors.push(format!("{path}: doesn't match any of the allowed schemas"));
+    }
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        let matching = schemas.iter().filter(|sub| is_valid(sub, value)).count();
+        if matching != 1 {
+            errors.push(format!(
+                "{path}: must match exactly one of the allowed schemas, matches {matching}"
+            ));
+        }
+    }
+    if let Some(sub) = schema.get("not")
+        && is_valid(sub, value)
+    {
+        errors.push(format!("{path}: matches a schema it must not match"));
+    }
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    object: &Map<String, Value>,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for key in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(key) {
+                errors.push(format!("{path}: missing required property \"{key}\""));
+            }
+        }
+    }
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (key, property) in object {
+        let property_path = format!("{path}/{key}");
+        match properties.and_then(|properties| properties.get(key)) {
+            Some(property_schema) => validate_at(property_schema, property, &property_path, errors),
+            None => match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    errors.push(format!("{path}: unexpected property \"{key}\""));
+                }
+                Some(additional) => validate_at(additional, property, &property_path, errors),
+                None => {}
+            },
+        }
+    }
+}
+
+fn check_bounds(
+    schema: &Map<String, Value>,
+    min_key: &str,
+    max_key: &str,
+    len: usize,
+    unit: &str,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    if let Some(min) = schema.get(min_key).and_then(Value::as_u64)
+        && (len as u64) < min
+    {
+        errors.push(format!(
+            "{path}: has {len} {unit}, at least {min} are required"
+        ));
+    }
+    if let Some(max) = schema.get(max_key).and_then(Value::as_u64)
+        && (len as u64) > max
+    {
+        errors.push(format!(
+            "{path}: has {len} {unit}, at most {max} are allowed"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_validate_object() {
+        let schema = json!({
+            "type": "object",
+            "required": ["title", "score"],
+            "properties": {
+                "title": { "type": "string", "minLength": 1 },
+                "score": { "type": "integer", "minimum": 0, "maximum": 10 },
+                "tags": { "type": "array", "items": { "enum": ["a", "b"] } }
+            },
+            "additionalProperties": false
+        });
+
+        assert!(validate(&schema, &json!({ "title": "x", "score": 3, "tags": ["a"] })).is_ok());
+
+        let errors = validate(
+            &schema,
+            &json!({ "score": 11.5, "tags": ["c"], "extra": 1 }),
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 4);
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.contains("missing required property \"title\""))
+        );
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.starts_with("/score: expected integer"))
+        );
+        assert!(errors.iter().any(|e| e.starts_with("/tags/0")));
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.contains("unexpected property \"extra\""))
+        );
+    }
+
+    #[test]
+    fn test_validate_combinators() {
+        let schema = json!({ "oneOf": [{ "type": "string" }, { "type": "number" }] });
+
+        assert!(validate(&schema, &json!("text")).is_ok());
+        assert!(validate(&schema, &json!(null)).is_err());
+        assert!(validate(&json!({ "not": { "const": 1 } }), &json!(1)).is_err());
+        assert!(validate(&json!(true), &json!({ "anything": [] })).is_ok());
+    }
+}
@@ -0,0 +1,97 @@
+//! Lets MCP clients that start their servers as programs talk to the endpoint of a
+//! running Evo Pro. Reads messages from stdin, one per line, forwards them to the
+//! endpoint and writes the responses to stdout.
+//!
+//! The endpoint is taken from `EVO_PRO_MCP_URL`, and the API key from
+//! `EVO_PRO_API_KEY`.
+
+use evo_pro_lib::mcp::{
+    endpoint::{DEFAULT_PORT, ENDPOINT_PATH},
+    protocol::{INTERNAL_ERROR, JsonRpcMessage, JsonRpcResponse, RequestId, parse_messages},
+};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[tokio::main]
+async fn main() {
+    let url = std::env::var("EVO_PRO_MCP_URL")
+        .unwrap_or_else(|_| format!("http://127.0.0.1:{DEFAULT_PORT}{ENDPOINT_PATH}"));
+    let Ok(api_key) = std::env::var("EVO_PRO_API_KEY") else {
+        eprintln!("EVO_PRO_API_KEY must be set to an API key created in Evo Pro");
+        std::process::exit(1);
+    };
+
+    let client = reqwest::Client::new();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(output) = forward(&client, &url, &api_key, line).await else {
+            continue;
+        };
+        let written = async {
+            stdout.write_all(output.as_bytes()).await?;
+            stdout.write_all(b"\n").await?;
+            stdout.flush().await
+        };
+        if written.await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Sends a line to the endpoint and returns what to answer, if anything
+async fn forward(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: &str,
+    line: String,
+) -> Option<String> {
+    let ids = request_ids(line.as_bytes());
+    let res = client
+        .post(url)
+        .bearer_auth(api_key)
+        .header("content-type", "application/json")
+        .header("accept", "application/json, text/event-stream")
+        .body(line)
+        .send()
+        .await;
+    let error = match res {
+        Ok(res) if res.status() == reqwest::StatusCode::ACCEPTED => return None,
+        Ok(res) if res.status().is_success() => match res.text().await {
+            // Responses are written on a single line
+            Ok(body) => return Some(body.replace('\n', "")),
+            Err(e) => format!("Failed to read the response of Evo Pro: {e}"),
+        },
+        Ok(res) => {
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            format!("Evo Pro refused the request ({status}): {body}")
+        }
+        Err(e) => format!("Failed to reach Evo Pro at {url}: {e}"),
+    };
+    eprintln!("{error}");
+
+    // Requests still get an answer, so the client doesn't wait for one forever
+    let errors: Vec<_> = ids
+        .into_iter()
+        .map(|id| JsonRpcResponse::error(Some(id), INTERNAL_ERROR, error.clone()))
+        .collect();
+    match errors.as_slice() {
+        [] => None,
+        [error] => serde_json::to_string(error).ok(),
+        errors => serde_json::to_string(errors).ok(),
+    }
+}
+
+fn request_ids(line: &[u8]) -> Vec<RequestId> {
+    parse_messages(line)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|message| match message {
+            JsonRpcMessage::Request(request) => Some(request.id),
+            _ => None,
+        })
+        .collect()
+}
@@ -1,21 +1,57 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use chrono::Utc;
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use tauri::State;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
     actors::{
+        chains::{CancelChain, ResumeChain, RunChain},
         conversation::SendMessage,
+        workflows::{CancelWorkflow, PauseWorkflow, ResumeWorkflow, StartWorkflow},
+        documents::{IngestDocument, IngestReport, RetrieveChunks, RetrievedChunk},
+        memory::{ExtractMemories, RecallMemories, RecalledMemory},
+        discovery::NetworkConfig,
+        hosting::{self, RemoteAgentRequest},
+        identity::RotateKey,
+        mcp::{ConnectMcpServer, DisconnectMcpServer, RefreshMcpTools, SetMcpToolAvailable},
+        mcp_endpoint::UpdateMcpEndpointSettings,
+        transfer::{
+            CancelFileTransfer, DownloadFile, FileManifest, ShareFile, TransferPolicy,
+        },
         database::{
-            CreateBatchParticipants, DeleteP2pNode, DeleteParticipant, DeleteTask, DeleteUser, ListAgents, ListConversations, ListParticipants, ListTasks, ListUsers, UpdateAgent, UpdateP2pNode, UpdateParticipant, UpdateTask, UpdateUser
+            CreateMcpServer, DeleteMcpServer, ListMcpServers, ListMcpTools, SetMcpServerActive,
+            DeleteApiKey, GetMcpEndpointSettings, ListApiKeys, ListAgentTools, SetAgentTools,
+            GetTransferPolicy, ListFileTransfers, SaveTransferPolicy, GetPeerCertificate,
+            PublishAgent, UnpublishAgent, ListPeerAgents,
+            GetNetworkConfig, SaveNetworkConfig, GetNetworkStats, ListP2pNodes,
+            ListAuditLog, ListPeerPermissions, UpdatePeerPermission,
+            DeleteWorkflow, DeleteWorkflowStep, ListWorkflowExecutions,
+            ListWorkflowStepExecutions, ListWorkflowSteps, ListWorkflows, UpdateWorkflow,
+            UpdateWorkflowStep,
+            DeleteAgentChain, DeleteAgentChainStep, ListAgentChainExecutions,
+            ListAgentChainStepExecutions, ListAgentChainSteps, ListAgentChains, UpdateAgentChain,
+            UpdateAgentChainStep, CreateBatchParticipants, DeleteCredential, DeleteP2pNode, DeleteParticipant, DeleteTask, DeleteUser, ListAgents, ListConversations, ListParticipants, ListTasks, ListUsers, UpdateAgent, UpdateP2pNode, UpdateParticipant, UpdateTask, UpdateUser
         },
     },
     entities::{
-        Agent, AgentFilter, Conversation, ConversationFilter, CreateAgent, CreateConversation, CreateConversationParticipant, CreateP2pNode, CreateParticipant, CreateTask, CreateUser, P2pNode, Participant, ParticipantFilter, ParticipantRole, Task, TaskFilter, User, UserFilter
+        AuditLogEntry, AuditLogFilter, PeerPermission, PeerPermissionFilter, P2pNetworkStats,
+        P2pNodeFilter, PeerAgent, PeerIdWrapper, PublishedAgent, FileTransfer, FileTransferFilter,
+        PeerCertificate, McpConnectionStatus, McpServer, McpServerFilter, McpTool, McpToolFilter,
+        McpTransport, ApiKey, ApiKeyFilter, CreateApiKey, Tool as ToolRecord,
+        CreateWorkflow, CreateWorkflowStep, Workflow, WorkflowExecution, WorkflowExecutionFilter,
+        WorkflowFilter, WorkflowStep, WorkflowStepExecution,
+        AgentChain, AgentChainExecution, AgentChainExecutionFilter, AgentChainFilter,
+        AgentChainStep, AgentChainStepExecution, CreateAgentChain, CreateAgentChainStep,
+        Agent, AgentFilter, Conversation, Credential, ConversationFilter, Memory, CreateAgent, CreateConversation, CreateConversationParticipant, CreateP2pNode, CreateParticipant, CreateTask, CreateUser, P2pNode, Participant, ParticipantFilter, ParticipantRole, Task, TaskFilter, User, UserFilter
     },
     error::Result,
-    keys::{PubKeyWrapper, KEY_PAIR, PEER_ID},
+    mcp::endpoint::{IssuedApiKey, McpEndpointSettings, generate_api_key, hash_api_key},
+    keys::{DeviceCertificate, KeyKind, KeyRotation, PubKeyWrapper, KEYSTORE, KEY_PAIR, PEER_ID},
     state::AppState,
 };
 
@@ -158,7 +194,62 @@ pub async fn delete_participant(participant: Uuid, state: State<'_, AppState>) -
 
 #[tauri::command(async)]
 pub async fn send_message(msg: SendMessage, state: State<'_, AppState>) -> Result<()> {
-    todo!()
+    Ok(state.actors.conversation_manager.ask(msg).await?)
+}
+
+#[tauri::command]
+pub async fn ingest_document(
+    request: IngestDocument,
+    state: State<'_, AppState>,
+) -> Result<IngestReport> {
+    Ok(state.actors.document_indexer.ask(request).await?)
+}
+
+#[tauri::command]
+pub async fn search_documents(
+    query: RetrieveChunks,
+    state: State<'_, AppState>,
+) -> Result<Vec<RetrievedChunk>> {
+    Ok(state.actors.document_indexer.ask(query).await?)
+}
+
+#[tauri::command]
+pub async fn extract_memories(
+    request: ExtractMemories,
+    state: State<'_, AppState>,
+) -> Result<Vec<Memory>> {
+    Ok(state.actors.memory_manager.ask(request).await?)
+}
+
+#[tauri::command]
+pub async fn recall_memories(
+    query: RecallMemories,
+    state: State<'_, AppState>,
+) -> Result<Vec<RecalledMemory>> {
+    Ok(state.actors.memory_manager.ask(query).await?)
+}
+
+#[tauri::command]
+pub async fn create_credential(
+    workspace_id: Option<Uuid>,
+    name: String,
+    credential_name: String,
+    value: String,
+    state: State<'_, AppState>,
+) -> Result<Credential> {
+    state
+        .actors
+        .providers
+        .store_credential(workspace_id, name, credential_name, &value)
+        .await
+}
+
+#[tauri::command]
+pub async fn delete_credential(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    state.actors.db.ask(DeleteCredential(id)).await?;
+    // Clients built with the removed key must not be reused
+    state.actors.providers.clear_cache().await;
+    Ok(())
 }
 
 #[tauri::command]
@@ -239,3 +330,646 @@ pub fn log_frontend_message(entry: LogEntry) -> Result<(), String> {
 
     Ok(())
 }
+
+#[tauri::command]
+pub async fn create_agent_chain(
+    chain: CreateAgentChain,
+    state: State<'_, AppState>,
+) -> Result<AgentChain> {
+    Ok(state.actors.db.ask(chain).await?)
+}
+
+#[tauri::command]
+pub async fn list_agent_chains(
+    filter: AgentChainFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<AgentChain>> {
+    Ok(state.actors.db.ask(ListAgentChains(filter)).await?)
+}
+
+#[tauri::command]
+pub async fn update_agent_chain(
+    chain: AgentChain,
+    state: State<'_, AppState>,
+) -> Result<AgentChain> {
+    Ok(state.actors.db.ask(UpdateAgentChain(chain)).await?)
+}
+
+#[tauri::command]
+pub async fn delete_agent_chain(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    Ok(state.actors.db.ask(DeleteAgentChain(id)).await?)
+}
+
+#[tauri::command]
+pub async fn create_agent_chain_step(
+    step: CreateAgentChainStep,
+    state: State<'_, AppState>,
+) -> Result<AgentChainStep> {
+    Ok(state.actors.db.ask(step).await?)
+}
+
+#[tauri::command]
+pub async fn list_agent_chain_steps(
+    chain_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<Vec<AgentChainStep>> {
+    Ok(state.actors.db.ask(ListAgentChainSteps(chain_id)).await?)
+}
+
+#[tauri::command]
+pub async fn update_agent_chain_step(
+    step: AgentChainStep,
+    state: State<'_, AppState>,
+) -> Result<AgentChainStep> {
+    Ok(state.actors.db.ask(UpdateAgentChainStep(step)).await?)
+}
+
+#[tauri::command]
+pub async fn delete_agent_chain_step(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    Ok(state.actors.db.ask(DeleteAgentChainStep(id)).await?)
+}
+
+#[tauri::command]
+pub async fn run_agent_chain(
+    request: RunChain,
+    state: State<'_, AppState>,
+) -> Result<AgentChainExecution> {
+    Ok(state.actors.chain_executor.ask(request).await?)
+}
+
+#[tauri::command]
+pub async fn resume_agent_chain(
+    execution_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<AgentChainExecution> {
+    Ok(state
+        .actors
+        .chain_executor
+        .ask(ResumeChain(execution_id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn cancel_agent_chain(
+    execution_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<AgentChainExecution> {
+    Ok(state
+        .actors
+        .chain_executor
+        .ask(CancelChain(execution_id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn list_agent_chain_executions(
+    filter: AgentChainExecutionFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<AgentChainExecution>> {
+    Ok(state.actors.db.ask(ListAgentChainExecutions(filter)).await?)
+}
+
+#[tauri::command]
+pub async fn list_agent_chain_step_executions(
+    execution_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<Vec<AgentChainStepExecution>> {
+    Ok(state
+        .actors
+        .db
+        .ask(ListAgentChainStepExecutions(execution_id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn create_workflow(
+    workflow: CreateWorkflow,
+    state: State<'_, AppState>,
+) -> Result<Workflow> {
+    Ok(state.actors.db.ask(workflow).await?)
+}
+
+#[tauri::command]
+pub async fn list_workflows(
+    filter: WorkflowFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<Workflow>> {
+    Ok(state.actors.db.ask(ListWorkflows(filter)).await?)
+}
+
+#[tauri::command]
+pub async fn update_workflow(workflow: Workflow, state: State<'_, AppState>) -> Result<Workflow> {
+    Ok(state.actors.db.ask(UpdateWorkflow(workflow)).await?)
+}
+
+#[tauri::command]
+pub async fn delete_workflow(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    Ok(state.actors.db.ask(DeleteWorkflow(id)).await?)
+}
+
+#[tauri::command]
+pub async fn create_workflow_step(
+    step: CreateWorkflowStep,
+    state: State<'_, AppState>,
+) -> Result<WorkflowStep> {
+    Ok(state.actors.db.ask(step).await?)
+}
+
+#[tauri::command]
+pub async fn list_workflow_steps(
+    workflow_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<Vec<WorkflowStep>> {
+    Ok(state.actors.db.ask(ListWorkflowSteps(workflow_id)).await?)
+}
+
+#[tauri::command]
+pub async fn update_workflow_step(
+    step: WorkflowStep,
+    state: State<'_, AppState>,
+) -> Result<WorkflowStep> {
+    Ok(state.actors.db.ask(UpdateWorkflowStep(step)).await?)
+}
+
+#[tauri::command]
+pub async fn delete_workflow_step(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    Ok(state.actors.db.ask(DeleteWorkflowStep(id)).await?)
+}
+
+#[tauri::command]
+pub async fn start_workflow(
+    request: StartWorkflow,
+    state: State<'_, AppState>,
+) -> Result<WorkflowExecution> {
+    Ok(state.actors.workflow_engine.ask(request).await?)
+}
+
+#[tauri::command]
+pub async fn pause_workflow(execution_id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    Ok(state
+        .actors
+        .workflow_engine
+        .ask(PauseWorkflow(execution_id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn resume_workflow(
+    execution_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<WorkflowExecution> {
+    Ok(state
+        .actors
+        .workflow_engine
+        .ask(ResumeWorkflow(execution_id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn cancel_workflow(
+    execution_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<WorkflowExecution> {
+    Ok(state
+        .actors
+        .workflow_engine
+        .ask(CancelWorkflow(execution_id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn list_workflow_executions(
+    filter: WorkflowExecutionFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<WorkflowExecution>> {
+    Ok(state.actors.db.ask(ListWorkflowExecutions(filter)).await?)
+}
+
+#[tauri::command]
+pub async fn list_workflow_step_executions(
+    execution_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<Vec<WorkflowStepExecution>> {
+    Ok(state
+        .actors
+        .db
+        .ask(ListWorkflowStepExecutions(execution_id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn list_peer_permissions(
+    filter: PeerPermissionFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<PeerPermission>> {
+    Ok(state.actors.db.ask(ListPeerPermissions(filter)).await?)
+}
+
+/// Approves, denies or changes the grants of a remote peer
+#[tauri::command]
+pub async fn update_peer_permission(
+    permission: PeerPermission,
+    state: State<'_, AppState>,
+) -> Result<PeerPermission> {
+    Ok(state.actors.db.ask(UpdatePeerPermission(permission)).await?)
+}
+
+#[tauri::command]
+pub async fn list_audit_log(
+    filter: AuditLogFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<AuditLogEntry>> {
+    Ok(state.actors.db.ask(ListAuditLog(filter)).await?)
+}
+
+#[tauri::command]
+pub async fn get_network_config(state: State<'_, AppState>) -> Result<NetworkConfig> {
+    Ok(state.actors.db.ask(GetNetworkConfig).await?)
+}
+
+/// Stores the network config, which is applied the next time the app starts
+#[tauri::command]
+pub async fn update_network_config(
+    config: NetworkConfig,
+    state: State<'_, AppState>,
+) -> Result<()> {
+    Ok(state.actors.db.ask(SaveNetworkConfig(config)).await?)
+}
+
+/// Lists the known nodes, set `order_by_quality` to get the best links first
+#[tauri::command]
+pub async fn list_p2p_nodes(
+    filter: P2pNodeFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<P2pNode>> {
+    Ok(state.actors.db.ask(ListP2pNodes(filter)).await?)
+}
+
+#[tauri::command]
+pub async fn get_network_stats(state: State<'_, AppState>) -> Result<P2pNetworkStats> {
+    Ok(state.actors.db.ask(GetNetworkStats).await?)
+}
+
+/// Lets peers run an agent, or changes the limits every peer is held to
+#[tauri::command]
+pub async fn publish_agent(
+    agent_id: Uuid,
+    max_concurrent_requests: i64,
+    daily_token_quota: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<PublishedAgent> {
+    Ok(state
+        .actors
+        .db
+        .ask(PublishAgent {
+            agent_id,
+            max_concurrent_requests,
+            daily_token_quota,
+        })
+        .await?)
+}
+
+#[tauri::command]
+pub async fn unpublish_agent(agent_id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    Ok(state.actors.db.ask(UnpublishAgent(agent_id)).await?)
+}
+
+#[tauri::command]
+pub async fn list_published_agents(state: State<'_, AppState>) -> Result<Vec<PeerAgent>> {
+    Ok(state.actors.db.ask(ListPeerAgents).await?)
+}
+
+/// Lists the agents a peer publishes that we may run
+#[tauri::command]
+pub async fn list_peer_agents(peer_id: PeerIdWrapper) -> Result<Vec<PeerAgent>> {
+    hosting::list_peer_agents(&peer_id.0).await
+}
+
+/// Runs an agent of a peer, its response is emitted as `peer-agent-response` events
+#[tauri::command]
+pub async fn prompt_peer_agent(
+    peer_id: PeerIdWrapper,
+    request: RemoteAgentRequest,
+    state: State<'_, AppState>,
+) -> Result<()> {
+    hosting::prompt_peer_agent(state.actors.bus.clone(), peer_id.0, request).await
+}
+
+/// Lets peers download a file, for example the file of an attachment sent to them.
+/// Use the attachment's `file_id` so that they know which file to download.
+#[tauri::command]
+pub async fn share_file(
+    path: PathBuf,
+    peer_ids: Vec<PeerIdWrapper>,
+    file_id: Option<Uuid>,
+    state: State<'_, AppState>,
+) -> Result<FileManifest> {
+    Ok(state
+        .actors
+        .file_transfers
+        .ask(ShareFile {
+            path,
+            peer_ids: peer_ids.into_iter().map(|peer_id| peer_id.0).collect(),
+            file_id,
+        })
+        .await?)
+}
+
+/// Downloads a file a peer shared with us, progress is emitted as
+/// `file-transfer-progress` events
+#[tauri::command]
+pub async fn download_file(
+    peer_id: PeerIdWrapper,
+    file_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<FileTransfer> {
+    Ok(state
+        .actors
+        .file_transfers
+        .ask(DownloadFile {
+            peer_id: peer_id.0,
+            file_id,
+        })
+        .await?)
+}
+
+#[tauri::command]
+pub async fn cancel_file_transfer(id: Uuid, state: State<'_, AppState>) -> Result<FileTransfer> {
+    Ok(state
+        .actors
+        .file_transfers
+        .ask(CancelFileTransfer(id))
+        .await?)
+}
+
+#[tauri::command]
+pub async fn list_file_transfers(
+    filter: FileTransferFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<FileTransfer>> {
+    Ok(state.actors.db.ask(ListFileTransfers(filter)).await?)
+}
+
+#[tauri::command]
+pub async fn get_file_transfer_policy(state: State<'_, AppState>) -> Result<TransferPolicy> {
+    Ok(state.actors.db.ask(GetTransferPolicy).await?)
+}
+
+#[tauri::command]
+pub async fn update_file_transfer_policy(
+    policy: TransferPolicy,
+    state: State<'_, AppState>,
+) -> Result<()> {
+    Ok(state.actors.db.ask(SaveTransferPolicy(policy)).await?)
+}
+
+/// The user key's certificate of this device
+#[tauri::command]
+pub fn get_device_certificate() -> DeviceCertificate {
+    KEYSTORE.read().unwrap().certificate().clone()
+}
+
+/// Replaces our user or device key and announces it to our contacts. A new device key,
+/// and so a new peer id, is used from the next start.
+#[tauri::command]
+pub async fn rotate_key(kind: KeyKind, state: State<'_, AppState>) -> Result<KeyRotation> {
+    Ok(state.actors.identity.ask(RotateKey(kind)).await?)
+}
+
+#[tauri::command]
+pub fn list_key_rotations() -> Vec<KeyRotation> {
+    KEYSTORE.read().unwrap().rotations().to_vec()
+}
+
+#[tauri::command]
+pub async fn get_peer_certificate(
+    peer_id: PeerIdWrapper,
+    state: State<'_, AppState>,
+) -> Result<Option<PeerCertificate>> {
+    Ok(state.actors.db.ask(GetPeerCertificate(peer_id)).await?)
+}
+
+/// Adds an MCP server, and connects to it when it is active
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_mcp_server(
+    name: String,
+    description: Option<String>,
+    transport: McpTransport,
+    url: Option<String>,
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    api_key: Option<String>,
+    auth_token: Option<String>,
+    is_active: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<McpServer> {
+    let now = Utc::now();
+    let server = state
+        .actors
+        .db
+        .ask(CreateMcpServer(McpServer {
+            id: Uuid::new_v4(),
+            name,
+            description: description.unwrap_or_default(),
+            transport,
+            url,
+            command,
+            args: Json(args.unwrap_or_default()),
+            env: Json(env.unwrap_or_default()),
+            api_key,
+            auth_token,
+            is_active: is_active.unwrap_or(true),
+            is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
+            created_at: now,
+            updated_at: now,
+        }))
+        .await?;
+    if server.is_active {
+        state
+            .actors
+            .mcp_manager
+            .tell(ConnectMcpServer(server.id))
+            .await?;
+    }
+    Ok(server)
+}
+
+#[tauri::command]
+pub async fn list_mcp_servers(
+    filter: McpServerFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<McpServer>> {
+    Ok(state.actors.db.ask(ListMcpServers(filter)).await?)
+}
+
+/// Connects to a server and starts using it, the tools it offers are returned
+#[tauri::command]
+pub async fn connect_mcp_server(id: Uuid, state: State<'_, AppState>) -> Result<Vec<McpTool>> {
+    state
+        .actors
+        .db
+        .ask(SetMcpServerActive {
+            id,
+            is_active: true,
+        })
+        .await?;
+    Ok(state.actors.mcp_manager.ask(ConnectMcpServer(id)).await?)
+}
+
+/// Stops using a server, also after the next start
+#[tauri::command]
+pub async fn disconnect_mcp_server(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    state
+        .actors
+        .db
+        .ask(SetMcpServerActive {
+            id,
+            is_active: false,
+        })
+        .await?;
+    Ok(state.actors.mcp_manager.ask(DisconnectMcpServer(id)).await?)
+}
+
+#[tauri::command]
+pub async fn delete_mcp_server(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    state.actors.mcp_manager.ask(DisconnectMcpServer(id)).await?;
+    Ok(state.actors.db.ask(DeleteMcpServer(id)).await?)
+}
+
+#[tauri::command]
+pub async fn list_mcp_tools(
+    filter: McpToolFilter,
+    state: State<'_, AppState>,
+) -> Result<Vec<McpTool>> {
+    Ok(state.actors.db.ask(ListMcpTools(filter)).await?)
+}
+
+/// Lists the tools of a connected server again
+#[tauri::command]
+pub async fn refresh_mcp_tools(id: Uuid, state: State<'_, AppState>) -> Result<Vec<McpTool>> {
+    Ok(state.actors.mcp_manager.ask(RefreshMcpTools(id)).await?)
+}
+
+/// Offers a tool of a server to agents, or stops offering it
+#[tauri::command]
+pub async fn set_mcp_tool_enabled(
+    id: Uuid,
+    is_enabled: bool,
+    state: State<'_, AppState>,
+) -> Result<McpTool> {
+    Ok(state
+        .actors
+        .mcp_manager
+        .ask(SetMcpToolAvailable { id, is_enabled })
+        .await?)
+}
+
+#[tauri::command]
+pub async fn get_mcp_endpoint_settings(
+    state: State<'_, AppState>,
+) -> Result<McpEndpointSettings> {
+    Ok(state.actors.db.ask(GetMcpEndpointSettings).await?)
+}
+
+/// Stores the settings of our MCP endpoint, and starts or stops it accordingly
+#[tauri::command]
+pub async fn update_mcp_endpoint_settings(
+    settings: McpEndpointSettings,
+    state: State<'_, AppState>,
+) -> Result<()> {
+    Ok(state
+        .actors
+        .mcp_endpoint
+        .ask(UpdateMcpEndpointSettings(settings))
+        .await?)
+}
+
+/// Creates an API key for MCP clients. The key itself is only returned here, we
+/// only store its hash.
+#[tauri::command]
+pub async fn create_mcp_api_key(
+    account_id: Uuid,
+    name: String,
+    description: String,
+    scopes: Vec<String>,
+    rate_limit: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<IssuedApiKey> {
+    let key = generate_api_key();
+    let api_key = state
+        .actors
+        .db
+        .ask(CreateApiKey {
+            account_id,
+            name,
+            description,
+            key_hash: hash_api_key(&key),
+            scopes: Json(serde_json::json!(scopes)),
+            rate_limit,
+            is_active: true,
+        })
+        .await?;
+    Ok(IssuedApiKey { api_key, key })
+}
+
+#[tauri::command]
+pub async fn list_api_keys(filter: ApiKeyFilter, state: State<'_, AppState>) -> Result<Vec<ApiKey>> {
+    Ok(state.actors.db.ask(ListApiKeys(filter)).await?)
+}
+
+/// Revokes an API key, clients using it are refused from their next request
+#[tauri::command]
+pub async fn delete_api_key(id: Uuid, state: State<'_, AppState>) -> Result<()> {
+    Ok(state.actors.db.ask(DeleteApiKey(id)).await?)
+}
+
+/// The built-in tools with their configurations, each starts out inactive
+#[tauri::command]
+pub async fn list_builtin_tools(state: State<'_, AppState>) -> Result<Vec<ToolRecord>> {
+    state.actors.builtin_tools.list().await
+}
+
+/// Configures a built-in tool, `None` for its defaults, and activates or deactivates it
+#[tauri::command]
+pub async fn configure_builtin_tool(
+    name: String,
+    config: Option<serde_json::Value>,
+    is_active: bool,
+    state: State<'_, AppState>,
+) -> Result<ToolRecord> {
+    state
+        .actors
+        .builtin_tools
+        .configure(&name, config, is_active)
+        .await
+}
+
+#[tauri::command]
+pub async fn list_agent_tools(
+    agent_id: Uuid,
+    state: State<'_, AppState>,
+) -> Result<Vec<ToolRecord>> {
+    Ok(state.actors.db.ask(ListAgentTools(agent_id)).await?)
+}
+
+/// Replaces the tools linked to an agent, built-in tools are offered only to the agents
+/// they're linked to or listed in the `tool_config` of
+#[tauri::command]
+pub async fn set_agent_tools(
+    agent_id: Uuid,
+    tool_ids: Vec<Uuid>,
+    state: State<'_, AppState>,
+) -> Result<()> {
+    Ok(state
+        .actors
+        .db
+        .ask(SetAgentTools { agent_id, tool_ids })
+        .await?)
+}
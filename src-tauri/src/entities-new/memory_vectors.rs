@@ -1,12 +1,17 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::prelude::FromRow;
+use sqlx::sqlite::SqliteRow;
 use sqlx::{QueryBuilder, Row, Sqlite};
 use tracing::{debug, instrument};
 use uuid::Uuid;
 
 use crate::error::{AppError, Result};
 use crate::storage::db::DatabaseManager;
+use crate::storage::vector::{SearchFilter, VectorEntry, VectorStore, VectorStoreConfig};
 
 /// Memory vector source type
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
@@ -46,6 +51,7 @@ pub struct MemoryVector {
     pub text: String,
     pub vector: Vec<f32>,
     pub model_id: Option<Uuid>,
+    pub workspace_id: Option<Uuid>, // None for vectors shared by all workspaces
     pub metadata: String, // JSON object with additional metadata
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -61,6 +67,8 @@ pub struct MemoryVectorFilter {
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub model_id: Option<Uuid>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub workspace_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
     pub search_term: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub created_after: Option<DateTime<Utc>>,
@@ -72,6 +80,77 @@ pub struct MemoryVectorFilter {
     pub offset: Option<usize>,
 }
 
+/// Restricts a similarity search to comparable vectors
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryVectorScope {
+    /// Embedding model the query was embedded with, `None` for vectors without a model
+    #[serde(default)]
+    pub model_id: Option<Uuid>,
+    #[serde(default)]
+    pub source_type: Option<MemoryVectorSourceType>,
+    /// Vectors of other workspaces are skipped, vectors without a workspace always match
+    #[serde(default)]
+    pub workspace_id: Option<Uuid>,
+}
+
+/// Collections smaller than this are searched exactly instead of through the index
+const EXACT_SEARCH_THRESHOLD: usize = 1000;
+
+const COLUMNS: &str = "id, source_id, source_type, text, vector, model_id, workspace_id,
+                metadata, created_at, updated_at";
+
+/// Name of the index collection holding the vectors of an embedding model
+fn index_name(model_id: Option<Uuid>) -> String {
+    match model_id {
+        Some(model_id) => format!("memory-{model_id}"),
+        None => "memory-default".to_string(),
+    }
+}
+
+fn index_entry(memory_vector: &MemoryVector) -> VectorEntry {
+    VectorEntry {
+        id: memory_vector.id,
+        workspace_id: memory_vector.workspace_id,
+        text: memory_vector.text.clone(),
+        embedding: memory_vector.vector.clone(),
+        metadata: json!({
+            "sourceId": memory_vector.source_id,
+            "sourceType": memory_vector.source_type as i32,
+        }),
+    }
+}
+
+fn parse_uuid(bytes: Vec<u8>) -> Result<Uuid> {
+    Uuid::from_slice(&bytes).map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))
+}
+
+fn memory_vector_from_row(row: &SqliteRow) -> Result<MemoryVector> {
+    let vector_json: String = row.get("vector");
+    let vector: Vec<f32> = serde_json::from_str(&vector_json).map_err(|e| {
+        AppError::DeserializationError(format!("Failed to deserialize vector: {e}"))
+    })?;
+
+    Ok(MemoryVector {
+        id: parse_uuid(row.get("id"))?,
+        source_id: parse_uuid(row.get("source_id"))?,
+        source_type: MemoryVectorSourceType::try_from(row.get::<i64, _>("source_type") as i32)?,
+        text: row.get("text"),
+        vector,
+        model_id: row
+            .get::<Option<Vec<u8>>, _>("model_id")
+            .map(parse_uuid)
+            .transpose()?,
+        workspace_id: row
+            .get::<Option<Vec<u8>>, _>("workspace_id")
+            .map(parse_uuid)
+            .transpose()?,
+        metadata: row.get("metadata"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
 impl DatabaseManager {
     /// Create a new memory vector in the database
     #[instrument(skip(self))]
@@ -85,10 +164,10 @@ impl DatabaseManager {
 
         let _result = sqlx::query(
             "INSERT INTO memory_vectors (
-                id, source_id, source_type, text, vector, model_id,
+                id, source_id, source_type, text, vector, model_id, workspace_id,
                 metadata, created_at, updated_at
             ) VALUES (
-                ?, ?, ?, ?, ?, ?, ?, ?, ?
+                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
             )",
         )
         .bind(memory_vector.id)
@@ -97,13 +176,14 @@ impl DatabaseManager {
         .bind(&memory_vector.text)
         .bind(&vector_json)
         .bind(memory_vector.model_id)
+        .bind(memory_vector.workspace_id)
         .bind(&memory_vector.metadata)
         .bind(memory_vector.created_at)
         .bind(memory_vector.updated_at)
         .execute(&self.pool)
         .await?;
 
-        Ok(())
+        self.index_memory_vector(memory_vector).await
     }
 
     /// Get a memory vector by ID
@@ -111,51 +191,12 @@ impl DatabaseManager {
     pub async fn get_memory_vector_by_id(&self, id: &Uuid) -> Result<Option<MemoryVector>> {
         debug!("Getting memory vector by ID: {}", id);
 
-        let row = sqlx::query(
-            r#"SELECT 
-                id, source_id, source_type, text, vector, model_id,
-                metadata, created_at, updated_at
-            FROM memory_vectors WHERE id = ?"#,
-        )
-        .bind(id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        if let Some(row) = row {
-            let vector_json: String = row.get("vector");
-            let vector: Vec<f32> = serde_json::from_str(&vector_json).map_err(|e| {
-                AppError::DeserializationError(format!("Failed to deserialize vector: {e}"))
-            })?;
+        let row = sqlx::query(&format!("SELECT {COLUMNS} FROM memory_vectors WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
 
-            let memory_vector = MemoryVector {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                source_id: row
-                    .get::<Vec<u8>, _>("source_id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                source_type: MemoryVectorSourceType::try_from(
-                    row.get::<i64, _>("source_type") as i32
-                )?,
-                text: row.get("text"),
-                vector,
-                model_id: row
-                    .get::<Option<Vec<u8>>, _>("model_id")
-                    .map(|v| {
-                        v.try_into()
-                            .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))
-                    })
-                    .transpose()?,
-                metadata: row.get("metadata"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            };
-            Ok(Some(memory_vector))
-        } else {
-            Ok(None)
-        }
+        row.as_ref().map(memory_vector_from_row).transpose()
     }
 
     /// List and filter memory vectors
@@ -166,12 +207,8 @@ impl DatabaseManager {
     ) -> Result<Vec<MemoryVector>> {
         debug!("Listing memory vectors with filter: {:?}", filter);
 
-        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
-            r#"SELECT 
-                id, source_id, source_type, text, vector, model_id,
-                metadata, created_at, updated_at
-            FROM memory_vectors"#,
-        );
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM memory_vectors"));
 
         let mut where_conditions: Vec<String> = Vec::new();
 
@@ -187,6 +224,10 @@ impl DatabaseManager {
             where_conditions.push(format!("model_id = '{model_id}'"));
         }
 
+        if let Some(workspace_id) = &filter.workspace_id {
+            where_conditions.push(format!("workspace_id = '{workspace_id}'"));
+        }
+
         if let Some(search_term) = &filter.search_term {
             where_conditions.push(format!(
                 "(text LIKE '%{search_term}%' OR metadata LIKE '%{search_term}%')"
@@ -220,42 +261,7 @@ impl DatabaseManager {
 
         let rows = qb.build().fetch_all(&self.pool).await?;
 
-        let mut memory_vectors = Vec::with_capacity(rows.len());
-        for row in rows {
-            let vector_json: String = row.get("vector");
-            let vector: Vec<f32> = serde_json::from_str(&vector_json).map_err(|e| {
-                AppError::DeserializationError(format!("Failed to deserialize vector: {e}"))
-            })?;
-
-            let memory_vector = MemoryVector {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                source_id: row
-                    .get::<Vec<u8>, _>("source_id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                source_type: MemoryVectorSourceType::try_from(
-                    row.get::<i64, _>("source_type") as i32
-                )?,
-                text: row.get("text"),
-                vector,
-                model_id: row
-                    .get::<Option<Vec<u8>>, _>("model_id")
-                    .map(|v| {
-                        v.try_into()
-                            .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))
-                    })
-                    .transpose()?,
-                metadata: row.get("metadata"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            };
-            memory_vectors.push(memory_vector);
-        }
-
-        Ok(memory_vectors)
+        rows.iter().map(memory_vector_from_row).collect()
     }
 
     /// Update a memory vector
@@ -268,10 +274,12 @@ impl DatabaseManager {
             AppError::DeserializationError(format!("Failed to serialize vector: {e}"))
         })?;
 
+        let previous = self.get_memory_vector_by_id(&memory_vector.id).await?;
+
         let affected = sqlx::query(
             "UPDATE memory_vectors SET 
                 source_id = ?, source_type = ?, text = ?, vector = ?, model_id = ?,
-                metadata = ?, updated_at = ?
+                workspace_id = ?, metadata = ?, updated_at = ?
             WHERE id = ?",
         )
         .bind(memory_vector.source_id)
@@ -279,6 +287,7 @@ impl DatabaseManager {
         .bind(&memory_vector.text)
         .bind(&vector_json)
         .bind(memory_vector.model_id)
+        .bind(memory_vector.workspace_id)
         .bind(&memory_vector.metadata)
         .bind(memory_vector.updated_at)
         .bind(memory_vector.id)
@@ -293,7 +302,11 @@ impl DatabaseManager {
             )));
         }
 
-        Ok(())
+        // Re-inserting replaces the entry, but a new model means a different collection
+        if let Some(previous) = previous.filter(|p| p.model_id != memory_vector.model_id) {
+            self.unindex_memory_vector(&previous).await?;
+        }
+        self.index_memory_vector(memory_vector).await
     }
 
     /// Update memory vector metadata
@@ -326,19 +339,18 @@ impl DatabaseManager {
     pub async fn delete_memory_vector(&self, id: &Uuid) -> Result<()> {
         debug!("Deleting memory vector with ID: {}", id);
 
-        let affected = sqlx::query("DELETE FROM memory_vectors WHERE id = ?")
-            .bind(id)
-            .execute(&self.pool)
-            .await?
-            .rows_affected();
-
-        if affected == 0 {
+        let Some(memory_vector) = self.get_memory_vector_by_id(id).await? else {
             return Err(AppError::NotFoundError(format!(
                 "Memory vector with ID {id} not found for delete"
             )));
-        }
+        };
 
-        Ok(())
+        sqlx::query("DELETE FROM memory_vectors WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        self.unindex_memory_vector(&memory_vector).await
     }
 
     /// Delete memory vectors by source ID and type
@@ -353,6 +365,10 @@ impl DatabaseManager {
             source_id, source_type
         );
 
+        let memory_vectors = self
+            .get_memory_vectors_by_source(source_id, source_type, None)
+            .await?;
+
         let result =
             sqlx::query("DELETE FROM memory_vectors WHERE source_id = ? AND source_type = ?")
                 .bind(source_id)
@@ -360,6 +376,10 @@ impl DatabaseManager {
                 .execute(&self.pool)
                 .await?;
 
+        for memory_vector in &memory_vectors {
+            self.unindex_memory_vector(memory_vector).await?;
+        }
+
         Ok(result.rows_affected() as usize)
     }
 
@@ -422,29 +442,40 @@ impl DatabaseManager {
         self.list_memory_vectors(&filter).await
     }
 
-    /// Perform vector similarity search
+    /// Perform vector similarity search within a scope.
+    ///
+    /// Large collections are searched through the ANN index of the scope's model,
+    /// small ones are compared exactly against every vector of the scope.
     #[instrument(skip(self, query_vector))]
     pub async fn search_memory_vectors_by_similarity(
         &self,
         query_vector: &[f32],
+        scope: &MemoryVectorScope,
         limit: Option<usize>,
         threshold: Option<f32>,
     ) -> Result<Vec<(MemoryVector, f32)>> {
         debug!("Performing vector similarity search");
 
-        // Get all memory vectors
-        let memory_vectors = self
-            .list_memory_vectors(&MemoryVectorFilter::default())
-            .await?;
+        let name = index_name(scope.model_id);
+        let store = match self.vectors.get(&name).await? {
+            Some(store) => Some(store),
+            // The index may not exist yet for vectors created before it was introduced
+            None if self.count_memory_vectors_by_model(scope.model_id).await?
+                >= EXACT_SEARCH_THRESHOLD as i64 =>
+            {
+                self.rebuild_memory_vector_index(scope.model_id).await?;
+                self.vectors.get(&name).await?
+            }
+            None => None,
+        };
 
-        // Calculate cosine similarity for each vector
-        let mut results: Vec<(MemoryVector, f32)> = memory_vectors
-            .into_iter()
-            .map(|mv| {
-                let similarity = cosine_similarity(query_vector, &mv.vector);
-                (mv, similarity)
-            })
-            .collect();
+        let mut results = match store {
+            Some(store) if store.len().await >= EXACT_SEARCH_THRESHOLD => {
+                self.search_memory_vector_index(&store, query_vector, scope, limit)
+                    .await?
+            }
+            _ => self.search_memory_vectors_exact(query_vector, scope).await?,
+        };
 
         // Filter by threshold if provided
         if let Some(threshold_value) = threshold {
@@ -462,6 +493,130 @@ impl DatabaseManager {
         Ok(results)
     }
 
+    async fn search_memory_vector_index(
+        &self,
+        store: &VectorStore,
+        query_vector: &[f32],
+        scope: &MemoryVectorScope,
+        limit: Option<usize>,
+    ) -> Result<Vec<(MemoryVector, f32)>> {
+        let mut filter = SearchFilter::new().workspace(scope.workspace_id);
+        if let Some(source_type) = scope.source_type {
+            filter = filter.eq("sourceType", source_type as i32);
+        }
+        let k = match limit {
+            Some(limit) => limit,
+            None => store.len().await,
+        };
+        let hits = store.search(query_vector, k, &filter).await?;
+        if hits.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let scores: HashMap<Uuid, f32> = hits.iter().map(|hit| (hit.entry.id, hit.score)).collect();
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            "SELECT {COLUMNS} FROM memory_vectors WHERE id IN ("
+        ));
+        let mut ids = qb.separated(", ");
+        for id in scores.keys() {
+            ids.push_bind(*id);
+        }
+        qb.push(")");
+        let rows = qb.build().fetch_all(&self.pool).await?;
+
+        rows.iter()
+            .map(|row| {
+                let memory_vector = memory_vector_from_row(row)?;
+                let score = scores[&memory_vector.id];
+                Ok((memory_vector, score))
+            })
+            .collect()
+    }
+
+    async fn search_memory_vectors_exact(
+        &self,
+        query_vector: &[f32],
+        scope: &MemoryVectorScope,
+    ) -> Result<Vec<(MemoryVector, f32)>> {
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM memory_vectors WHERE "));
+        match scope.model_id {
+            Some(model_id) => qb.push("model_id = ").push_bind(model_id),
+            None => qb.push("model_id IS NULL"),
+        };
+        if let Some(source_type) = scope.source_type {
+            qb.push(" AND source_type = ").push_bind(source_type as i32);
+        }
+        if let Some(workspace_id) = scope.workspace_id {
+            qb.push(" AND (workspace_id IS NULL OR workspace_id = ")
+                .push_bind(workspace_id)
+                .push(")");
+        }
+        let rows = qb.build().fetch_all(&self.pool).await?;
+
+        rows.iter()
+            .map(|row| {
+                let memory_vector = memory_vector_from_row(row)?;
+                let similarity = cosine_similarity(query_vector, &memory_vector.vector);
+                Ok((memory_vector, similarity))
+            })
+            .collect()
+    }
+
+    /// Re-creates the ANN index of an embedding model from the stored vectors
+    #[instrument(err, skip(self))]
+    pub async fn rebuild_memory_vector_index(&self, model_id: Option<Uuid>) -> Result<usize> {
+        let memory_vectors: Vec<MemoryVector> = self
+            .list_memory_vectors(&MemoryVectorFilter {
+                model_id,
+                ..Default::default()
+            })
+            .await?
+            .into_iter()
+            .filter(|memory_vector| memory_vector.model_id == model_id)
+            .collect();
+
+        if let Some(store) = self.vectors.get(&index_name(model_id)).await? {
+            store.delete_where(&SearchFilter::new()).await?;
+        }
+        for memory_vector in &memory_vectors {
+            self.index_memory_vector(memory_vector).await?;
+        }
+
+        debug!("Rebuilt memory vector index with {} vectors", memory_vectors.len());
+        Ok(memory_vectors.len())
+    }
+
+    async fn index_memory_vector(&self, memory_vector: &MemoryVector) -> Result<()> {
+        if memory_vector.vector.is_empty() {
+            return Ok(());
+        }
+        let store = self
+            .vectors
+            .open(
+                &index_name(memory_vector.model_id),
+                VectorStoreConfig::with_dimension(memory_vector.vector.len()),
+            )
+            .await?;
+        Ok(store.insert(index_entry(memory_vector)).await?)
+    }
+
+    async fn unindex_memory_vector(&self, memory_vector: &MemoryVector) -> Result<()> {
+        if let Some(store) = self.vectors.get(&index_name(memory_vector.model_id)).await? {
+            store.delete(&memory_vector.id).await?;
+        }
+        Ok(())
+    }
+
+    async fn count_memory_vectors_by_model(&self, model_id: Option<Uuid>) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM memory_vectors WHERE model_id IS ?")
+            .bind(model_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<i64, _>("count"))
+    }
+
     /// Count memory vectors by source type
     #[instrument(skip(self))]
     pub async fn count_memory_vectors_by_source_type(
@@ -527,6 +682,7 @@ mod tests {
             text: "This is a test message for vector embedding".to_string(),
             vector: create_test_vector(384), // Common embedding dimension
             model_id: Some(model_id),
+            workspace_id: None,
             metadata: r#"{"importance": "high", "context": "test"}"#.to_string(),
             created_at: now,
             updated_at: now,
@@ -585,6 +741,7 @@ mod tests {
                 text: format!("Test content {}", i),
                 vector: create_test_vector(384),
                 model_id: Some(model_id),
+                workspace_id: None,
                 metadata: format!(r#"{{"index": {}}}"#, i),
                 created_at: now,
                 updated_at: now,
@@ -630,6 +787,7 @@ mod tests {
         // Filter by model_id
         let filter = MemoryVectorFilter {
             model_id: Some(model_id),
+            workspace_id: None,
             ..Default::default()
         };
         let vectors = db
@@ -698,6 +856,7 @@ mod tests {
             text: "Original text".to_string(),
             vector: create_test_vector(384),
             model_id: Some(model_id),
+            workspace_id: None,
             metadata: r#"{"original": true}"#.to_string(),
             created_at: now,
             updated_at: now,
@@ -717,6 +876,7 @@ mod tests {
             text: "Updated text".to_string(),
             vector: create_test_vector(384), // Different vector values
             model_id: Some(model_id),
+            workspace_id: None,
             metadata: r#"{"updated": true}"#.to_string(),
             created_at: vector.created_at,
             updated_at: Utc::now(),
@@ -752,6 +912,7 @@ mod tests {
             text: "Test text".to_string(),
             vector: create_test_vector(384),
             model_id: None,
+            workspace_id: None,
             metadata: r#"{"original": true}"#.to_string(),
             created_at: now,
             updated_at: now,
@@ -791,6 +952,7 @@ mod tests {
             text: "Test text".to_string(),
             vector: create_test_vector(384),
             model_id: None,
+            workspace_id: None,
             metadata: r#"{"test": true}"#.to_string(),
             created_at: now,
             updated_at: now,
@@ -832,6 +994,7 @@ mod tests {
                 text: format!("Test text {}", i),
                 vector: create_test_vector(384),
                 model_id: None,
+                workspace_id: None,
                 metadata: r#"{"test": true}"#.to_string(),
                 created_at: now,
                 updated_at: now,
@@ -913,6 +1076,7 @@ mod tests {
                 text: text.clone(),
                 vector: vec.clone(),
                 model_id: None,
+                workspace_id: None,
                 metadata: r#"{"test": true}"#.to_string(),
                 created_at: now,
                 updated_at: now,
@@ -925,10 +1089,11 @@ mod tests {
 
         // Query vector (very similar to the first vector)
         let query_vector = vec![0.11, 0.21, 0.31, 0.41, 0.51];
+        let scope = MemoryVectorScope::default();
 
         // Search with no threshold
         let results = db
-            .search_memory_vectors_by_similarity(&query_vector, None, None)
+            .search_memory_vectors_by_similarity(&query_vector, &scope, None, None)
             .await
             .expect("Failed to search by similarity");
 
@@ -942,7 +1107,7 @@ mod tests {
 
         // Search with threshold
         let results = db
-            .search_memory_vectors_by_similarity(&query_vector, None, Some(0.8))
+            .search_memory_vectors_by_similarity(&query_vector, &scope, None, Some(0.8))
             .await
             .expect("Failed to search by similarity with threshold");
 
@@ -952,7 +1117,7 @@ mod tests {
 
         // Search with limit
         let results = db
-            .search_memory_vectors_by_similarity(&query_vector, Some(2), None)
+            .search_memory_vectors_by_similarity(&query_vector, &scope, Some(2), None)
             .await
             .expect("Failed to search by similarity with limit");
 
@@ -961,6 +1126,87 @@ mod tests {
         assert_eq!(results[1].0.id, vectors[1].0);
     }
 
+    #[tokio::test]
+    async fn test_similarity_search_is_scoped() {
+        let db = DatabaseManager::setup_test_db().await;
+        let workspace_id = Uuid::new_v4();
+        let model_id = Uuid::new_v4();
+        let now = Utc::now();
+
+        let vectors = [
+            (Some(workspace_id), Some(model_id), MemoryVectorSourceType::Message),
+            (None, Some(model_id), MemoryVectorSourceType::Message),
+            (Some(Uuid::new_v4()), Some(model_id), MemoryVectorSourceType::Message),
+            (Some(workspace_id), Some(model_id), MemoryVectorSourceType::Document),
+            (Some(workspace_id), None, MemoryVectorSourceType::Message),
+        ];
+        for (workspace, model, source_type) in vectors {
+            db.create_memory_vector(&MemoryVector {
+                id: Uuid::new_v4(),
+                source_id: Uuid::new_v4(),
+                source_type,
+                text: "Scoped text".to_string(),
+                vector: vec![1.0, 0.0, 0.0],
+                model_id: model,
+                workspace_id: workspace,
+                metadata: "{}".to_string(),
+                created_at: now,
+                updated_at: now,
+            })
+            .await
+            .expect("Failed to create memory vector");
+        }
+
+        let scope = MemoryVectorScope {
+            model_id: Some(model_id),
+            source_type: Some(MemoryVectorSourceType::Message),
+            workspace_id: Some(workspace_id),
+        };
+        let results = db
+            .search_memory_vectors_by_similarity(&[1.0, 0.0, 0.0], &scope, None, None)
+            .await
+            .expect("Failed to search by similarity");
+
+        // Only the workspace's own vector and the global one match
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(v, _)| v.workspace_id.is_none_or(|id| id == workspace_id)));
+    }
+
+    #[tokio::test]
+    async fn test_index_follows_create_and_delete() {
+        let db = DatabaseManager::setup_test_db().await;
+        let model_id = Uuid::new_v4();
+        let now = Utc::now();
+        let vector = MemoryVector {
+            id: Uuid::new_v4(),
+            source_id: Uuid::new_v4(),
+            source_type: MemoryVectorSourceType::Message,
+            text: "Indexed text".to_string(),
+            vector: create_test_vector(8),
+            model_id: Some(model_id),
+            workspace_id: None,
+            metadata: "{}".to_string(),
+            created_at: now,
+            updated_at: now,
+        };
+
+        db.create_memory_vector(&vector)
+            .await
+            .expect("Failed to create memory vector");
+        let store = db
+            .vectors
+            .get(&index_name(Some(model_id)))
+            .await
+            .unwrap()
+            .expect("Index was not created");
+        assert!(store.get(&vector.id).await.is_some());
+
+        db.delete_memory_vector(&vector.id)
+            .await
+            .expect("Failed to delete memory vector");
+        assert!(store.get(&vector.id).await.is_none());
+    }
+
     #[test]
     fn test_cosine_similarity() {
         // Test identical vectors
@@ -0,0 +1,289 @@
+use boilermates::boilermates;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::{QueryBuilder, Sqlite};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionStatus {
+    Pending = 0,
+    Running = 1,
+    Completed = 2,
+    Failed = 3,
+    Cancelled = 4,
+}
+
+/// A run of an agent chain
+#[skip_serializing_none]
+#[boilermates("CreateAgentChainExecution")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentChainExecution {
+    #[boilermates(not_in("CreateAgentChainExecution"))]
+    pub id: Uuid,
+    pub chain_id: Uuid,
+    pub conversation_id: Option<Uuid>,
+    pub triggered_by_id: Option<Uuid>,
+    pub status: ExecutionStatus,
+    pub current_step_id: Option<Uuid>,
+    pub input_data: Option<String>,
+    pub output_data: Option<String>,
+    pub error_details: Option<String>,
+    #[boilermates(not_in("CreateAgentChainExecution"))]
+    pub started_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateAgentChainExecution"))]
+    pub completed_at: Option<DateTime<Utc>>,
+    #[boilermates(not_in("CreateAgentChainExecution"))]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A single attempt at a step of a chain run. Subtasks that an operator delegated
+/// to its workers reference the operator's step execution.
+#[skip_serializing_none]
+#[boilermates("CreateAgentChainStepExecution")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentChainStepExecution {
+    #[boilermates(not_in("CreateAgentChainStepExecution"))]
+    pub id: Uuid,
+    pub execution_id: Uuid,
+    pub step_id: Uuid,
+    pub agent_id: Uuid,
+    pub delegated_by_id: Option<Uuid>,
+    pub status: ExecutionStatus,
+    pub input_data: Option<String>,
+    pub output_data: Option<String>,
+    pub error_details: Option<String>,
+    pub retry_attempt: i64,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    #[boilermates(not_in("CreateAgentChainStepExecution"))]
+    pub created_at: DateTime<Utc>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentChainExecutionFilter {
+    pub chain_id: Option<Uuid>,
+    pub conversation_id: Option<Uuid>,
+    pub triggered_by_id: Option<Uuid>,
+    pub status: Option<ExecutionStatus>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+const EXECUTION_COLUMNS: &str = "id, chain_id, conversation_id, triggered_by_id, status, \
+    current_step_id, input_data, output_data, error_details, started_at, completed_at, created_at";
+
+const STEP_EXECUTION_COLUMNS: &str = "id, execution_id, step_id, agent_id, delegated_by_id, \
+    status, input_data, output_data, error_details, retry_attempt, started_at, completed_at, \
+    created_at";
+
+impl DatabaseManager {
+    /// Create a new agent chain execution
+    #[instrument(skip(self, execution))]
+    pub async fn create_agent_chain_execution(
+        &self,
+        execution: &CreateAgentChainExecution,
+    ) -> Result<AgentChainExecution> {
+        let id = Uuid::new_v4();
+        debug!("Creating agent chain execution with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO agent_chain_executions (
+                id, chain_id, conversation_id, triggered_by_id, status, current_step_id,
+                input_data, output_data, error_details
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING {EXECUTION_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(execution.chain_id)
+        .bind(execution.conversation_id)
+        .bind(execution.triggered_by_id)
+        .bind(execution.status)
+        .bind(execution.current_step_id)
+        .bind(&execution.input_data)
+        .bind(&execution.output_data)
+        .bind(&execution.error_details)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Get an agent chain execution by ID
+    #[instrument(skip(self))]
+    pub async fn get_agent_chain_execution_by_id(
+        &self,
+        id: &Uuid,
+    ) -> Result<Option<AgentChainExecution>> {
+        debug!("Getting agent chain execution by ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {EXECUTION_COLUMNS} FROM agent_chain_executions WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// List agent chain executions with filtering, most recent first
+    #[instrument(skip(self))]
+    pub async fn list_agent_chain_executions(
+        &self,
+        filter: &AgentChainExecutionFilter,
+    ) -> Result<Vec<AgentChainExecution>> {
+        debug!("Listing agent chain executions with filter: {:?}", filter);
+
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {EXECUTION_COLUMNS} FROM agent_chain_executions"));
+        let mut add_where = add_where();
+
+        if let Some(chain_id) = &filter.chain_id {
+            add_where(&mut qb);
+            qb.push("chain_id = ");
+            qb.push_bind(chain_id);
+        }
+
+        if let Some(conversation_id) = &filter.conversation_id {
+            add_where(&mut qb);
+            qb.push("conversation_id = ");
+            qb.push_bind(conversation_id);
+        }
+
+        if let Some(triggered_by_id) = &filter.triggered_by_id {
+            add_where(&mut qb);
+            qb.push("triggered_by_id = ");
+            qb.push_bind(triggered_by_id);
+        }
+
+        if let Some(status) = filter.status {
+            add_where(&mut qb);
+            qb.push("status = ");
+            qb.push_bind(status as i64);
+        }
+
+        qb.push(" ORDER BY started_at DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Update the progress of an agent chain execution
+    #[instrument(err, skip(self, execution))]
+    pub async fn update_agent_chain_execution(
+        &self,
+        execution: &AgentChainExecution,
+    ) -> Result<AgentChainExecution> {
+        debug!("Updating agent chain execution with ID: {}", execution.id);
+
+        sqlx::query_as(&format!(
+            "UPDATE agent_chain_executions SET
+                status = ?, current_step_id = ?, input_data = ?, output_data = ?,
+                error_details = ?, completed_at = ?
+            WHERE id = ?
+            RETURNING {EXECUTION_COLUMNS}"
+        ))
+        .bind(execution.status)
+        .bind(execution.current_step_id)
+        .bind(&execution.input_data)
+        .bind(&execution.output_data)
+        .bind(&execution.error_details)
+        .bind(execution.completed_at)
+        .bind(execution.id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("AgentChainExecution", execution.id))
+    }
+
+    /// Record the start of a step execution
+    #[instrument(skip(self, step_execution))]
+    pub async fn create_agent_chain_step_execution(
+        &self,
+        step_execution: &CreateAgentChainStepExecution,
+    ) -> Result<AgentChainStepExecution> {
+        let id = Uuid::new_v4();
+        debug!("Creating agent chain step execution with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO agent_chain_step_executions (
+                id, execution_id, step_id, agent_id, delegated_by_id, status, input_data,
+                output_data, error_details, retry_attempt, started_at, completed_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING {STEP_EXECUTION_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(step_execution.execution_id)
+        .bind(step_execution.step_id)
+        .bind(step_execution.agent_id)
+        .bind(step_execution.delegated_by_id)
+        .bind(step_execution.status)
+        .bind(&step_execution.input_data)
+        .bind(&step_execution.output_data)
+        .bind(&step_execution.error_details)
+        .bind(step_execution.retry_attempt)
+        .bind(step_execution.started_at)
+        .bind(step_execution.completed_at)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Update the outcome of a step execution
+    #[instrument(err, skip(self, step_execution))]
+    pub async fn update_agent_chain_step_execution(
+        &self,
+        step_execution: &AgentChainStepExecution,
+    ) -> Result<AgentChainStepExecution> {
+        debug!(
+            "Updating agent chain step execution with ID: {}",
+            step_execution.id
+        );
+
+        sqlx::query_as(&format!(
+            "UPDATE agent_chain_step_executions SET
+                status = ?, output_data = ?, error_details = ?, completed_at = ?
+            WHERE id = ?
+            RETURNING {STEP_EXECUTION_COLUMNS}"
+        ))
+        .bind(step_execution.status)
+        .bind(&step_execution.output_data)
+        .bind(&step_execution.error_details)
+        .bind(step_execution.completed_at)
+        .bind(step_execution.id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("AgentChainStepExecution", step_execution.id))
+    }
+
+    /// List the step executions of a chain run in the order they were started
+    #[instrument(skip(self))]
+    pub async fn list_agent_chain_step_executions(
+        &self,
+        execution_id: &Uuid,
+    ) -> Result<Vec<AgentChainStepExecution>> {
+        debug!("Listing step executions of agent chain execution: {}", execution_id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {STEP_EXECUTION_COLUMNS} FROM agent_chain_step_executions
+             WHERE execution_id = ? ORDER BY created_at ASC, rowid ASC"
+        ))
+        .bind(execution_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
@@ -0,0 +1,303 @@
+use boilermates::boilermates;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Sqlite};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentChainStatus {
+    Active = 0,
+    Inactive = 1,
+    Archived = 2,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentChainStepType {
+    /// The step's agent answers the step input itself
+    Agent = 0,
+    /// The step's agent is an operator that hands subtasks to its workers
+    Delegate = 1,
+}
+
+/// A sequence of agent steps where the output of each step is the input of the next
+#[skip_serializing_none]
+#[boilermates("CreateAgentChain")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentChain {
+    #[boilermates(not_in("CreateAgentChain"))]
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub status: AgentChainStatus,
+    pub config: Option<Json<Value>>,
+    pub metadata: Option<Json<Value>>,
+    #[boilermates(not_in("CreateAgentChain"))]
+    pub created_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateAgentChain"))]
+    pub updated_at: DateTime<Utc>,
+    pub workspace_id: Option<Uuid>,
+    pub created_by_id: Option<Uuid>,
+}
+
+#[skip_serializing_none]
+#[boilermates("CreateAgentChainStep")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentChainStep {
+    #[boilermates(not_in("CreateAgentChainStep"))]
+    pub id: Uuid,
+    pub chain_id: Uuid,
+    pub step_number: i64,
+    pub agent_id: Uuid,
+    pub step_name: String,
+    pub step_type: AgentChainStepType,
+    /// Prompt template of the step. `{{input}}` is replaced with the output of the
+    /// previous step and `{{chain_input}}` with the input of the chain.
+    pub input_template: Option<String>,
+    pub timeout_seconds: i64,
+    pub retry_count: i64,
+    pub config: Option<Json<Value>>,
+    #[boilermates(not_in("CreateAgentChainStep"))]
+    pub created_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateAgentChainStep"))]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentChainFilter {
+    pub workspace_id: Option<Uuid>,
+    pub status: Option<AgentChainStatus>,
+    pub search_term: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+const CHAIN_COLUMNS: &str = "id, name, description, status, config, metadata, created_at, \
+    updated_at, workspace_id, created_by_id";
+
+const STEP_COLUMNS: &str = "id, chain_id, step_number, agent_id, step_name, step_type, \
+    input_template, timeout_seconds, retry_count, config, created_at, updated_at";
+
+impl DatabaseManager {
+    /// Create a new agent chain
+    #[instrument(skip(self, chain))]
+    pub async fn create_agent_chain(&self, chain: &CreateAgentChain) -> Result<AgentChain> {
+        let id = Uuid::new_v4();
+        debug!("Creating agent chain with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO agent_chains (
+                id, name, description, status, config, metadata, workspace_id, created_by_id
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING {CHAIN_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(&chain.name)
+        .bind(&chain.description)
+        .bind(chain.status)
+        .bind(&chain.config)
+        .bind(&chain.metadata)
+        .bind(chain.workspace_id)
+        .bind(chain.created_by_id)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Get an agent chain by ID
+    #[instrument(skip(self))]
+    pub async fn get_agent_chain_by_id(&self, id: &Uuid) -> Result<Option<AgentChain>> {
+        debug!("Getting agent chain by ID: {}", id);
+
+        Ok(
+            sqlx::query_as(&format!("SELECT {CHAIN_COLUMNS} FROM agent_chains WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    /// List agent chains with filtering
+    #[instrument(skip(self))]
+    pub async fn list_agent_chains(&self, filter: &AgentChainFilter) -> Result<Vec<AgentChain>> {
+        debug!("Listing agent chains with filter: {:?}", filter);
+
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {CHAIN_COLUMNS} FROM agent_chains"));
+        let mut add_where = add_where();
+
+        if let Some(workspace_id) = &filter.workspace_id {
+            add_where(&mut qb);
+            qb.push("workspace_id = ");
+            qb.push_bind(workspace_id);
+        }
+
+        if let Some(status) = filter.status {
+            add_where(&mut qb);
+            qb.push("status = ");
+            qb.push_bind(status as i64);
+        }
+
+        if let Some(search_term) = &filter.search_term {
+            add_where(&mut qb);
+            qb.push("(name LIKE ");
+            qb.push_bind(format!("%{search_term}%"));
+            qb.push(" OR description LIKE ");
+            qb.push_bind(format!("%{search_term}%"));
+            qb.push(")");
+        }
+
+        qb.push(" ORDER BY created_at DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Update an agent chain
+    #[instrument(err, skip(self, chain))]
+    pub async fn update_agent_chain(&self, chain: &AgentChain) -> Result<AgentChain> {
+        debug!("Updating agent chain with ID: {}", chain.id);
+
+        sqlx::query_as(&format!(
+            "UPDATE agent_chains SET
+                name = ?, description = ?, status = ?, config = ?, metadata = ?,
+                workspace_id = ?, created_by_id = ?
+            WHERE id = ?
+            RETURNING {CHAIN_COLUMNS}"
+        ))
+        .bind(&chain.name)
+        .bind(&chain.description)
+        .bind(chain.status)
+        .bind(&chain.config)
+        .bind(&chain.metadata)
+        .bind(chain.workspace_id)
+        .bind(chain.created_by_id)
+        .bind(chain.id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("AgentChain", chain.id))
+    }
+
+    /// Delete an agent chain together with its steps and executions
+    #[instrument(err, skip(self))]
+    pub async fn delete_agent_chain(&self, id: &Uuid) -> Result<()> {
+        debug!("Deleting agent chain with ID: {}", id);
+
+        let affected = sqlx::query("DELETE FROM agent_chains WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if affected == 0 {
+            return Err(AppError::not_found("AgentChain", *id));
+        }
+        Ok(())
+    }
+
+    /// Add a step to an agent chain
+    #[instrument(skip(self, step))]
+    pub async fn create_agent_chain_step(
+        &self,
+        step: &CreateAgentChainStep,
+    ) -> Result<AgentChainStep> {
+        let id = Uuid::new_v4();
+        debug!("Creating agent chain step with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO agent_chain_steps (
+                id, chain_id, step_number, agent_id, step_name, step_type, input_template,
+                timeout_seconds, retry_count, config
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING {STEP_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(step.chain_id)
+        .bind(step.step_number)
+        .bind(step.agent_id)
+        .bind(&step.step_name)
+        .bind(step.step_type)
+        .bind(&step.input_template)
+        .bind(step.timeout_seconds)
+        .bind(step.retry_count)
+        .bind(&step.config)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// List the steps of an agent chain in the order they run
+    #[instrument(skip(self))]
+    pub async fn list_agent_chain_steps(&self, chain_id: &Uuid) -> Result<Vec<AgentChainStep>> {
+        debug!("Listing steps of agent chain: {}", chain_id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {STEP_COLUMNS} FROM agent_chain_steps
+             WHERE chain_id = ? ORDER BY step_number ASC"
+        ))
+        .bind(chain_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Update an agent chain step
+    #[instrument(err, skip(self, step))]
+    pub async fn update_agent_chain_step(&self, step: &AgentChainStep) -> Result<AgentChainStep> {
+        debug!("Updating agent chain step with ID: {}", step.id);
+
+        sqlx::query_as(&format!(
+            "UPDATE agent_chain_steps SET
+                step_number = ?, agent_id = ?, step_name = ?, step_type = ?, input_template = ?,
+                timeout_seconds = ?, retry_count = ?, config = ?
+            WHERE id = ?
+            RETURNING {STEP_COLUMNS}"
+        ))
+        .bind(step.step_number)
+        .bind(step.agent_id)
+        .bind(&step.step_name)
+        .bind(step.step_type)
+        .bind(&step.input_template)
+        .bind(step.timeout_seconds)
+        .bind(step.retry_count)
+        .bind(&step.config)
+        .bind(step.id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("AgentChainStep", step.id))
+    }
+
+    /// Delete an agent chain step
+    #[instrument(err, skip(self))]
+    pub async fn delete_agent_chain_step(&self, id: &Uuid) -> Result<()> {
+        debug!("Deleting agent chain step with ID: {}", id);
+
+        let affected = sqlx::query("DELETE FROM agent_chain_steps WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if affected == 0 {
+            return Err(AppError::not_found("AgentChainStep", *id));
+        }
+        Ok(())
+    }
+}
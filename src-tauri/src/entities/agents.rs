@@ -11,7 +11,6 @@ use uuid::Uuid;
 
 use crate::error::{AppError, Result};
 use crate::storage::db::DatabaseManager;
-use crate::utils::add_where;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
 #[serde(rename_all = "lowercase")]
@@ -77,6 +76,7 @@ pub struct AgentFilter {
     pub workspace_id: Option<String>,
     pub status: Option<AgentStatus>,
     pub agent_type: Option<AgentType>,
+    pub parent_agent_id: Option<Uuid>,
     pub search_term: Option<String>,
     pub limit: Option<usize>,
     pub offset: Option<usize>,
@@ -201,30 +201,33 @@ impl DatabaseManager {
                     FROM agents WHERE status != 2"#,
         );
 
-        let mut add_where = add_where();
-
-
         if let Some(workspace_id) = &filter.workspace_id {
-            add_where(&mut qb);
+            qb.push(" AND ");
             let uuid = Uuid::parse_str(workspace_id)?;
             qb.push("workspace_id = ");
             qb.push_bind(uuid);
         }
 
         if let Some(status) = &filter.status {
-            add_where(&mut qb);
+            qb.push(" AND ");
             qb.push("status = ");
             qb.push_bind(status.clone());
         }
 
         if let Some(agent_type) = &filter.agent_type {
-            add_where(&mut qb);
-            qb.push("\"type\" = ");
+            qb.push(" AND ");
+            qb.push("agent_type = ");
             qb.push_bind(agent_type.clone());
         }
 
+        if let Some(parent_agent_id) = &filter.parent_agent_id {
+            qb.push(" AND ");
+            qb.push("parent_agent_id = ");
+            qb.push_bind(parent_agent_id);
+        }
+
         if let Some(search_term) = &filter.search_term {
-            add_where(&mut qb);
+            qb.push(" AND ");
             let pattern = format!("%{search_term}%");
             qb.push("(name LIKE ");
             qb.push_bind(pattern.clone());
@@ -75,12 +75,12 @@ impl DatabaseManager {
             api_key.description,
             api_key.key_hash,
             scopes,
-            now,
+            None::<DateTime<Utc>>,
             api_key.rate_limit,
             api_key.is_active, 
             now,
             now,
-            now,
+            None::<DateTime<Utc>>,
         )
         .fetch_one(&self.pool)
         .await?)
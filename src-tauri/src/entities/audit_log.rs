@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub table_name: String,
+    pub record_id: Uuid,
+    /// INSERT, UPDATE and DELETE for changes, or the decision for access checks
+    pub operation: String,
+    pub user_id: Option<Uuid>,
+    pub old_values: Option<Json<Value>>,
+    pub new_values: Option<Json<Value>>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditLogFilter {
+    pub table_name: Option<String>,
+    pub record_id: Option<Uuid>,
+    pub operation: Option<String>,
+    pub after: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+const COLUMNS: &str = "id, table_name, record_id, operation, user_id, old_values, new_values, \
+    ip_address, user_agent, timestamp";
+
+/// Records an entry in the audit log, usually in the transaction of the change it audits
+pub(crate) async fn insert_audit_log_entry(
+    conn: &mut SqliteConnection,
+    entry: &AuditLogEntry,
+) -> Result<()> {
+    sqlx::query(&format!(
+        "INSERT INTO audit_log ({COLUMNS}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    ))
+    .bind(entry.id)
+    .bind(&entry.table_name)
+    .bind(entry.record_id)
+    .bind(&entry.operation)
+    .bind(entry.user_id)
+    .bind(&entry.old_values)
+    .bind(&entry.new_values)
+    .bind(&entry.ip_address)
+    .bind(&entry.user_agent)
+    .bind(entry.timestamp)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+impl DatabaseManager {
+    /// List audit log entries with filtering, newest first
+    #[instrument(skip(self))]
+    pub async fn list_audit_log(&self, filter: &AuditLogFilter) -> Result<Vec<AuditLogEntry>> {
+        debug!("Listing audit log with filter: {:?}", filter);
+
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM audit_log"));
+        let mut add_where = add_where();
+
+        if let Some(table_name) = &filter.table_name {
+            add_where(&mut qb);
+            qb.push("table_name = ");
+            qb.push_bind(table_name);
+        }
+
+        if let Some(record_id) = &filter.record_id {
+            add_where(&mut qb);
+            qb.push("record_id = ");
+            qb.push_bind(record_id);
+        }
+
+        if let Some(operation) = &filter.operation {
+            add_where(&mut qb);
+            qb.push("operation = ");
+            qb.push_bind(operation);
+        }
+
+        if let Some(after) = &filter.after {
+            add_where(&mut qb);
+            qb.push("timestamp >= ");
+            qb.push_bind(after);
+        }
+
+        qb.push(" ORDER BY timestamp DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+}
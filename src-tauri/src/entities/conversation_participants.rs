@@ -324,6 +324,29 @@ impl DatabaseManager {
             .collect())
     }       
 
+    /// Get the id of a participant if they're an active member of the conversation
+    #[instrument(skip(self))]
+    pub async fn get_conversation_participant_id(
+        &self,
+        conversation_id: &Uuid,
+        participant: ParticipantType,
+    ) -> Result<Option<Uuid>> {
+        let (user_id, agent_id, contact_id) = participant.into_id_triplet();
+        Ok(sqlx::query_scalar(
+            "SELECT p.id FROM conversation_participants c
+            INNER JOIN participants p ON c.participant_id = p.id
+            WHERE c.conversation_id = ? AND c.is_active = true
+            AND p.user_id IS ? AND p.agent_id IS ? AND p.contact_id IS ?
+            LIMIT 1",
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .bind(agent_id)
+        .bind(contact_id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
     pub async fn create_batch_participants(
         &self,
         participants: Vec<CreateConversationParticipant>,
@@ -0,0 +1,119 @@
+use boilermates::boilermates;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+
+/// Credential model matching the SQLite schema
+///
+/// `encrypted_value` is the base64 encoded output of [`crate::integration::SecretCipher`],
+/// it is never sent to the frontend.
+#[boilermates("CreateCredential")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Credential {
+    #[boilermates(not_in("CreateCredential"))]
+    pub id: Uuid,
+    pub workspace_id: Option<Uuid>,
+    /// Human readable name of the credential
+    pub name: String,
+    /// What the credential is used for, e.g. the provider name `openai`
+    pub credential_name: String,
+    #[serde(skip_serializing)]
+    pub encrypted_value: String,
+    #[boilermates(not_in("CreateCredential"))]
+    pub created_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateCredential"))]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DatabaseManager {
+    /// Create a new credential in the database
+    #[instrument(skip(self, credential))]
+    pub async fn create_credential(&self, credential: &CreateCredential) -> Result<Credential> {
+        let id = Uuid::new_v4();
+        debug!("Creating credential with ID: {}", id);
+
+        Ok(sqlx::query_as!(
+            Credential,
+            r#"INSERT INTO credentials (id, workspace_id, name, credential_name, encrypted_value)
+                VALUES (?, ?, ?, ?, ?)
+                RETURNING id AS "id: _", workspace_id AS "workspace_id: _", name, credential_name,
+                encrypted_value, created_at AS "created_at: _", updated_at AS "updated_at: _""#,
+            id,
+            credential.workspace_id,
+            credential.name,
+            credential.credential_name,
+            credential.encrypted_value,
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Get a credential by ID
+    #[instrument(skip(self))]
+    pub async fn get_credential_by_id(&self, id: &Uuid) -> Result<Option<Credential>> {
+        debug!("Getting credential by ID: {}", id);
+
+        Ok(sqlx::query_as!(
+            Credential,
+            r#"SELECT id AS "id: _", workspace_id AS "workspace_id: _", name, credential_name,
+                encrypted_value, created_at AS "created_at: _", updated_at AS "updated_at: _"
+                FROM credentials WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Get the most recent credential with the given purpose for a workspace.
+    /// Credentials without a workspace act as the global default.
+    #[instrument(skip(self))]
+    pub async fn get_workspace_credential(
+        &self,
+        workspace_id: Option<&Uuid>,
+        credential_name: &str,
+    ) -> Result<Option<Credential>> {
+        debug!(
+            "Getting credential {} for workspace {:?}",
+            credential_name, workspace_id
+        );
+
+        Ok(sqlx::query_as!(
+            Credential,
+            r#"SELECT id AS "id: _", workspace_id AS "workspace_id: _", name, credential_name,
+                encrypted_value, created_at AS "created_at: _", updated_at AS "updated_at: _"
+                FROM credentials
+                WHERE credential_name = ? AND (workspace_id = ? OR workspace_id IS NULL)
+                ORDER BY workspace_id IS NULL, updated_at DESC
+                LIMIT 1"#,
+            credential_name,
+            workspace_id
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Delete a credential by ID
+    #[instrument(err, skip(self))]
+    pub async fn delete_credential(&self, id: &Uuid) -> Result<()> {
+        debug!("Deleting credential with ID: {}", id);
+
+        let affected = sqlx::query!("DELETE FROM credentials WHERE id = ?", id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        if affected == 0 {
+            return Err(AppError::NotFoundError(format!(
+                "Credential with ID {id} not found"
+            )));
+        }
+
+        Ok(())
+    }
+}
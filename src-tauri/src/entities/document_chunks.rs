@@ -166,8 +166,9 @@ impl DatabaseManager {
         debug!("Listing document chunks with filter: {:?}", filter);
 
         let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
-            r#"SELECT id, document_id, chunk_index, content, metadata, embedding_id, is_embedded,
-               created_at, updated_at 
+            r#"SELECT id, document_id, parent_chunk_id, content, content_hash, chunk_type,
+               order_index, semantic_level, metadata, created_at, updated_at, embedding_id,
+               workspace_id
                FROM document_chunks"#,
         );
 
@@ -181,41 +182,38 @@ impl DatabaseManager {
 
         if let Some(is_embedded) = filter.is_embedded {
             add_where(&mut qb);
-            qb.push("is_embedded = ");
-            qb.push_bind(if is_embedded { 1 } else { 0 });
+            qb.push(if is_embedded {
+                "embedding_id IS NOT NULL"
+            } else {
+                "embedding_id IS NULL"
+            });
         }
 
         if let Some(search_term) = &filter.search_term {
             add_where(&mut qb);
-            qb.push("content LIKE '%");
-            qb.push_bind(search_term);
-            qb.push("%'");
+            qb.push("content LIKE ");
+            qb.push_bind(format!("%{search_term}%"));
         }
 
         if let Some(min_chunk_index) = filter.min_chunk_index {
             add_where(&mut qb);
-            qb.push("chunk_index >= ");
+            qb.push("order_index >= ");
             qb.push_bind(min_chunk_index);
         }
 
         if let Some(max_chunk_index) = filter.max_chunk_index {
             add_where(&mut qb);
-            qb.push("chunk_index <= ");
+            qb.push("order_index <= ");
             qb.push_bind(max_chunk_index);
         }
 
-        qb.push(" ORDER BY document_id, chunk_index ASC");
+        qb.push(" ORDER BY document_id, order_index ASC");
 
         if let Some(limit) = filter.limit {
             qb.push(" LIMIT ");
             qb.push_bind(limit as i64);
         }
 
-        if let Some(offset) = filter.offset {
-            qb.push(" OFFSET ");
-            qb.push_bind(offset as i64);
-        }
-
         if let Some(offset) = filter.offset {
             qb.push(" OFFSET ");
             qb.push_bind(offset as i64);
@@ -468,9 +466,10 @@ impl DatabaseManager {
         debug!("Counting embedded chunks for document: {}", document_id);   
 
         let affected = sqlx::query_scalar!(
-            r#"SELECT COUNT(*) as count FROM document_chunks WHERE document_id = ?"#,
+            r#"SELECT COUNT(*) as count FROM document_chunks
+                WHERE document_id = ? AND embedding_id IS NOT NULL"#,
             document_id,
-        )       
+        )
         .fetch_one(&self.pool)
         .await?;
 
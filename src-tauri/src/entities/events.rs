@@ -1,5 +1,6 @@
 use boilermates::boilermates;
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use sqlx::prelude::FromRow;
@@ -13,7 +14,7 @@ use crate::error::{AppError, Result};
 use crate::storage::db::DatabaseManager;
 use crate::utils::add_where;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, JsonSchema)]
 #[sqlx(type_name = "event_type")]
 #[serde(rename_all = "lowercase")]
 pub enum EventType {
@@ -24,7 +25,7 @@ pub enum EventType {
     Other = 4,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type, JsonSchema)]
 #[sqlx(type_name = "event_status")]
 #[serde(rename_all = "lowercase")]
 pub enum EventStatus {
@@ -94,7 +95,7 @@ pub struct Event {
     pub parent_event_id: Option<Uuid>,
 }
 #[skip_serializing_none]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EventFilter {
     pub event_type: Option<EventType>,
@@ -231,24 +232,8 @@ impl DatabaseManager {
     /// List events with filtering
     #[instrument(err, skip(self))]
     pub async fn list_events(&self, filter: &EventFilter) -> Result<Vec<Event>> {
-        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
-            r#"SELECT id AS "id: _", title, description, event_type AS "event_type: EventType", status AS "status: EventStatus",
-                    start_time AS "start_time: _", end_time AS "end_time: _", is_all_day_event,
-                    timezone_sid_key, location, virtual_meeting_url, meeting_platform, is_recurrence,
-                    recurrence_rule AS "recurrence_rule: _", recurrence_parent_id AS "recurrence_parent_id: _",
-                    agent_participation AS "agent_participation: _", requires_transcription,
-                    requires_summarization, agent_capabilities AS "agent_capabilities: _", is_private,
-                    allow_guests, max_attendees, requires_approval, agenda, meeting_notes, transcription,
-                    summary, action_items AS "action_items: _",
-                    is_child_event, is_group_event, is_archived, event_relation AS "event_relation: _",
-                    activity_date AS "activity_date: _", duration_in_minutes, show_as, is_reminder_set,
-                    reminder_date_time AS "reminder_date_time: _", metadata AS "metadata: _",
-                    created_at AS "created_at: _", updated_at AS "updated_at: _", plan_id AS "plan_id: _",
-                    task_id AS "task_id: _", created_by_user_id AS "created_by_user_id: _",
-                    last_modified_by_user_id AS "last_modified_by_user_id: _", workspace_id AS "workspace_id: _",
-                    parent_event_id AS "parent_event_id: _"
-             FROM events"#
-        );
+        // Type overrides only apply to the query macros, here the columns keep their names
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT * FROM events");
 
         let mut add_where = add_where();
 
@@ -0,0 +1,249 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Sqlite};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::entities::PeerIdWrapper;
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Outgoing = 0,
+    Incoming = 1,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferStatus {
+    Pending = 0,
+    Active = 1,
+    /// Stopped by a disconnect, resumed when the peer connects again
+    Paused = 2,
+    Completed = 3,
+    Failed = 4,
+    Cancelled = 5,
+}
+
+impl TransferStatus {
+    /// Whether the transfer is over and won't be resumed
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TransferStatus::Completed | TransferStatus::Failed | TransferStatus::Cancelled
+        )
+    }
+}
+
+/// A file sent to or received from a peer
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct FileTransfer {
+    pub id: Uuid,
+    pub file_id: Uuid,
+    pub peer_id: PeerIdWrapper,
+    pub direction: TransferDirection,
+    pub status: TransferStatus,
+    pub file_name: String,
+    pub file_path: String,
+    pub size: i64,
+    /// SHA-256 of the whole file, hex encoded
+    pub hash: String,
+    pub chunk_size: i64,
+    /// Hashes of the chunks, in the order they appear in the file
+    pub chunks: Json<Vec<String>>,
+    /// Indexes of the chunks that were sent or received
+    pub done_chunks: Json<Vec<i64>>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Default, Deserialize)]
+pub struct FileTransferFilter {
+    pub peer_id: Option<PeerIdWrapper>,
+    pub file_id: Option<Uuid>,
+    pub direction: Option<TransferDirection>,
+    pub status: Option<TransferStatus>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+const COLUMNS: &str = "id, file_id, peer_id, direction, status, file_name, file_path, size, \
+    hash, chunk_size, chunks, done_chunks, error, created_at, updated_at";
+
+impl DatabaseManager {
+    /// Create a transfer, or return the existing one of the same file, peer and direction
+    #[instrument(err, skip(self, transfer))]
+    pub async fn create_file_transfer(&self, transfer: &FileTransfer) -> Result<FileTransfer> {
+        debug!(
+            "Creating {:?} transfer of file {} with peer {}",
+            transfer.direction, transfer.file_id, transfer.peer_id
+        );
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO file_transfers (
+                id, file_id, peer_id, direction, status, file_name, file_path, size, hash,
+                chunk_size, chunks, done_chunks, error
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (file_id, peer_id, direction) DO NOTHING",
+        )
+        .bind(transfer.id)
+        .bind(transfer.file_id)
+        .bind(&transfer.peer_id)
+        .bind(transfer.direction as i32)
+        .bind(transfer.status as i32)
+        .bind(&transfer.file_name)
+        .bind(&transfer.file_path)
+        .bind(transfer.size)
+        .bind(&transfer.hash)
+        .bind(transfer.chunk_size)
+        .bind(&transfer.chunks)
+        .bind(&transfer.done_chunks)
+        .bind(&transfer.error)
+        .execute(&mut *tx)
+        .await?;
+        let created = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM file_transfers
+             WHERE file_id = ? AND peer_id = ? AND direction = ?"
+        ))
+        .bind(transfer.file_id)
+        .bind(&transfer.peer_id)
+        .bind(transfer.direction as i32)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_file_transfer(&self, id: Uuid) -> Result<Option<FileTransfer>> {
+        debug!("Getting file transfer: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM file_transfers WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// List file transfers with filtering, the most recent first
+    #[instrument(skip(self))]
+    pub async fn list_file_transfers(
+        &self,
+        filter: &FileTransferFilter,
+    ) -> Result<Vec<FileTransfer>> {
+        debug!("Listing file transfers with filter: {:?}", filter);
+
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM file_transfers"));
+        let mut add_where = add_where();
+
+        if let Some(peer_id) = &filter.peer_id {
+            add_where(&mut qb);
+            qb.push("peer_id = ");
+            qb.push_bind(peer_id.clone());
+        }
+
+        if let Some(file_id) = filter.file_id {
+            add_where(&mut qb);
+            qb.push("file_id = ");
+            qb.push_bind(file_id);
+        }
+
+        if let Some(direction) = filter.direction {
+            add_where(&mut qb);
+            qb.push("direction = ");
+            qb.push_bind(direction as i32);
+        }
+
+        if let Some(status) = filter.status {
+            add_where(&mut qb);
+            qb.push("status = ");
+            qb.push_bind(status as i32);
+        }
+
+        qb.push(" ORDER BY created_at DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Record that a chunk was sent or received. Pending transfers become active.
+    #[instrument(err, skip(self))]
+    pub async fn mark_file_transfer_chunk_done(
+        &self,
+        id: Uuid,
+        index: i64,
+    ) -> Result<FileTransfer> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "UPDATE file_transfers SET
+                done_chunks = json_insert(done_chunks, '$[#]', ?1),
+                status = CASE WHEN status = ?2 THEN ?3 ELSE status END
+             WHERE id = ?4
+                AND NOT EXISTS (SELECT 1 FROM json_each(done_chunks) WHERE value = ?1)",
+        )
+        .bind(index)
+        .bind(TransferStatus::Pending as i32)
+        .bind(TransferStatus::Active as i32)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        let transfer = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM file_transfers WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::not_found("FileTransfer", id))?;
+        tx.commit().await?;
+        Ok(transfer)
+    }
+
+    /// Change the status of a transfer. Received files are moved to `file_path` once
+    /// they are complete.
+    #[instrument(err, skip(self))]
+    pub async fn update_file_transfer_status(
+        &self,
+        id: Uuid,
+        status: TransferStatus,
+        error: Option<&str>,
+        file_path: Option<&str>,
+    ) -> Result<FileTransfer> {
+        debug!("Setting status of file transfer {} to {:?}", id, status);
+
+        sqlx::query_as(&format!(
+            "UPDATE file_transfers SET
+                status = ?, error = ?, file_path = COALESCE(?, file_path)
+             WHERE id = ?
+             RETURNING {COLUMNS}"
+        ))
+        .bind(status as i32)
+        .bind(error)
+        .bind(file_path)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("FileTransfer", id))
+    }
+}
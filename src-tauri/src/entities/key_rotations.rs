@@ -0,0 +1,181 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::entities::{P2pMessageStatus, P2pNodeStatus, PeerIdWrapper, PeerPermissionStatus};
+use crate::error::{AppError, Result};
+use crate::keys::{DeviceCertificate, KeyKind, KeyRotation};
+use crate::storage::db::DatabaseManager;
+
+/// The certificate a peer's user key issued for its device key, the peer id
+#[derive(Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerCertificate {
+    pub peer_id: PeerIdWrapper,
+    /// Protobuf encoded public key of the user the device belongs to
+    pub user_key: Vec<u8>,
+    pub certificate: Json<DeviceCertificate>,
+    /// The device key that replaced this one
+    pub replaced_by: Option<PeerIdWrapper>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+const COLUMNS: &str = "peer_id, user_key, certificate, replaced_by, created_at, updated_at";
+
+impl DatabaseManager {
+    #[instrument(skip(self))]
+    pub async fn get_peer_certificate(
+        &self,
+        peer_id: &PeerIdWrapper,
+    ) -> Result<Option<PeerCertificate>> {
+        debug!("Getting certificate of peer: {}", peer_id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM peer_certificates WHERE peer_id = ?"
+        ))
+        .bind(peer_id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Moves the trust we put in the old key of a verified rotation to the new key. A
+    /// new device key takes over the nodes, the permissions and the queued messages of
+    /// the old one. Peers we have no certificate of yet are trusted on first use.
+    #[instrument(err, skip(self, rotation))]
+    pub async fn apply_key_rotation(
+        &self,
+        sender: &PeerIdWrapper,
+        rotation: &KeyRotation,
+    ) -> Result<()> {
+        debug!(
+            "Applying {:?} key rotation of peer {}",
+            rotation.kind, sender
+        );
+
+        let old_key = rotation.old_key.0.encode_protobuf();
+        let new_key = rotation.new_key.0.encode_protobuf();
+        let user_key = rotation.certificate.user_key.0.encode_protobuf();
+        let mut tx = self.pool.begin().await?;
+        let known: Option<Vec<u8>> =
+            sqlx::query_scalar("SELECT user_key FROM peer_certificates WHERE peer_id = ?")
+                .bind(sender)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let expected = match rotation.kind {
+            KeyKind::User => &old_key,
+            KeyKind::Device => &user_key,
+        };
+        if let Some(known) = known
+            && known != *expected
+        {
+            return Err(AppError::authorization(format!(
+                "Key rotation of peer {sender} doesn't match the user key we know of it"
+            )));
+        }
+
+        let peer_id = PeerIdWrapper(rotation.certificate.peer_id());
+        if rotation.kind == KeyKind::User {
+            // Every device of the user moves to the new key
+            sqlx::query("UPDATE peer_certificates SET user_key = ? WHERE user_key = ?")
+                .bind(&new_key)
+                .bind(&old_key)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query(
+            "INSERT INTO peer_certificates (peer_id, user_key, certificate) VALUES (?, ?, ?)
+            ON CONFLICT (peer_id) DO UPDATE SET
+                user_key = excluded.user_key, certificate = excluded.certificate",
+        )
+        .bind(&peer_id)
+        .bind(&user_key)
+        .bind(Json(&rotation.certificate))
+        .execute(&mut *tx)
+        .await?;
+
+        if rotation.kind == KeyKind::Device {
+            sqlx::query("UPDATE peer_certificates SET replaced_by = ? WHERE peer_id = ?")
+                .bind(&peer_id)
+                .bind(sender)
+                .execute(&mut *tx)
+                .await?;
+            // The new peer id is offline until it connects
+            sqlx::query(
+                "INSERT OR IGNORE INTO p2p_nodes (
+                    participant_id, peer_id, node_type, multiaddr, capabilities, status, metadata
+                )
+                SELECT participant_id, ?, node_type, multiaddr, capabilities, ?, metadata
+                FROM p2p_nodes WHERE peer_id = ?",
+            )
+            .bind(&peer_id)
+            .bind(P2pNodeStatus::Offline as i32)
+            .bind(sender)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "INSERT INTO peer_permissions (
+                    id, peer_id, contact_id, status, allowed_messages, allowed_tools, allowed_agents
+                )
+                SELECT ?, ?, contact_id, status, allowed_messages, allowed_tools, allowed_agents
+                FROM peer_permissions WHERE peer_id = ?
+                ON CONFLICT (peer_id) DO NOTHING",
+            )
+            .bind(Uuid::new_v4())
+            .bind(&peer_id)
+            .bind(sender)
+            .execute(&mut *tx)
+            .await?;
+            // The old peer id won't connect again to receive what is queued for it
+            sqlx::query(
+                "UPDATE p2p_message_queue SET to_peer_id = ?
+                WHERE to_peer_id = ? AND status IN (?, ?)",
+            )
+            .bind(peer_id.to_string())
+            .bind(sender.to_string())
+            .bind(P2pMessageStatus::Pending as i32)
+            .bind(P2pMessageStatus::Sent as i32)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO key_rotations (
+                id, peer_id, kind, old_key, new_key, statement, rotated_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (old_key, new_key) DO NOTHING",
+        )
+        .bind(Uuid::new_v4())
+        .bind(sender)
+        .bind(rotation.kind as i32)
+        .bind(&old_key)
+        .bind(&new_key)
+        .bind(Json(rotation))
+        .bind(rotation.rotated_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Peers of our contacts and the peers we allowed, who are told about our key
+    /// rotations
+    #[instrument(skip(self))]
+    pub async fn list_trusting_peer_ids(&self) -> Result<Vec<PeerIdWrapper>> {
+        debug!("Listing peers that trust our keys");
+
+        Ok(sqlx::query_scalar(
+            "SELECT pn.peer_id FROM p2p_nodes pn
+                INNER JOIN participants p ON p.id = pn.participant_id
+                WHERE p.contact_id IS NOT NULL
+            UNION
+            SELECT peer_id FROM peer_permissions WHERE status = ?",
+        )
+        .bind(PeerPermissionStatus::Allowed as i32)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
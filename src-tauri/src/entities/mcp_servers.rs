@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use sqlx::prelude::FromRow;
+use sqlx::types::Json;
 use sqlx::{QueryBuilder, Row, Sqlite};
 use tracing::{debug, instrument};
 use uuid::Uuid;
@@ -8,6 +12,27 @@ use uuid::Uuid;
 use crate::error::{AppError, Result};
 use crate::storage::db::DatabaseManager;
 
+/// How we talk to an MCP server
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum McpTransport {
+    /// A program we start, spoken to over its stdin and stdout
+    Stdio = 0,
+    /// Streamable HTTP, every message is POSTed to `url`
+    Http = 1,
+    /// The older HTTP transport, responses arrive over a server-sent event stream
+    Sse = 2,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum McpConnectionStatus {
+    Disconnected = 0,
+    Connecting = 1,
+    Connected = 2,
+    Failed = 3,
+}
+
 /// MCP server model matching the SQLite schema
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 #[serde(rename_all = "camelCase")]
@@ -15,12 +40,23 @@ pub struct McpServer {
     pub id: Uuid,
     pub name: String,
     pub description: String,
-    pub host: String,
-    pub port: i64,
+    pub transport: McpTransport,
+    /// Endpoint of HTTP and SSE servers
+    pub url: Option<String>,
+    /// Program started for stdio servers
+    pub command: Option<String>,
+    pub args: Json<Vec<String>>,
+    pub env: Json<HashMap<String, String>>,
     pub api_key: Option<String>,
     pub auth_token: Option<String>,
     pub is_active: bool,
     pub is_default: bool,
+    /// What the server declared when it was initialized
+    pub capabilities: Option<Json<Value>>,
+    pub server_info: Option<Json<Value>>,
+    pub connection_status: McpConnectionStatus,
+    pub last_error: Option<String>,
+    pub last_connected_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -40,9 +76,13 @@ pub struct McpServerFilter {
     pub offset: Option<usize>,
 }
 
+const COLUMNS: &str = "id, name, description, transport, url, command, args, env, api_key, \
+    auth_token, is_active, is_default, capabilities, server_info, connection_status, \
+    last_error, last_connected_at, created_at, updated_at";
+
 impl DatabaseManager {
     /// Create a new MCP server in the database
-    #[instrument(skip(self))]
+    #[instrument(skip(self, server), fields(id = %server.id))]
     pub async fn create_mcp_server(&self, server: &McpServer) -> Result<()> {
         debug!("Creating MCP server with ID: {}", server.id);
 
@@ -53,17 +93,20 @@ impl DatabaseManager {
 
         let _result = sqlx::query(
             "INSERT INTO mcp_servers (
-                    id, name, description, host, port, api_key, auth_token,
-                    is_active, is_default, created_at, updated_at
+                    id, name, description, transport, url, command, args, env, api_key,
+                    auth_token, is_active, is_default, created_at, updated_at
                 ) VALUES (
-                    ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+                    ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
                 )",
         )
         .bind(server.id)
         .bind(&server.name)
         .bind(&server.description)
-        .bind(&server.host)
-        .bind(server.port)
+        .bind(server.transport as i32)
+        .bind(&server.url)
+        .bind(&server.command)
+        .bind(&server.args)
+        .bind(&server.env)
         .bind(&server.api_key)
         .bind(&server.auth_token)
         .bind(server.is_active)
@@ -81,37 +124,12 @@ impl DatabaseManager {
     pub async fn get_mcp_server_by_id(&self, id: &Uuid) -> Result<Option<McpServer>> {
         debug!("Getting MCP server by ID: {}", id);
 
-        let row = sqlx::query(
-            r#"SELECT 
-                    id, name, description, host, port, api_key, auth_token,
-                    is_active, is_default, created_at, updated_at
-                FROM mcp_servers WHERE id = ?"#,
+        Ok(
+            sqlx::query_as(&format!("SELECT {COLUMNS} FROM mcp_servers WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?,
         )
-        .bind(id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        if let Some(row) = row {
-            let server = McpServer {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                name: row.get("name"),
-                description: row.get("description"),
-                host: row.get("host"),
-                port: row.get("port"),
-                api_key: row.get("api_key"),
-                auth_token: row.get("auth_token"),
-                is_active: row.get::<i64, _>("is_active") != 0,
-                is_default: row.get::<i64, _>("is_default") != 0,
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            };
-            Ok(Some(server))
-        } else {
-            Ok(None)
-        }
     }
 
     /// Get an MCP server by name
@@ -119,37 +137,12 @@ impl DatabaseManager {
     pub async fn get_mcp_server_by_name(&self, name: &str) -> Result<Option<McpServer>> {
         debug!("Getting MCP server by name: {}", name);
 
-        let row = sqlx::query(
-            r#"SELECT 
-                    id, name, description, host, port, api_key, auth_token,
-                    is_active, is_default, created_at, updated_at
-                FROM mcp_servers WHERE name = ?"#,
+        Ok(
+            sqlx::query_as(&format!("SELECT {COLUMNS} FROM mcp_servers WHERE name = ?"))
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await?,
         )
-        .bind(name)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        if let Some(row) = row {
-            let server = McpServer {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                name: row.get("name"),
-                description: row.get("description"),
-                host: row.get("host"),
-                port: row.get("port"),
-                api_key: row.get("api_key"),
-                auth_token: row.get("auth_token"),
-                is_active: row.get::<i64, _>("is_active") != 0,
-                is_default: row.get::<i64, _>("is_default") != 0,
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            };
-            Ok(Some(server))
-        } else {
-            Ok(None)
-        }
     }
 
     /// Get the default MCP server
@@ -157,36 +150,11 @@ impl DatabaseManager {
     pub async fn get_default_mcp_server(&self) -> Result<Option<McpServer>> {
         debug!("Getting default MCP server");
 
-        let row = sqlx::query(
-            r#"SELECT 
-                    id, name, description, host, port, api_key, auth_token,
-                    is_active, is_default, created_at, updated_at
-                FROM mcp_servers WHERE is_default = 1"#,
-        )
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM mcp_servers WHERE is_default = 1"
+        ))
         .fetch_optional(&self.pool)
-        .await?;
-
-        if let Some(row) = row {
-            let server = McpServer {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                name: row.get("name"),
-                description: row.get("description"),
-                host: row.get("host"),
-                port: row.get("port"),
-                api_key: row.get("api_key"),
-                auth_token: row.get("auth_token"),
-                is_active: row.get::<i64, _>("is_active") != 0,
-                is_default: row.get::<i64, _>("is_default") != 0,
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            };
-            Ok(Some(server))
-        } else {
-            Ok(None)
-        }
+        .await?)
     }
 
     /// List and filter MCP servers
@@ -194,31 +162,38 @@ impl DatabaseManager {
     pub async fn list_mcp_servers(&self, filter: &McpServerFilter) -> Result<Vec<McpServer>> {
         debug!("Listing MCP servers with filter: {:?}", filter);
 
-        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
-            r#"SELECT id, name, description, host, port, api_key, auth_token,
-               is_active, is_default, created_at, updated_at 
-               FROM mcp_servers"#,
-        );
-
-        let mut where_conditions: Vec<String> = Vec::new();
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM mcp_servers"));
+        let mut has_where = false;
+        let mut add_where = |qb: &mut QueryBuilder<Sqlite>| {
+            qb.push(if has_where { " AND " } else { " WHERE " });
+            has_where = true;
+        };
 
         if let Some(is_active) = filter.is_active {
-            where_conditions.push(format!("is_active = {}", if is_active { 1 } else { 0 }));
+            add_where(&mut qb);
+            qb.push("is_active = ");
+            qb.push_bind(is_active);
         }
 
         if let Some(is_default) = filter.is_default {
-            where_conditions.push(format!("is_default = {}", if is_default { 1 } else { 0 }));
+            add_where(&mut qb);
+            qb.push("is_default = ");
+            qb.push_bind(is_default);
         }
 
         if let Some(search_term) = &filter.search_term {
-            where_conditions.push(format!(
-                "(name LIKE '%{search_term}%' OR description LIKE '%{search_term}%' OR host LIKE '%{search_term}%')"
-            ));
-        }
-
-        if !where_conditions.is_empty() {
-            qb.push(" WHERE ");
-            qb.push(where_conditions.join(" AND "));
+            let pattern = format!("%{search_term}%");
+            add_where(&mut qb);
+            qb.push("(name LIKE ");
+            qb.push_bind(pattern.clone());
+            qb.push(" OR description LIKE ");
+            qb.push_bind(pattern.clone());
+            qb.push(" OR url LIKE ");
+            qb.push_bind(pattern.clone());
+            qb.push(" OR command LIKE ");
+            qb.push_bind(pattern);
+            qb.push(")");
         }
 
         qb.push(" ORDER BY name ASC");
@@ -233,34 +208,11 @@ impl DatabaseManager {
             qb.push_bind(offset as i64);
         }
 
-        let rows = qb.build().fetch_all(&self.pool).await?;
-
-        let mut servers = Vec::new();
-        for row in rows {
-            let server = McpServer {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                name: row.get("name"),
-                description: row.get("description"),
-                host: row.get("host"),
-                port: row.get("port"),
-                api_key: row.get("api_key"),
-                auth_token: row.get("auth_token"),
-                is_active: row.get::<i64, _>("is_active") != 0,
-                is_default: row.get::<i64, _>("is_default") != 0,
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            };
-            servers.push(server);
-        }
-
-        Ok(servers)
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
     }
 
     /// Update an MCP server
-    #[instrument(err, skip(self))]
+    #[instrument(err, skip(self, server), fields(id = %server.id))]
     pub async fn update_mcp_server(&self, server: &McpServer) -> Result<()> {
         debug!("Updating MCP server with ID: {}", server.id);
 
@@ -271,14 +223,18 @@ impl DatabaseManager {
 
         let affected = sqlx::query(
             "UPDATE mcp_servers SET 
-                name = ?, description = ?, host = ?, port = ?, api_key = ?,
-                auth_token = ?, is_active = ?, is_default = ?, updated_at = ?
+                name = ?, description = ?, transport = ?, url = ?, command = ?, args = ?,
+                env = ?, api_key = ?, auth_token = ?, is_active = ?, is_default = ?,
+                updated_at = ?
             WHERE id = ?",
         )
         .bind(&server.name)
         .bind(&server.description)
-        .bind(&server.host)
-        .bind(server.port)
+        .bind(server.transport as i32)
+        .bind(&server.url)
+        .bind(&server.command)
+        .bind(&server.args)
+        .bind(&server.env)
         .bind(&server.api_key)
         .bind(&server.auth_token)
         .bind(server.is_active)
@@ -299,27 +255,44 @@ impl DatabaseManager {
         Ok(())
     }
 
-    /// Update MCP server connection details
-    #[instrument(err, skip(self))]
+    /// Record the state of the connection to an MCP server. The capabilities and info
+    /// the server declared are kept when none are given.
+    #[instrument(err, skip(self, capabilities, server_info))]
     pub async fn update_mcp_server_connection(
         &self,
         id: &Uuid,
-        host: &str,
-        port: i64,
+        status: McpConnectionStatus,
+        error: Option<&str>,
+        capabilities: Option<&Value>,
+        server_info: Option<&Value>,
     ) -> Result<()> {
-        debug!("Updating connection details for MCP server: {}", id);
+        debug!(
+            "Setting connection status of MCP server {} to {:?}",
+            id, status
+        );
 
         let now = Utc::now();
 
-        let affected =
-            sqlx::query("UPDATE mcp_servers SET host = ?, port = ?, updated_at = ? WHERE id = ?")
-                .bind(host)
-                .bind(port)
-                .bind(now)
-                .bind(id)
-                .execute(&self.pool)
-                .await?
-                .rows_affected();
+        let affected = sqlx::query(
+            "UPDATE mcp_servers SET
+                connection_status = ?, last_error = ?,
+                capabilities = COALESCE(?, capabilities),
+                server_info = COALESCE(?, server_info),
+                last_connected_at = CASE WHEN ? THEN ? ELSE last_connected_at END,
+                updated_at = ?
+            WHERE id = ?",
+        )
+        .bind(status as i32)
+        .bind(error)
+        .bind(capabilities.map(Json))
+        .bind(server_info.map(Json))
+        .bind(status == McpConnectionStatus::Connected)
+        .bind(now)
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
 
         if affected == 0 {
             return Err(AppError::NotFoundError(format!(
@@ -494,12 +467,20 @@ mod tests {
             id: server_id,
             name: "Test Server".to_string(),
             description: "A test MCP server".to_string(),
-            host: "localhost".to_string(),
-            port: 8080,
+            transport: McpTransport::Http,
+            url: Some("http://localhost:8080/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: Some("test-api-key".to_string()),
             auth_token: None,
             is_active: true,
             is_default: true,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -520,8 +501,12 @@ mod tests {
         assert_eq!(retrieved.id, server_id);
         assert_eq!(retrieved.name, "Test Server");
         assert_eq!(retrieved.description, "A test MCP server");
-        assert_eq!(retrieved.host, "localhost");
-        assert_eq!(retrieved.port, 8080);
+        assert_eq!(retrieved.transport, McpTransport::Http);
+        assert_eq!(retrieved.url, Some("http://localhost:8080/mcp".to_string()));
+        assert_eq!(
+            retrieved.connection_status,
+            McpConnectionStatus::Disconnected
+        );
         assert_eq!(retrieved.api_key, Some("test-api-key".to_string()));
         assert_eq!(retrieved.auth_token, None);
         assert_eq!(retrieved.is_active, true);
@@ -558,12 +543,20 @@ mod tests {
                 id: server_id,
                 name: format!("Server {}", i),
                 description: format!("Description for server {}", i),
-                host: format!("host{}.example.com", i),
-                port: 8080 + i as i64,
+                transport: McpTransport::Http,
+                url: Some(format!("http://host{}.example.com:{}/mcp", i, 8080 + i)),
+                command: None,
+                args: Json(Vec::new()),
+                env: Json(HashMap::new()),
                 api_key: Some(format!("api-key-{}", i)),
                 auth_token: None,
                 is_active: i != 3,  // Make the last one inactive
                 is_default: i == 1, // Make the first one default
+                capabilities: None,
+                server_info: None,
+                connection_status: McpConnectionStatus::Disconnected,
+                last_error: None,
+                last_connected_at: None,
                 created_at: now,
                 updated_at: now,
             };
@@ -642,12 +635,20 @@ mod tests {
             id: server_id,
             name: "Original Server".to_string(),
             description: "Original description".to_string(),
-            host: "original.example.com".to_string(),
-            port: 8080,
+            transport: McpTransport::Http,
+            url: Some("http://original.example.com:8080/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: Some("original-key".to_string()),
             auth_token: None,
             is_active: true,
             is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -662,12 +663,20 @@ mod tests {
             id: server_id,
             name: "Updated Server".to_string(),
             description: "Updated description".to_string(),
-            host: "updated.example.com".to_string(),
-            port: 9090,
+            transport: McpTransport::Stdio,
+            url: None,
+            command: Some("npx".to_string()),
+            args: Json(vec!["-y".to_string(), "mcp-server".to_string()]),
+            env: Json(HashMap::from([("DEBUG".to_string(), "1".to_string())])),
             api_key: None,
             auth_token: Some("updated-token".to_string()),
             is_active: false,
             is_default: true,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: server.created_at,
             updated_at: Utc::now(),
         };
@@ -684,8 +693,11 @@ mod tests {
             .unwrap();
         assert_eq!(retrieved.name, "Updated Server");
         assert_eq!(retrieved.description, "Updated description");
-        assert_eq!(retrieved.host, "updated.example.com");
-        assert_eq!(retrieved.port, 9090);
+        assert_eq!(retrieved.transport, McpTransport::Stdio);
+        assert_eq!(retrieved.url, None);
+        assert_eq!(retrieved.command, Some("npx".to_string()));
+        assert_eq!(retrieved.args.0, vec!["-y", "mcp-server"]);
+        assert_eq!(retrieved.env.0.get("DEBUG"), Some(&"1".to_string()));
         assert_eq!(retrieved.api_key, None);
         assert_eq!(retrieved.auth_token, Some("updated-token".to_string()));
         assert_eq!(retrieved.is_active, false);
@@ -702,12 +714,20 @@ mod tests {
             id: server_id,
             name: "Test Server".to_string(),
             description: "Test description".to_string(),
-            host: "original.example.com".to_string(),
-            port: 8080,
+            transport: McpTransport::Http,
+            url: Some("http://original.example.com:8080/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: Some("test-key".to_string()),
             auth_token: None,
             is_active: true,
             is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -717,23 +737,67 @@ mod tests {
             .await
             .expect("Failed to create MCP server");
 
-        // Update just the connection details
-        let new_host = "new.example.com";
-        let new_port = 9090;
-        db.update_mcp_server_connection(&server_id, new_host, new_port)
-            .await
-            .expect("Failed to update MCP server connection");
+        // The server connected and declared what it supports
+        let capabilities = serde_json::json!({ "tools": { "listChanged": true } });
+        let server_info = serde_json::json!({ "name": "test", "version": "1.0.0" });
+        db.update_mcp_server_connection(
+            &server_id,
+            McpConnectionStatus::Connected,
+            None,
+            Some(&capabilities),
+            Some(&server_info),
+        )
+        .await
+        .expect("Failed to update MCP server connection");
 
-        // Get the updated MCP server
         let retrieved = db
             .get_mcp_server_by_id(&server_id)
             .await
             .expect("Failed to get MCP server")
             .unwrap();
-        assert_eq!(retrieved.host, new_host);
-        assert_eq!(retrieved.port, new_port);
+        assert_eq!(retrieved.connection_status, McpConnectionStatus::Connected);
+        assert_eq!(
+            retrieved.capabilities.map(|c| c.0),
+            Some(capabilities.clone())
+        );
+        assert!(retrieved.last_connected_at.is_some());
         assert_eq!(retrieved.name, "Test Server"); // Other fields should remain unchanged
         assert_eq!(retrieved.api_key, Some("test-key".to_string()));
+
+        // A failure keeps what the server declared
+        db.update_mcp_server_connection(
+            &server_id,
+            McpConnectionStatus::Failed,
+            Some("connection refused"),
+            None,
+            None,
+        )
+        .await
+        .expect("Failed to update MCP server connection");
+
+        let retrieved = db
+            .get_mcp_server_by_id(&server_id)
+            .await
+            .expect("Failed to get MCP server")
+            .unwrap();
+        assert_eq!(retrieved.connection_status, McpConnectionStatus::Failed);
+        assert_eq!(retrieved.last_error, Some("connection refused".to_string()));
+        assert_eq!(retrieved.capabilities.map(|c| c.0), Some(capabilities));
+        assert_eq!(retrieved.server_info.map(|i| i.0), Some(server_info));
+
+        // Unknown servers are reported
+        let missing = Uuid::from_str("00000000-0000-0000-0000-000000000009").unwrap();
+        assert!(
+            db.update_mcp_server_connection(
+                &missing,
+                McpConnectionStatus::Connecting,
+                None,
+                None,
+                None
+            )
+            .await
+            .is_err()
+        );
     }
 
     #[tokio::test]
@@ -746,12 +810,20 @@ mod tests {
             id: server_id,
             name: "Test Server".to_string(),
             description: "Test description".to_string(),
-            host: "test.example.com".to_string(),
-            port: 8080,
+            transport: McpTransport::Http,
+            url: Some("http://test.example.com:8080/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: Some("original-key".to_string()),
             auth_token: None,
             is_active: true,
             is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -776,7 +848,10 @@ mod tests {
             .unwrap();
         assert_eq!(retrieved.api_key, Some("new-api-key".to_string()));
         assert_eq!(retrieved.auth_token, Some("new-auth-token".to_string()));
-        assert_eq!(retrieved.host, "test.example.com"); // Other fields should remain unchanged
+        assert_eq!(
+            retrieved.url,
+            Some("http://test.example.com:8080/mcp".to_string())
+        ); // Other fields should remain unchanged
     }
 
     #[tokio::test]
@@ -789,12 +864,20 @@ mod tests {
             id: server_id,
             name: "Test Server".to_string(),
             description: "Test description".to_string(),
-            host: "test.example.com".to_string(),
-            port: 8080,
+            transport: McpTransport::Http,
+            url: Some("http://test.example.com:8080/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: None,
             auth_token: None,
             is_active: true,
             is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -845,12 +928,20 @@ mod tests {
             id: server1_id,
             name: "Server 1".to_string(),
             description: "First server".to_string(),
-            host: "server1.example.com".to_string(),
-            port: 8081,
+            transport: McpTransport::Http,
+            url: Some("http://server1.example.com:8081/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: None,
             auth_token: None,
             is_active: true,
             is_default: true, // First one is default
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -859,12 +950,20 @@ mod tests {
             id: server2_id,
             name: "Server 2".to_string(),
             description: "Second server".to_string(),
-            host: "server2.example.com".to_string(),
-            port: 8082,
+            transport: McpTransport::Http,
+            url: Some("http://server2.example.com:8082/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: None,
             auth_token: None,
             is_active: true,
             is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -917,12 +1016,20 @@ mod tests {
             id: server_id,
             name: "Test Server".to_string(),
             description: "Test description".to_string(),
-            host: "test.example.com".to_string(),
-            port: 8080,
+            transport: McpTransport::Http,
+            url: Some("http://test.example.com:8080/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: None,
             auth_token: None,
             is_active: true,
             is_default: true,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
@@ -970,12 +1077,20 @@ mod tests {
             id: server_id,
             name: "Test Server".to_string(),
             description: "Test description".to_string(),
-            host: "test.example.com".to_string(),
-            port: 8080,
+            transport: McpTransport::Http,
+            url: Some("http://test.example.com:8080/mcp".to_string()),
+            command: None,
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
             api_key: None,
             auth_token: None,
             is_active: true,
             is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
             created_at: now,
             updated_at: now,
         };
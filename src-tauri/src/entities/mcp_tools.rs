@@ -1,6 +1,10 @@
 use crate::error::{AppError, Result};
+use crate::mcp::protocol::Tool;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::sqlite::SqliteRow;
+use sqlx::types::Json;
 use sqlx::{Pool, QueryBuilder, Row, Sqlite};
 use uuid::Uuid;
 
@@ -10,6 +14,8 @@ pub struct McpTool {
     pub mcp_server_id: Uuid,
     pub name: String,
     pub description: Option<String>,
+    /// JSON schema of the arguments, as the server declared it
+    pub input_schema: Json<Value>,
     pub is_enabled: bool,
     pub tool_type: McpToolType,
     pub status: McpToolStatus,
@@ -71,7 +77,7 @@ impl TryFrom<i32> for McpToolStatus {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct McpToolFilter {
     pub mcp_server_id: Option<Uuid>,
     pub tool_type: Option<McpToolType>,
@@ -89,14 +95,15 @@ impl McpTool {
     pub async fn create(pool: &Pool<Sqlite>, mcp_tool: &McpTool) -> Result<()> {
         sqlx::query(
             "INSERT INTO mcp_tools (
-                id, mcp_server_id, name, description, is_enabled, type, status,
+                id, mcp_server_id, name, description, input_schema, is_enabled, type, status,
                 created_at, updated_at
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(mcp_tool.id)
         .bind(mcp_tool.mcp_server_id)
         .bind(&mcp_tool.name)
         .bind(&mcp_tool.description)
+        .bind(&mcp_tool.input_schema)
         .bind(mcp_tool.is_enabled)
         .bind(mcp_tool.tool_type.to_string())
         .bind(mcp_tool.status as i32)
@@ -111,7 +118,7 @@ impl McpTool {
     /// Get MCP tool by ID
     pub async fn get_by_id(pool: &Pool<Sqlite>, id: &Uuid) -> Result<Option<McpTool>> {
         let row = sqlx::query(
-            "SELECT id, mcp_server_id, name, description, is_enabled, type, status,
+            "SELECT id, mcp_server_id, name, description, input_schema, is_enabled, type, status,
                     created_at, updated_at
              FROM mcp_tools WHERE id = ?",
         )
@@ -119,72 +126,65 @@ impl McpTool {
         .fetch_optional(pool)
         .await?;
 
-        if let Some(row) = row {
-            Ok(Some(McpTool {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                mcp_server_id: row
-                    .get::<Vec<u8>, _>("mcp_server_id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                name: row.get("name"),
-                description: row.get("description"),
-                is_enabled: row.get::<i64, _>("is_enabled") != 0,
-                tool_type: McpToolType::try_from(row.get::<String, _>("type"))?,
-                status: McpToolStatus::try_from(row.get::<i32, _>("status"))?,
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            }))
-        } else {
-            Ok(None)
-        }
+        row.map(|row| Self::from_row(&row)).transpose()
     }
 
     /// List MCP tools with filtering
     pub async fn list(pool: &Pool<Sqlite>, filter: &McpToolFilter) -> Result<Vec<McpTool>> {
         let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
-            "SELECT id, mcp_server_id, name, description, is_enabled, type, status,
+            "SELECT id, mcp_server_id, name, description, input_schema, is_enabled, type, status,
                     created_at, updated_at
              FROM mcp_tools",
         );
 
-        let mut where_conditions = Vec::new();
+        let mut has_where = false;
+        let mut add_where = |qb: &mut QueryBuilder<Sqlite>| {
+            qb.push(if has_where { " AND " } else { " WHERE " });
+            has_where = true;
+        };
 
         if let Some(mcp_server_id) = &filter.mcp_server_id {
-            where_conditions.push(format!("mcp_server_id = '{mcp_server_id}'"));
+            add_where(&mut qb);
+            qb.push("mcp_server_id = ");
+            qb.push_bind(*mcp_server_id);
         }
 
         if let Some(tool_type) = filter.tool_type {
-            where_conditions.push(format!("type = '{}'", tool_type.to_string()));
+            add_where(&mut qb);
+            qb.push("type = ");
+            qb.push_bind(tool_type.to_string());
         }
 
         if let Some(status) = filter.status {
-            where_conditions.push(format!("status = {}", status as i32));
+            add_where(&mut qb);
+            qb.push("status = ");
+            qb.push_bind(status as i32);
         }
 
         if let Some(is_enabled) = filter.is_enabled {
-            where_conditions.push(format!("is_enabled = {}", if is_enabled { 1 } else { 0 }));
+            add_where(&mut qb);
+            qb.push("is_enabled = ");
+            qb.push_bind(is_enabled);
         }
 
         if let Some(search_term) = &filter.search_term {
-            where_conditions.push(format!(
-                "(name LIKE '%{search_term}%' OR description LIKE '%{search_term}%')"
-            ));
+            let pattern = format!("%{search_term}%");
+            add_where(&mut qb);
+            qb.push("(name LIKE ");
+            qb.push_bind(pattern.clone());
+            qb.push(" OR description LIKE ");
+            qb.push_bind(pattern);
+            qb.push(")");
         }
 
         if filter.active_only.unwrap_or(false) {
-            where_conditions.push("status = 0".to_string()); // Active status
+            add_where(&mut qb);
+            qb.push("status = 0"); // Active status
         }
 
         if filter.enabled_only.unwrap_or(false) {
-            where_conditions.push("is_enabled = 1".to_string());
-        }
-
-        if !where_conditions.is_empty() {
-            qb.push(" WHERE ");
-            qb.push(where_conditions.join(" AND "));
+            add_where(&mut qb);
+            qb.push("is_enabled = 1");
         }
 
         qb.push(" ORDER BY created_at DESC");
@@ -200,42 +200,36 @@ impl McpTool {
         }
 
         let rows = qb.build().fetch_all(pool).await?;
-        let mut mcp_tools = Vec::new();
-
-        for row in rows {
-            mcp_tools.push(McpTool {
-                id: row
-                    .get::<Vec<u8>, _>("id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                mcp_server_id: row
-                    .get::<Vec<u8>, _>("mcp_server_id")
-                    .try_into()
-                    .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
-                name: row.get("name"),
-                description: row.get("description"),
-                is_enabled: row.get::<i64, _>("is_enabled") != 0,
-                tool_type: McpToolType::try_from(row.get::<String, _>("type"))?,
-                status: McpToolStatus::try_from(row.get::<i32, _>("status"))?,
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            });
-        }
+        rows.iter().map(Self::from_row).collect()
+    }
 
-        Ok(mcp_tools)
+    fn from_row(row: &SqliteRow) -> Result<McpTool> {
+        Ok(McpTool {
+            id: row.get("id"),
+            mcp_server_id: row.get("mcp_server_id"),
+            name: row.get("name"),
+            description: row.get("description"),
+            input_schema: row.get("input_schema"),
+            is_enabled: row.get::<i64, _>("is_enabled") != 0,
+            tool_type: McpToolType::try_from(row.get::<String, _>("type"))?,
+            status: McpToolStatus::try_from(row.get::<i32, _>("status"))?,
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        })
     }
 
     /// Update MCP tool
     pub async fn update(pool: &Pool<Sqlite>, mcp_tool: &McpTool) -> Result<()> {
         let affected = sqlx::query(
             "UPDATE mcp_tools SET
-                mcp_server_id = ?, name = ?, description = ?, is_enabled = ?,
-                type = ?, status = ?, updated_at = ?
+                mcp_server_id = ?, name = ?, description = ?, input_schema = ?,
+                is_enabled = ?, type = ?, status = ?, updated_at = ?
              WHERE id = ?",
         )
         .bind(mcp_tool.mcp_server_id)
         .bind(&mcp_tool.name)
         .bind(&mcp_tool.description)
+        .bind(&mcp_tool.input_schema)
         .bind(mcp_tool.is_enabled)
         .bind(mcp_tool.tool_type.to_string())
         .bind(mcp_tool.status as i32)
@@ -467,4 +461,148 @@ impl McpTool {
         tx.commit().await?;
         Ok(total_affected)
     }
+
+    /// Brings the tools of a server in line with what it lists. New tools are enabled,
+    /// tools it no longer lists are archived and listed ones are active again. Whether
+    /// a known tool is enabled is kept.
+    pub async fn sync_server_tools(
+        pool: &Pool<Sqlite>,
+        mcp_server_id: &Uuid,
+        tool_type: McpToolType,
+        tools: &[Tool],
+    ) -> Result<Vec<McpTool>> {
+        let mut tx = pool.begin().await?;
+        let now = Utc::now();
+
+        for tool in tools {
+            sqlx::query(
+                "INSERT INTO mcp_tools (
+                    id, mcp_server_id, name, description, input_schema, is_enabled, type,
+                    status, created_at, updated_at
+                ) VALUES (?, ?, ?, ?, ?, 1, ?, ?, ?, ?)
+                ON CONFLICT (mcp_server_id, name) DO UPDATE SET
+                    description = excluded.description, input_schema = excluded.input_schema,
+                    type = excluded.type, status = excluded.status,
+                    updated_at = excluded.updated_at",
+            )
+            .bind(Uuid::new_v4())
+            .bind(mcp_server_id)
+            .bind(&tool.name)
+            .bind(&tool.description)
+            .bind(Json(&tool.input_schema))
+            .bind(tool_type.to_string())
+            .bind(McpToolStatus::Active as i32)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new("UPDATE mcp_tools SET status = ");
+        qb.push_bind(McpToolStatus::Archived as i32);
+        qb.push(", updated_at = ");
+        qb.push_bind(now);
+        qb.push(" WHERE mcp_server_id = ");
+        qb.push_bind(*mcp_server_id);
+        qb.push(" AND status = ");
+        qb.push_bind(McpToolStatus::Active as i32);
+        if !tools.is_empty() {
+            qb.push(" AND name NOT IN (");
+            let mut names = qb.separated(", ");
+            for tool in tools {
+                names.push_bind(&tool.name);
+            }
+            qb.push(")");
+        }
+        qb.build().execute(&mut *tx).await?;
+
+        tx.commit().await?;
+        Self::get_by_server(pool, mcp_server_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::{McpConnectionStatus, McpServer, McpTransport};
+    use crate::storage::db::DatabaseManager;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn tool(name: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: Some(format!("The {name} tool")),
+            input_schema: json!({ "type": "object", "properties": {} }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_server_tools() {
+        let db = DatabaseManager::setup_test_db().await;
+        let server_id = Uuid::new_v4();
+        let now = Utc::now();
+        db.create_mcp_server(&McpServer {
+            id: server_id,
+            name: "files".to_string(),
+            description: String::new(),
+            transport: McpTransport::Stdio,
+            url: None,
+            command: Some("mcp-files".to_string()),
+            args: Json(Vec::new()),
+            env: Json(HashMap::new()),
+            api_key: None,
+            auth_token: None,
+            is_active: true,
+            is_default: false,
+            capabilities: None,
+            server_info: None,
+            connection_status: McpConnectionStatus::Disconnected,
+            last_error: None,
+            last_connected_at: None,
+            created_at: now,
+            updated_at: now,
+        })
+        .await
+        .expect("Failed to create MCP server");
+
+        let synced =
+            McpTool::sync_server_tools(&db.pool, &server_id, McpToolType::Local, &[
+                tool("read"),
+                tool("write"),
+            ])
+            .await
+            .expect("Failed to sync MCP tools");
+        assert_eq!(synced.len(), 2);
+        assert!(synced.iter().all(|t| t.is_enabled));
+        let write = synced.iter().find(|t| t.name == "write").unwrap();
+        McpTool::update_enabled(&db.pool, &write.id, false)
+            .await
+            .expect("Failed to disable MCP tool");
+
+        // The server stopped listing one tool and changed the other
+        let mut read = tool("read");
+        read.description = Some("Reads a file".to_string());
+        let synced = McpTool::sync_server_tools(&db.pool, &server_id, McpToolType::Local, &[read])
+            .await
+            .expect("Failed to sync MCP tools");
+        let read = synced.iter().find(|t| t.name == "read").unwrap();
+        assert_eq!(read.description.as_deref(), Some("Reads a file"));
+        assert!(matches!(read.status, McpToolStatus::Active));
+        let write = synced.iter().find(|t| t.name == "write").unwrap();
+        assert!(matches!(write.status, McpToolStatus::Archived));
+
+        // Listed again, it is active but stays disabled
+        let synced =
+            McpTool::sync_server_tools(&db.pool, &server_id, McpToolType::Local, &[
+                tool("read"),
+                tool("write"),
+            ])
+            .await
+            .expect("Failed to sync MCP tools");
+        let write = synced.iter().find(|t| t.name == "write").unwrap();
+        assert!(matches!(write.status, McpToolStatus::Active));
+        assert!(!write.is_enabled);
+        assert_eq!(synced.len(), 2);
+    }
 }
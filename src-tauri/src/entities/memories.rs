@@ -36,7 +36,7 @@ pub struct Memory {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
 pub enum MemoryType {
     Working = 0,
     Episodic = 1,
@@ -50,7 +50,7 @@ pub enum MemoryType {
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MemoryFilter {
     pub workspace_id: Option<Uuid>,
     pub participant_id: Option<Uuid>,
@@ -68,38 +68,41 @@ pub struct MemoryFilter {
 
 impl DatabaseManager {
     /// Create a new memory item
-    pub async fn create_memory(&self, memory: &CreateMemory) -> Result<()> {
+    #[instrument(skip(self, memory))]
+    pub async fn create_memory(&self, memory: &CreateMemory) -> Result<Memory> {
         let id = Uuid::new_v4();
-        sqlx::query!(
+        debug!("Creating memory with ID: {}", id);
+
+        Ok(sqlx::query_as(
             "INSERT INTO memories (
                 id, workspace_id, participant_id, conversation_id, memory_type, content,
                 summary, importance, access_count, metadata, embedding
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-            id,
-            memory.workspace_id,
-            memory.participant_id,
-            memory.conversation_id,
-            memory.memory_type,
-            memory.content,
-            memory.summary,
-            memory.importance,
-            memory.access_count,
-            memory.metadata,
-            memory.embedding,
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id, workspace_id, participant_id, conversation_id, memory_type, content,
+                summary, importance, last_accessed_at, access_count, metadata, embedding,
+                created_at, updated_at",
         )
-        .execute(&self.pool)
-        .await?;
-
-        Ok(())
+        .bind(id)
+        .bind(memory.workspace_id)
+        .bind(memory.participant_id)
+        .bind(memory.conversation_id)
+        .bind(memory.memory_type)
+        .bind(&memory.content)
+        .bind(&memory.summary)
+        .bind(memory.importance)
+        .bind(memory.access_count)
+        .bind(&memory.metadata)
+        .bind(&memory.embedding)
+        .fetch_one(&self.pool)
+        .await?)
     }
 
     /// Get memory item by ID
     pub async fn get_memory_by_id(&self, id: &Uuid) -> Result<Option<Memory>> {
         Ok(sqlx::query_as(
-            "SELECT id, workspace_id, participant_id, conversation_id, memory_type, payload,
-                    priority, confidence_score, emotional_valence, emotional_arousal,
-                    last_accessed_at, metadata, created_at, updated_at, memory_source_id,
-                    memory_context_id, memory_session_id, intention_id, concept_id
+            "SELECT id, workspace_id, participant_id, conversation_id, memory_type, content,
+                summary, importance, last_accessed_at, access_count, metadata, embedding,
+                created_at, updated_at
              FROM memories WHERE id = ?",
         )
         .bind(id)
@@ -111,7 +114,8 @@ impl DatabaseManager {
     pub async fn list_memories(&self, filter: &MemoryFilter) -> Result<Vec<Memory>> {
         let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
             "SELECT id, workspace_id, participant_id, conversation_id, memory_type, content,
-                summary, importance, access_count, metadata, embedding, created_at, updated_at
+                summary, importance, last_accessed_at, access_count, metadata, embedding,
+                created_at, updated_at
              FROM memories",
         );
 
@@ -229,6 +233,54 @@ impl DatabaseManager {
         Ok(())
     }
 
+    /// Record that memories were recalled
+    #[instrument(skip(self))]
+    pub async fn touch_memories(&self, ids: &[Uuid]) -> Result<u64> {
+        debug!("Touching {} memories", ids.len());
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "UPDATE memories SET access_count = access_count + 1,
+                last_accessed_at = CURRENT_TIMESTAMP
+             WHERE id IN (",
+        );
+        let mut separated = qb.separated(", ");
+        for id in ids {
+            separated.push_bind(id);
+        }
+        qb.push(")");
+
+        Ok(qb.build().execute(&self.pool).await?.rows_affected())
+    }
+
+    /// Multiply the importance of memories that weren't accessed since `accessed_before`
+    /// by `factor`, without letting it drop below `floor`
+    #[instrument(skip(self))]
+    pub async fn decay_memory_importance(
+        &self,
+        memory_type: MemoryType,
+        factor: f64,
+        floor: f64,
+        accessed_before: DateTime<Utc>,
+    ) -> Result<u64> {
+        debug!("Decaying {:?} memories by {}", memory_type, factor);
+
+        Ok(sqlx::query!(
+            "UPDATE memories SET importance = MAX(?, importance * ?)
+             WHERE memory_type = ? AND importance > ? AND last_accessed_at < ?",
+            floor,
+            factor,
+            memory_type,
+            floor,
+            accessed_before
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected())
+    }
+
     /// Delete memory item
     pub async fn delete_memory(&self, id: &Uuid) -> Result<()> {
         let affected = sqlx::query!("DELETE FROM memories WHERE id = ?", id)
@@ -1,29 +1,41 @@
 // Core entities
 pub mod accounts;
+pub mod agent_chain_executions;
+pub mod agent_chains;
 pub mod agents;
 pub mod api_keys;
 pub mod attachments;
+pub mod audit_log;
 pub mod contacts;
 pub mod conversation_participants;
 pub mod conversations;
+pub mod credentials;
 pub mod document_chunks;
 pub mod documents;
+pub mod file_transfers;
 pub mod files;
 pub mod group_members;
 pub mod groups;
+pub mod key_rotations;
 pub mod mcp_servers;
 pub mod mcp_tools;
 pub mod memories;
 pub mod messages;
 pub mod models;
+pub mod notes;
 pub mod registry;
+pub mod settings;
 pub mod notifications;
 pub mod p2p_message_queue;
 pub mod p2p_nodes;
+pub mod peer_permissions;
 pub mod participants;
 pub mod prompts;
+pub mod published_agents;
 pub mod tools;
 pub mod users;
+pub mod workflow_executions;
+pub mod workflows;
 pub mod workspaces;
 
 // Task & Event management entities
@@ -36,30 +48,42 @@ pub mod peer_id;
 
 // Type re-exports
 pub use accounts::*;
+pub use agent_chain_executions::*;
+pub use agent_chains::*;
 pub use agents::*;
 pub use api_keys::*;
 pub use attachments::*;
+pub use audit_log::*;
 pub use contacts::*;
 pub use conversation_participants::*;
 pub use conversations::*;
+pub use credentials::*;
 pub use document_chunks::*;
 pub use documents::*;
+pub use file_transfers::*;
 pub use files::*;
 pub use group_members::*;
 pub use groups::*;
+pub use key_rotations::*;
 pub use mcp_servers::*;
 pub use mcp_tools::*;
 pub use memories::*;
 pub use messages::*;
 pub use models::*;
+pub use notes::*;
 pub use registry::*;
+pub use settings::*;
 pub use notifications::*;
 pub use p2p_message_queue::*;
 pub use p2p_nodes::*;
 pub use peer_id::*;
+pub use peer_permissions::*;
 pub use participants::{Participant, ParticipantFilter, ParticipantStatus, ParticipantType, CreateParticipant};
 pub use prompts::*;
+pub use published_agents::*;
 pub use users::*;
+pub use workflow_executions::*;
+pub use workflows::*;
 pub use workspaces::*;
 
 
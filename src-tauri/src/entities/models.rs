@@ -20,9 +20,10 @@ pub enum ModelType {
     Mcp = 1,
     Tool = 2,
     Other = 3,
+    Embedding = 4,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type)]
 #[serde(rename_all = "lowercase")]
 pub enum ModelProvider {
     OpenAI = 0,
@@ -35,6 +36,22 @@ pub enum ModelProvider {
     Ollama = 7,
 }
 
+impl ModelProvider {
+    /// The `credential_name` under which keys for this provider are stored
+    pub fn credential_name(&self) -> &'static str {
+        match self {
+            ModelProvider::OpenAI => "openai",
+            ModelProvider::Cohere => "cohere",
+            ModelProvider::Anthropic => "anthropic",
+            ModelProvider::Perplexity => "perplexity",
+            ModelProvider::Gemini => "gemini",
+            ModelProvider::XAi => "xai",
+            ModelProvider::DeepSeek => "deepseek",
+            ModelProvider::Ollama => "ollama",
+        }
+    }
+}
+
 #[boilermates("CreateModel")]
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 #[serde(rename_all = "camelCase")]
@@ -229,13 +246,11 @@ impl DatabaseManager {
         qb.push(" ORDER BY updated_at DESC");
 
         if let Some(limit) = filter.limit {
-            add_where(&mut qb);
             qb.push(" LIMIT ");
             qb.push_bind(limit as i64);
         }
 
         if let Some(offset) = filter.offset {
-            add_where(&mut qb);
             qb.push(" OFFSET ");
             qb.push_bind(offset as i64);
         }
@@ -0,0 +1,225 @@
+use boilermates::boilermates;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Sqlite};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteType {
+    Note = 0,
+    Task = 1,
+    Event = 2,
+    Other = 3,
+}
+
+/// Note model matching the SQLite schema
+#[boilermates("CreateNote")]
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+    #[boilermates(not_in("CreateNote"))]
+    pub id: Uuid,
+    pub workspace_id: Option<Uuid>,
+    pub parent_note_id: Option<Uuid>,
+    pub task_id: Option<Uuid>,
+    pub event_id: Option<Uuid>,
+    pub note_type: NoteType,
+    pub title: String,
+    pub content: String,
+    pub metadata: Option<Json<Value>>,
+    #[boilermates(not_in("CreateNote"))]
+    pub created_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateNote"))]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Additional filtering options for note queries
+#[skip_serializing_none]
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteFilter {
+    pub workspace_id: Option<Uuid>,
+    pub parent_note_id: Option<Uuid>,
+    pub task_id: Option<Uuid>,
+    pub event_id: Option<Uuid>,
+    pub note_type: Option<NoteType>,
+    pub search_term: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl DatabaseManager {
+    /// Create a new note
+    #[instrument(err, skip(self, note))]
+    pub async fn create_note(&self, note: &CreateNote) -> Result<Note> {
+        let id = Uuid::new_v4();
+        debug!("Creating note with ID: {}", id);
+        let metadata = note.metadata.as_deref();
+        let now = Utc::now();
+
+        Ok(sqlx::query_as!(
+            Note,
+            r#"INSERT INTO notes (
+                id, workspace_id, parent_note_id, task_id, event_id, type, title, content,
+                metadata, created_at, updated_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING
+                id as "id: _", workspace_id as "workspace_id: _", parent_note_id as "parent_note_id: _",
+                task_id as "task_id: _", event_id as "event_id: _", type as "note_type: NoteType",
+                title, content, metadata as "metadata: _", created_at as "created_at: _",
+                updated_at as "updated_at: _""#,
+            id,
+            note.workspace_id,
+            note.parent_note_id,
+            note.task_id,
+            note.event_id,
+            note.note_type,
+            note.title,
+            note.content,
+            metadata,
+            now,
+            now
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Get note by ID
+    #[instrument(err, skip(self))]
+    pub async fn get_note_by_id(&self, id: &Uuid) -> Result<Option<Note>> {
+        debug!("Getting note by ID: {}", id);
+        Ok(sqlx::query_as!(
+            Note,
+            r#"SELECT
+                id as "id: _", workspace_id as "workspace_id: _", parent_note_id as "parent_note_id: _",
+                task_id as "task_id: _", event_id as "event_id: _", type as "note_type: NoteType",
+                title, content, metadata as "metadata: _", created_at as "created_at: _",
+                updated_at as "updated_at: _"
+            FROM notes WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// List notes with filtering, most recently updated first
+    #[instrument(err, skip(self))]
+    pub async fn list_notes(&self, filter: &NoteFilter) -> Result<Vec<Note>> {
+        debug!("Listing notes with filter: {:?}", filter);
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
+            r#"SELECT
+                id, workspace_id, parent_note_id, task_id, event_id, type AS note_type, title,
+                content, metadata, created_at, updated_at
+            FROM notes"#,
+        );
+
+        let mut add_where = add_where();
+
+        if let Some(workspace_id) = &filter.workspace_id {
+            add_where(&mut qb);
+            qb.push("workspace_id = ");
+            qb.push_bind(workspace_id);
+        }
+        if let Some(parent_note_id) = &filter.parent_note_id {
+            add_where(&mut qb);
+            qb.push("parent_note_id = ");
+            qb.push_bind(parent_note_id);
+        }
+        if let Some(task_id) = &filter.task_id {
+            add_where(&mut qb);
+            qb.push("task_id = ");
+            qb.push_bind(task_id);
+        }
+        if let Some(event_id) = &filter.event_id {
+            add_where(&mut qb);
+            qb.push("event_id = ");
+            qb.push_bind(event_id);
+        }
+        if let Some(note_type) = &filter.note_type {
+            add_where(&mut qb);
+            qb.push("type = ");
+            qb.push_bind(*note_type);
+        }
+        if let Some(search_term) = &filter.search_term {
+            let pattern = format!("%{search_term}%");
+            add_where(&mut qb);
+            qb.push("(title LIKE ");
+            qb.push_bind(pattern.clone());
+            qb.push(" OR content LIKE ");
+            qb.push_bind(pattern);
+            qb.push(")");
+        }
+
+        qb.push(" ORDER BY updated_at DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as::<Note>().fetch_all(&self.pool).await?)
+    }
+
+    /// Update note
+    #[instrument(err, skip(self, note))]
+    pub async fn update_note(&self, note: &Note) -> Result<Note> {
+        debug!("Updating note with ID: {}", note.id);
+        let metadata = note.metadata.as_deref();
+        let now = Utc::now();
+
+        sqlx::query_as!(
+            Note,
+            r#"UPDATE notes SET
+                workspace_id = ?, parent_note_id = ?, task_id = ?, event_id = ?, type = ?,
+                title = ?, content = ?, metadata = ?, updated_at = ?
+            WHERE id = ?
+            RETURNING
+                id as "id: _", workspace_id as "workspace_id: _", parent_note_id as "parent_note_id: _",
+                task_id as "task_id: _", event_id as "event_id: _", type as "note_type: NoteType",
+                title, content, metadata as "metadata: _", created_at as "created_at: _",
+                updated_at as "updated_at: _""#,
+            note.workspace_id,
+            note.parent_note_id,
+            note.task_id,
+            note.event_id,
+            note.note_type,
+            note.title,
+            note.content,
+            metadata,
+            now,
+            note.id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("Note", note.id))
+    }
+
+    /// Delete note
+    #[instrument(err, skip(self))]
+    pub async fn delete_note(&self, id: &Uuid) -> Result<()> {
+        debug!("Deleting note with ID: {}", id);
+        let affected = sqlx::query!("DELETE FROM notes WHERE id = ?", id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if affected == 0 {
+            return Err(AppError::not_found("Note", id));
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
+use crate::entities::PeerIdWrapper;
 use crate::error::{AppError, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, QueryBuilder, Row, Sqlite};
+use sqlx::{Pool, QueryBuilder, Row, Sqlite, sqlite::SqliteRow};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -634,6 +635,118 @@ impl P2pMessageQueue {
         Self::list(pool, &filter).await
     }
 
+    /// Get the messages that are waiting for delivery to a peer, in the order they should
+    /// be sent
+    pub async fn get_deliverable_for_peer(
+        pool: &Pool<Sqlite>,
+        peer_id: &str,
+    ) -> Result<Vec<P2pMessageQueue>> {
+        let rows = sqlx::query(
+            "SELECT id, from_peer_id, to_peer_id, message_type, priority, payload,
+                    conversation_id, agent_chain_execution_id, status, retry_count, max_retries,
+                    expires_at, sent_at, delivered_at, error_details, created_at
+             FROM p2p_message_queue
+             WHERE to_peer_id = ?
+               AND (status = 0 OR (status = 3 AND retry_count < max_retries))
+               AND (expires_at IS NULL OR expires_at > ?)
+             ORDER BY priority DESC, created_at ASC, rowid ASC",
+        )
+        .bind(peer_id)
+        .bind(Utc::now())
+        .fetch_all(pool)
+        .await?;
+
+        rows.iter().map(Self::from_row).collect()
+    }
+
+    /// Get the peers that have messages waiting for delivery
+    pub async fn get_peers_with_deliverable_messages(pool: &Pool<Sqlite>) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar(
+            "SELECT DISTINCT to_peer_id FROM p2p_message_queue
+             WHERE (status = 0 OR (status = 3 AND retry_count < max_retries))
+               AND (expires_at IS NULL OR expires_at > ?)",
+        )
+        .bind(Utc::now())
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Mark messages that were sent before `sent_before` but never acknowledged as failed
+    pub async fn mark_unacknowledged_as_failed(
+        pool: &Pool<Sqlite>,
+        sent_before: &DateTime<Utc>,
+    ) -> Result<u64> {
+        let affected = sqlx::query(
+            "UPDATE p2p_message_queue
+             SET status = 3, retry_count = retry_count + 1, error_details = 'Not acknowledged'
+             WHERE status = 1 AND sent_at < ?",
+        )
+        .bind(sent_before)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        Ok(affected)
+    }
+
+    /// Remember a signed message a peer delivered from its queue, returning whether it
+    /// wasn't received before
+    pub async fn record_received(
+        pool: &Pool<Sqlite>,
+        signer: &PeerIdWrapper,
+        issued_at: &DateTime<Utc>,
+        nonce: &Uuid,
+    ) -> Result<bool> {
+        let affected = sqlx::query(
+            "INSERT INTO received_messages (peer_id, nonce, issued_at) VALUES (?, ?, ?)
+             ON CONFLICT (peer_id, nonce) DO NOTHING",
+        )
+        .bind(signer)
+        .bind(nonce)
+        .bind(issued_at)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        Ok(affected == 1)
+    }
+
+    fn from_row(row: &SqliteRow) -> Result<P2pMessageQueue> {
+        Ok(P2pMessageQueue {
+            id: row
+                .get::<Vec<u8>, _>("id")
+                .try_into()
+                .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))?,
+            from_peer_id: row.get("from_peer_id"),
+            to_peer_id: row.get("to_peer_id"),
+            message_type: P2pMessageType::try_from(row.get::<i32, _>("message_type"))?,
+            priority: P2pMessagePriority::try_from(row.get::<i32, _>("priority"))?,
+            payload: row.get("payload"),
+            conversation_id: row
+                .get::<Option<Vec<u8>>, _>("conversation_id")
+                .map(|v| {
+                    v.try_into()
+                        .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))
+                })
+                .transpose()?,
+            agent_chain_execution_id: row
+                .get::<Option<Vec<u8>>, _>("agent_chain_execution_id")
+                .map(|v| {
+                    v.try_into()
+                        .map_err(|_| AppError::DatabaseError("Invalid UUID".to_string()))
+                })
+                .transpose()?,
+            status: P2pMessageStatus::try_from(row.get::<i32, _>("status"))?,
+            retry_count: row.get("retry_count"),
+            max_retries: row.get("max_retries"),
+            expires_at: row.get("expires_at"),
+            sent_at: row.get("sent_at"),
+            delivered_at: row.get("delivered_at"),
+            error_details: row.get("error_details"),
+            created_at: row.get("created_at"),
+        })
+    }
+
     /// Count messages by status
     pub async fn count_by_status(pool: &Pool<Sqlite>, status: P2pMessageStatus) -> Result<i64> {
         let row = sqlx::query("SELECT COUNT(*) as count FROM p2p_message_queue WHERE status = ?")
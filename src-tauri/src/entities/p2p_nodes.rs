@@ -1,7 +1,7 @@
 use boilermates::boilermates;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Value, json};
 use serde_with::skip_serializing_none;
 use sqlx::prelude::FromRow;
 use sqlx::types::Json;
@@ -14,6 +14,10 @@ use crate::error::{AppError, Result};
 use crate::storage::db::DatabaseManager;
 use crate::utils::add_where;
 
+/// Participant of the nodes that were discovered on the network but haven't been linked
+/// to a contact yet
+pub const UNASSIGNED_PARTICIPANT_ID: Uuid = Uuid::nil();
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
 #[serde(rename_all = "lowercase")]
 pub enum P2pNodeStatus {
@@ -65,6 +69,8 @@ pub struct P2pNodeFilter {
     pub search_term: Option<String>, // Search in peer_id or multiaddr
     pub last_seen_after: Option<DateTime<Utc>>,
     pub last_seen_before: Option<DateTime<Utc>>,
+    /// Best links first instead of the most recently seen nodes
+    pub order_by_quality: Option<bool>,
     pub limit: Option<usize>,
     pub offset: Option<usize>,
 }
@@ -233,7 +239,11 @@ impl DatabaseManager {
             qb.push_bind(last_seen_before);
         }
 
-        qb.push(" ORDER BY last_seen DESC");
+        if filter.order_by_quality.unwrap_or(false) {
+            qb.push(" ORDER BY connection_quality DESC NULLS LAST, latency_ms ASC NULLS LAST");
+        } else {
+            qb.push(" ORDER BY last_seen DESC");
+        }
 
         if let Some(limit) = filter.limit {
             qb.push(" LIMIT ");
@@ -319,6 +329,77 @@ impl DatabaseManager {
         Ok(())
     }
 
+    /// Get the peer ids of every node registered for a contact
+    #[instrument(skip(self))]
+    pub async fn get_peer_ids_by_contact_id(&self, contact_id: &Uuid) -> Result<Vec<PeerIdWrapper>> {
+        debug!("Getting peer ids for contact: {}", contact_id);
+
+        Ok(sqlx::query_scalar!(
+            r#"SELECT pn.peer_id AS "peer_id: PeerIdWrapper"
+                FROM p2p_nodes pn
+                INNER JOIN participants p ON p.id = pn.participant_id
+                WHERE p.contact_id = ?"#,
+            contact_id
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Records a peer found through mDNS or a rendezvous point as online. Nodes we
+    /// already know of are updated, unknown peers are stored without a participant.
+    #[instrument(skip(self))]
+    pub async fn record_discovered_peer(
+        &self,
+        peer_id: &PeerIdWrapper,
+        multiaddr: &str,
+        source: &str,
+    ) -> Result<()> {
+        debug!("Recording peer {} discovered through {}", peer_id, source);
+
+        let now = Utc::now();
+        let known: Vec<P2pNode> = sqlx::query_as(
+            "SELECT participant_id, peer_id, node_type, multiaddr, public_key,
+                capabilities, status, last_seen, connection_quality, latency_ms,
+                metadata, created_at, updated_at
+            FROM p2p_nodes WHERE peer_id = ?",
+        )
+        .bind(peer_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let nodes = if known.is_empty() {
+            vec![P2pNode {
+                participant_id: UNASSIGNED_PARTICIPANT_ID,
+                peer_id: peer_id.clone(),
+                node_type: P2pNodeType::GatewayNode,
+                multiaddr: multiaddr.to_string(),
+                public_key: None,
+                capabilities: None,
+                status: P2pNodeStatus::Online,
+                last_seen: Some(now),
+                connection_quality: None,
+                latency_ms: None,
+                metadata: Some(Json(json!({ "discoveredVia": source }))),
+                created_at: now,
+                updated_at: now,
+            }]
+        } else {
+            known
+                .into_iter()
+                .map(|node| P2pNode {
+                    multiaddr: multiaddr.to_string(),
+                    status: P2pNodeStatus::Online,
+                    last_seen: Some(now),
+                    updated_at: now,
+                    ..node
+                })
+                .collect()
+        };
+        for node in &nodes {
+            self.upsert(node).await?;
+        }
+        Ok(())
+    }
+
     /// Get online nodes
     #[instrument(skip(self))]
     pub async fn get_online_nodes(&self) -> Result<Vec<P2pNode>> {
@@ -332,6 +413,7 @@ impl DatabaseManager {
             search_term: None,
             last_seen_after: None,
             last_seen_before: None,
+            order_by_quality: None,
             limit: None,
             offset: None,
         };
@@ -352,6 +434,7 @@ impl DatabaseManager {
             search_term: None,
             last_seen_after: None,
             last_seen_before: None,
+            order_by_quality: None,
             limit: None,
             offset: None,
         };
@@ -372,6 +455,7 @@ impl DatabaseManager {
             search_term: None,
             last_seen_after: None,
             last_seen_before: None,
+            order_by_quality: Some(true),
             limit: None,
             offset: None,
         };
@@ -406,33 +490,35 @@ impl DatabaseManager {
         Ok(())
     }
 
-    /// Update connection quality
+    /// Update the connection quality of every node of the peer, which is a property of
+    /// our link to the peer rather than of a participant
     #[instrument(skip(self))]
     pub async fn update_p2p_node_connection_quality(
         &self,
-        peer_id: &Uuid,
-        participant_id: &Uuid,
+        peer_id: &PeerIdWrapper,
         quality: f64,
         latency_ms: Option<i64>,
     ) -> Result<()> {
-        debug!(
-            "Updating connection quality for P2P node with peer_id: {peer_id} and participant_id: {participant_id}",
-        );
+        debug!("Updating connection quality for P2P node with peer_id: {peer_id}");
 
-        let affected = sqlx::query!(
-            "UPDATE p2p_nodes SET connection_quality = ?, latency_ms = ? WHERE peer_id = ? AND participant_id = ?",
-            quality,
-            latency_ms,
-            peer_id,
-            participant_id
+        let now = Utc::now();
+        let affected = sqlx::query(
+            "UPDATE p2p_nodes SET connection_quality = ?, latency_ms = COALESCE(?, latency_ms),
+                last_seen = ?, updated_at = ?
+            WHERE peer_id = ?",
         )
+        .bind(quality)
+        .bind(latency_ms)
+        .bind(now)
+        .bind(now)
+        .bind(peer_id)
         .execute(&self.pool)
         .await?
         .rows_affected();
 
         if affected == 0 {
             return Err(AppError::NotFoundError(format!(
-                "P2P node with peer_id {peer_id} and participant_id {participant_id} not found"
+                "P2P node with peer_id {peer_id} not found"
             )));
         }
 
@@ -464,15 +550,15 @@ impl DatabaseManager {
 
     /// Mark node as online
     #[instrument(skip(self))]
-    pub async fn mark_online(&self, peer_id: &str) -> Result<()> {
+    pub async fn mark_online(&self, peer_id: &PeerIdWrapper) -> Result<()> {
         let now = Utc::now();
 
-        let affected = sqlx::query!(
+        let affected = sqlx::query(
             "UPDATE p2p_nodes SET status = 0, last_seen = ?, updated_at = ? WHERE peer_id = ?",
-            now,
-            now,
-            peer_id
         )
+        .bind(now)
+        .bind(now)
+        .bind(peer_id)
         .execute(&self.pool)
         .await?
         .rows_affected();
@@ -488,14 +574,77 @@ impl DatabaseManager {
 
     /// Mark node as offline
     #[instrument(skip(self))]
-    pub async fn mark_offline(&self, peer_id: &str) -> Result<()> {
+    pub async fn mark_offline(&self, peer_id: &PeerIdWrapper) -> Result<()> {
         let now = Utc::now();
 
-        let affected = sqlx::query!(
-            "UPDATE p2p_nodes SET status = 1, updated_at = ? WHERE peer_id = ?",
-            now,
-            peer_id
+        let affected =
+            sqlx::query("UPDATE p2p_nodes SET status = 1, updated_at = ? WHERE peer_id = ?")
+                .bind(now)
+                .bind(peer_id)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+
+        if affected == 0 {
+            return Err(AppError::NotFoundError(format!(
+                "P2P node with peer_id {peer_id} not found"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Stores what a peer told us about itself through identify. Peers that offer
+    /// relay reservations become relay nodes.
+    #[instrument(skip(self, metadata))]
+    pub async fn record_peer_identity(
+        &self,
+        peer_id: &PeerIdWrapper,
+        protocols: &[String],
+        is_relay: bool,
+        metadata: &Value,
+    ) -> Result<()> {
+        debug!("Recording identity of P2P node with peer_id: {peer_id}");
+
+        let affected = sqlx::query(
+            "UPDATE p2p_nodes SET capabilities = ?,
+                node_type = CASE WHEN ? THEN ? ELSE node_type END,
+                metadata = json_patch(COALESCE(metadata, '{}'), ?), updated_at = ?
+            WHERE peer_id = ?",
+        )
+        .bind(Json(protocols))
+        .bind(is_relay)
+        .bind(P2pNodeType::RelayNode as i32)
+        .bind(Json(metadata))
+        .bind(Utc::now())
+        .bind(peer_id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        if affected == 0 {
+            return Err(AppError::NotFoundError(format!(
+                "P2P node with peer_id {peer_id} not found"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Merge the given object into the metadata of every node of the peer
+    #[instrument(skip(self))]
+    pub async fn patch_p2p_node_metadata(
+        &self,
+        peer_id: &PeerIdWrapper,
+        metadata: &Value,
+    ) -> Result<()> {
+        let affected = sqlx::query(
+            "UPDATE p2p_nodes SET metadata = json_patch(COALESCE(metadata, '{}'), ?), updated_at = ?
+            WHERE peer_id = ?",
         )
+        .bind(Json(metadata))
+        .bind(Utc::now())
+        .bind(peer_id)
         .execute(&self.pool)
         .await?
         .rows_affected();
@@ -0,0 +1,483 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Sqlite};
+use tracing::{debug, instrument, warn};
+use uuid::Uuid;
+
+use crate::entities::{AuditLogEntry, PeerIdWrapper, audit_log::insert_audit_log_entry};
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerPermissionStatus {
+    /// The peer is unknown and waits for the user to allow or deny it
+    Pending = 0,
+    Allowed = 1,
+    Denied = 2,
+}
+
+/// The kinds of messages a peer can send to our gateway
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PeerMessageKind {
+    CreateConversation,
+    SendMessage,
+    ChatMessage,
+    AgentResponse,
+    AgentRequest,
+    ListAgents,
+    GetTools,
+    UseTool,
+    KeyRotation,
+}
+
+/// Messages a peer that belongs to one of our contacts may send without being approved
+pub const CONTACT_MESSAGES: &[PeerMessageKind] = &[
+    PeerMessageKind::CreateConversation,
+    PeerMessageKind::SendMessage,
+    PeerMessageKind::ChatMessage,
+    PeerMessageKind::AgentResponse,
+    PeerMessageKind::KeyRotation,
+];
+
+/// What a peer asks our gateway to do
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum PeerAction {
+    CreateConversation,
+    SendMessage,
+    ChatMessage,
+    AgentResponse,
+    #[serde(rename_all = "camelCase")]
+    AgentRequest {
+        agent_id: Uuid,
+    },
+    ListAgents,
+    GetTools,
+    UseTool {
+        tool: String,
+    },
+    KeyRotation,
+}
+
+impl PeerAction {
+    pub fn kind(&self) -> PeerMessageKind {
+        match self {
+            PeerAction::CreateConversation => PeerMessageKind::CreateConversation,
+            PeerAction::SendMessage => PeerMessageKind::SendMessage,
+            PeerAction::ChatMessage => PeerMessageKind::ChatMessage,
+            PeerAction::AgentResponse => PeerMessageKind::AgentResponse,
+            PeerAction::AgentRequest { .. } => PeerMessageKind::AgentRequest,
+            PeerAction::ListAgents => PeerMessageKind::ListAgents,
+            PeerAction::GetTools => PeerMessageKind::GetTools,
+            PeerAction::UseTool { .. } => PeerMessageKind::UseTool,
+            PeerAction::KeyRotation => PeerMessageKind::KeyRotation,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "decision", content = "reason")]
+pub enum PeerDecision {
+    Allow,
+    Deny(String),
+    /// The peer hasn't been approved yet
+    Pending,
+}
+
+impl PeerDecision {
+    fn operation(&self) -> &'static str {
+        match self {
+            PeerDecision::Allow => "ALLOW",
+            PeerDecision::Deny(_) => "DENY",
+            PeerDecision::Pending => "PENDING",
+        }
+    }
+}
+
+/// What a remote peer is allowed to do on this machine
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerPermission {
+    pub id: Uuid,
+    pub peer_id: PeerIdWrapper,
+    pub contact_id: Option<Uuid>,
+    pub status: PeerPermissionStatus,
+    pub allowed_messages: Json<Vec<PeerMessageKind>>,
+    /// Names of the local tools the peer may use
+    pub allowed_tools: Json<Vec<String>>,
+    /// Ids of the local agents the peer may send requests to
+    pub allowed_agents: Json<Vec<Uuid>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PeerPermission {
+    pub fn decide(&self, action: &PeerAction) -> PeerDecision {
+        match self.status {
+            PeerPermissionStatus::Pending => return PeerDecision::Pending,
+            PeerPermissionStatus::Denied => {
+                return PeerDecision::Deny("The peer has been denied".into());
+            }
+            PeerPermissionStatus::Allowed => {}
+        }
+        let kind = action.kind();
+        if !self.allowed_messages.contains(&kind) {
+            return PeerDecision::Deny(format!("The peer may not send {kind:?} messages"));
+        }
+        match action {
+            PeerAction::UseTool { tool } if !self.allows_tool(tool) => {
+                PeerDecision::Deny(format!("The peer may not use the tool {tool}"))
+            }
+            PeerAction::AgentRequest { agent_id } if !self.allows_agent(agent_id) => {
+                PeerDecision::Deny(format!("The peer may not use the agent {agent_id}"))
+            }
+            _ => PeerDecision::Allow,
+        }
+    }
+
+    pub fn allows_tool(&self, tool: &str) -> bool {
+        self.allowed_tools.iter().any(|allowed| allowed == tool)
+    }
+
+    pub fn allows_agent(&self, agent_id: &Uuid) -> bool {
+        self.allowed_agents.contains(agent_id)
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerPermissionFilter {
+    pub status: Option<PeerPermissionStatus>,
+    pub contact_id: Option<Uuid>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// How many unknown peers may wait for approval before further ones are turned away
+/// without being stored
+pub const MAX_PENDING_PEERS: i64 = 1_000;
+
+/// Pending and denied decisions for a peer are audited at most once in this many seconds
+pub const AUDIT_COALESCE_SECS: i64 = 60;
+
+const COLUMNS: &str = "id, peer_id, contact_id, status, allowed_messages, allowed_tools, \
+    allowed_agents, created_at, updated_at";
+
+impl DatabaseManager {
+    /// Get the permissions of a peer
+    #[instrument(skip(self))]
+    pub async fn get_peer_permission(
+        &self,
+        peer_id: &PeerIdWrapper,
+    ) -> Result<Option<PeerPermission>> {
+        debug!("Getting permissions of peer: {}", peer_id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM peer_permissions WHERE peer_id = ?"
+        ))
+        .bind(peer_id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// List peer permissions with filtering
+    #[instrument(skip(self))]
+    pub async fn list_peer_permissions(
+        &self,
+        filter: &PeerPermissionFilter,
+    ) -> Result<Vec<PeerPermission>> {
+        debug!("Listing peer permissions with filter: {:?}", filter);
+
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {COLUMNS} FROM peer_permissions"));
+        let mut add_where = add_where();
+
+        if let Some(status) = filter.status {
+            add_where(&mut qb);
+            qb.push("status = ");
+            qb.push_bind(status as i64);
+        }
+
+        if let Some(contact_id) = &filter.contact_id {
+            add_where(&mut qb);
+            qb.push("contact_id = ");
+            qb.push_bind(contact_id);
+        }
+
+        qb.push(" ORDER BY created_at DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Update the permissions of a peer and record the change in the audit log
+    #[instrument(err, skip(self, permission))]
+    pub async fn update_peer_permission(
+        &self,
+        permission: &PeerPermission,
+    ) -> Result<PeerPermission> {
+        debug!("Updating permissions of peer: {}", permission.peer_id);
+
+        let mut tx = self.pool.begin().await?;
+        let old: PeerPermission = sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM peer_permissions WHERE id = ?"
+        ))
+        .bind(permission.id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::not_found("PeerPermission", permission.id))?;
+        let updated: PeerPermission = sqlx::query_as(&format!(
+            "UPDATE peer_permissions SET
+                contact_id = ?, status = ?, allowed_messages = ?, allowed_tools = ?,
+                allowed_agents = ?
+            WHERE id = ?
+            RETURNING {COLUMNS}"
+        ))
+        .bind(permission.contact_id)
+        .bind(permission.status)
+        .bind(&permission.allowed_messages)
+        .bind(&permission.allowed_tools)
+        .bind(&permission.allowed_agents)
+        .bind(permission.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        insert_audit_log_entry(
+            &mut tx,
+            &AuditLogEntry {
+                id: Uuid::new_v4(),
+                table_name: "peer_permissions".into(),
+                record_id: updated.id,
+                operation: "UPDATE".into(),
+                user_id: None,
+                old_values: Some(Json(serde_json::to_value(&old)?)),
+                new_values: Some(Json(serde_json::to_value(&updated)?)),
+                ip_address: None,
+                user_agent: None,
+                timestamp: Utc::now(),
+            },
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    /// Decides whether a peer may perform an action and records the decision in the
+    /// audit log. Peers seen for the first time get a permission row: peers of a known
+    /// contact may send the [`CONTACT_MESSAGES`], everyone else is pending approval.
+    /// Once [`MAX_PENDING_PEERS`] are waiting, further unknown peers aren't stored, and
+    /// pending or denied decisions are audited once per [`AUDIT_COALESCE_SECS`].
+    ///
+    /// Returns the decision and whether the peer was seen for the first time.
+    #[instrument(err, skip(self))]
+    pub async fn authorize_peer(
+        &self,
+        peer_id: &PeerIdWrapper,
+        action: &PeerAction,
+    ) -> Result<(PeerDecision, bool)> {
+        let select = format!("SELECT {COLUMNS} FROM peer_permissions WHERE peer_id = ?");
+        let mut tx = self.pool.begin().await?;
+        let existing: Option<PeerPermission> = sqlx::query_as(&select)
+            .bind(peer_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let mut is_new = false;
+        let permission = match existing {
+            Some(permission) => permission,
+            None => {
+                let contact_id: Option<Uuid> = sqlx::query_scalar(
+                    "SELECT p.contact_id
+                    FROM p2p_nodes pn
+                    INNER JOIN participants p ON p.id = pn.participant_id
+                    WHERE pn.peer_id = ? AND p.contact_id IS NOT NULL
+                    LIMIT 1",
+                )
+                .bind(peer_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let (status, allowed_messages) = match contact_id {
+                    Some(_) => (PeerPermissionStatus::Allowed, CONTACT_MESSAGES.to_vec()),
+                    None => (PeerPermissionStatus::Pending, Vec::new()),
+                };
+                if status == PeerPermissionStatus::Pending {
+                    let pending: i64 = sqlx::query_scalar(
+                        "SELECT COUNT(*) FROM peer_permissions WHERE status = ?",
+                    )
+                    .bind(PeerPermissionStatus::Pending)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    if pending >= MAX_PENDING_PEERS {
+                        warn!(%peer_id, "Too many peers are waiting for approval, ignoring peer");
+                        return Ok((PeerDecision::Pending, false));
+                    }
+                }
+                debug!(%peer_id, ?status, "Creating permissions for new peer");
+                // Another message of the peer may have created the row in the meantime
+                is_new = sqlx::query(
+                    "INSERT INTO peer_permissions (
+                        id, peer_id, contact_id, status, allowed_messages
+                    ) VALUES (?, ?, ?, ?, ?)
+                    ON CONFLICT (peer_id) DO NOTHING",
+                )
+                .bind(Uuid::new_v4())
+                .bind(peer_id)
+                .bind(contact_id)
+                .bind(status)
+                .bind(Json(allowed_messages))
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                    == 1;
+                sqlx::query_as(&select)
+                    .bind(peer_id)
+                    .fetch_one(&mut *tx)
+                    .await?
+            }
+        };
+        let decision = permission.decide(action);
+        let now = Utc::now();
+        let operation = decision.operation();
+        // Peers keep retrying while they wait or after being turned away, so only
+        // the first of these decisions in a while is recorded
+        let audited_recently = decision != PeerDecision::Allow
+            && sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM audit_log
+                WHERE table_name = 'peer_permissions' AND record_id = ? AND operation = ?
+                    AND timestamp > ?",
+            )
+            .bind(permission.id)
+            .bind(operation)
+            .bind(now - Duration::seconds(AUDIT_COALESCE_SECS))
+            .fetch_one(&mut *tx)
+            .await?
+                > 0;
+        if !audited_recently {
+            insert_audit_log_entry(
+                &mut tx,
+                &AuditLogEntry {
+                    id: Uuid::new_v4(),
+                    table_name: "peer_permissions".into(),
+                    record_id: permission.id,
+                    operation: operation.into(),
+                    user_id: None,
+                    old_values: None,
+                    new_values: Some(Json(json!({
+                        "peerId": peer_id,
+                        "action": action,
+                        "decision": decision,
+                    }))),
+                    ip_address: None,
+                    user_agent: None,
+                    timestamp: now,
+                },
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok((decision, is_new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+    use crate::entities::AuditLogFilter;
+
+    fn permission(status: PeerPermissionStatus) -> PeerPermission {
+        PeerPermission {
+            id: Uuid::new_v4(),
+            peer_id: Keypair::generate_ed25519().public().to_peer_id().into(),
+            contact_id: None,
+            status,
+            allowed_messages: Json(vec![PeerMessageKind::ChatMessage, PeerMessageKind::UseTool]),
+            allowed_tools: Json(vec!["search_documents".into()]),
+            allowed_agents: Json(Vec::new()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        let allowed = permission(PeerPermissionStatus::Allowed);
+        let use_tool = |tool: &str| PeerAction::UseTool { tool: tool.into() };
+
+        assert_eq!(
+            allowed.decide(&PeerAction::ChatMessage),
+            PeerDecision::Allow
+        );
+        assert_eq!(
+            allowed.decide(&use_tool("search_documents")),
+            PeerDecision::Allow
+        );
+        assert!(matches!(
+            allowed.decide(&use_tool("shell")),
+            PeerDecision::Deny(_)
+        ));
+        assert!(matches!(
+            allowed.decide(&PeerAction::CreateConversation),
+            PeerDecision::Deny(_)
+        ));
+        assert!(matches!(
+            permission(PeerPermissionStatus::Denied).decide(&PeerAction::ChatMessage),
+            PeerDecision::Deny(_)
+        ));
+        assert_eq!(
+            permission(PeerPermissionStatus::Pending).decide(&PeerAction::ChatMessage),
+            PeerDecision::Pending
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_peers_are_coalesced() {
+        let db = DatabaseManager::setup_test_db().await;
+        let peer_id: PeerIdWrapper = Keypair::generate_ed25519().public().to_peer_id().into();
+
+        let first = db.authorize_peer(&peer_id, &PeerAction::ChatMessage).await;
+        assert_eq!(first.unwrap(), (PeerDecision::Pending, true));
+        let again = db.authorize_peer(&peer_id, &PeerAction::ChatMessage).await;
+        assert_eq!(again.unwrap(), (PeerDecision::Pending, false));
+
+        let permission = db.get_peer_permission(&peer_id).await.unwrap().unwrap();
+        let audited = db
+            .list_audit_log(&AuditLogFilter {
+                record_id: Some(permission.id),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(audited.len(), 1);
+
+        // Once enough peers wait for approval, new ones aren't stored anymore
+        sqlx::query(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?)
+            INSERT INTO peer_permissions (id, peer_id) SELECT randomblob(16), randomblob(38) FROM n",
+        )
+        .bind(MAX_PENDING_PEERS)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        let stranger: PeerIdWrapper = Keypair::generate_ed25519().public().to_peer_id().into();
+        let ignored = db.authorize_peer(&stranger, &PeerAction::ChatMessage).await;
+        assert_eq!(ignored.unwrap(), (PeerDecision::Pending, false));
+        assert!(db.get_peer_permission(&stranger).await.unwrap().is_none());
+    }
+}
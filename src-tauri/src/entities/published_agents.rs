@@ -0,0 +1,162 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::entities::PeerIdWrapper;
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+
+/// A local agent that peers may run, with the limits every peer is held to
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishedAgent {
+    pub agent_id: Uuid,
+    pub max_concurrent_requests: i64,
+    /// Tokens a peer may use per day, `None` for no limit
+    pub daily_token_quota: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// How a published agent is listed to peers
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerAgent {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub avatar_url: Option<String>,
+    pub version: String,
+    pub max_concurrent_requests: i64,
+    pub daily_token_quota: Option<i64>,
+}
+
+const COLUMNS: &str = "agent_id, max_concurrent_requests, daily_token_quota, created_at, \
+    updated_at";
+
+impl DatabaseManager {
+    /// Publish an agent to peers, or change the limits of a published agent
+    #[instrument(err, skip(self))]
+    pub async fn publish_agent(
+        &self,
+        agent_id: Uuid,
+        max_concurrent_requests: i64,
+        daily_token_quota: Option<i64>,
+    ) -> Result<PublishedAgent> {
+        debug!("Publishing agent: {}", agent_id);
+
+        if max_concurrent_requests < 1 {
+            return Err(AppError::validation(
+                "A published agent must allow at least one request at a time",
+            ));
+        }
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO published_agents (agent_id, max_concurrent_requests, daily_token_quota)
+             VALUES (?, ?, ?)
+             ON CONFLICT (agent_id) DO UPDATE SET
+                max_concurrent_requests = excluded.max_concurrent_requests,
+                daily_token_quota = excluded.daily_token_quota
+             RETURNING {COLUMNS}"
+        ))
+        .bind(agent_id)
+        .bind(max_concurrent_requests)
+        .bind(daily_token_quota)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Stop peers from running an agent. Requests that are running are finished.
+    #[instrument(err, skip(self))]
+    pub async fn unpublish_agent(&self, agent_id: Uuid) -> Result<()> {
+        debug!("Unpublishing agent: {}", agent_id);
+
+        let result = sqlx::query("DELETE FROM published_agents WHERE agent_id = ?")
+            .bind(agent_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::not_found("PublishedAgent", agent_id));
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_published_agent(&self, agent_id: Uuid) -> Result<Option<PublishedAgent>> {
+        debug!("Getting published agent: {}", agent_id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM published_agents WHERE agent_id = ?"
+        ))
+        .bind(agent_id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// List the published agents that are active, as they are shown to peers
+    #[instrument(skip(self))]
+    pub async fn list_peer_agents(&self) -> Result<Vec<PeerAgent>> {
+        debug!("Listing published agents");
+
+        Ok(sqlx::query_as(
+            "SELECT a.id, a.name, a.description, a.avatar_url, a.version,
+                pa.max_concurrent_requests, pa.daily_token_quota
+             FROM published_agents pa
+             INNER JOIN agents a ON a.id = pa.agent_id
+             WHERE a.status = 0
+             ORDER BY a.name ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Tokens a peer used of an agent on the given day
+    #[instrument(skip(self))]
+    pub async fn get_peer_agent_tokens(
+        &self,
+        peer_id: &PeerIdWrapper,
+        agent_id: Uuid,
+        day: NaiveDate,
+    ) -> Result<i64> {
+        Ok(sqlx::query_scalar(
+            "SELECT tokens FROM peer_agent_usage WHERE peer_id = ? AND agent_id = ? AND day = ?",
+        )
+        .bind(peer_id)
+        .bind(agent_id)
+        .bind(day)
+        .fetch_optional(&self.pool)
+        .await?
+        .unwrap_or(0))
+    }
+
+    /// Count a finished request of a peer and the tokens it used
+    #[instrument(err, skip(self))]
+    pub async fn record_peer_agent_usage(
+        &self,
+        peer_id: &PeerIdWrapper,
+        agent_id: Uuid,
+        day: NaiveDate,
+        tokens: i64,
+    ) -> Result<()> {
+        debug!(%peer_id, %agent_id, tokens, "Recording agent usage of peer");
+
+        sqlx::query(
+            "INSERT INTO peer_agent_usage (peer_id, agent_id, day, requests, tokens)
+             VALUES (?, ?, ?, 1, ?)
+             ON CONFLICT (peer_id, agent_id, day) DO UPDATE SET
+                requests = requests + 1,
+                tokens = tokens + excluded.tokens",
+        )
+        .bind(peer_id)
+        .bind(agent_id)
+        .bind(day)
+        .bind(tokens)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
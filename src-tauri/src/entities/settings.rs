@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::storage::db::DatabaseManager;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum SettingsType {
+    String = 0,
+    Number = 1,
+    Boolean = 2,
+    Object = 3,
+}
+
+/// A named value, either global or scoped to a workspace
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Setting {
+    pub id: Uuid,
+    pub name: String,
+    pub value: String,
+    pub settings_type: SettingsType,
+    pub description: Option<String>,
+    pub metadata: Option<Json<Value>>,
+    pub workspace_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+const COLUMNS: &str = "id, name, value, settings_type, description, metadata, workspace_id, \
+    created_at, updated_at";
+
+impl DatabaseManager {
+    /// Get the setting with the given name that isn't scoped to a workspace
+    #[instrument(skip(self))]
+    pub async fn get_global_setting(&self, name: &str) -> Result<Option<Setting>> {
+        debug!("Getting global setting: {}", name);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM settings WHERE name = ? AND workspace_id IS NULL
+             ORDER BY updated_at DESC LIMIT 1"
+        ))
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// List every setting with the given name, global and per workspace
+    #[instrument(skip(self))]
+    pub async fn list_settings_by_name(&self, name: &str) -> Result<Vec<Setting>> {
+        debug!("Listing settings named: {}", name);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM settings WHERE name = ? ORDER BY created_at ASC"
+        ))
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Set the value of a global setting, creating it if it doesn't exist yet
+    #[instrument(skip(self, value))]
+    pub async fn set_global_setting(
+        &self,
+        name: &str,
+        value: &str,
+        settings_type: SettingsType,
+    ) -> Result<Setting> {
+        debug!("Setting global setting: {}", name);
+
+        let mut tx = self.pool.begin().await?;
+        let updated: Option<Setting> = sqlx::query_as(&format!(
+            "UPDATE settings SET value = ?, settings_type = ?
+             WHERE name = ? AND workspace_id IS NULL
+             RETURNING {COLUMNS}"
+        ))
+        .bind(value)
+        .bind(settings_type)
+        .bind(name)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let setting = match updated {
+            Some(setting) => setting,
+            None => {
+                sqlx::query_as(&format!(
+                    "INSERT INTO settings (id, name, value, settings_type)
+                     VALUES (?, ?, ?, ?)
+                     RETURNING {COLUMNS}"
+                ))
+                .bind(Uuid::new_v4())
+                .bind(name)
+                .bind(value)
+                .bind(settings_type)
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+        tx.commit().await?;
+        Ok(setting)
+    }
+}
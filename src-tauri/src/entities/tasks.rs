@@ -1,5 +1,6 @@
 use boilermates::boilermates;
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serde_with::skip_serializing_none;
@@ -13,7 +14,7 @@ use crate::error::{AppError, Result};
 use crate::storage::db::DatabaseManager;
 use crate::utils::add_where;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum TaskPriority {
     Low = 0,
@@ -21,7 +22,7 @@ pub enum TaskPriority {
     High = 2,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum TaskImportance {
     Low = 0,
@@ -29,7 +30,7 @@ pub enum TaskImportance {
     High = 2,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum TaskStatus {
     Pending = 0,
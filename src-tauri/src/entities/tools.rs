@@ -349,4 +349,47 @@ impl DatabaseManager {
 
         Ok(())
     }
+
+    /// The active tools linked to an agent through `agent_tools`
+    #[instrument(err, skip(self))]
+    pub async fn list_agent_tools(&self, agent_id: &Uuid) -> Result<Vec<Tool>> {
+        debug!("Listing tools of agent: {}", agent_id);
+
+        Ok(sqlx::query_as!(
+            Tool,
+            r#"SELECT
+                    t.id AS "id: _", t.name, t.description, t.category as "category: ToolCategory", t.definition as "definition: _", t.config as "config: _", t.auth_required, t.is_active,
+                    t.created_by_id as "created_by_id: Uuid",
+                    t.workspace_id AS "workspace_id: _", t.created_at AS "created_at: _", t.updated_at AS "updated_at: _"
+                FROM tools t
+                JOIN agent_tools at ON at.tool_id = t.id
+                WHERE at.agent_id = ? AND t.is_active = 1
+                ORDER BY t.name ASC"#,
+            agent_id
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Replaces the tools linked to an agent
+    #[instrument(err, skip(self))]
+    pub async fn set_agent_tools(&self, agent_id: &Uuid, tool_ids: &[Uuid]) -> Result<()> {
+        debug!("Setting {} tools of agent: {}", tool_ids.len(), agent_id);
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("DELETE FROM agent_tools WHERE agent_id = ?", agent_id)
+            .execute(&mut *tx)
+            .await?;
+        for tool_id in tool_ids {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO agent_tools (agent_id, tool_id) VALUES (?, ?)",
+                agent_id,
+                tool_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
 }
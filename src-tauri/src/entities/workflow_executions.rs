@@ -0,0 +1,314 @@
+use boilermates::boilermates;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkflowExecutionStatus {
+    Pending = 0,
+    Running = 1,
+    Completed = 2,
+    Failed = 3,
+    Paused = 4,
+    Cancelled = 5,
+}
+
+/// A run of a workflow. `metadata` holds the checkpoint the run continues from
+/// after it was paused or the app was restarted.
+#[skip_serializing_none]
+#[boilermates("CreateWorkflowExecution")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowExecution {
+    #[boilermates(not_in("CreateWorkflowExecution"))]
+    pub id: Uuid,
+    pub workflow_id: Uuid,
+    pub initiated_by_participant_id: Option<Uuid>,
+    pub status: WorkflowExecutionStatus,
+    pub input_data: Option<Json<Value>>,
+    pub output_data: Option<Json<Value>>,
+    #[boilermates(not_in("CreateWorkflowExecution"))]
+    pub started_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateWorkflowExecution"))]
+    pub completed_at: Option<DateTime<Utc>>,
+    pub metadata: Option<Json<Value>>,
+    #[boilermates(not_in("CreateWorkflowExecution"))]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A single attempt at a step of a workflow run
+#[skip_serializing_none]
+#[boilermates("CreateWorkflowStepExecution")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowStepExecution {
+    #[boilermates(not_in("CreateWorkflowStepExecution"))]
+    pub id: Uuid,
+    pub workflow_execution_id: Uuid,
+    pub workflow_step_id: Uuid,
+    pub status: WorkflowExecutionStatus,
+    pub attempt_number: i64,
+    pub input_data: Option<Json<Value>>,
+    pub output_data: Option<Json<Value>>,
+    pub error_details: Option<String>,
+    #[boilermates(not_in("CreateWorkflowStepExecution"))]
+    pub started_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub metadata: Option<Json<Value>>,
+    #[boilermates(not_in("CreateWorkflowStepExecution"))]
+    pub created_at: DateTime<Utc>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowExecutionFilter {
+    pub workflow_id: Option<Uuid>,
+    pub initiated_by_participant_id: Option<Uuid>,
+    pub status: Option<WorkflowExecutionStatus>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+const EXECUTION_COLUMNS: &str = "id, workflow_id, initiated_by_participant_id, status, \
+    input_data, output_data, started_at, completed_at, metadata, created_at";
+
+const STEP_EXECUTION_COLUMNS: &str = "id, workflow_execution_id, workflow_step_id, status, \
+    attempt_number, input_data, output_data, error_details, started_at, completed_at, metadata, \
+    created_at";
+
+async fn update_execution(
+    conn: &mut SqliteConnection,
+    execution: &WorkflowExecution,
+) -> Result<WorkflowExecution> {
+    sqlx::query_as(&format!(
+        "UPDATE workflow_executions SET
+            status = ?, output_data = ?, completed_at = ?, metadata = ?
+        WHERE id = ?
+        RETURNING {EXECUTION_COLUMNS}"
+    ))
+    .bind(execution.status)
+    .bind(&execution.output_data)
+    .bind(execution.completed_at)
+    .bind(&execution.metadata)
+    .bind(execution.id)
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| AppError::not_found("WorkflowExecution", execution.id))
+}
+
+async fn update_step_execution(
+    conn: &mut SqliteConnection,
+    step_execution: &WorkflowStepExecution,
+) -> Result<WorkflowStepExecution> {
+    sqlx::query_as(&format!(
+        "UPDATE workflow_step_executions SET
+            status = ?, output_data = ?, error_details = ?, completed_at = ?, metadata = ?
+        WHERE id = ?
+        RETURNING {STEP_EXECUTION_COLUMNS}"
+    ))
+    .bind(step_execution.status)
+    .bind(&step_execution.output_data)
+    .bind(&step_execution.error_details)
+    .bind(step_execution.completed_at)
+    .bind(&step_execution.metadata)
+    .bind(step_execution.id)
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| AppError::not_found("WorkflowStepExecution", step_execution.id))
+}
+
+impl DatabaseManager {
+    /// Create a new workflow execution
+    #[instrument(skip(self, execution))]
+    pub async fn create_workflow_execution(
+        &self,
+        execution: &CreateWorkflowExecution,
+    ) -> Result<WorkflowExecution> {
+        let id = Uuid::new_v4();
+        debug!("Creating workflow execution with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO workflow_executions (
+                id, workflow_id, initiated_by_participant_id, status, input_data, output_data,
+                metadata
+            ) VALUES (?, ?, ?, ?, ?, ?, ?)
+            RETURNING {EXECUTION_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(execution.workflow_id)
+        .bind(execution.initiated_by_participant_id)
+        .bind(execution.status)
+        .bind(&execution.input_data)
+        .bind(&execution.output_data)
+        .bind(&execution.metadata)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Get a workflow execution by ID
+    #[instrument(skip(self))]
+    pub async fn get_workflow_execution_by_id(
+        &self,
+        id: &Uuid,
+    ) -> Result<Option<WorkflowExecution>> {
+        debug!("Getting workflow execution by ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {EXECUTION_COLUMNS} FROM workflow_executions WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// List workflow executions with filtering, most recent first
+    #[instrument(skip(self))]
+    pub async fn list_workflow_executions(
+        &self,
+        filter: &WorkflowExecutionFilter,
+    ) -> Result<Vec<WorkflowExecution>> {
+        debug!("Listing workflow executions with filter: {:?}", filter);
+
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {EXECUTION_COLUMNS} FROM workflow_executions"));
+        let mut add_where = add_where();
+
+        if let Some(workflow_id) = &filter.workflow_id {
+            add_where(&mut qb);
+            qb.push("workflow_id = ");
+            qb.push_bind(workflow_id);
+        }
+
+        if let Some(initiated_by_participant_id) = &filter.initiated_by_participant_id {
+            add_where(&mut qb);
+            qb.push("initiated_by_participant_id = ");
+            qb.push_bind(initiated_by_participant_id);
+        }
+
+        if let Some(status) = filter.status {
+            add_where(&mut qb);
+            qb.push("status = ");
+            qb.push_bind(status as i64);
+        }
+
+        qb.push(" ORDER BY started_at DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Update the status, output and checkpoint of a workflow execution
+    #[instrument(err, skip(self, execution))]
+    pub async fn update_workflow_execution(
+        &self,
+        execution: &WorkflowExecution,
+    ) -> Result<WorkflowExecution> {
+        debug!("Updating workflow execution with ID: {}", execution.id);
+
+        let mut conn = self.pool.acquire().await?;
+        update_execution(&mut conn, execution).await
+    }
+
+    /// Record the start of a step execution
+    #[instrument(skip(self, step_execution))]
+    pub async fn create_workflow_step_execution(
+        &self,
+        step_execution: &CreateWorkflowStepExecution,
+    ) -> Result<WorkflowStepExecution> {
+        let id = Uuid::new_v4();
+        debug!("Creating workflow step execution with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO workflow_step_executions (
+                id, workflow_execution_id, workflow_step_id, status, attempt_number, input_data,
+                output_data, error_details, completed_at, metadata
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING {STEP_EXECUTION_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(step_execution.workflow_execution_id)
+        .bind(step_execution.workflow_step_id)
+        .bind(step_execution.status)
+        .bind(step_execution.attempt_number)
+        .bind(&step_execution.input_data)
+        .bind(&step_execution.output_data)
+        .bind(&step_execution.error_details)
+        .bind(step_execution.completed_at)
+        .bind(&step_execution.metadata)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Update the outcome of a step execution
+    #[instrument(err, skip(self, step_execution))]
+    pub async fn update_workflow_step_execution(
+        &self,
+        step_execution: &WorkflowStepExecution,
+    ) -> Result<WorkflowStepExecution> {
+        debug!("Updating workflow step execution with ID: {}", step_execution.id);
+
+        let mut conn = self.pool.acquire().await?;
+        update_step_execution(&mut conn, step_execution).await
+    }
+
+    /// Stores the outcome of a step together with the checkpoint of the run, so that
+    /// a restarted run never repeats a step that was recorded as completed
+    #[instrument(err, skip(self, step_execution, execution))]
+    pub async fn checkpoint_workflow_step(
+        &self,
+        step_execution: &WorkflowStepExecution,
+        execution: &WorkflowExecution,
+    ) -> Result<(WorkflowStepExecution, WorkflowExecution)> {
+        debug!(
+            "Checkpointing workflow execution {} after step execution {}",
+            execution.id, step_execution.id
+        );
+
+        let mut tx = self.pool.begin().await?;
+        let step_execution = update_step_execution(&mut tx, step_execution).await?;
+        let execution = update_execution(&mut tx, execution).await?;
+        tx.commit().await?;
+        Ok((step_execution, execution))
+    }
+
+    /// List the step executions of a workflow run in the order they were started
+    #[instrument(skip(self))]
+    pub async fn list_workflow_step_executions(
+        &self,
+        workflow_execution_id: &Uuid,
+    ) -> Result<Vec<WorkflowStepExecution>> {
+        debug!(
+            "Listing step executions of workflow execution: {}",
+            workflow_execution_id
+        );
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {STEP_EXECUTION_COLUMNS} FROM workflow_step_executions
+             WHERE workflow_execution_id = ? ORDER BY created_at ASC, rowid ASC"
+        ))
+        .bind(workflow_execution_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
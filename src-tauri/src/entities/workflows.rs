@@ -0,0 +1,333 @@
+use boilermates::boilermates;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use sqlx::prelude::FromRow;
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Sqlite};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::storage::db::DatabaseManager;
+use crate::utils::add_where;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowType {
+    AgentChain = 0,
+    DataPipeline = 1,
+    LongRunningProcess = 2,
+    TraditionalWorkflow = 3,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkflowStatus {
+    Draft = 0,
+    Active = 1,
+    Paused = 2,
+    Completed = 3,
+    Failed = 4,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkflowStepType {
+    /// Runs the step's participant on the workflow state
+    Task = 0,
+    /// Jumps to one of two steps depending on the workflow state
+    Condition = 1,
+    /// Jumps back to an earlier step while a condition holds
+    Loop = 2,
+}
+
+#[skip_serializing_none]
+#[boilermates("CreateWorkflow")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Workflow {
+    #[boilermates(not_in("CreateWorkflow"))]
+    pub id: Uuid,
+    pub workspace_id: Option<Uuid>,
+    pub name: String,
+    pub description: Option<String>,
+    pub workflow_type: WorkflowType,
+    pub status: WorkflowStatus,
+    pub config: Option<Json<Value>>,
+    pub metadata: Option<Json<Value>>,
+    #[boilermates(not_in("CreateWorkflow"))]
+    pub created_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateWorkflow"))]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[skip_serializing_none]
+#[boilermates("CreateWorkflowStep")]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowStep {
+    #[boilermates(not_in("CreateWorkflowStep"))]
+    pub id: Uuid,
+    pub workflow_id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub step_order: i64,
+    pub step_type: WorkflowStepType,
+    pub participant_id: Uuid,
+    /// Task steps: `{ "prompt": "..." }` with `{{input}}` replaced by the step input.
+    /// Condition steps: `{ "condition": ..., "then": "<step>", "else": "<step>" }`.
+    /// Loop steps: `{ "condition": ..., "target": "<step>", "maxIterations": 10 }`.
+    pub participant_config: Option<Json<Value>>,
+    pub input_schema: Option<Json<Value>>,
+    pub output_schema: Option<Json<Value>>,
+    pub retry_policy: Option<Json<Value>>,
+    pub timeout_seconds: Option<i64>,
+    pub metadata: Option<Json<Value>>,
+    #[boilermates(not_in("CreateWorkflowStep"))]
+    pub created_at: DateTime<Utc>,
+    #[boilermates(not_in("CreateWorkflowStep"))]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowFilter {
+    pub workspace_id: Option<Uuid>,
+    pub workflow_type: Option<WorkflowType>,
+    pub status: Option<WorkflowStatus>,
+    pub search_term: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+const WORKFLOW_COLUMNS: &str = "id, workspace_id, name, description, workflow_type, status, \
+    config, metadata, created_at, updated_at";
+
+const STEP_COLUMNS: &str = "id, workflow_id, name, description, step_order, step_type, \
+    participant_id, participant_config, input_schema, output_schema, retry_policy, \
+    timeout_seconds, metadata, created_at, updated_at";
+
+impl DatabaseManager {
+    /// Create a new workflow
+    #[instrument(skip(self, workflow))]
+    pub async fn create_workflow(&self, workflow: &CreateWorkflow) -> Result<Workflow> {
+        let id = Uuid::new_v4();
+        debug!("Creating workflow with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO workflows (
+                id, workspace_id, name, description, workflow_type, status, config, metadata
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING {WORKFLOW_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(workflow.workspace_id)
+        .bind(&workflow.name)
+        .bind(&workflow.description)
+        .bind(workflow.workflow_type)
+        .bind(workflow.status)
+        .bind(&workflow.config)
+        .bind(&workflow.metadata)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Get a workflow by ID
+    #[instrument(skip(self))]
+    pub async fn get_workflow_by_id(&self, id: &Uuid) -> Result<Option<Workflow>> {
+        debug!("Getting workflow by ID: {}", id);
+
+        Ok(
+            sqlx::query_as(&format!("SELECT {WORKFLOW_COLUMNS} FROM workflows WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    /// List workflows with filtering
+    #[instrument(skip(self))]
+    pub async fn list_workflows(&self, filter: &WorkflowFilter) -> Result<Vec<Workflow>> {
+        debug!("Listing workflows with filter: {:?}", filter);
+
+        let mut qb: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {WORKFLOW_COLUMNS} FROM workflows"));
+        let mut add_where = add_where();
+
+        if let Some(workspace_id) = &filter.workspace_id {
+            add_where(&mut qb);
+            qb.push("workspace_id = ");
+            qb.push_bind(workspace_id);
+        }
+
+        if let Some(workflow_type) = filter.workflow_type {
+            add_where(&mut qb);
+            qb.push("workflow_type = ");
+            qb.push_bind(workflow_type as i64);
+        }
+
+        if let Some(status) = filter.status {
+            add_where(&mut qb);
+            qb.push("status = ");
+            qb.push_bind(status as i64);
+        }
+
+        if let Some(search_term) = &filter.search_term {
+            add_where(&mut qb);
+            qb.push("(name LIKE ");
+            qb.push_bind(format!("%{search_term}%"));
+            qb.push(" OR description LIKE ");
+            qb.push_bind(format!("%{search_term}%"));
+            qb.push(")");
+        }
+
+        qb.push(" ORDER BY created_at DESC");
+
+        if let Some(limit) = filter.limit {
+            qb.push(" LIMIT ");
+            qb.push_bind(limit as i64);
+        }
+
+        if let Some(offset) = filter.offset {
+            qb.push(" OFFSET ");
+            qb.push_bind(offset as i64);
+        }
+
+        Ok(qb.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Update a workflow
+    #[instrument(err, skip(self, workflow))]
+    pub async fn update_workflow(&self, workflow: &Workflow) -> Result<Workflow> {
+        debug!("Updating workflow with ID: {}", workflow.id);
+
+        sqlx::query_as(&format!(
+            "UPDATE workflows SET
+                workspace_id = ?, name = ?, description = ?, workflow_type = ?, status = ?,
+                config = ?, metadata = ?
+            WHERE id = ?
+            RETURNING {WORKFLOW_COLUMNS}"
+        ))
+        .bind(workflow.workspace_id)
+        .bind(&workflow.name)
+        .bind(&workflow.description)
+        .bind(workflow.workflow_type)
+        .bind(workflow.status)
+        .bind(&workflow.config)
+        .bind(&workflow.metadata)
+        .bind(workflow.id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("Workflow", workflow.id))
+    }
+
+    /// Delete a workflow together with its steps and executions
+    #[instrument(err, skip(self))]
+    pub async fn delete_workflow(&self, id: &Uuid) -> Result<()> {
+        debug!("Deleting workflow with ID: {}", id);
+
+        let affected = sqlx::query("DELETE FROM workflows WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if affected == 0 {
+            return Err(AppError::not_found("Workflow", *id));
+        }
+        Ok(())
+    }
+
+    /// Add a step to a workflow
+    #[instrument(skip(self, step))]
+    pub async fn create_workflow_step(&self, step: &CreateWorkflowStep) -> Result<WorkflowStep> {
+        let id = Uuid::new_v4();
+        debug!("Creating workflow step with ID: {}", id);
+
+        Ok(sqlx::query_as(&format!(
+            "INSERT INTO workflow_steps (
+                id, workflow_id, name, description, step_order, step_type, participant_id,
+                participant_config, input_schema, output_schema, retry_policy, timeout_seconds,
+                metadata
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING {STEP_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(step.workflow_id)
+        .bind(&step.name)
+        .bind(&step.description)
+        .bind(step.step_order)
+        .bind(step.step_type)
+        .bind(step.participant_id)
+        .bind(&step.participant_config)
+        .bind(&step.input_schema)
+        .bind(&step.output_schema)
+        .bind(&step.retry_policy)
+        .bind(step.timeout_seconds)
+        .bind(&step.metadata)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// List the steps of a workflow in the order they run
+    #[instrument(skip(self))]
+    pub async fn list_workflow_steps(&self, workflow_id: &Uuid) -> Result<Vec<WorkflowStep>> {
+        debug!("Listing steps of workflow: {}", workflow_id);
+
+        Ok(sqlx::query_as(&format!(
+            "SELECT {STEP_COLUMNS} FROM workflow_steps
+             WHERE workflow_id = ? ORDER BY step_order ASC, created_at ASC"
+        ))
+        .bind(workflow_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Update a workflow step
+    #[instrument(err, skip(self, step))]
+    pub async fn update_workflow_step(&self, step: &WorkflowStep) -> Result<WorkflowStep> {
+        debug!("Updating workflow step with ID: {}", step.id);
+
+        sqlx::query_as(&format!(
+            "UPDATE workflow_steps SET
+                name = ?, description = ?, step_order = ?, step_type = ?, participant_id = ?,
+                participant_config = ?, input_schema = ?, output_schema = ?, retry_policy = ?,
+                timeout_seconds = ?, metadata = ?
+            WHERE id = ?
+            RETURNING {STEP_COLUMNS}"
+        ))
+        .bind(&step.name)
+        .bind(&step.description)
+        .bind(step.step_order)
+        .bind(step.step_type)
+        .bind(step.participant_id)
+        .bind(&step.participant_config)
+        .bind(&step.input_schema)
+        .bind(&step.output_schema)
+        .bind(&step.retry_policy)
+        .bind(step.timeout_seconds)
+        .bind(&step.metadata)
+        .bind(step.id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::not_found("WorkflowStep", step.id))
+    }
+
+    /// Delete a workflow step
+    #[instrument(err, skip(self))]
+    pub async fn delete_workflow_step(&self, id: &Uuid) -> Result<()> {
+        debug!("Deleting workflow step with ID: {}", id);
+
+        let affected = sqlx::query("DELETE FROM workflow_steps WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if affected == 0 {
+            return Err(AppError::not_found("WorkflowStep", *id));
+        }
+        Ok(())
+    }
+}
@@ -270,6 +270,8 @@ pub enum AppError {
     ConfigurationError(String),
     #[error("External service error: {0}")]
     ExternalServiceError(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
 
     #[error("Privacy policy violation: {rule_id} - {details}")]
     PrivacyPolicyViolation {
@@ -418,6 +420,11 @@ impl AppError {
         Self::ExternalServiceError(message.into())
     }
 
+    /// Create a new timeout error
+    pub fn timeout(message: impl Into<String>) -> Self {
+        Self::Timeout(message.into())
+    }
+
     /// Get the error category for this error
     pub fn category(&self) -> ErrorCategory {
         match self {
@@ -425,7 +432,7 @@ impl AppError {
             Self::AuthorizationError(_) => ErrorCategory::Authorization,
             Self::ValidationError(_) => ErrorCategory::Validation,
             Self::DatabaseError(_) | Self::SqlxError(_) | Self::QueryError(_) => ErrorCategory::Database,
-            Self::TransportError(_) | Self::RemoteSendError(_) | Self::SendError(_) => ErrorCategory::Network,
+            Self::TransportError(_) | Self::RemoteSendError(_) | Self::SendError(_) | Self::Timeout(_) => ErrorCategory::Network,
             Self::ExternalServiceError(_) => ErrorCategory::ExternalService,
             Self::ConfigurationError(_) => ErrorCategory::Configuration,
             Self::ResourceLimitExceeded(_) => ErrorCategory::ResourceLimit,
@@ -452,6 +459,7 @@ impl AppError {
     pub fn is_retriable(&self) -> bool {
         match self {
             Self::TransportError(_) | Self::RemoteSendError(_) | Self::SendError(_) => true,
+            Self::Timeout(_) => true,
             Self::DatabaseError(_) | Self::SqlxError(_) => true,
             Self::ExternalServiceError(_) => true,
             Self::ContextualError { context, .. } => context.retriable,
@@ -4,6 +4,7 @@
 //! with external systems and services.
 
 use async_trait::async_trait;
+use base64::{Engine as _, prelude::BASE64_STANDARD};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
@@ -206,7 +207,7 @@ impl AuthProvider for BasicAuthProvider {
         // In a real implementation, we would properly encode the credentials
         // and possibly make a test request to validate them
         let token_value = format!("{}:{}", username, password);
-        let encoded = base64::encode(token_value);
+        let encoded = BASE64_STANDARD.encode(token_value);
 
         Ok(AuthToken {
             token: encoded,
@@ -377,7 +378,7 @@ impl JwtAuthProvider {
 
         // Create a header
         let header = format!("{{\"alg\":\"{}\",\"typ\":\"JWT\"}}", self.config.algorithm);
-        let header_base64 = base64::encode(header);
+        let header_base64 = BASE64_STANDARD.encode(header);
 
         // Create the claims
         let mut claims_map = claims;
@@ -402,7 +403,7 @@ impl JwtAuthProvider {
             )
         })?;
 
-        let claims_base64 = base64::encode(claims_json);
+        let claims_base64 = BASE64_STANDARD.encode(claims_json);
 
         // Create the signature (in a real implementation, this would use the appropriate algorithm)
         let signature = match self.config.algorithm.as_str() {
@@ -416,7 +417,7 @@ impl JwtAuthProvider {
 
                 // In a real implementation, we would use a proper HMAC function
                 // This is just a placeholder
-                base64::encode(format!("hmac_signature_for_{}_{}", header_base64, claims_base64))
+                BASE64_STANDARD.encode(format!("hmac_signature_for_{}_{}", header_base64, claims_base64))
             },
             "RS256" | "RS384" | "RS512" | "ES256" | "ES384" | "ES512" => {
                 let private_key = self.config.private_key.as_ref().ok_or_else(|| {
@@ -428,7 +429,7 @@ impl JwtAuthProvider {
 
                 // In a real implementation, we would use a proper RSA/ECDSA signing function
                 // This is just a placeholder
-                base64::encode(format!("rsa_signature_for_{}_{}", header_base64, claims_base64))
+                BASE64_STANDARD.encode(format!("rsa_signature_for_{}_{}", header_base64, claims_base64))
             },
             _ => {
                 return Err(crate::error::Error::new(
@@ -457,7 +458,7 @@ impl JwtAuthProvider {
         }
 
         // Decode the claims
-        let claims_json = base64::decode(parts[1]).map_err(|e| {
+        let claims_json = BASE64_STANDARD.decode(parts[1]).map_err(|e| {
             crate::error::Error::new(
                 crate::error::ErrorKind::Authentication,
                 &format!("Failed to decode JWT claims: {}", e)
@@ -686,7 +687,7 @@ impl OAuth2PkceProvider {
 
                 // Base64url encode the hash
                 // Note: This is a simplified implementation
-                let encoded = base64::encode(&hash);
+                let encoded = BASE64_STANDARD.encode(&hash);
                 let encoded = encoded.replace('+', "-").replace('/', "_").replace('=', "");
 
                 Ok(encoded)
@@ -1072,7 +1073,7 @@ pub enum KeyDerivationMethod {
         iterations: u32,
     },
 
-    /// Use a system-protected key (e.g., keychain, keyring)
+    /// Use a random key kept in the OS keychain, created on first use
     SystemProtected {
         /// Key identifier in the system keystore
         key_id: String,
@@ -1088,62 +1089,12 @@ pub enum KeyDerivationMethod {
     },
 }
 
-/// Encrypted file-based credential store
-pub struct EncryptedFileCredentialStore {
-    /// Configuration for the credential store
-    config: EncryptedFileCredentialStoreConfig,
-
-    /// Cache of decrypted credentials
-    cache: std::sync::RwLock<HashMap<String, Credentials>>,
-
-    /// Whether the store has been initialized
-    initialized: std::sync::atomic::AtomicBool,
-}
-
-impl EncryptedFileCredentialStore {
-    /// Create a new encrypted file credential store
-    pub fn new(config: EncryptedFileCredentialStoreConfig) -> Self {
-        Self {
-            config,
-            cache: std::sync::RwLock::new(HashMap::new()),
-            initialized: std::sync::atomic::AtomicBool::new(false),
-        }
-    }
-
-    /// Initialize the credential store
-    pub fn initialize(&self) -> Result<()> {
-        // Check if the store is already initialized
-        if self.initialized.load(std::sync::atomic::Ordering::Relaxed) {
-            return Ok(());
-        }
-
-        // Create the directory if it doesn't exist
-        if let Some(parent) = self.config.file_path.parent() {
-            std::fs::create_dir_all(parent).map_err(|e| {
-                crate::error::Error::new(
-                    crate::error::ErrorKind::IO,
-                    &format!("Failed to create directory for credential store: {}", e)
-                )
-            })?;
-        }
+impl KeyDerivationMethod {
+    /// Derive the 256-bit encryption key described by this method
+    pub fn derive_key(&self) -> Result<Vec<u8>> {
+        use sha2::Digest;
 
-        // If the file doesn't exist, create it with an empty set of credentials
-        if !self.config.file_path.exists() {
-            self.save_to_file(&HashMap::new())?;
-        }
-
-        // Load credentials from the file
-        self.load_from_file()?;
-
-        // Mark as initialized
-        self.initialized.store(true, std::sync::atomic::Ordering::Relaxed);
-
-        Ok(())
-    }
-
-    /// Derive encryption key from the configuration
-    fn derive_key(&self) -> Result<Vec<u8>> {
-        match &self.config.key_derivation {
+        match self {
             KeyDerivationMethod::Password { password, salt, iterations } => {
                 use ring::pbkdf2;
 
@@ -1158,17 +1109,7 @@ impl EncryptedFileCredentialStore {
 
                 Ok(key.to_vec())
             },
-            KeyDerivationMethod::SystemProtected { key_id } => {
-                // In a real implementation, we would use the system keystore
-                // This is a placeholder implementation
-
-                // Generate a deterministic key from the key ID
-                let mut hasher = sha2::Sha256::new();
-                hasher.update(key_id.as_bytes());
-                let hash = hasher.finalize();
-
-                Ok(hash.to_vec())
-            },
+            KeyDerivationMethod::SystemProtected { key_id } => system_protected_key(key_id),
             KeyDerivationMethod::Hsm { key_id, config: _ } => {
                 // In a real implementation, we would use the HSM
                 // This is a placeholder implementation
@@ -1182,19 +1123,82 @@ impl EncryptedFileCredentialStore {
             },
         }
     }
+}
 
-    /// Encrypt data using the derived key
-    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
-        use aes_gcm::{Aes256Gcm, Key, Nonce};
-        use aes_gcm::aead::{Aead, NewAead};
-        use rand::{thread_rng, Rng};
+/// Service the keys of [`KeyDerivationMethod::SystemProtected`] are stored under in the
+/// OS keychain
+const SYSTEM_KEY_SERVICE: &str = "evo-pro";
+
+/// Read the key stored under the key ID in the OS keychain, creating a random one if
+/// there is none yet
+fn system_protected_key(key_id: &str) -> Result<Vec<u8>> {
+    use ring::rand::{SecureRandom, SystemRandom};
+
+    let keychain_error = |e: keyring::Error| {
+        crate::error::Error::new(
+            crate::error::ErrorKind::Security,
+            &format!("Failed to access key {} in the OS keychain: {}", key_id, e)
+        )
+    };
+
+    let entry = keyring::Entry::new(SYSTEM_KEY_SERVICE, key_id).map_err(keychain_error)?;
+    match entry.get_secret() {
+        Ok(key) if key.len() == 32 => Ok(key),
+        Ok(_) => Err(crate::error::Error::new(
+            crate::error::ErrorKind::Security,
+            &format!("Key {} in the OS keychain isn't 256 bits", key_id)
+        )),
+        Err(keyring::Error::NoEntry) => {
+            // Generate the key on first use
+            let mut key = vec![0u8; 32];
+            SystemRandom::new().fill(&mut key).map_err(|_| {
+                crate::error::Error::new(
+                    crate::error::ErrorKind::Security,
+                    "Failed to generate encryption key"
+                )
+            })?;
+            entry.set_secret(&key).map_err(keychain_error)?;
+
+            Ok(key)
+        },
+        Err(e) => Err(keychain_error(e)),
+    }
+}
+
+/// AES-256-GCM cipher keyed by a [`KeyDerivationMethod`].
+///
+/// Shared by the credential stores so that every secret at rest is sealed the same way.
+pub struct SecretCipher {
+    key: Vec<u8>,
+}
+
+impl SecretCipher {
+    /// Create a cipher using the key derived from the given method
+    pub fn new(key_derivation: &KeyDerivationMethod) -> Result<Self> {
+        Ok(Self {
+            key: key_derivation.derive_key()?,
+        })
+    }
+
+    fn cipher(&self) -> Result<aes_gcm::Aes256Gcm> {
+        use aes_gcm::{Aes256Gcm, KeyInit};
+
+        Aes256Gcm::new_from_slice(&self.key).map_err(|_| {
+            crate::error::Error::new(
+                crate::error::ErrorKind::Security,
+                "Encryption key must be 256 bits"
+            )
+        })
+    }
 
-        // Derive the encryption key
-        let key_bytes = self.derive_key()?;
-        let key = Key::from_slice(&key_bytes);
+    /// Encrypt data, prefixing the ciphertext with its random nonce
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        use aes_gcm::Nonce;
+        use aes_gcm::aead::Aead;
+        use rand::{thread_rng, Rng};
 
         // Create the cipher
-        let cipher = Aes256Gcm::new(key);
+        let cipher = self.cipher()?;
 
         // Generate a random nonce
         let mut nonce_bytes = [0u8; 12];
@@ -1217,17 +1221,13 @@ impl EncryptedFileCredentialStore {
         Ok(result)
     }
 
-    /// Decrypt data using the derived key
-    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
-        use aes_gcm::{Aes256Gcm, Key, Nonce};
-        use aes_gcm::aead::{Aead, NewAead};
-
-        // Derive the encryption key
-        let key_bytes = self.derive_key()?;
-        let key = Key::from_slice(&key_bytes);
+    /// Decrypt data produced by [`SecretCipher::encrypt`]
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        use aes_gcm::Nonce;
+        use aes_gcm::aead::Aead;
 
         // Create the cipher
-        let cipher = Aes256Gcm::new(key);
+        let cipher = self.cipher()?;
 
         // Split the data into nonce and ciphertext
         if data.len() < 12 {
@@ -1250,6 +1250,70 @@ impl EncryptedFileCredentialStore {
 
         Ok(plaintext)
     }
+}
+
+/// Encrypted file-based credential store
+pub struct EncryptedFileCredentialStore {
+    /// Configuration for the credential store
+    config: EncryptedFileCredentialStoreConfig,
+
+    /// Cache of decrypted credentials
+    cache: std::sync::RwLock<HashMap<String, Credentials>>,
+
+    /// Whether the store has been initialized
+    initialized: std::sync::atomic::AtomicBool,
+}
+
+impl EncryptedFileCredentialStore {
+    /// Create a new encrypted file credential store
+    pub fn new(config: EncryptedFileCredentialStoreConfig) -> Self {
+        Self {
+            config,
+            cache: std::sync::RwLock::new(HashMap::new()),
+            initialized: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    /// Initialize the credential store
+    pub fn initialize(&self) -> Result<()> {
+        // Check if the store is already initialized
+        if self.initialized.load(std::sync::atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        // Create the directory if it doesn't exist
+        if let Some(parent) = self.config.file_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                crate::error::Error::new(
+                    crate::error::ErrorKind::IO,
+                    &format!("Failed to create directory for credential store: {}", e)
+                )
+            })?;
+        }
+
+        // If the file doesn't exist, create it with an empty set of credentials
+        if !self.config.file_path.exists() {
+            self.save_to_file(&HashMap::new())?;
+        }
+
+        // Load credentials from the file
+        self.load_from_file()?;
+
+        // Mark as initialized
+        self.initialized.store(true, std::sync::atomic::Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Encrypt data using the derived key
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        SecretCipher::new(&self.config.key_derivation)?.encrypt(data)
+    }
+
+    /// Decrypt data using the derived key
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        SecretCipher::new(&self.config.key_derivation)?.decrypt(data)
+    }
 
     /// Load credentials from the encrypted file
     fn load_from_file(&self) -> Result<()> {
@@ -1,48 +1,56 @@
-use std::sync::{Arc, LazyLock, OnceLock, RwLock};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, LazyLock, OnceLock, RwLock},
+};
 
+use chrono::{DateTime, Duration, Utc};
 use kameo::remote::Keypair;
 use libp2p::{PeerId, identity::PublicKey};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 use uuid::Uuid;
 
-use crate::{error::Result, utils::get_data_dir};
+use crate::{
+    error::{AppError, Result},
+    keystore::{Keystore, KeystoreConfig},
+};
 
-pub static KEY_PAIR: LazyLock<Arc<RwLock<Keypair>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(fetch_peer_keypair())));
+/// Our user and device keys, opened on first use
+pub static KEYSTORE: LazyLock<RwLock<Keystore>> = LazyLock::new(|| {
+    RwLock::new(Keystore::open(KeystoreConfig::default()).expect("failed to open keystore"))
+});
+/// The device key, which messages are signed with and the peer id is derived from.
+/// Tests sign with a throwaway key instead of opening the keystore.
+pub static KEY_PAIR: LazyLock<Arc<RwLock<Keypair>>> = LazyLock::new(|| {
+    #[cfg(test)]
+    let key_pair = Keypair::generate_ed25519();
+    #[cfg(not(test))]
+    let key_pair = fetch_peer_keypair();
+    Arc::new(RwLock::new(key_pair))
+});
 pub static PEER_ID: OnceLock<PeerId> = OnceLock::new();
 
+/// Prefix of every signed payload, so that signatures can't be reused in another context
+const SIGNATURE_DOMAIN: &[u8] = b"evo-design/signed/v1\0";
+const CERTIFICATE_DOMAIN: &[u8] = b"evo-design/device-certificate/v1\0";
+const ROTATION_DOMAIN: &[u8] = b"evo-design/key-rotation/v1\0";
+/// How long a signed message is accepted after it was issued
+pub const MAX_MESSAGE_AGE: Duration = Duration::minutes(5);
+/// How far ahead of our clock a peer's clock may be
+pub const MAX_CLOCK_SKEW: Duration = Duration::seconds(30);
+/// Number of nonces a gateway remembers per peer to reject replayed messages
+pub const REPLAY_CACHE_CAPACITY: usize = 16384;
+/// Number of peers a gateway remembers nonces for at once
+pub const REPLAY_CACHE_PEERS: usize = 256;
+
+/// The key identifying the user across their devices
 pub fn fetch_user_keypair() -> Keypair {
-    let key_path = get_data_dir().join("keypair.proto");
-    if key_path.is_file() {
-        Keypair::from_protobuf_encoding(&std::fs::read(key_path).expect("failed to read keypair"))
-            .expect("failed to decode keypair")
-    } else {
-        let pair = Keypair::generate_ed25519();
-        std::fs::write(
-            key_path,
-            pair.to_protobuf_encoding()
-                .expect("keypair should have valid protobuf encoding"),
-        )
-        .expect("failed to write keypair");
-        pair
-    }
+    KEYSTORE.read().unwrap().user_keypair().clone()
 }
 
+/// The key of this device, its peer id
 pub fn fetch_peer_keypair() -> Keypair {
-    let key_path = get_data_dir().join("peer-keypair.proto");
-    if key_path.is_file() {
-        Keypair::from_protobuf_encoding(&std::fs::read(key_path).expect("failed to read keypair"))
-            .expect("failed to decode keypair")
-    } else {
-        let pair = Keypair::generate_ed25519();
-        std::fs::write(
-            key_path,
-            pair.to_protobuf_encoding()
-                .expect("keypair should have valid protobuf encoding"),
-        )
-        .expect("failed to write keypair");
-        pair
-    }
+    KEYSTORE.read().unwrap().device_keypair().clone()
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -58,6 +66,8 @@ pub struct Signed<T> {
     public_key: PubKeyWrapper,
     client_peer_id: PeerId,
     task_id: Option<Uuid>,
+    issued_at: DateTime<Utc>,
+    nonce: Uuid,
 }
 
 impl<T> Signed<T> {
@@ -76,6 +86,12 @@ impl<T> Signed<T> {
     pub fn take_task_id(&mut self) -> Option<Uuid> {
         self.task_id.take()
     }
+    pub fn issued_at(&self) -> &DateTime<Utc> {
+        &self.issued_at
+    }
+    pub fn nonce(&self) -> &Uuid {
+        &self.nonce
+    }
 }
 
 impl<T: Serialize> Signed<T> {
@@ -85,20 +101,360 @@ impl<T: Serialize> Signed<T> {
 
     pub fn with_task(inner: T, task_id: Option<Uuid>) -> Self {
         let key_pair = KEY_PAIR.read().unwrap().clone();
-        let signature = key_pair.sign(&serde_json::to_vec(&inner).unwrap()).unwrap();
+        let client_peer_id = PEER_ID.get().cloned().unwrap();
+        let issued_at = Utc::now();
+        let nonce = Uuid::new_v4();
+        let payload = signing_payload(
+            &serde_json::to_value(&inner).unwrap(),
+            &client_peer_id,
+            task_id.as_ref(),
+            &issued_at,
+            &nonce,
+        );
+        let signature = key_pair.sign(&payload).unwrap();
         Self {
             inner,
             signature,
             public_key: PubKeyWrapper(key_pair.public()),
-            client_peer_id: PEER_ID.get().cloned().unwrap(),
+            client_peer_id,
             task_id,
+            issued_at,
+            nonce,
         }
     }
 
+    /// Checks that the message was signed by the key of the peer it claims to come
+    /// from. Whether the message is fresh is checked by a [`ReplayCache`].
     pub fn verify_signature(&self) -> bool {
-        self.public_key
-            .0
-            .verify(&serde_json::to_vec(&self.inner).unwrap(), &self.signature)
+        if self.public_key.0.to_peer_id() != self.client_peer_id {
+            return false;
+        }
+        let Ok(inner) = serde_json::to_value(&self.inner) else {
+            return false;
+        };
+        let payload = signing_payload(
+            &inner,
+            &self.client_peer_id,
+            self.task_id.as_ref(),
+            &self.issued_at,
+            &self.nonce,
+        );
+        self.public_key.0.verify(&payload, &self.signature)
+    }
+}
+
+/// Encodes everything a signature covers. Variable length fields are length prefixed
+/// so that no two different messages share an encoding.
+fn signing_payload(
+    inner: &Value,
+    client_peer_id: &PeerId,
+    task_id: Option<&Uuid>,
+    issued_at: &DateTime<Utc>,
+    nonce: &Uuid,
+) -> Vec<u8> {
+    let mut content = Vec::new();
+    write_canonical_json(inner, &mut content);
+    let peer_id = client_peer_id.to_bytes();
+
+    let mut payload = Vec::with_capacity(SIGNATURE_DOMAIN.len() + content.len() + 80);
+    payload.extend_from_slice(SIGNATURE_DOMAIN);
+    payload.extend_from_slice(&(content.len() as u64).to_be_bytes());
+    payload.extend_from_slice(&content);
+    payload.extend_from_slice(&(peer_id.len() as u64).to_be_bytes());
+    payload.extend_from_slice(&peer_id);
+    match task_id {
+        Some(task_id) => {
+            payload.push(1);
+            payload.extend_from_slice(task_id.as_bytes());
+        }
+        None => payload.push(0),
+    }
+    payload.extend_from_slice(&issued_at.timestamp_micros().to_be_bytes());
+    payload.extend_from_slice(nonce.as_bytes());
+    payload
+}
+
+/// Writes JSON with object keys sorted and without whitespace, so that both ends
+/// produce the same bytes regardless of field or map ordering.
+fn write_canonical_json(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => {
+            serde_json::to_writer(&mut *out, value).expect("writing to a Vec can't fail");
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(b',');
+                }
+                write_canonical_json(item, out);
+            }
+            out.push(b']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+            out.push(b'{');
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, key).expect("writing to a Vec can't fail");
+                out.push(b':');
+                write_canonical_json(value, out);
+            }
+            out.push(b'}');
+        }
+    }
+}
+
+/// A user key's statement that a device key, and so the peer id derived from it,
+/// belongs to the user
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCertificate {
+    pub user_key: PubKeyWrapper,
+    pub device_key: PubKeyWrapper,
+    pub issued_at: DateTime<Utc>,
+    signature: Vec<u8>,
+}
+
+impl DeviceCertificate {
+    pub fn issue(user: &Keypair, device_key: PublicKey) -> Result<Self> {
+        let issued_at = Utc::now();
+        let payload = certificate_payload(&user.public(), &device_key, &issued_at);
+        Ok(Self {
+            user_key: PubKeyWrapper(user.public()),
+            device_key: PubKeyWrapper(device_key),
+            issued_at,
+            signature: sign(user, &payload)?,
+        })
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.device_key.0.to_peer_id()
+    }
+
+    pub fn verify(&self) -> bool {
+        let payload = certificate_payload(&self.user_key.0, &self.device_key.0, &self.issued_at);
+        self.user_key.0.verify(&payload, &self.signature)
+    }
+}
+
+fn certificate_payload(
+    user_key: &PublicKey,
+    device_key: &PublicKey,
+    issued_at: &DateTime<Utc>,
+) -> Vec<u8> {
+    let mut payload = CERTIFICATE_DOMAIN.to_vec();
+    push_field(&mut payload, &user_key.encode_protobuf());
+    push_field(&mut payload, &device_key.encode_protobuf());
+    payload.extend_from_slice(&issued_at.timestamp_micros().to_be_bytes());
+    payload
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyKind {
+    User = 0,
+    Device = 1,
+}
+
+/// Replaces one of our keys. The old key signs it to show that its owner made the
+/// change, and the new key to show that the owner holds it. Contacts that trusted the
+/// old key move their trust to the new one.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyRotation {
+    pub kind: KeyKind,
+    pub old_key: PubKeyWrapper,
+    pub new_key: PubKeyWrapper,
+    pub rotated_at: DateTime<Utc>,
+    /// The user key's certificate of the device key after the rotation
+    pub certificate: DeviceCertificate,
+    old_signature: Vec<u8>,
+    new_signature: Vec<u8>,
+}
+
+impl KeyRotation {
+    pub fn new(
+        kind: KeyKind,
+        old: &Keypair,
+        new: &Keypair,
+        certificate: DeviceCertificate,
+    ) -> Result<Self> {
+        let rotated_at = Utc::now();
+        let payload = rotation_payload(
+            kind,
+            &old.public(),
+            &new.public(),
+            &rotated_at,
+            &certificate,
+        );
+        Ok(Self {
+            kind,
+            old_key: PubKeyWrapper(old.public()),
+            new_key: PubKeyWrapper(new.public()),
+            rotated_at,
+            old_signature: sign(old, &payload)?,
+            new_signature: sign(new, &payload)?,
+            certificate,
+        })
+    }
+
+    /// Checks the signatures, and that the rotation was announced by the device it
+    /// concerns
+    pub fn verify(&self, sender: &PeerId) -> Result<()> {
+        let payload = rotation_payload(
+            self.kind,
+            &self.old_key.0,
+            &self.new_key.0,
+            &self.rotated_at,
+            &self.certificate,
+        );
+        if !self.old_key.0.verify(&payload, &self.old_signature)
+            || !self.new_key.0.verify(&payload, &self.new_signature)
+            || !self.certificate.verify()
+        {
+            return Err(AppError::authentication("Invalid key rotation signature"));
+        }
+        let (certified, device) = match self.kind {
+            KeyKind::User => (&self.certificate.user_key, self.certificate.peer_id()),
+            KeyKind::Device => (&self.certificate.device_key, self.old_key.0.to_peer_id()),
+        };
+        if certified.0 != self.new_key.0 {
+            return Err(AppError::authentication(
+                "The certificate of a key rotation doesn't certify the new key",
+            ));
+        }
+        if device != *sender {
+            return Err(AppError::authorization(format!(
+                "Peer {sender} announced a key rotation of peer {device}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn rotation_payload(
+    kind: KeyKind,
+    old_key: &PublicKey,
+    new_key: &PublicKey,
+    rotated_at: &DateTime<Utc>,
+    certificate: &DeviceCertificate,
+) -> Vec<u8> {
+    let mut payload = ROTATION_DOMAIN.to_vec();
+    payload.push(kind as u8);
+    push_field(&mut payload, &old_key.encode_protobuf());
+    push_field(&mut payload, &new_key.encode_protobuf());
+    payload.extend_from_slice(&rotated_at.timestamp_micros().to_be_bytes());
+    push_field(&mut payload, &certificate.signature);
+    payload
+}
+
+fn push_field(payload: &mut Vec<u8>, field: &[u8]) {
+    payload.extend_from_slice(&(field.len() as u64).to_be_bytes());
+    payload.extend_from_slice(field);
+}
+
+fn sign(key: &Keypair, payload: &[u8]) -> Result<Vec<u8>> {
+    key.sign(payload)
+        .map_err(|e| AppError::internal(format!("Failed to sign: {e}")))
+}
+
+/// Remembers the nonces of recently accepted messages to reject replays of them.
+///
+/// Messages older than [`MAX_MESSAGE_AGE`] are always rejected, so nonces only need to
+/// be kept for that long. Nonces are kept per peer: once a peer's nonces fill the cache
+/// its oldest nonce is forgotten and everything that peer issued up to that point is
+/// rejected from then on, without affecting the other peers. Once the cache tracks too
+/// many peers the least recently seen one is forgotten, and peers the cache doesn't
+/// track are rejected for everything issued up to that peer's last message.
+pub struct ReplayCache {
+    capacity: usize,
+    max_peers: usize,
+    peers: HashMap<PeerId, PeerNonces>,
+    /// Messages of untracked peers issued at or before this time are rejected
+    evicted: Option<DateTime<Utc>>,
+}
+
+/// The nonces accepted from a single peer
+#[derive(Default)]
+struct PeerNonces {
+    seen: BTreeSet<(DateTime<Utc>, Uuid)>,
+    /// Messages issued at or before this time are rejected
+    watermark: Option<DateTime<Utc>>,
+}
+
+impl ReplayCache {
+    pub fn new(capacity: usize, max_peers: usize) -> Self {
+        Self {
+            capacity,
+            max_peers,
+            peers: HashMap::new(),
+            evicted: None,
+        }
+    }
+
+    /// Records the message and returns whether it is fresh and hasn't been seen before.
+    /// Only call this for messages whose signature has been verified.
+    pub fn check<T>(&mut self, signed: &Signed<T>, now: DateTime<Utc>) -> bool {
+        self.accept(signed.client_peer_id, signed.issued_at, signed.nonce, now)
+    }
+
+    fn accept(
+        &mut self,
+        peer_id: PeerId,
+        issued_at: DateTime<Utc>,
+        nonce: Uuid,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let oldest = now - MAX_MESSAGE_AGE;
+        if issued_at < oldest || issued_at > now + MAX_CLOCK_SKEW {
+            return false;
+        }
+        if !self.peers.contains_key(&peer_id) {
+            // The peer may have been evicted along with the nonces it used
+            if self.evicted.is_some_and(|evicted| issued_at <= evicted) {
+                return false;
+            }
+            if self.peers.len() >= self.max_peers {
+                // Peers whose nonces all expired are covered by the age check
+                self.peers
+                    .retain(|_, nonces| nonces.seen.last().is_some_and(|(at, _)| *at >= oldest));
+            }
+            if self.peers.len() >= self.max_peers
+                && let Some((&evicted, _)) = self
+                    .peers
+                    .iter()
+                    .min_by_key(|(_, nonces)| nonces.seen.last().map(|(at, _)| *at))
+            {
+                let last_seen = self
+                    .peers
+                    .remove(&evicted)
+                    .and_then(|nonces| nonces.seen.last().map(|(at, _)| *at));
+                self.evicted = self.evicted.max(last_seen);
+            }
+        }
+        let nonces = self.peers.entry(peer_id).or_default();
+        if nonces
+            .watermark
+            .is_some_and(|watermark| issued_at <= watermark)
+        {
+            return false;
+        }
+        while nonces.seen.first().is_some_and(|(at, _)| *at < oldest) {
+            nonces.seen.pop_first();
+        }
+        if !nonces.seen.insert((issued_at, nonce)) {
+            return false;
+        }
+        if nonces.seen.len() > self.capacity
+            && let Some((at, _)) = nonces.seen.pop_first()
+        {
+            nonces.watermark = Some(at);
+        }
+        true
     }
 }
 
@@ -116,3 +472,117 @@ where
     let bytes: Vec<u8> = Deserialize::deserialize(deserializer)?;
     PublicKey::try_decode_protobuf(&bytes).map_err(serde::de::Error::custom)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_canonical_json_sorts_keys() {
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        write_canonical_json(
+            &json!({ "b": [1, { "y": null, "x": "é" }], "a": true }),
+            &mut a,
+        );
+        write_canonical_json(
+            &json!({ "a": true, "b": [1, { "x": "é", "y": null }] }),
+            &mut b,
+        );
+
+        assert_eq!(a, b);
+        assert_eq!(a, r#"{"a":true,"b":[1,{"x":"é","y":null}]}"#.as_bytes());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let user = Keypair::generate_ed25519();
+        let device = Keypair::generate_ed25519();
+        let new_device = Keypair::generate_ed25519();
+        let certificate = DeviceCertificate::issue(&user, new_device.public()).unwrap();
+        assert!(certificate.verify());
+
+        let rotation =
+            KeyRotation::new(KeyKind::Device, &device, &new_device, certificate.clone()).unwrap();
+        assert!(rotation.verify(&device.public().to_peer_id()).is_ok());
+        // Only the device whose key is rotated may announce it
+        assert!(rotation.verify(&new_device.public().to_peer_id()).is_err());
+
+        let mut forged = rotation.clone();
+        forged.new_key = PubKeyWrapper(Keypair::generate_ed25519().public());
+        assert!(forged.verify(&device.public().to_peer_id()).is_err());
+
+        // A user rotation must come with a certificate by the new user key
+        let new_user = Keypair::generate_ed25519();
+        let rotation = KeyRotation::new(KeyKind::User, &user, &new_user, certificate).unwrap();
+        assert!(rotation.verify(&new_device.public().to_peer_id()).is_err());
+    }
+
+    #[test]
+    fn test_replay_cache() {
+        let now = Utc::now();
+        let peer = PeerId::random();
+        let mut cache = ReplayCache::new(2, 2);
+        let nonce = Uuid::new_v4();
+
+        assert!(cache.accept(peer, now, nonce, now));
+        assert!(!cache.accept(peer, now, nonce, now));
+        assert!(!cache.accept(peer, now - Duration::minutes(6), Uuid::new_v4(), now));
+        assert!(!cache.accept(peer, now + Duration::minutes(1), Uuid::new_v4(), now));
+
+        // Overflowing the cache rejects everything up to the forgotten nonce
+        let first = now - Duration::seconds(10);
+        assert!(cache.accept(peer, first, Uuid::new_v4(), now));
+        assert!(cache.accept(peer, now - Duration::seconds(5), Uuid::new_v4(), now));
+        assert!(!cache.accept(peer, first, Uuid::new_v4(), now));
+        assert!(cache.accept(peer, now, Uuid::new_v4(), now));
+    }
+
+    #[test]
+    fn test_replay_cache_keeps_peers_apart() {
+        let now = Utc::now();
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut cache = ReplayCache::new(2, 2);
+        let at = |secs| now - Duration::seconds(secs);
+        let nonce = Uuid::new_v4();
+
+        // Nonces are chosen by the signer, so another peer may pick the same one
+        assert!(cache.accept(a, at(30), nonce, now));
+        assert!(cache.accept(b, at(30), nonce, now));
+        assert!(cache.accept(a, at(10), Uuid::new_v4(), now));
+        assert!(cache.accept(b, at(25), Uuid::new_v4(), now));
+        assert!(cache.accept(a, at(5), Uuid::new_v4(), now));
+        assert!(cache.accept(a, at(0), Uuid::new_v4(), now));
+
+        // Peer a overflowing its nonces doesn't push the watermark of peer b
+        assert!(!cache.accept(a, at(20), Uuid::new_v4(), now));
+        assert!(cache.accept(b, at(20), Uuid::new_v4(), now));
+        assert!(!cache.accept(b, at(30), nonce, now));
+    }
+
+    #[test]
+    fn test_replay_cache_evicts_least_recently_seen_peer() {
+        let now = Utc::now();
+        let mut cache = ReplayCache::new(2, 2);
+        let at = |secs| now - Duration::seconds(secs);
+        let peers: Vec<_> = (0..4).map(|_| PeerId::random()).collect();
+        let nonce = Uuid::new_v4();
+
+        assert!(cache.accept(peers[0], at(30), nonce, now));
+        assert!(cache.accept(peers[1], at(20), Uuid::new_v4(), now));
+
+        // Filling the cache past its peers forgets the peer seen least recently
+        assert!(cache.accept(peers[2], at(10), Uuid::new_v4(), now));
+        assert_eq!(cache.peers.len(), 2);
+        assert!(!cache.peers.contains_key(&peers[0]));
+
+        // Its messages can't be replayed, while fresh ones are still accepted
+        assert!(!cache.accept(peers[0], at(30), nonce, now));
+        assert!(!cache.accept(peers[3], at(35), Uuid::new_v4(), now));
+        assert!(cache.accept(peers[3], at(5), Uuid::new_v4(), now));
+        assert!(cache.accept(peers[0], at(0), Uuid::new_v4(), now));
+        assert_eq!(cache.peers.len(), 2);
+    }
+}
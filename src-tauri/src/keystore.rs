@@ -0,0 +1,284 @@
+//! Our identity keys, encrypted at rest.
+//!
+//! The user key identifies the user and certifies the device key, which signs our
+//! messages and is the peer id of the swarm. Both can be rotated. A new user key is
+//! used right away, a new device key from the next start, since the swarm can't change
+//! its peer id while it runs.
+
+use std::path::PathBuf;
+
+use kameo::remote::Keypair;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    error::{AppError, Result},
+    integration::{KeyDerivationMethod, SecretCipher},
+    keys::{DeviceCertificate, KeyKind, KeyRotation},
+    utils::get_data_dir,
+};
+
+/// Key in the OS keychain the keystore is sealed with
+const KEYSTORE_KEY_ID: &str = "evo-pro-keystore";
+const KEYSTORE_FILE: &str = "keystore.enc";
+/// Unencrypted keys written by earlier versions, imported into the keystore
+const LEGACY_USER_KEY_FILE: &str = "keypair.proto";
+const LEGACY_DEVICE_KEY_FILE: &str = "peer-keypair.proto";
+
+#[derive(Debug, Clone)]
+pub struct KeystoreConfig {
+    pub file_path: PathBuf,
+    pub key_derivation: KeyDerivationMethod,
+}
+
+impl Default for KeystoreConfig {
+    fn default() -> Self {
+        Self {
+            file_path: get_data_dir().join(KEYSTORE_FILE),
+            key_derivation: KeyDerivationMethod::SystemProtected {
+                key_id: KEYSTORE_KEY_ID.to_string(),
+            },
+        }
+    }
+}
+
+/// What is encrypted in the keystore file. Keys are protobuf encoded.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredKeys {
+    user_key: Vec<u8>,
+    device_key: Vec<u8>,
+    certificate: DeviceCertificate,
+    /// Device key that replaces `device_key` on the next start
+    next_device: Option<StoredDevice>,
+    /// Our rotations, the oldest first
+    rotations: Vec<KeyRotation>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredDevice {
+    key: Vec<u8>,
+    certificate: DeviceCertificate,
+}
+
+pub struct Keystore {
+    config: KeystoreConfig,
+    cipher: SecretCipher,
+    user: Keypair,
+    device: Keypair,
+    certificate: DeviceCertificate,
+    next_device: Option<(Keypair, DeviceCertificate)>,
+    rotations: Vec<KeyRotation>,
+}
+
+impl Keystore {
+    /// Opens the keystore, or creates it from the keys of earlier versions or new keys.
+    /// A device key rotated since the last start takes effect.
+    pub fn open(config: KeystoreConfig) -> Result<Self> {
+        let cipher = SecretCipher::new(&config.key_derivation)?;
+        let mut keystore = if config.file_path.is_file() {
+            let stored: StoredKeys =
+                serde_json::from_slice(&cipher.decrypt(&std::fs::read(&config.file_path)?)?)?;
+            Self {
+                user: decode_keypair(&stored.user_key)?,
+                device: decode_keypair(&stored.device_key)?,
+                certificate: stored.certificate,
+                next_device: stored
+                    .next_device
+                    .map(|next| Ok::<_, AppError>((decode_keypair(&next.key)?, next.certificate)))
+                    .transpose()?,
+                rotations: stored.rotations,
+                config,
+                cipher,
+            }
+        } else {
+            Self::create(config, cipher)?
+        };
+        if let Some((device, certificate)) = keystore.next_device.take() {
+            info!(peer_id = %certificate.peer_id(), "Using the rotated device key");
+            keystore.device = device;
+            keystore.certificate = certificate;
+            keystore.save()?;
+        }
+        Ok(keystore)
+    }
+
+    fn create(config: KeystoreConfig, cipher: SecretCipher) -> Result<Self> {
+        let dir = config
+            .file_path
+            .parent()
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        std::fs::create_dir_all(&dir)?;
+        let user_path = dir.join(LEGACY_USER_KEY_FILE);
+        let device_path = dir.join(LEGACY_DEVICE_KEY_FILE);
+        let user = read_legacy_keypair(&user_path)?.unwrap_or_else(Keypair::generate_ed25519);
+        // Keeping the device key keeps our peer id
+        let device = read_legacy_keypair(&device_path)?.unwrap_or_else(Keypair::generate_ed25519);
+        let keystore = Self {
+            certificate: DeviceCertificate::issue(&user, device.public())?,
+            user,
+            device,
+            next_device: None,
+            rotations: Vec::new(),
+            config,
+            cipher,
+        };
+        keystore.save()?;
+        // Only drop the unencrypted keys once the keystore can be read back with a key
+        // derived anew, e.g. once the OS keychain kept the key we created
+        let sealed = std::fs::read(&keystore.config.file_path)?;
+        SecretCipher::new(&keystore.config.key_derivation)?.decrypt(&sealed)?;
+        for path in [user_path, device_path] {
+            if path.is_file()
+                && let Err(e) = std::fs::remove_file(&path)
+            {
+                warn!(path = %path.display(), "Failed to remove unencrypted key: {e}");
+            }
+        }
+        Ok(keystore)
+    }
+
+    pub fn user_keypair(&self) -> &Keypair {
+        &self.user
+    }
+
+    pub fn device_keypair(&self) -> &Keypair {
+        &self.device
+    }
+
+    /// The user key's certificate of the device key in use
+    pub fn certificate(&self) -> &DeviceCertificate {
+        &self.certificate
+    }
+
+    pub fn rotations(&self) -> &[KeyRotation] {
+        &self.rotations
+    }
+
+    /// Replaces a key with a new one and returns the statement to send to contacts
+    pub fn rotate(&mut self, kind: KeyKind) -> Result<KeyRotation> {
+        let new = Keypair::generate_ed25519();
+        let rotation = match kind {
+            KeyKind::User => {
+                let certificate = DeviceCertificate::issue(&new, self.device.public())?;
+                let rotation = KeyRotation::new(kind, &self.user, &new, certificate.clone())?;
+                if let Some((device, next)) = &mut self.next_device {
+                    *next = DeviceCertificate::issue(&new, device.public())?;
+                }
+                self.user = new;
+                self.certificate = certificate;
+                rotation
+            }
+            KeyKind::Device => {
+                // Announced by the device in use, which is the one contacts know
+                let certificate = DeviceCertificate::issue(&self.user, new.public())?;
+                let rotation = KeyRotation::new(kind, &self.device, &new, certificate.clone())?;
+                self.next_device = Some((new, certificate));
+                rotation
+            }
+        };
+        self.rotations.push(rotation.clone());
+        self.save()?;
+        Ok(rotation)
+    }
+
+    /// Writes the keystore to a temporary file first, so that a crash can't leave a
+    /// partly written keystore behind
+    fn save(&self) -> Result<()> {
+        let stored = StoredKeys {
+            user_key: encode_keypair(&self.user)?,
+            device_key: encode_keypair(&self.device)?,
+            certificate: self.certificate.clone(),
+            next_device: self
+                .next_device
+                .as_ref()
+                .map(|(key, certificate)| {
+                    Ok::<_, AppError>(StoredDevice {
+                        key: encode_keypair(key)?,
+                        certificate: certificate.clone(),
+                    })
+                })
+                .transpose()?,
+            rotations: self.rotations.clone(),
+        };
+        let encrypted = self.cipher.encrypt(&serde_json::to_vec(&stored)?)?;
+        let tmp_path = self.config.file_path.with_extension("tmp");
+        std::fs::write(&tmp_path, encrypted)?;
+        std::fs::rename(&tmp_path, &self.config.file_path)?;
+        Ok(())
+    }
+}
+
+fn read_legacy_keypair(path: &std::path::Path) -> Result<Option<Keypair>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    info!(path = %path.display(), "Importing unencrypted key into the keystore");
+    decode_keypair(&std::fs::read(path)?).map(Some)
+}
+
+fn decode_keypair(bytes: &[u8]) -> Result<Keypair> {
+    Keypair::from_protobuf_encoding(bytes)
+        .map_err(|e| AppError::validation(format!("Invalid key in keystore: {e}")))
+}
+
+fn encode_keypair(key: &Keypair) -> Result<Vec<u8>> {
+    key.to_protobuf_encoding()
+        .map_err(|e| AppError::internal(format!("Failed to encode key: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &std::path::Path) -> KeystoreConfig {
+        KeystoreConfig {
+            file_path: dir.join(KEYSTORE_FILE),
+            key_derivation: KeyDerivationMethod::Password {
+                password: uuid::Uuid::new_v4().to_string(),
+                salt: uuid::Uuid::new_v4().as_bytes().to_vec(),
+                iterations: 1_000,
+            },
+        }
+    }
+
+    #[test]
+    fn test_keystore_imports_legacy_keys_and_rotates() {
+        let dir = std::env::temp_dir().join(format!("keystore-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let legacy = Keypair::generate_ed25519();
+        std::fs::write(
+            dir.join(LEGACY_DEVICE_KEY_FILE),
+            legacy.to_protobuf_encoding().unwrap(),
+        )
+        .unwrap();
+
+        let config = config(&dir);
+        let mut keystore = Keystore::open(config.clone()).unwrap();
+        let peer_id = legacy.public().to_peer_id();
+        assert_eq!(keystore.device_keypair().public().to_peer_id(), peer_id);
+        assert!(keystore.certificate().verify());
+        assert!(!dir.join(LEGACY_DEVICE_KEY_FILE).exists());
+
+        let rotation = keystore.rotate(KeyKind::Device).unwrap();
+        assert!(rotation.verify(&peer_id).is_ok());
+        // The new device key is used from the next start
+        assert_eq!(keystore.device_keypair().public().to_peer_id(), peer_id);
+        let rotation = keystore.rotate(KeyKind::User).unwrap();
+        assert!(rotation.verify(&peer_id).is_ok());
+
+        let reopened = Keystore::open(config).unwrap();
+        assert_eq!(reopened.rotations().len(), 2);
+        assert_eq!(
+            reopened.device_keypair().public(),
+            reopened.rotations()[0].new_key.0
+        );
+        assert_eq!(reopened.user_keypair().public(), rotation.new_key.0);
+        // The rotated device key is certified by the rotated user key
+        assert!(reopened.certificate().verify());
+        assert_eq!(reopened.certificate().user_key.0, rotation.new_key.0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
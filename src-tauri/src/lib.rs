@@ -4,7 +4,9 @@ pub mod entities;
 pub mod error;
 pub mod integration;
 pub mod keys;
+pub mod keystore;
 pub mod logging;
+pub mod mcp;
 pub mod plugins;
 pub mod privacy;
 pub mod repositories;
@@ -13,6 +15,7 @@ pub mod security;
 pub mod state;
 pub mod storage;
 // pub mod swarms;
+pub mod tools;
 pub mod utils;
 use sqlx::migrate::MigrateError;
 use tauri::Manager;
@@ -75,6 +78,7 @@ pub async fn run() {
             _ => panic!("Migration failed: {}", e),
         }
     }
+    let db = db.with_vector_dir(get_data_dir().join("vectors"));
 
     // Initialize resource detection and adaptation
     tracing::info!("Initializing resource detection and adaptation...");
@@ -146,6 +150,79 @@ pub async fn run() {
             commands::update_participant,
             commands::delete_participant,
             commands::list_participants,
+            commands::send_message,
+            commands::ingest_document,
+            commands::search_documents,
+            commands::extract_memories,
+            commands::recall_memories,
+            commands::create_agent_chain,
+            commands::list_agent_chains,
+            commands::update_agent_chain,
+            commands::delete_agent_chain,
+            commands::create_agent_chain_step,
+            commands::list_agent_chain_steps,
+            commands::update_agent_chain_step,
+            commands::delete_agent_chain_step,
+            commands::run_agent_chain,
+            commands::resume_agent_chain,
+            commands::cancel_agent_chain,
+            commands::list_agent_chain_executions,
+            commands::list_agent_chain_step_executions,
+            commands::create_workflow,
+            commands::list_workflows,
+            commands::update_workflow,
+            commands::delete_workflow,
+            commands::create_workflow_step,
+            commands::list_workflow_steps,
+            commands::update_workflow_step,
+            commands::delete_workflow_step,
+            commands::start_workflow,
+            commands::pause_workflow,
+            commands::resume_workflow,
+            commands::cancel_workflow,
+            commands::list_workflow_executions,
+            commands::list_workflow_step_executions,
+            commands::list_peer_permissions,
+            commands::update_peer_permission,
+            commands::list_audit_log,
+            commands::get_network_config,
+            commands::update_network_config,
+            commands::list_p2p_nodes,
+            commands::get_network_stats,
+            commands::publish_agent,
+            commands::unpublish_agent,
+            commands::list_published_agents,
+            commands::list_peer_agents,
+            commands::prompt_peer_agent,
+            commands::share_file,
+            commands::download_file,
+            commands::cancel_file_transfer,
+            commands::list_file_transfers,
+            commands::get_file_transfer_policy,
+            commands::update_file_transfer_policy,
+            commands::get_device_certificate,
+            commands::rotate_key,
+            commands::list_key_rotations,
+            commands::get_peer_certificate,
+            commands::create_mcp_server,
+            commands::list_mcp_servers,
+            commands::connect_mcp_server,
+            commands::disconnect_mcp_server,
+            commands::delete_mcp_server,
+            commands::list_mcp_tools,
+            commands::refresh_mcp_tools,
+            commands::set_mcp_tool_enabled,
+            commands::get_mcp_endpoint_settings,
+            commands::update_mcp_endpoint_settings,
+            commands::create_mcp_api_key,
+            commands::list_api_keys,
+            commands::delete_api_key,
+            commands::list_builtin_tools,
+            commands::configure_builtin_tool,
+            commands::list_agent_tools,
+            commands::set_agent_tools,
+            commands::create_credential,
+            commands::delete_credential,
             // Data management commands
             services::export_user_data,
             services::get_retention_policy,
@@ -167,7 +244,10 @@ pub async fn run() {
             services::install_plugin_from_marketplace,
             services::uninstall_plugin_from_marketplace,
             services::update_plugin_from_marketplace,
-            services::refresh_plugin_marketplace
+            services::refresh_plugin_marketplace,
+            services::get_plugin_publisher_keys,
+            services::trust_plugin_publisher_key,
+            services::distrust_plugin_publisher_key
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,705 @@
+//! Client of an MCP server, over stdio, streamable HTTP or the older HTTP with
+//! server-sent events transport.
+//!
+//! Messages to the server are queued to a task that owns the transport. Whatever the
+//! server sends back is handed to the [`Inbox`], which completes the pending requests,
+//! answers the server's pings and reports notifications as [`ClientEvent`]s.
+
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::Duration,
+};
+
+use reqwest::{
+    Response, StatusCode, Url,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderValue},
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    entities::{McpServer, McpTransport},
+    error::{AppError, Result},
+    mcp::protocol::{
+        CallToolParams, CallToolResult, Implementation, InitializeParams, InitializeResult,
+        JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, ListToolsResult,
+        METHOD_NOT_FOUND, PROTOCOL_VERSION, RequestId, SUPPORTED_PROTOCOL_VERSIONS, Tool,
+        parse_messages,
+    },
+};
+
+/// How long the server has to answer a request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a tool may run
+pub const TOOL_CALL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const SESSION_HEADER: &str = "mcp-session-id";
+
+/// What happened to the connection, besides the answers to our requests
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// The server changed the tools it offers
+    ToolsChanged,
+    /// The connection is gone, with the reason if we know it
+    Closed(Option<String>),
+}
+
+type Pending = Mutex<HashMap<RequestId, oneshot::Sender<Result<Value>>>>;
+
+/// Receives the messages of the server
+struct Inbox {
+    server: String,
+    pending: Pending,
+    events: mpsc::UnboundedSender<ClientEvent>,
+    outgoing: mpsc::UnboundedSender<JsonRpcMessage>,
+}
+
+impl Inbox {
+    fn dispatch(&self, message: JsonRpcMessage) {
+        match message {
+            JsonRpcMessage::Response(response) => {
+                let Some(id) = response.id.clone() else {
+                    warn!(server = %self.server, "MCP server failed to parse a request: {:?}", response.error);
+                    return;
+                };
+                match self.pending.lock().unwrap().remove(&id) {
+                    Some(tx) => {
+                        tx.send(response.into_result()).ok();
+                    }
+                    None => {
+                        debug!(server = %self.server, %id, "Response to an unknown MCP request")
+                    }
+                }
+            }
+            JsonRpcMessage::Notification(notification) => match notification.method.as_str() {
+                "notifications/tools/list_changed" => {
+                    self.events.send(ClientEvent::ToolsChanged).ok();
+                }
+                "notifications/message" => {
+                    info!(server = %self.server, "MCP server log: {}", notification.params.unwrap_or_default());
+                }
+                method => debug!(server = %self.server, "Ignoring MCP notification {method}"),
+            },
+            JsonRpcMessage::Request(request) => {
+                let response = match request.method.as_str() {
+                    "ping" => JsonRpcResponse::success(request.id, json!({})),
+                    method => JsonRpcResponse::error(
+                        Some(request.id),
+                        METHOD_NOT_FOUND,
+                        format!("Method not supported: {method}"),
+                    ),
+                };
+                self.outgoing.send(response.into()).ok();
+            }
+        }
+    }
+
+    fn fail(&self, id: &RequestId, error: AppError) {
+        if let Some(tx) = self.pending.lock().unwrap().remove(id) {
+            tx.send(Err(error)).ok();
+        }
+    }
+
+    /// Fails the pending requests, nothing will answer them anymore
+    fn close(&self, reason: Option<String>) {
+        for (_, tx) in self.pending.lock().unwrap().drain() {
+            tx.send(Err(AppError::external_service(format!(
+                "Connection to MCP server {} closed",
+                self.server
+            ))))
+            .ok();
+        }
+        self.events.send(ClientEvent::Closed(reason)).ok();
+    }
+}
+
+pub struct McpClient {
+    inbox: Arc<Inbox>,
+    next_id: AtomicI64,
+    /// Tasks of the transport, stopped when the client is dropped
+    tasks: Vec<JoinHandle<()>>,
+    pub server_info: Implementation,
+    pub capabilities: Value,
+    pub protocol_version: String,
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl McpClient {
+    /// Connects to the server and runs the initialize handshake
+    pub async fn connect(
+        server: &McpServer,
+    ) -> Result<(Arc<Self>, mpsc::UnboundedReceiver<ClientEvent>)> {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let inbox = Arc::new(Inbox {
+            server: server.name.clone(),
+            pending: Mutex::new(HashMap::new()),
+            events: events_tx,
+            outgoing: outgoing_tx,
+        });
+        let tasks = match server.transport {
+            McpTransport::Stdio => spawn_stdio(server, inbox.clone(), outgoing_rx)?,
+            McpTransport::Http => spawn_http(server, inbox.clone(), outgoing_rx)?,
+            McpTransport::Sse => spawn_sse(server, inbox.clone(), outgoing_rx).await?,
+        };
+        let mut client = Self {
+            inbox,
+            next_id: AtomicI64::new(1),
+            tasks,
+            server_info: Implementation {
+                name: server.name.clone(),
+                version: String::new(),
+            },
+            capabilities: Value::Null,
+            protocol_version: PROTOCOL_VERSION.to_string(),
+        };
+
+        let result: InitializeResult = client
+            .request(
+                "initialize",
+                serde_json::to_value(InitializeParams {
+                    protocol_version: PROTOCOL_VERSION.to_string(),
+                    capabilities: json!({}),
+                    client_info: Implementation::evo_pro(),
+                })?,
+                REQUEST_TIMEOUT,
+            )
+            .await?;
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&result.protocol_version.as_str()) {
+            return Err(AppError::external_service(format!(
+                "MCP server {} speaks unsupported protocol version {}",
+                server.name, result.protocol_version
+            )));
+        }
+        client.notify("notifications/initialized", None);
+        client.server_info = result.server_info;
+        client.capabilities = result.capabilities;
+        client.protocol_version = result.protocol_version;
+        info!(
+            server = %server.name,
+            "Connected to MCP server {} {}",
+            client.server_info.name, client.server_info.version
+        );
+        Ok((Arc::new(client), events_rx))
+    }
+
+    pub async fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<T> {
+        let id = RequestId::Number(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (tx, rx) = oneshot::channel();
+        self.inbox.pending.lock().unwrap().insert(id.clone(), tx);
+        let request = JsonRpcRequest::new(id.clone(), method, Some(params));
+        if self.inbox.outgoing.send(request.into()).is_err() {
+            self.inbox.pending.lock().unwrap().remove(&id);
+            return Err(AppError::external_service(format!(
+                "Connection to MCP server {} closed",
+                self.inbox.server
+            )));
+        }
+        let result = match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(result)) => result?,
+            Ok(Err(_)) => {
+                return Err(AppError::external_service(format!(
+                    "Connection to MCP server {} closed",
+                    self.inbox.server
+                )));
+            }
+            Err(_) => {
+                self.inbox.pending.lock().unwrap().remove(&id);
+                // Tell the server to stop working on it
+                self.notify(
+                    "notifications/cancelled",
+                    Some(json!({ "requestId": id, "reason": "Timed out" })),
+                );
+                return Err(AppError::timeout(format!(
+                    "MCP server {} didn't answer {method} in time",
+                    self.inbox.server
+                )));
+            }
+        };
+        Ok(serde_json::from_value(result)?)
+    }
+
+    pub fn notify(&self, method: &str, params: Option<Value>) {
+        self.inbox
+            .outgoing
+            .send(JsonRpcNotification::new(method, params).into())
+            .ok();
+    }
+
+    /// Every tool of the server, following the pages of the list
+    pub async fn list_tools(&self) -> Result<Vec<Tool>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let page: ListToolsResult = self.request("tools/list", params, REQUEST_TIMEOUT).await?;
+            tools.extend(page.tools);
+            match page.next_cursor {
+                Some(next) if cursor.as_ref() != Some(&next) => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(tools)
+    }
+
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<CallToolResult> {
+        self.request(
+            "tools/call",
+            serde_json::to_value(CallToolParams {
+                name: name.to_string(),
+                arguments,
+            })?,
+            TOOL_CALL_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        self.request::<Value>("ping", json!({}), REQUEST_TIMEOUT)
+            .await
+            .map(|_| ())
+    }
+
+    /// Whether the server tells us when its tools change
+    pub fn notifies_tool_changes(&self) -> bool {
+        self.capabilities["tools"]["listChanged"].as_bool() == Some(true)
+    }
+}
+
+/// Starts the program of the server, which reads a message per line on stdin and
+/// writes them to stdout
+fn spawn_stdio(
+    server: &McpServer,
+    inbox: Arc<Inbox>,
+    mut outgoing: mpsc::UnboundedReceiver<JsonRpcMessage>,
+) -> Result<Vec<JoinHandle<()>>> {
+    let program = server.command.as_deref().ok_or_else(|| {
+        AppError::configuration(format!("MCP server {} has no command", server.name))
+    })?;
+    let mut child = Command::new(program)
+        .args(server.args.iter())
+        .envs(server.env.iter())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            AppError::external_service(format!("Failed to start MCP server {program}: {e}"))
+        })?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let reader = tokio::spawn({
+        let inbox = inbox.clone();
+        async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match parse_messages(line.as_bytes()) {
+                    Ok(messages) => messages.into_iter().for_each(|m| inbox.dispatch(m)),
+                    Err(e) => warn!(server = %inbox.server, "Invalid message from MCP server: {e}"),
+                }
+            }
+            inbox.close(Some("The server exited".to_string()));
+        }
+    });
+    let logger = tokio::spawn({
+        let server = server.name.clone();
+        async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!(%server, "{line}");
+            }
+        }
+    });
+    // The writer owns the process, which is killed when the task is stopped
+    let writer = tokio::spawn(async move {
+        let _child = child;
+        while let Some(message) = outgoing.recv().await {
+            let mut line = match serde_json::to_vec(&message) {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!(server = %inbox.server, "Failed to encode MCP message: {e}");
+                    continue;
+                }
+            };
+            line.push(b'\n');
+            if let Err(e) = async {
+                stdin.write_all(&line).await?;
+                stdin.flush().await
+            }
+            .await
+            {
+                inbox.close(Some(format!("Failed to write to the server: {e}")));
+                break;
+            }
+        }
+    });
+    Ok(vec![reader, logger, writer])
+}
+
+fn auth_headers(server: &McpServer) -> Result<HeaderMap> {
+    let invalid = |_| AppError::configuration(format!("Invalid credentials of {}", server.name));
+    let mut headers = HeaderMap::new();
+    if let Some(token) = &server.auth_token {
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).map_err(invalid)?,
+        );
+    }
+    if let Some(key) = &server.api_key {
+        headers.insert("x-api-key", HeaderValue::from_str(key).map_err(invalid)?);
+    }
+    Ok(headers)
+}
+
+fn server_url(server: &McpServer) -> Result<Url> {
+    let url = server
+        .url
+        .as_deref()
+        .ok_or_else(|| AppError::configuration(format!("MCP server {} has no URL", server.name)))?;
+    Url::parse(url).map_err(|e| AppError::configuration(format!("Invalid URL {url}: {e}")))
+}
+
+/// Streamable HTTP: every message is POSTed, the answers come back in the body of
+/// the response, either as JSON or as an event stream
+fn spawn_http(
+    server: &McpServer,
+    inbox: Arc<Inbox>,
+    mut outgoing: mpsc::UnboundedReceiver<JsonRpcMessage>,
+) -> Result<Vec<JoinHandle<()>>> {
+    let url = server_url(server)?;
+    let headers = auth_headers(server)?;
+    let client = reqwest::Client::new();
+    let writer = tokio::spawn(async move {
+        let mut session: Option<HeaderValue> = None;
+        while let Some(message) = outgoing.recv().await {
+            let id = match &message {
+                JsonRpcMessage::Request(request) => Some(request.id.clone()),
+                _ => None,
+            };
+            let body = match serde_json::to_vec(&message) {
+                Ok(body) => body,
+                Err(e) => {
+                    warn!(server = %inbox.server, "Failed to encode MCP message: {e}");
+                    continue;
+                }
+            };
+            let mut request = client
+                .post(url.clone())
+                .headers(headers.clone())
+                .header(ACCEPT, "application/json, text/event-stream")
+                .header(CONTENT_TYPE, "application/json")
+                .body(body);
+            if let Some(session) = &session {
+                request = request.header(SESSION_HEADER, session.clone());
+            }
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    if let Some(id) = &id {
+                        inbox.fail(id, AppError::external_service(e.to_string()));
+                    }
+                    continue;
+                }
+            };
+            if let Some(value) = response.headers().get(SESSION_HEADER) {
+                session = Some(value.clone());
+            }
+            let status = response.status();
+            if status == StatusCode::NOT_FOUND && session.is_some() {
+                inbox.close(Some("The session expired".to_string()));
+                break;
+            }
+            if !status.is_success() {
+                if let Some(id) = &id {
+                    inbox.fail(
+                        id,
+                        AppError::external_service(format!("MCP server answered {status}")),
+                    );
+                }
+                continue;
+            }
+            // Notifications and responses are only acknowledged
+            if status == StatusCode::ACCEPTED || id.is_none() {
+                continue;
+            }
+            // Long tool calls mustn't hold up the messages after them
+            let inbox = inbox.clone();
+            tokio::spawn(async move {
+                if let Err(e) = read_body(response, &inbox).await
+                    && let Some(id) = &id
+                {
+                    inbox.fail(id, e);
+                }
+            });
+        }
+    });
+    Ok(vec![writer])
+}
+
+async fn read_body(mut response: Response, inbox: &Inbox) -> Result<()> {
+    let is_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    if !is_stream {
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| AppError::external_service(e.to_string()))?;
+        parse_messages(&body)?
+            .into_iter()
+            .for_each(|m| inbox.dispatch(m));
+        return Ok(());
+    }
+    let mut parser = SseParser::default();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| AppError::external_service(e.to_string()))?
+    {
+        for event in parser.feed(&chunk) {
+            if event.event == "message" {
+                parse_messages(event.data.as_bytes())?
+                    .into_iter()
+                    .for_each(|m| inbox.dispatch(m));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The older HTTP transport: the server streams its messages over a GET request, and
+/// first tells us where to POST ours to
+async fn spawn_sse(
+    server: &McpServer,
+    inbox: Arc<Inbox>,
+    mut outgoing: mpsc::UnboundedReceiver<JsonRpcMessage>,
+) -> Result<Vec<JoinHandle<()>>> {
+    let url = server_url(server)?;
+    let headers = auth_headers(server)?;
+    let client = reqwest::Client::new();
+    let mut response = client
+        .get(url.clone())
+        .headers(headers.clone())
+        .header(ACCEPT, "text/event-stream")
+        .send()
+        .await
+        .and_then(Response::error_for_status)
+        .map_err(|e| AppError::external_service(e.to_string()))?;
+
+    let (endpoint_tx, endpoint_rx) = oneshot::channel();
+    let reader = tokio::spawn({
+        let inbox = inbox.clone();
+        async move {
+            let mut endpoint_tx = Some(endpoint_tx);
+            let mut parser = SseParser::default();
+            let reason = loop {
+                let chunk = match response.chunk().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break None,
+                    Err(e) => break Some(e.to_string()),
+                };
+                for event in parser.feed(&chunk) {
+                    match event.event.as_str() {
+                        "endpoint" => {
+                            if let Some(tx) = endpoint_tx.take() {
+                                tx.send(url.join(event.data.trim())).ok();
+                            }
+                        }
+                        "message" => match parse_messages(event.data.as_bytes()) {
+                            Ok(messages) => messages.into_iter().for_each(|m| inbox.dispatch(m)),
+                            Err(e) => {
+                                warn!(server = %inbox.server, "Invalid message from MCP server: {e}")
+                            }
+                        },
+                        _ => {}
+                    }
+                }
+            };
+            inbox.close(reason);
+        }
+    });
+
+    let endpoint = match tokio::time::timeout(REQUEST_TIMEOUT, endpoint_rx).await {
+        Ok(Ok(Ok(endpoint))) => endpoint,
+        Ok(Ok(Err(e))) => {
+            reader.abort();
+            return Err(AppError::external_service(format!(
+                "MCP server {} sent an invalid endpoint: {e}",
+                server.name
+            )));
+        }
+        _ => {
+            reader.abort();
+            return Err(AppError::external_service(format!(
+                "MCP server {} didn't send an endpoint",
+                server.name
+            )));
+        }
+    };
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            let id = match &message {
+                JsonRpcMessage::Request(request) => Some(request.id.clone()),
+                _ => None,
+            };
+            let body = match serde_json::to_vec(&message) {
+                Ok(body) => body,
+                Err(e) => {
+                    warn!(server = %inbox.server, "Failed to encode MCP message: {e}");
+                    continue;
+                }
+            };
+            // The answer arrives over the event stream
+            let result = client
+                .post(endpoint.clone())
+                .headers(headers.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .and_then(Response::error_for_status);
+            if let Err(e) = result
+                && let Some(id) = &id
+            {
+                inbox.fail(id, AppError::external_service(e.to_string()));
+            }
+        }
+    });
+    Ok(vec![reader, writer])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+/// Splits a stream of server-sent events into events
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(SseEvent {
+                        event: self.event.take().unwrap_or_else(|| "message".to_string()),
+                        data: self.data.join("\n"),
+                    });
+                }
+                self.event = None;
+                self.data.clear();
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+        assert!(
+            parser
+                .feed(b": keep-alive\n\nevent: endpoint\r\ndata: /messages?session=1")
+                .is_empty()
+        );
+        let events = parser.feed(b"\r\n\r\ndata: {\"a\":\ndata: 1}\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: "endpoint".to_string(),
+                    data: "/messages?session=1".to_string(),
+                },
+                SseEvent {
+                    event: "message".to_string(),
+                    data: "{\"a\":\n1}".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inbox_answers_pings() {
+        let (events, _events_rx) = mpsc::unbounded_channel();
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+        let inbox = Inbox {
+            server: "test".to_string(),
+            pending: Mutex::new(HashMap::new()),
+            events,
+            outgoing,
+        };
+        let (tx, rx) = oneshot::channel();
+        inbox
+            .pending
+            .lock()
+            .unwrap()
+            .insert(RequestId::Number(1), tx);
+
+        inbox.dispatch(JsonRpcRequest::new(RequestId::Number(7), "ping", None).into());
+        inbox
+            .dispatch(JsonRpcResponse::success(RequestId::Number(1), json!({ "ok": true })).into());
+
+        let Some(JsonRpcMessage::Response(pong)) = outgoing_rx.recv().await else {
+            panic!("Expected an answer to the ping");
+        };
+        assert_eq!(pong.id, Some(RequestId::Number(7)));
+        assert_eq!(rx.await.unwrap().unwrap(), json!({ "ok": true }));
+    }
+}
@@ -0,0 +1,324 @@
+//! Our own MCP endpoint, through which other editors and assistants call the tools of
+//! the [`ToolExecutorActor`] and the agents the user selected.
+//!
+//! The endpoint speaks streamable HTTP on the address of the `mcp_endpoint` setting.
+//! Clients that only start programs reach it over stdio through the `evo-pro-mcp`
+//! binary, which forwards every line to the endpoint. Every message must come with
+//! an API key, and the key's scopes decide which tools the client sees and may call.
+//!
+//! [`ToolExecutorActor`]: crate::actors::tools::ToolExecutorActor
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use kameo::prelude::ActorRef as LocalActorRef;
+use rand::{RngCore, rngs::OsRng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    actors::mcp_endpoint::{AuthenticateMcpKey, HandleMcpMessage, McpEndpointActor},
+    entities::{ApiKey, SettingsType},
+    error::Result,
+    mcp::protocol::{JsonRpcMessage, JsonRpcResponse, PARSE_ERROR, parse_messages},
+    storage::db::DatabaseManager,
+};
+
+const ENDPOINT_SETTING: &str = "mcp_endpoint";
+pub const DEFAULT_PORT: u16 = 7331;
+/// Path the endpoint is served on
+pub const ENDPOINT_PATH: &str = "/mcp";
+/// The window the rate limit of an API key counts requests in
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// What we offer over MCP. Changes apply right away.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct McpEndpointSettings {
+    /// Whether the endpoint is served
+    pub enabled: bool,
+    /// Where the endpoint listens, only this machine can reach the default address
+    pub listen_addr: SocketAddr,
+    /// Agents offered as tools, besides the tools of the executor
+    pub agent_ids: Vec<Uuid>,
+}
+
+impl Default for McpEndpointSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, DEFAULT_PORT)),
+            agent_ids: Vec::new(),
+        }
+    }
+}
+
+impl McpEndpointSettings {
+    /// Loads the settings from the `mcp_endpoint` setting. Invalid settings are logged
+    /// and replaced by the default ones.
+    pub async fn load(db: &DatabaseManager) -> Result<Self> {
+        Ok(match db.get_global_setting(ENDPOINT_SETTING).await? {
+            Some(setting) => serde_json::from_str(&setting.value).unwrap_or_else(|e| {
+                warn!("Invalid MCP endpoint setting, using the default settings: {e}");
+                Self::default()
+            }),
+            None => Self::default(),
+        })
+    }
+
+    /// Stores the settings in the `mcp_endpoint` setting
+    pub async fn save(&self, db: &DatabaseManager) -> Result<()> {
+        db.set_global_setting(
+            ENDPOINT_SETTING,
+            &serde_json::to_string(self)?,
+            SettingsType::Object,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// A key that was just created, the only time the key itself is known
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuedApiKey {
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// A new random API key
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("evo_{hex}")
+}
+
+/// What an API key is stored as
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Whether the scopes of an API key let it use a tool or an agent. `kind` is `tools`
+/// or `agents`, and the scopes are a JSON array of:
+/// - `*` or `mcp`: everything
+/// - `mcp:tools` or `mcp:agents`: every tool, or every offered agent
+/// - `mcp:tools:<name>` or `mcp:agents:<id>`: a single one
+pub fn scope_allows(scopes: &Value, kind: &str, name: &str) -> bool {
+    scopes
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .any(|scope| match scope.strip_prefix("mcp") {
+            None => scope == "*",
+            Some("") => true,
+            Some(rest) => match rest
+                .strip_prefix(':')
+                .and_then(|rest| rest.strip_prefix(kind))
+            {
+                Some("") => true,
+                Some(rest) => rest.strip_prefix(':') == Some(name),
+                None => false,
+            },
+        })
+}
+
+/// Requests an API key made in the current window
+#[derive(Debug, Clone, Copy)]
+pub struct RequestWindow {
+    started: Instant,
+    count: u64,
+}
+
+impl RequestWindow {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            count: 0,
+        }
+    }
+
+    /// Counts a request and returns whether it is within `limit` requests per window.
+    /// Keys without a limit may make any number of requests.
+    pub fn admit(&mut self, limit: Option<i64>, now: Instant) -> bool {
+        if now.duration_since(self.started) >= RATE_LIMIT_WINDOW {
+            *self = Self::new(now);
+        }
+        self.count += 1;
+        limit.is_none_or(|limit| self.count <= limit.max(0) as u64)
+    }
+}
+
+/// Serves the endpoint until the task is stopped
+pub async fn serve(addr: SocketAddr, endpoint: LocalActorRef<McpEndpointActor>) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("Serving MCP on http://{addr}{ENDPOINT_PATH}");
+    let router = Router::new()
+        .route(ENDPOINT_PATH, post(handle_post).get(method_not_allowed))
+        .with_state(endpoint);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+/// We don't send messages of our own, so there is no stream to open
+async fn method_not_allowed() -> StatusCode {
+    StatusCode::METHOD_NOT_ALLOWED
+}
+
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::trim)
+}
+
+/// Browsers send an origin, and only pages of this machine may call us. Stops other
+/// sites from reaching the endpoint through DNS rebinding.
+fn is_local_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let Some(host) = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| url::Url::parse(origin).ok())
+        .and_then(|url| url.host_str().map(str::to_string))
+    else {
+        return false;
+    };
+    matches!(
+        host.as_str(),
+        "localhost" | "127.0.0.1" | "[::1]" | "tauri.localhost"
+    )
+}
+
+async fn handle_post(
+    State(endpoint): State<LocalActorRef<McpEndpointActor>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !is_local_origin(&headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(key) = api_key(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "An API key is required").into_response();
+    };
+    let key = match endpoint.ask(AuthenticateMcpKey(key.to_string())).await {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return (StatusCode::UNAUTHORIZED, "Invalid API key").into_response();
+        }
+        Err(e) => {
+            warn!("Failed to check MCP API key: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let is_batch = body.trim_ascii_start().starts_with(b"[");
+    let messages = match parse_messages(&body) {
+        Ok(messages) => messages,
+        Err(e) => {
+            return json_response(&JsonRpcResponse::error(None, PARSE_ERROR, e.to_string()));
+        }
+    };
+
+    let mut responses = Vec::new();
+    for message in messages {
+        let res = endpoint
+            .ask(HandleMcpMessage {
+                key: key.clone(),
+                message,
+            })
+            .await;
+        match res {
+            Ok(Some(response)) => responses.push(JsonRpcMessage::from(response)),
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Failed to handle MCP message: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+    match responses.len() {
+        // Only notifications and responses, which are acknowledged
+        0 => StatusCode::ACCEPTED.into_response(),
+        1 if !is_batch => json_response(&responses[0]),
+        _ => json_response(&responses),
+    }
+}
+
+fn json_response(body: &impl Serialize) -> Response {
+    match serde_json::to_vec(body) {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_scope_allows() {
+        let scopes = json!(["mcp:tools:search_documents", "mcp:agents", "read"]);
+        assert!(scope_allows(&scopes, "tools", "search_documents"));
+        assert!(!scope_allows(&scopes, "tools", "read_file"));
+        assert!(scope_allows(&scopes, "agents", "6f1c"));
+
+        assert!(scope_allows(&json!(["*"]), "tools", "read_file"));
+        assert!(scope_allows(&json!(["mcp"]), "agents", "6f1c"));
+        assert!(!scope_allows(
+            &json!(["mcp:toolsx", "mcpx"]),
+            "tools",
+            "read_file"
+        ));
+        assert!(!scope_allows(&json!("mcp"), "tools", "read_file"));
+        assert!(!scope_allows(&json!([]), "tools", "read_file"));
+    }
+
+    #[test]
+    fn test_request_window() {
+        let start = Instant::now();
+        let mut window = RequestWindow::new(start);
+        assert!(window.admit(Some(2), start));
+        assert!(window.admit(Some(2), start));
+        assert!(!window.admit(Some(2), start + Duration::from_secs(59)));
+        // A new window starts
+        assert!(window.admit(Some(2), start + RATE_LIMIT_WINDOW));
+
+        let mut unlimited = RequestWindow::new(start);
+        assert!((0..1000).all(|_| unlimited.admit(None, start)));
+        assert!(!RequestWindow::new(start).admit(Some(0), start));
+    }
+
+    #[test]
+    fn test_is_local_origin() {
+        let mut headers = HeaderMap::new();
+        assert!(is_local_origin(&headers));
+        headers.insert(header::ORIGIN, "http://localhost:1420".parse().unwrap());
+        assert!(is_local_origin(&headers));
+        headers.insert(header::ORIGIN, "https://evil.example".parse().unwrap());
+        assert!(!is_local_origin(&headers));
+    }
+}
@@ -0,0 +1,11 @@
+//! Model Context Protocol. We connect to the MCP servers the user added, and offer
+//! their tools to our agents. Our own tools and agents are offered to other MCP
+//! clients through the [`endpoint`].
+
+pub mod client;
+pub mod endpoint;
+pub mod protocol;
+pub mod tool;
+
+pub use client::{ClientEvent, McpClient};
+pub use tool::{McpRemoteTool, tool_name};
@@ -0,0 +1,339 @@
+//! JSON-RPC messages of the Model Context Protocol, and the parts of the MCP
+//! schema we use.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{AppError, Result};
+
+/// Revision of the protocol we ask for when initializing
+pub const PROTOCOL_VERSION: &str = "2025-03-26";
+/// Revisions we can talk, newest first. Tools work the same way in all of them.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(i64),
+    String(String),
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestId::Number(id) => write!(f, "{id}"),
+            RequestId::String(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+/// A message sent by either side. Requests carry an id and a method, notifications
+/// only a method, responses only an id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JsonRpcMessage {
+    Request(JsonRpcRequest),
+    Notification(JsonRpcNotification),
+    Response(JsonRpcResponse),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub id: RequestId,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    /// Missing when the request couldn't be parsed
+    pub id: Option<RequestId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcRequest {
+    pub fn new(id: RequestId, method: impl Into<String>, params: Option<Value>) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            method: method.into(),
+            params,
+        }
+    }
+}
+
+impl JsonRpcNotification {
+    pub fn new(method: impl Into<String>, params: Option<Value>) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.into(),
+            params,
+        }
+    }
+}
+
+impl JsonRpcResponse {
+    pub fn success(id: RequestId, result: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: Some(id),
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Option<RequestId>, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result: None,
+            error: Some(JsonRpcError {
+                code,
+                message: message.into(),
+                data: None,
+            }),
+        }
+    }
+
+    pub fn into_result(self) -> Result<Value> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(AppError::external_service(format!(
+                "MCP error {}: {}",
+                error.code, error.message
+            ))),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(AppError::external_service(
+                "MCP response has neither a result nor an error",
+            )),
+        }
+    }
+}
+
+impl From<JsonRpcRequest> for JsonRpcMessage {
+    fn from(request: JsonRpcRequest) -> Self {
+        JsonRpcMessage::Request(request)
+    }
+}
+
+impl From<JsonRpcNotification> for JsonRpcMessage {
+    fn from(notification: JsonRpcNotification) -> Self {
+        JsonRpcMessage::Notification(notification)
+    }
+}
+
+impl From<JsonRpcResponse> for JsonRpcMessage {
+    fn from(response: JsonRpcResponse) -> Self {
+        JsonRpcMessage::Response(response)
+    }
+}
+
+/// Parses a message, or a batch of them
+pub fn parse_messages(body: &[u8]) -> Result<Vec<JsonRpcMessage>> {
+    let body = body.trim_ascii_start();
+    if body.starts_with(b"[") {
+        Ok(serde_json::from_slice(body)?)
+    } else {
+        Ok(vec![serde_json::from_slice(body)?])
+    }
+}
+
+/// Name and version of a client or server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Implementation {
+    pub name: String,
+    pub version: String,
+}
+
+impl Implementation {
+    pub fn evo_pro() -> Self {
+        Self {
+            name: "evo-pro".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeParams {
+    pub protocol_version: String,
+    pub capabilities: Value,
+    pub client_info: Implementation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub protocol_version: String,
+    #[serde(default)]
+    pub capabilities: Value,
+    pub server_info: Implementation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the arguments
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListToolsResult {
+    pub tools: Vec<Tool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallToolParams {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallToolResult {
+    #[serde(default)]
+    pub content: Vec<Content>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_content: Option<Value>,
+    /// The tool failed, the content describes why
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+impl CallToolResult {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            content: vec![Content::Text { text: text.into() }],
+            ..Default::default()
+        }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self {
+            is_error: true,
+            ..Self::text(text)
+        }
+    }
+
+    /// The text parts of the content, one per line
+    pub fn joined_text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|content| match content {
+                Content::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Content {
+    Text {
+        text: String,
+    },
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+    Audio {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+    Resource {
+        resource: Value,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_messages() {
+        let messages = parse_messages(
+            br#"[
+                {"jsonrpc": "2.0", "id": 1, "method": "ping"},
+                {"jsonrpc": "2.0", "method": "notifications/tools/list_changed"},
+                {"jsonrpc": "2.0", "id": "a", "result": {}},
+                {"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": "Parse error"}}
+            ]"#,
+        )
+        .unwrap();
+        assert!(matches!(&messages[0], JsonRpcMessage::Request(r) if r.id == RequestId::Number(1)));
+        assert!(matches!(&messages[1], JsonRpcMessage::Notification(_)));
+        assert!(
+            matches!(&messages[2], JsonRpcMessage::Response(r) if r.id == Some(RequestId::String("a".into())))
+        );
+        let JsonRpcMessage::Response(error) = messages[3].clone() else {
+            panic!("Expected a response");
+        };
+        assert!(error.into_result().is_err());
+
+        let messages = parse_messages(br#"{"jsonrpc": "2.0", "id": 2, "result": {}}"#).unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn test_call_tool_result() {
+        let result: CallToolResult = serde_json::from_value(json!({
+            "content": [
+                {"type": "text", "text": "first"},
+                {"type": "image", "data": "aGk=", "mimeType": "image/png"},
+                {"type": "resource_link", "uri": "file:///a"},
+                {"type": "text", "text": "second"}
+            ],
+            "isError": true
+        }))
+        .unwrap();
+        assert!(result.is_error);
+        assert!(matches!(result.content[2], Content::Unknown));
+        assert_eq!(result.joined_text(), "first\nsecond");
+    }
+}
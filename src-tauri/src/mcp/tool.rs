@@ -0,0 +1,124 @@
+//! Tools of MCP servers, offered to agents through the [`ToolExecutorActor`].
+//!
+//! [`ToolExecutorActor`]: crate::actors::tools::ToolExecutorActor
+
+use std::{borrow::Cow, sync::Arc};
+
+use futures_util::future::BoxFuture;
+use schemars::{schema::RootSchema, schema_for};
+use serde_json::{Value, json};
+
+use crate::{
+    actors::tools::{ToolDefinition, ToolDyn},
+    entities::McpTool,
+    error::{AppError, Result},
+    mcp::client::McpClient,
+};
+
+/// Names of tools may only hold these characters, and be this long, for the
+/// providers to accept them
+const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// Name an agent knows a tool of a server by, unique across servers
+pub fn tool_name(server: &str, tool: &str) -> String {
+    let slug = |name: &str| -> String {
+        name.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    };
+    let mut name = format!("{}__{}", slug(server), slug(tool));
+    name.truncate(MAX_TOOL_NAME_LEN);
+    name
+}
+
+pub struct McpRemoteTool {
+    name: Cow<'static, str>,
+    /// Name of the tool on the server
+    remote_name: String,
+    description: String,
+    input_schema: Value,
+    client: Arc<McpClient>,
+}
+
+impl McpRemoteTool {
+    pub fn new(server: &str, tool: &McpTool, client: Arc<McpClient>) -> Self {
+        Self {
+            name: tool_name(server, &tool.name).into(),
+            remote_name: tool.name.clone(),
+            description: tool.description.clone().unwrap_or_default(),
+            input_schema: tool.input_schema.0.clone(),
+            client,
+        }
+    }
+}
+
+impl ToolDyn for McpRemoteTool {
+    fn name(&self) -> Cow<'static, str> {
+        self.name.clone()
+    }
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: self.name.clone(),
+            description: self.description.clone().into(),
+            params: serde_json::from_value::<RootSchema>(self.input_schema.clone())
+                .unwrap_or_else(|_| schema_for!(Value)),
+            returns: None,
+        }
+    }
+
+    fn call(&self, args: String) -> BoxFuture<Result<String, AppError>> {
+        Box::pin(async move {
+            let arguments = if args.trim().is_empty() {
+                json!({})
+            } else {
+                serde_json::from_str(&args)?
+            };
+            let result = self.client.call_tool(&self.remote_name, arguments).await?;
+            if result.is_error {
+                return Err(AppError::external_service(format!(
+                    "Tool {} failed: {}",
+                    self.name,
+                    result.joined_text()
+                )));
+            }
+            match result.structured_content {
+                Some(content) => Ok(serde_json::to_string(&content)?),
+                None => Ok(serde_json::to_string(&result.joined_text())?),
+            }
+        })
+    }
+
+    /// The schema the server declared, which may use parts of JSON schema that
+    /// don't survive the round trip through [`RootSchema`]
+    fn to_rig_tool(&self) -> rig::completion::ToolDefinition {
+        rig::completion::ToolDefinition {
+            name: self.name.to_string(),
+            description: self.description.clone(),
+            parameters: self.input_schema.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_name() {
+        assert_eq!(tool_name("files", "read_file"), "files__read_file");
+        assert_eq!(
+            tool_name("My Server", "web.search"),
+            "My_Server__web_search"
+        );
+        let long = tool_name(&"s".repeat(40), &"t".repeat(40));
+        assert_eq!(long.len(), MAX_TOOL_NAME_LEN);
+        assert!(long.starts_with(&"s".repeat(40)));
+    }
+}
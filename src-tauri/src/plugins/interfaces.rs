@@ -54,6 +54,62 @@ pub struct PluginMetadata {
     
     /// Whether this is a built-in plugin
     pub built_in: bool,
+    
+    /// Permissions the plugin requests
+    #[serde(default)]
+    pub permissions: Vec<PluginPermission>,
+    
+    /// Plugin signature
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// Plugin permission
+///
+/// A permission allows a plugin to access a resource of the host. Plugins
+/// request permissions in their metadata, and only the ones granted through
+/// the security validator are available to them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PluginPermission {
+    /// Access to a UI component
+    UI {
+        /// Component name
+        component: String,
+    },
+    
+    /// Access to the plugin's storage keys with a prefix
+    Storage {
+        /// Key prefix, empty for all keys
+        key_prefix: String,
+    },
+    
+    /// Access to files within a directory
+    FileSystem {
+        /// Directory path, relative to the plugin data directory unless absolute
+        path: String,
+    },
+    
+    /// Access to a network host and its subdomains
+    Network {
+        /// Host name
+        host: String,
+    },
+    
+    /// Execution of a process
+    Process {
+        /// Command name
+        command: String,
+    },
+    
+    /// Publishing and receiving events of a type
+    Events {
+        /// Event type
+        event_type: String,
+    },
+    
+    /// Registering tools for agents
+    Tools,
 }
 
 /// Plugin dependency
@@ -126,6 +182,9 @@ pub struct PluginContext {
     
     /// Plugin temporary directory
     pub temp_dir: PathBuf,
+    
+    /// Permissions granted to the plugin
+    pub permissions: Vec<PluginPermission>,
 }
 
 /// Core plugin trait that all plugins must implement
@@ -9,6 +9,7 @@ use async_trait::async_trait;
 use crate::error::{Error, ErrorKind, Result};
 
 use super::interfaces::{Plugin, PluginMetadata, PluginState, PluginType, PluginFactory};
+use super::wasm::{WasmLimits, WasmRuntime, WASM_ENTRY_FILE};
 
 /// Plugin loader for loading plugins from various sources
 pub struct PluginLoader {
@@ -17,6 +18,9 @@ pub struct PluginLoader {
     
     /// Plugin paths by ID
     plugin_paths: HashMap<String, PathBuf>,
+    
+    /// Runtime of WebAssembly plugins
+    wasm_runtime: WasmRuntime,
 }
 
 impl PluginLoader {
@@ -25,9 +29,16 @@ impl PluginLoader {
         Self {
             factories: HashMap::new(),
             plugin_paths: HashMap::new(),
+            wasm_runtime: WasmRuntime::new(WasmLimits::default())
+                .expect("Failed to create WebAssembly plugin runtime"),
         }
     }
     
+    /// Get the runtime of WebAssembly plugins
+    pub fn wasm_runtime(&self) -> &WasmRuntime {
+        &self.wasm_runtime
+    }
+    
     /// Register a plugin factory
     pub fn register_factory(&mut self, factory: Box<dyn PluginFactory>) -> Result<()> {
         let metadata = factory.metadata();
@@ -161,13 +172,19 @@ impl PluginLoader {
     }
     
     /// Load plugin metadata from a WebAssembly plugin
+    ///
+    /// The metadata of a plugin component is kept next to it, in a JSON file
+    /// with the same name, e.g. `search.json` for `search.wasm`.
     fn load_plugin_metadata_from_wasm(&self, path: &Path) -> Result<PluginMetadata> {
-        // In a real implementation, we would load the WebAssembly module and call a function
-        // to get the plugin metadata. For now, we'll just return an error.
-        Err(Error::new(
-            ErrorKind::NotImplemented,
-            "Loading metadata from WebAssembly plugins is not implemented yet"
-        ))
+        let manifest_path = path.with_extension("json");
+        if !manifest_path.exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                &format!("WebAssembly plugin {:?} has no manifest at {:?}", path, manifest_path)
+            ));
+        }
+        
+        self.load_plugin_metadata_from_json(&manifest_path)
     }
     
     /// Load plugin metadata from a JavaScript plugin
@@ -252,11 +269,16 @@ impl PluginLoader {
     
     /// Load a plugin from a directory
     async fn load_plugin_from_directory(&self, path: &Path, metadata: &PluginMetadata) -> Result<Box<dyn Plugin>> {
-        // In a real implementation, we would determine the plugin type from the directory
-        // contents and load it accordingly. For now, we'll just return an error.
+        // Check if the directory contains a WebAssembly component
+        let wasm_path = path.join(WASM_ENTRY_FILE);
+        if wasm_path.is_file() {
+            return self.load_plugin_from_wasm(&wasm_path, metadata).await;
+        }
+        
+        // Other plugin types can't be loaded from directories yet
         Err(Error::new(
             ErrorKind::NotImplemented,
-            "Loading plugins from directories is not implemented yet"
+            &format!("Plugin directory {:?} has no {} to load", path, WASM_ENTRY_FILE)
         ))
     }
     
@@ -270,14 +292,10 @@ impl PluginLoader {
         ))
     }
     
-    /// Load a plugin from a WebAssembly module
+    /// Load a plugin from a WebAssembly component
     async fn load_plugin_from_wasm(&self, path: &Path, metadata: &PluginMetadata) -> Result<Box<dyn Plugin>> {
-        // In a real implementation, we would load the WebAssembly module and create a plugin
-        // instance. For now, we'll just return an error.
-        Err(Error::new(
-            ErrorKind::NotImplemented,
-            "Loading WebAssembly plugins is not implemented yet"
-        ))
+        let plugin = self.wasm_runtime.load_plugin(path, metadata).await?;
+        Ok(Box::new(plugin))
     }
     
     /// Load a plugin from a JavaScript file
@@ -65,14 +65,18 @@ impl PluginManager {
         let data_dir = get_data_dir();
         plugin_dirs.push(data_dir.join("plugins"));
         
+        // Events dispatched by the host reach the WebAssembly plugins
+        let loader = PluginLoader::new();
+        let wasm_runtime: Arc<dyn PluginEventListener> = Arc::new(loader.wasm_runtime().clone());
+        
         Self {
             registry: PluginRegistry::new(),
-            loader: PluginLoader::new(),
-            security_manager: PluginSecurityManager::new(),
+            loader,
+            security_manager: PluginSecurityManager::default_manager(),
             capability_manager: CapabilityManager::new(),
             version_manager: VersionManager::new(),
             plugins: HashMap::new(),
-            event_listeners: Vec::new(),
+            event_listeners: vec![wasm_runtime],
             plugin_dirs,
             discovery_performed: false,
         }
@@ -99,6 +103,33 @@ impl PluginManager {
         Ok(())
     }
     
+    /// Register a plugin and the path to load it from
+    pub fn register_plugin_at(&mut self, metadata: PluginMetadata, path: PathBuf) -> Result<()> {
+        let plugin_id = metadata.id.clone();
+        self.register_plugin(metadata)?;
+        self.loader.register_plugin_path(&plugin_id, path);
+        
+        Ok(())
+    }
+    
+    /// Unregister a plugin that isn't loaded
+    pub fn unregister_plugin(&mut self, plugin_id: &str) -> Result<()> {
+        // Check if the plugin is still loaded
+        if self.plugins.contains_key(plugin_id) {
+            return Err(Error::new(
+                ErrorKind::InvalidState,
+                &format!("Plugin with ID {} must be unloaded before it's unregistered", plugin_id)
+            ));
+        }
+        
+        // Unregister the plugin
+        self.registry.unregister_plugin(plugin_id)?;
+        
+        tracing::info!("Unregistered plugin: {}", plugin_id);
+        
+        Ok(())
+    }
+    
     /// Load a plugin
     pub async fn load_plugin(&mut self, plugin_id: &str) -> Result<()> {
         // Check if the plugin is registered
@@ -207,22 +238,32 @@ impl PluginManager {
     
     /// Unload a plugin
     pub async fn unload_plugin(&mut self, plugin_id: &str) -> Result<()> {
-        // Check if the plugin is loaded
-        let mut plugin = self.plugins.remove(plugin_id).ok_or_else(|| {
-            Error::new(
-                ErrorKind::NotFound,
-                &format!("Plugin with ID {} is not loaded", plugin_id)
-            )
-        })?;
+        let mut plugin = self.take_plugin(plugin_id)?;
         
         // Unload the plugin
         plugin.unload().await?;
         
+        self.plugin_unloaded(plugin.as_ref())
+    }
+    
+    /// Take a loaded plugin out of the manager, so that it can be unloaded without
+    /// holding a lock on the manager. Call `plugin_unloaded` once it's unloaded.
+    pub fn take_plugin(&mut self, plugin_id: &str) -> Result<Box<dyn Plugin>> {
+        self.plugins.remove(plugin_id).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                &format!("Plugin with ID {} is not loaded", plugin_id)
+            )
+        })
+    }
+    
+    /// Finish unloading a plugin taken out with `take_plugin`
+    pub fn plugin_unloaded(&mut self, plugin: &dyn Plugin) -> Result<()> {
         // Unregister plugin capabilities
         let metadata = plugin.metadata();
         self.capability_manager.unregister_plugin_capabilities(&metadata.id)?;
         
-        tracing::info!("Unloaded plugin: {}", plugin_id);
+        tracing::info!("Unloaded plugin: {}", metadata.id);
         
         Ok(())
     }
@@ -242,6 +283,31 @@ impl PluginManager {
         self.registry.get_all_plugin_metadata()
     }
     
+    /// Check if a plugin is registered
+    pub fn is_plugin_registered(&self, plugin_id: &str) -> bool {
+        self.registry.is_registered(plugin_id)
+    }
+    
+    /// Get the metadata of a registered plugin
+    pub fn get_registered_plugin(&self, plugin_id: &str) -> Result<PluginMetadata> {
+        self.registry.get_plugin_metadata(plugin_id)
+    }
+    
+    /// Get the plugin security manager
+    pub fn security_manager(&self) -> &PluginSecurityManager {
+        &self.security_manager
+    }
+    
+    /// Get the plugin security manager for changes, e.g. to trust publishers
+    pub fn security_manager_mut(&mut self) -> &mut PluginSecurityManager {
+        &mut self.security_manager
+    }
+    
+    /// Replace the plugin security manager
+    pub fn set_security_manager(&mut self, security_manager: PluginSecurityManager) {
+        self.security_manager = security_manager;
+    }
+    
     /// Check if a plugin is loaded
     pub fn is_plugin_loaded(&self, plugin_id: &str) -> bool {
         self.plugins.contains_key(plugin_id)
@@ -319,8 +385,8 @@ impl PluginManager {
                 // Try to load the plugin metadata
                 match self.loader.load_plugin_metadata(&path) {
                     Ok(metadata) => {
-                        // Register the plugin
-                        if let Err(e) = self.register_plugin(metadata) {
+                        // Register the plugin and where to load it from
+                        if let Err(e) = self.register_plugin_at(metadata, path.clone()) {
                             tracing::warn!("Failed to register plugin at {:?}: {}", path, e);
                         }
                     },
@@ -378,6 +444,12 @@ impl PluginManager {
             )
         })?;
         
+        // Keep the requested permissions that were granted
+        let permissions = metadata.permissions.iter()
+            .filter(|permission| self.security_manager.has_permission(plugin_id, permission))
+            .cloned()
+            .collect();
+        
         // Create the plugin context
         let context = PluginContext {
             config: HashMap::new(), // In a real implementation, this would be populated with app config
@@ -385,6 +457,7 @@ impl PluginManager {
             data_dir,
             cache_dir,
             temp_dir,
+            permissions,
         };
         
         Ok(context)
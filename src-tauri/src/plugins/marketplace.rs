@@ -5,15 +5,35 @@
 //! plugin ratings and reviews.
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use reqwest::Client;
+use url::Url;
+use uuid::Uuid;
 use crate::error::{Error, ErrorKind, Result};
 use crate::utils::get_data_dir;
 
 use super::interfaces::{PluginMetadata, PluginDependency};
 use super::manager::PluginManager;
+use super::package::{
+    check_plugin_id, extract_package, verify_checksum, PackageSignature, MANIFEST_FILE,
+    MAX_PACKAGE_BYTES,
+};
+
+/// File a marketplace index is read from within a directory or under a URL
+pub const INDEX_FILE: &str = "index.json";
+
+/// Largest marketplace index that is downloaded
+const MAX_INDEX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Largest package signature that is downloaded
+const MAX_SIGNATURE_BYTES: u64 = 64 * 1024;
+
+/// Time a marketplace request may take
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
 
 /// Plugin marketplace source
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,9 +69,15 @@ pub struct MarketplaceEntry {
     /// Source ID
     pub source_id: String,
     
-    /// Download URL
+    /// Download URL or path of the package
     pub download_url: String,
     
+    /// URL or path of the package's detached signature
+    pub signature_url: String,
+    
+    /// SHA-256 hash of the package, hex encoded
+    pub sha256: String,
+    
     /// Plugin size in bytes
     pub size: u64,
     
@@ -114,6 +140,249 @@ pub struct PluginReview {
     pub version: String,
 }
 
+/// Marketplace index, listing the plugins of a source
+///
+/// The index of a source is the JSON file at its URL or path, or the
+/// `index.json` under its URL or within its directory. Mirrors can be plain
+/// directories holding the index, the packages and their signatures.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketplaceIndex {
+    /// Plugins in the index
+    pub plugins: Vec<MarketplaceIndexEntry>,
+}
+
+/// Plugin in a marketplace index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketplaceIndexEntry {
+    /// Plugin metadata
+    pub metadata: PluginMetadata,
+    
+    /// Package location, relative to the index or absolute
+    pub package: String,
+    
+    /// Detached signature location, relative to the index or absolute.
+    /// Defaults to the package location with `.sig` appended.
+    #[serde(default)]
+    pub signature: Option<String>,
+    
+    /// SHA-256 hash of the package, hex encoded
+    pub sha256: String,
+    
+    /// Package size in bytes
+    pub size: u64,
+    
+    /// Plugin release date
+    pub release_date: DateTime<Utc>,
+    
+    /// Plugin download count
+    #[serde(default)]
+    pub download_count: u64,
+    
+    /// Plugin average rating (0-5)
+    #[serde(default)]
+    pub average_rating: f32,
+    
+    /// Plugin rating count
+    #[serde(default)]
+    pub rating_count: u32,
+    
+    /// Plugin screenshots
+    #[serde(default)]
+    pub screenshots: Vec<String>,
+    
+    /// Plugin changelog
+    #[serde(default)]
+    pub changelog: Option<String>,
+    
+    /// Whether the plugin is featured
+    #[serde(default)]
+    pub featured: bool,
+    
+    /// Plugin categories
+    #[serde(default)]
+    pub categories: Vec<String>,
+    
+    /// Plugin tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl MarketplaceIndexEntry {
+    /// Turn the index entry into a marketplace entry, resolving its locations
+    /// against the index
+    pub fn into_entry(self, source_id: &str, index: &MarketplaceLocation) -> Result<MarketplaceEntry> {
+        // The plugin ID names its directory once installed
+        check_plugin_id(&self.metadata.id)?;
+        
+        // Resolve the package and its signature
+        let signature = self.signature.clone().unwrap_or_else(|| format!("{}.sig", self.package));
+        let download_url = index.join(&self.package)?.to_string();
+        let signature_url = index.join(&signature)?.to_string();
+        
+        Ok(MarketplaceEntry {
+            metadata: self.metadata,
+            source_id: source_id.to_string(),
+            download_url,
+            signature_url,
+            sha256: self.sha256,
+            size: self.size,
+            release_date: self.release_date,
+            download_count: self.download_count,
+            average_rating: self.average_rating,
+            rating_count: self.rating_count,
+            screenshots: self.screenshots,
+            changelog: self.changelog,
+            featured: self.featured,
+            categories: self.categories,
+            tags: self.tags,
+        })
+    }
+}
+
+/// Location of a marketplace index, package or signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarketplaceLocation {
+    /// HTTP(S) URL
+    Remote(Url),
+    
+    /// Local file, e.g. of a mirror
+    Local(PathBuf),
+}
+
+impl MarketplaceLocation {
+    /// Parse an HTTP(S) URL, a `file://` URL or a path
+    pub fn parse(location: &str) -> Result<Self> {
+        match Url::parse(location) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(Self::Remote(url)),
+            Ok(url) if url.scheme() == "file" => url.to_file_path().map(Self::Local).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidArgument,
+                    &format!("Invalid file URL: {}", location)
+                )
+            }),
+            // Anything else, including Windows paths, is a path
+            _ => Ok(Self::Local(PathBuf::from(location))),
+        }
+    }
+    
+    /// Get the location of the index of a source
+    pub fn index(source_url: &str) -> Result<Self> {
+        Ok(match Self::parse(source_url)? {
+            Self::Remote(url) if url.path().ends_with(".json") => Self::Remote(url),
+            Self::Remote(mut url) => {
+                // Join relative to the last path segment, not its parent
+                if !url.path().ends_with('/') {
+                    let path = format!("{}/", url.path());
+                    url.set_path(&path);
+                }
+                Self::Remote(url.join(INDEX_FILE).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidArgument,
+                        &format!("Invalid marketplace URL {}: {}", source_url, e)
+                    )
+                })?)
+            },
+            Self::Local(path) if path.is_dir() => Self::Local(path.join(INDEX_FILE)),
+            local => local,
+        })
+    }
+    
+    /// Resolve a location given in an index, relative to the index
+    pub fn join(&self, reference: &str) -> Result<Self> {
+        match self {
+            Self::Remote(url) => {
+                let joined = url.join(reference).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidArgument,
+                        &format!("Invalid location {} in marketplace index: {}", reference, e)
+                    )
+                })?;
+                
+                // Remote indexes can't point at local files
+                if !matches!(joined.scheme(), "http" | "https") {
+                    return Err(Error::new(
+                        ErrorKind::Security,
+                        &format!("Remote marketplace index refers to {}", joined)
+                    ));
+                }
+                
+                Ok(Self::Remote(joined))
+            },
+            Self::Local(path) => Ok(match Self::parse(reference)? {
+                Self::Local(relative) => {
+                    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                    Self::Local(dir.join(relative))
+                },
+                remote => remote,
+            }),
+        }
+    }
+    
+    /// Read the contents, up to a limit, reporting the bytes read so far
+    pub async fn read(&self, client: &Client, max_bytes: u64, mut progress: impl FnMut(u64)) -> Result<Vec<u8>> {
+        let too_large = || {
+            Error::new(
+                ErrorKind::Validation,
+                &format!("{} is larger than {} bytes", self, max_bytes)
+            )
+        };
+        
+        match self {
+            Self::Local(path) => {
+                let io_error = |e: std::io::Error| {
+                    Error::new(
+                        ErrorKind::IO,
+                        &format!("Failed to read {:?}: {}", path, e)
+                    )
+                };
+                
+                // Check the size before reading
+                if tokio::fs::metadata(path).await.map_err(io_error)?.len() > max_bytes {
+                    return Err(too_large());
+                }
+                
+                let bytes = tokio::fs::read(path).await.map_err(io_error)?;
+                progress(bytes.len() as u64);
+                Ok(bytes)
+            },
+            Self::Remote(url) => {
+                let download_error = |e: reqwest::Error| {
+                    Error::new(
+                        ErrorKind::IO,
+                        &format!("Failed to download {}: {}", url, e)
+                    )
+                };
+                let mut res = client.get(url.clone())
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(download_error)?;
+                
+                // Read the body, stopping at the limit
+                let mut bytes = Vec::new();
+                while let Some(chunk) = res.chunk().await.map_err(download_error)? {
+                    bytes.extend_from_slice(&chunk);
+                    if bytes.len() as u64 > max_bytes {
+                        return Err(too_large());
+                    }
+                    progress(bytes.len() as u64);
+                }
+                
+                Ok(bytes)
+            },
+        }
+    }
+}
+
+impl fmt::Display for MarketplaceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote(url) => write!(f, "{}", url),
+            Self::Local(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
 /// Plugin installation status
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstallationStatus {
@@ -144,9 +413,15 @@ pub struct MarketplaceManager {
     /// Plugin manager reference
     plugin_manager: Arc<Mutex<PluginManager>>,
     
-    /// Download directory
+    /// Download directory, where packages are staged while they're installed
     download_dir: PathBuf,
     
+    /// Directory plugins are installed into
+    plugin_dir: PathBuf,
+    
+    /// HTTP client for remote sources
+    client: Client,
+    
     /// Last refresh time
     last_refresh: Option<DateTime<Utc>>,
 }
@@ -154,18 +429,34 @@ pub struct MarketplaceManager {
 impl MarketplaceManager {
     /// Create a new marketplace manager
     pub fn new(plugin_manager: Arc<Mutex<PluginManager>>) -> Self {
-        // Create the download directory
+        // Plugins are installed where the plugin manager discovers them
         let download_dir = get_data_dir().join("downloads").join("plugins");
+        let plugin_dir = get_data_dir().join("plugins");
+        
+        Self::with_dirs(plugin_manager, download_dir, plugin_dir)
+    }
+    
+    /// Create a marketplace manager that stages packages in `download_dir` and
+    /// installs plugins into `plugin_dir`
+    pub fn with_dirs(plugin_manager: Arc<Mutex<PluginManager>>, download_dir: PathBuf, plugin_dir: PathBuf) -> Self {
+        // Create the download directory
         std::fs::create_dir_all(&download_dir).unwrap_or_else(|e| {
             tracing::warn!("Failed to create plugin download directory: {}", e);
         });
         
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        
         Self {
             sources: Vec::new(),
             entries: HashMap::new(),
             installation_statuses: HashMap::new(),
             plugin_manager,
             download_dir,
+            plugin_dir,
+            client,
             last_refresh: None,
         }
     }
@@ -252,8 +543,9 @@ impl MarketplaceManager {
         // Clear existing entries
         self.entries.clear();
         
-        // Refresh entries from each enabled source
-        for source in self.sources.iter().filter(|s| s.enabled) {
+        // Refresh entries from each enabled source, in order of priority
+        let sources: Vec<_> = self.sources.iter().filter(|s| s.enabled).cloned().collect();
+        for source in &sources {
             match self.refresh_source(source).await {
                 Ok(_) => {
                     tracing::info!("Refreshed marketplace entries from source: {}", source.name);
@@ -274,128 +566,26 @@ impl MarketplaceManager {
     
     /// Refresh marketplace entries from a specific source
     async fn refresh_source(&mut self, source: &MarketplaceSource) -> Result<()> {
-        // In a real implementation, this would make an HTTP request to the source URL
-        // to fetch the list of available plugins. For this example, we'll just create
-        // some dummy entries.
-        
-        // Simulate network delay
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        
-        // Create some dummy entries
-        if source.id == "official" {
-            // Add some official plugins
-            let entries = vec![
-                MarketplaceEntry {
-                    metadata: PluginMetadata {
-                        id: "com.example.markdown-editor".to_string(),
-                        name: "Markdown Editor".to_string(),
-                        version: "1.0.0".to_string(),
-                        description: Some("Advanced markdown editing capabilities".to_string()),
-                        author: Some("Evo Design Team".to_string()),
-                        homepage: Some("https://example.com/plugins/markdown-editor".to_string()),
-                        repository: Some("https://github.com/example/markdown-editor".to_string()),
-                        license: Some("MIT".to_string()),
-                        min_app_version: Some("1.0.0".to_string()),
-                        max_app_version: None,
-                        dependencies: Vec::new(),
-                        capabilities: vec!["editor".to_string(), "markdown".to_string()],
-                        config_schema: None,
-                        built_in: false,
-                    },
-                    source_id: source.id.clone(),
-                    download_url: "https://example.com/plugins/markdown-editor/download".to_string(),
-                    size: 1024 * 1024, // 1 MB
-                    release_date: Utc::now() - chrono::Duration::days(30),
-                    download_count: 5000,
-                    average_rating: 4.5,
-                    rating_count: 120,
-                    screenshots: vec![
-                        "https://example.com/plugins/markdown-editor/screenshot1.png".to_string(),
-                        "https://example.com/plugins/markdown-editor/screenshot2.png".to_string(),
-                    ],
-                    changelog: Some("Initial release".to_string()),
-                    featured: true,
-                    categories: vec!["Editor".to_string(), "Productivity".to_string()],
-                    tags: vec!["markdown".to_string(), "editor".to_string(), "text".to_string()],
-                },
-                MarketplaceEntry {
-                    metadata: PluginMetadata {
-                        id: "com.example.code-formatter".to_string(),
-                        name: "Code Formatter".to_string(),
-                        version: "1.2.0".to_string(),
-                        description: Some("Format code in various languages".to_string()),
-                        author: Some("Evo Design Team".to_string()),
-                        homepage: Some("https://example.com/plugins/code-formatter".to_string()),
-                        repository: Some("https://github.com/example/code-formatter".to_string()),
-                        license: Some("MIT".to_string()),
-                        min_app_version: Some("1.0.0".to_string()),
-                        max_app_version: None,
-                        dependencies: Vec::new(),
-                        capabilities: vec!["formatter".to_string(), "code".to_string()],
-                        config_schema: None,
-                        built_in: false,
-                    },
-                    source_id: source.id.clone(),
-                    download_url: "https://example.com/plugins/code-formatter/download".to_string(),
-                    size: 2 * 1024 * 1024, // 2 MB
-                    release_date: Utc::now() - chrono::Duration::days(15),
-                    download_count: 3500,
-                    average_rating: 4.2,
-                    rating_count: 85,
-                    screenshots: vec![
-                        "https://example.com/plugins/code-formatter/screenshot1.png".to_string(),
-                    ],
-                    changelog: Some("Added support for Rust and Go".to_string()),
-                    featured: false,
-                    categories: vec!["Development".to_string(), "Productivity".to_string()],
-                    tags: vec!["code".to_string(), "formatter".to_string(), "development".to_string()],
-                },
-            ];
-            
-            // Add entries to the cache
-            for entry in entries {
-                self.entries.insert(entry.metadata.id.clone(), entry);
-            }
-        } else if source.id == "community" {
-            // Add some community plugins
-            let entries = vec![
-                MarketplaceEntry {
-                    metadata: PluginMetadata {
-                        id: "com.community.theme-creator".to_string(),
-                        name: "Theme Creator".to_string(),
-                        version: "0.9.0".to_string(),
-                        description: Some("Create and share custom themes".to_string()),
-                        author: Some("Community Developer".to_string()),
-                        homepage: Some("https://example.com/plugins/theme-creator".to_string()),
-                        repository: Some("https://github.com/community/theme-creator".to_string()),
-                        license: Some("MIT".to_string()),
-                        min_app_version: Some("1.0.0".to_string()),
-                        max_app_version: None,
-                        dependencies: Vec::new(),
-                        capabilities: vec!["theme".to_string(), "ui".to_string()],
-                        config_schema: None,
-                        built_in: false,
-                    },
-                    source_id: source.id.clone(),
-                    download_url: "https://example.com/plugins/theme-creator/download".to_string(),
-                    size: 500 * 1024, // 500 KB
-                    release_date: Utc::now() - chrono::Duration::days(5),
-                    download_count: 1200,
-                    average_rating: 3.8,
-                    rating_count: 45,
-                    screenshots: vec![
-                        "https://example.com/plugins/theme-creator/screenshot1.png".to_string(),
-                    ],
-                    changelog: Some("Beta release".to_string()),
-                    featured: false,
-                    categories: vec!["UI".to_string(), "Customization".to_string()],
-                    tags: vec!["theme".to_string(), "ui".to_string(), "customization".to_string()],
+        // Read the index of the source
+        let location = MarketplaceLocation::index(&source.url)?;
+        let index = location.read(&self.client, MAX_INDEX_BYTES, |_| {}).await?;
+        let index: MarketplaceIndex = serde_json::from_slice(&index).map_err(|e| {
+            Error::new(
+                ErrorKind::Parse,
+                &format!("Failed to parse marketplace index {}: {}", location, e)
+            )
+        })?;
+        
+        // Add entries to the cache, keeping the ones of sources with higher priority
+        for index_entry in index.plugins {
+            let plugin_id = index_entry.metadata.id.clone();
+            match index_entry.into_entry(&source.id, &location) {
+                Ok(entry) => {
+                    self.entries.entry(plugin_id).or_insert(entry);
                 },
-            ];
-            
-            // Add entries to the cache
-            for entry in entries {
-                self.entries.insert(entry.metadata.id.clone(), entry);
+                Err(e) => {
+                    tracing::warn!("Skipping plugin {} of marketplace source {}: {}", plugin_id, source.name, e);
+                }
             }
         }
         
@@ -449,64 +639,16 @@ impl MarketplaceManager {
     
     /// Install a plugin from the marketplace
     pub async fn install_plugin(&mut self, plugin_id: &str) -> Result<()> {
-        // Get the marketplace entry
-        let entry = self.entries.get(plugin_id).ok_or_else(|| {
-            Error::new(
-                ErrorKind::NotFound,
-                &format!("Plugin with ID {} not found in marketplace", plugin_id)
-            )
-        })?;
-        
         // Check if the plugin is already installed
-        let plugin_manager = self.plugin_manager.lock().unwrap();
-        if plugin_manager.is_plugin_loaded(plugin_id) {
+        if self.plugin_manager.lock().unwrap().is_plugin_registered(plugin_id) {
             return Err(Error::new(
                 ErrorKind::AlreadyExists,
                 &format!("Plugin with ID {} is already installed", plugin_id)
             ));
         }
-        drop(plugin_manager);
-        
-        // Set installation status to downloading
-        self.installation_statuses.insert(plugin_id.to_string(), InstallationStatus::Downloading(0));
-        
-        // Create a download path
-        let download_path = self.download_dir.join(format!("{}.zip", plugin_id));
-        
-        // In a real implementation, this would download the plugin from the download URL
-        // For this example, we'll just simulate the download
-        
-        // Simulate download progress
-        for progress in (0..=100).step_by(10) {
-            self.installation_statuses.insert(
-                plugin_id.to_string(),
-                InstallationStatus::Downloading(progress as u8)
-            );
-            
-            // Simulate network delay
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-        }
-        
-        // Set installation status to installing
-        self.installation_statuses.insert(plugin_id.to_string(), InstallationStatus::Installing);
         
-        // In a real implementation, this would extract the plugin and install it
-        // For this example, we'll just simulate the installation
-        
-        // Simulate installation delay
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        
-        // Register the plugin with the plugin manager
-        let mut plugin_manager = self.plugin_manager.lock().unwrap();
-        plugin_manager.register_plugin(entry.metadata.clone())?;
-        
-        // Set installation status to complete
-        self.installation_statuses.insert(plugin_id.to_string(), InstallationStatus::Complete);
-        
-        // Increment download count (in a real implementation, this would be done on the server)
-        if let Some(entry) = self.entries.get_mut(plugin_id) {
-            entry.download_count += 1;
-        }
+        // Install the package
+        self.install_package(plugin_id, None).await?;
         
         tracing::info!("Installed plugin: {}", plugin_id);
         
@@ -515,19 +657,31 @@ impl MarketplaceManager {
     
     /// Uninstall a plugin
     pub async fn uninstall_plugin(&mut self, plugin_id: &str) -> Result<()> {
+        // The plugin ID names its directory
+        check_plugin_id(plugin_id)?;
+        
         // Check if the plugin is installed
-        let mut plugin_manager = self.plugin_manager.lock().unwrap();
-        if !plugin_manager.is_plugin_loaded(plugin_id) {
+        if !self.plugin_manager.lock().unwrap().is_plugin_registered(plugin_id) {
             return Err(Error::new(
                 ErrorKind::NotFound,
                 &format!("Plugin with ID {} is not installed", plugin_id)
             ));
         }
         
-        // Unload the plugin
-        plugin_manager.unload_plugin(plugin_id).await?;
+        // Unload and unregister the plugin
+        self.unload_if_loaded(plugin_id).await?;
+        self.plugin_manager.lock().unwrap().unregister_plugin(plugin_id)?;
         
-        // In a real implementation, this would also remove the plugin files
+        // Remove the plugin files
+        let plugin_path = self.plugin_dir.join(plugin_id);
+        if plugin_path.exists() {
+            std::fs::remove_dir_all(&plugin_path).map_err(|e| {
+                Error::new(
+                    ErrorKind::IO,
+                    &format!("Failed to remove plugin directory {:?}: {}", plugin_path, e)
+                )
+            })?;
+        }
         
         tracing::info!("Uninstalled plugin: {}", plugin_id);
         
@@ -537,25 +691,22 @@ impl MarketplaceManager {
     /// Update a plugin
     pub async fn update_plugin(&mut self, plugin_id: &str) -> Result<()> {
         // Get the marketplace entry
-        let entry = self.entries.get(plugin_id).ok_or_else(|| {
+        let entry = self.entries.get(plugin_id).cloned().ok_or_else(|| {
             Error::new(
                 ErrorKind::NotFound,
                 &format!("Plugin with ID {} not found in marketplace", plugin_id)
             )
         })?;
         
-        // Check if the plugin is installed
-        let plugin_manager = self.plugin_manager.lock().unwrap();
-        if !plugin_manager.is_plugin_loaded(plugin_id) {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                &format!("Plugin with ID {} is not installed", plugin_id)
-            ));
-        }
-        
         // Get the installed plugin metadata
-        let installed_metadata = plugin_manager.get_plugin(plugin_id).unwrap().metadata().clone();
-        drop(plugin_manager);
+        let installed_metadata = self.plugin_manager.lock().unwrap()
+            .get_registered_plugin(plugin_id)
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    &format!("Plugin with ID {} is not installed", plugin_id)
+                )
+            })?;
         
         // Check if an update is available
         if installed_metadata.version == entry.metadata.version {
@@ -565,17 +716,240 @@ impl MarketplaceManager {
             ));
         }
         
-        // Uninstall the old version
-        self.uninstall_plugin(plugin_id).await?;
-        
-        // Install the new version
-        self.install_plugin(plugin_id).await?;
+        // Install the new version in place of the old one
+        let installed_version = installed_metadata.version.clone();
+        self.install_package(plugin_id, Some(installed_metadata)).await?;
         
-        tracing::info!("Updated plugin: {} from {} to {}", plugin_id, installed_metadata.version, entry.metadata.version);
+        tracing::info!("Updated plugin: {} from {} to {}", plugin_id, installed_version, entry.metadata.version);
         
         Ok(())
     }
     
+    /// Download, verify and install the package of a plugin, replacing the
+    /// previously installed version if there is one
+    async fn install_package(&mut self, plugin_id: &str, previous: Option<PluginMetadata>) -> Result<()> {
+        // Get the marketplace entry
+        let entry = self.entries.get(plugin_id).cloned().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                &format!("Plugin with ID {} not found in marketplace", plugin_id)
+            )
+        })?;
+        
+        self.installation_statuses.insert(plugin_id.to_string(), InstallationStatus::Downloading(0));
+        
+        let result = self.download_and_install(&entry, previous).await;
+        
+        // Update the installation status
+        let status = match result {
+            Ok(_) => InstallationStatus::Complete,
+            Err(_) => InstallationStatus::Failed,
+        };
+        self.installation_statuses.insert(plugin_id.to_string(), status);
+        
+        // Update the download count
+        if result.is_ok() {
+            if let Some(entry) = self.entries.get_mut(plugin_id) {
+                entry.download_count += 1;
+            }
+        }
+        
+        result
+    }
+    
+    /// Download and verify a package, then install it
+    async fn download_and_install(&mut self, entry: &MarketplaceEntry, previous: Option<PluginMetadata>) -> Result<()> {
+        let plugin_id = entry.metadata.id.clone();
+        
+        // Download the package, reporting progress
+        let size = entry.size.max(1);
+        let statuses = &mut self.installation_statuses;
+        let package = MarketplaceLocation::parse(&entry.download_url)?
+            .read(&self.client, MAX_PACKAGE_BYTES, |read| {
+                let progress = (read * 100 / size).min(100) as u8;
+                statuses.insert(plugin_id.clone(), InstallationStatus::Downloading(progress));
+            })
+            .await?;
+        
+        // Download its detached signature
+        let signature = MarketplaceLocation::parse(&entry.signature_url)?
+            .read(&self.client, MAX_SIGNATURE_BYTES, |_| {})
+            .await?;
+        let signature: PackageSignature = serde_json::from_slice(&signature).map_err(|e| {
+            Error::new(
+                ErrorKind::Parse,
+                &format!("Failed to parse signature of plugin {}: {}", plugin_id, e)
+            )
+        })?;
+        
+        self.installation_statuses.insert(plugin_id.clone(), InstallationStatus::Installing);
+        
+        // Verify the package before anything is extracted
+        let digest = verify_checksum(&package, &entry.sha256)?;
+        self.plugin_manager.lock().unwrap()
+            .security_manager()
+            .verify_package_signature(&digest, &signature)?;
+        
+        // Extract it into a staging directory
+        let staging = self.download_dir.join(format!("{}-{}", plugin_id, Uuid::new_v4()));
+        let extract_dir = staging.clone();
+        let extracted = tokio::task::spawn_blocking(move || extract_package(&package, &extract_dir))
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidState,
+                    &format!("Failed to extract plugin {}: {}", plugin_id, e)
+                )
+            })?;
+        let result = match extracted {
+            Ok(metadata) => self.install_extracted(entry, metadata, &signature.public_key, &staging, previous).await,
+            Err(e) => Err(e),
+        };
+        
+        // The staging directory is only left behind by failures
+        if staging.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&staging) {
+                tracing::warn!("Failed to remove staging directory {:?}: {}", staging, e);
+            }
+        }
+        
+        result
+    }
+    
+    /// Move an extracted package into the plugin directory and register it,
+    /// restoring the previous version if anything fails
+    async fn install_extracted(
+        &mut self,
+        entry: &MarketplaceEntry,
+        mut metadata: PluginMetadata,
+        public_key: &str,
+        staging: &Path,
+        previous: Option<PluginMetadata>,
+    ) -> Result<()> {
+        // The manifest must describe the plugin that was listed
+        if metadata.id != entry.metadata.id || metadata.version != entry.metadata.version {
+            return Err(Error::new(
+                ErrorKind::Validation,
+                &format!(
+                    "Plugin package contains {} {} instead of {} {}",
+                    metadata.id, metadata.version, entry.metadata.id, entry.metadata.version
+                )
+            ));
+        }
+        
+        // Record the key the package was signed with, which is checked when
+        // the plugin is validated
+        metadata.signature = Some(public_key.to_string());
+        let manifest = serde_json::to_vec_pretty(&metadata).map_err(|e| {
+            Error::new(
+                ErrorKind::Parse,
+                &format!("Failed to serialize plugin manifest: {}", e)
+            )
+        })?;
+        std::fs::write(staging.join(MANIFEST_FILE), manifest).map_err(|e| {
+            Error::new(
+                ErrorKind::IO,
+                &format!("Failed to write plugin manifest: {}", e)
+            )
+        })?;
+        
+        let io_error = |e: std::io::Error| {
+            Error::new(
+                ErrorKind::IO,
+                &format!("Failed to install plugin {}: {}", entry.metadata.id, e)
+            )
+        };
+        let target = self.plugin_dir.join(&metadata.id);
+        let backup = staging.with_extension("previous");
+        
+        // Unload and unregister the previous version
+        if let Some(previous) = &previous {
+            self.unload_if_loaded(&previous.id).await?;
+        }
+        let mut plugin_manager = self.plugin_manager.lock().unwrap();
+        if let Some(previous) = &previous {
+            plugin_manager.unregister_plugin(&previous.id)?;
+        }
+        
+        // Move the previous version's files aside
+        let moved_aside = if target.exists() {
+            if previous.is_none() {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    &format!("Plugin directory {:?} already exists", target)
+                ));
+            }
+            std::fs::rename(&target, &backup).map_err(io_error)
+        } else {
+            Ok(())
+        };
+        
+        // Move the new version into place and register it
+        let moved_aside_ok = moved_aside.is_ok();
+        let result = moved_aside
+            .and_then(|_| std::fs::create_dir_all(&self.plugin_dir).map_err(io_error))
+            .and_then(|_| std::fs::rename(staging, &target).map_err(io_error))
+            .and_then(|_| plugin_manager.register_plugin_at(metadata, target.clone()));
+        
+        match result {
+            Ok(()) => {
+                // The previous version is no longer needed
+                if backup.exists() {
+                    if let Err(e) = std::fs::remove_dir_all(&backup) {
+                        tracing::warn!("Failed to remove previous version {:?}: {}", backup, e);
+                    }
+                }
+                
+                Ok(())
+            },
+            Err(e) => {
+                // Roll back to the previous version
+                let restore_backup = backup.exists();
+                if restore_backup {
+                    if target.exists() {
+                        if let Err(e) = std::fs::remove_dir_all(&target) {
+                            tracing::error!("Failed to remove partially installed plugin {:?}: {}", target, e);
+                        }
+                    }
+                    if let Err(e) = std::fs::rename(&backup, &target) {
+                        tracing::error!("Failed to restore previous version of plugin {}: {}", entry.metadata.id, e);
+                    }
+                } else if moved_aside_ok && target.exists() {
+                    if let Err(e) = std::fs::remove_dir_all(&target) {
+                        tracing::error!("Failed to remove partially installed plugin {:?}: {}", target, e);
+                    }
+                }
+                if let Some(previous) = previous {
+                    // A previous version installed elsewhere keeps its registered path
+                    let registered = if restore_backup {
+                        plugin_manager.register_plugin_at(previous, target)
+                    } else {
+                        plugin_manager.register_plugin(previous)
+                    };
+                    if let Err(e) = registered {
+                        tracing::error!("Failed to register previous version of plugin {}: {}", entry.metadata.id, e);
+                    }
+                }
+                
+                Err(e)
+            }
+        }
+    }
+    
+    /// Unload a plugin if it's loaded. The plugin manager isn't locked while the
+    /// plugin shuts down.
+    async fn unload_if_loaded(&self, plugin_id: &str) -> Result<()> {
+        let mut plugin = {
+            let mut plugin_manager = self.plugin_manager.lock().unwrap();
+            if !plugin_manager.is_plugin_loaded(plugin_id) {
+                return Ok(());
+            }
+            plugin_manager.take_plugin(plugin_id)?
+        };
+        plugin.unload().await?;
+        self.plugin_manager.lock().unwrap().plugin_unloaded(plugin.as_ref())
+    }
+    
     /// Get the installation status of a plugin
     pub fn get_installation_status(&self, plugin_id: &str) -> Option<InstallationStatus> {
         self.installation_statuses.get(plugin_id).copied()
@@ -712,6 +1086,34 @@ pub async fn refresh_marketplace() -> Result<()> {
     marketplace_manager.refresh().await
 }
 
+/// Get the keys of the trusted plugin publishers
+#[tauri::command]
+pub async fn get_trusted_publisher_keys() -> Result<Vec<String>> {
+    let plugin_manager = super::get_plugin_manager();
+    let plugin_manager = plugin_manager.lock().unwrap();
+    
+    Ok(plugin_manager.security_manager().trusted_publisher_keys())
+}
+
+/// Trust plugin packages signed with a publisher's key
+#[tauri::command]
+pub async fn add_trusted_publisher_key(public_key: String) -> Result<()> {
+    let plugin_manager = super::get_plugin_manager();
+    let mut plugin_manager = plugin_manager.lock().unwrap();
+    
+    plugin_manager.security_manager_mut().trust_publisher_key(&public_key)
+}
+
+/// Stop trusting plugin packages signed with a publisher's key. Plugins that
+/// are already installed stay installed.
+#[tauri::command]
+pub async fn remove_trusted_publisher_key(public_key: String) -> Result<()> {
+    let plugin_manager = super::get_plugin_manager();
+    let mut plugin_manager = plugin_manager.lock().unwrap();
+    
+    plugin_manager.security_manager_mut().distrust_publisher_key(&public_key)
+}
+
 /// Global marketplace manager instance
 static MARKETPLACE_MANAGER_INIT: std::sync::Once = std::sync::Once::new();
 static mut MARKETPLACE_MANAGER: Option<Arc<Mutex<MarketplaceManager>>> = None;
@@ -736,4 +1138,143 @@ pub fn get_marketplace_manager() -> Arc<Mutex<MarketplaceManager>> {
         
         MARKETPLACE_MANAGER.clone().unwrap()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use base64::{Engine as _, prelude::BASE64_STANDARD};
+    use flate2::{Compression, write::GzEncoder};
+    use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+    
+    use super::*;
+    use super::super::package::content_hash;
+    use super::super::security::{
+        DefaultPluginSecurityValidator, NoOpPluginSandbox, PluginSecurityManager, PluginSecurityValidator,
+        TRUSTED_KEYS_FILE,
+    };
+    
+    const PLUGIN_ID: &str = "com.example.search";
+    
+    const MANIFEST: &str = r#"{
+        "id": "com.example.search",
+        "name": "Search",
+        "version": "1.0.0",
+        "dependencies": [],
+        "capabilities": [],
+        "built_in": false,
+        "permissions": [{"type": "tools"}]
+    }"#;
+    
+    fn build_package() -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(MANIFEST.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, MANIFEST_FILE, MANIFEST.as_bytes()).unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+    
+    /// Publish a package signed with a new publisher key in a local mirror,
+    /// returning its signature
+    fn publish(mirror: &Path) -> PackageSignature {
+        let package = build_package();
+        let digest = verify_checksum(&package, &content_hash(&package)).unwrap();
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let signature = PackageSignature {
+            public_key: BASE64_STANDARD.encode(key_pair.public_key().as_ref()),
+            signature: BASE64_STANDARD.encode(key_pair.sign(&digest).as_ref()),
+        };
+        
+        let index = serde_json::json!({
+            "plugins": [{
+                "metadata": serde_json::from_str::<serde_json::Value>(MANIFEST).unwrap(),
+                "package": "search.evoplugin",
+                "sha256": content_hash(&package),
+                "size": package.len(),
+                "release_date": Utc::now(),
+            }]
+        });
+        std::fs::create_dir_all(mirror).unwrap();
+        std::fs::write(mirror.join("search.evoplugin"), &package).unwrap();
+        std::fs::write(mirror.join("search.evoplugin.sig"), serde_json::to_vec(&signature).unwrap()).unwrap();
+        std::fs::write(mirror.join(INDEX_FILE), serde_json::to_vec(&index).unwrap()).unwrap();
+        
+        signature
+    }
+    
+    #[tokio::test]
+    async fn test_install_from_trusted_publisher() {
+        let dir = std::env::temp_dir().join(format!("evo-marketplace-{}", Uuid::new_v4()));
+        let signature = publish(&dir.join("mirror"));
+        
+        // Start out trusting no publisher
+        let plugin_manager = Arc::new(Mutex::new(PluginManager::new()));
+        let validator = DefaultPluginSecurityValidator::with_trusted_keys_file(false, dir.join(TRUSTED_KEYS_FILE)).unwrap();
+        plugin_manager.lock().unwrap().set_security_manager(PluginSecurityManager::new(
+            Box::new(validator),
+            Box::new(NoOpPluginSandbox::new()),
+        ));
+        let mut marketplace = MarketplaceManager::with_dirs(
+            plugin_manager.clone(),
+            dir.join("downloads"),
+            dir.join("plugins"),
+        );
+        marketplace.add_source(MarketplaceSource {
+            id: "mirror".to_string(),
+            name: "Mirror".to_string(),
+            url: dir.join("mirror").display().to_string(),
+            description: None,
+            official: false,
+            enabled: true,
+            priority: 0,
+        }).unwrap();
+        marketplace.refresh().await.unwrap();
+        assert!(marketplace.get_entry(PLUGIN_ID).is_some());
+        
+        // Packages of untrusted publishers are refused
+        assert!(marketplace.install_plugin(PLUGIN_ID).await.is_err());
+        assert!(!plugin_manager.lock().unwrap().is_plugin_registered(PLUGIN_ID));
+        assert!(!dir.join("plugins").join(PLUGIN_ID).exists());
+        
+        // Once the publisher is trusted the package is installed
+        plugin_manager.lock().unwrap()
+            .security_manager_mut()
+            .trust_publisher_key(&signature.public_key)
+            .unwrap();
+        marketplace.install_plugin(PLUGIN_ID).await.unwrap();
+        let installed = plugin_manager.lock().unwrap().get_registered_plugin(PLUGIN_ID).unwrap();
+        assert_eq!(installed.signature.as_deref(), Some(signature.public_key.as_str()));
+        assert!(dir.join("plugins").join(PLUGIN_ID).join(MANIFEST_FILE).exists());
+        
+        // And the publisher stays trusted
+        let validator = DefaultPluginSecurityValidator::with_trusted_keys_file(false, dir.join(TRUSTED_KEYS_FILE)).unwrap();
+        assert_eq!(validator.trusted_publisher_keys(), vec![signature.public_key]);
+        
+        std::fs::remove_dir_all(dir).ok();
+    }
+    
+    #[test]
+    fn test_marketplace_location() {
+        // Remote sources hold their index under their URL
+        let index = MarketplaceLocation::index("https://plugins.example.com/v1").unwrap();
+        assert_eq!(index.to_string(), "https://plugins.example.com/v1/index.json");
+        let index = MarketplaceLocation::index("https://plugins.example.com/v1/stable.json").unwrap();
+        assert_eq!(index.to_string(), "https://plugins.example.com/v1/stable.json");
+        
+        // Packages resolve relative to the index, but never to local files
+        assert_eq!(
+            index.join("packages/search.evoplugin").unwrap().to_string(),
+            "https://plugins.example.com/v1/packages/search.evoplugin"
+        );
+        assert!(index.join("file:///etc/passwd").is_err());
+        
+        // Local mirrors resolve relative to the index file
+        let index = MarketplaceLocation::parse("file:///srv/mirror/index.json").unwrap();
+        assert_eq!(
+            index.join("search.evoplugin").unwrap(),
+            MarketplaceLocation::Local(PathBuf::from("/srv/mirror/search.evoplugin"))
+        );
+    }
+}
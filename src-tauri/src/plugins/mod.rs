@@ -12,6 +12,8 @@ mod registry;
 mod capabilities;
 mod versioning;
 mod marketplace;
+mod package;
+mod wasm;
 
 pub use interfaces::*;
 pub use manager::*;
@@ -21,6 +23,8 @@ pub use registry::*;
 pub use capabilities::*;
 pub use versioning::*;
 pub use marketplace::*;
+pub use package::*;
+pub use wasm::*;
 
 use std::sync::{Arc, Mutex, Once};
 use crate::error::Result;
@@ -0,0 +1,264 @@
+//! Plugin packages
+//!
+//! A plugin package (`.evoplugin`) is a gzipped tar archive holding the
+//! plugin's `manifest.json` at its root along with its files, e.g. the
+//! `plugin.wasm` of a WebAssembly plugin. Packages are published with the
+//! SHA-256 hash of the archive and a detached signature, a JSON
+//! [`PackageSignature`] made over that hash with the publisher's Ed25519 key.
+
+use std::path::Path;
+
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use flate2::read::GzDecoder;
+use ring::signature::{ED25519, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, ErrorKind, Result};
+
+use super::interfaces::PluginMetadata;
+
+/// File holding the plugin metadata at the root of a package
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Extension of plugin packages
+pub const PACKAGE_EXTENSION: &str = "evoplugin";
+
+/// Largest package that is downloaded
+pub const MAX_PACKAGE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Largest size the files of a package may add up to once extracted
+pub const MAX_UNPACKED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Detached signature of a plugin package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageSignature {
+    /// Ed25519 public key of the publisher, base64 encoded
+    pub public_key: String,
+
+    /// Ed25519 signature of the package's SHA-256 hash, base64 encoded
+    pub signature: String,
+}
+
+/// Get the SHA-256 hash of a package, hex encoded
+pub fn content_hash(package: &[u8]) -> String {
+    Sha256::digest(package)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Check a package against its published hash, returning the hash
+pub fn verify_checksum(package: &[u8], sha256: &str) -> Result<Vec<u8>> {
+    if !content_hash(package).eq_ignore_ascii_case(sha256.trim()) {
+        return Err(Error::new(
+            ErrorKind::Security,
+            "Plugin package doesn't match its published SHA-256 hash",
+        ));
+    }
+
+    Ok(Sha256::digest(package).to_vec())
+}
+
+/// Check that a signature was made over a package hash with its public key.
+/// Whether the key is trusted is up to the caller.
+pub fn verify_signature(digest: &[u8], signature: &PackageSignature) -> Result<()> {
+    let decode = |value: &str, what: &str| {
+        BASE64_STANDARD.decode(value).map_err(|e| {
+            Error::new(
+                ErrorKind::Parse,
+                &format!("Invalid {} of plugin package: {}", what, e),
+            )
+        })
+    };
+    let public_key = decode(&signature.public_key, "public key")?;
+    let signature = decode(&signature.signature, "signature")?;
+
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(digest, &signature)
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::Security,
+                "Plugin package signature doesn't match its hash",
+            )
+        })
+}
+
+/// Check that a plugin ID can name its directory
+pub fn check_plugin_id(plugin_id: &str) -> Result<()> {
+    let valid = !plugin_id.is_empty()
+        && !plugin_id.starts_with('.')
+        && plugin_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if !valid {
+        return Err(Error::new(
+            ErrorKind::Validation,
+            &format!("Invalid plugin ID: {:?}", plugin_id),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Extract a package into a directory, returning its metadata
+///
+/// Only files and directories are extracted, links and entries leading out of
+/// the directory are refused.
+pub fn extract_package(package: &[u8], dir: &Path) -> Result<PluginMetadata> {
+    let io_error = |e: std::io::Error| {
+        Error::new(
+            ErrorKind::IO,
+            &format!("Failed to extract plugin package: {}", e),
+        )
+    };
+    std::fs::create_dir_all(dir).map_err(io_error)?;
+
+    let mut archive = tar::Archive::new(GzDecoder::new(package));
+    let mut unpacked = 0u64;
+    for entry in archive.entries().map_err(io_error)? {
+        let mut entry = entry.map_err(io_error)?;
+        let path = entry
+            .path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+
+        // Links could point anywhere
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            return Err(Error::new(
+                ErrorKind::Security,
+                &format!("Plugin package entry {} isn't a file or directory", path),
+            ));
+        }
+
+        // Guard against archives that expand without bound
+        unpacked += entry.size();
+        if unpacked > MAX_UNPACKED_BYTES {
+            return Err(Error::new(
+                ErrorKind::Validation,
+                &format!(
+                    "Plugin package is larger than {} bytes extracted",
+                    MAX_UNPACKED_BYTES
+                ),
+            ));
+        }
+
+        // Entries leading out of the directory are skipped by tar
+        if !entry.unpack_in(dir).map_err(io_error)? {
+            return Err(Error::new(
+                ErrorKind::Security,
+                &format!("Plugin package entry {} leads out of the package", path),
+            ));
+        }
+    }
+
+    // Read the manifest
+    let manifest = std::fs::read(dir.join(MANIFEST_FILE)).map_err(|e| {
+        Error::new(
+            ErrorKind::Validation,
+            &format!("Plugin package has no readable {}: {}", MANIFEST_FILE, e),
+        )
+    })?;
+    let metadata: PluginMetadata = serde_json::from_slice(&manifest).map_err(|e| {
+        Error::new(
+            ErrorKind::Parse,
+            &format!("Failed to parse plugin package manifest: {}", e),
+        )
+    })?;
+    check_plugin_id(&metadata.id)?;
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{Compression, write::GzEncoder};
+    use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+    use uuid::Uuid;
+
+    use super::*;
+
+    const MANIFEST: &str = r#"{
+        "id": "com.example.search",
+        "name": "Search",
+        "version": "1.0.0",
+        "dependencies": [],
+        "capabilities": [],
+        "built_in": false,
+        "permissions": [{"type": "tools"}]
+    }"#;
+
+    fn build_package(files: &[(&str, &[u8])], link: Option<&str>) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        if let Some(target) = link {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, "escape", target).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn temp_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("evo-package-{}", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_verify_package() {
+        let package = build_package(&[(MANIFEST_FILE, MANIFEST.as_bytes())], None);
+
+        // The hash must match the published one
+        let digest = verify_checksum(&package, &content_hash(&package).to_uppercase()).unwrap();
+        assert!(verify_checksum(&package, &content_hash(b"other")).is_err());
+
+        // Sign the hash with a publisher key
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let signature = PackageSignature {
+            public_key: BASE64_STANDARD.encode(key_pair.public_key().as_ref()),
+            signature: BASE64_STANDARD.encode(key_pair.sign(&digest).as_ref()),
+        };
+        assert!(verify_signature(&digest, &signature).is_ok());
+
+        // Signatures of other packages don't match
+        let other = Sha256::digest(b"other").to_vec();
+        assert!(verify_signature(&other, &signature).is_err());
+    }
+
+    #[test]
+    fn test_extract_package() {
+        // Extract the manifest and the plugin's files
+        let dir = temp_dir();
+        let package = build_package(
+            &[
+                (MANIFEST_FILE, MANIFEST.as_bytes()),
+                ("plugin.wasm", b"\0asm"),
+            ],
+            None,
+        );
+        let metadata = extract_package(&package, &dir).unwrap();
+        assert_eq!(metadata.id, "com.example.search");
+        assert_eq!(std::fs::read(dir.join("plugin.wasm")).unwrap(), b"\0asm");
+
+        // Links are refused
+        let package = build_package(&[(MANIFEST_FILE, MANIFEST.as_bytes())], Some("/etc"));
+        assert!(extract_package(&package, &temp_dir()).is_err());
+
+        // So are packages without a manifest
+        let package = build_package(&[("plugin.wasm", b"\0asm")], None);
+        assert!(extract_package(&package, &temp_dir()).is_err());
+
+        assert!(check_plugin_id("com.example.search").is_ok());
+        assert!(check_plugin_id("../search").is_err());
+        assert!(check_plugin_id(".hidden").is_err());
+    }
+}
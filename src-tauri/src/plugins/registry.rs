@@ -151,6 +151,8 @@ mod tests {
             capabilities: vec!["test".to_string()],
             config_schema: None,
             built_in: false,
+            permissions: Vec::new(),
+            signature: None,
         }
     }
     
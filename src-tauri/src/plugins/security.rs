@@ -4,11 +4,20 @@
 //! sandboxing, signature validation, and permission enforcement.
 
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use async_trait::async_trait;
+use base64::{Engine as _, prelude::BASE64_STANDARD};
 use crate::error::{Error, ErrorKind, Result};
+use crate::utils::get_data_dir;
 
 use super::interfaces::{Plugin, PluginMetadata, PluginPermission};
+use super::package::{verify_signature, PackageSignature};
+
+/// File in the data directory holding the keys of trusted plugin publishers
+pub const TRUSTED_KEYS_FILE: &str = "trusted_plugin_keys.json";
+
+/// Length of an Ed25519 public key
+const PUBLIC_KEY_LEN: usize = 32;
 
 /// Plugin security validator
 ///
@@ -26,6 +35,37 @@ pub trait PluginSecurityValidator: Send + Sync {
     
     /// Revoke a permission from a plugin
     fn revoke_permission(&mut self, plugin_id: &str, permission: &PluginPermission) -> Result<()>;
+    
+    /// Verify the detached signature of a plugin package against its hash
+    fn verify_package_signature(&self, digest: &[u8], signature: &PackageSignature) -> Result<()> {
+        let _ = (digest, signature);
+        Err(Error::new(
+            ErrorKind::Security,
+            "Plugin package signatures can't be verified"
+        ))
+    }
+    
+    /// Trust packages signed with a publisher's base64 encoded Ed25519 public key
+    fn trust_publisher_key(&mut self, public_key: &str) -> Result<()> {
+        let _ = public_key;
+        Err(Error::new(
+            ErrorKind::InvalidOperation,
+            "Plugin publisher keys can't be trusted"
+        ))
+    }
+    
+    /// Stop trusting packages signed with a publisher's key
+    fn distrust_publisher_key(&mut self, public_key: &str) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::NotFound,
+            &format!("Plugin publisher key {} isn't trusted", public_key)
+        ))
+    }
+    
+    /// Get the keys of the trusted publishers
+    fn trusted_publisher_keys(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 /// Default plugin security validator
@@ -33,9 +73,13 @@ pub struct DefaultPluginSecurityValidator {
     /// Permissions granted to plugins
     permissions: HashMap<String, HashSet<PluginPermission>>,
     
-    /// Trusted plugin signatures
+    /// Trusted plugin signatures, the base64 encoded Ed25519 public keys of
+    /// trusted publishers
     trusted_signatures: HashSet<String>,
     
+    /// File the trusted publisher keys are persisted to
+    trusted_keys_file: Option<PathBuf>,
+    
     /// Whether to require signatures for all plugins
     require_signatures: bool,
 }
@@ -46,10 +90,67 @@ impl DefaultPluginSecurityValidator {
         Self {
             permissions: HashMap::new(),
             trusted_signatures: HashSet::new(),
+            trusted_keys_file: None,
             require_signatures,
         }
     }
     
+    /// Create a validator that persists the trusted publisher keys to a file,
+    /// trusting the keys already in it
+    pub fn with_trusted_keys_file(require_signatures: bool, path: PathBuf) -> Result<Self> {
+        let mut validator = Self::new(require_signatures);
+        
+        // Nothing has been trusted yet if the file doesn't exist
+        if path.exists() {
+            let keys = std::fs::read(&path).map_err(|e| {
+                Error::new(
+                    ErrorKind::IO,
+                    &format!("Failed to read trusted plugin keys from {:?}: {}", path, e)
+                )
+            })?;
+            let keys: Vec<String> = serde_json::from_slice(&keys).map_err(|e| {
+                Error::new(
+                    ErrorKind::Parse,
+                    &format!("Failed to parse trusted plugin keys from {:?}: {}", path, e)
+                )
+            })?;
+            validator.trusted_signatures.extend(keys);
+        }
+        
+        validator.trusted_keys_file = Some(path);
+        Ok(validator)
+    }
+    
+    /// Write the trusted publisher keys to their file, if there is one
+    fn save_trusted_keys(&self) -> Result<()> {
+        let Some(path) = &self.trusted_keys_file else {
+            return Ok(());
+        };
+        
+        let mut keys: Vec<_> = self.trusted_signatures.iter().collect();
+        keys.sort();
+        let keys = serde_json::to_vec_pretty(&keys).map_err(|e| {
+            Error::new(
+                ErrorKind::Parse,
+                &format!("Failed to serialize trusted plugin keys: {}", e)
+            )
+        })?;
+        
+        // Write a temporary file first so that a failed write keeps the old keys
+        let io_error = |e: std::io::Error| {
+            Error::new(
+                ErrorKind::IO,
+                &format!("Failed to write trusted plugin keys to {:?}: {}", path, e)
+            )
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, keys).map_err(io_error)?;
+        std::fs::rename(&temp_path, path).map_err(io_error)
+    }
+    
     /// Add a trusted signature
     pub fn add_trusted_signature(&mut self, signature: String) {
         self.trusted_signatures.insert(signature);
@@ -100,36 +201,23 @@ impl DefaultPluginSecurityValidator {
     fn validate_permissions(&self, metadata: &PluginMetadata) -> Result<()> {
         // Check if the plugin requests any dangerous permissions
         for permission in &metadata.permissions {
-            match permission {
-                PluginPermission::FileSystem { .. } => {
-                    // File system access is potentially dangerous
-                    // In a real implementation, we would check if the plugin is trusted
-                    // or if the user has explicitly granted this permission
-                    return Err(Error::new(
-                        ErrorKind::Security,
-                        &format!("Plugin {} requests dangerous file system permission", metadata.id)
-                    ));
-                },
-                PluginPermission::Network { .. } => {
-                    // Network access is potentially dangerous
-                    // In a real implementation, we would check if the plugin is trusted
-                    // or if the user has explicitly granted this permission
-                    return Err(Error::new(
-                        ErrorKind::Security,
-                        &format!("Plugin {} requests dangerous network permission", metadata.id)
-                    ));
-                },
-                PluginPermission::Process { .. } => {
-                    // Process execution is potentially dangerous
-                    // In a real implementation, we would check if the plugin is trusted
-                    // or if the user has explicitly granted this permission
-                    return Err(Error::new(
-                        ErrorKind::Security,
-                        &format!("Plugin {} requests dangerous process permission", metadata.id)
-                    ));
-                },
+            let kind = match permission {
+                // File system access is potentially dangerous
+                PluginPermission::FileSystem { .. } => "file system",
+                // Network access is potentially dangerous
+                PluginPermission::Network { .. } => "network",
+                // Process execution is potentially dangerous
+                PluginPermission::Process { .. } => "process",
                 // Other permissions are considered safe by default
-                _ => {}
+                _ => continue,
+            };
+            
+            // Dangerous permissions must have been explicitly granted
+            if !self.has_permission(&metadata.id, permission) {
+                return Err(Error::new(
+                    ErrorKind::Security,
+                    &format!("Plugin {} requests dangerous {} permission", metadata.id, kind)
+                ));
             }
         }
         
@@ -185,6 +273,67 @@ impl PluginSecurityValidator for DefaultPluginSecurityValidator {
             ))
         }
     }
+    
+    fn verify_package_signature(&self, digest: &[u8], signature: &PackageSignature) -> Result<()> {
+        // Check if the package was signed with a trusted key
+        if !self.trusted_signatures.contains(&signature.public_key) {
+            return Err(Error::new(
+                ErrorKind::Security,
+                &format!("Plugin package is signed with an untrusted key: {}", signature.public_key)
+            ));
+        }
+        
+        // Check the signature itself
+        verify_signature(digest, signature)
+    }
+    
+    fn trust_publisher_key(&mut self, public_key: &str) -> Result<()> {
+        // Only keys that signatures can be checked with are trusted
+        let public_key = public_key.trim();
+        let decoded = BASE64_STANDARD.decode(public_key).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidArgument,
+                &format!("Invalid plugin publisher key: {}", e)
+            )
+        })?;
+        if decoded.len() != PUBLIC_KEY_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                &format!("Plugin publisher key must be a {} byte Ed25519 public key", PUBLIC_KEY_LEN)
+            ));
+        }
+        
+        if self.trusted_signatures.insert(public_key.to_string()) {
+            if let Err(e) = self.save_trusted_keys() {
+                self.trusted_signatures.remove(public_key);
+                return Err(e);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn distrust_publisher_key(&mut self, public_key: &str) -> Result<()> {
+        if !self.trusted_signatures.remove(public_key) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                &format!("Plugin publisher key {} isn't trusted", public_key)
+            ));
+        }
+        
+        if let Err(e) = self.save_trusted_keys() {
+            self.trusted_signatures.insert(public_key.to_string());
+            return Err(e);
+        }
+        
+        Ok(())
+    }
+    
+    fn trusted_publisher_keys(&self) -> Vec<String> {
+        let mut keys: Vec<_> = self.trusted_signatures.iter().cloned().collect();
+        keys.sort();
+        keys
+    }
 }
 
 impl Default for DefaultPluginSecurityValidator {
@@ -276,10 +425,19 @@ impl PluginSecurityManager {
         }
     }
     
-    /// Create a default plugin security manager
+    /// Create a default plugin security manager, trusting the publisher keys
+    /// persisted in the data directory
     pub fn default_manager() -> Self {
+        let path = get_data_dir().join(TRUSTED_KEYS_FILE);
+        let validator = DefaultPluginSecurityValidator::with_trusted_keys_file(false, path)
+            .unwrap_or_else(|e| {
+                // Don't overwrite keys that couldn't be read
+                tracing::warn!("Failed to load trusted plugin keys, trusting none: {}", e);
+                DefaultPluginSecurityValidator::default()
+            });
+        
         Self {
-            validator: Box::new(DefaultPluginSecurityValidator::default()),
+            validator: Box::new(validator),
             sandbox: Box::new(NoOpPluginSandbox::new()),
         }
     }
@@ -304,6 +462,26 @@ impl PluginSecurityManager {
         self.validator.revoke_permission(plugin_id, permission)
     }
     
+    /// Verify the detached signature of a plugin package against its hash
+    pub fn verify_package_signature(&self, digest: &[u8], signature: &PackageSignature) -> Result<()> {
+        self.validator.verify_package_signature(digest, signature)
+    }
+    
+    /// Trust packages signed with a publisher's key
+    pub fn trust_publisher_key(&mut self, public_key: &str) -> Result<()> {
+        self.validator.trust_publisher_key(public_key)
+    }
+    
+    /// Stop trusting packages signed with a publisher's key
+    pub fn distrust_publisher_key(&mut self, public_key: &str) -> Result<()> {
+        self.validator.distrust_publisher_key(public_key)
+    }
+    
+    /// Get the keys of the trusted publishers
+    pub fn trusted_publisher_keys(&self) -> Vec<String> {
+        self.validator.trusted_publisher_keys()
+    }
+    
     /// Create a sandbox for a plugin
     pub async fn create_sandbox(&self, metadata: &PluginMetadata) -> Result<Box<dyn PluginSandboxInstance>> {
         self.sandbox.create_sandbox(metadata).await
@@ -452,6 +630,30 @@ mod tests {
         assert!(validator.validate_plugin(&metadata_with_network, None).await.is_err());
     }
     
+    #[test]
+    fn test_trusted_publisher_keys_are_persisted() {
+        let path = std::env::temp_dir().join(format!("evo-trusted-keys-{}.json", uuid::Uuid::new_v4()));
+        let key = BASE64_STANDARD.encode([7u8; PUBLIC_KEY_LEN]);
+        
+        // Only Ed25519 public keys can be trusted
+        let mut validator = DefaultPluginSecurityValidator::with_trusted_keys_file(false, path.clone()).unwrap();
+        assert!(validator.trust_publisher_key("not a key").is_err());
+        assert!(validator.trust_publisher_key(&BASE64_STANDARD.encode([7u8; 16])).is_err());
+        assert!(validator.trust_publisher_key(&key).is_ok());
+        
+        // The keys are trusted again once the file is read
+        let mut validator = DefaultPluginSecurityValidator::with_trusted_keys_file(false, path.clone()).unwrap();
+        assert_eq!(validator.trusted_publisher_keys(), vec![key.clone()]);
+        
+        // Removing a key removes it from the file
+        assert!(validator.distrust_publisher_key(&key).is_ok());
+        assert!(validator.distrust_publisher_key(&key).is_err());
+        let validator = DefaultPluginSecurityValidator::with_trusted_keys_file(false, path.clone()).unwrap();
+        assert!(validator.trusted_publisher_keys().is_empty());
+        
+        std::fs::remove_file(path).ok();
+    }
+    
     #[tokio::test]
     async fn test_noop_sandbox() {
         // Create a sandbox
@@ -0,0 +1,1374 @@
+//! WebAssembly plugin runtime
+//!
+//! This module runs plugins built as WebAssembly components with the plugin
+//! SDK (`plugin-sdk`). Each plugin runs in its own sandboxed store with WASI
+//! but no preopened directories, sockets or environment, and only gets the
+//! host interfaces (storage, network, filesystem, events and tools) for the
+//! permissions it was granted and the capabilities the host offers. Every call
+//! into a plugin is limited in fuel, memory and time.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use kameo::prelude::ActorRef as LocalActorRef;
+use reqwest::{Client, Method, redirect};
+use schemars::{schema::RootSchema, schema_for};
+use serde_json::Value;
+use tokio::sync::{Mutex, broadcast};
+use tokio::task::JoinHandle;
+use url::Url;
+use wasmtime::component::{Component, Linker, ResourceTable};
+use wasmtime::{Config, Engine, Store, StoreLimits, StoreLimitsBuilder, Trap};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiView};
+
+use crate::actors::tools::{
+    RegisterTools, ToolDefinition, ToolDyn, ToolExecutorActor, UnregisterTools,
+};
+use crate::error::{AppError, Error, ErrorKind, Result};
+use crate::mcp::tool::tool_name;
+use crate::tools::{http::is_allowed, sandbox::Sandbox};
+
+use super::capabilities::{Capability, CapabilityNegotiator, CapabilityRegistry, standard};
+use super::interfaces::{
+    Plugin, PluginContext, PluginEvent, PluginEventListener, PluginMetadata, PluginPermission,
+    PluginState, PluginType,
+};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "plugin-sdk/wit",
+        world: "evo-plugin",
+        async: true,
+    });
+}
+
+use bindings::EvoPlugin;
+use bindings::evo::plugin::{events, filesystem, network, storage, tools};
+
+/// Package of the host interfaces, defined in `plugin-sdk/wit/plugin.wit`
+const INTERFACE_PACKAGE: &str = "evo:plugin";
+
+/// File a plugin directory holds its component in, next to its `manifest.json`
+pub const WASM_ENTRY_FILE: &str = "plugin.wasm";
+
+/// File the storage of a plugin is kept in, within its data directory
+const STORAGE_FILE: &str = "storage.json";
+
+/// Fuel a plugin runs on before yielding to other tasks, so calls can time out
+const FUEL_YIELD_INTERVAL: u64 = 100_000;
+
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// Events buffered for plugins that are slow to handle them
+const EVENT_BUFFER: usize = 256;
+
+/// Tool executor that tools registered by plugins are offered to agents through
+pub static PLUGIN_TOOL_EXECUTOR: OnceLock<LocalActorRef<ToolExecutorActor>> = OnceLock::new();
+
+/// Limits of WebAssembly plugins
+#[derive(Debug, Clone)]
+pub struct WasmLimits {
+    /// Fuel each call into a plugin gets, roughly the instructions it may run
+    pub fuel: u64,
+
+    /// Bytes of linear memory a plugin may grow to
+    pub max_memory_bytes: usize,
+
+    /// Time a call into a plugin may take, including its calls to the host
+    pub call_timeout: Duration,
+
+    /// Bytes of a response body or file a plugin may read
+    pub max_transfer_bytes: usize,
+
+    /// Bytes a plugin may keep in its storage
+    pub max_storage_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 1_000_000_000,
+            max_memory_bytes: 64 * 1024 * 1024,
+            call_timeout: Duration::from_secs(30),
+            max_transfer_bytes: 4 * 1024 * 1024,
+            max_storage_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Host interface a plugin can import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostInterface {
+    /// Key-value storage
+    Storage,
+
+    /// HTTP requests
+    Network,
+
+    /// Files within granted directories
+    FileSystem,
+
+    /// Publishing events
+    Events,
+
+    /// Registering tools for agents
+    Tools,
+}
+
+impl HostInterface {
+    /// All host interfaces
+    pub const ALL: [HostInterface; 5] = [
+        HostInterface::Storage,
+        HostInterface::Network,
+        HostInterface::FileSystem,
+        HostInterface::Events,
+        HostInterface::Tools,
+    ];
+
+    /// Name of the interface in the WIT package
+    pub fn name(&self) -> &'static str {
+        match self {
+            HostInterface::Storage => "storage",
+            HostInterface::Network => "network",
+            HostInterface::FileSystem => "filesystem",
+            HostInterface::Events => "events",
+            HostInterface::Tools => "tools",
+        }
+    }
+
+    /// Get the host interface a component import refers to, e.g.
+    /// `evo:plugin/storage@0.1.0`
+    pub fn from_import(name: &str) -> Option<Self> {
+        let interface = name.strip_prefix(INTERFACE_PACKAGE)?.strip_prefix('/')?;
+        let interface = interface.split('@').next()?;
+        Self::ALL
+            .into_iter()
+            .find(|host_interface| host_interface.name() == interface)
+    }
+
+    /// Capability the host must offer for the interface
+    pub fn capability(&self) -> Capability {
+        match self {
+            HostInterface::Storage => standard::storage("1.0.0"),
+            HostInterface::Network => standard::network("1.0.0"),
+            HostInterface::FileSystem => standard::filesystem("1.0.0"),
+            HostInterface::Events => Capability::new("event", "1.0.0"),
+            HostInterface::Tools => standard::api("1.0.0", "tools"),
+        }
+    }
+}
+
+/// What a WebAssembly plugin was granted, collected from its permissions
+#[derive(Debug, Clone, Default)]
+pub struct WasmGrants {
+    /// Storage key prefixes
+    pub key_prefixes: Vec<String>,
+
+    /// Network hosts, each including its subdomains
+    pub hosts: Vec<String>,
+
+    /// Directories within the plugin data directory, relative to it
+    pub paths: Vec<String>,
+
+    /// Event types
+    pub event_types: Vec<String>,
+
+    /// Whether tools may be registered
+    pub tools: bool,
+}
+
+/// Check that a granted directory stays within the plugin data directory, which
+/// absolute paths and `..` would lead out of
+fn check_granted_path(plugin_id: &str, path: &str) -> Result<()> {
+    let within = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !within {
+        return Err(Error::new(
+            ErrorKind::Security,
+            &format!(
+                "Plugin {} requests directory {}, which is outside of its data directory",
+                plugin_id, path
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+impl WasmGrants {
+    /// Collect the grants of permissions, failing for permissions WebAssembly
+    /// plugins can't be given
+    pub fn from_permissions(plugin_id: &str, permissions: &[PluginPermission]) -> Result<Self> {
+        let mut grants = Self::default();
+        for permission in permissions {
+            match permission {
+                PluginPermission::Storage { key_prefix } => {
+                    grants.key_prefixes.push(key_prefix.clone())
+                }
+                PluginPermission::Network { host } => grants.hosts.push(host.clone()),
+                PluginPermission::FileSystem { path } => {
+                    check_granted_path(plugin_id, path)?;
+                    grants.paths.push(path.clone())
+                }
+                PluginPermission::Events { event_type } => {
+                    grants.event_types.push(event_type.clone())
+                }
+                PluginPermission::Tools => grants.tools = true,
+                PluginPermission::UI { .. } | PluginPermission::Process { .. } => {
+                    return Err(Error::new(
+                        ErrorKind::Capability,
+                        &format!(
+                            "Plugin {} requests {:?}, which WebAssembly plugins can't be given",
+                            plugin_id, permission
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(grants)
+    }
+
+    /// Get the host interfaces that are linked for the grants
+    pub fn interfaces(&self) -> HashSet<HostInterface> {
+        let mut interfaces = HashSet::new();
+        if !self.key_prefixes.is_empty() {
+            interfaces.insert(HostInterface::Storage);
+        }
+        if !self.hosts.is_empty() {
+            interfaces.insert(HostInterface::Network);
+        }
+        if !self.paths.is_empty() {
+            interfaces.insert(HostInterface::FileSystem);
+        }
+        if !self.event_types.is_empty() {
+            interfaces.insert(HostInterface::Events);
+        }
+        if self.tools {
+            interfaces.insert(HostInterface::Tools);
+        }
+        interfaces
+    }
+
+    /// Check if a storage key starts with a granted prefix
+    pub fn allows_key(&self, key: &str) -> bool {
+        self.key_prefixes
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_str()))
+    }
+}
+
+/// Check that a component only imports the host interfaces it was granted
+pub fn check_imports<'a>(
+    plugin_id: &str,
+    imports: impl IntoIterator<Item = &'a str>,
+    interfaces: &HashSet<HostInterface>,
+) -> Result<()> {
+    for import in imports {
+        // Other imports, e.g. of WASI, are left to the linker
+        let Some(interface) = HostInterface::from_import(import) else {
+            continue;
+        };
+
+        if !interfaces.contains(&interface) {
+            return Err(Error::new(
+                ErrorKind::Security,
+                &format!(
+                    "Plugin {} imports {} without being granted a {} permission",
+                    plugin_id,
+                    import,
+                    interface.name()
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// WebAssembly plugin runtime
+///
+/// The runtime compiles plugin components and holds what all its plugins
+/// share: the engine, the capabilities offered to them and their events.
+#[derive(Clone)]
+pub struct WasmRuntime {
+    /// Engine compiling and running the components
+    engine: Engine,
+
+    /// Negotiator of the capabilities offered to plugins
+    negotiator: Arc<CapabilityNegotiator>,
+
+    /// Limits of every plugin
+    limits: WasmLimits,
+
+    /// Events published by plugins and the host
+    events: broadcast::Sender<PluginEvent>,
+}
+
+impl WasmRuntime {
+    /// Create a new runtime offering all host interfaces
+    pub fn new(limits: WasmLimits) -> Result<Self> {
+        // Register the capabilities of the host interfaces
+        let mut registry = CapabilityRegistry::new();
+        for interface in HostInterface::ALL {
+            registry.register_capability(interface.capability())?;
+        }
+
+        Self::with_capabilities(limits, registry)
+    }
+
+    /// Create a new runtime offering the host interfaces of the capabilities
+    /// in a registry
+    pub fn with_capabilities(limits: WasmLimits, registry: CapabilityRegistry) -> Result<Self> {
+        // Configure the engine for async components metered with fuel
+        let mut config = Config::new();
+        config
+            .wasm_component_model(true)
+            .async_support(true)
+            .consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidState,
+                &format!("Failed to create WebAssembly engine: {}", e),
+            )
+        })?;
+
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+
+        Ok(Self {
+            engine,
+            negotiator: Arc::new(CapabilityNegotiator::new(registry)),
+            limits,
+            events,
+        })
+    }
+
+    /// Get the limits of every plugin
+    pub fn limits(&self) -> &WasmLimits {
+        &self.limits
+    }
+
+    /// Get the negotiator of the capabilities offered to plugins
+    pub fn negotiator(&self) -> &CapabilityNegotiator {
+        &self.negotiator
+    }
+
+    /// Subscribe to the events published by plugins and the host
+    pub fn subscribe(&self) -> broadcast::Receiver<PluginEvent> {
+        self.events.subscribe()
+    }
+
+    /// Compile a plugin component
+    pub async fn load_plugin(&self, path: &Path, metadata: &PluginMetadata) -> Result<WasmPlugin> {
+        // Compiling is CPU bound, so it's kept off the async workers
+        let engine = self.engine.clone();
+        let component_path = path.to_path_buf();
+        let component =
+            tokio::task::spawn_blocking(move || Component::from_file(&engine, component_path))
+                .await
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidState,
+                        &format!("Failed to compile plugin {}: {}", metadata.id, e),
+                    )
+                })?
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::Parse,
+                        &format!(
+                            "Failed to compile plugin {} from {:?}: {}",
+                            metadata.id, path, e
+                        ),
+                    )
+                })?;
+
+        Ok(WasmPlugin {
+            metadata: metadata.clone(),
+            runtime: self.clone(),
+            component,
+            state: PluginState::Loaded,
+            grants: WasmGrants::default(),
+            instance: None,
+            event_task: None,
+        })
+    }
+
+    /// Create a linker with WASI and the host interfaces of the grants
+    fn linker(&self, interfaces: &HashSet<HostInterface>) -> Result<Linker<HostState>> {
+        let mut linker = Linker::new(&self.engine);
+        let link_error = |e: wasmtime::Error| {
+            Error::new(
+                ErrorKind::InvalidState,
+                &format!("Failed to link host interfaces: {}", e),
+            )
+        };
+
+        // WASI is sandboxed by its context, see `HostState::new`
+        wasmtime_wasi::add_to_linker_async(&mut linker).map_err(link_error)?;
+
+        // Only the granted interfaces exist for the plugin
+        for interface in interfaces {
+            match interface {
+                HostInterface::Storage => {
+                    storage::add_to_linker(&mut linker, |state: &mut HostState| state)
+                }
+                HostInterface::Network => {
+                    network::add_to_linker(&mut linker, |state: &mut HostState| state)
+                }
+                HostInterface::FileSystem => {
+                    filesystem::add_to_linker(&mut linker, |state: &mut HostState| state)
+                }
+                HostInterface::Events => {
+                    events::add_to_linker(&mut linker, |state: &mut HostState| state)
+                }
+                HostInterface::Tools => {
+                    tools::add_to_linker(&mut linker, |state: &mut HostState| state)
+                }
+            }
+            .map_err(link_error)?;
+        }
+
+        Ok(linker)
+    }
+}
+
+#[async_trait]
+impl PluginEventListener for WasmRuntime {
+    /// Pass events dispatched by the host on to the plugins
+    async fn handle_event(&self, event: &PluginEvent) -> Result<()> {
+        // Nobody listening is fine
+        let _ = self.events.send(event.clone());
+        Ok(())
+    }
+}
+
+/// Definition of a tool registered by a plugin
+#[derive(Debug, Clone)]
+struct PluginToolDefinition {
+    name: String,
+    description: String,
+    parameters: Value,
+}
+
+/// Storage of a plugin, kept as JSON in its data directory
+struct PluginStorage {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl PluginStorage {
+    /// Open the storage in a data directory
+    async fn open(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(STORAGE_FILE);
+        let values = match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                Error::new(
+                    ErrorKind::Parse,
+                    &format!("Failed to parse plugin storage {:?}: {}", path, e),
+                )
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(Error::new(
+                    ErrorKind::IO,
+                    &format!("Failed to read plugin storage {:?}: {}", path, e),
+                ));
+            }
+        };
+
+        Ok(Self { path, values })
+    }
+
+    /// Get the bytes the values take
+    fn size(&self) -> usize {
+        self.values
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum()
+    }
+
+    async fn save(&self) -> std::result::Result<(), String> {
+        let bytes = serde_json::to_vec(&self.values).map_err(|e| e.to_string())?;
+        tokio::fs::write(&self.path, bytes)
+            .await
+            .map_err(|e| format!("Failed to save storage: {}", e))
+    }
+}
+
+/// State of the store a plugin runs in, backing its host interfaces
+struct HostState {
+    /// Plugin ID
+    plugin_id: String,
+
+    /// What the plugin was granted
+    grants: WasmGrants,
+
+    /// WASI context, without directories, sockets or environment
+    wasi: WasiCtx,
+
+    /// Resources of WASI
+    table: ResourceTable,
+
+    /// Memory limits
+    limits: StoreLimits,
+
+    /// Byte limits
+    max_transfer_bytes: usize,
+    max_storage_bytes: usize,
+
+    /// Storage of the plugin
+    storage: PluginStorage,
+
+    /// Granted directories
+    sandboxes: Vec<Sandbox>,
+
+    /// HTTP client staying on the granted hosts
+    client: Client,
+
+    /// Events published by plugins and the host
+    events: broadcast::Sender<PluginEvent>,
+
+    /// Tools registered by the plugin
+    tools: Vec<PluginToolDefinition>,
+}
+
+impl HostState {
+    /// Create the state of a plugin's store
+    async fn new(
+        plugin_id: &str,
+        grants: WasmGrants,
+        context: &PluginContext,
+        runtime: &WasmRuntime,
+    ) -> Result<Self> {
+        let limits = &runtime.limits;
+
+        // Granted directories are relative to the plugin data directory, which
+        // they were checked to stay within
+        let mut sandboxes = Vec::new();
+        for path in &grants.paths {
+            let sandbox = Sandbox::new(context.data_dir.join(path)).map_err(|e| {
+                Error::new(
+                    ErrorKind::IO,
+                    &format!(
+                        "Failed to open directory {} for plugin {}: {}",
+                        path, plugin_id, e
+                    ),
+                )
+            })?;
+            sandboxes.push(sandbox);
+        }
+
+        // Redirects must stay on the granted hosts too
+        let hosts = grants.hosts.clone();
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("Too many redirects")
+            } else if is_allowed(attempt.url(), &hosts) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        });
+        let client = Client::builder()
+            .redirect(policy)
+            .timeout(limits.call_timeout)
+            .build()
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidState,
+                    &format!("Failed to create HTTP client: {}", e),
+                )
+            })?;
+
+        // The memory of the plugin is limited to a single instance
+        let store_limits = StoreLimitsBuilder::new()
+            .memory_size(limits.max_memory_bytes)
+            .instances(1)
+            .build();
+
+        Ok(Self {
+            plugin_id: plugin_id.to_string(),
+            grants,
+            // The default context has no directories, environment or sockets
+            wasi: WasiCtxBuilder::new().build(),
+            table: ResourceTable::new(),
+            limits: store_limits,
+            max_transfer_bytes: limits.max_transfer_bytes,
+            max_storage_bytes: limits.max_storage_bytes,
+            storage: PluginStorage::open(&context.data_dir).await?,
+            sandboxes,
+            client,
+            events: runtime.events.clone(),
+            tools: Vec::new(),
+        })
+    }
+
+    /// Check a storage key against the granted prefixes
+    fn check_key(&self, key: &str) -> std::result::Result<(), String> {
+        if !self.grants.allows_key(key) {
+            return Err(format!("Storage key {} isn't under a granted prefix", key));
+        }
+        Ok(())
+    }
+
+    /// Resolve a path within the granted directories, relative paths within
+    /// the first one
+    fn resolve(&self, path: &str) -> std::result::Result<PathBuf, String> {
+        let mut error = "No directory was granted".to_string();
+        for sandbox in &self.sandboxes {
+            match sandbox.resolve(path) {
+                Ok(resolved) => return Ok(resolved),
+                Err(e) => error = e.to_string(),
+            }
+        }
+        Err(error)
+    }
+}
+
+impl WasiView for HostState {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+impl storage::Host for HostState {
+    async fn get(&mut self, key: String) -> std::result::Result<Option<String>, String> {
+        self.check_key(&key)?;
+        Ok(self.storage.values.get(&key).cloned())
+    }
+
+    async fn set(&mut self, key: String, value: String) -> std::result::Result<(), String> {
+        self.check_key(&key)?;
+
+        // Replacing a value frees its bytes first
+        let replaced = self
+            .storage
+            .values
+            .get(&key)
+            .map_or(0, |old| key.len() + old.len());
+        if self.storage.size() - replaced + key.len() + value.len() > self.max_storage_bytes {
+            return Err(format!(
+                "Storage would exceed its limit of {} bytes",
+                self.max_storage_bytes
+            ));
+        }
+
+        self.storage.values.insert(key, value);
+        self.storage.save().await
+    }
+
+    async fn delete(&mut self, key: String) -> std::result::Result<(), String> {
+        self.check_key(&key)?;
+        if self.storage.values.remove(&key).is_some() {
+            self.storage.save().await?;
+        }
+        Ok(())
+    }
+
+    async fn list_keys(&mut self, prefix: String) -> std::result::Result<Vec<String>, String> {
+        Ok(self
+            .storage
+            .values
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_str()) && self.grants.allows_key(key))
+            .cloned()
+            .collect())
+    }
+}
+
+impl network::Host for HostState {
+    async fn fetch(
+        &mut self,
+        request: network::HttpRequest,
+    ) -> std::result::Result<network::HttpResponse, String> {
+        let url =
+            Url::parse(&request.url).map_err(|e| format!("Invalid URL {}: {}", request.url, e))?;
+        if !is_allowed(&url, &self.grants.hosts) {
+            return Err(format!("{} isn't on a granted host", url));
+        }
+        let method = Method::from_bytes(request.method.as_bytes())
+            .map_err(|_| format!("Invalid method {}", request.method))?;
+
+        // Build the request
+        let mut builder = self.client.request(method, url.clone());
+        for header in request.headers {
+            builder = builder.header(header.name, header.value);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        // Send it, reading the body up to the limit
+        let mut res = builder
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch {}: {}", url, e))?;
+        let status = res.status().as_u16();
+        let headers = res
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                Some(network::Header {
+                    name: name.to_string(),
+                    value: value.to_str().ok()?.to_string(),
+                })
+            })
+            .collect();
+        let mut body = Vec::new();
+        while let Some(chunk) = res
+            .chunk()
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", url, e))?
+        {
+            let keep = chunk.len().min(self.max_transfer_bytes - body.len());
+            body.extend_from_slice(&chunk[..keep]);
+            if keep < chunk.len() {
+                break;
+            }
+        }
+
+        Ok(network::HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+impl filesystem::Host for HostState {
+    async fn read_file(&mut self, path: String) -> std::result::Result<Vec<u8>, String> {
+        let path = self.resolve(&path)?;
+        let size = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+            .len();
+        if size > self.max_transfer_bytes as u64 {
+            return Err(format!(
+                "{} is larger than the limit of {} bytes",
+                path.display(),
+                self.max_transfer_bytes
+            ));
+        }
+        tokio::fs::read(&path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    }
+
+    async fn write_file(
+        &mut self,
+        path: String,
+        contents: Vec<u8>,
+    ) -> std::result::Result<(), String> {
+        let path = self.resolve(&path)?;
+        if contents.len() > self.max_transfer_bytes {
+            return Err(format!(
+                "Contents are larger than the limit of {} bytes",
+                self.max_transfer_bytes
+            ));
+        }
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    async fn list_dir(&mut self, path: String) -> std::result::Result<Vec<String>, String> {
+        let path = self.resolve(&path)?;
+        let mut entries = tokio::fs::read_dir(&path)
+            .await
+            .map_err(|e| format!("Failed to list {}: {}", path.display(), e))?;
+
+        // Directories are marked with a trailing slash
+        let mut names = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to list {}: {}", path.display(), e))?
+        {
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            if entry
+                .file_type()
+                .await
+                .is_ok_and(|file_type| file_type.is_dir())
+            {
+                name.push('/');
+            }
+            names.push(name);
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+impl events::Host for HostState {
+    async fn emit(&mut self, event_type: String, data: String) -> std::result::Result<(), String> {
+        if !self.grants.event_types.contains(&event_type) {
+            return Err(format!("Event type {} wasn't granted", event_type));
+        }
+        let data = serde_json::from_str(&data).map_err(|e| format!("Invalid event data: {}", e))?;
+
+        // Nobody listening is fine
+        let _ = self.events.send(PluginEvent {
+            plugin_id: self.plugin_id.clone(),
+            event_type,
+            data,
+            timestamp: Utc::now(),
+        });
+        Ok(())
+    }
+}
+
+impl tools::Host for HostState {
+    async fn register_tool(
+        &mut self,
+        definition: tools::ToolDefinition,
+    ) -> std::result::Result<(), String> {
+        if self.tools.iter().any(|tool| tool.name == definition.name) {
+            return Err(format!("Tool {} is already registered", definition.name));
+        }
+        let parameters = serde_json::from_str(&definition.parameters)
+            .map_err(|e| format!("Invalid parameters of tool {}: {}", definition.name, e))?;
+
+        self.tools.push(PluginToolDefinition {
+            name: definition.name,
+            description: definition.description,
+            parameters,
+        });
+        Ok(())
+    }
+}
+
+/// Instance of a plugin component in its store
+struct WasmInstance {
+    /// Store of the instance
+    store: Store<HostState>,
+
+    /// Exports of the instance
+    bindings: EvoPlugin,
+
+    /// Limits of the instance
+    limits: WasmLimits,
+
+    /// Whether a call trapped or timed out, which leaves the instance unusable
+    failed: bool,
+}
+
+impl WasmInstance {
+    /// Prepare a call into the plugin, refilling its fuel
+    fn prepare(&mut self, function: &str) -> Result<()> {
+        if self.failed {
+            return Err(Error::new(
+                ErrorKind::InvalidState,
+                &format!(
+                    "Plugin {} failed before {} and must be loaded again",
+                    self.store.data().plugin_id,
+                    function
+                ),
+            ));
+        }
+
+        self.store.set_fuel(self.limits.fuel).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidState,
+                &format!(
+                    "Failed to refuel plugin {}: {}",
+                    self.store.data().plugin_id,
+                    e
+                ),
+            )
+        })
+    }
+
+    /// Turn the outcome of a call into the plugin into a result
+    fn finish<T>(
+        &mut self,
+        function: &str,
+        outcome: std::result::Result<
+            wasmtime::Result<std::result::Result<T, String>>,
+            tokio::time::error::Elapsed,
+        >,
+    ) -> Result<T> {
+        let plugin_id = &self.store.data().plugin_id;
+        match outcome {
+            Ok(Ok(Ok(value))) => Ok(value),
+            // The plugin returned an error
+            Ok(Ok(Err(e))) => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                &format!("Plugin {} failed in {}: {}", plugin_id, function, e),
+            )),
+            // The plugin trapped, e.g. running out of fuel or memory
+            Ok(Err(e)) => {
+                let reason = match e.downcast_ref::<Trap>() {
+                    Some(Trap::OutOfFuel) => "it ran out of fuel".to_string(),
+                    _ => e.to_string(),
+                };
+                let error = Error::new(
+                    ErrorKind::InvalidOperation,
+                    &format!("Plugin {} trapped in {}: {}", plugin_id, function, reason),
+                );
+                self.failed = true;
+                Err(error)
+            }
+            // The call was dropped while the plugin was running
+            Err(_) => {
+                let error = Error::new(
+                    ErrorKind::Timeout,
+                    &format!(
+                        "Plugin {} didn't return from {} within {:?}",
+                        plugin_id, function, self.limits.call_timeout
+                    ),
+                );
+                self.failed = true;
+                Err(error)
+            }
+        }
+    }
+
+    async fn initialize(&mut self, config: &str) -> Result<()> {
+        self.prepare("initialize")?;
+        let guest = self.bindings.evo_plugin_guest();
+        let outcome = tokio::time::timeout(
+            self.limits.call_timeout,
+            guest.call_initialize(&mut self.store, config),
+        )
+        .await;
+        self.finish("initialize", outcome)
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        self.prepare("start")?;
+        let guest = self.bindings.evo_plugin_guest();
+        let outcome =
+            tokio::time::timeout(self.limits.call_timeout, guest.call_start(&mut self.store)).await;
+        self.finish("start", outcome)
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.prepare("stop")?;
+        let guest = self.bindings.evo_plugin_guest();
+        let outcome =
+            tokio::time::timeout(self.limits.call_timeout, guest.call_stop(&mut self.store)).await;
+        self.finish("stop", outcome)
+    }
+
+    async fn call_tool(&mut self, name: &str, args: &str) -> Result<String> {
+        self.prepare("call-tool")?;
+        let guest = self.bindings.evo_plugin_guest();
+        let outcome = tokio::time::timeout(
+            self.limits.call_timeout,
+            guest.call_call_tool(&mut self.store, name, args),
+        )
+        .await;
+        self.finish("call-tool", outcome)
+    }
+
+    async fn handle_event(&mut self, event: &PluginEvent) -> Result<()> {
+        self.prepare("handle-event")?;
+        let data = event.data.to_string();
+        let guest = self.bindings.evo_plugin_guest();
+        let outcome = tokio::time::timeout(
+            self.limits.call_timeout,
+            guest.call_handle_event(&mut self.store, &event.event_type, &data),
+        )
+        .await;
+        self.finish("handle-event", outcome)
+    }
+}
+
+/// WebAssembly plugin
+///
+/// A plugin component compiled by a [`WasmRuntime`]. It's instantiated when
+/// it's initialized, with the host interfaces of the permissions it was
+/// granted.
+pub struct WasmPlugin {
+    /// Plugin metadata
+    metadata: PluginMetadata,
+
+    /// Runtime the plugin was compiled by
+    runtime: WasmRuntime,
+
+    /// Compiled component
+    component: Component,
+
+    /// Plugin state
+    state: PluginState,
+
+    /// What the plugin was granted when it was initialized
+    grants: WasmGrants,
+
+    /// Instance of the component, once initialized
+    instance: Option<Arc<Mutex<WasmInstance>>>,
+
+    /// Task passing events on to the plugin while it's active
+    event_task: Option<JoinHandle<()>>,
+}
+
+impl WasmPlugin {
+    /// Get the instance of the component
+    fn instance(&self) -> Result<Arc<Mutex<WasmInstance>>> {
+        self.instance.clone().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidState,
+                &format!("Plugin {} is not initialized", self.metadata.id),
+            )
+        })
+    }
+
+    /// Get the tools the plugin registered, offered to agents while it's active
+    pub async fn tools(&self) -> Result<Vec<Arc<dyn ToolDyn>>> {
+        let instance = self.instance()?;
+        let definitions = instance.lock().await.store.data().tools.clone();
+        Ok(definitions
+            .into_iter()
+            .map(|definition| {
+                Arc::new(WasmTool {
+                    name: tool_name(&self.metadata.id, &definition.name).into(),
+                    definition,
+                    instance: instance.clone(),
+                }) as Arc<dyn ToolDyn>
+            })
+            .collect())
+    }
+
+    /// Pass the events of the granted types, published by the host and other
+    /// plugins, on to the plugin
+    fn spawn_event_task(&self, instance: Arc<Mutex<WasmInstance>>) -> JoinHandle<()> {
+        let mut events = self.runtime.subscribe();
+        let plugin_id = self.metadata.id.clone();
+        let event_types = self.grants.event_types.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        // Plugins don't receive their own events
+                        if event.plugin_id == plugin_id || !event_types.contains(&event.event_type)
+                        {
+                            continue;
+                        }
+                        if let Err(e) = instance.lock().await.handle_event(&event).await {
+                            tracing::warn!("Plugin {} failed to handle event: {}", plugin_id, e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Plugin {} missed {} events", plugin_id, missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for WasmPlugin {
+    fn metadata(&self) -> &PluginMetadata {
+        &self.metadata
+    }
+
+    async fn initialize(&mut self, context: PluginContext) -> Result<()> {
+        let plugin_id = self.metadata.id.clone();
+
+        // Negotiate the capabilities of the granted permissions
+        let grants = WasmGrants::from_permissions(&plugin_id, &context.permissions)?;
+        let interfaces = grants.interfaces();
+        let required: Vec<Capability> = interfaces.iter().map(HostInterface::capability).collect();
+        self.runtime.negotiator.negotiate_capabilities(&required)?;
+
+        // Refuse components that import interfaces they weren't granted
+        let component_type = self.component.component_type();
+        let imports = component_type
+            .imports(&self.runtime.engine)
+            .map(|(name, _)| name);
+        check_imports(&plugin_id, imports, &interfaces)?;
+
+        // Create the store of the plugin with its limits
+        let linker = self.runtime.linker(&interfaces)?;
+        let state = HostState::new(&plugin_id, grants.clone(), &context, &self.runtime).await?;
+        let mut store = Store::new(&self.runtime.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))
+            .and_then(|_| store.set_fuel(self.runtime.limits.fuel))
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidState,
+                    &format!("Failed to limit plugin {}: {}", plugin_id, e),
+                )
+            })?;
+
+        // Instantiate the component
+        let bindings = EvoPlugin::instantiate_async(&mut store, &self.component, &linker)
+            .await
+            .map_err(|e| {
+                self.state = PluginState::Failed;
+                Error::new(
+                    ErrorKind::InvalidState,
+                    &format!("Failed to instantiate plugin {}: {}", plugin_id, e),
+                )
+            })?;
+        let mut instance = WasmInstance {
+            store,
+            bindings,
+            limits: self.runtime.limits.clone(),
+            failed: false,
+        };
+
+        // Initialize the plugin with its configuration
+        let config = serde_json::to_string(&context.plugin_config).map_err(|e| {
+            Error::new(
+                ErrorKind::Parse,
+                &format!(
+                    "Failed to serialize configuration of plugin {}: {}",
+                    plugin_id, e
+                ),
+            )
+        })?;
+        if let Err(e) = instance.initialize(&config).await {
+            self.state = PluginState::Failed;
+            return Err(e);
+        }
+
+        self.grants = grants;
+        self.instance = Some(Arc::new(Mutex::new(instance)));
+        Ok(())
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let instance = self.instance()?;
+        if let Err(e) = instance.lock().await.start().await {
+            self.state = PluginState::Failed;
+            return Err(e);
+        }
+
+        // Offer the registered tools to agents
+        if let Some(tool_executor) = PLUGIN_TOOL_EXECUTOR.get() {
+            let tools = self.tools().await?;
+            if !tools.is_empty() {
+                if let Err(e) = tool_executor.tell(RegisterTools(tools)).await {
+                    tracing::warn!(
+                        "Failed to register tools of plugin {}: {}",
+                        self.metadata.id,
+                        e
+                    );
+                }
+            }
+        }
+
+        if !self.grants.event_types.is_empty() {
+            self.event_task = Some(self.spawn_event_task(instance));
+        }
+
+        self.state = PluginState::Active;
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        let instance = self.instance()?;
+        if let Some(event_task) = self.event_task.take() {
+            event_task.abort();
+        }
+
+        // Withdraw the registered tools
+        if let Some(tool_executor) = PLUGIN_TOOL_EXECUTOR.get() {
+            let names: Vec<_> = self.tools().await?.iter().map(|tool| tool.name()).collect();
+            if !names.is_empty() {
+                if let Err(e) = tool_executor.tell(UnregisterTools(names)).await {
+                    tracing::warn!(
+                        "Failed to unregister tools of plugin {}: {}",
+                        self.metadata.id,
+                        e
+                    );
+                }
+            }
+        }
+
+        if let Err(e) = instance.lock().await.stop().await {
+            self.state = PluginState::Failed;
+            return Err(e);
+        }
+
+        self.state = PluginState::Loaded;
+        Ok(())
+    }
+
+    async fn unload(&mut self) -> Result<()> {
+        // Stop the plugin if it's still active
+        if self.state == PluginState::Active {
+            if let Err(e) = self.stop().await {
+                tracing::warn!(
+                    "Failed to stop plugin {} before unloading: {}",
+                    self.metadata.id,
+                    e
+                );
+            }
+        }
+
+        // Dropping the instance frees its store
+        self.instance = None;
+        self.state = PluginState::Registered;
+        Ok(())
+    }
+
+    fn state(&self) -> PluginState {
+        self.state
+    }
+
+    fn plugin_type(&self) -> PluginType {
+        PluginType::Wasm
+    }
+}
+
+/// Tool registered by a WebAssembly plugin
+struct WasmTool {
+    /// Name agents know the tool by, prefixed with the plugin ID
+    name: std::borrow::Cow<'static, str>,
+
+    /// Definition the plugin registered
+    definition: PluginToolDefinition,
+
+    /// Instance of the plugin
+    instance: Arc<Mutex<WasmInstance>>,
+}
+
+impl ToolDyn for WasmTool {
+    fn name(&self) -> std::borrow::Cow<'static, str> {
+        self.name.clone()
+    }
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: self.name.clone(),
+            description: self.definition.description.clone().into(),
+            params: serde_json::from_value::<RootSchema>(self.definition.parameters.clone())
+                .unwrap_or_else(|_| schema_for!(Value)),
+            returns: None,
+        }
+    }
+
+    fn call(&self, args: String) -> BoxFuture<std::result::Result<String, AppError>> {
+        Box::pin(async move {
+            self.instance
+                .lock()
+                .await
+                .call_tool(&self.definition.name, &args)
+                .await
+                .map_err(|e| AppError::external_service(e.to_string()))
+        })
+    }
+
+    /// The schema the plugin registered, which may use parts of JSON schema
+    /// that don't survive the round trip through [`RootSchema`]
+    fn to_rig_tool(&self) -> rig::completion::ToolDefinition {
+        rig::completion::ToolDefinition {
+            name: self.name.to_string(),
+            description: self.definition.description.clone(),
+            parameters: self.definition.parameters.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasm_grants() {
+        // Collect the grants of permissions
+        let grants = WasmGrants::from_permissions(
+            "test-plugin",
+            &[
+                PluginPermission::Storage {
+                    key_prefix: "cache/".to_string(),
+                },
+                PluginPermission::Network {
+                    host: "example.com".to_string(),
+                },
+                PluginPermission::Tools,
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            grants.interfaces(),
+            HashSet::from([
+                HostInterface::Storage,
+                HostInterface::Network,
+                HostInterface::Tools
+            ])
+        );
+
+        // Storage keys must start with a granted prefix
+        assert!(grants.allows_key("cache/page"));
+        assert!(!grants.allows_key("secrets/token"));
+
+        // Directories must stay within the plugin data directory
+        let directory = |path: &str| PluginPermission::FileSystem {
+            path: path.to_string(),
+        };
+        let grants = WasmGrants::from_permissions("test-plugin", &[directory("cache/pages")]);
+        assert_eq!(grants.unwrap().paths, vec!["cache/pages".to_string()]);
+        assert!(WasmGrants::from_permissions("test-plugin", &[directory("/etc")]).is_err());
+        assert!(WasmGrants::from_permissions("test-plugin", &[directory("../other")]).is_err());
+        assert!(WasmGrants::from_permissions("test-plugin", &[directory("cache/../..")]).is_err());
+
+        // Processes can't be granted to WebAssembly plugins
+        let process = PluginPermission::Process {
+            command: "sh".to_string(),
+        };
+        assert!(WasmGrants::from_permissions("test-plugin", &[process]).is_err());
+        assert!(WasmGrants::default().interfaces().is_empty());
+    }
+
+    #[test]
+    fn test_check_imports() {
+        let granted = HashSet::from([HostInterface::Storage]);
+        let imports = ["wasi:io/streams@0.2.0", "evo:plugin/storage@0.1.0"];
+
+        // Granted interfaces and WASI can be imported
+        assert!(check_imports("test-plugin", imports, &granted).is_ok());
+
+        // Other host interfaces can't
+        assert!(check_imports("test-plugin", ["evo:plugin/network@0.1.0"], &granted).is_err());
+        assert_eq!(
+            HostInterface::from_import("evo:plugin/tools"),
+            Some(HostInterface::Tools)
+        );
+        assert_eq!(HostInterface::from_import("evo:plugins/tools@0.1.0"), None);
+    }
+
+    #[test]
+    fn test_runtime_capabilities() {
+        // A runtime offering only storage refuses the network capability
+        let mut registry = CapabilityRegistry::new();
+        registry
+            .register_capability(HostInterface::Storage.capability())
+            .unwrap();
+        let runtime = WasmRuntime::with_capabilities(WasmLimits::default(), registry).unwrap();
+        assert!(
+            runtime
+                .negotiator()
+                .negotiate_capabilities(&[HostInterface::Storage.capability()])
+                .is_ok()
+        );
+        assert!(
+            runtime
+                .negotiator()
+                .negotiate_capabilities(&[HostInterface::Network.capability()])
+                .is_err()
+        );
+    }
+}
@@ -45,7 +45,10 @@ pub use plugin_marketplace::{
     install_plugin_from_marketplace,
     uninstall_plugin_from_marketplace,
     update_plugin_from_marketplace,
-    refresh_plugin_marketplace
+    refresh_plugin_marketplace,
+    get_plugin_publisher_keys,
+    trust_plugin_publisher_key,
+    distrust_plugin_publisher_key
 };
 pub use privacy_analytics::PrivacyAnalyticsService;
 pub use privacy_policy::PrivacyPolicyService;
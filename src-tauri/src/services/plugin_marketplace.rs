@@ -5,7 +5,8 @@
 use crate::error::Result;
 use crate::plugins::marketplace::{
     MarketplaceSource, MarketplaceEntry, get_marketplace_sources, get_marketplace_entries,
-    search_marketplace_entries, install_plugin, uninstall_plugin, update_plugin, refresh_marketplace
+    search_marketplace_entries, install_plugin, uninstall_plugin, update_plugin, refresh_marketplace,
+    get_trusted_publisher_keys, add_trusted_publisher_key, remove_trusted_publisher_key
 };
 
 /// Get all marketplace sources
@@ -48,4 +49,22 @@ pub async fn update_plugin_from_marketplace(plugin_id: String) -> Result<()> {
 #[tauri::command]
 pub async fn refresh_plugin_marketplace() -> Result<()> {
     refresh_marketplace().await
+}
+
+/// Get the keys of the trusted plugin publishers
+#[tauri::command]
+pub async fn get_plugin_publisher_keys() -> Result<Vec<String>> {
+    get_trusted_publisher_keys().await
+}
+
+/// Trust plugins signed with a publisher's base64 encoded Ed25519 public key
+#[tauri::command]
+pub async fn trust_plugin_publisher_key(public_key: String) -> Result<()> {
+    add_trusted_publisher_key(public_key).await
+}
+
+/// Stop trusting plugins signed with a publisher's key
+#[tauri::command]
+pub async fn distrust_plugin_publisher_key(public_key: String) -> Result<()> {
+    remove_trusted_publisher_key(public_key).await
 }
\ No newline at end of file
@@ -1,9 +1,16 @@
 use kameo::prelude::ActorRef as LocalActorRef;
 use tauri::AppHandle;
 
-use crate::actors::{
-    SystemEventBus, agents::AgentManagerActor, conversation::ConversationManagerActor,
-    database::DatabaseActor, tools::ToolExecutorActor,
+use crate::{
+    actors::{
+        SystemEventBus, agents::AgentManagerActor, chains::ChainExecutorActor,
+        conversation::ConversationManagerActor, database::DatabaseActor,
+        documents::DocumentIndexerActor, identity::IdentityActor, mcp::McpManagerActor,
+        mcp_endpoint::McpEndpointActor, memory::MemoryManagerActor,
+        providers::ProviderRegistry, tools::ToolExecutorActor, transfer::FileTransferActor,
+        workflows::WorkflowEngineActor,
+    },
+    tools::BuiltinTools,
 };
 
 #[derive(Clone)]
@@ -19,4 +26,14 @@ pub struct ActorManager {
     pub agent_manager: LocalActorRef<AgentManagerActor>,
     pub tool_ref: LocalActorRef<ToolExecutorActor>,
     pub conversation_manager: LocalActorRef<ConversationManagerActor>,
+    pub document_indexer: LocalActorRef<DocumentIndexerActor>,
+    pub chain_executor: LocalActorRef<ChainExecutorActor>,
+    pub workflow_engine: LocalActorRef<WorkflowEngineActor>,
+    pub memory_manager: LocalActorRef<MemoryManagerActor>,
+    pub file_transfers: LocalActorRef<FileTransferActor>,
+    pub identity: LocalActorRef<IdentityActor>,
+    pub mcp_manager: LocalActorRef<McpManagerActor>,
+    pub mcp_endpoint: LocalActorRef<McpEndpointActor>,
+    pub providers: ProviderRegistry,
+    pub builtin_tools: BuiltinTools,
 }
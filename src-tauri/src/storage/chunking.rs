@@ -0,0 +1,314 @@
+use crate::entities::{DocumentChunkSemanticLevel, DocumentChunkType};
+
+/// Size limits used when splitting a document into chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkOptions {
+    /// Maximum number of characters in a chunk
+    pub max_chars: usize,
+    /// Number of characters shared by consecutive fixed size chunks
+    pub overlap: usize,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self {
+            max_chars: 1500,
+            overlap: 200,
+        }
+    }
+}
+
+/// A piece of a document produced by [`chunk_text`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextChunk {
+    pub content: String,
+    pub semantic_level: DocumentChunkSemanticLevel,
+    /// Index of the header chunk this chunk belongs to (semantic chunking only)
+    pub parent: Option<usize>,
+    /// Heading of the section the chunk was taken from (semantic chunking only)
+    pub heading: Option<String>,
+}
+
+impl TextChunk {
+    fn new(content: impl Into<String>, semantic_level: DocumentChunkSemanticLevel) -> Self {
+        Self {
+            content: content.into(),
+            semantic_level,
+            parent: None,
+            heading: None,
+        }
+    }
+}
+
+/// Splits a document's text into chunks using the given strategy.
+///
+/// * `Fixed` cuts the text into windows of `max_chars` that overlap by `overlap` characters.
+/// * `Sentence` groups whole sentences into chunks of up to `max_chars`.
+/// * `Paragraph` groups whole paragraphs, paragraphs that are too long are split by sentence.
+/// * `Semantic` follows the markdown heading structure, every heading becomes a header
+///   chunk that is the parent of the paragraph chunks of its section.
+pub fn chunk_text(
+    text: &str,
+    strategy: DocumentChunkType,
+    options: ChunkOptions,
+) -> Vec<TextChunk> {
+    let options = ChunkOptions {
+        max_chars: options.max_chars.max(1),
+        overlap: options.overlap.min(options.max_chars.saturating_sub(1)),
+    };
+    match strategy {
+        DocumentChunkType::Fixed => fixed_chunks(text, options)
+            .into_iter()
+            .map(|content| TextChunk::new(content, DocumentChunkSemanticLevel::Other))
+            .collect(),
+        DocumentChunkType::Sentence => group(split_sentences(text), options.max_chars)
+            .into_iter()
+            .map(|content| TextChunk::new(content, DocumentChunkSemanticLevel::Sentence))
+            .collect(),
+        DocumentChunkType::Paragraph | DocumentChunkType::Other => {
+            paragraph_chunks(text, options)
+                .into_iter()
+                .map(|content| TextChunk::new(content, DocumentChunkSemanticLevel::Paragraph))
+                .collect()
+        }
+        DocumentChunkType::Semantic => semantic_chunks(text, options),
+    }
+}
+
+fn fixed_chunks(text: &str, options: ChunkOptions) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let step = options.max_chars - options.overlap;
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let end = (start + options.max_chars).min(chars.len());
+        let chunk: String = chars[start..end].iter().collect();
+        if !chunk.trim().is_empty() {
+            chunks.push(chunk);
+        }
+        if end == chars.len() {
+            break;
+        }
+        start += step;
+    }
+    chunks
+}
+
+fn split_paragraphs(text: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(current.join("\n"));
+                current.clear();
+            }
+        } else {
+            current.push(line.trim_end());
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current.join("\n"));
+    }
+    paragraphs
+}
+
+fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            // A single line break inside a paragraph doesn't end a sentence
+            if chars.peek() == Some(&'\n') {
+                push_trimmed(&mut sentences, &mut current);
+            } else {
+                current.push(' ');
+            }
+            continue;
+        }
+        current.push(c);
+        if matches!(c, '.' | '!' | '?') && chars.peek().is_none_or(|next| next.is_whitespace()) {
+            push_trimmed(&mut sentences, &mut current);
+        }
+    }
+    push_trimmed(&mut sentences, &mut current);
+    sentences
+}
+
+fn push_trimmed(parts: &mut Vec<String>, current: &mut String) {
+    let trimmed = current.trim();
+    if !trimmed.is_empty() {
+        parts.push(trimmed.to_string());
+    }
+    current.clear();
+}
+
+/// Joins consecutive parts as long as they fit in `max_chars`.
+/// Parts that are too long on their own are cut into fixed size pieces.
+fn group(parts: Vec<String>, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for part in parts {
+        let len = part.chars().count();
+        if len > max_chars {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+            }
+            chunks.extend(fixed_chunks(
+                &part,
+                ChunkOptions {
+                    max_chars,
+                    overlap: 0,
+                },
+            ));
+            continue;
+        }
+        if !current.is_empty() && current.chars().count() + 1 + len > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(&part);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn paragraph_chunks(text: &str, options: ChunkOptions) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for paragraph in split_paragraphs(text) {
+        let len = paragraph.chars().count();
+        if len > options.max_chars {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+            }
+            chunks.extend(group(split_sentences(&paragraph), options.max_chars));
+            continue;
+        }
+        if !current.is_empty() && current.chars().count() + 2 + len > options.max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(&paragraph);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn push_section(
+    chunks: &mut Vec<TextChunk>,
+    heading: Option<&(usize, String)>,
+    section: &mut String,
+    options: ChunkOptions,
+) {
+    for content in paragraph_chunks(section, options) {
+        chunks.push(TextChunk {
+            content,
+            semantic_level: DocumentChunkSemanticLevel::Paragraph,
+            parent: heading.map(|(index, _)| *index),
+            heading: heading.map(|(_, title)| title.clone()),
+        });
+    }
+    section.clear();
+}
+
+fn semantic_chunks(text: &str, options: ChunkOptions) -> Vec<TextChunk> {
+    let mut chunks = Vec::new();
+    let mut heading: Option<(usize, String)> = None;
+    let mut section = String::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let is_heading = trimmed.starts_with('#')
+            && trimmed
+                .trim_start_matches('#')
+                .starts_with(|c: char| c.is_whitespace());
+        if is_heading {
+            push_section(&mut chunks, heading.as_ref(), &mut section, options);
+            let title = trimmed.trim_start_matches('#').trim().to_string();
+            chunks.push(TextChunk {
+                content: trimmed.to_string(),
+                semantic_level: DocumentChunkSemanticLevel::Header,
+                parent: None,
+                heading: Some(title.clone()),
+            });
+            heading = Some((chunks.len() - 1, title));
+        } else {
+            section.push_str(line);
+            section.push('\n');
+        }
+    }
+    push_section(&mut chunks, heading.as_ref(), &mut section, options);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_chunks_overlap() {
+        let chunks = chunk_text(
+            "abcdefghij",
+            DocumentChunkType::Fixed,
+            ChunkOptions {
+                max_chars: 4,
+                overlap: 1,
+            },
+        );
+        let contents: Vec<_> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, ["abcd", "defg", "ghij"]);
+    }
+
+    #[test]
+    fn test_sentence_chunks_keep_sentences_whole() {
+        let chunks = chunk_text(
+            "One. Two is here! Three?\nStill three.",
+            DocumentChunkType::Sentence,
+            ChunkOptions {
+                max_chars: 20,
+                overlap: 0,
+            },
+        );
+        let contents: Vec<_> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, ["One. Two is here!", "Three? Still three."]);
+    }
+
+    #[test]
+    fn test_paragraph_chunks_merge_small_paragraphs() {
+        let chunks = chunk_text(
+            "First.\n\nSecond.\n\n\nThird paragraph is longer.",
+            DocumentChunkType::Paragraph,
+            ChunkOptions {
+                max_chars: 30,
+                overlap: 0,
+            },
+        );
+        let contents: Vec<_> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, ["First.\n\nSecond.", "Third paragraph is longer."]);
+    }
+
+    #[test]
+    fn test_semantic_chunks_follow_headings() {
+        let chunks = chunk_text(
+            "Intro text.\n\n# Setup\nInstall it.\n\n## Usage\nRun it.",
+            DocumentChunkType::Semantic,
+            ChunkOptions::default(),
+        );
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[0].parent, None);
+        assert_eq!(chunks[1].semantic_level, DocumentChunkSemanticLevel::Header);
+        assert_eq!(chunks[2].parent, Some(1));
+        assert_eq!(chunks[2].heading.as_deref(), Some("Setup"));
+        assert_eq!(chunks[4].content, "Run it.");
+        assert_eq!(chunks[4].parent, Some(3));
+    }
+}
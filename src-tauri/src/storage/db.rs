@@ -3,10 +3,18 @@ use sqlx::{
     Pool, Sqlite,
     sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
 };
-use std::{env, path::Path, str::FromStr, sync::Arc};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 use tracing::{info, instrument};
 
-use crate::storage::migration::{MigrationManager, MigrationOptions};
+use crate::storage::{
+    migration::{MigrationManager, MigrationOptions},
+    vector::VectorStoreRegistry,
+};
 
 /// DatabaseManager handles SQLite connection pooling and database operations
 #[derive(Clone)]
@@ -15,6 +23,8 @@ pub struct DatabaseManager {
     pub pool: Pool<Sqlite>,
     /// Path to the database file
     pub db_path: Arc<str>,
+    /// ANN indexes kept in sync with the tables that store embeddings
+    pub vectors: VectorStoreRegistry,
 }
 
 impl DatabaseManager {
@@ -41,6 +51,7 @@ impl DatabaseManager {
         let db_manager = Self {
             pool,
             db_path: db_path.into(),
+            vectors: VectorStoreRegistry::in_memory(),
         };
 
         Ok(db_manager)
@@ -51,6 +62,12 @@ impl DatabaseManager {
         &self.db_path
     }
 
+    /// Persist the vector indexes below the given directory instead of keeping them in memory
+    pub fn with_vector_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.vectors = VectorStoreRegistry::new(dir);
+        self
+    }
+
     #[instrument(skip(self))]
     pub async fn run_migrations(&self) -> Result<()> {
         // Determine which migrations directory to use
@@ -1,4 +1,5 @@
 // Core storage modules
+pub mod chunking;
 pub mod db;
 pub mod manager;
 pub use manager::StorageManager;
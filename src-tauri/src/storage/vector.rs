@@ -1,116 +1,943 @@
-use color_eyre::eyre::Result;
-use hnsw_rs::prelude::*;
+use color_eyre::eyre::{Result, bail, ensure};
+use hnsw_rs::{filter::FilterT, prelude::*};
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, path::Path, sync::Arc};
-use tokio::sync::RwLock;
-use tracing::instrument;
-use uuid::{self, Uuid};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::{Mutex, OwnedRwLockWriteGuard, RwLock};
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
+
+const SNAPSHOT_FILE: &str = "snapshot.bin";
+const WAL_FILE: &str = "wal.bin";
+const CONFIG_FILE: &str = "config.json";
+const SNAPSHOT_MAGIC: &[u8; 8] = b"EVOVEC01";
+const MAX_LAYER: usize = 16;
+const WAL_INSERT: u8 = 1;
+const WAL_DELETE: u8 = 2;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VectorEntry {
     pub id: Uuid,
+    /// Workspace the entry belongs to, entries without one are visible in every workspace
+    #[serde(default)]
+    pub workspace_id: Option<Uuid>,
     pub text: String,
     pub embedding: Vec<f32>,
     pub metadata: serde_json::Value,
 }
 
+/// A search hit together with its similarity to the query (higher is closer)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoredEntry {
+    pub entry: VectorEntry,
+    pub score: f32,
+}
+
+/// Distance function used to compare embeddings
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Distance {
+    #[default]
+    Cosine,
+    L2,
+    /// Dot product, only meaningful for normalized embeddings
+    Dot,
+}
+
+impl Distance {
+    fn similarity(&self, distance: f32) -> f32 {
+        match self {
+            Distance::Cosine | Distance::Dot => 1.0 - distance,
+            Distance::L2 => 1.0 / (1.0 + distance),
+        }
+    }
+
+    fn as_byte(&self) -> u8 {
+        match self {
+            Distance::Cosine => 0,
+            Distance::L2 => 1,
+            Distance::Dot => 2,
+        }
+    }
+}
+
+/// Settings of a vector store, usually derived from the embedding model it holds vectors of
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VectorStoreConfig {
+    pub dimension: usize,
+    pub distance: Distance,
+    /// Number of bi-directional links per point (M)
+    pub max_connections: usize,
+    /// Size of the dynamic candidate list while inserting
+    pub ef_construction: usize,
+    /// Size of the dynamic candidate list while searching
+    pub ef_search: usize,
+    /// Expected maximum number of points
+    pub max_elements: usize,
+    /// Share of deleted points in the graph that triggers a compaction
+    pub compaction_threshold: f32,
+    /// Number of logged writes after which a new snapshot is written
+    pub snapshot_interval: usize,
+}
+
+impl Default for VectorStoreConfig {
+    fn default() -> Self {
+        Self {
+            dimension: 1536,
+            distance: Distance::Cosine,
+            max_connections: 16,
+            ef_construction: 200,
+            ef_search: 100,
+            max_elements: 1_000_000,
+            compaction_threshold: 0.2,
+            snapshot_interval: 1000,
+        }
+    }
+}
+
+impl VectorStoreConfig {
+    pub fn with_dimension(dimension: usize) -> Self {
+        Self {
+            dimension,
+            ..Default::default()
+        }
+    }
+}
+
+/// A condition on a top level key of an entry's metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "op")]
+pub enum MetadataPredicate {
+    Eq { key: String, value: Value },
+    In { key: String, values: Vec<Value> },
+    Exists { key: String },
+}
+
+impl MetadataPredicate {
+    fn matches(&self, metadata: &Value) -> bool {
+        match self {
+            MetadataPredicate::Eq { key, value } => metadata.get(key) == Some(value),
+            MetadataPredicate::In { key, values } => {
+                metadata.get(key).is_some_and(|v| values.contains(v))
+            }
+            MetadataPredicate::Exists { key } => metadata.get(key).is_some_and(|v| !v.is_null()),
+        }
+    }
+}
+
+/// Restricts a search or bulk delete to matching entries
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchFilter {
+    /// Only match entries of this workspace and entries without a workspace
+    pub workspace_id: Option<Uuid>,
+    /// Every predicate has to match
+    pub predicates: Vec<MetadataPredicate>,
+}
+
+impl SearchFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn workspace(mut self, workspace_id: Option<Uuid>) -> Self {
+        self.workspace_id = workspace_id;
+        self
+    }
+
+    pub fn eq(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.predicates.push(MetadataPredicate::Eq {
+            key: key.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    pub fn one_of(mut self, key: impl Into<String>, values: Vec<Value>) -> Self {
+        self.predicates.push(MetadataPredicate::In {
+            key: key.into(),
+            values,
+        });
+        self
+    }
+
+    pub fn matches(&self, entry: &VectorEntry) -> bool {
+        if let (Some(workspace_id), Some(entry_workspace)) = (self.workspace_id, entry.workspace_id)
+        {
+            if workspace_id != entry_workspace {
+                return false;
+            }
+        }
+        self.predicates
+            .iter()
+            .all(|predicate| predicate.matches(&entry.metadata))
+    }
+}
+
+enum Graph {
+    Cosine(Hnsw<'static, f32, DistCosine>),
+    L2(Hnsw<'static, f32, DistL2>),
+    Dot(Hnsw<'static, f32, DistDot>),
+}
+
+impl Graph {
+    fn new(config: &VectorStoreConfig) -> Self {
+        let (m, max, ef) = (
+            config.max_connections,
+            config.max_elements,
+            config.ef_construction,
+        );
+        match config.distance {
+            Distance::Cosine => Graph::Cosine(Hnsw::new(m, max, MAX_LAYER, ef, DistCosine)),
+            Distance::L2 => Graph::L2(Hnsw::new(m, max, MAX_LAYER, ef, DistL2)),
+            Distance::Dot => Graph::Dot(Hnsw::new(m, max, MAX_LAYER, ef, DistDot)),
+        }
+    }
+
+    fn insert(&self, embedding: &Vec<f32>, point: usize) {
+        match self {
+            Graph::Cosine(hnsw) => hnsw.insert((embedding, point)),
+            Graph::L2(hnsw) => hnsw.insert((embedding, point)),
+            Graph::Dot(hnsw) => hnsw.insert((embedding, point)),
+        }
+    }
+
+    fn insert_all(&self, points: &[(&Vec<f32>, usize)]) {
+        match self {
+            Graph::Cosine(hnsw) => hnsw.parallel_insert(points),
+            Graph::L2(hnsw) => hnsw.parallel_insert(points),
+            Graph::Dot(hnsw) => hnsw.parallel_insert(points),
+        }
+    }
+
+    fn search(&self, query: &[f32], k: usize, ef: usize, filter: &dyn FilterT) -> Vec<Neighbour> {
+        match self {
+            Graph::Cosine(hnsw) => hnsw.search_filter(query, k, ef, Some(filter)),
+            Graph::L2(hnsw) => hnsw.search_filter(query, k, ef, Some(filter)),
+            Graph::Dot(hnsw) => hnsw.search_filter(query, k, ef, Some(filter)),
+        }
+    }
+
+    /// Distance between two embeddings as the graph measures it
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        use hnsw_rs::prelude::Distance as _;
+        match self {
+            Graph::Cosine(_) => DistCosine.eval(a, b),
+            Graph::L2(_) => DistL2.eval(a, b),
+            Graph::Dot(_) => DistDot.eval(a, b),
+        }
+    }
+}
+
+struct Inner {
+    graph: Graph,
+    /// Every point in the graph, including deleted ones until the next compaction
+    points: HashMap<usize, VectorEntry>,
+    /// Point of every live entry
+    ids: HashMap<Uuid, usize>,
+    tombstones: HashSet<usize>,
+    next_point: usize,
+    wal: Option<BufWriter<File>>,
+    wal_records: usize,
+}
+
+impl Inner {
+    fn empty(config: &VectorStoreConfig) -> Self {
+        Self {
+            graph: Graph::new(config),
+            points: HashMap::new(),
+            ids: HashMap::new(),
+            tombstones: HashSet::new(),
+            next_point: 0,
+            wal: None,
+            wal_records: 0,
+        }
+    }
+
+    fn apply_insert(&mut self, entry: VectorEntry) {
+        self.apply_delete(&entry.id);
+        let point = self.next_point;
+        self.next_point += 1;
+        self.graph.insert(&entry.embedding, point);
+        self.ids.insert(entry.id, point);
+        self.points.insert(point, entry);
+    }
+
+    fn apply_delete(&mut self, id: &Uuid) -> bool {
+        match self.ids.remove(id) {
+            Some(point) => {
+                self.tombstones.insert(point);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn log(&mut self, record: &[u8]) -> Result<()> {
+        if let Some(wal) = &mut self.wal {
+            write_frame(wal, record)?;
+            wal.flush()?;
+            self.wal_records += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Approximate nearest neighbour index over embeddings.
+///
+/// Points are never moved once inserted, deletes only mark them as tombstones that are
+/// skipped while searching. Once too many points are deleted the graph is compacted.
+/// Persistent stores write every change to a write-ahead log and periodically fold the
+/// log into a binary snapshot of the live entries.
+pub struct VectorStore {
+    config: VectorStoreConfig,
+    dir: Option<PathBuf>,
+    inner: Arc<RwLock<Inner>>,
+}
+
 impl Debug for VectorStore {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("VectorStore")
+            .field("config", &self.config)
+            .field("dir", &self.dir)
             .field(
                 "entries_count",
-                &self.entries.try_read().map(|e| e.len()).unwrap_or(0),
+                &self.inner.try_read().map(|i| i.ids.len()).unwrap_or(0),
             )
             .finish()
     }
 }
 
-pub struct VectorStore {
-    index: Arc<RwLock<Hnsw<'static, f32, DistCosine>>>,
-    entries: Arc<RwLock<Vec<VectorEntry>>>,
-}
+impl VectorStore {
+    /// Creates an in-memory store
+    pub fn new(config: VectorStoreConfig) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Inner::empty(&config))),
+            config,
+            dir: None,
+        }
+    }
 
-impl Default for VectorStore {
-    fn default() -> Self {
-        Self::new()
+    /// Opens (or creates) a persistent store in the given directory, restoring the latest
+    /// snapshot and replaying the write-ahead log on top of it
+    #[instrument(err, skip(config))]
+    pub fn open(dir: impl AsRef<Path> + Debug, config: VectorStoreConfig) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let config_path = dir.join(CONFIG_FILE);
+        if config_path.exists() {
+            let stored: VectorStoreConfig = serde_json::from_reader(File::open(&config_path)?)?;
+            ensure!(
+                stored.dimension == config.dimension && stored.distance == config.distance,
+                "Vector store in {} was created with dimension {} and {:?} distance",
+                dir.display(),
+                stored.dimension,
+                stored.distance
+            );
+        }
+        serde_json::to_writer_pretty(File::create(&config_path)?, &config)?;
+
+        let mut inner = Inner::empty(&config);
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let entries = read_snapshot(&snapshot_path, &config)?;
+            let points: Vec<(&Vec<f32>, usize)> = entries
+                .iter()
+                .enumerate()
+                .map(|(point, entry)| (&entry.embedding, point))
+                .collect();
+            inner.graph.insert_all(&points);
+            for (point, entry) in entries.into_iter().enumerate() {
+                inner.ids.insert(entry.id, point);
+                inner.points.insert(point, entry);
+            }
+            inner.next_point = inner.points.len();
+        }
+
+        let wal_path = dir.join(WAL_FILE);
+        if wal_path.exists() {
+            let replayed = replay_wal(&wal_path, &config, &mut inner)?;
+            inner.wal_records = replayed;
+            debug!(replayed, "Replayed vector store write-ahead log");
+        }
+        inner.wal = Some(BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&wal_path)?,
+        ));
+        info!(entries = inner.ids.len(), dir = %dir.display(), "Opened vector store");
+
+        Ok(Self {
+            config,
+            dir: Some(dir),
+            inner: Arc::new(RwLock::new(inner)),
+        })
     }
-}
 
-impl VectorStore {
-    pub fn new() -> Self {
-        Self {
-            index: Arc::new(RwLock::new(Hnsw::new(
-                16,      // M: number of bi-directional links
-                200,     // ef_construction: size of dynamic candidate list
-                128,     // dimension: embedding dimension
-                1000000, // max_elements: maximum number of elements
-                DistCosine,
-            ))),
-            entries: Arc::new(RwLock::new(Vec::new())),
+    /// Reads the config of an existing persistent store
+    pub fn stored_config(dir: impl AsRef<Path>) -> Result<Option<VectorStoreConfig>> {
+        let path = dir.as_ref().join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(None);
         }
+        Ok(Some(serde_json::from_reader(File::open(path)?)?))
+    }
+
+    pub fn config(&self) -> &VectorStoreConfig {
+        &self.config
     }
 
+    pub async fn len(&self) -> usize {
+        self.inner.read().await.ids.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.inner.read().await.ids.is_empty()
+    }
+
+    pub async fn get(&self, id: &Uuid) -> Option<VectorEntry> {
+        let inner = self.inner.read().await;
+        inner
+            .ids
+            .get(id)
+            .and_then(|point| inner.points.get(point))
+            .cloned()
+    }
+
+    /// Adds an entry, replacing any entry with the same id
+    #[instrument(err, skip(self, entry), fields(id = %entry.id))]
+    pub async fn insert(&self, entry: VectorEntry) -> Result<()> {
+        ensure!(
+            entry.embedding.len() == self.config.dimension,
+            "Expected an embedding of dimension {} but got {}",
+            self.config.dimension,
+            entry.embedding.len()
+        );
+        let mut inner = self.inner.clone().write_owned().await;
+        let mut record = vec![WAL_INSERT];
+        encode_entry(&mut record, &entry);
+        inner.log(&record)?;
+        inner.apply_insert(entry);
+        self.maintain(inner).await
+    }
+
+    /// Deletes an entry, returning whether it existed
     #[instrument(err, skip(self))]
-    pub async fn store(&self, text: &str, embedding: &[f32]) -> Result<()> {
-        let entry = VectorEntry {
-            id: Uuid::new_v4(),
-            text: text.to_string(),
-            embedding: embedding.to_vec(),
-            metadata: serde_json::json!({}),
-        };
-        // Add to HNSW index
-        let index = self.index.write().await;
-        let entry_idx = self.entries.read().await.len();
-        index.insert((&entry.embedding, entry_idx));
-        // Store entry
-        self.entries.write().await.push(entry);
-        Ok(())
+    pub async fn delete(&self, id: &Uuid) -> Result<bool> {
+        let mut inner = self.inner.clone().write_owned().await;
+        if !inner.ids.contains_key(id) {
+            return Ok(false);
+        }
+        let mut record = vec![WAL_DELETE];
+        record.extend_from_slice(id.as_bytes());
+        inner.log(&record)?;
+        inner.apply_delete(id);
+        self.maintain(inner).await?;
+        Ok(true)
     }
 
+    /// Deletes every entry matching the filter, returning the number of deleted entries
     #[instrument(err, skip(self))]
-    pub async fn search(&self, query: &[f32], k: usize) -> Result<Vec<VectorEntry>> {
-        let index = self.index.read().await;
-        let entries = self.entries.read().await;
-        let results = index.search(query, k, 100); // ef_search = 100
-        let matches = results
+    pub async fn delete_where(&self, filter: &SearchFilter) -> Result<usize> {
+        let mut inner = self.inner.clone().write_owned().await;
+        let ids: Vec<Uuid> = inner
+            .ids
+            .iter()
+            .filter(|(_, point)| inner.points.get(point).is_some_and(|e| filter.matches(e)))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            let mut record = vec![WAL_DELETE];
+            record.extend_from_slice(id.as_bytes());
+            inner.log(&record)?;
+            inner.apply_delete(id);
+        }
+        self.maintain(inner).await?;
+        Ok(ids.len())
+    }
+
+    /// Finds the `k` entries closest to the query that match the filter
+    #[instrument(err, skip(self, query))]
+    pub async fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        filter: &SearchFilter,
+    ) -> Result<Vec<ScoredEntry>> {
+        ensure!(
+            query.len() == self.config.dimension,
+            "Expected a query of dimension {} but got {}",
+            self.config.dimension,
+            query.len()
+        );
+        let inner = self.inner.read().await;
+        if inner.ids.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+        let accept = |point: &usize| {
+            !inner.tombstones.contains(point)
+                && inner
+                    .points
+                    .get(point)
+                    .is_some_and(|entry| filter.matches(entry))
+        };
+        let mut neighbours: Vec<(usize, f32)> = inner
+            .graph
+            .search(query, k, self.config.ef_search.max(k), &accept)
             .into_iter()
-            .filter_map(|result| entries.get(result.d_id).cloned())
+            .map(|neighbour| (neighbour.d_id, neighbour.distance))
             .collect();
-        Ok(matches)
+        // The graph can miss entries of a selective filter, so they're scanned instead
+        if neighbours.len() < k {
+            neighbours = inner
+                .ids
+                .values()
+                .filter(|point| accept(point))
+                .filter_map(|point| {
+                    let entry = inner.points.get(point)?;
+                    Some((*point, inner.graph.distance(query, &entry.embedding)))
+                })
+                .collect();
+            neighbours.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+            neighbours.truncate(k);
+        }
+        Ok(neighbours
+            .into_iter()
+            .filter_map(|(point, distance)| {
+                let entry = inner.points.get(&point)?;
+                Some(ScoredEntry {
+                    entry: entry.clone(),
+                    score: self.config.distance.similarity(distance),
+                })
+            })
+            .collect())
     }
 
+    /// Rebuilds the graph without deleted points
     #[instrument(err, skip(self))]
-    pub async fn delete(&self, id: &Uuid) -> Result<()> {
-        let mut entries = self.entries.write().await;
-        if let Some(pos) = entries.iter().position(|e| e.id == *id) {
-            entries.remove(pos);
-        }
-        Ok(())
+    pub async fn compact(&self) -> Result<()> {
+        let inner = self.inner.clone().write_owned().await;
+        self.run_blocking(inner, Self::compact_locked).await
     }
 
+    /// Writes a snapshot of the live entries and truncates the write-ahead log
     #[instrument(err, skip(self))]
-    pub async fn save_to_disk(&self, path: impl AsRef<Path> + Debug) -> Result<()> {
-        let path = path.as_ref();
-        let entries = self.entries.read().await;
-        let file = std::fs::File::create(path)?;
-        serde_json::to_writer(file, &*entries)?;
+    pub async fn snapshot(&self) -> Result<()> {
+        let inner = self.inner.clone().write_owned().await;
+        self.run_blocking(inner, Self::snapshot_locked).await
+    }
+
+    async fn maintain(&self, inner: OwnedRwLockWriteGuard<Inner>) -> Result<()> {
+        let graph_points = inner.points.len();
+        if !inner.tombstones.is_empty()
+            && inner.tombstones.len() as f32 > graph_points as f32 * self.config.compaction_threshold
+        {
+            return self.run_blocking(inner, Self::compact_locked).await;
+        }
+        if inner.wal_records >= self.config.snapshot_interval {
+            return self.run_blocking(inner, Self::snapshot_locked).await;
+        }
         Ok(())
     }
 
-    #[instrument(err, skip(self))]
-    pub async fn load_from_disk(&self, path: &str) -> Result<()> {
-        let file = std::fs::File::open(path)?;
-        let entries: Vec<VectorEntry> = serde_json::from_reader(file)?;
+    /// Rebuilding the graph and writing snapshots take a while, so they run on a blocking
+    /// thread. The lock is held until they're done.
+    async fn run_blocking(
+        &self,
+        mut inner: OwnedRwLockWriteGuard<Inner>,
+        task: fn(&VectorStoreConfig, Option<&Path>, &mut Inner) -> Result<()>,
+    ) -> Result<()> {
+        let (config, dir) = (self.config.clone(), self.dir.clone());
+        tokio::task::spawn_blocking(move || task(&config, dir.as_deref(), &mut inner)).await?
+    }
+
+    fn compact_locked(
+        config: &VectorStoreConfig,
+        dir: Option<&Path>,
+        inner: &mut Inner,
+    ) -> Result<()> {
+        let removed = inner.tombstones.len();
+        for point in inner.tombstones.drain() {
+            inner.points.remove(&point);
+        }
+        // Points keep their ids, only the graph is rebuilt
+        let graph = Graph::new(config);
+        let points: Vec<(&Vec<f32>, usize)> = inner
+            .points
+            .iter()
+            .map(|(point, entry)| (&entry.embedding, *point))
+            .collect();
+        graph.insert_all(&points);
+        inner.graph = graph;
+        debug!(removed, remaining = inner.points.len(), "Compacted vector store");
+        Self::snapshot_locked(config, dir, inner)
+    }
 
-        let index = Hnsw::new(16, 200, 128, 1000000, DistCosine);
+    fn snapshot_locked(
+        config: &VectorStoreConfig,
+        dir: Option<&Path>,
+        inner: &mut Inner,
+    ) -> Result<()> {
+        let Some(dir) = dir else {
+            return Ok(());
+        };
+        let mut points: Vec<(&usize, &VectorEntry)> = inner
+            .ids
+            .values()
+            .filter_map(|point| inner.points.get_key_value(point))
+            .collect();
+        points.sort_by_key(|(point, _)| **point);
 
-        for (i, entry) in entries.iter().enumerate() {
-            index.insert((&entry.embedding, i));
+        let tmp_path = dir.join(format!("{SNAPSHOT_FILE}.tmp"));
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(SNAPSHOT_MAGIC)?;
+            writer.write_all(&(config.dimension as u32).to_le_bytes())?;
+            writer.write_all(&[config.distance.as_byte()])?;
+            writer.write_all(&(points.len() as u64).to_le_bytes())?;
+            let mut buf = Vec::new();
+            for (_, entry) in points {
+                buf.clear();
+                encode_entry(&mut buf, entry);
+                write_frame(&mut writer, &buf)?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
         }
+        std::fs::rename(&tmp_path, dir.join(SNAPSHOT_FILE))?;
 
-        *self.index.write().await = index;
-        *self.entries.write().await = entries;
+        // Everything in the log is part of the snapshot now
+        inner.wal = Some(BufWriter::new(File::create(dir.join(WAL_FILE))?));
+        inner.wal_records = 0;
+        debug!(entries = inner.ids.len(), "Wrote vector store snapshot");
         Ok(())
     }
 }
+
+/// Lazily opens one [`VectorStore`] per collection, persisted below a directory
+#[derive(Debug, Clone)]
+pub struct VectorStoreRegistry {
+    dir: Option<PathBuf>,
+    stores: Arc<Mutex<HashMap<String, Arc<VectorStore>>>>,
+}
+
+impl VectorStoreRegistry {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+            stores: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Creates a registry whose stores are only kept in memory
+    pub fn in_memory() -> Self {
+        Self {
+            dir: None,
+            stores: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Opens a collection, creating it with the given config if it doesn't exist yet
+    pub async fn open(&self, name: &str, config: VectorStoreConfig) -> Result<Arc<VectorStore>> {
+        let mut stores = self.stores.lock().await;
+        if let Some(store) = stores.get(name) {
+            return Ok(store.clone());
+        }
+        let store = Arc::new(match &self.dir {
+            Some(dir) => VectorStore::open(dir.join(name), config)?,
+            None => VectorStore::new(config),
+        });
+        stores.insert(name.to_string(), store.clone());
+        Ok(store)
+    }
+
+    /// Opens a collection only if it already exists
+    pub async fn get(&self, name: &str) -> Result<Option<Arc<VectorStore>>> {
+        let mut stores = self.stores.lock().await;
+        if let Some(store) = stores.get(name) {
+            return Ok(Some(store.clone()));
+        }
+        let Some(dir) = self.dir.as_ref().map(|dir| dir.join(name)) else {
+            return Ok(None);
+        };
+        let Some(config) = VectorStore::stored_config(&dir)? else {
+            return Ok(None);
+        };
+        let store = Arc::new(VectorStore::open(dir, config)?);
+        stores.insert(name.to_string(), store.clone());
+        Ok(Some(store))
+    }
+}
+
+/// FNV-1a, used to detect torn writes at the end of the log
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn write_frame(writer: &mut impl Write, data: &[u8]) -> Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.write_all(&checksum(data).to_le_bytes())?;
+    Ok(())
+}
+
+/// Reads the next frame, returning `None` at the end of the input or on a torn frame
+fn read_frame(reader: &mut impl Read) -> Option<Vec<u8>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).ok()?;
+    let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut data).ok()?;
+    let mut sum = [0u8; 4];
+    reader.read_exact(&mut sum).ok()?;
+    (u32::from_le_bytes(sum) == checksum(&data)).then_some(data)
+}
+
+fn encode_entry(buf: &mut Vec<u8>, entry: &VectorEntry) {
+    buf.extend_from_slice(entry.id.as_bytes());
+    match entry.workspace_id {
+        Some(workspace_id) => {
+            buf.push(1);
+            buf.extend_from_slice(workspace_id.as_bytes());
+        }
+        None => buf.push(0),
+    }
+    let metadata = entry.metadata.to_string();
+    for bytes in [entry.text.as_bytes(), metadata.as_bytes()] {
+        buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        buf.extend_from_slice(bytes);
+    }
+    buf.extend_from_slice(&(entry.embedding.len() as u32).to_le_bytes());
+    for value in &entry.embedding {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(self.0.len() >= n, "Unexpected end of vector store record");
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn uuid(&mut self) -> Result<Uuid> {
+        Ok(Uuid::from_slice(self.take(16)?)?)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+}
+
+fn decode_entry(decoder: &mut Decoder) -> Result<VectorEntry> {
+    let id = decoder.uuid()?;
+    let workspace_id = match decoder.take(1)?[0] {
+        0 => None,
+        _ => Some(decoder.uuid()?),
+    };
+    let text = decoder.string()?;
+    let metadata = serde_json::from_str(&decoder.string()?)?;
+    let len = decoder.u32()? as usize;
+    let embedding = decoder
+        .take(len * 4)?
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    Ok(VectorEntry {
+        id,
+        workspace_id,
+        text,
+        embedding,
+        metadata,
+    })
+}
+
+fn read_snapshot(path: &Path, config: &VectorStoreConfig) -> Result<Vec<VectorEntry>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 8 + 4 + 1 + 8];
+    reader.read_exact(&mut header)?;
+    let mut decoder = Decoder(&header);
+    ensure!(
+        decoder.take(8)? == SNAPSHOT_MAGIC,
+        "{} is not a vector store snapshot",
+        path.display()
+    );
+    let dimension = decoder.u32()? as usize;
+    let distance = decoder.take(1)?[0];
+    ensure!(
+        dimension == config.dimension && distance == config.distance.as_byte(),
+        "Snapshot {} doesn't match the store config",
+        path.display()
+    );
+    let count = u64::from_le_bytes(decoder.take(8)?.try_into()?) as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let Some(frame) = read_frame(&mut reader) else {
+            bail!("Snapshot {} is truncated", path.display());
+        };
+        entries.push(decode_entry(&mut Decoder(&frame))?);
+    }
+    Ok(entries)
+}
+
+fn replay_wal(path: &Path, config: &VectorStoreConfig, inner: &mut Inner) -> Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut replayed = 0;
+    while let Some(frame) = read_frame(&mut reader) {
+        let mut decoder = Decoder(&frame);
+        match decoder.take(1)?[0] {
+            WAL_INSERT => {
+                let entry = decode_entry(&mut decoder)?;
+                if entry.embedding.len() != config.dimension {
+                    warn!(id = %entry.id, "Skipping logged entry with the wrong dimension");
+                    continue;
+                }
+                inner.apply_insert(entry);
+            }
+            WAL_DELETE => {
+                inner.apply_delete(&decoder.uuid()?);
+            }
+            tag => bail!("Unknown write-ahead log record {tag}"),
+        }
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn entry(embedding: Vec<f32>, workspace_id: Option<Uuid>, kind: &str) -> VectorEntry {
+        VectorEntry {
+            id: Uuid::new_v4(),
+            workspace_id,
+            text: kind.to_string(),
+            embedding,
+            metadata: json!({ "kind": kind }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deleted_entries_are_not_returned() {
+        let store = VectorStore::new(VectorStoreConfig::with_dimension(2));
+        let first = entry(vec![1.0, 0.0], None, "a");
+        let second = entry(vec![0.9, 0.1], None, "b");
+        store.insert(first.clone()).await.unwrap();
+        store.insert(second.clone()).await.unwrap();
+
+        assert!(store.delete(&first.id).await.unwrap());
+        let results = store
+            .search(&[1.0, 0.0], 2, &SearchFilter::new())
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].entry.id, second.id);
+    }
+
+    #[tokio::test]
+    async fn test_search_applies_filters() {
+        let store = VectorStore::new(VectorStoreConfig::with_dimension(2));
+        let workspace = Uuid::new_v4();
+        store
+            .insert(entry(vec![1.0, 0.0], Some(Uuid::new_v4()), "a"))
+            .await
+            .unwrap();
+        store
+            .insert(entry(vec![1.0, 0.1], Some(workspace), "b"))
+            .await
+            .unwrap();
+        store
+            .insert(entry(vec![1.0, 0.2], None, "c"))
+            .await
+            .unwrap();
+
+        let results = store
+            .search(
+                &[1.0, 0.0],
+                3,
+                &SearchFilter::new().workspace(Some(workspace)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+
+        let results = store
+            .search(&[1.0, 0.0], 3, &SearchFilter::new().eq("kind", "c"))
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].entry.text, "c");
+    }
+
+    #[tokio::test]
+    async fn test_selective_filter_returns_every_match() {
+        let store = VectorStore::new(VectorStoreConfig::with_dimension(2));
+        for i in 0..500 {
+            store
+                .insert(entry(vec![1.0, i as f32 / 500.0], None, "common"))
+                .await
+                .unwrap();
+        }
+        for y in [1.0, 2.0, 3.0] {
+            store
+                .insert(entry(vec![-1.0, y], None, "rare"))
+                .await
+                .unwrap();
+        }
+
+        let results = store
+            .search(&[1.0, 0.0], 3, &SearchFilter::new().eq("kind", "rare"))
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.windows(2).all(|w| w[0].score >= w[1].score));
+    }
+
+    #[tokio::test]
+    async fn test_reopen_restores_snapshot_and_log() {
+        let dir = std::env::temp_dir().join(format!("vector-store-{}", Uuid::new_v4()));
+        let config = VectorStoreConfig {
+            snapshot_interval: 2,
+            compaction_threshold: 1.0,
+            ..VectorStoreConfig::with_dimension(2)
+        };
+        let kept = entry(vec![0.0, 1.0], None, "kept");
+        {
+            let store = VectorStore::open(&dir, config.clone()).unwrap();
+            let deleted = entry(vec![1.0, 0.0], None, "deleted");
+            store.insert(deleted.clone()).await.unwrap();
+            // The second write triggers a snapshot, the delete only ends up in the log
+            store.insert(kept.clone()).await.unwrap();
+            store.delete(&deleted.id).await.unwrap();
+        }
+
+        let store = VectorStore::open(&dir, config).unwrap();
+        assert_eq!(store.len().await, 1);
+        assert!(store.get(&kept.id).await.is_some());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
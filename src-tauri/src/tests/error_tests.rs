@@ -140,6 +140,7 @@ mod tests {
             (AppError::ResourceLimitExceeded("test".to_string()), ErrorCategory::ResourceLimit, ErrorSeverity::Error),
             (AppError::ConfigurationError("test".to_string()), ErrorCategory::Configuration, ErrorSeverity::Error),
             (AppError::ExternalServiceError("test".to_string()), ErrorCategory::ExternalService, ErrorSeverity::Error),
+            (AppError::Timeout("test".to_string()), ErrorCategory::Network, ErrorSeverity::Error),
         ];
 
         for (error, expected_category, expected_severity) in errors {
@@ -154,6 +155,7 @@ mod tests {
         let retriable_errors = vec![
             AppError::DatabaseError("test".to_string()),
             AppError::ExternalServiceError("test".to_string()),
+            AppError::Timeout("test".to_string()),
         ];
 
         for error in retriable_errors {
@@ -0,0 +1,414 @@
+//! Reading, writing and searching the files of the workspace sandbox
+
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use sync_wrapper::SyncFuture;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    actors::tools::{Tool, ToolDefinition},
+    error::{AppError, Result},
+    tools::sandbox::Sandbox,
+    utils::get_data_dir,
+};
+
+/// Maximum number of matches a search returns when the agent doesn't ask for fewer
+const SEARCH_LIMIT: usize = 50;
+
+/// Configuration of the file tools
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FilesConfig {
+    /// Directory the tool is confined to
+    pub root: PathBuf,
+    /// Largest file that is read, written or searched
+    pub max_bytes: u64,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            root: get_data_dir().join("workspace"),
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+pub struct ReadFile {
+    pub sandbox: Arc<Sandbox>,
+    pub max_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ReadFileArgs {
+    /// Path of the file, relative to the workspace
+    pub path: PathBuf,
+}
+
+impl Tool for ReadFile {
+    type Error = AppError;
+    type Args = ReadFileArgs;
+    type Output = String;
+
+    const NAME: &'static str = "read_file";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: "Reads the entire contents of a text file in the workspace.".into(),
+            params: schema_for!(ReadFileArgs),
+            returns: Some(schema_for!(String)),
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let path = self.sandbox.resolve(&args.path);
+        let max_bytes = self.max_bytes;
+        SyncFuture::new(async move {
+            let path = path?;
+            let len = tokio::fs::metadata(&path).await?.len();
+            if len > max_bytes {
+                return Err(AppError::resource_limit_exceeded(format!(
+                    "{} has {len} bytes, files of up to {max_bytes} bytes can be read",
+                    args.path.display()
+                )));
+            }
+            Ok(tokio::fs::read_to_string(&path).await?)
+        })
+    }
+}
+
+pub struct WriteFile {
+    pub sandbox: Arc<Sandbox>,
+    pub max_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct WriteFileArgs {
+    /// Path of the file, relative to the workspace. Missing directories are created.
+    pub path: PathBuf,
+    pub content: String,
+    /// Append to the file instead of replacing it
+    #[serde(default)]
+    pub append: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WriteFileOutput {
+    pub path: String,
+    pub bytes_written: usize,
+}
+
+impl Tool for WriteFile {
+    type Error = AppError;
+    type Args = WriteFileArgs;
+    type Output = WriteFileOutput;
+
+    const NAME: &'static str = "write_file";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description:
+                "Writes a text file in the workspace, replacing it unless `append` is set.".into(),
+            params: schema_for!(WriteFileArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let path = self.sandbox.resolve(&args.path);
+        let sandbox = self.sandbox.clone();
+        let max_bytes = self.max_bytes;
+        SyncFuture::new(async move {
+            let path = path?;
+            if args.content.len() as u64 > max_bytes {
+                return Err(AppError::resource_limit_exceeded(format!(
+                    "Files of up to {max_bytes} bytes can be written"
+                )));
+            }
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(args.append)
+                .truncate(!args.append)
+                .open(&path)
+                .await?;
+            file.write_all(args.content.as_bytes()).await?;
+            file.flush().await?;
+            Ok(WriteFileOutput {
+                path: sandbox.display(&path),
+                bytes_written: args.content.len(),
+            })
+        })
+    }
+}
+
+pub struct SearchFiles {
+    pub sandbox: Arc<Sandbox>,
+    pub max_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct SearchFilesArgs {
+    /// Only files whose name matches this pattern, `*` matches any characters and `?`
+    /// a single one, e.g. `*.md`
+    pub name: Option<String>,
+    /// Only files containing this text, matched case-insensitively. Each matching line
+    /// is returned.
+    pub text: Option<String>,
+    /// Directory to search, relative to the workspace. Defaults to the whole workspace.
+    pub directory: Option<PathBuf>,
+    /// Maximum number of matches to return, defaults to 50
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileMatch {
+    pub path: String,
+    /// Number of the matching line, starting at 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl Tool for SearchFiles {
+    type Error = AppError;
+    type Args = SearchFilesArgs;
+    type Output = Vec<FileMatch>;
+
+    const NAME: &'static str = "search_files";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Borrowed(
+                "Finds files in the workspace by name pattern and/or by the text they contain.",
+            ),
+            params: schema_for!(SearchFilesArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let directory = self
+            .sandbox
+            .resolve(args.directory.as_deref().unwrap_or(Path::new(".")));
+        let sandbox = self.sandbox.clone();
+        let max_bytes = self.max_bytes;
+        SyncFuture::new(async move {
+            let directory = directory?;
+            tokio::task::spawn_blocking(move || search(&sandbox, &directory, &args, max_bytes))
+                .await
+                .map_err(|e| AppError::internal(format!("File search failed: {e}")))?
+        })
+    }
+}
+
+fn search(
+    sandbox: &Sandbox,
+    directory: &Path,
+    args: &SearchFilesArgs,
+    max_bytes: u64,
+) -> Result<Vec<FileMatch>> {
+    let limit = args.limit.unwrap_or(SEARCH_LIMIT).min(SEARCH_LIMIT);
+    let text = args.text.as_deref().map(str::to_lowercase);
+    let mut matches = Vec::new();
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = std::fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            // Symlinks may lead out of the sandbox
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(pattern) = &args.name
+                && !glob_match(pattern, &name)
+            {
+                continue;
+            }
+            let Some(text) = &text else {
+                matches.push(FileMatch {
+                    path: sandbox.display(&path),
+                    line: None,
+                    text: None,
+                });
+                if matches.len() >= limit {
+                    return Ok(matches);
+                }
+                continue;
+            };
+            if entry.metadata().map_or(true, |meta| meta.len() > max_bytes) {
+                continue;
+            }
+            // Binary files aren't searched
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            for (i, line) in content.lines().enumerate() {
+                if line.to_lowercase().contains(text) {
+                    matches.push(FileMatch {
+                        path: sandbox.display(&path),
+                        line: Some(i + 1),
+                        text: Some(line.trim().chars().take(200).collect()),
+                    });
+                    if matches.len() >= limit {
+                        return Ok(matches);
+                    }
+                }
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Matches a file name against a pattern where `*` stands for any characters and `?`
+/// for a single one
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` when the rest doesn't match
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    p = star + 1;
+                    n = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.md", "notes.md"));
+        assert!(glob_match("*", "notes.md"));
+        assert!(glob_match("n?tes.*", "notes.md"));
+        assert!(glob_match("*o*s*", "notes.md"));
+        assert!(glob_match("notes.md", "notes.md"));
+        assert!(!glob_match("*.md", "notes.txt"));
+        assert!(!glob_match("n?tes", "notes.md"));
+        assert!(!glob_match("", "notes.md"));
+    }
+
+    #[tokio::test]
+    async fn test_file_tools() {
+        let dir = std::env::temp_dir().join(format!("evo-files-{}", uuid::Uuid::new_v4()));
+        let sandbox = Arc::new(Sandbox::new(&dir).unwrap());
+        let write = WriteFile {
+            sandbox: sandbox.clone(),
+            max_bytes: 64,
+        };
+        let read = ReadFile {
+            sandbox: sandbox.clone(),
+            max_bytes: 64,
+        };
+        let search = SearchFiles {
+            sandbox: sandbox.clone(),
+            max_bytes: 64,
+        };
+
+        let written = write
+            .call(WriteFileArgs {
+                path: "notes/todo.md".into(),
+                content: "Buy milk\n".to_string(),
+                append: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(written.bytes_written, 9);
+        write
+            .call(WriteFileArgs {
+                path: "notes/todo.md".into(),
+                content: "Call Bob\n".to_string(),
+                append: true,
+            })
+            .await
+            .unwrap();
+        let content = read
+            .call(ReadFileArgs {
+                path: "notes/todo.md".into(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(content, "Buy milk\nCall Bob\n");
+
+        let too_large = write
+            .call(WriteFileArgs {
+                path: "large.txt".into(),
+                content: "x".repeat(65),
+                append: false,
+            })
+            .await;
+        assert!(too_large.is_err());
+        let outside = read
+            .call(ReadFileArgs {
+                path: "../outside.txt".into(),
+            })
+            .await;
+        assert!(outside.is_err());
+
+        let found = search
+            .call(SearchFilesArgs {
+                name: Some("*.md".to_string()),
+                text: Some("bob".to_string()),
+                directory: None,
+                limit: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line, Some(2));
+        assert_eq!(found[0].text.as_deref(), Some("Call Bob"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,188 @@
+//! Fetching web pages and APIs from allowed domains
+
+use std::{borrow::Cow, sync::Arc, time::Duration};
+
+use reqwest::{Client, redirect};
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use sync_wrapper::SyncFuture;
+use url::Url;
+
+use crate::{
+    actors::tools::{Tool, ToolDefinition},
+    error::{AppError, Result},
+};
+
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// Configuration of the HTTP tool. Nothing may be fetched until domains are allowed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HttpConfig {
+    /// Domains that may be fetched, each including its subdomains
+    pub allowed_domains: Vec<String>,
+    pub timeout_secs: u64,
+    /// Bodies beyond this many bytes are cut off
+    pub max_bytes: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            timeout_secs: 30,
+            max_bytes: 512 * 1024,
+        }
+    }
+}
+
+/// Whether a URL is on one of the allowed domains, over HTTP(S)
+pub fn is_allowed(url: &Url, allowed_domains: &[String]) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    allowed_domains.iter().any(|domain| {
+        let domain = domain.trim_start_matches("*.").to_ascii_lowercase();
+        host == domain
+            || host
+                .strip_suffix(&domain)
+                .is_some_and(|sub| sub.ends_with('.'))
+    })
+}
+
+pub struct FetchUrl {
+    pub client: Client,
+    pub config: Arc<HttpConfig>,
+}
+
+impl FetchUrl {
+    pub fn new(config: HttpConfig) -> Result<Self> {
+        let config = Arc::new(config);
+        let allowed = config.clone();
+        // Redirects must stay on the allowed domains too
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("Too many redirects")
+            } else if is_allowed(attempt.url(), &allowed.allowed_domains) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        });
+        let client = Client::builder()
+            .redirect(policy)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .map_err(|e| AppError::configuration(format!("Invalid HTTP client: {e}")))?;
+        Ok(Self { client, config })
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct FetchUrlArgs {
+    /// The http or https URL to fetch with GET
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FetchUrlOutput {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: String,
+    /// Whether the body was cut off
+    pub truncated: bool,
+}
+
+impl Tool for FetchUrl {
+    type Error = AppError;
+    type Args = FetchUrlArgs;
+    type Output = FetchUrlOutput;
+
+    const NAME: &'static str = "fetch_url";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Owned(format!(
+                "Fetches a URL and returns the response body as text. Allowed domains: {}.",
+                match self.config.allowed_domains.as_slice() {
+                    [] => "none".to_string(),
+                    allowed => allowed.join(", "),
+                }
+            )),
+            params: schema_for!(FetchUrlArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let client = self.client.clone();
+        let config = self.config.clone();
+        SyncFuture::new(async move {
+            let url = Url::parse(&args.url)
+                .map_err(|e| AppError::validation(format!("Invalid URL {}: {e}", args.url)))?;
+            if !is_allowed(&url, &config.allowed_domains) {
+                return Err(AppError::authorization(format!(
+                    "{url} isn't on an allowed domain"
+                )));
+            }
+            let mut res =
+                client.get(url.clone()).send().await.map_err(|e| {
+                    AppError::external_service(format!("Failed to fetch {url}: {e}"))
+                })?;
+            let status = res.status().as_u16();
+            let content_type = res
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let mut body = Vec::new();
+            let mut truncated = false;
+            while let Some(chunk) = res
+                .chunk()
+                .await
+                .map_err(|e| AppError::external_service(format!("Failed to read {url}: {e}")))?
+            {
+                let keep = chunk.len().min(config.max_bytes - body.len());
+                body.extend_from_slice(&chunk[..keep]);
+                if keep < chunk.len() {
+                    truncated = true;
+                    break;
+                }
+            }
+            Ok(FetchUrlOutput {
+                status,
+                content_type,
+                body: String::from_utf8_lossy(&body).into_owned(),
+                truncated,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allowed() {
+        let allowed = vec!["example.com".to_string(), "*.docs.rs".to_string()];
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert!(is_allowed(&url("https://example.com/page"), &allowed));
+        assert!(is_allowed(&url("http://api.Example.com./v1"), &allowed));
+        assert!(is_allowed(&url("https://docs.rs/serde"), &allowed));
+        assert!(!is_allowed(&url("https://notexample.com"), &allowed));
+        assert!(!is_allowed(&url("https://example.com.evil.net"), &allowed));
+        assert!(!is_allowed(&url("file:///etc/passwd"), &allowed));
+        assert!(!is_allowed(&url("ftp://example.com"), &allowed));
+        assert!(!is_allowed(&url("https://example.com"), &[]));
+    }
+}
@@ -0,0 +1,245 @@
+//! Tools built into the app that agents can be given
+//!
+//! Every built-in tool has a row in the `tools` table holding its configuration, which
+//! is validated by the tool's config type, e.g. [`shell::ShellConfig`]. Tools start out
+//! inactive, and once activated they're offered only to the agents that select them,
+//! through `agent_tools` or the names listed under `tools` in their `tool_config`.
+
+use std::{borrow::Cow, collections::HashSet, sync::Arc};
+
+use chrono::Utc;
+use kameo::prelude::ActorRef as LocalActorRef;
+use rig::completion::ToolDefinition;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sqlx::types::Json;
+use tracing::error;
+
+use crate::{
+    actors::{
+        database::{DatabaseActor, EnsureBuiltinTools, GetToolByName, UpdateTool},
+        tools::{RegisterTools, Tool, ToolDyn, ToolExecutorActor, UnregisterTools},
+    },
+    entities::Tool as ToolRecord,
+    error::{AppError, Result},
+};
+
+pub mod files;
+pub mod http;
+pub mod records;
+pub mod sandbox;
+pub mod shell;
+pub mod sql;
+
+use self::{
+    files::{FilesConfig, ReadFile, SearchFiles, WriteFile},
+    http::{FetchUrl, HttpConfig},
+    records::{ManageEvents, ManageNotes, ManageTasks, RecordsConfig},
+    sandbox::Sandbox,
+    shell::{RunCommand, ShellConfig},
+    sql::{QueryDatabase, SqlConfig},
+};
+
+/// Names and descriptions of the built-in tools
+pub const BUILTIN_TOOLS: &[(&str, &str)] = &[
+    (ReadFile::NAME, "Reads text files in the workspace"),
+    (WriteFile::NAME, "Writes text files in the workspace"),
+    (
+        SearchFiles::NAME,
+        "Finds files in the workspace by name or content",
+    ),
+    (RunCommand::NAME, "Runs allowed programs in the workspace"),
+    (FetchUrl::NAME, "Fetches URLs on allowed domains"),
+    (
+        QueryDatabase::NAME,
+        "Runs read-only SQL queries over tasks, events and notes",
+    ),
+    (
+        ManageTasks::NAME,
+        "Lists, creates, updates and deletes tasks",
+    ),
+    (
+        ManageEvents::NAME,
+        "Lists, creates, updates and deletes events",
+    ),
+    (
+        ManageNotes::NAME,
+        "Lists, creates, updates and deletes notes",
+    ),
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN_TOOLS.iter().any(|(builtin, _)| *builtin == name)
+}
+
+/// Names of the tools an agent selected, the ones linked through `agent_tools` and the
+/// ones listed in its `tool_config`, e.g. `{"tools": ["read_file"]}`
+pub fn selected_tools(
+    tool_config: Option<&Value>,
+    linked: impl IntoIterator<Item = String>,
+) -> HashSet<String> {
+    let configured = tool_config
+        .and_then(|config| config.get("tools"))
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::to_string);
+    linked.into_iter().chain(configured).collect()
+}
+
+/// Keeps the built-in tools among the definitions that were selected, other tools are
+/// offered to every agent
+pub fn offered_to(
+    definitions: Vec<ToolDefinition>,
+    selected: &HashSet<String>,
+) -> Vec<ToolDefinition> {
+    definitions
+        .into_iter()
+        .filter(|definition| !is_builtin(&definition.name) || selected.contains(&definition.name))
+        .collect()
+}
+
+fn parse_config<T: DeserializeOwned + Default>(name: &str, config: Option<&Value>) -> Result<T> {
+    match config {
+        None => Ok(T::default()),
+        Some(config) => serde_json::from_value(config.clone())
+            .map_err(|e| AppError::validation(format!("Invalid configuration of {name}: {e}"))),
+    }
+}
+
+/// Loads and configures the built-in tools of the tool executor
+#[derive(Clone)]
+pub struct BuiltinTools {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub tool_executor: LocalActorRef<ToolExecutorActor>,
+    /// Connection string of the app's database, which the SQL tool opens read-only
+    pub db_path: Arc<str>,
+}
+
+impl BuiltinTools {
+    /// Registers the active built-in tools, adding the rows of the ones that are missing
+    pub async fn load(&self) -> Result<()> {
+        let records = self.list().await?;
+        let mut tools = Vec::new();
+        for record in records.iter().filter(|record| record.is_active) {
+            match self.build(&record.name, record.config.as_deref()) {
+                Ok(tool) => tools.push(tool),
+                Err(e) => error!("Failed to load built-in tool {}: {e}", record.name),
+            }
+        }
+        self.tool_executor.tell(RegisterTools(tools)).await?;
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Result<Vec<ToolRecord>> {
+        self.db
+            .ask(EnsureBuiltinTools(BUILTIN_TOOLS.to_vec()))
+            .await
+    }
+
+    /// Stores the configuration of a built-in tool, `None` for the defaults, and
+    /// registers or unregisters it
+    pub async fn configure(
+        &self,
+        name: &str,
+        config: Option<Value>,
+        is_active: bool,
+    ) -> Result<ToolRecord> {
+        let mut record = self
+            .db
+            .ask(GetToolByName(name.to_string()))
+            .await?
+            .filter(|_| is_builtin(name))
+            .ok_or_else(|| AppError::not_found("Built-in tool", name))?;
+        // Invalid configurations are refused before they're stored
+        let tool = self.build(name, config.as_ref())?;
+        record.config = config.map(Json);
+        record.is_active = is_active;
+        record.updated_at = Utc::now();
+        let record = self.db.ask(UpdateTool(record)).await?;
+        if is_active {
+            self.tool_executor.tell(RegisterTools(vec![tool])).await?;
+        } else {
+            self.tool_executor
+                .tell(UnregisterTools(vec![Cow::Owned(name.to_string())]))
+                .await?;
+        }
+        Ok(record)
+    }
+
+    fn build(&self, name: &str, config: Option<&Value>) -> Result<Arc<dyn ToolDyn>> {
+        Ok(match name {
+            ReadFile::NAME | WriteFile::NAME | SearchFiles::NAME => {
+                let config: FilesConfig = parse_config(name, config)?;
+                let sandbox = Arc::new(Sandbox::new(&config.root)?);
+                let max_bytes = config.max_bytes;
+                match name {
+                    ReadFile::NAME => Arc::new(ReadFile { sandbox, max_bytes }),
+                    WriteFile::NAME => Arc::new(WriteFile { sandbox, max_bytes }),
+                    _ => Arc::new(SearchFiles { sandbox, max_bytes }),
+                }
+            }
+            RunCommand::NAME => {
+                let config: ShellConfig = parse_config(name, config)?;
+                Arc::new(RunCommand {
+                    sandbox: Arc::new(Sandbox::new(&config.root)?),
+                    config: Arc::new(config),
+                })
+            }
+            FetchUrl::NAME => Arc::new(FetchUrl::new(parse_config::<HttpConfig>(name, config)?)?),
+            QueryDatabase::NAME => Arc::new(QueryDatabase {
+                db_path: self.db_path.clone(),
+                config: parse_config::<SqlConfig>(name, config)?,
+            }),
+            ManageTasks::NAME => Arc::new(ManageTasks {
+                db: self.db.clone(),
+                config: parse_config::<RecordsConfig>(name, config)?,
+            }),
+            ManageEvents::NAME => Arc::new(ManageEvents {
+                db: self.db.clone(),
+                config: parse_config::<RecordsConfig>(name, config)?,
+            }),
+            ManageNotes::NAME => Arc::new(ManageNotes {
+                db: self.db.clone(),
+                config: parse_config::<RecordsConfig>(name, config)?,
+            }),
+            _ => return Err(AppError::not_found("Built-in tool", name)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn definition(name: &str) -> ToolDefinition {
+        ToolDefinition {
+            name: name.to_string(),
+            description: String::new(),
+            parameters: Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_offered_to() {
+        let definitions = vec![
+            definition("search_documents"),
+            definition("read_file"),
+            definition("run_command"),
+            definition("fetch_url"),
+        ];
+        let config = json!({"tools": ["fetch_url", "unknown"]});
+        let selected = selected_tools(Some(&config), ["read_file".to_string()]);
+
+        let offered = offered_to(definitions.clone(), &selected);
+        let names: Vec<_> = offered.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["search_documents", "read_file", "fetch_url"]);
+
+        let offered = offered_to(definitions, &selected_tools(None, []));
+        let names: Vec<_> = offered.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["search_documents"]);
+    }
+}
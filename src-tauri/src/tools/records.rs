@@ -0,0 +1,601 @@
+//! Managing tasks, events and notes
+
+use std::borrow::Cow;
+
+use chrono::{DateTime, Duration, Utc};
+use kameo::prelude::ActorRef as LocalActorRef;
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::types::Json;
+use sync_wrapper::SyncFuture;
+use uuid::Uuid;
+
+use crate::{
+    actors::{
+        database::{
+            CreateEvent, DatabaseActor, DeleteEvent, DeleteNote, DeleteTask, GetEvent, GetNote,
+            GetTask, ListEvents, ListNotes, ListTasks, UpdateEvent, UpdateNote, UpdateTask,
+        },
+        tools::{Tool, ToolDefinition},
+    },
+    entities::{
+        CreateNote, CreateTask, Event, EventFilter, EventStatus, EventType, Note, NoteFilter,
+        NoteType, Task, TaskFilter, TaskImportance, TaskPriority, TaskStatus,
+    },
+    error::{AppError, Result},
+};
+
+/// Maximum number of records a list returns when the agent doesn't ask for fewer
+const LIST_LIMIT: usize = 50;
+
+/// Configuration of the task, event and note tools
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RecordsConfig {
+    /// Workspace the records are kept in, records of other workspaces can't be reached
+    pub workspace_id: Option<Uuid>,
+    /// Whether records can only be listed and read
+    pub read_only: bool,
+}
+
+impl RecordsConfig {
+    fn check(&self, action: RecordAction) -> Result<()> {
+        if self.read_only && !matches!(action, RecordAction::List | RecordAction::Get) {
+            return Err(AppError::authorization(
+                "Records can only be listed and read with this tool",
+            ));
+        }
+        Ok(())
+    }
+
+    fn contains(&self, workspace_id: Option<Uuid>) -> bool {
+        self.workspace_id.is_none_or(|id| workspace_id == Some(id))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordAction {
+    List,
+    Get,
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RecordOutput<T> {
+    One(T),
+    Many(Vec<T>),
+    Deleted { deleted: Uuid },
+}
+
+fn required<T>(value: Option<T>, field: &str) -> Result<T> {
+    value.ok_or_else(|| AppError::validation(format!("`{field}` is required for this action")))
+}
+
+fn list_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(LIST_LIMIT).min(LIST_LIMIT)
+}
+
+pub struct ManageTasks {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub config: RecordsConfig,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ManageTasksArgs {
+    pub action: RecordAction,
+    /// Id of the task to get, update or delete
+    pub id: Option<Uuid>,
+    /// Required to create a task
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Also filters the list
+    pub status: Option<TaskStatus>,
+    /// Also filters the list
+    pub priority: Option<TaskPriority>,
+    pub importance: Option<TaskImportance>,
+    pub due_date: Option<DateTime<Utc>>,
+    /// Text to look for in titles and descriptions when listing
+    pub search: Option<String>,
+    /// Maximum number of tasks to list, defaults to 50
+    pub limit: Option<usize>,
+}
+
+impl Tool for ManageTasks {
+    type Error = AppError;
+    type Args = ManageTasksArgs;
+    type Output = RecordOutput<Task>;
+
+    const NAME: &'static str = "manage_tasks";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Borrowed(
+                "Lists, gets, creates, updates or deletes tasks. Updates only change the \
+                fields that are given.",
+            ),
+            params: schema_for!(ManageTasksArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let db = self.db.clone();
+        let config = self.config.clone();
+        SyncFuture::new(async move {
+            config.check(args.action)?;
+            match args.action {
+                RecordAction::List => {
+                    let filter = TaskFilter {
+                        workspace_id: config.workspace_id,
+                        status: args.status,
+                        priority: args.priority,
+                        importance: args.importance,
+                        search_term: args.search,
+                        limit: Some(list_limit(args.limit)),
+                        ..Default::default()
+                    };
+                    Ok(RecordOutput::Many(db.ask(ListTasks(filter)).await?))
+                }
+                RecordAction::Get => {
+                    let task = get_task(&db, &config, required(args.id, "id")?).await?;
+                    Ok(RecordOutput::One(task))
+                }
+                RecordAction::Create => {
+                    let task = CreateTask {
+                        title: required(args.title, "title")?,
+                        description: args.description,
+                        status: args.status.unwrap_or(TaskStatus::Pending),
+                        start_time: Utc::now(),
+                        end_time: None,
+                        due_date: args.due_date,
+                        priority: args.priority.unwrap_or(TaskPriority::Low),
+                        importance: args.importance.unwrap_or(TaskImportance::Low),
+                        tags: Json(json!([])),
+                        url: None,
+                        metadata: None,
+                        created_by_id: None,
+                        assignee_participant_id: None,
+                        workspace_id: config.workspace_id,
+                        conversation_id: None,
+                        memory_id: None,
+                        plan_id: None,
+                        document_id: None,
+                        file_id: None,
+                    };
+                    Ok(RecordOutput::One(db.ask(task).await?))
+                }
+                RecordAction::Update => {
+                    let mut task = get_task(&db, &config, required(args.id, "id")?).await?;
+                    if let Some(title) = args.title {
+                        task.title = title;
+                    }
+                    if args.description.is_some() {
+                        task.description = args.description;
+                    }
+                    if let Some(status) = args.status {
+                        if status == TaskStatus::Completed && task.end_time.is_none() {
+                            task.end_time = Some(Utc::now());
+                        }
+                        task.status = status;
+                    }
+                    if let Some(priority) = args.priority {
+                        task.priority = priority;
+                    }
+                    if let Some(importance) = args.importance {
+                        task.importance = importance;
+                    }
+                    if args.due_date.is_some() {
+                        task.due_date = args.due_date;
+                    }
+                    task.updated_at = Utc::now();
+                    db.ask(UpdateTask(task.clone())).await?;
+                    Ok(RecordOutput::One(task))
+                }
+                RecordAction::Delete => {
+                    let task = get_task(&db, &config, required(args.id, "id")?).await?;
+                    db.ask(DeleteTask(task.id)).await?;
+                    Ok(RecordOutput::Deleted { deleted: task.id })
+                }
+            }
+        })
+    }
+}
+
+async fn get_task(
+    db: &LocalActorRef<DatabaseActor>,
+    config: &RecordsConfig,
+    id: Uuid,
+) -> Result<Task> {
+    db.ask(GetTask(id))
+        .await?
+        .filter(|task| config.contains(task.workspace_id))
+        .ok_or_else(|| AppError::not_found("Task", id))
+}
+
+pub struct ManageEvents {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub config: RecordsConfig,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ManageEventsArgs {
+    pub action: RecordAction,
+    /// Id of the event to get, update or delete
+    pub id: Option<Uuid>,
+    /// Required to create an event
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Also filters the list
+    pub event_type: Option<EventType>,
+    /// Also filters the list
+    pub status: Option<EventStatus>,
+    /// Required to create an event. Lists only events starting from it.
+    pub start_time: Option<DateTime<Utc>>,
+    /// Defaults to an hour after the start. Lists only events ending before it.
+    pub end_time: Option<DateTime<Utc>>,
+    pub is_all_day_event: Option<bool>,
+    pub location: Option<String>,
+    /// Text to look for in titles, descriptions and agendas when listing
+    pub search: Option<String>,
+    /// Maximum number of events to list, defaults to 50
+    pub limit: Option<usize>,
+}
+
+impl Tool for ManageEvents {
+    type Error = AppError;
+    type Args = ManageEventsArgs;
+    type Output = RecordOutput<Event>;
+
+    const NAME: &'static str = "manage_events";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Borrowed(
+                "Lists, gets, creates, updates or deletes calendar events. Updates only \
+                change the fields that are given.",
+            ),
+            params: schema_for!(ManageEventsArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let db = self.db.clone();
+        let config = self.config.clone();
+        SyncFuture::new(async move {
+            config.check(args.action)?;
+            match args.action {
+                RecordAction::List => {
+                    let filter = EventFilter {
+                        event_type: args.event_type,
+                        status: args.status,
+                        workspace_id: config.workspace_id,
+                        start_date: args.start_time,
+                        end_date: args.end_time,
+                        location: args.location,
+                        is_all_day_event: args.is_all_day_event,
+                        is_private: Some(false),
+                        search_term: args.search,
+                        limit: Some(list_limit(args.limit)),
+                        ..Default::default()
+                    };
+                    Ok(RecordOutput::Many(db.ask(ListEvents(filter)).await?))
+                }
+                RecordAction::Get => {
+                    let event = get_event(&db, &config, required(args.id, "id")?).await?;
+                    Ok(RecordOutput::One(event))
+                }
+                RecordAction::Create => {
+                    let start_time = required(args.start_time, "start_time")?;
+                    let end_time = args.end_time.unwrap_or(start_time + Duration::hours(1));
+                    let event = new_event(
+                        required(args.title, "title")?,
+                        start_time,
+                        end_time,
+                        config.workspace_id,
+                    )?;
+                    let event = Event {
+                        description: args.description,
+                        event_type: args.event_type.unwrap_or(EventType::Other),
+                        status: args.status.unwrap_or(EventStatus::Scheduled),
+                        is_all_day_event: args.is_all_day_event.unwrap_or(false),
+                        location: args.location,
+                        ..event
+                    };
+                    Ok(RecordOutput::One(db.ask(CreateEvent(event)).await?))
+                }
+                RecordAction::Update => {
+                    let mut event = get_event(&db, &config, required(args.id, "id")?).await?;
+                    if let Some(title) = args.title {
+                        event.title = title;
+                    }
+                    if args.description.is_some() {
+                        event.description = args.description;
+                    }
+                    if let Some(event_type) = args.event_type {
+                        event.event_type = event_type;
+                    }
+                    if let Some(status) = args.status {
+                        event.status = status;
+                    }
+                    if let Some(start_time) = args.start_time {
+                        event.start_time = start_time;
+                        event.reminder_date_time = start_time;
+                    }
+                    if let Some(end_time) = args.end_time {
+                        event.end_time = end_time;
+                    }
+                    if event.end_time < event.start_time {
+                        return Err(AppError::validation("Events can't end before they start"));
+                    }
+                    event.duration_in_minutes =
+                        Some((event.end_time - event.start_time).num_minutes());
+                    if let Some(is_all_day_event) = args.is_all_day_event {
+                        event.is_all_day_event = is_all_day_event;
+                    }
+                    if args.location.is_some() {
+                        event.location = args.location;
+                    }
+                    event.updated_at = Utc::now();
+                    db.ask(UpdateEvent(event.clone())).await?;
+                    Ok(RecordOutput::One(event))
+                }
+                RecordAction::Delete => {
+                    let event = get_event(&db, &config, required(args.id, "id")?).await?;
+                    db.ask(DeleteEvent(event.id)).await?;
+                    Ok(RecordOutput::Deleted { deleted: event.id })
+                }
+            }
+        })
+    }
+}
+
+/// Private events are kept from agents
+async fn get_event(
+    db: &LocalActorRef<DatabaseActor>,
+    config: &RecordsConfig,
+    id: Uuid,
+) -> Result<Event> {
+    db.ask(GetEvent(id))
+        .await?
+        .filter(|event| !event.is_private && config.contains(event.workspace_id))
+        .ok_or_else(|| AppError::not_found("Event", id))
+}
+
+fn new_event(
+    title: String,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    workspace_id: Option<Uuid>,
+) -> Result<Event> {
+    if end_time < start_time {
+        return Err(AppError::validation("Events can't end before they start"));
+    }
+    let now = Utc::now();
+    Ok(Event {
+        id: Uuid::new_v4(),
+        title,
+        description: None,
+        event_type: EventType::Other,
+        status: EventStatus::Scheduled,
+        start_time,
+        end_time,
+        is_all_day_event: false,
+        timezone_sid_key: None,
+        location: None,
+        virtual_meeting_url: None,
+        meeting_platform: None,
+        is_recurrence: false,
+        recurrence_rule: None,
+        recurrence_parent_id: None,
+        agent_participation: None,
+        requires_transcription: false,
+        requires_summarization: false,
+        agent_capabilities: None,
+        is_private: false,
+        allow_guests: false,
+        max_attendees: None,
+        requires_approval: false,
+        agenda: None,
+        meeting_notes: None,
+        transcription: None,
+        summary: None,
+        action_items: None,
+        is_child_event: false,
+        is_group_event: false,
+        is_archived: false,
+        event_relation: None,
+        activity_date: None,
+        duration_in_minutes: Some((end_time - start_time).num_minutes()),
+        show_as: None,
+        is_reminder_set: false,
+        reminder_date_time: start_time,
+        metadata: None,
+        created_at: now,
+        updated_at: now,
+        plan_id: None,
+        task_id: None,
+        created_by_user_id: None,
+        last_modified_by_user_id: None,
+        workspace_id,
+        parent_event_id: None,
+    })
+}
+
+pub struct ManageNotes {
+    pub db: LocalActorRef<DatabaseActor>,
+    pub config: RecordsConfig,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ManageNotesArgs {
+    pub action: RecordAction,
+    /// Id of the note to get, update or delete
+    pub id: Option<Uuid>,
+    /// Required to create a note
+    pub title: Option<String>,
+    /// Required to create a note
+    pub content: Option<String>,
+    /// Also filters the list
+    pub note_type: Option<NoteType>,
+    /// Task the note is about, also filters the list
+    pub task_id: Option<Uuid>,
+    /// Event the note is about, also filters the list
+    pub event_id: Option<Uuid>,
+    /// Text to look for in titles and contents when listing
+    pub search: Option<String>,
+    /// Maximum number of notes to list, defaults to 50
+    pub limit: Option<usize>,
+}
+
+impl Tool for ManageNotes {
+    type Error = AppError;
+    type Args = ManageNotesArgs;
+    type Output = RecordOutput<Note>;
+
+    const NAME: &'static str = "manage_notes";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Borrowed(
+                "Lists, gets, creates, updates or deletes notes, optionally about a task or \
+                an event. Updates only change the fields that are given.",
+            ),
+            params: schema_for!(ManageNotesArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let db = self.db.clone();
+        let config = self.config.clone();
+        SyncFuture::new(async move {
+            config.check(args.action)?;
+            match args.action {
+                RecordAction::List => {
+                    let filter = NoteFilter {
+                        workspace_id: config.workspace_id,
+                        task_id: args.task_id,
+                        event_id: args.event_id,
+                        note_type: args.note_type,
+                        search_term: args.search,
+                        limit: Some(list_limit(args.limit)),
+                        ..Default::default()
+                    };
+                    Ok(RecordOutput::Many(db.ask(ListNotes(filter)).await?))
+                }
+                RecordAction::Get => {
+                    let note = get_note(&db, &config, required(args.id, "id")?).await?;
+                    Ok(RecordOutput::One(note))
+                }
+                RecordAction::Create => {
+                    let note = CreateNote {
+                        workspace_id: config.workspace_id,
+                        parent_note_id: None,
+                        task_id: args.task_id,
+                        event_id: args.event_id,
+                        note_type: args.note_type.unwrap_or(NoteType::Note),
+                        title: required(args.title, "title")?,
+                        content: required(args.content, "content")?,
+                        metadata: None,
+                    };
+                    Ok(RecordOutput::One(db.ask(note).await?))
+                }
+                RecordAction::Update => {
+                    let mut note = get_note(&db, &config, required(args.id, "id")?).await?;
+                    if let Some(title) = args.title {
+                        note.title = title;
+                    }
+                    if let Some(content) = args.content {
+                        note.content = content;
+                    }
+                    if let Some(note_type) = args.note_type {
+                        note.note_type = note_type;
+                    }
+                    if args.task_id.is_some() {
+                        note.task_id = args.task_id;
+                    }
+                    if args.event_id.is_some() {
+                        note.event_id = args.event_id;
+                    }
+                    Ok(RecordOutput::One(db.ask(UpdateNote(note)).await?))
+                }
+                RecordAction::Delete => {
+                    let note = get_note(&db, &config, required(args.id, "id")?).await?;
+                    db.ask(DeleteNote(note.id)).await?;
+                    Ok(RecordOutput::Deleted { deleted: note.id })
+                }
+            }
+        })
+    }
+}
+
+async fn get_note(
+    db: &LocalActorRef<DatabaseActor>,
+    config: &RecordsConfig,
+    id: Uuid,
+) -> Result<Note> {
+    db.ask(GetNote(id))
+        .await?
+        .filter(|note| config.contains(note.workspace_id))
+        .ok_or_else(|| AppError::not_found("Note", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_config() {
+        let workspace_id = Uuid::new_v4();
+        let config = RecordsConfig {
+            workspace_id: Some(workspace_id),
+            read_only: true,
+        };
+        assert!(config.check(RecordAction::List).is_ok());
+        assert!(config.check(RecordAction::Get).is_ok());
+        assert!(config.check(RecordAction::Create).is_err());
+        assert!(config.check(RecordAction::Delete).is_err());
+        assert!(config.contains(Some(workspace_id)));
+        assert!(!config.contains(Some(Uuid::new_v4())));
+        assert!(!config.contains(None));
+        assert!(RecordsConfig::default().contains(None));
+
+        let start = Utc::now();
+        assert!(
+            new_event(
+                "Standup".to_string(),
+                start,
+                start - Duration::hours(1),
+                None
+            )
+            .is_err()
+        );
+        let event = new_event(
+            "Standup".to_string(),
+            start,
+            start + Duration::hours(1),
+            None,
+        )
+        .unwrap();
+        assert_eq!(event.duration_in_minutes, Some(60));
+        assert_eq!(event.reminder_date_time, start);
+    }
+}
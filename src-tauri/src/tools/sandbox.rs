@@ -0,0 +1,129 @@
+//! The directory the file and shell tools are confined to
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::error::{AppError, Result};
+
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    /// Confines tools to `root`, which is created if it doesn't exist
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::create_dir_all(&root)?;
+        Ok(Self {
+            root: root.as_ref().canonicalize()?,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves a path an agent gave, relative to the root or absolute within it.
+    /// Paths that lead out of the root with `..` are refused, and so are paths through
+    /// symlinks, which could point anywhere, including dangling ones that a write
+    /// would follow.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) if path.is_absolute() => return Err(self.outside(path)),
+            Err(_) => path,
+        };
+
+        let mut resolved = self.root.clone();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                Component::ParentDir if resolved != self.root => {
+                    resolved.pop();
+                }
+                _ => return Err(self.outside(path)),
+            }
+        }
+
+        // Only the part of the path that exists can contain symlinks
+        let mut current = self.root.clone();
+        for part in resolved.strip_prefix(&self.root).unwrap_or(Path::new("")) {
+            current.push(part);
+            match std::fs::symlink_metadata(&current) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    return Err(AppError::authorization(format!(
+                        "{} leads through the symlink {}",
+                        path.display(),
+                        self.display(&current)
+                    )));
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// How a path inside the sandbox is shown to agents
+    pub fn display(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn outside(&self, path: &Path) -> AppError {
+        AppError::authorization(format!(
+            "{} is outside of the workspace {}",
+            path.display(),
+            self.root.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(format!("evo-sandbox-{}", uuid::Uuid::new_v4()));
+        let sandbox = Sandbox::new(&dir).unwrap();
+        let root = sandbox.root().to_path_buf();
+
+        assert_eq!(
+            sandbox.resolve("notes/a.md").unwrap(),
+            root.join("notes/a.md")
+        );
+        assert_eq!(
+            sandbox.resolve("./notes/../b.md").unwrap(),
+            root.join("b.md")
+        );
+        assert_eq!(
+            sandbox.resolve(root.join("c.md")).unwrap(),
+            root.join("c.md")
+        );
+        assert!(sandbox.resolve("../escape.md").is_err());
+        assert!(sandbox.resolve("notes/../../escape.md").is_err());
+        assert!(sandbox.resolve("/etc/passwd").is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(std::env::temp_dir(), root.join("link")).unwrap();
+            assert!(sandbox.resolve("link/escape.md").is_err());
+            assert!(sandbox.resolve("link").is_err());
+
+            // A dangling symlink would be followed by a write
+            let target = std::env::temp_dir().join(format!("evo-escape-{}", uuid::Uuid::new_v4()));
+            std::os::unix::fs::symlink(&target, root.join("dangling")).unwrap();
+            assert!(sandbox.resolve("dangling").is_err());
+            assert!(sandbox.resolve("dangling/escape.md").is_err());
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
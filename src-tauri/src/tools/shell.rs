@@ -0,0 +1,268 @@
+//! Running allowed programs in the workspace sandbox
+
+use std::{borrow::Cow, path::PathBuf, process::Stdio, sync::Arc, time::Duration};
+
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use sync_wrapper::SyncFuture;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::Command,
+};
+
+use crate::{
+    actors::tools::{Tool, ToolDefinition},
+    error::{AppError, Result},
+    tools::sandbox::Sandbox,
+    utils::get_data_dir,
+};
+
+/// Configuration of the shell tool. Nothing may run until programs are allowed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShellConfig {
+    /// Directory commands run in, agents can't choose another one
+    pub root: PathBuf,
+    /// Names of the programs that may run, e.g. `git` or `cargo`. Arguments that are
+    /// paths must lie in the workspace, but programs can take paths in forms that aren't
+    /// recognized, e.g. `git -C/path`, or reach outside by themselves, so only programs
+    /// that are safe to run with any arguments should be allowed.
+    pub allowed_commands: Vec<String>,
+    /// Commands still running after this are killed
+    pub timeout_secs: u64,
+    /// Output beyond this many bytes, of stdout and stderr each, is cut off
+    pub max_output_bytes: usize,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            root: get_data_dir().join("workspace"),
+            allowed_commands: Vec::new(),
+            timeout_secs: 30,
+            max_output_bytes: 64 * 1024,
+        }
+    }
+}
+
+pub struct RunCommand {
+    pub sandbox: Arc<Sandbox>,
+    pub config: Arc<ShellConfig>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct RunCommandArgs {
+    /// Name of the program, one of the allowed commands
+    pub command: String,
+    /// Arguments passed to the program as they are, no shell interprets them
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandOutput {
+    /// Missing when the command was killed
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+impl RunCommand {
+    /// Programs are named without a path, so only the allowed ones found on the `PATH`
+    /// can run
+    fn check(&self, command: &str) -> Result<()> {
+        let allowed = !command.contains(['/', '\\'])
+            && self
+                .config
+                .allowed_commands
+                .iter()
+                .any(|allowed| allowed == command);
+        if !allowed {
+            return Err(AppError::authorization(format!(
+                "{command} isn't an allowed command, allowed are: {}",
+                self.config.allowed_commands.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Arguments, and the values of `--option=value` arguments, are refused when they
+    /// are paths leading out of the workspace, i.e. absolute paths elsewhere, paths up
+    /// with `..` and paths through symlinks
+    fn check_args(&self, args: &[String]) -> Result<()> {
+        for arg in args {
+            self.sandbox.resolve(arg)?;
+            if let Some((_, value)) = arg.split_once('=') {
+                self.sandbox.resolve(value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Tool for RunCommand {
+    type Error = AppError;
+    type Args = RunCommandArgs;
+    type Output = CommandOutput;
+
+    const NAME: &'static str = "run_command";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Owned(format!(
+                "Runs a program in the workspace and returns its output. Allowed programs: {}. \
+                Paths given as arguments must be within the workspace. \
+                Commands are killed after {} seconds.",
+                match self.config.allowed_commands.as_slice() {
+                    [] => "none".to_string(),
+                    allowed => allowed.join(", "),
+                },
+                self.config.timeout_secs
+            )),
+            params: schema_for!(RunCommandArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let allowed = self
+            .check(&args.command)
+            .and_then(|_| self.check_args(&args.args));
+        let root = self.sandbox.root().to_path_buf();
+        let config = self.config.clone();
+        SyncFuture::new(async move {
+            allowed?;
+            let mut child = Command::new(&args.command)
+                .args(&args.args)
+                .current_dir(root)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| {
+                    AppError::external_service(format!("Failed to run {}: {e}", args.command))
+                })?;
+            let stdout = read_limited(child.stdout.take(), config.max_output_bytes);
+            let stderr = read_limited(child.stderr.take(), config.max_output_bytes);
+            let run = async {
+                let (status, stdout, stderr) = tokio::join!(child.wait(), stdout, stderr);
+                Ok::<_, AppError>((status?, stdout, stderr))
+            };
+            match tokio::time::timeout(Duration::from_secs(config.timeout_secs), run).await {
+                Ok(res) => {
+                    let (status, stdout, stderr) = res?;
+                    Ok(CommandOutput {
+                        exit_code: status.code(),
+                        stdout,
+                        stderr,
+                        timed_out: false,
+                    })
+                }
+                // Dropping the child kills it
+                Err(_) => Ok(CommandOutput {
+                    exit_code: None,
+                    stdout: String::new(),
+                    stderr: format!("Killed after {} seconds", config.timeout_secs),
+                    timed_out: true,
+                }),
+            }
+        })
+    }
+}
+
+/// Reads a stream to its end, keeping the first `limit` bytes
+async fn read_limited(stream: Option<impl AsyncRead + Unpin>, limit: usize) -> String {
+    let Some(mut stream) = stream else {
+        return String::new();
+    };
+    let mut output = Vec::new();
+    let mut buf = [0; 8192];
+    let mut truncated = false;
+    while let Ok(n) = stream.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        let keep = n.min(limit.saturating_sub(output.len()));
+        output.extend_from_slice(&buf[..keep]);
+        truncated |= keep < n;
+    }
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    if truncated {
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_command(allowed_commands: &[&str], timeout_secs: u64) -> RunCommand {
+        let dir = std::env::temp_dir().join(format!("evo-shell-{}", uuid::Uuid::new_v4()));
+        RunCommand {
+            sandbox: Arc::new(Sandbox::new(dir).unwrap()),
+            config: Arc::new(ShellConfig {
+                allowed_commands: allowed_commands.iter().map(|c| c.to_string()).collect(),
+                timeout_secs,
+                max_output_bytes: 4,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_allowed_commands() {
+        let tool = run_command(&["git"], 30);
+        assert!(tool.check("git").is_ok());
+        assert!(tool.check("rm").is_err());
+        assert!(tool.check("/usr/bin/git").is_err());
+        assert!(tool.check("./git").is_err());
+        assert!(run_command(&[], 30).check("git").is_err());
+    }
+
+    #[test]
+    fn test_args_stay_in_workspace() {
+        let tool = run_command(&["git"], 30);
+        let root = tool.sandbox.root().to_path_buf();
+        let allowed = |args: &[&str]| {
+            let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+            tool.check_args(&args).is_ok()
+        };
+
+        assert!(allowed(&["log", "-n", "3", "src/main.rs"]));
+        assert!(allowed(&[&root.join("a.md").display().to_string()]));
+        assert!(allowed(&["commit", "--message=fix"]));
+        assert!(!allowed(&["-C", "/etc"]));
+        assert!(!allowed(&["../other"]));
+        assert!(!allowed(&["--git-dir=/home/user/.git"]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command() {
+        let output = run_command(&["echo"], 30)
+            .call(RunCommandArgs {
+                command: "echo".to_string(),
+                args: vec!["hello".to_string()],
+            })
+            .await
+            .unwrap();
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.stdout, "hell\n[output truncated]");
+
+        let output = run_command(&["sleep"], 1)
+            .call(RunCommandArgs {
+                command: "sleep".to_string(),
+                args: vec!["10".to_string()],
+            })
+            .await
+            .unwrap();
+        assert!(output.timed_out);
+    }
+}
@@ -0,0 +1,275 @@
+//! Read-only SQL queries over the views made for agents
+//!
+//! Queries run on a read-only connection with an authorizer that refuses everything
+//! but reading the [`QUERYABLE_VIEWS`], so neither writes nor other tables, e.g. the
+//! credentials, can be reached however the query is written.
+
+use std::{
+    borrow::Cow,
+    ffi::{CStr, c_char, c_int, c_void},
+    ptr,
+    str::FromStr,
+    sync::Arc,
+};
+
+use futures_util::TryStreamExt;
+use libsqlite3_sys::{
+    SQLITE_DENY, SQLITE_FUNCTION, SQLITE_OK, SQLITE_READ, SQLITE_RECURSIVE, SQLITE_SELECT,
+    sqlite3_set_authorizer,
+};
+use schemars::{JsonSchema, schema_for};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{
+    Column, ConnectOptions, Row, SqliteConnection, TypeInfo, ValueRef,
+    sqlite::{SqliteConnectOptions, SqliteRow},
+};
+use sync_wrapper::SyncFuture;
+use uuid::Uuid;
+
+use crate::{
+    actors::tools::{Tool, ToolDefinition},
+    error::{AppError, Result},
+};
+
+/// Views agents may query, created by the `builtin_tools` migration
+pub const QUERYABLE_VIEWS: &[&str] = &["tool_tasks", "tool_events", "tool_notes"];
+
+/// Configuration of the SQL tool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SqlConfig {
+    /// Rows beyond this are left out of the result
+    pub max_rows: usize,
+}
+
+impl Default for SqlConfig {
+    fn default() -> Self {
+        Self { max_rows: 200 }
+    }
+}
+
+pub struct QueryDatabase {
+    /// Connection string of the app's database
+    pub db_path: Arc<str>,
+    pub config: SqlConfig,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct QueryDatabaseArgs {
+    /// A single SQLite SELECT statement
+    pub sql: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryOutput {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    /// Whether rows were left out
+    pub truncated: bool,
+}
+
+impl Tool for QueryDatabase {
+    type Error = AppError;
+    type Args = QueryDatabaseArgs;
+    type Output = QueryOutput;
+
+    const NAME: &'static str = "query_database";
+
+    fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.into(),
+            description: Cow::Owned(format!(
+                "Runs a read-only SQLite query. Only these views can be queried: {}. \
+                Ids are returned as UUID strings.",
+                QUERYABLE_VIEWS.join(", ")
+            )),
+            params: schema_for!(QueryDatabaseArgs),
+            returns: None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: Self::Args,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send + Sync + 'static {
+        let db_path = self.db_path.clone();
+        let max_rows = self.config.max_rows;
+        SyncFuture::new(async move {
+            let mut conn = SqliteConnectOptions::from_str(&db_path)?
+                .read_only(true)
+                .connect()
+                .await?;
+            query_views(&mut conn, &args.sql, max_rows).await
+        })
+    }
+}
+
+/// Runs a query that may only read the [`QUERYABLE_VIEWS`]
+pub async fn query_views(
+    conn: &mut SqliteConnection,
+    sql: &str,
+    max_rows: usize,
+) -> Result<QueryOutput> {
+    set_authorizer(conn, true).await?;
+    let res = fetch(conn, sql, max_rows).await;
+    set_authorizer(conn, false).await?;
+    res
+}
+
+async fn fetch(conn: &mut SqliteConnection, sql: &str, max_rows: usize) -> Result<QueryOutput> {
+    // Statements are authorized when they are prepared, so none may come from the cache
+    let mut rows = sqlx::query(sql).persistent(false).fetch(&mut *conn);
+    let mut output = QueryOutput {
+        columns: Vec::new(),
+        rows: Vec::new(),
+        truncated: false,
+    };
+    while let Some(row) = rows.try_next().await.map_err(|e| match e {
+        sqlx::Error::Database(e) => AppError::validation(format!("Query failed: {e}")),
+        e => e.into(),
+    })? {
+        if output.rows.len() >= max_rows {
+            output.truncated = true;
+            break;
+        }
+        if output.columns.is_empty() {
+            output.columns = row
+                .columns()
+                .iter()
+                .map(|column| column.name().to_string())
+                .collect();
+        }
+        output.rows.push(row_values(&row)?);
+    }
+    Ok(output)
+}
+
+fn row_values(row: &SqliteRow) -> Result<Vec<Value>> {
+    (0..row.len())
+        .map(|i| {
+            let raw = row.try_get_raw(i)?;
+            if raw.is_null() {
+                return Ok(Value::Null);
+            }
+            // The storage class of the value, the views' columns have no declared types
+            Ok(match raw.type_info().name() {
+                "INTEGER" | "BOOLEAN" => row.try_get_unchecked::<i64, _>(i)?.into(),
+                "REAL" | "NUMERIC" => row.try_get_unchecked::<f64, _>(i)?.into(),
+                "BLOB" => {
+                    let bytes: Vec<u8> = row.try_get_unchecked(i)?;
+                    match Uuid::from_slice(&bytes) {
+                        Ok(id) => id.to_string().into(),
+                        Err(_) => bytes
+                            .iter()
+                            .map(|b| format!("{b:02x}"))
+                            .collect::<String>()
+                            .into(),
+                    }
+                }
+                _ => row.try_get_unchecked::<String, _>(i)?.into(),
+            })
+        })
+        .collect()
+}
+
+async fn set_authorizer(conn: &mut SqliteConnection, enabled: bool) -> Result<()> {
+    let mut handle = conn.lock_handle().await?;
+    let callback = enabled.then_some(authorize as _);
+    // SAFETY: the handle is locked, and the callback doesn't use its user data
+    let code = unsafe {
+        sqlite3_set_authorizer(handle.as_raw_handle().as_ptr(), callback, ptr::null_mut())
+    };
+    if code != SQLITE_OK {
+        return Err(AppError::internal(format!(
+            "Failed to set the SQLite authorizer: {code}"
+        )));
+    }
+    Ok(())
+}
+
+/// Allows selecting, functions and reading columns on behalf of a queryable view. Reads
+/// of tables through a view are reported with the view's name as the last argument.
+unsafe extern "C" fn authorize(
+    _user_data: *mut c_void,
+    action: c_int,
+    _table: *const c_char,
+    _column: *const c_char,
+    _database: *const c_char,
+    view: *const c_char,
+) -> c_int {
+    match action {
+        SQLITE_SELECT | SQLITE_FUNCTION | SQLITE_RECURSIVE => SQLITE_OK,
+        SQLITE_READ if !view.is_null() => {
+            // SAFETY: SQLite passes a valid C string or null, and it was checked above
+            let view = unsafe { CStr::from_ptr(view) };
+            match view.to_str() {
+                Ok(view) if QUERYABLE_VIEWS.contains(&view) => SQLITE_OK,
+                _ => SQLITE_DENY,
+            }
+        }
+        _ => SQLITE_DENY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Connection, Executor};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_views() {
+        let mut conn = SqliteConnection::connect(":memory:").await.unwrap();
+        conn.execute(
+            "CREATE TABLE tasks (id BLOB, title TEXT, done INTEGER, secret TEXT);
+            CREATE VIEW tool_tasks AS SELECT id, title, done FROM tasks;
+            INSERT INTO tasks VALUES (x'0123456789abcdef0123456789abcdef', 'Write docs', 0, 'hunter2');
+            INSERT INTO tasks VALUES (x'00', 'Ship', 1, 'hunter3');",
+        )
+        .await
+        .unwrap();
+
+        let output = query_views(&mut conn, "SELECT * FROM tool_tasks ORDER BY title", 1)
+            .await
+            .unwrap();
+        assert_eq!(output.columns, ["id", "title", "done"]);
+        assert_eq!(
+            output.rows,
+            [vec![Value::from("00"), "Ship".into(), 1.into()]]
+        );
+        assert!(output.truncated);
+
+        let output = query_views(
+            &mut conn,
+            "SELECT upper(title) FROM tool_tasks WHERE done = 0",
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.rows, [vec![Value::from("WRITE DOCS")]]);
+
+        assert!(
+            query_views(&mut conn, "SELECT secret FROM tasks", 10)
+                .await
+                .is_err()
+        );
+        assert!(
+            query_views(&mut conn, "DELETE FROM tasks", 10)
+                .await
+                .is_err()
+        );
+        assert!(
+            query_views(&mut conn, "SELECT * FROM sqlite_master", 10)
+                .await
+                .is_err()
+        );
+
+        // The connection works as before once the query is done
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM tasks")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+}